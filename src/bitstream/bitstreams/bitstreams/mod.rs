@@ -109,6 +109,29 @@ impl BitOrder {
             BitOrder::JPEG => unimplemented!(),
         }
     }
+
+    /// The size of the chunks in which the bitstream is read.
+    #[inline]
+    #[must_use]
+    pub const fn mcu_bytelen(self) -> usize {
+        match self {
+            BitOrder::LSB => {
+                size_of::<<BitOrderLSB as BitStreamTraits>::MCUByteArrayType>()
+            }
+            BitOrder::MSB => {
+                size_of::<<BitOrderMSB as BitStreamTraits>::MCUByteArrayType>()
+            }
+            BitOrder::MSB16 => size_of::<
+                <BitOrderMSB16 as BitStreamTraits>::MCUByteArrayType,
+            >(),
+            BitOrder::MSB32 => size_of::<
+                <BitOrderMSB32 as BitStreamTraits>::MCUByteArrayType,
+            >(),
+            BitOrder::JPEG => {
+                size_of::<<BitOrderJPEG as BitStreamTraits>::MCUByteArrayType>()
+            }
+        }
+    }
 }
 
 mod jpeg;
@@ -46,5 +46,13 @@ macro_rules! test_bitstream_packing_size_predictions {
                 }
             }
         }
+        #[test]
+        fn bitstream_mcu_bytelen() {
+            use $crate::bitstreams::BitStreamTraits;
+            assert_eq!(
+                <$BitOrder as BitStreamTraits>::TAG.mcu_bytelen(),
+                size_of::<<$BitOrder as BitStreamTraits>::MCUByteArrayType>()
+            );
+        }
     };
 }
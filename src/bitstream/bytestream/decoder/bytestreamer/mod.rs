@@ -5,6 +5,7 @@ use rawspeed_memory_endianness::endianness::{
     Endianness, SwapBytes, get_host_endianness,
 };
 
+#[derive(Debug, Clone, Copy)]
 #[non_exhaustive]
 #[must_use]
 pub struct ByteStreamer<'a> {
@@ -12,12 +13,13 @@ pub struct ByteStreamer<'a> {
     endianness: Endianness,
 }
 
-#[cfg_attr(not(test), expect(dead_code))]
 impl<'a> ByteStreamer<'a> {
+    #[inline]
     pub const fn new(slice: &'a [u8], endianness: Endianness) -> Self {
         Self { slice, endianness }
     }
 
+    #[inline]
     pub fn read<T>(&mut self) -> T
     where
         T: FromBits,
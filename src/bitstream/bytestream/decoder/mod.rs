@@ -1 +1 @@
-mod bytestreamer;
+pub mod bytestreamer;
@@ -0,0 +1,401 @@
+use rawspeed_bitstream_bitstream_decoder::bitstreamer::{
+    BitStream as _, BitStreamerBase,
+};
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrderJPEG;
+use rawspeed_codecs_huffman::huffman::{
+    HuffmanCode, HuffmanError, HuffmanTable,
+};
+use rawspeed_std::coord_common::{Dimensions2D, RowIndex};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+
+/// The number of decoder tables a file can select from.
+pub const NUM_TABLES: usize = 3;
+
+/// The code length histogram and the symbols of a Huffman tree.
+type Tree = ([u8; 16], &'static [u8]);
+
+/// The Huffman trees of each decoder table: the first one codes the
+/// first difference of a block, the second one all the others.
+///
+/// The symbols of the second tree hold the number of differences that
+/// are skipped (left at zero) in their high nibble, and the length of
+/// the difference in their low nibble.
+const TREES: [[Tree; 2]; NUM_TABLES] = [
+    [
+        (
+            [0, 1, 4, 2, 3, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            &[
+                0x04, 0x03, 0x05, 0x06, 0x02, 0x07, 0x01, 0x08, 0x09, 0x00,
+                0x0a, 0x0b, 0xff,
+            ],
+        ),
+        (
+            [0, 2, 2, 2, 1, 4, 2, 1, 2, 5, 1, 1, 0, 0, 0, 139],
+            &[
+                0x03, 0x04, 0x02, 0x05, 0x01, 0x06, 0x07, 0x08, 0x12, 0x13,
+                0x11, 0x14, 0x09, 0x15, 0x22, 0x00, 0x21, 0x16, 0x0a, 0xf0,
+                0x23, 0x17, 0x24, 0x31, 0x32, 0x18, 0x19, 0x33, 0x25, 0x41,
+                0x34, 0x42, 0x35, 0x51, 0x36, 0x37, 0x38, 0x29, 0x79, 0x26,
+                0x1a, 0x39, 0x56, 0x57, 0x28, 0x27, 0x52, 0x55, 0x58, 0x43,
+                0x76, 0x59, 0x77, 0x54, 0x61, 0xf9, 0x71, 0x78, 0x75, 0x96,
+                0x97, 0x49, 0xb7, 0x53, 0xd7, 0x74, 0xb6, 0x98, 0x47, 0x48,
+                0x95, 0x69, 0x99, 0x91, 0xfa, 0xb8, 0x68, 0xb5, 0xb9, 0xd6,
+                0xf7, 0xd8, 0x67, 0x46, 0x45, 0x94, 0x89, 0xf8, 0x81, 0xd5,
+                0xf6, 0xb4, 0x88, 0xb1, 0x2a, 0x44, 0x72, 0xd9, 0x87, 0x66,
+                0xd4, 0xf5, 0x3a, 0xa7, 0x73, 0xa9, 0xa8, 0x86, 0x62, 0xc7,
+                0x65, 0xc8, 0xc9, 0xa1, 0xf4, 0xd1, 0xe9, 0x5a, 0x92, 0x85,
+                0xa6, 0xe7, 0x93, 0xe8, 0xc1, 0xc6, 0x7a, 0x64, 0xe1, 0x4a,
+                0x6a, 0xe6, 0xb3, 0xf1, 0xd3, 0xa5, 0x8a, 0xb2, 0x9a, 0xba,
+                0x84, 0xa4, 0x63, 0xe5, 0xc5, 0xf3, 0xd2, 0xc4, 0x82, 0xaa,
+                0xda, 0xe4, 0xf2, 0xca, 0x83, 0xa3, 0xa2, 0xc3, 0xea, 0xc2,
+                0xe2, 0xe3,
+            ],
+        ),
+    ],
+    [
+        (
+            [0, 2, 2, 3, 1, 1, 1, 1, 2, 0, 0, 0, 0, 0, 0, 0],
+            &[
+                0x03, 0x02, 0x04, 0x01, 0x05, 0x00, 0x06, 0x07, 0x09, 0x08,
+                0x0a, 0x0b, 0xff,
+            ],
+        ),
+        (
+            [0, 2, 2, 1, 4, 1, 4, 1, 3, 3, 1, 0, 0, 0, 0, 140],
+            &[
+                0x02, 0x03, 0x01, 0x04, 0x05, 0x12, 0x11, 0x06, 0x13, 0x07,
+                0x15, 0x14, 0x16, 0x08, 0x22, 0x21, 0x17, 0x09, 0x23, 0x00,
+                0x18, 0x31, 0x32, 0x19, 0x0a, 0x24, 0x25, 0x33, 0x26, 0x34,
+                0x41, 0x27, 0x35, 0x42, 0x28, 0x36, 0x43, 0x51, 0x37, 0x44,
+                0x29, 0x38, 0x52, 0x45, 0x61, 0x1a, 0x39, 0x53, 0x46, 0x71,
+                0x62, 0x47, 0x2a, 0x54, 0x3a, 0x81, 0x72, 0x55, 0x63, 0x91,
+                0x48, 0x64, 0xa1, 0x56, 0x73, 0x82, 0x49, 0xb1, 0x65, 0x57,
+                0x74, 0x92, 0x83, 0xc1, 0x4a, 0x66, 0x58, 0xa2, 0x75, 0xd1,
+                0x93, 0x84, 0x67, 0xe1, 0x59, 0xb2, 0x76, 0x85, 0xf1, 0x68,
+                0xa3, 0x94, 0x5a, 0x77, 0xc2, 0x86, 0x69, 0xb3, 0x95, 0xa4,
+                0x78, 0xd2, 0x87, 0x6a, 0xe2, 0xc3, 0x96, 0x79, 0xb4, 0xa5,
+                0x88, 0xf2, 0x97, 0xd3, 0x7a, 0xc4, 0x89, 0xb5, 0xa6, 0xe3,
+                0x98, 0x8a, 0xd4, 0xc5, 0xb6, 0xa7, 0xf3, 0x99, 0xe4, 0xd5,
+                0xc6, 0x9a, 0xb7, 0xa8, 0xf4, 0xe5, 0xd6, 0xc7, 0xb8, 0xa9,
+                0xf5, 0xe6, 0xd7, 0xc8, 0xb9, 0xaa, 0xf6, 0xe7, 0xd8, 0xc9,
+                0xba, 0xf7, 0xe8, 0xd9, 0xca, 0xf8, 0xe9, 0xda, 0xf9, 0xea,
+                0xfa, 0xf0,
+            ],
+        ),
+    ],
+    [
+        (
+            [0, 0, 6, 3, 1, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            &[
+                0x06, 0x05, 0x07, 0x04, 0x08, 0x03, 0x09, 0x02, 0x00, 0x0a,
+                0x01, 0x0b, 0xff,
+            ],
+        ),
+        (
+            [0, 0, 6, 2, 1, 3, 3, 2, 5, 1, 2, 2, 8, 10, 0, 117],
+            &[
+                0x04, 0x05, 0x03, 0x06, 0x02, 0x07, 0x01, 0x08, 0x09, 0x12,
+                0x13, 0x14, 0x11, 0x15, 0x0a, 0x16, 0x17, 0xf0, 0x00, 0x22,
+                0x21, 0x18, 0x23, 0x19, 0x24, 0x32, 0x31, 0x25, 0x33, 0x38,
+                0x37, 0x34, 0x35, 0x36, 0x39, 0x79, 0x57, 0x58, 0x59, 0x28,
+                0x56, 0x78, 0x27, 0x41, 0x29, 0x77, 0x26, 0x42, 0x76, 0x99,
+                0x1a, 0x55, 0x98, 0x97, 0xf9, 0x48, 0x54, 0x96, 0x89, 0x47,
+                0xb7, 0x49, 0xfa, 0x75, 0x68, 0xb6, 0x67, 0x69, 0xb9, 0xb8,
+                0xd8, 0x52, 0xd7, 0x88, 0xb5, 0x74, 0x51, 0x46, 0xd9, 0xf8,
+                0x3a, 0xd6, 0x87, 0x45, 0x7a, 0x95, 0xd5, 0xf6, 0x86, 0xb4,
+                0xa9, 0x94, 0x53, 0x2a, 0xa8, 0x43, 0xf5, 0xf7, 0xd4, 0x66,
+                0xa7, 0x5a, 0x44, 0x8a, 0xc9, 0xe8, 0xc8, 0xe7, 0x9a, 0x6a,
+                0x73, 0x4a, 0x61, 0xc7, 0xf4, 0xc6, 0x65, 0xe9, 0x72, 0xe6,
+                0x71, 0x91, 0x93, 0xa6, 0xda, 0x92, 0x85, 0x62, 0xf3, 0xc5,
+                0xb2, 0xa4, 0x84, 0xba, 0x64, 0xa5, 0xb3, 0xd2, 0x81, 0xe5,
+                0xd3, 0xaa, 0xc4, 0xca, 0xf2, 0xb1, 0xe4, 0xd1, 0x83, 0x63,
+                0xea, 0xc3, 0xe2, 0x82, 0xf1, 0xa3, 0xc2, 0xa1, 0xc1, 0xe3,
+                0xa2, 0xe1,
+            ],
+        ),
+    ],
+];
+
+/// The samples are coded in blocks of this many, each of which has its
+/// differences coded in order, with runs of zeros skipped.
+const BLOCK_LEN: usize = 64;
+
+/// The blocks span stripes of this many rows, in row-major order.
+const STRIPE_ROWS: usize = 8;
+
+/// The symbol that ends a block, unless it is the first of the block.
+const END_OF_BLOCK: u8 = 0x00;
+
+/// The symbol that codes a zero difference.
+const ZERO_DIFFERENCE: u8 = 0xff;
+
+/// The prediction of the first two samples of each row.
+const ROW_BASE: i32 = 512;
+
+/// The number of bits of the Huffman-coded samples, to which the low
+/// bits are appended.
+const HIGH_BITS: u32 = 10;
+
+/// The number of low bits of each sample, four of which are packed into
+/// each byte.
+const LOW_BITS: u32 = 2;
+
+/// The low bits are followed by this many bytes of unknown purpose.
+const LOW_BITS_PADDING: usize = 514;
+
+/// Like dcraw, the darkest samples of the sensors that are this wide are
+/// lifted a little.
+const LIFTED_WIDTH: usize = 2672;
+const LIFTED_BELOW: u16 = 512;
+const LIFT: u16 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CrwError {
+    InvalidTable(u32),
+    InvalidDimensions,
+    InvalidHuffmanCode,
+    ValueOutOfBounds,
+    TruncatedData,
+    OutputDimensionsMismatch,
+}
+
+impl core::fmt::Display for CrwError {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            CrwError::InvalidTable(table) => {
+                write!(f, "CrwError(InvalidTable({table}))")
+            }
+            CrwError::InvalidDimensions => {
+                write!(f, "CrwError(InvalidDimensions)")
+            }
+            CrwError::InvalidHuffmanCode => {
+                write!(f, "CrwError(InvalidHuffmanCode)")
+            }
+            CrwError::ValueOutOfBounds => {
+                write!(f, "CrwError(ValueOutOfBounds)")
+            }
+            CrwError::TruncatedData => write!(f, "CrwError(TruncatedData)"),
+            CrwError::OutputDimensionsMismatch => {
+                write!(f, "CrwError(OutputDimensionsMismatch)")
+            }
+        }
+    }
+}
+
+impl From<HuffmanError> for CrwError {
+    #[inline]
+    fn from(err: HuffmanError) -> Self {
+        if err == HuffmanError::EndOfStream {
+            CrwError::TruncatedData
+        } else {
+            CrwError::InvalidHuffmanCode
+        }
+    }
+}
+
+/// Reads a difference of `len` bits, sign-extended JPEG-style.
+fn get_difference(
+    bs: &mut BitStreamerBase<'_, BitOrderJPEG>,
+    len: u8,
+) -> Result<i32, CrwError> {
+    let nbits = u32::from(len);
+    bs.fill(nbits).map_err(|_err| CrwError::TruncatedData)?;
+    let bits = bs.peek_bits_no_fill(nbits).zext();
+    bs.skip_bits_no_fill(nbits);
+    let diff = i32::try_from(bits).unwrap();
+    if diff < 1 << (len - 1) {
+        Ok(diff - (1 << len) + 1)
+    } else {
+        Ok(diff)
+    }
+}
+
+/// Decodes the differences of a block. Those that are not coded, because
+/// they are skipped or follow the end of the block, are zero.
+fn decode_block(
+    bs: &mut BitStreamerBase<'_, BitOrderJPEG>,
+    tables: &[HuffmanTable; 2],
+) -> Result<[i32; BLOCK_LEN], CrwError> {
+    let mut diffs = [0; BLOCK_LEN];
+    let mut index = 0;
+    while index < BLOCK_LEN {
+        let table = tables.get(usize::from(index != 0)).unwrap();
+        let symbol = table.decode_code_value(bs)?;
+        if symbol == END_OF_BLOCK && index != 0 {
+            break;
+        }
+        if symbol != ZERO_DIFFERENCE {
+            index += usize::from(symbol >> 4);
+            let len = symbol & 0xf;
+            if len != 0 {
+                let diff = get_difference(bs, len)?;
+                // A run past the end of the block is not an error.
+                if let Some(slot) = diffs.get_mut(index) {
+                    *slot = diff;
+                }
+            }
+        }
+        index += 1;
+    }
+    Ok(diffs)
+}
+
+/// The decompressor of the Canon CRW files: the 10 high bits of each
+/// sample are Huffman-coded, and the 2 low bits, if any, are stored
+/// separately, uncompressed.
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct CrwDecompressor<'a> {
+    codes: [HuffmanCode; 2],
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    low_bits: Option<&'a [u8]>,
+    input: &'a [u8],
+}
+
+impl<'a> CrwDecompressor<'a> {
+    /// `table` is the decoder table that the file names. If the file has
+    /// `low_bits`, the input starts with them, and the high bits follow
+    /// some padding.
+    #[inline]
+    pub fn new(
+        input: &'a [u8],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        table: u32,
+        low_bits: bool,
+    ) -> Result<Self, CrwError> {
+        let trees = usize::try_from(table)
+            .ok()
+            .and_then(|index| TREES.get(index))
+            .ok_or(CrwError::InvalidTable(table))?;
+        let codes = trees.each_ref().map(|(counts, symbols)| {
+            HuffmanCode::new(counts, symbols).unwrap()
+        });
+        let (width, height) = (dims.row_len().get(), dims.row_count().get());
+        // Every stripe, including the last, shorter one, must hold whole
+        // blocks.
+        if !(width * STRIPE_ROWS).is_multiple_of(BLOCK_LEN)
+            || !(width * (height % STRIPE_ROWS)).is_multiple_of(BLOCK_LEN)
+        {
+            return Err(CrwError::InvalidDimensions);
+        }
+        let (low_bits, input) = if low_bits {
+            let low_bits_len = width * height / 4;
+            let (low, rest) = input
+                .split_at_checked(low_bits_len)
+                .ok_or(CrwError::TruncatedData)?;
+            let high = rest
+                .get(LOW_BITS_PADDING..)
+                .ok_or(CrwError::TruncatedData)?;
+            (Some(low), high)
+        } else {
+            (None, input)
+        };
+        Ok(Self {
+            codes,
+            dims,
+            low_bits,
+            input,
+        })
+    }
+
+    /// The high bits of the samples of a stripe. The difference of the
+    /// first sample of each block is relative to that of the previous
+    /// block, and each sample is predicted from the previous one of the
+    /// same column parity.
+    fn decode_stripe(
+        &self,
+        bs: &mut BitStreamerBase<'_, BitOrderJPEG>,
+        tables: &[HuffmanTable; 2],
+        carry: &mut i32,
+        stripe: &mut [u16],
+    ) -> Result<(), CrwError> {
+        let width = self.dims.row_len().get();
+        let mut preds = [ROW_BASE; 2];
+        for (block_index, block) in
+            stripe.chunks_exact_mut(BLOCK_LEN).enumerate()
+        {
+            let mut diffs = decode_block(bs, tables)?;
+            let first = diffs.first_mut().unwrap();
+            *first += *carry;
+            *carry = *first;
+            for (index, (sample, diff)) in
+                block.iter_mut().zip(diffs).enumerate()
+            {
+                let col = (block_index * BLOCK_LEN + index) % width;
+                if col == 0 {
+                    preds = [ROW_BASE; 2];
+                }
+                let pred = preds.get_mut(col % 2).unwrap();
+                *pred += diff;
+                *sample = u16::try_from(*pred)
+                    .ok()
+                    .filter(|value| value >> HIGH_BITS == 0)
+                    .ok_or(CrwError::ValueOutOfBounds)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends the low bits of the samples of a stripe, which are packed
+    /// least significant first.
+    fn append_low_bits(&self, low_bits: &[u8], stripe: &mut [u16]) {
+        let width = self.dims.row_len().get();
+        let per_byte = usize::try_from(u8::BITS / LOW_BITS).unwrap();
+        for (byte, samples) in low_bits.iter().zip(stripe.chunks_mut(per_byte))
+        {
+            for (index, sample) in (0_u32..).zip(samples) {
+                let low = (u16::from(*byte) >> (index * LOW_BITS)) & 0x3;
+                let mut value = (*sample << LOW_BITS) | low;
+                if width == LIFTED_WIDTH && value < LIFTED_BELOW {
+                    value += LIFT;
+                }
+                *sample = value;
+            }
+        }
+    }
+
+    #[inline(never)]
+    pub fn decode(
+        &self,
+        output: &mut Array2DRefMut<'_, u16>,
+    ) -> Result<(), CrwError> {
+        if output.dims() != self.dims {
+            return Err(CrwError::OutputDimensionsMismatch);
+        }
+        let tables = self.codes.each_ref().map(|code| {
+            HuffmanTable::new(code, HuffmanTable::DEFAULT_LUT_BITS).unwrap()
+        });
+        let mut bs = BitStreamerBase::<BitOrderJPEG>::try_from(self.input)
+            .map_err(|_err| CrwError::TruncatedData)?;
+        let (width, height) =
+            (self.dims.row_len().get(), self.dims.row_count().get());
+        let mut carry = 0;
+        for first_row in (0..height).step_by(STRIPE_ROWS) {
+            let num_rows = STRIPE_ROWS.min(height - first_row);
+            let mut stripe = vec![0; num_rows * width];
+            self.decode_stripe(&mut bs, &tables, &mut carry, &mut stripe)?;
+            if let Some(low_bits) = self.low_bits {
+                let per_byte = usize::try_from(u8::BITS / LOW_BITS).unwrap();
+                let start = first_row * width / per_byte;
+                let stripe_low_bits = low_bits
+                    .get(start..start + stripe.len() / per_byte)
+                    .unwrap();
+                self.append_low_bits(stripe_low_bits, &mut stripe);
+            }
+            for (row, samples) in (first_row..).zip(stripe.chunks_exact(width))
+            {
+                output
+                    .get_row_mut(RowIndex::new(row))
+                    .unwrap()
+                    .copy_from_slice(samples);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
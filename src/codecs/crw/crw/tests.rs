@@ -0,0 +1,236 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_codecs_huffman::huffman::HuffmanCode;
+use rawspeed_std::coord_common::{Dimensions2D, RowCount, RowLength, RowPitch};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+
+use super::{
+    BLOCK_LEN, CrwDecompressor, CrwError, LOW_BITS_PADDING, ROW_BASE,
+    STRIPE_ROWS, TREES,
+};
+
+#[derive(Debug)]
+struct Encoder {
+    codes: [Vec<(u8, (u32, u32))>; 2],
+    bits: BitStreamBuilder,
+}
+
+impl Encoder {
+    fn new(table: usize) -> Self {
+        let codes =
+            TREES
+                .get(table)
+                .unwrap()
+                .each_ref()
+                .map(|(counts, symbols)| {
+                    let code = HuffmanCode::new(counts, symbols).unwrap();
+                    symbols.iter().copied().zip(code.codes()).collect()
+                });
+        Self {
+            codes,
+            bits: BitStreamBuilder::new(),
+        }
+    }
+
+    fn put_symbol(&mut self, tree: usize, symbol: u8) {
+        let (_, (len, bits)) = *self
+            .codes
+            .get(tree)
+            .unwrap()
+            .iter()
+            .find(|(sym, _)| *sym == symbol)
+            .unwrap();
+        self.bits.put(bits, len);
+    }
+
+    /// The first difference with the first tree, then runs of zeros
+    /// and differences with the second one.
+    fn put_block(&mut self, diffs: &[i32]) {
+        let put = |encoder: &mut Self, tree, run: u8, diff: i32| {
+            let len = 32 - diff.unsigned_abs().leading_zeros();
+            encoder.put_symbol(tree, (run << 4) | u8::try_from(len).unwrap());
+            let diff_bits = if diff < 0 {
+                diff + (1 << len) - 1
+            } else {
+                diff
+            };
+            encoder.bits.put(u32::try_from(diff_bits).unwrap(), len);
+        };
+        let (first, rest) = diffs.split_first().unwrap();
+        put(self, 0, 0, *first);
+        let mut run = 0;
+        for diff in rest {
+            if *diff == 0 {
+                run += 1;
+                continue;
+            }
+            while run > 15 {
+                self.put_symbol(1, 0xf0);
+                run -= 16;
+            }
+            put(self, 1, run, *diff);
+            run = 0;
+        }
+        if run != 0 {
+            self.put_symbol(1, 0x00);
+        }
+    }
+}
+
+/// Encodes the high bits of the samples, predicting them like the
+/// decoder does.
+fn encode(samples: &[Vec<u16>], table: usize) -> Vec<u8> {
+    let mut encoder = Encoder::new(table);
+    let mut carry = 0;
+    for stripe in samples.chunks(STRIPE_ROWS) {
+        let mut diffs = vec![];
+        for row in stripe {
+            let mut preds = [ROW_BASE; 2];
+            for (col, sample) in row.iter().enumerate() {
+                let pred = preds.get_mut(col % 2).unwrap();
+                diffs.push(i32::from(*sample) - *pred);
+                *pred = i32::from(*sample);
+            }
+        }
+        for block in diffs.chunks_exact_mut(BLOCK_LEN) {
+            let first = block.first_mut().unwrap();
+            (*first, carry) = (*first - carry, *first);
+            encoder.put_block(block);
+        }
+    }
+    encoder.bits.finish(BitOrder::JPEG)
+}
+
+/// Mostly flat samples, for long runs of zero differences.
+fn samples(width: usize, height: usize) -> Vec<Vec<u16>> {
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| {
+                    if col % 16 < 6 {
+                        u16::try_from((row * 37 + col * 11) % 1000).unwrap()
+                    } else {
+                        u16::try_from(300 + col % 2).unwrap()
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn dims(
+    width: usize,
+    height: usize,
+) -> Dimensions2D<core::num::NonZero<usize>> {
+    Dimensions2D::new(
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowCount::new(core::num::NonZero::new(height).unwrap()),
+    )
+}
+
+fn decode(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    table: u32,
+    low_bits: bool,
+) -> Result<Vec<u16>, CrwError> {
+    let decoder =
+        CrwDecompressor::new(data, dims(width, height), table, low_bits)?;
+    let mut buf = vec![0; width * height];
+    let mut output = Array2DRefMut::new(
+        &mut buf,
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowPitch::new(core::num::NonZero::new(width).unwrap()),
+    );
+    decoder.decode(&mut output)?;
+    Ok(buf)
+}
+
+#[test]
+fn high_bits_test() {
+    let samples = samples(32, 12);
+    for table in 0..3 {
+        let data = encode(&samples, table);
+        assert_eq!(
+            decode(&data, 32, 12, table.try_into().unwrap(), false),
+            Ok(samples.concat())
+        );
+    }
+}
+
+#[test]
+fn low_bits_test() {
+    let samples = samples(16, 8);
+    let low_bits: Vec<u8> = (0..16 * 8 / 4_usize)
+        .map(|index| u8::try_from(index * 73 % 256).unwrap())
+        .collect();
+    let mut data = low_bits.clone();
+    data.resize(data.len() + LOW_BITS_PADDING, 0xAA);
+    data.extend(encode(&samples, 1));
+    let expected: Vec<u16> = samples
+        .concat()
+        .into_iter()
+        .enumerate()
+        .map(|(index, sample)| {
+            let byte = *low_bits.get(index / 4).unwrap();
+            (sample << 2) | ((u16::from(byte) >> (2 * (index % 4))) & 3)
+        })
+        .collect();
+    assert_eq!(decode(&data, 16, 8, 1, true), Ok(expected));
+}
+
+#[test]
+fn invalid_table_test() {
+    let data = encode(&samples(16, 8), 0);
+    assert_eq!(
+        decode(&data, 16, 8, 3, false),
+        Err(CrwError::InvalidTable(3))
+    );
+}
+
+#[test]
+fn invalid_dimensions_test() {
+    let data = encode(&samples(16, 8), 0);
+    assert_eq!(
+        decode(&data, 12, 8, 0, false),
+        Err(CrwError::InvalidDimensions)
+    );
+    assert_eq!(
+        decode(&data, 16, 9, 0, false),
+        Err(CrwError::InvalidDimensions)
+    );
+}
+
+#[test]
+fn truncated_test() {
+    let data = encode(&samples(16, 8), 0);
+    assert_eq!(decode(&data, 16, 8, 0, true), Err(CrwError::TruncatedData));
+}
+
+#[test]
+fn value_out_of_bounds_test() {
+    let mut samples = samples(16, 8);
+    *samples.last_mut().unwrap().last_mut().unwrap() = 1 << 10;
+    let data = encode(&samples, 2);
+    assert_eq!(
+        decode(&data, 16, 8, 2, false),
+        Err(CrwError::ValueOutOfBounds)
+    );
+}
+
+#[test]
+fn output_dimensions_mismatch_test() {
+    let data = encode(&samples(16, 8), 0);
+    let decoder = CrwDecompressor::new(&data, dims(16, 8), 0, false).unwrap();
+    let mut buf = vec![0; 16 * 4];
+    let mut output = Array2DRefMut::new(
+        &mut buf,
+        RowLength::new(core::num::NonZero::new(16).unwrap()),
+        RowPitch::new(core::num::NonZero::new(16).unwrap()),
+    );
+    assert_eq!(
+        decoder.decode(&mut output),
+        Err(CrwError::OutputDimensionsMismatch)
+    );
+}
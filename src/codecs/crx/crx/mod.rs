@@ -0,0 +1,1120 @@
+use rawspeed_bitstream_bitstream_decoder::bitstreamer::{
+    BitStream as _, BitStreamerBase,
+};
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrderMSB;
+use rawspeed_bitstream_bytestream_decoder::bytestreamer::ByteStreamer;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_std::coord_common::{
+    ColIndex, Coord2D, Dimensions2D, RowCount, RowIndex, RowLength,
+};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+
+/// The versions of the CMP1 header.
+const VERSION_1: u16 = 0x100;
+const VERSION_2: u16 = 0x200;
+
+/// The image parameters of the CMP1 header.
+const CMP1_SIZE: usize = 32;
+
+/// The extended CMP1 header may say that the samples are centered on
+/// a median of other than their own bits.
+const EXTENDED_FLAGS: usize = 32;
+const MEDIAN_FLAGS: usize = 56;
+const MEDIAN_BITS: usize = 84;
+
+/// The CFA images are coded as four planes, one per CFA position.
+const NUM_PLANES: u32 = 4;
+
+/// Neither the (plane) tiles nor the planes may be of other sizes.
+const MIN_TILE_DIM: usize = 0x16;
+const MAX_PLANE_DIM: usize = 0x7FFF;
+
+/// The most levels of the wavelet transform.
+const MAX_LEVELS: u32 = 3;
+
+/// Each tile, each of its planes, and each subband of those starts with
+/// a header: a signature and the size of the rest. The signatures of the
+/// version 2 headers have an extra bit set.
+const TILE_SIGNATURE: u16 = 0xFF01;
+const PLANE_SIGNATURE: u16 = 0xFF02;
+const SUBBAND_SIGNATURE: u16 = 0xFF03;
+const SIGNATURE_V2_BIT: u16 = 0x10;
+const HEADER_SIZE: u16 = 8;
+const EXTENDED_HEADER_SIZE: u16 = 16;
+
+/// The extended tile headers end with this, where the others end with zero.
+const EXTENDED_TILE_TAIL: u16 = 0x4000;
+
+/// The symbols are Golomb-Rice coded, with a parameter of up to this.
+const MAX_K: u32 = 15;
+
+/// Unary prefixes of this many zeros escape the symbol, which is then
+/// stored verbatim.
+const ESCAPE_ZEROS: u32 = 41;
+const ESCAPE_BITS: u32 = 21;
+
+/// The same, for the updates of the quantization parameter.
+const MAX_Q_K: u32 = 7;
+const Q_ESCAPE_ZEROS: u32 = 23;
+const Q_ESCAPE_BITS: u32 = 8;
+
+/// Each further bit of a run extends it by the step of the run parameter,
+/// which then increases; the rest of the run is stored in as many bits as
+/// the step is long.
+const RUN_STEPS: [usize; 32] = [
+    1, 1, 1, 1, 2, 2, 2, 2, 4, 4, 4, 4, 8, 8, 8, 8, 0x10, 0x10, 0x20, 0x20,
+    0x40, 0x40, 0x80, 0x80, 0x100, 0x200, 0x400, 0x800, 0x1000, 0x2000, 0x4000,
+    0x8000,
+];
+const RUN_REMAINDER_BITS: [u32; 32] = [
+    0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8,
+    9, 10, 11, 12, 13, 14, 15,
+];
+
+/// The quantization step doubles every six values of the parameter; these
+/// are the steps of an octave, scaled by 64.
+const Q_STEPS: [i64; 6] = [0x28, 0x2D, 0x33, 0x39, 0x40, 0x48];
+const Q_STEP_SHIFT: u32 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CrxError {
+    InvalidImageHeader,
+    UnsupportedEncoding,
+    UnsupportedTiling,
+    UnsupportedQuantization,
+    UnsupportedRounding,
+    InvalidTileHeader,
+    InvalidPlaneHeader,
+    InvalidSubbandHeader,
+    InvalidRun,
+    InvalidQuantization,
+    TruncatedData,
+    OutputDimensionsMismatch,
+}
+
+impl core::fmt::Display for CrxError {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            CrxError::InvalidImageHeader => {
+                write!(f, "CrxError(InvalidImageHeader)")
+            }
+            CrxError::UnsupportedEncoding => {
+                write!(f, "CrxError(UnsupportedEncoding)")
+            }
+            CrxError::UnsupportedTiling => {
+                write!(f, "CrxError(UnsupportedTiling)")
+            }
+            CrxError::UnsupportedQuantization => {
+                write!(f, "CrxError(UnsupportedQuantization)")
+            }
+            CrxError::UnsupportedRounding => {
+                write!(f, "CrxError(UnsupportedRounding)")
+            }
+            CrxError::InvalidTileHeader => {
+                write!(f, "CrxError(InvalidTileHeader)")
+            }
+            CrxError::InvalidPlaneHeader => {
+                write!(f, "CrxError(InvalidPlaneHeader)")
+            }
+            CrxError::InvalidSubbandHeader => {
+                write!(f, "CrxError(InvalidSubbandHeader)")
+            }
+            CrxError::InvalidRun => write!(f, "CrxError(InvalidRun)"),
+            CrxError::InvalidQuantization => {
+                write!(f, "CrxError(InvalidQuantization)")
+            }
+            CrxError::TruncatedData => write!(f, "CrxError(TruncatedData)"),
+            CrxError::OutputDimensionsMismatch => {
+                write!(f, "CrxError(OutputDimensionsMismatch)")
+            }
+        }
+    }
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Option<u16> {
+    data.get(pos..)
+        .and_then(|data| data.get(..size_of::<u16>()))
+        .map(|bytes| ByteStreamer::new(bytes, Endianness::Big).read())
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    data.get(pos..)
+        .and_then(|data| data.get(..size_of::<u32>()))
+        .map(|bytes| ByteStreamer::new(bytes, Endianness::Big).read())
+}
+
+fn read_usize(data: &[u8], pos: usize) -> Option<usize> {
+    read_u32(data, pos).map(|val| usize::try_from(val).unwrap())
+}
+
+/// The image parameters, from the CMP1 box of the track.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+#[must_use]
+pub struct CrxHeader {
+    pub version: u16,
+    pub width: usize,
+    pub height: usize,
+    pub tile_width: usize,
+    pub tile_height: usize,
+    pub bits: u32,
+    pub planes: u32,
+    /// Which CFA position the first plane is of.
+    pub cfa_layout: usize,
+    pub enc_type: u32,
+    /// The levels of the wavelet transform; lossless images have none.
+    pub levels: u32,
+    pub median_bits: u32,
+    /// The size of the tile headers, which precede the tiles.
+    pub mdat_header_size: usize,
+}
+
+impl CrxHeader {
+    #[inline]
+    pub fn parse(cmp1: &[u8]) -> Result<Self, CrxError> {
+        let header =
+            cmp1.get(..CMP1_SIZE).ok_or(CrxError::InvalidImageHeader)?;
+        let byte = |pos| u32::from(*header.get(pos).unwrap());
+        let bits = byte(24);
+        let planes = byte(25) >> 4;
+        let extended = cmp1.get(EXTENDED_FLAGS).is_some_and(|f| f >> 7 != 0);
+        let median_bits = match (cmp1.get(MEDIAN_FLAGS), cmp1.get(MEDIAN_BITS))
+        {
+            (Some(flags), Some(median_bits))
+                if extended
+                    && planes == NUM_PLANES
+                    && (flags >> 6) & 1 != 0 =>
+            {
+                u32::from(*median_bits)
+            }
+            _ => bits,
+        };
+        let header = Self {
+            version: read_u16(header, 0).unwrap(),
+            width: read_usize(header, 8).unwrap(),
+            height: read_usize(header, 12).unwrap(),
+            tile_width: read_usize(header, 16).unwrap(),
+            tile_height: read_usize(header, 20).unwrap(),
+            bits,
+            planes,
+            cfa_layout: usize::try_from(byte(25) & 0xF).unwrap(),
+            enc_type: byte(26) >> 4,
+            levels: byte(26) & 0xF,
+            median_bits,
+            mdat_header_size: read_usize(header, 28).unwrap(),
+        };
+        if header.is_valid() {
+            Ok(header)
+        } else {
+            Err(CrxError::InvalidImageHeader)
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        let max_bits = if self.enc_type == 1 { 15 } else { 14 };
+        let planes_valid = if self.planes == 1 {
+            self.cfa_layout == 0 && self.enc_type == 0 && self.bits == 8
+        } else {
+            self.planes == NUM_PLANES
+                && [self.width, self.height, self.tile_width, self.tile_height]
+                    .iter()
+                    .all(|dim| dim.is_multiple_of(2))
+                && self.cfa_layout <= 3
+                && self.bits != 8
+        };
+        matches!(self.version, VERSION_1 | VERSION_2)
+            && self.mdat_header_size != 0
+            && matches!(self.enc_type, 0 | 1 | 3)
+            && (1..=max_bits).contains(&self.bits)
+            && (1..=16).contains(&self.median_bits)
+            && planes_valid
+            && (1..=self.width).contains(&self.tile_width)
+            && (1..=self.height).contains(&self.tile_height)
+            && self.levels <= MAX_LEVELS
+    }
+}
+
+/// The headers of the tiles, one after the other.
+#[derive(Debug)]
+struct Headers<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Headers<'a> {
+    /// The signature and the rest of the next header.
+    fn next(
+        &mut self,
+        signature: u16,
+        sizes: &[u16],
+        err: CrxError,
+    ) -> Result<(bool, &'a [u8]), CrxError> {
+        let (Some(sig), Some(size)) = (
+            read_u16(self.data, self.pos),
+            read_u16(self.data, self.pos + 2),
+        ) else {
+            return Err(err);
+        };
+        let v2 = sig == signature | SIGNATURE_V2_BIT;
+        if (sig != signature && !v2) || !sizes.contains(&size) {
+            return Err(err);
+        }
+        let start = self.pos + 4;
+        let end = start + usize::from(size);
+        let rest = self.data.get(start..end).ok_or(err)?;
+        self.pos = end;
+        Ok((v2, rest))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Subband {
+    /// Into the tile data.
+    offset: usize,
+    size: usize,
+    q_param: i32,
+    /// Whether each line updates the quantization parameter.
+    q_updates: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Plane {
+    /// Whether the samples of the first subband are predicted from their
+    /// neighbours, rather than coded as they are.
+    predicted: bool,
+    subbands: Vec<Subband>,
+}
+
+#[derive(Debug, Clone)]
+struct Tile {
+    /// In plane samples.
+    row: usize,
+    col: usize,
+    width: usize,
+    height: usize,
+    planes: Vec<Plane>,
+}
+
+/// The sizes of each level of the wavelet transform, from the tile itself.
+fn level_dims(width: usize, height: usize, levels: u32) -> Vec<(usize, usize)> {
+    let mut dims = vec![(width, height)];
+    for _ in 0..levels {
+        let (last_width, last_height) = *dims.last().unwrap();
+        dims.push((last_width.div_ceil(2), last_height.div_ceil(2)));
+    }
+    dims
+}
+
+/// The sizes of the subbands, in the order in which they are stored: the
+/// lowpass band of the last level, then the highpass bands of each level,
+/// from the last one.
+fn subband_dims(
+    width: usize,
+    height: usize,
+    levels: u32,
+) -> Vec<(usize, usize)> {
+    let dims = level_dims(width, height, levels);
+    let mut subbands = vec![*dims.last().unwrap()];
+    for (level_width, level_height) in dims.iter().rev().skip(1) {
+        subbands.extend([
+            (level_width / 2, level_height.div_ceil(2)),
+            (level_width.div_ceil(2), level_height / 2),
+            (level_width / 2, level_height / 2),
+        ]);
+    }
+    subbands
+}
+
+fn get_bits(
+    bs: &mut BitStreamerBase<'_, BitOrderMSB>,
+    nbits: u32,
+) -> Result<u32, CrxError> {
+    if nbits == 0 {
+        return Ok(0);
+    }
+    bs.fill(nbits).map_err(|_err| CrxError::TruncatedData)?;
+    let bits = bs.peek_bits_no_fill(nbits).zext();
+    bs.skip_bits_no_fill(nbits);
+    Ok(u32::try_from(bits).unwrap())
+}
+
+/// The number of zero bits before the next one bit, which is skipped too.
+fn get_zeros(
+    bs: &mut BitStreamerBase<'_, BitOrderMSB>,
+) -> Result<u32, CrxError> {
+    const WORD_BITS: u32 = u32::BITS;
+    let mut zeros = 0;
+    loop {
+        bs.fill(WORD_BITS).map_err(|_err| CrxError::TruncatedData)?;
+        let word =
+            u32::try_from(bs.peek_bits_no_fill(WORD_BITS).zext()).unwrap();
+        if word != 0 {
+            let leading = word.leading_zeros();
+            bs.skip_bits_no_fill(leading + 1);
+            return Ok(zeros + leading);
+        }
+        bs.skip_bits_no_fill(WORD_BITS);
+        zeros += WORD_BITS;
+    }
+}
+
+/// A unary prefix, then `k` more bits, unless the prefix is too long.
+fn get_code(
+    bs: &mut BitStreamerBase<'_, BitOrderMSB>,
+    k: u32,
+    escape_zeros: u32,
+    escape_bits: u32,
+) -> Result<u32, CrxError> {
+    let zeros = get_zeros(bs)?;
+    if zeros >= escape_zeros {
+        get_bits(bs, escape_bits)
+    } else {
+        Ok((zeros << k) | get_bits(bs, k)?)
+    }
+}
+
+/// The codes interleave the non-negative and the negative values.
+fn to_signed(code: u32) -> i32 {
+    let half = i32::try_from(code >> 1).unwrap();
+    if code & 1 == 0 { half } else { !half }
+}
+
+/// The Golomb-Rice parameter adapts to the magnitude of the codes.
+fn predict_k(k: u32, code: u32, max: Option<u32>) -> u32 {
+    let k = k + u32::from(code >> k > 2) + u32::from(code >> k > 5)
+        - u32::from(code < (1 << k) >> 1);
+    max.map_or(k, |max| k.min(max))
+}
+
+/// The median edge detector: the gradient from the top left sample,
+/// unless the top left sample is outside of the range of the others.
+const fn predict_median(left: i32, top: i32, top_left: i32) -> i32 {
+    let delta = top.wrapping_sub(top_left);
+    let gradient = left.wrapping_add(delta);
+    let negative = delta < 0;
+    match ((top_left < left) ^ negative, (left < top) ^ negative) {
+        (false, _) => gradient,
+        (true, false) => left,
+        (true, true) => top,
+    }
+}
+
+/// The decoder of the lines of a subband.
+#[derive(Debug)]
+struct BandDecoder<'a> {
+    bs: BitStreamerBase<'a, BitOrderMSB>,
+    width: usize,
+    k: u32,
+    run_param: usize,
+    /// The previous and the current line, with one more sample on either
+    /// side.
+    prev: Vec<i32>,
+    cur: Vec<i32>,
+    /// The Golomb-Rice parameter after each sample of the previous line.
+    ks: Vec<u32>,
+}
+
+impl<'a> BandDecoder<'a> {
+    fn new(input: &'a [u8], width: usize) -> Result<Self, CrxError> {
+        Ok(Self {
+            bs: BitStreamerBase::<BitOrderMSB>::try_from(input)
+                .map_err(|_err| CrxError::TruncatedData)?,
+            width,
+            k: 0,
+            run_param: 0,
+            prev: vec![0; width + 2],
+            cur: vec![0; width + 2],
+            ks: vec![0; width + 1],
+        })
+    }
+
+    fn get_bits(&mut self, nbits: u32) -> Result<u32, CrxError> {
+        get_bits(&mut self.bs, nbits)
+    }
+
+    fn get_code(&mut self) -> Result<u32, CrxError> {
+        get_code(&mut self.bs, self.k, ESCAPE_ZEROS, ESCAPE_BITS)
+    }
+
+    fn set(&mut self, col: usize, val: i32) {
+        *self.cur.get_mut(col + 1).unwrap() = val;
+    }
+
+    fn left(&self, col: usize) -> i32 {
+        *self.cur.get(col).unwrap()
+    }
+
+    /// The samples of the previous line, from the top left one.
+    fn above(&self, col: usize, offset: usize) -> i32 {
+        *self.prev.get(col + offset).unwrap()
+    }
+
+    /// The length of a run of up to `len` samples, whose first one bit has
+    /// been read already.
+    fn get_run(&mut self, len: usize) -> Result<usize, CrxError> {
+        let mut run = 1;
+        while self.get_bits(1)? == 1 {
+            run += RUN_STEPS.get(self.run_param).unwrap();
+            if run > len {
+                run = len;
+                break;
+            }
+            self.run_param = (self.run_param + 1).min(RUN_STEPS.len() - 1);
+            if run == len {
+                break;
+            }
+        }
+        if run < len {
+            let nbits = *RUN_REMAINDER_BITS.get(self.run_param).unwrap();
+            run += usize::try_from(self.get_bits(nbits)?).unwrap();
+            self.run_param = self.run_param.saturating_sub(1);
+            if run > len {
+                return Err(CrxError::InvalidRun);
+            }
+        }
+        Ok(run)
+    }
+
+    /// Repeats the sample left of `col`, `run` times.
+    fn repeat(&mut self, col: usize, run: usize) {
+        let val = self.left(col);
+        self.cur.get_mut(col + 1..=col + run).unwrap().fill(val);
+    }
+
+    /// Adds the next code to the prediction of the sample at `col`.
+    fn decode_predicted(
+        &mut self,
+        col: usize,
+        median: bool,
+    ) -> Result<(), CrxError> {
+        let pred = if median {
+            predict_median(
+                self.left(col),
+                self.above(col, 1),
+                self.above(col, 0),
+            )
+        } else {
+            self.above(col, 1)
+        };
+        let code = self.get_code()?;
+        self.set(col, pred.wrapping_add(to_signed(code)));
+        // The next sample of the previous line hints at that of this one.
+        let hint = if col + 1 < self.width {
+            let delta = self.above(col, 2).wrapping_sub(self.above(col, 1));
+            u32::try_from(
+                (u64::from(code) + 2 * u64::from(delta.unsigned_abs())) >> 1,
+            )
+            .unwrap()
+        } else {
+            code
+        };
+        self.k = predict_k(self.k, hint, Some(MAX_K));
+        Ok(())
+    }
+
+    /// The first line is predicted from the previous sample, and the runs
+    /// are of zeros.
+    fn decode_top_line(&mut self) -> Result<(), CrxError> {
+        *self.cur.first_mut().unwrap() = 0;
+        let mut col = 0;
+        while col < self.width {
+            let len = self.width - col;
+            let mut pred = self.left(col);
+            if len > 1 && pred == 0 {
+                if self.get_bits(1)? == 1 {
+                    let run = self.get_run(len)?;
+                    self.repeat(col, run);
+                    col += run;
+                    if col == self.width {
+                        break;
+                    }
+                }
+                pred = 0;
+            }
+            let code = self.get_code()?;
+            self.set(col, pred.wrapping_add(to_signed(code)));
+            self.k = predict_k(self.k, code, Some(MAX_K));
+            col += 1;
+        }
+        self.finish_predicted_line();
+        Ok(())
+    }
+
+    /// The other lines are predicted with the median edge detector, and the
+    /// runs repeat the previous sample, where it matches those above.
+    fn decode_line(&mut self) -> Result<(), CrxError> {
+        *self.cur.first_mut().unwrap() = self.above(0, 1);
+        let mut col = 0;
+        while col < self.width {
+            let len = self.width - col;
+            let left = self.left(col);
+            if len > 1
+                && left == self.above(col, 1)
+                && left == self.above(col, 2)
+            {
+                if self.get_bits(1)? == 1 {
+                    let run = self.get_run(len)?;
+                    self.repeat(col, run);
+                    col += run;
+                }
+                if col < self.width {
+                    self.decode_predicted(col, false)?;
+                    col += 1;
+                }
+            } else {
+                self.decode_predicted(col, true)?;
+                col += 1;
+            }
+        }
+        self.finish_predicted_line();
+        Ok(())
+    }
+
+    /// The sample right of the line never matches the last one.
+    fn finish_predicted_line(&mut self) {
+        let last = self.left(self.width);
+        self.set(self.width, last.wrapping_add(1));
+    }
+
+    /// A sample that is coded as it is; after a run of zeros, it cannot be
+    /// zero itself.
+    fn decode_unpredicted(
+        &mut self,
+        col: usize,
+        nonzero: bool,
+        max_k: Option<u32>,
+    ) -> Result<u32, CrxError> {
+        let code = self.get_code()?;
+        self.set(col, to_signed(code + u32::from(nonzero)));
+        self.k = predict_k(self.k, code, max_k);
+        Ok(code)
+    }
+
+    fn set_k(&mut self, col: usize) {
+        *self.ks.get_mut(col).unwrap() = self.k;
+    }
+
+    /// The highpass bands are coded without prediction, with runs of zeros.
+    fn decode_unpredicted_top_line(&mut self) -> Result<(), CrxError> {
+        *self.prev.first_mut().unwrap() = 0;
+        *self.cur.first_mut().unwrap() = 0;
+        let mut col = 0;
+        while col < self.width {
+            let len = self.width - col;
+            if len > 1 && self.left(col) == 0 {
+                if self.get_bits(1)? == 1 {
+                    let run = self.get_run(len)?;
+                    self.repeat(col, run);
+                    self.ks.get_mut(col..col + run).unwrap().fill(0);
+                    col += run;
+                    if col == self.width {
+                        break;
+                    }
+                }
+                self.decode_unpredicted(col, true, Some(MAX_K))?;
+            } else {
+                self.decode_unpredicted(col, false, Some(MAX_K))?;
+            }
+            self.set_k(col);
+            col += 1;
+        }
+        self.set(self.width, 0);
+        Ok(())
+    }
+
+    /// The parameter is also adapted to that of the sample above right.
+    fn adapt_k(&mut self, col: usize) {
+        if *self.ks.get(col + 1).unwrap() <= self.k + 1 {
+            self.k = self.k.min(MAX_K);
+        } else {
+            self.k += 1;
+        }
+    }
+
+    fn decode_unpredicted_line(&mut self) -> Result<(), CrxError> {
+        let mut col = 0;
+        while col + 1 < self.width {
+            if self.above(col, 2) != 0
+                || self.above(col, 1) != 0
+                || self.left(col) != 0
+            {
+                self.decode_unpredicted(col, false, None)?;
+                self.adapt_k(col);
+            } else {
+                let run = if self.get_bits(1)? == 1 {
+                    self.get_run(self.width - col)?
+                } else {
+                    0
+                };
+                self.cur.get_mut(col + 1..=col + run).unwrap().fill(0);
+                self.ks.get_mut(col..col + run).unwrap().fill(0);
+                col += run;
+                if col + 1 >= self.width {
+                    if col + 1 == self.width {
+                        self.decode_unpredicted(col, true, Some(MAX_K))?;
+                        self.set_k(col);
+                    }
+                    col += 1;
+                    continue;
+                }
+                self.decode_unpredicted(col, true, None)?;
+                self.adapt_k(col);
+            }
+            self.set_k(col);
+            col += 1;
+        }
+        if col + 1 == self.width {
+            self.decode_unpredicted(col, false, Some(MAX_K))?;
+            self.set_k(col);
+        }
+        Ok(())
+    }
+
+    fn decode_line_of(
+        &mut self,
+        row: usize,
+        predicted: bool,
+    ) -> Result<&[i32], CrxError> {
+        core::mem::swap(&mut self.prev, &mut self.cur);
+        match (row, predicted) {
+            (0, true) => self.decode_top_line()?,
+            (_, true) => self.decode_line()?,
+            (0, false) => self.decode_unpredicted_top_line()?,
+            (_, false) => self.decode_unpredicted_line()?,
+        }
+        Ok(self.cur.get(1..=self.width).unwrap())
+    }
+}
+
+/// The quantization parameter of a subband, which each line may update.
+#[derive(Debug)]
+struct Quantization {
+    q_param: i32,
+    k: u32,
+}
+
+impl Quantization {
+    fn update(
+        &mut self,
+        bs: &mut BitStreamerBase<'_, BitOrderMSB>,
+    ) -> Result<(), CrxError> {
+        let code = get_code(bs, self.k, Q_ESCAPE_ZEROS, Q_ESCAPE_BITS)?;
+        self.q_param = self.q_param.wrapping_add(to_signed(code));
+        self.k = predict_k(self.k, code, None);
+        if self.k > MAX_Q_K {
+            return Err(CrxError::InvalidQuantization);
+        }
+        Ok(())
+    }
+
+    fn scale(&self) -> Result<i32, CrxError> {
+        let q_param = u32::try_from(self.q_param)
+            .map_err(|_err| CrxError::InvalidQuantization)?;
+        let step = *Q_STEPS.get(usize::try_from(q_param % 6).unwrap()).unwrap();
+        let octave = q_param / 6;
+        let scale = if octave >= Q_STEP_SHIFT {
+            (octave - Q_STEP_SHIFT < 32)
+                .then(|| step << (octave - Q_STEP_SHIFT))
+        } else {
+            Some(step >> (Q_STEP_SHIFT - octave))
+        };
+        scale
+            .and_then(|scale| i32::try_from(scale).ok())
+            .ok_or(CrxError::InvalidQuantization)
+    }
+}
+
+/// The bitstreams are consumed in 32-bit chunks.
+fn padded(input: &[u8]) -> Vec<u8> {
+    let mut data = input.to_vec();
+    data.resize(data.len().next_multiple_of(size_of::<u32>()), 0);
+    data
+}
+
+/// The inverse of the reversible 5/3 lifting, with symmetric extension.
+fn synthesize(low: &[i32], high: &[i32]) -> Vec<i32> {
+    let Some(last_high) = high.len().checked_sub(1) else {
+        return low.to_vec();
+    };
+    let len = low.len() + high.len();
+    let high_at = |i: usize| *high.get(i.min(last_high)).unwrap();
+    let mut out = vec![0_i32; len];
+    for (i, val) in low.iter().enumerate() {
+        let left = high_at(i.saturating_sub(1));
+        let sum = left.wrapping_add(high_at(i)).wrapping_add(2);
+        *out.get_mut(2 * i).unwrap() = val.wrapping_sub(sum >> 2);
+    }
+    for (i, val) in high.iter().enumerate() {
+        let even = *out.get(2 * i).unwrap();
+        let next = out.get(2 * i + 2).copied().unwrap_or(even);
+        *out.get_mut(2 * i + 1).unwrap() =
+            val.wrapping_add(even.wrapping_add(next) >> 1);
+    }
+    out
+}
+
+fn band_row(band: &[i32], width: usize, row: usize) -> &[i32] {
+    band.get(row * width..(row + 1) * width).unwrap()
+}
+
+/// One level of the inverse transform: the rows of the subbands first,
+/// then the columns.
+fn synthesize_level(
+    bands: [&[i32]; 4],
+    width: usize,
+    height: usize,
+) -> Vec<i32> {
+    let [low_low, high_low, low_high, high_high] = bands;
+    let (low_width, high_width) = (width.div_ceil(2), width / 2);
+    let rows = |low: &[i32], high: &[i32], count: usize| {
+        (0..count)
+            .map(|row| {
+                synthesize(
+                    band_row(low, low_width, row),
+                    band_row(high, high_width, row),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    let low_rows = rows(low_low, high_low, height.div_ceil(2));
+    let high_rows = rows(low_high, high_high, height / 2);
+    let mut out = vec![0; width * height];
+    for col in 0..width {
+        let column = |lines: &[Vec<i32>]| {
+            lines
+                .iter()
+                .map(|row| *row.get(col).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let samples = synthesize(&column(&low_rows), &column(&high_rows));
+        for (row, val) in samples.into_iter().enumerate() {
+            *out.get_mut(row * width + col).unwrap() = val;
+        }
+    }
+    out
+}
+
+/// The Canon CRX compression of CR3: the image is split into four planes,
+/// which are split into tiles. Each plane of each tile is either coded
+/// losslessly, or transformed by up to three levels of a 5/3 wavelet,
+/// whose subbands are quantized ("C-RAW").
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct CrxDecompressor<'a> {
+    header: CrxHeader,
+    /// The tiles, past their headers.
+    data: &'a [u8],
+    tiles: Vec<Tile>,
+}
+
+impl<'a> CrxDecompressor<'a> {
+    fn parse_subbands(
+        header: &CrxHeader,
+        headers: &mut Headers<'_>,
+        plane_offset: usize,
+    ) -> Result<Vec<Subband>, CrxError> {
+        let mut subbands = vec![];
+        let mut offset = plane_offset;
+        for index in 0..=3 * header.levels {
+            let err = CrxError::InvalidSubbandHeader;
+            let (v2, rest) = headers.next(
+                SUBBAND_SIGNATURE,
+                &[HEADER_SIZE, EXTENDED_HEADER_SIZE],
+                err,
+            )?;
+            let size = read_usize(rest, 0).unwrap();
+            let flags = read_u32(rest, 4).unwrap();
+            if flags >> 28 != index
+                || usize::from(v2) != rest.len() / usize::from(HEADER_SIZE) - 1
+            {
+                return Err(err);
+            }
+            // The data may be followed by some padding.
+            let (padding, q_param, q_updates) = if v2 {
+                if (flags >> 16) & 0xFFF != 0 || read_u16(rest, 14) != Some(0) {
+                    return Err(err);
+                }
+                if header.levels != 0 {
+                    return Err(CrxError::UnsupportedQuantization);
+                }
+                (usize::from(read_u16(rest, 12).unwrap()), 0, false)
+            } else {
+                (
+                    usize::try_from(flags & 0x7_FFFF).unwrap(),
+                    i32::try_from((flags >> 19) & 0xFF).unwrap(),
+                    flags & 0x800_0000 != 0,
+                )
+            };
+            subbands.push(Subband {
+                offset,
+                size: size.checked_sub(padding).ok_or(err)?,
+                q_param,
+                q_updates,
+            });
+            offset += size;
+        }
+        Ok(subbands)
+    }
+
+    fn parse_plane(
+        header: &CrxHeader,
+        headers: &mut Headers<'_>,
+        index: u32,
+        offset: usize,
+    ) -> Result<(Plane, usize), CrxError> {
+        let err = CrxError::InvalidPlaneHeader;
+        let (_, rest) = headers.next(PLANE_SIGNATURE, &[HEADER_SIZE], err)?;
+        let flags = read_u32(rest, 4).unwrap();
+        if flags >> 28 != index || flags & 0xFF_FFFF != 0 {
+            return Err(err);
+        }
+        let size = read_usize(rest, 0).unwrap();
+        let predicted = flags & 0x800_0000 != 0;
+        if (flags >> 25) & 3 != 0 {
+            if header.levels != 0 || !predicted {
+                return Err(err);
+            }
+            return Err(CrxError::UnsupportedRounding);
+        }
+        let subbands = Self::parse_subbands(header, headers, offset)?;
+        Ok((
+            Plane {
+                predicted,
+                subbands,
+            },
+            size,
+        ))
+    }
+
+    /// The tile header, then those of its planes.
+    fn parse_tile(
+        header: &CrxHeader,
+        headers: &mut Headers<'_>,
+        index: usize,
+        offset: usize,
+    ) -> Result<(Vec<Plane>, usize), CrxError> {
+        let err = CrxError::InvalidTileHeader;
+        let (v2, rest) = headers.next(
+            TILE_SIGNATURE,
+            &[HEADER_SIZE, EXTENDED_HEADER_SIZE],
+            err,
+        )?;
+        let extended = rest.len() == usize::from(EXTENDED_HEADER_SIZE);
+        let tail = if extended { EXTENDED_TILE_TAIL } else { 0 };
+        if (extended && !v2)
+            || read_u16(rest, 4).map(usize::from) != Some(index)
+            || read_u16(rest, 6) != Some(tail)
+            || (extended && read_u16(rest, 14) != Some(0))
+        {
+            return Err(err);
+        }
+        let size = read_usize(rest, 0).unwrap();
+        // The quantization tables of the tile precede its planes.
+        let (qp_size, extra_size) = if extended {
+            (
+                read_usize(rest, 8).unwrap(),
+                usize::from(read_u16(rest, 12).unwrap()),
+            )
+        } else {
+            (0, 0)
+        };
+        if extended && header.version == VERSION_2 && header.levels != 0 {
+            return Err(CrxError::UnsupportedQuantization);
+        }
+        let mut plane_offset = offset + qp_size + extra_size;
+        let mut planes = vec![];
+        for plane in 0..header.planes {
+            let (plane, plane_size) =
+                Self::parse_plane(header, headers, plane, plane_offset)?;
+            planes.push(plane);
+            plane_offset += plane_size;
+        }
+        Ok((planes, size))
+    }
+
+    /// `input` holds the tile headers, then the tiles.
+    #[inline]
+    pub fn new(header: CrxHeader, input: &'a [u8]) -> Result<Self, CrxError> {
+        if header.planes != NUM_PLANES || header.enc_type != 0 {
+            return Err(CrxError::UnsupportedEncoding);
+        }
+        let (plane_width, plane_height) = (header.width / 2, header.height / 2);
+        let (tile_width, tile_height) =
+            (header.tile_width / 2, header.tile_height / 2);
+        let (cols, rows) = (
+            plane_width.div_ceil(tile_width),
+            plane_height.div_ceil(tile_height),
+        );
+        let last_width = plane_width - tile_width * (cols - 1);
+        let last_height = plane_height - tile_height * (rows - 1);
+        if tile_width.min(tile_height).min(last_width).min(last_height)
+            < MIN_TILE_DIM
+            || plane_width.max(plane_height) > MAX_PLANE_DIM
+        {
+            return Err(CrxError::InvalidImageHeader);
+        }
+        // The tiles of a transformed image overlap, which is unsupported.
+        if header.levels != 0 && (cols > 1 || rows > 1) {
+            return Err(CrxError::UnsupportedTiling);
+        }
+        let (headers, data) = input
+            .split_at_checked(header.mdat_header_size)
+            .ok_or(CrxError::TruncatedData)?;
+        let mut headers = Headers {
+            data: headers,
+            pos: 0,
+        };
+        let mut tiles = vec![];
+        let mut offset = 0;
+        for index in 0..rows * cols {
+            let (row, col) = (index / cols, index % cols);
+            let (planes, size) =
+                Self::parse_tile(&header, &mut headers, index, offset)?;
+            tiles.push(Tile {
+                row: row * tile_height,
+                col: col * tile_width,
+                width: if col + 1 == cols {
+                    last_width
+                } else {
+                    tile_width
+                },
+                height: if row + 1 == rows {
+                    last_height
+                } else {
+                    tile_height
+                },
+                planes,
+            });
+            offset += size;
+        }
+        let in_bounds = tiles
+            .iter()
+            .flat_map(|tile| &tile.planes)
+            .flat_map(|plane| &plane.subbands)
+            .all(|subband| subband.offset + subband.size <= data.len());
+        if !in_bounds {
+            return Err(CrxError::TruncatedData);
+        }
+        Ok(Self {
+            header,
+            data,
+            tiles,
+        })
+    }
+
+    #[inline]
+    pub const fn dims(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        Dimensions2D::new(
+            RowLength::new(core::num::NonZero::new(self.header.width).unwrap()),
+            RowCount::new(core::num::NonZero::new(self.header.height).unwrap()),
+        )
+    }
+
+    /// Each line of a transformed subband may update its quantization, and
+    /// is dequantized.
+    fn decode_subband(
+        &self,
+        subband: &Subband,
+        (width, height): (usize, usize),
+        predicted: bool,
+    ) -> Result<Vec<i32>, CrxError> {
+        if subband.size == 0 || width == 0 {
+            return Ok(vec![0; width * height]);
+        }
+        let data = padded(
+            self.data
+                .get(subband.offset..subband.offset + subband.size)
+                .unwrap(),
+        );
+        let mut decoder = BandDecoder::new(&data, width)?;
+        let mut quantization = Quantization {
+            q_param: subband.q_param,
+            k: 0,
+        };
+        let mut out = Vec::with_capacity(width * height);
+        for row in 0..height {
+            if self.header.levels == 0 {
+                out.extend(decoder.decode_line_of(row, predicted)?);
+                continue;
+            }
+            if subband.q_updates {
+                quantization.update(&mut decoder.bs)?;
+            }
+            let scale = quantization.scale()?;
+            let line = decoder.decode_line_of(row, predicted)?;
+            out.extend(line.iter().map(|val| val.wrapping_mul(scale)));
+        }
+        Ok(out)
+    }
+
+    /// The samples of a plane of a tile, relative to the median.
+    fn decode_plane(
+        &self,
+        tile: &Tile,
+        plane: &Plane,
+    ) -> Result<Vec<i32>, CrxError> {
+        let levels = self.header.levels;
+        let mut subbands = plane
+            .subbands
+            .iter()
+            .zip(subband_dims(tile.width, tile.height, levels))
+            .enumerate()
+            .map(|(index, (subband, dims))| {
+                self.decode_subband(
+                    subband,
+                    dims,
+                    index == 0 && plane.predicted,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter();
+        let mut low = subbands.next().unwrap();
+        let dims = level_dims(tile.width, tile.height, levels);
+        for (width, height) in dims.into_iter().rev().skip(1) {
+            let [high_low, low_high, high_high] =
+                [(); 3].map(|()| subbands.next().unwrap());
+            low = synthesize_level(
+                [&low, &high_low, &low_high, &high_high],
+                width,
+                height,
+            );
+        }
+        Ok(low)
+    }
+
+    #[inline(never)]
+    pub fn decode(
+        &self,
+        output: &mut Array2DRefMut<'_, u16>,
+    ) -> Result<(), CrxError> {
+        if output.dims() != self.dims() {
+            return Err(CrxError::OutputDimensionsMismatch);
+        }
+        let max = (1_i64 << self.header.bits) - 1;
+        let median = 1_i64 << (self.header.median_bits - 1);
+        for tile in &self.tiles {
+            for (index, plane) in tile.planes.iter().enumerate() {
+                let samples = self.decode_plane(tile, plane)?;
+                let pos = index ^ self.header.cfa_layout;
+                for (row, line) in samples.chunks(tile.width).enumerate() {
+                    for (col, val) in line.iter().enumerate() {
+                        let sample = (i64::from(*val) + median).clamp(0, max);
+                        let row = 2 * (tile.row + row) + (pos >> 1);
+                        let col = 2 * (tile.col + col) + (pos & 1);
+                        output[Coord2D::new(
+                            RowIndex::new(row),
+                            ColIndex::new(col),
+                        )] = u16::try_from(sample).unwrap();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
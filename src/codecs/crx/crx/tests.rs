@@ -0,0 +1,853 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_std::coord_common::{RowLength, RowPitch};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+
+use super::{
+    CrxDecompressor, CrxError, CrxHeader, ESCAPE_BITS, ESCAPE_ZEROS, MAX_K,
+    Q_ESCAPE_BITS, Q_ESCAPE_ZEROS, RUN_REMAINDER_BITS, RUN_STEPS, predict_k,
+    predict_median,
+};
+
+/// A unary prefix of `zeros`, terminated by a one bit.
+fn put_unary(bits: &mut BitStreamBuilder, zeros: u32) {
+    for _ in 0..zeros {
+        bits.put(0, 1);
+    }
+    bits.put(1, 1);
+}
+
+/// A unary prefix, then the low `k` bits, or the escape.
+fn put_code(
+    bits: &mut BitStreamBuilder,
+    code: u32,
+    k: u32,
+    escape: (u32, u32),
+) {
+    let (escape_zeros, escape_bits) = escape;
+    if code >> k >= escape_zeros {
+        put_unary(bits, escape_zeros);
+        bits.put(code, escape_bits);
+    } else {
+        put_unary(bits, code >> k);
+        bits.put(code & ((1 << k) - 1), k);
+    }
+}
+
+fn to_code(val: i32) -> u32 {
+    ((val << 1) ^ (val >> 31)).cast_unsigned()
+}
+
+/// The CRX headers are big-endian.
+fn be16(val: u16) -> [u8; 2] {
+    let mut bytes = val.to_le_bytes();
+    bytes.reverse();
+    bytes
+}
+
+fn be32(val: u32) -> [u8; 4] {
+    let mut bytes = val.to_le_bytes();
+    bytes.reverse();
+    bytes
+}
+
+/// The encoder of the lines of a subband, which mirrors the decoder.
+#[derive(Debug)]
+struct BandEncoder {
+    bits: BitStreamBuilder,
+    width: usize,
+    k: u32,
+    run_param: usize,
+    prev: Vec<i32>,
+    cur: Vec<i32>,
+    ks: Vec<u32>,
+}
+
+impl BandEncoder {
+    fn new(width: usize) -> Self {
+        Self {
+            bits: BitStreamBuilder::new(),
+            width,
+            k: 0,
+            run_param: 0,
+            prev: vec![0; width + 2],
+            cur: vec![0; width + 2],
+            ks: vec![0; width + 1],
+        }
+    }
+
+    fn put_code(&mut self, code: u32) {
+        put_code(&mut self.bits, code, self.k, (ESCAPE_ZEROS, ESCAPE_BITS));
+    }
+
+    fn left(&self, col: usize) -> i32 {
+        *self.cur.get(col).unwrap()
+    }
+
+    fn above(&self, col: usize, offset: usize) -> i32 {
+        *self.prev.get(col + offset).unwrap()
+    }
+
+    fn set(&mut self, col: usize, val: i32) {
+        *self.cur.get_mut(col + 1).unwrap() = val;
+    }
+
+    /// A run of `run` samples, of up to `len`, after its first one bit.
+    fn put_run(&mut self, run: usize, len: usize) {
+        let mut count = 1;
+        if run == len {
+            loop {
+                self.bits.put(1, 1);
+                count += RUN_STEPS.get(self.run_param).unwrap();
+                if count > len {
+                    return;
+                }
+                self.run_param = (self.run_param + 1).min(RUN_STEPS.len() - 1);
+                if count == len {
+                    return;
+                }
+            }
+        }
+        while count + RUN_STEPS.get(self.run_param).unwrap() <= run {
+            self.bits.put(1, 1);
+            count += RUN_STEPS.get(self.run_param).unwrap();
+            self.run_param = (self.run_param + 1).min(RUN_STEPS.len() - 1);
+        }
+        self.bits.put(0, 1);
+        let nbits = *RUN_REMAINDER_BITS.get(self.run_param).unwrap();
+        self.bits.put(u32::try_from(run - count).unwrap(), nbits);
+        self.run_param = self.run_param.saturating_sub(1);
+    }
+
+    /// The longest run of `val` from `col`, after its flag.
+    fn put_flagged_run(&mut self, line: &[i32], col: usize, val: i32) -> usize {
+        let run = line
+            .get(col..)
+            .unwrap()
+            .iter()
+            .take_while(|sample| **sample == val)
+            .count();
+        if run == 0 {
+            self.bits.put(0, 1);
+        } else {
+            self.bits.put(1, 1);
+            self.put_run(run, self.width - col);
+        }
+        self.cur.get_mut(col + 1..=col + run).unwrap().fill(val);
+        run
+    }
+
+    fn put_predicted(&mut self, line: &[i32], col: usize, median: bool) {
+        let pred = if median {
+            predict_median(
+                self.left(col),
+                self.above(col, 1),
+                self.above(col, 0),
+            )
+        } else {
+            self.above(col, 1)
+        };
+        let val = *line.get(col).unwrap();
+        let code = to_code(val.wrapping_sub(pred));
+        self.put_code(code);
+        self.set(col, val);
+        let hint = if col + 1 < self.width {
+            let delta = self.above(col, 2).wrapping_sub(self.above(col, 1));
+            (code + 2 * delta.unsigned_abs()) >> 1
+        } else {
+            code
+        };
+        self.k = predict_k(self.k, hint, Some(MAX_K));
+    }
+
+    fn finish_predicted_line(&mut self) {
+        let last = self.left(self.width);
+        self.set(self.width, last + 1);
+    }
+
+    fn put_top_line(&mut self, line: &[i32]) {
+        *self.cur.first_mut().unwrap() = 0;
+        let mut col = 0;
+        while col < self.width {
+            let mut pred = self.left(col);
+            if self.width - col > 1 && pred == 0 {
+                col += self.put_flagged_run(line, col, 0);
+                if col == self.width {
+                    break;
+                }
+                pred = 0;
+            }
+            let val = *line.get(col).unwrap();
+            let code = to_code(val - pred);
+            self.put_code(code);
+            self.set(col, val);
+            self.k = predict_k(self.k, code, Some(MAX_K));
+            col += 1;
+        }
+        self.finish_predicted_line();
+    }
+
+    fn put_line(&mut self, line: &[i32]) {
+        *self.cur.first_mut().unwrap() = self.above(0, 1);
+        let mut col = 0;
+        while col < self.width {
+            let left = self.left(col);
+            if self.width - col > 1
+                && left == self.above(col, 1)
+                && left == self.above(col, 2)
+            {
+                col += self.put_flagged_run(line, col, left);
+                if col < self.width {
+                    self.put_predicted(line, col, false);
+                    col += 1;
+                }
+            } else {
+                self.put_predicted(line, col, true);
+                col += 1;
+            }
+        }
+        self.finish_predicted_line();
+    }
+
+    fn put_unpredicted(
+        &mut self,
+        line: &[i32],
+        col: usize,
+        nonzero: bool,
+        max_k: Option<u32>,
+    ) {
+        let val = *line.get(col).unwrap();
+        let code = to_code(val) - u32::from(nonzero);
+        self.put_code(code);
+        self.set(col, val);
+        self.k = predict_k(self.k, code, max_k);
+    }
+
+    fn set_k(&mut self, col: usize) {
+        *self.ks.get_mut(col).unwrap() = self.k;
+    }
+
+    fn put_unpredicted_top_line(&mut self, line: &[i32]) {
+        *self.prev.first_mut().unwrap() = 0;
+        *self.cur.first_mut().unwrap() = 0;
+        let mut col = 0;
+        while col < self.width {
+            if self.width - col > 1 && self.left(col) == 0 {
+                let run = self.put_flagged_run(line, col, 0);
+                self.ks.get_mut(col..col + run).unwrap().fill(0);
+                col += run;
+                if col == self.width {
+                    break;
+                }
+                self.put_unpredicted(line, col, true, Some(MAX_K));
+            } else {
+                self.put_unpredicted(line, col, false, Some(MAX_K));
+            }
+            self.set_k(col);
+            col += 1;
+        }
+        self.set(self.width, 0);
+    }
+
+    fn adapt_k(&mut self, col: usize) {
+        if *self.ks.get(col + 1).unwrap() <= self.k + 1 {
+            self.k = self.k.min(MAX_K);
+        } else {
+            self.k += 1;
+        }
+    }
+
+    fn put_unpredicted_line(&mut self, line: &[i32]) {
+        let mut col = 0;
+        while col + 1 < self.width {
+            if self.above(col, 2) != 0
+                || self.above(col, 1) != 0
+                || self.left(col) != 0
+            {
+                self.put_unpredicted(line, col, false, None);
+                self.adapt_k(col);
+            } else {
+                let run = self.put_flagged_run(line, col, 0);
+                self.ks.get_mut(col..col + run).unwrap().fill(0);
+                col += run;
+                if col + 1 >= self.width {
+                    if col + 1 == self.width {
+                        self.put_unpredicted(line, col, true, Some(MAX_K));
+                        self.set_k(col);
+                    }
+                    col += 1;
+                    continue;
+                }
+                self.put_unpredicted(line, col, true, None);
+                self.adapt_k(col);
+            }
+            self.set_k(col);
+            col += 1;
+        }
+        if col + 1 == self.width {
+            self.put_unpredicted(line, col, false, Some(MAX_K));
+            self.set_k(col);
+        }
+    }
+}
+
+/// The lines of a subband, each preceded by an update of the quantization
+/// parameter, if there are any.
+fn encode_band(
+    band: &[i32],
+    width: usize,
+    predicted: bool,
+    q_updates: Option<&[i32]>,
+) -> Vec<u8> {
+    if width == 0 || band.is_empty() {
+        return vec![];
+    }
+    let mut encoder = BandEncoder::new(width);
+    let mut q_k = 0;
+    for (row, line) in band.chunks(width).enumerate() {
+        if let Some(update) = q_updates.and_then(|updates| updates.get(row)) {
+            let code = to_code(*update);
+            let escape = (Q_ESCAPE_ZEROS, Q_ESCAPE_BITS);
+            put_code(&mut encoder.bits, code, q_k, escape);
+            q_k = predict_k(q_k, code, None);
+        }
+        core::mem::swap(&mut encoder.prev, &mut encoder.cur);
+        match (row, predicted) {
+            (0, true) => encoder.put_top_line(line),
+            (_, true) => encoder.put_line(line),
+            (0, false) => encoder.put_unpredicted_top_line(line),
+            (_, false) => encoder.put_unpredicted_line(line),
+        }
+    }
+    encoder.bits.finish(BitOrder::MSB)
+}
+
+/// The forward of the 5/3 lifting, which the synthesis exactly inverts.
+fn analyze(samples: &[i32]) -> (Vec<i32>, Vec<i32>) {
+    let len = samples.len();
+    if len < 2 {
+        return (samples.to_vec(), vec![]);
+    }
+    let at = |i: usize| *samples.get(i).unwrap();
+    let high = (0..len / 2)
+        .map(|i| {
+            let next = samples.get(2 * i + 2).copied().unwrap_or(at(2 * i));
+            at(2 * i + 1) - ((at(2 * i) + next) >> 1)
+        })
+        .collect::<Vec<_>>();
+    let high_at = |i: usize| *high.get(i.min(high.len() - 1)).unwrap();
+    let low = (0..len.div_ceil(2))
+        .map(|i| {
+            let sum = high_at(i.saturating_sub(1)) + high_at(i) + 2;
+            at(2 * i) + (sum >> 2)
+        })
+        .collect();
+    (low, high)
+}
+
+/// One level of the transform: the columns first, then the rows. Returns
+/// the lowpass band, then the highpass ones.
+fn analyze_level(plane: &[i32], width: usize) -> [Vec<i32>; 4] {
+    let height = plane.len() / width;
+    let (mut low_rows, mut high_rows) = (
+        vec![vec![0; width]; height.div_ceil(2)],
+        vec![vec![0; width]; height / 2],
+    );
+    for col in 0..width {
+        let column = plane.iter().skip(col).step_by(width).copied();
+        let (low, high) = analyze(&column.collect::<Vec<_>>());
+        for (row, val) in low.into_iter().enumerate() {
+            *low_rows.get_mut(row).unwrap().get_mut(col).unwrap() = val;
+        }
+        for (row, val) in high.into_iter().enumerate() {
+            *high_rows.get_mut(row).unwrap().get_mut(col).unwrap() = val;
+        }
+    }
+    let split = |rows: &[Vec<i32>]| {
+        let (low, high): (Vec<_>, Vec<_>) =
+            rows.iter().map(|row| analyze(row)).unzip();
+        (low.concat(), high.concat())
+    };
+    let (low_low, high_low) = split(&low_rows);
+    let (low_high, high_high) = split(&high_rows);
+    [low_low, high_low, low_high, high_high]
+}
+
+/// The subbands of a plane, and their widths, in the order in which they
+/// are stored.
+fn analyze_plane(
+    plane: &[i32],
+    width: usize,
+    levels: u32,
+) -> Vec<(Vec<i32>, usize)> {
+    let mut low = (plane.to_vec(), width);
+    let mut highs = vec![];
+    for _ in 0..levels {
+        let [low_low, high_low, low_high, high_high] =
+            analyze_level(&low.0, low.1);
+        let (low_width, high_width) = (low.1.div_ceil(2), low.1 / 2);
+        highs.push([
+            (high_low, high_width),
+            (low_high, low_width),
+            (high_high, high_width),
+        ]);
+        low = (low_low, low_width);
+    }
+    let mut bands = vec![low];
+    bands.extend(highs.into_iter().rev().flatten());
+    bands
+}
+
+#[derive(Debug, Clone)]
+struct Band {
+    data: Vec<u8>,
+    q_param: u32,
+    q_updates: bool,
+}
+
+impl Band {
+    fn new(data: Vec<u8>) -> Self {
+        Self {
+            data,
+            q_param: 4,
+            q_updates: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Plane {
+    predicted: bool,
+    bands: Vec<Band>,
+}
+
+fn header_bytes(signature: u16, size: u32, fields: &[u32]) -> Vec<u8> {
+    let mut out = be16(signature).to_vec();
+    out.extend(be16(u16::try_from(4 * (fields.len() + 1)).unwrap()));
+    out.extend(be32(size));
+    out.extend(fields.iter().flat_map(|field| be32(*field)));
+    out
+}
+
+fn to_u32(val: usize) -> u32 {
+    u32::try_from(val).unwrap()
+}
+
+/// The tile headers, then the tiles.
+fn build(header: &mut CrxHeader, tiles: &[Vec<Plane>]) -> Vec<u8> {
+    let mut headers = vec![];
+    let mut data = vec![];
+    for (tile_index, planes) in tiles.iter().enumerate() {
+        let tile = planes
+            .iter()
+            .flat_map(|plane| &plane.bands)
+            .flat_map(|band| band.data.clone())
+            .collect::<Vec<_>>();
+        headers.extend(header_bytes(
+            0xFF01,
+            to_u32(tile.len()),
+            &[to_u32(tile_index) << 16],
+        ));
+        for (plane_index, plane) in planes.iter().enumerate() {
+            let plane_size = plane.bands.iter().map(|band| band.data.len());
+            let plane_flags = (to_u32(plane_index) << 28)
+                | (u32::from(plane.predicted) << 27);
+            headers.extend(header_bytes(
+                0xFF02,
+                to_u32(plane_size.sum()),
+                &[plane_flags],
+            ));
+            for (band_index, band) in plane.bands.iter().enumerate() {
+                let band_flags = (to_u32(band_index) << 28)
+                    | (u32::from(band.q_updates) << 27)
+                    | (band.q_param << 19);
+                let band_size = to_u32(band.data.len());
+                headers.extend(header_bytes(0xFF03, band_size, &[band_flags]));
+            }
+        }
+        data.extend(tile);
+    }
+    header.mdat_header_size = headers.len();
+    headers.extend(data);
+    headers
+}
+
+fn crx_header(
+    width: usize,
+    height: usize,
+    tile_dims: (usize, usize),
+) -> CrxHeader {
+    CrxHeader {
+        version: 0x100,
+        width,
+        height,
+        tile_width: tile_dims.0,
+        tile_height: tile_dims.1,
+        bits: 14,
+        planes: 4,
+        cfa_layout: 0,
+        enc_type: 0,
+        levels: 0,
+        median_bits: 14,
+        mdat_header_size: 0,
+    }
+}
+
+fn cmp1(header: &CrxHeader) -> Vec<u8> {
+    let mut out = be16(header.version).to_vec();
+    out.extend([0; 6]);
+    for dim in [
+        header.width,
+        header.height,
+        header.tile_width,
+        header.tile_height,
+    ] {
+        out.extend(be32(to_u32(dim)));
+    }
+    out.extend([
+        u8::try_from(header.bits).unwrap(),
+        u8::try_from((header.planes << 4) | to_u32(header.cfa_layout)).unwrap(),
+        u8::try_from((header.enc_type << 4) | header.levels).unwrap(),
+        0,
+    ]);
+    out.extend(be32(to_u32(header.mdat_header_size)));
+    out
+}
+
+/// Mostly flat, with smooth and noisy blocks.
+fn test_image(width: usize, height: usize) -> Vec<Vec<u16>> {
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| {
+                    let sample = match (col / 6 + row / 5) % 3 {
+                        0 => 0x2000,
+                        1 => 0x1F9C + (row * 37 + col * 11) % 200,
+                        _ => (row * 977 + col * 313 + 5) % 0x4000,
+                    };
+                    u16::try_from(sample).unwrap()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The samples of a plane of a tile, relative to the median.
+fn plane_samples(
+    image: &[Vec<u16>],
+    header: &CrxHeader,
+    (row, col, width, height): (usize, usize, usize, usize),
+    plane: usize,
+) -> Vec<i32> {
+    let pos = plane ^ header.cfa_layout;
+    let median = 1 << (header.median_bits - 1);
+    (0..height)
+        .flat_map(|r| (0..width).map(move |c| (r, c)))
+        .map(|(r, c)| {
+            let line = image.get(2 * (row + r) + (pos >> 1)).unwrap();
+            i32::from(*line.get(2 * (col + c) + (pos & 1)).unwrap()) - median
+        })
+        .collect()
+}
+
+fn encode_plane(
+    samples: &[i32],
+    width: usize,
+    header: &CrxHeader,
+    predicted: bool,
+) -> Plane {
+    let bands = analyze_plane(samples, width, header.levels)
+        .into_iter()
+        .enumerate()
+        .map(|(index, (band, width))| {
+            Band::new(encode_band(&band, width, predicted && index == 0, None))
+        })
+        .collect();
+    Plane { predicted, bands }
+}
+
+fn encode(
+    image: &[Vec<u16>],
+    header: &mut CrxHeader,
+    predicted: bool,
+) -> Vec<u8> {
+    let (plane_width, plane_height) = (header.width / 2, header.height / 2);
+    let (tile_width, tile_height) =
+        (header.tile_width / 2, header.tile_height / 2);
+    let mut tiles = vec![];
+    for row in (0..plane_height).step_by(tile_height) {
+        for col in (0..plane_width).step_by(tile_width) {
+            let width = tile_width.min(plane_width - col);
+            let height = tile_height.min(plane_height - row);
+            let tile = (row, col, width, height);
+            tiles.push(
+                (0..4)
+                    .map(|plane| {
+                        let samples = plane_samples(image, header, tile, plane);
+                        encode_plane(&samples, width, header, predicted)
+                    })
+                    .collect(),
+            );
+        }
+    }
+    build(header, &tiles)
+}
+
+fn decode(header: &CrxHeader, input: &[u8]) -> Result<Vec<Vec<u16>>, CrxError> {
+    let decompressor = CrxDecompressor::new(*header, input)?;
+    let width = header.width;
+    let mut out = vec![0_u16; width * header.height];
+    let mut output = Array2DRefMut::new(
+        &mut out,
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowPitch::new(core::num::NonZero::new(width).unwrap()),
+    );
+    decompressor.decode(&mut output)?;
+    Ok(out.chunks(width).map(<[u16]>::to_vec).collect())
+}
+
+fn round_trip(header: &mut CrxHeader, predicted: bool) {
+    let image = test_image(header.width, header.height);
+    let input = encode(&image, header, predicted);
+    assert_eq!(decode(header, &input), Ok(image));
+}
+
+#[test]
+fn header_test() {
+    let mut header = crx_header(96, 88, (48, 44));
+    header.mdat_header_size = 0x100;
+    assert_eq!(CrxHeader::parse(&cmp1(&header)), Ok(header));
+    let invalid: [fn(&mut CrxHeader); 6] = [
+        |h| h.version = 0x300,
+        |h| h.bits = 15,
+        |h| h.width = 95,
+        |h| h.tile_height = 90,
+        |h| h.levels = 4,
+        |h| h.mdat_header_size = 0,
+    ];
+    for invalidate in invalid {
+        let mut invalidated = header;
+        invalidate(&mut invalidated);
+        assert_eq!(
+            CrxHeader::parse(&cmp1(&invalidated)),
+            Err(CrxError::InvalidImageHeader)
+        );
+    }
+    let truncated = cmp1(&header);
+    assert_eq!(
+        CrxHeader::parse(truncated.get(..31).unwrap()),
+        Err(CrxError::InvalidImageHeader)
+    );
+}
+
+#[test]
+fn median_bits_test() {
+    let header = crx_header(96, 88, (48, 44));
+    let mut extended = cmp1(&CrxHeader {
+        mdat_header_size: 0x100,
+        ..header
+    });
+    extended.resize(0x60, 0);
+    *extended.get_mut(32).unwrap() = 0x80;
+    *extended.get_mut(56).unwrap() = 0x40;
+    *extended.get_mut(84).unwrap() = 12;
+    assert_eq!(CrxHeader::parse(&extended).unwrap().median_bits, 12);
+}
+
+#[test]
+fn lossless_test() {
+    // Two by two tiles, of which the last ones are narrower.
+    for predicted in [true, false] {
+        round_trip(&mut crx_header(96, 88, (48, 44)), predicted);
+        round_trip(&mut crx_header(92, 88, (48, 44)), predicted);
+    }
+}
+
+#[test]
+fn cfa_layout_test() {
+    for cfa_layout in 1..4 {
+        let mut header = crx_header(44, 44, (44, 44));
+        header.cfa_layout = cfa_layout;
+        round_trip(&mut header, true);
+    }
+}
+
+#[test]
+fn wavelet_test() {
+    for levels in 1..=3 {
+        for predicted in [true, false] {
+            let mut header = crx_header(50, 46, (50, 46));
+            header.levels = levels;
+            round_trip(&mut header, predicted);
+        }
+    }
+}
+
+/// A transformed plane whose highpass bands are all empty.
+fn lowpass_plane(
+    low: &[i32],
+    width: usize,
+    q_updates: Option<&[i32]>,
+) -> Plane {
+    let mut band = Band::new(encode_band(low, width, true, q_updates));
+    band.q_updates = q_updates.is_some();
+    let mut bands = vec![Band::new(vec![]); 4];
+    *bands.first_mut().unwrap() = band;
+    Plane {
+        predicted: true,
+        bands,
+    }
+}
+
+#[test]
+fn dequantization_test() {
+    // The quantization step of the parameter 12 is two.
+    let mut header = crx_header(44, 44, (44, 44));
+    header.levels = 1;
+    let mut plane = lowpass_plane(&[25; 11 * 11], 11, None);
+    plane.bands.first_mut().unwrap().q_param = 12;
+    let input = build(&mut header, &[vec![plane; 4]]);
+    assert_eq!(decode(&header, &input), Ok(vec![vec![0x2000 + 50; 44]; 44]));
+}
+
+#[test]
+fn quantization_updates_test() {
+    // The step doubles with each line of the lowpass band, whose lines
+    // the highpass lines interpolate.
+    let mut header = crx_header(44, 44, (44, 44));
+    header.levels = 1;
+    let mut updates = vec![0];
+    updates.extend([6; 10]);
+    let plane = lowpass_plane(&[1; 11 * 11], 11, Some(&updates));
+    let input = build(&mut header, &[vec![plane; 4]]);
+    let steps = (0..11).map(|row| 1 << row).collect::<Vec<i32>>();
+    let (low, high) = (steps.iter(), steps.iter().skip(1));
+    let interpolated = low.zip(high.chain([steps.last().unwrap()]));
+    let plane_rows = interpolated
+        .flat_map(|(even, next)| [*even, (even + next) >> 1])
+        .collect::<Vec<_>>();
+    let expected = plane_rows
+        .iter()
+        .flat_map(|val| [u16::try_from(0x2000 + val).unwrap(); 2])
+        .map(|sample| vec![sample; 44])
+        .collect();
+    assert_eq!(decode(&header, &input), Ok(expected));
+}
+
+#[test]
+fn invalid_quantization_test() {
+    let mut header = crx_header(44, 44, (44, 44));
+    header.levels = 1;
+    let plane = lowpass_plane(&[1; 11 * 11], 11, Some(&[-5]));
+    let input = build(&mut header, &[vec![plane; 4]]);
+    assert_eq!(decode(&header, &input), Err(CrxError::InvalidQuantization));
+}
+
+#[test]
+fn unsupported_test() {
+    let mut header = crx_header(96, 88, (48, 44));
+    header.levels = 1;
+    assert_eq!(
+        CrxDecompressor::new(header, &[0; 4]).unwrap_err(),
+        CrxError::UnsupportedTiling
+    );
+    header.enc_type = 1;
+    assert_eq!(
+        CrxDecompressor::new(header, &[0; 4]).unwrap_err(),
+        CrxError::UnsupportedEncoding
+    );
+    assert_eq!(
+        CrxDecompressor::new(crx_header(40, 40, (40, 40)), &[0; 4])
+            .unwrap_err(),
+        CrxError::InvalidImageHeader
+    );
+}
+
+fn flat_input(header: &mut CrxHeader) -> Vec<u8> {
+    let plane = Plane {
+        predicted: true,
+        bands: vec![Band::new(vec![0; 4])],
+    };
+    build(header, &[vec![plane; 4]])
+}
+
+#[test]
+fn invalid_headers_test() {
+    // The signature of the tile, the index of the plane, and the index of
+    // the subband.
+    for (pos, err) in [
+        (0, CrxError::InvalidTileHeader),
+        (20, CrxError::InvalidPlaneHeader),
+        (32, CrxError::InvalidSubbandHeader),
+    ] {
+        let mut header = crx_header(44, 44, (44, 44));
+        let mut input = flat_input(&mut header);
+        *input.get_mut(pos).unwrap() ^= 0x10;
+        assert_eq!(CrxDecompressor::new(header, &input).unwrap_err(), err);
+    }
+}
+
+#[test]
+fn unsupported_rounding_test() {
+    let mut header = crx_header(44, 44, (44, 44));
+    let mut input = flat_input(&mut header);
+    *input.get_mut(20).unwrap() |= 0x02;
+    assert_eq!(
+        CrxDecompressor::new(header, &input).unwrap_err(),
+        CrxError::UnsupportedRounding
+    );
+}
+
+#[test]
+fn truncated_test() {
+    let mut header = crx_header(44, 44, (44, 44));
+    let mut input = flat_input(&mut header);
+    input.pop();
+    assert_eq!(
+        CrxDecompressor::new(header, &input).unwrap_err(),
+        CrxError::TruncatedData
+    );
+    let image = test_image(44, 44);
+    let samples = plane_samples(&image, &header, (0, 0, 22, 22), 0);
+    let mut data = encode_band(&samples, 22, true, None);
+    data.truncate(4);
+    let plane = Plane {
+        predicted: true,
+        bands: vec![Band::new(data)],
+    };
+    let truncated = build(&mut header, &[vec![plane; 4]]);
+    assert_eq!(decode(&header, &truncated), Err(CrxError::TruncatedData));
+}
+
+#[test]
+fn invalid_run_test() {
+    // A run of 21 samples, then a remainder of three, in a line of 22.
+    let mut bits = BitStreamBuilder::new();
+    bits.put(0x7FF, 11);
+    bits.put(0, 1);
+    bits.put(3, 2);
+    let plane = Plane {
+        predicted: false,
+        bands: vec![Band::new(bits.finish(BitOrder::MSB))],
+    };
+    let mut header = crx_header(44, 44, (44, 44));
+    let input = build(&mut header, &[vec![plane; 4]]);
+    assert_eq!(decode(&header, &input), Err(CrxError::InvalidRun));
+}
+
+#[test]
+fn output_dimensions_mismatch_test() {
+    let mut header = crx_header(44, 44, (44, 44));
+    let input = flat_input(&mut header);
+    let decompressor = CrxDecompressor::new(header, &input).unwrap();
+    let mut out = vec![0_u16; 44 * 46];
+    let mut output = Array2DRefMut::new(
+        &mut out,
+        RowLength::new(core::num::NonZero::new(44).unwrap()),
+        RowPitch::new(core::num::NonZero::new(44).unwrap()),
+    );
+    assert_eq!(
+        decompressor.decode(&mut output),
+        Err(CrxError::OutputDimensionsMismatch)
+    );
+}
@@ -0,0 +1,272 @@
+use miniz_oxide::inflate::TINFLStatus;
+use miniz_oxide::inflate::core::{
+    DecompressorOxide, decompress, inflate_flags,
+};
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_std::coord_common::{Dimensions2D, RowIndex};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DeflateError {
+    InvalidData,
+    TruncatedData,
+    UnsupportedFormat,
+    OutputDimensionsMismatch,
+}
+
+impl core::fmt::Display for DeflateError {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            DeflateError::InvalidData => write!(f, "DeflateError(InvalidData)"),
+            DeflateError::TruncatedData => {
+                write!(f, "DeflateError(TruncatedData)")
+            }
+            DeflateError::UnsupportedFormat => {
+                write!(f, "DeflateError(UnsupportedFormat)")
+            }
+            DeflateError::OutputDimensionsMismatch => {
+                write!(f, "DeflateError(OutputDimensionsMismatch)")
+            }
+        }
+    }
+}
+
+/// How the samples were transformed before compression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Predictor {
+    None,
+    /// Each sample is stored as the difference to the same component of
+    /// its left neighbour.
+    Horizontal,
+    /// The bytes of each row of floats are split into planes, most
+    /// significant first, and are then each stored as the difference to
+    /// the byte one pixel before.
+    FloatingPoint,
+}
+
+/// Widens a binary floating-point number with the given exponent and
+/// mantissa widths, laid out like the IEEE-754 ones, to `f32`. This is
+/// exact for all the formats DNG uses.
+const fn widen_float(val: u32, exp_bits: u32, mant_bits: u32) -> f32 {
+    let sign = (val >> (exp_bits + mant_bits)) & 1;
+    let exp_mask = (1 << exp_bits) - 1;
+    let exp = (val >> mant_bits) & exp_mask;
+    let mut mant = val & ((1 << mant_bits) - 1);
+    let bias = (1 << (exp_bits - 1)) - 1;
+    let f32_exp = if exp == exp_mask {
+        // Infinity or NaN.
+        0xFF
+    } else if exp != 0 {
+        exp + 127 - bias
+    } else if mant == 0 {
+        0
+    } else {
+        // Subnormal, but representable as a normal `f32`.
+        let mut f32_exp = 128 - bias;
+        while mant & (1 << mant_bits) == 0 {
+            mant <<= 1;
+            f32_exp -= 1;
+        }
+        mant &= (1 << mant_bits) - 1;
+        f32_exp
+    };
+    f32::from_bits((sign << 31) | (f32_exp << 23) | (mant << (23 - mant_bits)))
+}
+
+fn float_from_bits(bits: u32, bits_per_sample: u32) -> f32 {
+    match bits_per_sample {
+        16 => widen_float(bits, 5, 10),
+        24 => widen_float(bits, 7, 16),
+        32 => f32::from_bits(bits),
+        _ => unreachable!(),
+    }
+}
+
+fn sample_from_bytes(bytes: &[u8], endianness: Endianness) -> u32 {
+    let fold = |acc: u32, byte: &u8| (acc << 8) | u32::from(*byte);
+    match endianness {
+        Endianness::Big => bytes.iter().fold(0, fold),
+        Endianness::Little => bytes.iter().rev().fold(0, fold),
+    }
+}
+
+/// A zlib stream of rows of 8- or 16-bit integer or 16-, 24- or 32-bit
+/// floating-point samples, as found in the tiles of DNGs, and of the HDR
+/// and panorama ones in particular.
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct DeflateDecompressor<'a> {
+    input: &'a [u8],
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    cpp: core::num::NonZero<usize>,
+    bits: u32,
+    predictor: Predictor,
+    endianness: Endianness,
+}
+
+impl<'a> DeflateDecompressor<'a> {
+    /// Only the first `dims` rows of the stream are decoded, so that
+    /// the tiles at the image edges can be decoded partially.
+    ///
+    /// The `dims` are in samples, of which there are `cpp` per pixel.
+    ///
+    /// Unless the floating-point predictor is used, the samples are stored
+    /// in the given byte order.
+    #[inline]
+    pub fn new(
+        input: &'a [u8],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        cpp: core::num::NonZero<usize>,
+        bits: u32,
+        predictor: Predictor,
+        endianness: Endianness,
+    ) -> Result<Self, DeflateError> {
+        let supported = match predictor {
+            Predictor::None => [8, 16, 24, 32].contains(&bits),
+            Predictor::Horizontal => [8, 16].contains(&bits),
+            Predictor::FloatingPoint => [16, 24, 32].contains(&bits),
+        };
+        if !supported || !dims.row_len().get().is_multiple_of(cpp.get()) {
+            return Err(DeflateError::UnsupportedFormat);
+        }
+        Ok(Self {
+            input,
+            dims,
+            cpp,
+            bits,
+            predictor,
+            endianness,
+        })
+    }
+
+    #[inline]
+    pub const fn dims(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        self.dims
+    }
+
+    fn bytes_per_sample(&self) -> usize {
+        usize::try_from(self.bits / 8).unwrap()
+    }
+
+    fn inflate(&self) -> Result<Vec<u8>, DeflateError> {
+        let len = self.dims.row_len().get()
+            * self.bytes_per_sample()
+            * self.dims.row_count().get();
+        let mut out = vec![0; len];
+        let (status, _, written) = decompress(
+            &mut DecompressorOxide::new(),
+            self.input,
+            &mut out,
+            0,
+            inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER
+                | inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+        );
+        match status {
+            // Trailing rows are not needed.
+            TINFLStatus::Done | TINFLStatus::HasMoreOutput
+                if written == len =>
+            {
+                Ok(out)
+            }
+            TINFLStatus::Done
+            | TINFLStatus::HasMoreOutput
+            | TINFLStatus::NeedsMoreInput
+            | TINFLStatus::FailedCannotMakeProgress => {
+                Err(DeflateError::TruncatedData)
+            }
+            TINFLStatus::BadParam
+            | TINFLStatus::Adler32Mismatch
+            | TINFLStatus::Failed => Err(DeflateError::InvalidData),
+        }
+    }
+
+    /// Decodes integer samples.
+    #[inline(never)]
+    pub fn decode(
+        &self,
+        output: &mut Array2DRefMut<'_, u16>,
+    ) -> Result<(), DeflateError> {
+        if ![8, 16].contains(&self.bits)
+            || self.predictor == Predictor::FloatingPoint
+        {
+            return Err(DeflateError::UnsupportedFormat);
+        }
+        if output.dims() != self.dims {
+            return Err(DeflateError::OutputDimensionsMismatch);
+        }
+        let data = self.inflate()?;
+        let bytes_per_row = self.dims.row_len().get() * self.bytes_per_sample();
+        let max = u16::MAX >> (16 - self.bits);
+        let cpp = self.cpp.get();
+        for (row, src) in data.chunks_exact(bytes_per_row).enumerate() {
+            let out = output.get_row_mut(RowIndex::new(row)).unwrap();
+            for (col, bytes) in
+                src.chunks_exact(self.bytes_per_sample()).enumerate()
+            {
+                let val =
+                    u16::try_from(sample_from_bytes(bytes, self.endianness))
+                        .unwrap();
+                let prev = match col.checked_sub(cpp) {
+                    Some(prev) if self.predictor == Predictor::Horizontal => {
+                        *out.get(prev).unwrap()
+                    }
+                    _ => 0,
+                };
+                *out.get_mut(col).unwrap() = prev.wrapping_add(val) & max;
+            }
+        }
+        Ok(())
+    }
+
+    /// Decodes floating-point samples.
+    #[inline(never)]
+    pub fn decode_f32(
+        &self,
+        output: &mut Array2DRefMut<'_, f32>,
+    ) -> Result<(), DeflateError> {
+        if self.bits == 8 || self.predictor == Predictor::Horizontal {
+            return Err(DeflateError::UnsupportedFormat);
+        }
+        if output.dims() != self.dims {
+            return Err(DeflateError::OutputDimensionsMismatch);
+        }
+        let mut data = self.inflate()?;
+        let width = self.dims.row_len().get();
+        let bytes_per_sample = self.bytes_per_sample();
+        for (row, src) in
+            data.chunks_exact_mut(width * bytes_per_sample).enumerate()
+        {
+            let out = output.get_row_mut(RowIndex::new(row)).unwrap();
+            if self.predictor == Predictor::FloatingPoint {
+                let cpp = self.cpp.get();
+                for index in cpp..src.len() {
+                    let prev = *src.get(index - cpp).unwrap();
+                    let byte = src.get_mut(index).unwrap();
+                    *byte = byte.wrapping_add(prev);
+                }
+                for (col, sample) in out.iter_mut().enumerate() {
+                    let bits = (0..bytes_per_sample).fold(0, |acc, plane| {
+                        let byte = src.get(plane * width + col).unwrap();
+                        (acc << 8) | u32::from(*byte)
+                    });
+                    *sample = float_from_bits(bits, self.bits);
+                }
+            } else {
+                for (sample, bytes) in
+                    out.iter_mut().zip(src.chunks_exact(bytes_per_sample))
+                {
+                    let bits = sample_from_bytes(bytes, self.endianness);
+                    *sample = float_from_bits(bits, self.bits);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,421 @@
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_std::coord_common::{Dimensions2D, RowCount, RowLength, RowPitch};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+
+use super::{DeflateDecompressor, DeflateError, Predictor, widen_float};
+
+fn dims(
+    width: usize,
+    height: usize,
+) -> Dimensions2D<core::num::NonZero<usize>> {
+    Dimensions2D::new(
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowCount::new(core::num::NonZero::new(height).unwrap()),
+    )
+}
+
+fn to_bytes(val: u32, len: usize, endianness: Endianness) -> Vec<u8> {
+    let bytes = val.to_le_bytes().into_iter().take(len);
+    match endianness {
+        Endianness::Little => bytes.collect(),
+        Endianness::Big => bytes.rev().collect(),
+    }
+}
+
+/// Applies the predictor to the rows of `bits`-wide samples, of which
+/// there are `cpp` per pixel, and compresses the result.
+fn encode_interleaved(
+    rows: &[Vec<u32>],
+    cpp: usize,
+    bits: u32,
+    predictor: Predictor,
+    endianness: Endianness,
+) -> Vec<u8> {
+    let len = usize::try_from(bits / 8).unwrap();
+    let mut data = vec![];
+    for row in rows {
+        match predictor {
+            Predictor::None => {
+                for sample in row {
+                    data.extend(to_bytes(*sample, len, endianness));
+                }
+            }
+            Predictor::Horizontal => {
+                for (col, sample) in row.iter().enumerate() {
+                    let prev = col
+                        .checked_sub(cpp)
+                        .map_or(0, |prev| *row.get(prev).unwrap());
+                    let diff = sample.wrapping_sub(prev) & ((1 << bits) - 1);
+                    data.extend(to_bytes(diff, len, endianness));
+                }
+            }
+            Predictor::FloatingPoint => {
+                let planes: Vec<u8> = (0..len)
+                    .rev()
+                    .flat_map(|plane| {
+                        row.iter().map(move |sample| {
+                            u8::try_from((sample >> (8 * plane)) & 0xFF)
+                                .unwrap()
+                        })
+                    })
+                    .collect();
+                for (index, byte) in planes.iter().enumerate() {
+                    let prev = index
+                        .checked_sub(cpp)
+                        .map_or(0, |prev| *planes.get(prev).unwrap());
+                    data.push(byte.wrapping_sub(prev));
+                }
+            }
+        }
+    }
+    compress_to_vec_zlib(&data, 6)
+}
+
+fn encode(
+    rows: &[Vec<u32>],
+    bits: u32,
+    predictor: Predictor,
+    endianness: Endianness,
+) -> Vec<u8> {
+    encode_interleaved(rows, 1, bits, predictor, endianness)
+}
+
+fn rows(width: usize, height: usize, bits: u32) -> Vec<Vec<u32>> {
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| {
+                    let sample = (row * 7919 + col * 2131 + 17) % (1 << 16);
+                    u32::try_from(sample).unwrap() << (bits - 16)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn decode(
+    input: &[u8],
+    width: usize,
+    height: usize,
+    bits: u32,
+    predictor: Predictor,
+    endianness: Endianness,
+) -> Result<Vec<u16>, DeflateError> {
+    let decoder = DeflateDecompressor::new(
+        input,
+        dims(width, height),
+        core::num::NonZero::<usize>::MIN,
+        bits,
+        predictor,
+        endianness,
+    )?;
+    let mut buf = vec![0; width * height];
+    let width = core::num::NonZero::new(width).unwrap();
+    let mut output = Array2DRefMut::new(
+        &mut buf,
+        RowLength::new(width),
+        RowPitch::new(width),
+    );
+    decoder.decode(&mut output)?;
+    Ok(buf)
+}
+
+fn decode_f32(
+    input: &[u8],
+    width: usize,
+    height: usize,
+    bits: u32,
+    predictor: Predictor,
+) -> Result<Vec<f32>, DeflateError> {
+    let decoder = DeflateDecompressor::new(
+        input,
+        dims(width, height),
+        core::num::NonZero::<usize>::MIN,
+        bits,
+        predictor,
+        Endianness::Little,
+    )?;
+    let mut buf = vec![0.0; width * height];
+    let width = core::num::NonZero::new(width).unwrap();
+    let mut output = Array2DRefMut::new(
+        &mut buf,
+        RowLength::new(width),
+        RowPitch::new(width),
+    );
+    decoder.decode_f32(&mut output)?;
+    Ok(buf)
+}
+
+fn to_bits(samples: &[f32]) -> Vec<u32> {
+    samples.iter().map(|sample| sample.to_bits()).collect()
+}
+
+#[test]
+fn widen_half_test() {
+    let half = |bits| widen_float(bits, 5, 10).to_bits();
+    assert_eq!(half(0x3C00), 1.0_f32.to_bits());
+    assert_eq!(half(0xC000), (-2.0_f32).to_bits());
+    assert_eq!(half(0x8000), (-0.0_f32).to_bits());
+    assert_eq!(half(0x7BFF), 65504.0_f32.to_bits());
+    // Subnormals.
+    assert_eq!(half(0x0001), 2.0_f32.powi(-24).to_bits());
+    assert_eq!(half(0x03FF), (1023.0 * 2.0_f32.powi(-24)).to_bits());
+    assert_eq!(half(0x7C00), f32::INFINITY.to_bits());
+    assert!(f32::from_bits(half(0x7E00)).is_nan());
+}
+
+#[test]
+fn widen_fp24_test() {
+    let fp24 = |bits| widen_float(bits, 7, 16).to_bits();
+    assert_eq!(fp24(0x3F_0000), 1.0_f32.to_bits());
+    assert_eq!(fp24(0xC0_8000), (-3.0_f32).to_bits());
+    assert_eq!(fp24(0x00_0001), 2.0_f32.powi(-78).to_bits());
+    assert_eq!(fp24(0xFF_0000), f32::NEG_INFINITY.to_bits());
+}
+
+#[test]
+fn integer_test() {
+    for bits in [8, 16] {
+        let rows: Vec<Vec<u32>> = rows(7, 3, 16)
+            .into_iter()
+            .map(|row| row.into_iter().map(|s| s >> (16 - bits)).collect())
+            .collect();
+        let expected: Vec<u16> = rows
+            .iter()
+            .flatten()
+            .map(|sample| u16::try_from(*sample).unwrap())
+            .collect();
+        for predictor in [Predictor::None, Predictor::Horizontal] {
+            for endianness in [Endianness::Little, Endianness::Big] {
+                let input = encode(&rows, bits, predictor, endianness);
+                assert_eq!(
+                    decode(&input, 7, 3, bits, predictor, endianness),
+                    Ok(expected.clone())
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn floating_point_test() {
+    for bits in [16, 24, 32] {
+        let rows = rows(5, 4, bits);
+        for predictor in [Predictor::None, Predictor::FloatingPoint] {
+            let input = encode(&rows, bits, predictor, Endianness::Little);
+            let expected: Vec<u32> = rows
+                .iter()
+                .flatten()
+                .map(|sample| match bits {
+                    16 => widen_float(*sample, 5, 10).to_bits(),
+                    24 => widen_float(*sample, 7, 16).to_bits(),
+                    _ => *sample,
+                })
+                .collect();
+            assert_eq!(
+                decode_f32(&input, 5, 4, bits, predictor).map(|s| to_bits(&s)),
+                Ok(expected)
+            );
+        }
+    }
+}
+
+/// Decodes rows of `width` samples, `cpp` per pixel, encoded with
+/// `predictor`.
+fn decode_interleaved<'a, S, F>(
+    input: &'a [u8],
+    width: usize,
+    height: usize,
+    cpp: usize,
+    predictor: Predictor,
+    decode: F,
+) -> Vec<S>
+where
+    S: Copy + Default,
+    F: FnOnce(
+        &DeflateDecompressor<'a>,
+        &mut Array2DRefMut<'_, S>,
+    ) -> Result<(), DeflateError>,
+{
+    let decoder = DeflateDecompressor::new(
+        input,
+        dims(width, height),
+        core::num::NonZero::new(cpp).unwrap(),
+        16,
+        predictor,
+        Endianness::Big,
+    )
+    .unwrap();
+    let mut buf = vec![S::default(); width * height];
+    let row_len = core::num::NonZero::new(width).unwrap();
+    let mut output = Array2DRefMut::new(
+        &mut buf,
+        RowLength::new(row_len),
+        RowPitch::new(row_len),
+    );
+    decode(&decoder, &mut output).unwrap();
+    buf
+}
+
+#[test]
+fn interleaved_integer_test() {
+    let rows = rows(4 * 3, 2, 16);
+    let expected: Vec<u16> = rows
+        .iter()
+        .flatten()
+        .map(|sample| u16::try_from(*sample).unwrap())
+        .collect();
+    let input = encode_interleaved(
+        &rows,
+        3,
+        16,
+        Predictor::Horizontal,
+        Endianness::Big,
+    );
+    let decoded = decode_interleaved(
+        &input,
+        4 * 3,
+        2,
+        3,
+        Predictor::Horizontal,
+        DeflateDecompressor::decode,
+    );
+    assert_eq!(decoded, expected);
+}
+
+#[test]
+fn interleaved_floating_point_test() {
+    let rows = rows(4 * 3, 2, 16);
+    let expected: Vec<u32> = rows
+        .iter()
+        .flatten()
+        .map(|sample| widen_float(*sample, 5, 10).to_bits())
+        .collect();
+    let input = encode_interleaved(
+        &rows,
+        3,
+        16,
+        Predictor::FloatingPoint,
+        Endianness::Big,
+    );
+    let decoded = decode_interleaved(
+        &input,
+        4 * 3,
+        2,
+        3,
+        Predictor::FloatingPoint,
+        DeflateDecompressor::decode_f32,
+    );
+    assert_eq!(to_bits(&decoded), expected);
+}
+
+#[test]
+fn trailing_rows_test() {
+    let rows = rows(4, 6, 32);
+    let input = encode(&rows, 32, Predictor::FloatingPoint, Endianness::Big);
+    let expected: Vec<u32> = rows.iter().take(2).flatten().copied().collect();
+    assert_eq!(
+        decode_f32(&input, 4, 2, 32, Predictor::FloatingPoint)
+            .map(|s| to_bits(&s)),
+        Ok(expected)
+    );
+}
+
+#[test]
+fn truncated_data_test() {
+    let rows = rows(4, 6, 16);
+    let input = encode(&rows, 16, Predictor::None, Endianness::Big);
+    assert_eq!(
+        decode(&input, 4, 7, 16, Predictor::None, Endianness::Big),
+        Err(DeflateError::TruncatedData)
+    );
+    assert_eq!(
+        decode(
+            input.get(..input.len() / 2).unwrap(),
+            4,
+            6,
+            16,
+            Predictor::None,
+            Endianness::Big
+        ),
+        Err(DeflateError::TruncatedData)
+    );
+}
+
+#[test]
+fn invalid_data_test() {
+    assert_eq!(
+        decode(&[0xFF; 16], 4, 4, 16, Predictor::None, Endianness::Big),
+        Err(DeflateError::InvalidData)
+    );
+}
+
+#[test]
+fn unsupported_format_test() {
+    for (bits, predictor) in [
+        (12, Predictor::None),
+        (32, Predictor::Horizontal),
+        (8, Predictor::FloatingPoint),
+    ] {
+        assert_eq!(
+            DeflateDecompressor::new(
+                &[],
+                dims(1, 1),
+                core::num::NonZero::<usize>::MIN,
+                bits,
+                predictor,
+                Endianness::Big
+            )
+            .unwrap_err(),
+            DeflateError::UnsupportedFormat
+        );
+    }
+    // The rows must hold whole pixels.
+    assert_eq!(
+        DeflateDecompressor::new(
+            &[],
+            dims(4, 1),
+            core::num::NonZero::new(3).unwrap(),
+            16,
+            Predictor::None,
+            Endianness::Big
+        )
+        .unwrap_err(),
+        DeflateError::UnsupportedFormat
+    );
+    // Integers are not floats, and vice versa.
+    let input = encode(&rows(2, 2, 16), 16, Predictor::None, Endianness::Big);
+    assert_eq!(
+        decode(&input, 2, 2, 16, Predictor::FloatingPoint, Endianness::Big),
+        Err(DeflateError::UnsupportedFormat)
+    );
+    assert_eq!(
+        decode_f32(&input, 2, 2, 8, Predictor::None),
+        Err(DeflateError::UnsupportedFormat)
+    );
+}
+
+#[test]
+fn output_dimensions_mismatch_test() {
+    let decoder = DeflateDecompressor::new(
+        &[],
+        dims(2, 2),
+        core::num::NonZero::<usize>::MIN,
+        16,
+        Predictor::None,
+        Endianness::Big,
+    )
+    .unwrap();
+    let mut buf = vec![0; 2];
+    let width = core::num::NonZero::new(2).unwrap();
+    let mut output = Array2DRefMut::new(
+        &mut buf,
+        RowLength::new(width),
+        RowPitch::new(width),
+    );
+    assert_eq!(
+        decoder.decode(&mut output),
+        Err(DeflateError::OutputDimensionsMismatch)
+    );
+}
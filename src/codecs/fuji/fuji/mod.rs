@@ -0,0 +1,744 @@
+use rawspeed_bitstream_bitstream_decoder::bitstreamer::{
+    BitStream, BitStreamerBase,
+};
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrderMSB;
+use rawspeed_bitstream_bytestream_decoder::bytestreamer::ByteStreamer;
+use rawspeed_common_bitseq::bitseq::BitSeqConstraints;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_colorfilterarray::colorfilterarray::ColorVariant;
+use rawspeed_std::coord_common::{ColIndex, Coord2D, Dimensions2D, RowIndex};
+use rawspeed_std_ndslice::{
+    array2drefmut::Array2DRefMut, offsetarray2dref::OffsetArray2DRef,
+};
+
+const SIGNATURE: u16 = 0x4953;
+const VERSION: u8 = 1;
+
+/// The `raw_type` of X-Trans sensors; Bayer ones use zero.
+const RAW_TYPE_XTRANS: u8 = 16;
+const RAW_TYPE_BAYER: u8 = 0;
+
+/// The width of each block column, i.e. of each strip.
+const BLOCK_SIZE: usize = 0x300;
+
+/// Each line of a strip spans this many rows of the image.
+const LINE_HEIGHT: usize = 6;
+
+/// The CFA pattern is repeated every this many rows and columns
+/// (the X-Trans period, which is also a multiple of the Bayer one).
+const MCU: usize = 6;
+
+/// The header is followed by the size of each strip, whose list is
+/// padded to a multiple of 16 bytes.
+const HEADER_LEN: usize = 16;
+
+/// The thresholds of the gradient quantization.
+const Q_POINTS: [i32; 3] = [0x12, 0x43, 0x114];
+
+/// The number of samples after which the gradient statistics are halved.
+const MIN_VALUE: i32 = 0x40;
+
+/// The number of quantized gradients: `9 * 4 + 4`, plus zero.
+const GRADIENTS: usize = 41;
+
+/// The line buffers of a strip: the two lines of the previous group
+/// (used for prediction), then those of the current one, of each color.
+const R0: usize = 0;
+const R2: usize = 2;
+const R3: usize = 3;
+const R4: usize = 4;
+const G0: usize = 5;
+const G2: usize = 7;
+const G3: usize = 8;
+const G4: usize = 9;
+const G5: usize = 10;
+const G6: usize = 11;
+const G7: usize = 12;
+const B0: usize = 13;
+const B2: usize = 15;
+const B3: usize = 16;
+const B4: usize = 17;
+const LINES: usize = 18;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FujiError {
+    UnexpectedEndOfInput,
+    InvalidHeader,
+    DimensionsMismatch,
+    InvalidCfa,
+    InvalidCode,
+    TruncatedData,
+    OutputDimensionsMismatch,
+}
+
+impl core::fmt::Display for FujiError {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            FujiError::UnexpectedEndOfInput => {
+                write!(f, "FujiError(UnexpectedEndOfInput)")
+            }
+            FujiError::InvalidHeader => write!(f, "FujiError(InvalidHeader)"),
+            FujiError::DimensionsMismatch => {
+                write!(f, "FujiError(DimensionsMismatch)")
+            }
+            FujiError::InvalidCfa => write!(f, "FujiError(InvalidCfa)"),
+            FujiError::InvalidCode => write!(f, "FujiError(InvalidCode)"),
+            FujiError::TruncatedData => write!(f, "FujiError(TruncatedData)"),
+            FujiError::OutputDimensionsMismatch => {
+                write!(f, "FujiError(OutputDimensionsMismatch)")
+            }
+        }
+    }
+}
+
+/// Big-endian reader over the header and the strip sizes.
+#[derive(Debug, Clone, Copy)]
+struct ByteReader<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    fn get_bytes(&mut self, len: usize) -> Result<&'a [u8], FujiError> {
+        let (bytes, rest) = self
+            .input
+            .split_at_checked(len)
+            .ok_or(FujiError::UnexpectedEndOfInput)?;
+        self.input = rest;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, FujiError> {
+        Ok(*self.get_bytes(1)?.first().unwrap())
+    }
+
+    fn read_u16(&mut self) -> Result<usize, FujiError> {
+        let bytes = self.get_bytes(size_of::<u16>())?;
+        Ok(ByteStreamer::new(bytes, Endianness::Big)
+            .read::<u16>()
+            .into())
+    }
+
+    fn read_u32(&mut self) -> Result<usize, FujiError> {
+        let bytes = self.get_bytes(size_of::<u32>())?;
+        let val: u32 = ByteStreamer::new(bytes, Endianness::Big).read();
+        Ok(val.try_into().unwrap())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Header {
+    xtrans: bool,
+    raw_bits: u32,
+    raw_height: usize,
+    raw_width: usize,
+    blocks_in_row: usize,
+}
+
+impl Header {
+    fn parse(reader: &mut ByteReader<'_>) -> Result<Self, FujiError> {
+        let signature = reader.read_u16()?;
+        let version = reader.read_u8()?;
+        let raw_type = reader.read_u8()?;
+        let raw_bits = u32::from(reader.read_u8()?);
+        let raw_height = reader.read_u16()?;
+        let raw_rounded_width = reader.read_u16()?;
+        let raw_width = reader.read_u16()?;
+        let block_size = reader.read_u16()?;
+        let blocks_in_row = usize::from(reader.read_u8()?);
+        let total_lines = reader.read_u16()?;
+        let valid = signature == usize::from(SIGNATURE)
+            && version == VERSION
+            && matches!(raw_type, RAW_TYPE_XTRANS | RAW_TYPE_BAYER)
+            && matches!(raw_bits, 12 | 14)
+            && block_size == BLOCK_SIZE
+            && raw_height != 0
+            && raw_height.is_multiple_of(LINE_HEIGHT)
+            && total_lines == raw_height / LINE_HEIGHT
+            && raw_width != 0
+            && blocks_in_row == raw_width.div_ceil(BLOCK_SIZE)
+            && raw_rounded_width == blocks_in_row * BLOCK_SIZE;
+        if !valid {
+            return Err(FujiError::InvalidHeader);
+        }
+        Ok(Self {
+            xtrans: raw_type == RAW_TYPE_XTRANS,
+            raw_bits,
+            raw_height,
+            raw_width,
+            blocks_in_row,
+        })
+    }
+
+    /// The number of samples in each line buffer.
+    const fn line_width(&self) -> usize {
+        if self.xtrans {
+            BLOCK_SIZE * 2 / 3
+        } else {
+            BLOCK_SIZE / 2
+        }
+    }
+
+    const fn max_value(&self) -> i32 {
+        (1 << self.raw_bits) - 1
+    }
+
+    /// The initial sum of the absolute differences of each gradient.
+    const fn max_diff(&self) -> i32 {
+        if self.raw_bits == 14 { 256 } else { 64 }
+    }
+
+    /// The number of leading zeros from which the code is stored verbatim.
+    const fn escape_len(&self) -> u32 {
+        3 * self.raw_bits - 1
+    }
+}
+
+/// The color planes that the CFA maps to the line buffers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Plane {
+    Red,
+    Green,
+    Blue,
+}
+
+/// Whether the even samples of a line are interpolated rather than coded.
+#[derive(Debug, Clone, Copy)]
+enum Even {
+    Coded,
+    Interpolated,
+    /// Only those at positions congruent to this, modulo 4.
+    InterpolatedAt(usize),
+}
+
+impl Even {
+    const fn is_interpolated(self, pos: usize) -> bool {
+        match self {
+            Even::Coded => false,
+            Even::Interpolated => true,
+            Even::InterpolatedAt(rem) => pos % 4 == rem,
+        }
+    }
+}
+
+/// Two lines of different colors that are decoded together, with the set
+/// of gradient statistics that they use.
+#[derive(Debug, Clone, Copy)]
+struct Pass {
+    lines: [(usize, Even); 2],
+    grads: usize,
+}
+
+const fn pass(first: usize, second: usize, grads: usize) -> Pass {
+    Pass {
+        lines: [(first, Even::Coded), (second, Even::Coded)],
+        grads,
+    }
+}
+
+const BAYER_PASSES: [Pass; 6] = [
+    pass(R2, G2, 0),
+    pass(G3, B2, 1),
+    pass(R3, G4, 2),
+    pass(G5, B3, 0),
+    pass(R4, G6, 1),
+    pass(G7, B4, 2),
+];
+
+/// X-Trans sensors have fewer red and blue samples, the missing ones
+/// being interpolated.
+const XTRANS_PASSES: [Pass; 6] = [
+    Pass {
+        lines: [(R2, Even::Interpolated), (G2, Even::Coded)],
+        grads: 0,
+    },
+    Pass {
+        lines: [(G3, Even::Coded), (B2, Even::Interpolated)],
+        grads: 1,
+    },
+    Pass {
+        lines: [(R3, Even::InterpolatedAt(0)), (G4, Even::Coded)],
+        grads: 2,
+    },
+    Pass {
+        lines: [(G5, Even::Coded), (B3, Even::InterpolatedAt(2))],
+        grads: 0,
+    },
+    Pass {
+        lines: [(R4, Even::InterpolatedAt(2)), (G6, Even::Coded)],
+        grads: 1,
+    },
+    Pass {
+        lines: [(G7, Even::Coded), (B4, Even::InterpolatedAt(0))],
+        grads: 2,
+    },
+];
+
+/// The first and last current lines of the color of the given line.
+const fn plane_lines(line: usize) -> (usize, usize) {
+    if line < G0 {
+        (R2, R4)
+    } else if line < B0 {
+        (G2, G7)
+    } else {
+        (B2, B4)
+    }
+}
+
+/// The quantized difference between two neighbours.
+const fn quantize(diff: i32) -> i32 {
+    let [q1, q2, q3] = Q_POINTS;
+    if diff <= -q3 {
+        -4
+    } else if diff <= -q2 {
+        -3
+    } else if diff <= -q1 {
+        -2
+    } else if diff < 0 {
+        -1
+    } else if diff == 0 {
+        0
+    } else if diff < q1 {
+        1
+    } else if diff < q2 {
+        2
+    } else if diff < q3 {
+        3
+    } else {
+        4
+    }
+}
+
+/// The average of the neighbours above, leaving out the one that differs
+/// the most from the one right above (`up`).
+const fn interpolate(up: i32, up_left: i32, up_right: i32, up2: i32) -> i32 {
+    let (diff_left, diff_right, diff_up2) = (
+        (up_left - up).abs(),
+        (up_right - up).abs(),
+        (up2 - up).abs(),
+    );
+    let sum = if diff_left > diff_up2 && diff_left > diff_right {
+        up2 + up_right
+    } else if diff_right > diff_left && diff_right > diff_up2 {
+        up2 + up_left
+    } else {
+        up_right + up_left
+    };
+    (sum + 2 * up) >> 2
+}
+
+/// The number of low bits to read verbatim, given the running sum of the
+/// absolute differences and the number of samples.
+const fn bit_diff(sum: i32, count: i32) -> u32 {
+    let mut bits = 0;
+    while bits < 15 && (count << bits) < sum {
+        bits += 1;
+    }
+    bits
+}
+
+fn get_bits<B>(bs: &mut B, nbits: u32) -> Result<u32, FujiError>
+where
+    B: BitStream,
+    B::T: BitSeqConstraints + core::fmt::Debug,
+    u64: From<B::T>,
+{
+    if nbits == 0 {
+        return Ok(0);
+    }
+    bs.fill(nbits).map_err(|_err| FujiError::TruncatedData)?;
+    let bits = u64::from(bs.peek_bits_no_fill(nbits).zext());
+    bs.skip_bits_no_fill(nbits);
+    Ok(u32::try_from(bits).unwrap())
+}
+
+/// The running statistics of the differences of a gradient.
+#[derive(Debug, Clone, Copy)]
+struct Stats {
+    sum: i32,
+    count: i32,
+}
+
+/// The decoding state of a strip.
+#[derive(Debug)]
+struct Strip<'a> {
+    header: Header,
+    bs: BitStreamerBase<'a, BitOrderMSB>,
+    lines: Vec<u16>,
+    even_stats: [[Stats; GRADIENTS]; 3],
+    odd_stats: [[Stats; GRADIENTS]; 3],
+}
+
+impl<'a> Strip<'a> {
+    fn new(header: Header, data: &'a [u8]) -> Result<Self, FujiError> {
+        let bs = BitStreamerBase::<BitOrderMSB>::try_from(data)
+            .map_err(|_err| FujiError::TruncatedData)?;
+        let stats = [[Stats {
+            sum: header.max_diff(),
+            count: 1,
+        }; GRADIENTS]; 3];
+        Ok(Self {
+            header,
+            bs,
+            lines: vec![0; LINES * (header.line_width() + 2)],
+            even_stats: stats,
+            odd_stats: stats,
+        })
+    }
+
+    /// The sample at the given column of a line buffer, whose first and
+    /// last columns extend it to the left and right.
+    fn get(&self, line: usize, col: usize) -> i32 {
+        let pitch = self.header.line_width() + 2;
+        i32::from(*self.lines.get(line * pitch + col).unwrap())
+    }
+
+    fn set(&mut self, line: usize, col: usize, val: u16) {
+        let pitch = self.header.line_width() + 2;
+        *self.lines.get_mut(line * pitch + col).unwrap() = val;
+    }
+
+    fn count_zeros(&mut self) -> Result<u32, FujiError> {
+        let mut count = 0;
+        loop {
+            self.bs.fill(32).map_err(|_err| FujiError::TruncatedData)?;
+            let batch = self.bs.peek_bits_no_fill(32).zext();
+            let zeros = u32::try_from(batch).unwrap().leading_zeros();
+            count += zeros;
+            if zeros < 32 {
+                self.bs.skip_bits_no_fill(zeros + 1);
+                return Ok(count);
+            }
+            self.bs.skip_bits_no_fill(32);
+        }
+    }
+
+    /// Reads a difference, whose high bits are unary-coded, unless there
+    /// are too many of them.
+    fn read_difference(&mut self, stats: &mut Stats) -> Result<i32, FujiError> {
+        let zeros = self.count_zeros()?;
+        let code = if zeros < self.header.escape_len() {
+            let bits = bit_diff(stats.sum, stats.count);
+            get_bits(&mut self.bs, bits)? + (zeros << bits)
+        } else {
+            get_bits(&mut self.bs, self.header.raw_bits)? + 1
+        };
+        if code > u32::try_from(self.header.max_value()).unwrap() {
+            return Err(FujiError::InvalidCode);
+        }
+        let code = i32::try_from(code).unwrap();
+        let diff = if code & 1 == 0 {
+            code >> 1
+        } else {
+            -1 - (code >> 1)
+        };
+        stats.sum += diff.abs();
+        if stats.count == MIN_VALUE {
+            stats.sum >>= 1;
+            stats.count >>= 1;
+        }
+        stats.count += 1;
+        Ok(diff)
+    }
+
+    /// Applies the difference to the prediction, wrapping around the range
+    /// of the samples.
+    fn reconstruct(&self, pred: i32, diff: i32, grad: i32) -> u16 {
+        let max = self.header.max_value();
+        let val = if grad < 0 { pred - diff } else { pred + diff };
+        let val = if val < 0 {
+            val + max + 1
+        } else if val > max {
+            val - max - 1
+        } else {
+            val
+        };
+        u16::try_from(val.clamp(0, max)).unwrap()
+    }
+
+    fn interpolate_even(&mut self, line: usize, pos: usize) {
+        let col = pos + 1;
+        let val = interpolate(
+            self.get(line - 1, col),
+            self.get(line - 1, col - 1),
+            self.get(line - 1, col + 1),
+            self.get(line - 2, col),
+        );
+        self.set(line, col, u16::try_from(val).unwrap());
+    }
+
+    fn decode_even(
+        &mut self,
+        line: usize,
+        pos: usize,
+        grads: usize,
+    ) -> Result<(), FujiError> {
+        let col = pos + 1;
+        let up = self.get(line - 1, col);
+        let up_left = self.get(line - 1, col - 1);
+        let up_right = self.get(line - 1, col + 1);
+        let up2 = self.get(line - 2, col);
+        let grad = quantize(up - up2) * 9 + quantize(up_left - up);
+        let index = usize::try_from(grad.unsigned_abs()).unwrap();
+        let mut stats =
+            *self.even_stats.get(grads).unwrap().get(index).unwrap();
+        let diff = self.read_difference(&mut stats)?;
+        *self
+            .even_stats
+            .get_mut(grads)
+            .unwrap()
+            .get_mut(index)
+            .unwrap() = stats;
+        let val = self.reconstruct(
+            interpolate(up, up_left, up_right, up2),
+            diff,
+            grad,
+        );
+        self.set(line, col, val);
+        Ok(())
+    }
+
+    /// The odd samples are predicted from their (already decoded) even
+    /// neighbours as well.
+    fn decode_odd(
+        &mut self,
+        line: usize,
+        pos: usize,
+        grads: usize,
+    ) -> Result<(), FujiError> {
+        let col = pos + 1;
+        let left = self.get(line, col - 1);
+        let right = self.get(line, col + 1);
+        let up = self.get(line - 1, col);
+        let up_left = self.get(line - 1, col - 1);
+        let up_right = self.get(line - 1, col + 1);
+        let grad = quantize(up - up_left) * 9 + quantize(up_left - left);
+        let index = usize::try_from(grad.unsigned_abs()).unwrap();
+        let pred = if (up > up_left && up > up_right)
+            || (up < up_left && up < up_right)
+        {
+            (right + left + 2 * up) >> 2
+        } else {
+            (left + right) >> 1
+        };
+        let mut stats = *self.odd_stats.get(grads).unwrap().get(index).unwrap();
+        let diff = self.read_difference(&mut stats)?;
+        *self
+            .odd_stats
+            .get_mut(grads)
+            .unwrap()
+            .get_mut(index)
+            .unwrap() = stats;
+        let val = self.reconstruct(pred, diff, grad);
+        self.set(line, col, val);
+        Ok(())
+    }
+
+    /// Copies the edge samples of the previous line into the extension
+    /// columns of each of the given lines.
+    fn extend(&mut self, (first, last): (usize, usize)) {
+        let width = self.header.line_width();
+        for line in first..=last {
+            let left = u16::try_from(self.get(line - 1, 1)).unwrap();
+            let right = u16::try_from(self.get(line - 1, width)).unwrap();
+            self.set(line, 0, left);
+            self.set(line, width + 1, right);
+        }
+    }
+
+    /// The odd samples trail the even ones, as they depend on them.
+    fn decode_pass(&mut self, pass: &Pass) -> Result<(), FujiError> {
+        let width = self.header.line_width();
+        let (mut even, mut odd) = (0, 1);
+        while even < width || odd < width {
+            if even < width {
+                for (line, mode) in pass.lines {
+                    if mode.is_interpolated(even) {
+                        self.interpolate_even(line, even);
+                    } else {
+                        self.decode_even(line, even, pass.grads)?;
+                    }
+                }
+                even += 2;
+            }
+            if even > 8 {
+                for (line, _) in pass.lines {
+                    self.decode_odd(line, odd, pass.grads)?;
+                }
+                odd += 2;
+            }
+        }
+        for (line, _) in pass.lines {
+            self.extend(plane_lines(line));
+        }
+        Ok(())
+    }
+
+    /// Moves the last two current lines of each color to the previous
+    /// ones, and clears the current ones.
+    fn advance(&mut self) {
+        let pitch = self.header.line_width() + 2;
+        for (prev, cur, count) in [(R0, R3, 3), (G0, G6, 6), (B0, B3, 3)] {
+            self.lines
+                .copy_within(cur * pitch..(cur + 2) * pitch, prev * pitch);
+            let first = cur + 2 - count;
+            self.lines
+                .get_mut(first * pitch..(first + count) * pitch)
+                .unwrap()
+                .fill(0);
+            self.extend((first, first));
+        }
+    }
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct FujiDecompressor<'a> {
+    header: Header,
+    strips: Vec<&'a [u8]>,
+    cfa: [[Plane; MCU]; MCU],
+}
+
+impl<'a> FujiDecompressor<'a> {
+    fn parse_strips(
+        reader: &mut ByteReader<'a>,
+        header: &Header,
+    ) -> Result<Vec<&'a [u8]>, FujiError> {
+        let sizes = core::iter::repeat_with(|| reader.read_u32())
+            .take(header.blocks_in_row)
+            .collect::<Result<Vec<_>, _>>()?;
+        let padding = (HEADER_LEN
+            - (size_of::<u32>() * header.blocks_in_row) % HEADER_LEN)
+            % HEADER_LEN;
+        reader.get_bytes(padding)?;
+        sizes
+            .into_iter()
+            .map(|size| reader.get_bytes(size))
+            .collect()
+    }
+
+    /// The CFA must be periodic over each line's 6x6 block of samples.
+    fn parse_cfa(
+        cfa: &OffsetArray2DRef<'_, ColorVariant>,
+    ) -> Result<[[Plane; MCU]; MCU], FujiError> {
+        let (width, height) = (cfa.row_length().get(), cfa.num_rows().get());
+        if !MCU.is_multiple_of(width) || !MCU.is_multiple_of(height) {
+            return Err(FujiError::InvalidCfa);
+        }
+        let mut planes = [[Plane::Green; MCU]; MCU];
+        for (row, planes_row) in planes.iter_mut().enumerate() {
+            for (col, plane) in planes_row.iter_mut().enumerate() {
+                let index = Coord2D::new(
+                    RowIndex::new(row % height),
+                    ColIndex::new(col % width),
+                );
+                #[expect(clippy::wildcard_enum_match_arm)]
+                {
+                    *plane = match cfa[index] {
+                        ColorVariant::Red => Plane::Red,
+                        ColorVariant::Green | ColorVariant::FujiGreen => {
+                            Plane::Green
+                        }
+                        ColorVariant::Blue => Plane::Blue,
+                        _ => return Err(FujiError::InvalidCfa),
+                    };
+                }
+            }
+        }
+        Ok(planes)
+    }
+
+    /// The data starts with a header that describes the image and the
+    /// sizes of its strips, which must match the given dimensions.
+    #[inline]
+    pub fn new(
+        input: &'a [u8],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        cfa: &OffsetArray2DRef<'_, ColorVariant>,
+    ) -> Result<Self, FujiError> {
+        let mut reader = ByteReader { input };
+        let header = Header::parse(&mut reader)?;
+        if header.raw_width != dims.row_len().get()
+            || header.raw_height != dims.row_count().get()
+        {
+            return Err(FujiError::DimensionsMismatch);
+        }
+        let strips = Self::parse_strips(&mut reader, &header)?;
+        let cfa = Self::parse_cfa(cfa)?;
+        Ok(Self {
+            header,
+            strips,
+            cfa,
+        })
+    }
+
+    /// The position of the sample of the given column in its line buffer.
+    const fn line_col(&self, col: usize) -> usize {
+        if self.header.xtrans {
+            ((col * 2 / 3) & !1) + ((col % 3) & 1) + ((col % 3) >> 1)
+        } else {
+            col >> 1
+        }
+    }
+
+    fn copy_line(
+        &self,
+        strip: &Strip<'_>,
+        output: &mut Array2DRefMut<'_, u16>,
+        index: usize,
+        line: usize,
+    ) {
+        let offset = index * BLOCK_SIZE;
+        let width = BLOCK_SIZE.min(self.header.raw_width - offset);
+        for (row, planes) in self.cfa.iter().enumerate() {
+            let out = output
+                .get_row_mut(RowIndex::new(line * LINE_HEIGHT + row))
+                .unwrap();
+            let out = out.get_mut(offset..offset + width).unwrap();
+            for (col, sample) in out.iter_mut().enumerate() {
+                let buf = match planes.get(col % MCU).unwrap() {
+                    Plane::Red => R2 + row / 2,
+                    Plane::Green => G2 + row,
+                    Plane::Blue => B2 + row / 2,
+                };
+                *sample = u16::try_from(strip.get(buf, self.line_col(col) + 1))
+                    .unwrap();
+            }
+        }
+    }
+
+    /// Each strip (block column) is coded independently, one line of six
+    /// rows at a time.
+    #[inline(never)]
+    pub fn decode(
+        &self,
+        output: &mut Array2DRefMut<'_, u16>,
+    ) -> Result<(), FujiError> {
+        if output.dims().row_len().get() != self.header.raw_width
+            || output.dims().row_count().get() != self.header.raw_height
+        {
+            return Err(FujiError::OutputDimensionsMismatch);
+        }
+        let passes = if self.header.xtrans {
+            &XTRANS_PASSES
+        } else {
+            &BAYER_PASSES
+        };
+        for (index, data) in self.strips.iter().enumerate() {
+            let mut strip = Strip::new(self.header, data)?;
+            for line in 0..self.header.raw_height / LINE_HEIGHT {
+                for pass in passes {
+                    strip.decode_pass(pass)?;
+                }
+                self.copy_line(&strip, output, index, line);
+                strip.advance();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,547 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_metadata_colorfilterarray::colorfilterarray::ColorVariant;
+use rawspeed_std::coord_common::{
+    ColOffset, CoordOffset2D, Dimensions2D, RowCount, RowLength, RowOffset,
+    RowPitch,
+};
+use rawspeed_std_ndslice::{
+    array2dref::Array2DRef, array2drefmut::Array2DRefMut,
+    offsetarray2dref::OffsetArray2DRef,
+};
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+
+use super::{
+    B0, B2, B3, BAYER_PASSES, BLOCK_SIZE, FujiDecompressor, FujiError, G0, G2,
+    G6, GRADIENTS, LINES, Pass, R0, R2, R3, XTRANS_PASSES, bit_diff,
+    interpolate, plane_lines, quantize,
+};
+
+const BAYER: [ColorVariant; 4] = [
+    ColorVariant::Red,
+    ColorVariant::Green,
+    ColorVariant::Green,
+    ColorVariant::Blue,
+];
+
+const XTRANS: [&str; 6] =
+    ["GGRGGB", "GGBGGR", "BRGRBG", "GGBGGR", "GGRGGB", "RBGBRG"];
+
+fn xtrans() -> Vec<ColorVariant> {
+    XTRANS
+        .iter()
+        .flat_map(|row| row.chars())
+        .map(|color| match color {
+            'R' => ColorVariant::Red,
+            'B' => ColorVariant::Blue,
+            _ => ColorVariant::Green,
+        })
+        .collect()
+}
+
+fn put_zeros(bits: &mut BitStreamBuilder, mut count: u32) {
+    while count > 0 {
+        let nbits = count.min(16);
+        bits.put(0, nbits);
+        count -= nbits;
+    }
+}
+
+/// Mirrors the decoder state of a strip, coding pseudo-random samples
+/// that mostly stay close to their prediction.
+#[derive(Debug)]
+struct Encoder {
+    raw_bits: u32,
+    width: usize,
+    lines: Vec<i32>,
+    even_stats: [[(i32, i32); GRADIENTS]; 3],
+    odd_stats: [[(i32, i32); GRADIENTS]; 3],
+    bits: BitStreamBuilder,
+    seed: u32,
+}
+
+impl Encoder {
+    fn new(xtrans: bool, raw_bits: u32, seed: u32) -> Self {
+        let width = if xtrans { 512 } else { 384 };
+        let max_diff = if raw_bits == 14 { 256 } else { 64 };
+        Self {
+            raw_bits,
+            width,
+            lines: vec![0; LINES * (width + 2)],
+            even_stats: [[(max_diff, 1); GRADIENTS]; 3],
+            odd_stats: [[(max_diff, 1); GRADIENTS]; 3],
+            bits: BitStreamBuilder::new(),
+            seed,
+        }
+    }
+
+    const fn max(&self) -> i32 {
+        (1 << self.raw_bits) - 1
+    }
+
+    fn get(&self, line: usize, col: usize) -> i32 {
+        *self.lines.get(line * (self.width + 2) + col).unwrap()
+    }
+
+    fn set(&mut self, line: usize, col: usize, val: i32) {
+        *self.lines.get_mut(line * (self.width + 2) + col).unwrap() = val;
+    }
+
+    fn random(&mut self) -> u32 {
+        self.seed = self.seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        self.seed >> 16
+    }
+
+    /// A sample near the prediction, but for the occasional jump.
+    fn target(&mut self, pred: i32) -> i32 {
+        let rand = self.random();
+        if rand.is_multiple_of(53) {
+            i32::try_from(self.random()).unwrap() & self.max()
+        } else {
+            let noise = i32::try_from(rand % 33).unwrap() - 16;
+            (pred + noise).clamp(0, self.max())
+        }
+    }
+
+    fn put_difference(&mut self, stats: &mut (i32, i32), diff: i32) {
+        let code =
+            u32::try_from(if diff < 0 { -2 * diff - 1 } else { 2 * diff })
+                .unwrap();
+        let bits = bit_diff(stats.0, stats.1);
+        let escape_len = 3 * self.raw_bits - 1;
+        if code >> bits < escape_len {
+            put_zeros(&mut self.bits, code >> bits);
+            self.bits.put(1, 1);
+            self.bits.put(code & ((1 << bits) - 1), bits);
+        } else {
+            put_zeros(&mut self.bits, escape_len);
+            self.bits.put(1, 1);
+            self.bits.put(code - 1, self.raw_bits);
+        }
+        stats.0 += diff.abs();
+        if stats.1 == 0x40 {
+            stats.0 >>= 1;
+            stats.1 >>= 1;
+        }
+        stats.1 += 1;
+    }
+
+    /// Codes a new sample, the difference wrapping around the range.
+    fn code(&mut self, pred: i32, grad: i32, stats: &mut (i32, i32)) -> i32 {
+        let val = self.target(pred);
+        let half = (self.max() + 1) / 2;
+        let delta = if grad < 0 { pred - val } else { val - pred };
+        let diff = (delta + half).rem_euclid(self.max() + 1) - half;
+        self.put_difference(stats, diff);
+        val
+    }
+
+    fn interpolate_even(&mut self, line: usize, col: usize) {
+        let val = interpolate(
+            self.get(line - 1, col),
+            self.get(line - 1, col - 1),
+            self.get(line - 1, col + 1),
+            self.get(line - 2, col),
+        );
+        self.set(line, col, val);
+    }
+
+    fn even(&mut self, line: usize, col: usize, grads: usize) {
+        let up = self.get(line - 1, col);
+        let up_left = self.get(line - 1, col - 1);
+        let up_right = self.get(line - 1, col + 1);
+        let up2 = self.get(line - 2, col);
+        let grad = quantize(up - up2) * 9 + quantize(up_left - up);
+        let index = usize::try_from(grad.unsigned_abs()).unwrap();
+        let mut stats =
+            *self.even_stats.get(grads).unwrap().get(index).unwrap();
+        let val = self.code(
+            interpolate(up, up_left, up_right, up2),
+            grad,
+            &mut stats,
+        );
+        *self
+            .even_stats
+            .get_mut(grads)
+            .unwrap()
+            .get_mut(index)
+            .unwrap() = stats;
+        self.set(line, col, val);
+    }
+
+    fn odd(&mut self, line: usize, col: usize, grads: usize) {
+        let left = self.get(line, col - 1);
+        let right = self.get(line, col + 1);
+        let up = self.get(line - 1, col);
+        let up_left = self.get(line - 1, col - 1);
+        let up_right = self.get(line - 1, col + 1);
+        let grad = quantize(up - up_left) * 9 + quantize(up_left - left);
+        let index = usize::try_from(grad.unsigned_abs()).unwrap();
+        let pred = if (up > up_left && up > up_right)
+            || (up < up_left && up < up_right)
+        {
+            (right + left + 2 * up) >> 2
+        } else {
+            (left + right) >> 1
+        };
+        let mut stats = *self.odd_stats.get(grads).unwrap().get(index).unwrap();
+        let val = self.code(pred, grad, &mut stats);
+        *self
+            .odd_stats
+            .get_mut(grads)
+            .unwrap()
+            .get_mut(index)
+            .unwrap() = stats;
+        self.set(line, col, val);
+    }
+
+    fn extend(&mut self, (first, last): (usize, usize)) {
+        for line in first..=last {
+            self.set(line, 0, self.get(line - 1, 1));
+            self.set(line, self.width + 1, self.get(line - 1, self.width));
+        }
+    }
+
+    fn pass(&mut self, pass: &Pass) {
+        let (mut even, mut odd) = (0, 1);
+        while even < self.width || odd < self.width {
+            if even < self.width {
+                for (line, mode) in pass.lines {
+                    if mode.is_interpolated(even) {
+                        self.interpolate_even(line, even + 1);
+                    } else {
+                        self.even(line, even + 1, pass.grads);
+                    }
+                }
+                even += 2;
+            }
+            if even > 8 {
+                for (line, _) in pass.lines {
+                    self.odd(line, odd + 1, pass.grads);
+                }
+                odd += 2;
+            }
+        }
+        for (line, _) in pass.lines {
+            self.extend(plane_lines(line));
+        }
+    }
+
+    fn advance(&mut self) {
+        let pitch = self.width + 2;
+        for (prev, cur, count) in [(R0, R3, 3), (G0, G6, 6), (B0, B3, 3)] {
+            self.lines
+                .copy_within(cur * pitch..(cur + 2) * pitch, prev * pitch);
+            let first = cur + 2 - count;
+            self.lines
+                .get_mut(first * pitch..(first + count) * pitch)
+                .unwrap()
+                .fill(0);
+            self.extend((first, first));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Image {
+    xtrans: bool,
+    raw_bits: u32,
+    width: usize,
+    height: usize,
+}
+
+impl Image {
+    fn cfa(&self) -> (Vec<ColorVariant>, usize) {
+        if self.xtrans {
+            (xtrans(), 6)
+        } else {
+            (BAYER.to_vec(), 2)
+        }
+    }
+
+    fn header(&self) -> Vec<u8> {
+        let blocks = self.width.div_ceil(BLOCK_SIZE);
+        let u16_bytes = |val: usize| {
+            let [hi, lo] = [val >> 8, val & 0xFF];
+            [hi, lo].map(|byte| u8::try_from(byte).unwrap())
+        };
+        let mut out = vec![0x49, 0x53, 1, if self.xtrans { 16 } else { 0 }];
+        out.push(u8::try_from(self.raw_bits).unwrap());
+        out.extend(u16_bytes(self.height));
+        out.extend(u16_bytes(blocks * BLOCK_SIZE));
+        out.extend(u16_bytes(self.width));
+        out.extend(u16_bytes(BLOCK_SIZE));
+        out.push(u8::try_from(blocks).unwrap());
+        out.extend(u16_bytes(self.height / 6));
+        out
+    }
+
+    /// Copies the current lines of the encoder into the given block
+    /// column of the image.
+    fn copy_line(
+        &self,
+        enc: &Encoder,
+        out: &mut [u16],
+        block: usize,
+        line: usize,
+    ) {
+        let (cfa, period) = self.cfa();
+        let start = block * BLOCK_SIZE;
+        for row in 0..6 {
+            for col in start..self.width.min(start + BLOCK_SIZE) {
+                let local = col - start;
+                let buf =
+                    match cfa.get((row % period) * period + local % period) {
+                        Some(ColorVariant::Red) => R2 + row / 2,
+                        Some(ColorVariant::Blue) => B2 + row / 2,
+                        _ => G2 + row,
+                    };
+                let buf_col = if self.xtrans {
+                    ((local * 2 / 3) & !1)
+                        + ((local % 3) & 1)
+                        + ((local % 3) >> 1)
+                } else {
+                    local >> 1
+                };
+                *out.get_mut((line * 6 + row) * self.width + col).unwrap() =
+                    u16::try_from(enc.get(buf, buf_col + 1)).unwrap();
+            }
+        }
+    }
+
+    /// The coded strips, and the samples that they decode to.
+    fn encode_strips(&self) -> (Vec<Vec<u8>>, Vec<u16>) {
+        let passes = if self.xtrans {
+            &XTRANS_PASSES
+        } else {
+            &BAYER_PASSES
+        };
+        let mut expected = vec![0; self.width * self.height];
+        let mut strips = vec![];
+        for block in 0..self.width.div_ceil(BLOCK_SIZE) {
+            let mut enc = Encoder::new(
+                self.xtrans,
+                self.raw_bits,
+                7 + u32::try_from(block).unwrap(),
+            );
+            for line in 0..self.height / 6 {
+                for pass in passes {
+                    enc.pass(pass);
+                }
+                self.copy_line(&enc, &mut expected, block, line);
+                enc.advance();
+            }
+            strips.push(enc.bits.finish(BitOrder::MSB));
+        }
+        (strips, expected)
+    }
+
+    fn encode(&self) -> (Vec<u8>, Vec<u16>) {
+        let (strips, expected) = self.encode_strips();
+        (with_header(self.header(), &strips), expected)
+    }
+
+    fn decompressor<'a>(
+        &self,
+        data: &'a [u8],
+    ) -> Result<FujiDecompressor<'a>, FujiError> {
+        let (cfa, period) = self.cfa();
+        decompressor(data, dims(self.width, self.height), &cfa, period)
+    }
+}
+
+/// Appends the strip sizes (padded to 16 bytes) and the strips.
+fn with_header(mut out: Vec<u8>, strips: &[Vec<u8>]) -> Vec<u8> {
+    for strip in strips {
+        let size = strip.len();
+        out.extend(
+            [
+                size >> 24,
+                (size >> 16) & 0xFF,
+                (size >> 8) & 0xFF,
+                size & 0xFF,
+            ]
+            .map(|byte| u8::try_from(byte).unwrap()),
+        );
+    }
+    out.resize(out.len().next_multiple_of(16), 0);
+    for strip in strips {
+        out.extend(strip);
+    }
+    out
+}
+
+fn dims(
+    width: usize,
+    height: usize,
+) -> Dimensions2D<core::num::NonZero<usize>> {
+    Dimensions2D::new(
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowCount::new(core::num::NonZero::new(height).unwrap()),
+    )
+}
+
+fn decompressor<'a>(
+    data: &'a [u8],
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    cfa: &[ColorVariant],
+    period: usize,
+) -> Result<FujiDecompressor<'a>, FujiError> {
+    let period = core::num::NonZero::new(period).unwrap();
+    let cfa =
+        Array2DRef::new(cfa, RowLength::new(period), RowPitch::new(period));
+    let cfa = OffsetArray2DRef::new(
+        cfa,
+        CoordOffset2D::new(RowOffset::new(0), ColOffset::new(0)),
+    );
+    FujiDecompressor::new(data, dims, &cfa)
+}
+
+fn decode(image: &Image, data: &[u8]) -> Result<Vec<u16>, FujiError> {
+    let decoder = image.decompressor(data)?;
+    let mut buf = vec![0; image.width * image.height];
+    let mut output = Array2DRefMut::new(
+        buf.as_mut_slice(),
+        RowLength::new(core::num::NonZero::new(image.width).unwrap()),
+        RowPitch::new(core::num::NonZero::new(image.width).unwrap()),
+    );
+    decoder.decode(&mut output)?;
+    Ok(buf)
+}
+
+fn round_trip(image: &Image) {
+    let (data, expected) = image.encode();
+    assert_eq!(decode(image, &data), Ok(expected));
+}
+
+const BAYER_IMAGE: Image = Image {
+    xtrans: false,
+    raw_bits: 12,
+    width: BLOCK_SIZE,
+    height: 12,
+};
+
+#[test]
+fn bayer_round_trip_test() {
+    round_trip(&BAYER_IMAGE);
+    round_trip(&Image {
+        raw_bits: 14,
+        ..BAYER_IMAGE
+    });
+}
+
+#[test]
+fn xtrans_round_trip_test() {
+    let image = Image {
+        xtrans: true,
+        ..BAYER_IMAGE
+    };
+    round_trip(&image);
+    round_trip(&Image {
+        raw_bits: 14,
+        ..image
+    });
+}
+
+#[test]
+fn multiple_strips_test() {
+    // The last strip is narrower than the others.
+    for xtrans in [false, true] {
+        round_trip(&Image {
+            xtrans,
+            width: 2 * BLOCK_SIZE + 24,
+            height: 6,
+            ..BAYER_IMAGE
+        });
+    }
+}
+
+#[test]
+fn invalid_header_test() {
+    let (data, _) = BAYER_IMAGE.encode();
+    // The signature, the bits per sample, the block size and the number
+    // of lines.
+    for (index, val) in [(0, 0x48), (4, 16), (11, 0x20), (15, 3)] {
+        let mut invalid = data.clone();
+        *invalid.get_mut(index).unwrap() = val;
+        assert_eq!(
+            BAYER_IMAGE.decompressor(&invalid).unwrap_err(),
+            FujiError::InvalidHeader
+        );
+    }
+}
+
+#[test]
+fn unexpected_end_of_input_test() {
+    let (data, _) = BAYER_IMAGE.encode();
+    for len in [10, 18, data.len() - 1] {
+        assert_eq!(
+            BAYER_IMAGE
+                .decompressor(data.get(..len).unwrap())
+                .unwrap_err(),
+            FujiError::UnexpectedEndOfInput
+        );
+    }
+}
+
+#[test]
+fn dimensions_mismatch_test() {
+    let (data, _) = BAYER_IMAGE.encode();
+    let image = Image {
+        height: 6,
+        ..BAYER_IMAGE
+    };
+    assert_eq!(
+        image.decompressor(&data).unwrap_err(),
+        FujiError::DimensionsMismatch
+    );
+}
+
+#[test]
+fn invalid_cfa_test() {
+    let (data, _) = BAYER_IMAGE.encode();
+    let dims = dims(BAYER_IMAGE.width, BAYER_IMAGE.height);
+    // The CFA must tile the 6x6 blocks.
+    let large = [ColorVariant::Green; 16];
+    assert_eq!(
+        decompressor(&data, dims, &large, 4).unwrap_err(),
+        FujiError::InvalidCfa
+    );
+    let cmy = [ColorVariant::Cyan; 4];
+    assert_eq!(
+        decompressor(&data, dims, &cmy, 2).unwrap_err(),
+        FujiError::InvalidCfa
+    );
+}
+
+#[test]
+fn invalid_code_test() {
+    // An escaped code one past the largest value.
+    let mut bits = BitStreamBuilder::new();
+    put_zeros(&mut bits, 35);
+    bits.put(1, 1);
+    bits.put(0xFFF, 12);
+    let data = with_header(BAYER_IMAGE.header(), &[bits.finish(BitOrder::MSB)]);
+    assert_eq!(decode(&BAYER_IMAGE, &data), Err(FujiError::InvalidCode));
+}
+
+#[test]
+fn truncated_test() {
+    let data = with_header(BAYER_IMAGE.header(), &[vec![0; 4]]);
+    assert_eq!(decode(&BAYER_IMAGE, &data), Err(FujiError::TruncatedData));
+}
+
+#[test]
+fn output_dimensions_mismatch_test() {
+    let (data, _) = BAYER_IMAGE.encode();
+    let decoder = BAYER_IMAGE.decompressor(&data).unwrap();
+    let mut buf = vec![0; BLOCK_SIZE * 6];
+    let mut output = Array2DRefMut::new(
+        buf.as_mut_slice(),
+        RowLength::new(core::num::NonZero::new(BLOCK_SIZE).unwrap()),
+        RowPitch::new(core::num::NonZero::new(BLOCK_SIZE).unwrap()),
+    );
+    assert_eq!(
+        decoder.decode(&mut output),
+        Err(FujiError::OutputDimensionsMismatch)
+    );
+}
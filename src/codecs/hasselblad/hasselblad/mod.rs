@@ -0,0 +1,389 @@
+use rawspeed_bitstream_bitstream_decoder::bitstreamer::{
+    BitStream as _, BitStreamerBase,
+};
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrderMSB32;
+use rawspeed_bitstream_bytestream_decoder::bytestreamer::ByteStreamer;
+use rawspeed_codecs_huffman::huffman::{
+    HuffmanCode, HuffmanError, HuffmanTable, MAX_DIFF_LENGTH,
+};
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_std::coord_common::{Dimensions2D, RowCount, RowIndex, RowLength};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+
+const MARKER_SOF3: u8 = 0xC3;
+const MARKER_DHT: u8 = 0xC4;
+const MARKER_SOI: u8 = 0xD8;
+const MARKER_SOS: u8 = 0xDA;
+
+/// The predictor the scan header names, which is not one of the standard
+/// ones: the samples of each row are predicted pairwise.
+const PREDICTOR: u8 = 8;
+
+/// The prediction of the first pair of samples of each row,
+/// before the pixel base offset is applied.
+const INITIAL_PREDICTION: i32 = 0x8000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum HasselbladError {
+    UnexpectedEndOfInput,
+    MissingSOI,
+    UnexpectedMarker(u8),
+    InvalidSegmentLength(u8),
+    InvalidFrameHeader,
+    InvalidHuffmanTable,
+    MissingHuffmanTable,
+    InvalidScanHeader,
+    InvalidPredictor(u8),
+    OddWidth,
+    InvalidHuffmanCode,
+    TruncatedData,
+    OutputDimensionsMismatch,
+}
+
+impl core::fmt::Display for HasselbladError {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            HasselbladError::UnexpectedEndOfInput => {
+                write!(f, "HasselbladError(UnexpectedEndOfInput)")
+            }
+            HasselbladError::MissingSOI => {
+                write!(f, "HasselbladError(MissingSOI)")
+            }
+            HasselbladError::UnexpectedMarker(marker) => {
+                write!(f, "HasselbladError(UnexpectedMarker(0x{marker:02x}))")
+            }
+            HasselbladError::InvalidSegmentLength(marker) => {
+                write!(
+                    f,
+                    "HasselbladError(InvalidSegmentLength(0x{marker:02x}))"
+                )
+            }
+            HasselbladError::InvalidFrameHeader => {
+                write!(f, "HasselbladError(InvalidFrameHeader)")
+            }
+            HasselbladError::InvalidHuffmanTable => {
+                write!(f, "HasselbladError(InvalidHuffmanTable)")
+            }
+            HasselbladError::MissingHuffmanTable => {
+                write!(f, "HasselbladError(MissingHuffmanTable)")
+            }
+            HasselbladError::InvalidScanHeader => {
+                write!(f, "HasselbladError(InvalidScanHeader)")
+            }
+            HasselbladError::InvalidPredictor(predictor) => {
+                write!(f, "HasselbladError(InvalidPredictor({predictor}))")
+            }
+            HasselbladError::OddWidth => {
+                write!(f, "HasselbladError(OddWidth)")
+            }
+            HasselbladError::InvalidHuffmanCode => {
+                write!(f, "HasselbladError(InvalidHuffmanCode)")
+            }
+            HasselbladError::TruncatedData => {
+                write!(f, "HasselbladError(TruncatedData)")
+            }
+            HasselbladError::OutputDimensionsMismatch => {
+                write!(f, "HasselbladError(OutputDimensionsMismatch)")
+            }
+        }
+    }
+}
+
+impl From<HuffmanError> for HasselbladError {
+    #[inline]
+    fn from(err: HuffmanError) -> Self {
+        if err == HuffmanError::EndOfStream {
+            HasselbladError::TruncatedData
+        } else {
+            HasselbladError::InvalidHuffmanCode
+        }
+    }
+}
+
+/// Big-endian reader over the marker segments.
+#[derive(Debug, Clone, Copy)]
+struct ByteReader<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    fn get_bytes(&mut self, len: usize) -> Result<&'a [u8], HasselbladError> {
+        let (bytes, rest) = self
+            .input
+            .split_at_checked(len)
+            .ok_or(HasselbladError::UnexpectedEndOfInput)?;
+        self.input = rest;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, HasselbladError> {
+        Ok(*self.get_bytes(1)?.first().unwrap())
+    }
+
+    fn read_u16(&mut self) -> Result<u16, HasselbladError> {
+        let bytes = self.get_bytes(size_of::<u16>())?;
+        Ok(ByteStreamer::new(bytes, Endianness::Big).read())
+    }
+
+    /// Reads a marker, skipping any preceding fill bytes.
+    fn read_marker(&mut self) -> Result<u8, HasselbladError> {
+        if self.read_u8()? != 0xFF {
+            return Err(HasselbladError::UnexpectedMarker(0));
+        }
+        loop {
+            match self.read_u8()? {
+                0xFF => {}
+                marker => return Ok(marker),
+            }
+        }
+    }
+
+    /// Reads the payload of the segment introduced by `marker`.
+    fn read_segment(&mut self, marker: u8) -> Result<Self, HasselbladError> {
+        let len = usize::from(self.read_u16()?);
+        let len = len
+            .checked_sub(size_of::<u16>())
+            .ok_or(HasselbladError::InvalidSegmentLength(marker))?;
+        Ok(Self {
+            input: self.get_bytes(len)?,
+        })
+    }
+
+    const fn is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+}
+
+/// A single component, whose samples are coded in pairs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Frame {
+    precision: u8,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+}
+
+fn parse_frame(mut segment: ByteReader<'_>) -> Result<Frame, HasselbladError> {
+    let precision = segment.read_u8()?;
+    let height = usize::from(segment.read_u16()?);
+    let width = usize::from(segment.read_u16()?);
+    let num_components = segment.read_u8()?;
+    if !(2..=16).contains(&precision) || num_components != 1 {
+        return Err(HasselbladError::InvalidFrameHeader);
+    }
+    let _id = segment.read_u8()?;
+    let sampling = segment.read_u8()?;
+    let _quantization_table = segment.read_u8()?;
+    if !segment.is_empty() {
+        return Err(HasselbladError::InvalidSegmentLength(MARKER_SOF3));
+    }
+    if sampling != 0x11 {
+        return Err(HasselbladError::InvalidFrameHeader);
+    }
+    let (Some(width), Some(height)) = (
+        core::num::NonZero::new(width),
+        core::num::NonZero::new(height),
+    ) else {
+        return Err(HasselbladError::InvalidFrameHeader);
+    };
+    if !width.get().is_multiple_of(2) {
+        return Err(HasselbladError::OddWidth);
+    }
+    Ok(Frame {
+        precision,
+        dims: Dimensions2D::new(RowLength::new(width), RowCount::new(height)),
+    })
+}
+
+/// Only the first table is ever selected by the scan.
+fn parse_huffman_table(
+    mut segment: ByteReader<'_>,
+    table: &mut Option<HuffmanTable>,
+) -> Result<(), HasselbladError> {
+    while !segment.is_empty() {
+        let class_and_index = segment.read_u8()?;
+        let counts: [u8; 16] = segment.get_bytes(16)?.try_into().unwrap();
+        let num_symbols = counts.iter().map(|count| usize::from(*count)).sum();
+        let symbols = segment.get_bytes(num_symbols)?;
+        if class_and_index != 0 {
+            continue;
+        }
+        if symbols.iter().any(|symbol| *symbol > MAX_DIFF_LENGTH) {
+            return Err(HasselbladError::InvalidHuffmanTable);
+        }
+        let code = HuffmanCode::new(&counts, symbols)
+            .map_err(|_err| HasselbladError::InvalidHuffmanTable)?;
+        *table = Some(
+            HuffmanTable::new(&code, HuffmanTable::DEFAULT_LUT_BITS)
+                .map_err(|_err| HasselbladError::InvalidHuffmanTable)?,
+        );
+    }
+    Ok(())
+}
+
+fn parse_scan(mut segment: ByteReader<'_>) -> Result<(), HasselbladError> {
+    let num_components = segment.read_u8()?;
+    if num_components != 1 {
+        return Err(HasselbladError::InvalidScanHeader);
+    }
+    let _id = segment.read_u8()?;
+    let table_index = segment.read_u8()? >> 4;
+    let predictor = segment.read_u8()?;
+    let _spectral_end = segment.read_u8()?;
+    let _point_transform = segment.read_u8()?;
+    if !segment.is_empty() {
+        return Err(HasselbladError::InvalidSegmentLength(MARKER_SOS));
+    }
+    if table_index != 0 {
+        return Err(HasselbladError::InvalidScanHeader);
+    }
+    if predictor != PREDICTOR {
+        return Err(HasselbladError::InvalidPredictor(predictor));
+    }
+    Ok(())
+}
+
+/// Reads a difference of `len` bits, sign-extended JPEG-style, except
+/// that the (all ones) 16-bit one is the most negative difference.
+fn get_difference(
+    bs: &mut BitStreamerBase<'_, BitOrderMSB32>,
+    len: u8,
+) -> Result<i32, HasselbladError> {
+    if len == 0 {
+        return Ok(0);
+    }
+    let nbits = u32::from(len);
+    bs.fill(nbits)
+        .map_err(|_err| HasselbladError::TruncatedData)?;
+    let bits = bs.peek_bits_no_fill(nbits).zext();
+    bs.skip_bits_no_fill(nbits);
+    let diff = i32::try_from(bits).unwrap();
+    if diff == 0xFFFF {
+        return Ok(-0x8000);
+    }
+    if diff < 1 << (len - 1) {
+        Ok(diff - (1 << len) + 1)
+    } else {
+        Ok(diff)
+    }
+}
+
+/// Hasselblad's lossless JPEG variant: the headers are standard, but
+/// the entropy-coded data is not byte-stuffed and is read in 32-bit
+/// little-endian chunks, and the two samples of each pair have their
+/// difference lengths coded before either difference.
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct HasselbladDecompressor<'a> {
+    table: HuffmanTable,
+    frame: Frame,
+    data: &'a [u8],
+    initial_prediction: i32,
+}
+
+impl<'a> HasselbladDecompressor<'a> {
+    /// `pixel_base_offset` shifts the prediction each row starts from.
+    #[inline]
+    pub fn new(
+        input: &'a [u8],
+        pixel_base_offset: i32,
+    ) -> Result<Self, HasselbladError> {
+        let mut reader = ByteReader { input };
+        if reader.read_marker() != Ok(MARKER_SOI) {
+            return Err(HasselbladError::MissingSOI);
+        }
+        let mut frame = None;
+        let mut table = None;
+        loop {
+            let marker = reader.read_marker()?;
+            match marker {
+                MARKER_SOF3 => {
+                    frame = Some(parse_frame(reader.read_segment(marker)?)?);
+                }
+                MARKER_DHT => {
+                    parse_huffman_table(
+                        reader.read_segment(marker)?,
+                        &mut table,
+                    )?;
+                }
+                MARKER_SOS => {
+                    let frame = frame
+                        .ok_or(HasselbladError::UnexpectedMarker(marker))?;
+                    parse_scan(reader.read_segment(marker)?)?;
+                    return Ok(Self {
+                        table: table
+                            .ok_or(HasselbladError::MissingHuffmanTable)?,
+                        frame,
+                        data: reader.input,
+                        initial_prediction: INITIAL_PREDICTION
+                            + pixel_base_offset,
+                    });
+                }
+                0xC0..=0xCF | MARKER_SOI | 0xD0..=0xD7 | 0xD9 => {
+                    return Err(HasselbladError::UnexpectedMarker(marker));
+                }
+                // APPn, COM, DQT and friends carry nothing of interest.
+                _ => {
+                    let _ = reader.read_segment(marker)?;
+                }
+            }
+        }
+    }
+
+    /// The dimensions of the frame.
+    #[inline]
+    pub const fn dims(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        self.frame.dims
+    }
+
+    /// The number of bits per sample the frame declares.
+    #[inline]
+    #[must_use]
+    pub const fn precision(&self) -> u8 {
+        self.frame.precision
+    }
+
+    #[inline(never)]
+    pub fn decode(
+        &self,
+        output: &mut Array2DRefMut<'_, u16>,
+    ) -> Result<(), HasselbladError> {
+        if output.dims() != self.frame.dims {
+            return Err(HasselbladError::OutputDimensionsMismatch);
+        }
+        // The bitstream is consumed in 32-bit chunks.
+        let mut data = self.data.to_vec();
+        data.resize(data.len().next_multiple_of(size_of::<u32>()), 0);
+        let mut bs =
+            BitStreamerBase::<BitOrderMSB32>::try_from(data.as_slice())
+                .map_err(|_err| HasselbladError::TruncatedData)?;
+        for row in 0..self.frame.dims.row_count().get() {
+            let mut preds = [self.initial_prediction; 2];
+            let out = output.get_row_mut(RowIndex::new(row)).unwrap();
+            for pair in out.chunks_exact_mut(2) {
+                let lens = [
+                    self.table.decode_code_value(&mut bs)?,
+                    self.table.decode_code_value(&mut bs)?,
+                ];
+                for ((sample, pred), len) in
+                    pair.iter_mut().zip(&mut preds).zip(lens)
+                {
+                    *pred += get_difference(&mut bs, len)?;
+                    // Reconstruction is performed modulo 2^16.
+                    #[expect(
+                        clippy::cast_possible_truncation,
+                        clippy::cast_sign_loss
+                    )]
+                    {
+                        *sample = *pred as u16;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
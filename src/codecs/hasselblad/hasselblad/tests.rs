@@ -0,0 +1,244 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_std::coord_common::{RowIndex, RowLength, RowPitch};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+
+use super::{HasselbladDecompressor, HasselbladError};
+
+/// All 17 difference lengths, as 5-bit codes equal to the length.
+const COUNTS: [u8; 16] = [0, 0, 0, 0, 17, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+const CODE_LENGTH: u32 = 5;
+
+fn diff_len(diff: i32) -> u32 {
+    if diff == -0x8000 {
+        16
+    } else {
+        32 - diff.unsigned_abs().leading_zeros()
+    }
+}
+
+fn diff_bits(diff: i32, len: u32) -> u32 {
+    match diff {
+        -0x8000 => 0xFFFF,
+        _ if diff < 0 => u32::try_from(diff + (1 << len) - 1).unwrap(),
+        _ => u32::try_from(diff).unwrap(),
+    }
+}
+
+/// The entropy-coded data of the given samples, row by row.
+fn encode_scan(rows: &[Vec<u16>], initial_prediction: i32) -> Vec<u8> {
+    let mut bits = BitStreamBuilder::new();
+    for row in rows {
+        let mut preds = [initial_prediction; 2];
+        for pair in row.chunks_exact(2) {
+            let diffs: Vec<i32> = pair
+                .iter()
+                .zip(&mut preds)
+                .map(|(sample, pred)| {
+                    let diff = i32::from(*sample) - *pred;
+                    *pred = i32::from(*sample);
+                    diff
+                })
+                .collect();
+            for diff in &diffs {
+                bits.put(diff_len(*diff), CODE_LENGTH);
+            }
+            for diff in diffs {
+                let len = diff_len(diff);
+                bits.put(diff_bits(diff, len), len);
+            }
+        }
+    }
+    bits.finish(BitOrder::MSB32)
+}
+
+fn segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+    let len = u16::try_from(payload.len() + 2).unwrap();
+    let mut out = vec![0xFF, marker];
+    out.extend(len.to_le_bytes().iter().rev());
+    out.extend(payload);
+    out
+}
+
+#[derive(Debug, Clone)]
+struct Encoder {
+    width: u16,
+    height: u16,
+    num_components: u8,
+    predictor: u8,
+    with_table: bool,
+}
+
+impl Encoder {
+    const fn new(width: u16, height: u16) -> Self {
+        Self {
+            width,
+            height,
+            num_components: 1,
+            predictor: 8,
+            with_table: true,
+        }
+    }
+
+    fn headers(&self) -> Vec<u8> {
+        let mut out = vec![0xFF, 0xD8];
+        out.extend(segment(0xE0, b"APP0"));
+        if self.with_table {
+            let mut dht = vec![0];
+            dht.extend(COUNTS);
+            dht.extend(0..=16);
+            out.extend(segment(0xC4, &dht));
+        }
+        let mut sof = vec![16];
+        sof.extend(self.height.to_le_bytes().iter().rev());
+        sof.extend(self.width.to_le_bytes().iter().rev());
+        sof.push(self.num_components);
+        for id in 0..self.num_components {
+            sof.extend([id, 0x11, 0]);
+        }
+        out.extend(segment(0xC3, &sof));
+        out.extend(segment(0xDA, &[1, 0, 0, self.predictor, 0, 0]));
+        out
+    }
+
+    fn encode(&self, rows: &[Vec<u16>], pixel_base_offset: i32) -> Vec<u8> {
+        let mut out = self.headers();
+        out.extend(encode_scan(rows, 0x8000 + pixel_base_offset));
+        out
+    }
+}
+
+fn test_image(width: usize, height: usize) -> Vec<Vec<u16>> {
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| {
+                    u16::try_from((row * 7919 + col * 104_729) % 0x1_0000)
+                        .unwrap()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn decode(
+    decompressor: &HasselbladDecompressor<'_>,
+) -> Result<Vec<Vec<u16>>, HasselbladError> {
+    let dims = decompressor.dims();
+    let (width, height) = (dims.row_len().get(), dims.row_count().get());
+    let mut buf = vec![0; width * height];
+    let mut output = Array2DRefMut::new(
+        buf.as_mut_slice(),
+        dims.row_len(),
+        RowPitch::new(core::num::NonZero::new(width).unwrap()),
+    );
+    decompressor.decode(&mut output)?;
+    Ok((0..height)
+        .map(|row| output.get_row(RowIndex::new(row)).unwrap().to_vec())
+        .collect())
+}
+
+#[test]
+fn round_trip_test() {
+    let rows = test_image(6, 4);
+    let input = Encoder::new(6, 4).encode(&rows, 0);
+    let decompressor = HasselbladDecompressor::new(&input, 0).unwrap();
+    assert_eq!(decode(&decompressor), Ok(rows));
+}
+
+#[test]
+fn pixel_base_offset_test() {
+    let rows = test_image(4, 3);
+    for offset in [-0x4000, -1, 1, 0x1000] {
+        let input = Encoder::new(4, 3).encode(&rows, offset);
+        let decompressor = HasselbladDecompressor::new(&input, offset).unwrap();
+        assert_eq!(decode(&decompressor), Ok(rows.clone()));
+    }
+}
+
+#[test]
+fn longest_difference_test() {
+    // From the initial prediction, 0 is a difference of -0x8000,
+    // and 0xFFFF one of 0x7FFF.
+    let rows = vec![vec![0, 0xFFFF, 0x8000, 0x7FFF]];
+    let input = Encoder::new(4, 1).encode(&rows, 0);
+    let decompressor = HasselbladDecompressor::new(&input, 0).unwrap();
+    assert_eq!(decode(&decompressor), Ok(rows));
+}
+
+fn new_err(encoder: &Encoder) -> HasselbladError {
+    HasselbladDecompressor::new(&encoder.headers(), 0).unwrap_err()
+}
+
+#[test]
+fn invalid_headers_test() {
+    let predictor = Encoder {
+        predictor: 1,
+        ..Encoder::new(4, 2)
+    };
+    assert_eq!(new_err(&predictor), HasselbladError::InvalidPredictor(1));
+    let components = Encoder {
+        num_components: 2,
+        ..Encoder::new(4, 2)
+    };
+    assert_eq!(new_err(&components), HasselbladError::InvalidFrameHeader);
+    let no_table = Encoder {
+        with_table: false,
+        ..Encoder::new(4, 2)
+    };
+    assert_eq!(new_err(&no_table), HasselbladError::MissingHuffmanTable);
+}
+
+#[test]
+fn odd_width_test() {
+    assert_eq!(new_err(&Encoder::new(5, 2)), HasselbladError::OddWidth);
+}
+
+#[test]
+fn missing_soi_test() {
+    let headers = Encoder::new(4, 2).headers();
+    for input in [&[][..], headers.get(2..).unwrap()] {
+        assert_eq!(
+            HasselbladDecompressor::new(input, 0).unwrap_err(),
+            HasselbladError::MissingSOI
+        );
+    }
+}
+
+#[test]
+fn truncated_headers_test() {
+    let headers = Encoder::new(4, 2).headers();
+    assert_eq!(
+        HasselbladDecompressor::new(
+            headers.get(..headers.len() - 1).unwrap(),
+            0
+        )
+        .unwrap_err(),
+        HasselbladError::UnexpectedEndOfInput
+    );
+}
+
+#[test]
+fn truncated_data_test() {
+    let rows = test_image(64, 64);
+    let input = Encoder::new(64, 64).encode(&rows, 0);
+    let truncated = input.get(..input.len() / 2).unwrap();
+    let decompressor = HasselbladDecompressor::new(truncated, 0).unwrap();
+    assert_eq!(decode(&decompressor), Err(HasselbladError::TruncatedData));
+}
+
+#[test]
+fn output_dimensions_mismatch_test() {
+    let input = Encoder::new(4, 2).encode(&test_image(4, 2), 0);
+    let decompressor = HasselbladDecompressor::new(&input, 0).unwrap();
+    let mut buf = vec![0; 8];
+    let mut output = Array2DRefMut::new(
+        buf.as_mut_slice(),
+        RowLength::new(core::num::NonZero::new(2).unwrap()),
+        RowPitch::new(core::num::NonZero::new(2).unwrap()),
+    );
+    assert_eq!(
+        decompressor.decode(&mut output),
+        Err(HasselbladError::OutputDimensionsMismatch)
+    );
+}
@@ -0,0 +1,360 @@
+use rawspeed_bitstream_bitstream_decoder::bitstreamer::BitStream;
+use rawspeed_common_bitseq::bitseq::BitSeqConstraints;
+
+/// The longest code a JPEG-style code length histogram can describe.
+pub const MAX_CODE_LENGTH: u32 = 16;
+
+/// Largest difference magnitude category ("diff length").
+pub const MAX_DIFF_LENGTH: u8 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum HuffmanError {
+    SymbolCountMismatch,
+    EmptyCode,
+    OverSubscribed(u32),
+    Incomplete,
+    InvalidLookupBits(u32),
+    InvalidDiffLength(u8),
+    InvalidCode,
+    EndOfStream,
+}
+
+impl core::fmt::Display for HuffmanError {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            HuffmanError::SymbolCountMismatch => {
+                write!(f, "HuffmanError(SymbolCountMismatch)")
+            }
+            HuffmanError::EmptyCode => write!(f, "HuffmanError(EmptyCode)"),
+            HuffmanError::OverSubscribed(len) => {
+                write!(f, "HuffmanError(OverSubscribed({len}))")
+            }
+            HuffmanError::Incomplete => write!(f, "HuffmanError(Incomplete)"),
+            HuffmanError::InvalidLookupBits(bits) => {
+                write!(f, "HuffmanError(InvalidLookupBits({bits}))")
+            }
+            HuffmanError::InvalidDiffLength(len) => {
+                write!(f, "HuffmanError(InvalidDiffLength({len}))")
+            }
+            HuffmanError::InvalidCode => write!(f, "HuffmanError(InvalidCode)"),
+            HuffmanError::EndOfStream => {
+                write!(f, "HuffmanError(EndOfStream)")
+            }
+        }
+    }
+}
+
+/// A canonical prefix code, as described by a JPEG `DHT` segment
+/// (ITU T.81, Annex C): the number of codes of each length `1..=16`,
+/// followed by the symbols in order of increasing code length.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct HuffmanCode {
+    counts: [u8; MAX_CODE_LENGTH as usize],
+    symbols: Vec<u8>,
+}
+
+impl HuffmanCode {
+    /// Validates the code, rejecting over-subscribed code lengths.
+    /// Incomplete codes (as JPEG produces, which never assigns the all-ones
+    /// code) are accepted; decoding an unassigned code is then an error.
+    #[inline]
+    pub fn new(
+        counts: &[u8; MAX_CODE_LENGTH as usize],
+        symbols: &[u8],
+    ) -> Result<Self, HuffmanError> {
+        let num_codes: usize =
+            counts.iter().map(|count| usize::from(*count)).sum();
+        if num_codes != symbols.len() {
+            return Err(HuffmanError::SymbolCountMismatch);
+        }
+        if num_codes == 0 {
+            return Err(HuffmanError::EmptyCode);
+        }
+        let mut code = 0_u32;
+        for (len, count) in (1..=MAX_CODE_LENGTH).zip(counts) {
+            code = (code << 1) + u32::from(*count);
+            // There are only `2^len` codes of length `len`.
+            if code > 1 << len {
+                return Err(HuffmanError::OverSubscribed(len));
+            }
+        }
+        Ok(Self {
+            counts: *counts,
+            symbols: symbols.to_vec(),
+        })
+    }
+
+    /// Like [`Self::new`], but also rejects codes that leave
+    /// part of the code space unassigned.
+    #[inline]
+    pub fn new_complete(
+        counts: &[u8; MAX_CODE_LENGTH as usize],
+        symbols: &[u8],
+    ) -> Result<Self, HuffmanError> {
+        let code = Self::new(counts, symbols)?;
+        if !code.is_complete() {
+            return Err(HuffmanError::Incomplete);
+        }
+        Ok(code)
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        let used = (1..=MAX_CODE_LENGTH)
+            .zip(self.counts)
+            .map(|(len, count)| u32::from(count) << (MAX_CODE_LENGTH - len))
+            .sum::<u32>();
+        used == 1 << MAX_CODE_LENGTH
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn counts(&self) -> &[u8; MAX_CODE_LENGTH as usize] {
+        &self.counts
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn symbols(&self) -> &[u8] {
+        &self.symbols
+    }
+
+    /// `(length, code)` of each symbol, in the order of [`Self::symbols`].
+    #[inline]
+    #[must_use]
+    pub fn codes(&self) -> Vec<(u32, u32)> {
+        let mut codes = Vec::with_capacity(self.symbols.len());
+        let mut code = 0_u32;
+        for (len, count) in (1..=MAX_CODE_LENGTH).zip(self.counts) {
+            for _ in 0..count {
+                codes.push((len, code));
+                code += 1;
+            }
+            code <<= 1;
+        }
+        codes
+    }
+}
+
+/// A lookup table entry, indexed by the next `lut_bits` bits of input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct LutEntry {
+    /// Length of the code starting with these bits,
+    /// or `0` if the code is longer than `lut_bits` (or unassigned).
+    code_len: u8,
+    symbol: u8,
+    /// Length of the code plus the difference bits,
+    /// or `0` if they do not all fit into `lut_bits`.
+    total_len: u8,
+    diff: i32,
+}
+
+/// A [`HuffmanCode`] prepared for decoding.
+///
+/// Codes of up to `lut_bits` bits are decoded with a single lookup, and,
+/// for the "full decode" ([`Self::decode_difference`]), so are differences
+/// whose code and value bits together fit. Everything else falls back to
+/// walking the canonical code length by length.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct HuffmanTable {
+    lut_bits: u32,
+    lut: Vec<LutEntry>,
+    /// Per code length: the first code, one past the last code,
+    /// and the index of the first symbol.
+    min_codes: [u32; MAX_CODE_LENGTH as usize + 1],
+    max_codes: [u32; MAX_CODE_LENGTH as usize + 1],
+    val_ptrs: [usize; MAX_CODE_LENGTH as usize + 1],
+    symbols: Vec<u8>,
+}
+
+impl HuffmanTable {
+    pub const DEFAULT_LUT_BITS: u32 = 11;
+
+    #[inline]
+    pub fn new(
+        code: &HuffmanCode,
+        lut_bits: u32,
+    ) -> Result<Self, HuffmanError> {
+        if !(1..=MAX_CODE_LENGTH).contains(&lut_bits) {
+            return Err(HuffmanError::InvalidLookupBits(lut_bits));
+        }
+        let mut table = Self {
+            lut_bits,
+            lut: vec![LutEntry::default(); 1 << lut_bits],
+            min_codes: [0; MAX_CODE_LENGTH as usize + 1],
+            max_codes: [0; MAX_CODE_LENGTH as usize + 1],
+            val_ptrs: [0; MAX_CODE_LENGTH as usize + 1],
+            symbols: code.symbols.clone(),
+        };
+        let mut next_code = 0_u32;
+        let mut num_symbols = 0_usize;
+        for (len, count) in (1..=MAX_CODE_LENGTH as usize).zip(code.counts) {
+            *table.val_ptrs.get_mut(len).unwrap() = num_symbols;
+            *table.min_codes.get_mut(len).unwrap() = next_code;
+            next_code += u32::from(count);
+            *table.max_codes.get_mut(len).unwrap() = next_code;
+            num_symbols += usize::from(count);
+            next_code <<= 1;
+        }
+        for ((len, bits), symbol) in code.codes().into_iter().zip(&code.symbols)
+        {
+            if len <= lut_bits {
+                table.fill_lut(len, bits, *symbol);
+            }
+        }
+        Ok(table)
+    }
+
+    fn fill_lut(&mut self, len: u32, code: u32, symbol: u8) {
+        let free_bits = self.lut_bits - len;
+        let first = usize::try_from(code << free_bits).unwrap();
+        let entries =
+            self.lut.get_mut(first..first + (1 << free_bits)).unwrap();
+        for (suffix, entry) in (0_u32..).zip(entries) {
+            *entry = LutEntry {
+                code_len: len.try_into().unwrap(),
+                symbol,
+                ..LutEntry::default()
+            };
+            if symbol > MAX_DIFF_LENGTH {
+                continue;
+            }
+            let diff_len = match symbol {
+                // Only the magnitude category is stored for the largest
+                // difference.
+                MAX_DIFF_LENGTH => 0,
+                _ => u32::from(symbol),
+            };
+            if diff_len <= free_bits {
+                let diff_bits = suffix >> (free_bits - diff_len);
+                entry.total_len = (len + diff_len).try_into().unwrap();
+                entry.diff = extend_difference(symbol, diff_bits);
+            }
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn lut_bits(&self) -> u32 {
+        self.lut_bits
+    }
+
+    fn lookup(&self, bits: u32) -> LutEntry {
+        let index = bits >> (MAX_CODE_LENGTH - self.lut_bits);
+        *self.lut.get(usize::try_from(index).unwrap()).unwrap()
+    }
+
+    /// Decodes a code of more than `lut_bits` bits,
+    /// given the next [`MAX_CODE_LENGTH`] bits of input.
+    fn decode_slow(&self, bits: u32) -> Result<(u32, u8), HuffmanError> {
+        for len in self.lut_bits + 1..=MAX_CODE_LENGTH {
+            let code = bits >> (MAX_CODE_LENGTH - len);
+            let index = usize::try_from(len).unwrap();
+            let max_code = *self.max_codes.get(index).unwrap();
+            let min_code = *self.min_codes.get(index).unwrap();
+            if (min_code..max_code).contains(&code) {
+                let symbol = *self.val_ptrs.get(index).unwrap()
+                    + usize::try_from(code - min_code).unwrap();
+                return Ok((len, *self.symbols.get(symbol).unwrap()));
+            }
+        }
+        Err(HuffmanError::InvalidCode)
+    }
+
+    fn decode_code_value_no_fill<B>(
+        &self,
+        bs: &mut B,
+    ) -> Result<u8, HuffmanError>
+    where
+        B: BitStream,
+        B::T: BitSeqConstraints + core::fmt::Debug,
+        u64: From<B::T>,
+    {
+        let bits = peek_bits(bs, MAX_CODE_LENGTH);
+        let entry = self.lookup(bits);
+        let (len, symbol) = match entry.code_len {
+            0 => self.decode_slow(bits)?,
+            len => (u32::from(len), entry.symbol),
+        };
+        bs.skip_bits_no_fill(len);
+        Ok(symbol)
+    }
+
+    /// Decodes a single symbol.
+    #[inline]
+    pub fn decode_code_value<B>(&self, bs: &mut B) -> Result<u8, HuffmanError>
+    where
+        B: BitStream,
+        B::T: BitSeqConstraints + core::fmt::Debug,
+        u64: From<B::T>,
+    {
+        bs.fill(MAX_CODE_LENGTH)
+            .map_err(|_err| HuffmanError::EndOfStream)?;
+        self.decode_code_value_no_fill(bs)
+    }
+
+    /// Decodes a single difference ("full decode"): the symbol is the
+    /// magnitude category, followed by that many bits of the value,
+    /// which are sign-extended.
+    #[inline]
+    pub fn decode_difference<B>(&self, bs: &mut B) -> Result<i32, HuffmanError>
+    where
+        B: BitStream,
+        B::T: BitSeqConstraints + core::fmt::Debug,
+        u64: From<B::T>,
+    {
+        bs.fill(2 * MAX_CODE_LENGTH)
+            .map_err(|_err| HuffmanError::EndOfStream)?;
+        let entry = self.lookup(peek_bits(bs, MAX_CODE_LENGTH));
+        if entry.total_len != 0 {
+            bs.skip_bits_no_fill(entry.total_len.into());
+            return Ok(entry.diff);
+        }
+        let diff_len = self.decode_code_value_no_fill(bs)?;
+        match diff_len {
+            0 | MAX_DIFF_LENGTH => Ok(extend_difference(diff_len, 0)),
+            1..MAX_DIFF_LENGTH => {
+                let diff_bits = peek_bits(bs, diff_len.into());
+                bs.skip_bits_no_fill(diff_len.into());
+                Ok(extend_difference(diff_len, diff_bits))
+            }
+            _ => Err(HuffmanError::InvalidDiffLength(diff_len)),
+        }
+    }
+}
+
+fn peek_bits<B>(bs: &mut B, nbits: u32) -> u32
+where
+    B: BitStream,
+    B::T: BitSeqConstraints + core::fmt::Debug,
+    u64: From<B::T>,
+{
+    u64::from(bs.peek_bits_no_fill(nbits).zext())
+        .try_into()
+        .unwrap()
+}
+
+/// Sign-extends `diff_len` bits of a difference value:
+/// values with the top bit clear are negative.
+fn extend_difference(diff_len: u8, diff_bits: u32) -> i32 {
+    match diff_len {
+        0 => 0,
+        MAX_DIFF_LENGTH => -0x8000,
+        _ => {
+            let diff = i32::try_from(diff_bits).unwrap();
+            if diff < 1 << (diff_len - 1) {
+                diff - (1 << diff_len) + 1
+            } else {
+                diff
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,200 @@
+use rawspeed_bitstream_bitstream_decoder::bitstreamer::BitStreamerBase;
+use rawspeed_bitstream_bitstreams::bitstreams::{BitOrder, BitOrderMSB};
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+
+use super::{HuffmanCode, HuffmanError, HuffmanTable, MAX_CODE_LENGTH};
+
+/// One code of each length `1..=15`, and two of length 16: complete.
+const LONG_COUNTS: [u8; 16] = [1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2];
+
+/// A difference code with codes of up to 16 bits:
+/// incomplete, since the all-ones code is unused.
+const DIFF_COUNTS: [u8; 16] = [0, 1, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1];
+const DIFF_SYMBOLS: [u8; 17] =
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+
+fn encode_symbols(code: &HuffmanCode, symbols: &[u8]) -> Vec<u8> {
+    let codes = code.codes();
+    let mut bits = BitStreamBuilder::new();
+    for symbol in symbols {
+        let index = code.symbols().iter().position(|s| s == symbol).unwrap();
+        let (len, value) = *codes.get(index).unwrap();
+        bits.put(value, len);
+    }
+    bits.finish(BitOrder::MSB)
+}
+
+fn encode_differences(code: &HuffmanCode, diffs: &[i32]) -> Vec<u8> {
+    let codes = code.codes();
+    let mut bits = BitStreamBuilder::new();
+    for diff in diffs {
+        let category = 32 - diff.unsigned_abs().leading_zeros();
+        let category = if *diff == -0x8000 { 16 } else { category };
+        let (len, value) =
+            *codes.get(usize::try_from(category).unwrap()).unwrap();
+        bits.put(value, len);
+        if category != 0 && category != 16 {
+            let diff_bits = if *diff < 0 {
+                diff + (1 << category) - 1
+            } else {
+                *diff
+            };
+            bits.put(diff_bits.try_into().unwrap(), category);
+        }
+    }
+    bits.finish(BitOrder::MSB)
+}
+
+fn diff_code() -> HuffmanCode {
+    HuffmanCode::new(&DIFF_COUNTS, &DIFF_SYMBOLS).unwrap()
+}
+
+fn all_differences() -> Vec<i32> {
+    let mut diffs = vec![0, -0x8000];
+    for category in 1..16 {
+        let (low, high) = (1 << (category - 1), (1 << category) - 1);
+        diffs.extend([low, high, -low, -high, i32::midpoint(low, high)]);
+    }
+    diffs
+}
+
+#[test]
+fn oversubscribed_test() {
+    let short_counts = [3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    assert_eq!(
+        HuffmanCode::new(&short_counts, &[0, 1, 2]),
+        Err(HuffmanError::OverSubscribed(1))
+    );
+    let mut counts = LONG_COUNTS;
+    *counts.last_mut().unwrap() = 3;
+    let symbols: Vec<u8> = (0..18).collect();
+    assert_eq!(
+        HuffmanCode::new(&counts, &symbols),
+        Err(HuffmanError::OverSubscribed(16))
+    );
+}
+
+#[test]
+fn symbol_count_mismatch_test() {
+    assert_eq!(
+        HuffmanCode::new(&DIFF_COUNTS, DIFF_SYMBOLS.get(1..).unwrap()),
+        Err(HuffmanError::SymbolCountMismatch)
+    );
+    assert_eq!(
+        HuffmanCode::new(&[0; 16], &[]),
+        Err(HuffmanError::EmptyCode)
+    );
+}
+
+#[test]
+fn completeness_test() {
+    assert!(!diff_code().is_complete());
+    assert_eq!(
+        HuffmanCode::new_complete(&DIFF_COUNTS, &DIFF_SYMBOLS),
+        Err(HuffmanError::Incomplete)
+    );
+    let symbols: Vec<u8> = (0..17).collect();
+    let code = HuffmanCode::new_complete(&LONG_COUNTS, &symbols).unwrap();
+    assert!(code.is_complete());
+}
+
+#[test]
+fn invalid_lookup_bits_test() {
+    for lut_bits in [0, 17] {
+        assert_eq!(
+            HuffmanTable::new(&diff_code(), lut_bits),
+            Err(HuffmanError::InvalidLookupBits(lut_bits))
+        );
+    }
+}
+
+#[test]
+fn decode_code_value_test() {
+    let symbols: Vec<u8> = (100..117).rev().collect();
+    let code = HuffmanCode::new(&LONG_COUNTS, &symbols).unwrap();
+    let input: Vec<u8> = symbols
+        .iter()
+        .chain(symbols.iter().rev())
+        .copied()
+        .collect();
+    let data = encode_symbols(&code, &input);
+    for lut_bits in 1..=MAX_CODE_LENGTH {
+        let table = HuffmanTable::new(&code, lut_bits).unwrap();
+        let mut bs =
+            BitStreamerBase::<BitOrderMSB>::try_from(data.as_slice()).unwrap();
+        for symbol in &input {
+            assert_eq!(table.decode_code_value(&mut bs), Ok(*symbol));
+        }
+    }
+}
+
+#[test]
+fn decode_difference_test() {
+    let diffs = all_differences();
+    let data = encode_differences(&diff_code(), &diffs);
+    for lut_bits in 1..=MAX_CODE_LENGTH {
+        let table = HuffmanTable::new(&diff_code(), lut_bits).unwrap();
+        let mut bs =
+            BitStreamerBase::<BitOrderMSB>::try_from(data.as_slice()).unwrap();
+        for diff in &diffs {
+            assert_eq!(table.decode_difference(&mut bs), Ok(*diff));
+        }
+    }
+}
+
+#[test]
+fn invalid_diff_length_test() {
+    let counts = [0, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    let code = HuffmanCode::new(&counts, &[1, 17]).unwrap();
+    let data = encode_symbols(&code, &[17]);
+    let table = HuffmanTable::new(&code, 8).unwrap();
+    let mut bs =
+        BitStreamerBase::<BitOrderMSB>::try_from(data.as_slice()).unwrap();
+    assert_eq!(
+        table.decode_difference(&mut bs),
+        Err(HuffmanError::InvalidDiffLength(17))
+    );
+}
+
+#[test]
+fn invalid_code_test() {
+    // The all-ones code is not assigned.
+    let data = [0xFF_u8; 8];
+    for lut_bits in [1, 8, 16] {
+        let table = HuffmanTable::new(&diff_code(), lut_bits).unwrap();
+        let mut bs =
+            BitStreamerBase::<BitOrderMSB>::try_from(data.as_slice()).unwrap();
+        assert_eq!(
+            table.decode_code_value(&mut bs),
+            Err(HuffmanError::InvalidCode)
+        );
+        assert_eq!(
+            table.decode_difference(&mut bs),
+            Err(HuffmanError::InvalidCode)
+        );
+    }
+}
+
+#[test]
+fn end_of_stream_test() {
+    let data = encode_differences(&diff_code(), &[0]);
+    let table = HuffmanTable::new(&diff_code(), HuffmanTable::DEFAULT_LUT_BITS)
+        .unwrap();
+    let mut bs =
+        BitStreamerBase::<BitOrderMSB>::try_from(data.as_slice()).unwrap();
+    let result = core::iter::repeat_with(|| table.decode_difference(&mut bs))
+        .find(Result::is_err);
+    assert_eq!(result, Some(Err(HuffmanError::EndOfStream)));
+}
+
+#[test]
+fn display_test() {
+    assert_eq!(
+        HuffmanError::OverSubscribed(3).to_string(),
+        "HuffmanError(OverSubscribed(3))"
+    );
+    assert_eq!(
+        HuffmanError::InvalidCode.to_string(),
+        "HuffmanError(InvalidCode)"
+    );
+}
@@ -0,0 +1,118 @@
+use rawspeed_std::coord_common::{Dimensions2D, RowCount, RowIndex, RowLength};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+use zune_jpeg::JpegDecoder;
+use zune_jpeg::zune_core::bytestream::ZCursor;
+use zune_jpeg::zune_core::colorspace::ColorSpace;
+use zune_jpeg::zune_core::options::DecoderOptions;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum JpegError {
+    /// The decoder rejected the stream, with its own description of why.
+    InvalidData(String),
+    UnsupportedComponents(u8),
+    OutputDimensionsMismatch,
+}
+
+impl core::fmt::Display for JpegError {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            JpegError::InvalidData(reason) => {
+                write!(f, "JpegError(InvalidData({reason}))")
+            }
+            JpegError::UnsupportedComponents(num_components) => {
+                write!(f, "JpegError(UnsupportedComponents({num_components}))")
+            }
+            JpegError::OutputDimensionsMismatch => {
+                write!(f, "JpegError(OutputDimensionsMismatch)")
+            }
+        }
+    }
+}
+
+fn invalid_data(err: impl core::fmt::Display) -> JpegError {
+    JpegError::InvalidData(err.to_string())
+}
+
+/// A lossy, 8-bit JPEG, as found in the tiles of lossy DNGs.
+///
+/// Three-component images are converted to RGB, and all components are
+/// stored interleaved, i.e. each output row holds `width * num_components`
+/// samples.
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct JpegDecompressor<'a> {
+    input: &'a [u8],
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    colorspace: ColorSpace,
+}
+
+impl<'a> JpegDecompressor<'a> {
+    #[inline]
+    pub fn new(input: &'a [u8]) -> Result<Self, JpegError> {
+        let mut decoder = JpegDecoder::new(ZCursor::new(input));
+        decoder.decode_headers().map_err(invalid_data)?;
+        let info = decoder
+            .info()
+            .ok_or_else(|| invalid_data("missing frame header"))?;
+        let colorspace = match info.components {
+            1 => ColorSpace::Luma,
+            3 => ColorSpace::RGB,
+            num_components => {
+                return Err(JpegError::UnsupportedComponents(num_components));
+            }
+        };
+        let width = usize::from(info.width) * usize::from(info.components);
+        let (Some(width), Some(height)) = (
+            core::num::NonZero::new(width),
+            core::num::NonZero::new(usize::from(info.height)),
+        ) else {
+            return Err(invalid_data("empty image"));
+        };
+        Ok(Self {
+            input,
+            dims: Dimensions2D::new(
+                RowLength::new(width),
+                RowCount::new(height),
+            ),
+            colorspace,
+        })
+    }
+
+    /// The dimensions of the buffer [`Self::decode`] expects.
+    #[inline]
+    pub const fn dims(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        self.dims
+    }
+
+    #[inline(never)]
+    pub fn decode(
+        &self,
+        output: &mut Array2DRefMut<'_, u16>,
+    ) -> Result<(), JpegError> {
+        if output.dims() != self.dims {
+            return Err(JpegError::OutputDimensionsMismatch);
+        }
+        let options =
+            DecoderOptions::default().jpeg_set_out_colorspace(self.colorspace);
+        let mut decoder =
+            JpegDecoder::new_with_options(ZCursor::new(self.input), options);
+        let samples = decoder.decode().map_err(invalid_data)?;
+        let width = self.dims.row_len().get();
+        if samples.len() != width * self.dims.row_count().get() {
+            return Err(invalid_data("unexpected sample count"));
+        }
+        for (row, src) in samples.chunks_exact(width).enumerate() {
+            let out = output.get_row_mut(RowIndex::new(row)).unwrap();
+            for (sample, src_sample) in out.iter_mut().zip(src) {
+                *sample = u16::from(*src_sample);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
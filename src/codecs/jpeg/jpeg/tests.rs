@@ -0,0 +1,201 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_std::coord_common::{RowLength, RowPitch};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+
+use super::{JpegDecompressor, JpegError};
+
+const BLOCK_SIZE: usize = 8;
+
+/// The DC difference categories 0 to 11, as 4-bit codes equal to the
+/// category.
+const DC_COUNTS: [u8; 16] = [0, 0, 0, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+const DC_CODE_LENGTH: u32 = 4;
+/// Only EOB, with the code `00`, is ever written.
+const AC_COUNTS: [u8; 16] = [0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+const AC_SYMBOLS: [u8; 2] = [0x00, 0xF0];
+
+fn be16(val: usize) -> [u8; 2] {
+    let val = u16::try_from(val).unwrap();
+    [
+        (val >> 8).try_into().unwrap(),
+        (val & 0xFF).try_into().unwrap(),
+    ]
+}
+
+fn segment(out: &mut Vec<u8>, marker: u8, payload: &[u8]) {
+    out.extend([0xFF, marker]);
+    out.extend(be16(payload.len() + 2));
+    out.extend(payload);
+}
+
+/// Writes a block whose only non-zero coefficient is DC.
+fn encode_block(bits: &mut BitStreamBuilder, diff: i32) {
+    let category = 32 - diff.unsigned_abs().leading_zeros();
+    bits.put(category, DC_CODE_LENGTH);
+    if category != 0 {
+        let value = if diff < 0 {
+            diff + (1 << category) - 1
+        } else {
+            diff
+        };
+        bits.put(value.try_into().unwrap(), category);
+    }
+    // EOB.
+    bits.put(0b00, 2);
+}
+
+/// Encodes a baseline JPEG whose 8x8 blocks are each flat,
+/// with the given luma values.
+///
+/// With the unit quantization table, a flat block of value `v` has
+/// a DC coefficient of `8 * (v - 128)`, so it decodes back losslessly.
+/// Chroma, if any, is neutral.
+fn encode(
+    blocks: &[Vec<u8>],
+    width: usize,
+    height: usize,
+    num_components: u8,
+) -> Vec<u8> {
+    let mut out = vec![0xFF, 0xD8];
+    let mut dqt = vec![0x00];
+    dqt.extend([1; 64]);
+    segment(&mut out, 0xDB, &dqt);
+    let mut sof = vec![8];
+    sof.extend(be16(height));
+    sof.extend(be16(width));
+    sof.push(num_components);
+    for id in 1..=num_components {
+        sof.extend([id, 0x11, 0]);
+    }
+    segment(&mut out, 0xC0, &sof);
+    let mut dht = vec![0x00];
+    dht.extend(DC_COUNTS);
+    dht.extend(0..12);
+    dht.push(0x10);
+    dht.extend(AC_COUNTS);
+    dht.extend(AC_SYMBOLS);
+    segment(&mut out, 0xC4, &dht);
+    let mut sos = vec![num_components];
+    for id in 1..=num_components {
+        sos.extend([id, 0x00]);
+    }
+    sos.extend([0, 63, 0]);
+    segment(&mut out, 0xDA, &sos);
+
+    let mut bits = BitStreamBuilder::new();
+    let mut pred = 0;
+    for block in blocks.iter().flatten() {
+        let dc = 8 * (i32::from(*block) - 128);
+        encode_block(&mut bits, dc - pred);
+        pred = dc;
+        for _ in 1..num_components {
+            encode_block(&mut bits, 0);
+        }
+    }
+    out.extend(bits.finish(BitOrder::JPEG));
+    out.extend([0xFF, 0xD9]);
+    out
+}
+
+fn blocks(width: usize, height: usize) -> Vec<Vec<u8>> {
+    (0..height.div_ceil(BLOCK_SIZE))
+        .map(|row| {
+            (0..width.div_ceil(BLOCK_SIZE))
+                .map(|col| {
+                    u8::try_from((row * 97 + col * 41 + 3) % 256).unwrap()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn expected(
+    blocks: &[Vec<u8>],
+    width: usize,
+    height: usize,
+    num_components: usize,
+) -> Vec<u16> {
+    (0..height)
+        .flat_map(|row| {
+            (0..width * num_components).map(move |col| {
+                let block_row = blocks.get(row / BLOCK_SIZE).unwrap();
+                let block =
+                    block_row.get(col / num_components / BLOCK_SIZE).unwrap();
+                u16::from(*block)
+            })
+        })
+        .collect()
+}
+
+fn decode(input: &[u8]) -> Result<Vec<u16>, JpegError> {
+    let decoder = JpegDecompressor::new(input)?;
+    let dims = decoder.dims();
+    let mut buf = vec![0; dims.row_len().get() * dims.row_count().get()];
+    let mut output = Array2DRefMut::new(
+        &mut buf,
+        dims.row_len(),
+        RowPitch::new(*dims.row_len()),
+    );
+    decoder.decode(&mut output)?;
+    Ok(buf)
+}
+
+#[test]
+fn grayscale_test() {
+    // The second image ends with partial blocks.
+    for (width, height) in [(16, 8), (20, 11)] {
+        let blocks = blocks(width, height);
+        let input = encode(&blocks, width, height, 1);
+        let decoder = JpegDecompressor::new(&input).unwrap();
+        assert_eq!(decoder.dims().row_len().get(), width);
+        assert_eq!(decoder.dims().row_count().get(), height);
+        assert_eq!(decode(&input), Ok(expected(&blocks, width, height, 1)));
+    }
+}
+
+#[test]
+fn color_test() {
+    let blocks = blocks(16, 16);
+    let input = encode(&blocks, 16, 16, 3);
+    let decoder = JpegDecompressor::new(&input).unwrap();
+    assert_eq!(decoder.dims().row_len().get(), 16 * 3);
+    assert_eq!(decode(&input), Ok(expected(&blocks, 16, 16, 3)));
+}
+
+#[test]
+fn output_dimensions_mismatch_test() {
+    let input = encode(&blocks(8, 8), 8, 8, 1);
+    let decoder = JpegDecompressor::new(&input).unwrap();
+    let mut buf = vec![0; 8 * 7];
+    let mut output = Array2DRefMut::new(
+        &mut buf,
+        RowLength::new(core::num::NonZero::new(8).unwrap()),
+        RowPitch::new(core::num::NonZero::new(8).unwrap()),
+    );
+    assert_eq!(
+        decoder.decode(&mut output),
+        Err(JpegError::OutputDimensionsMismatch)
+    );
+}
+
+#[test]
+fn unsupported_components_test() {
+    let input = encode(&blocks(8, 8), 8, 8, 2);
+    assert_eq!(
+        JpegDecompressor::new(&input).unwrap_err(),
+        JpegError::UnsupportedComponents(2)
+    );
+}
+
+#[test]
+fn invalid_data_test() {
+    assert!(matches!(
+        JpegDecompressor::new(&[0; 16]),
+        Err(JpegError::InvalidData(_))
+    ));
+    // The scan is missing.
+    let mut input = encode(&blocks(8, 8), 8, 8, 1);
+    input.truncate(input.len() / 2);
+    assert!(matches!(decode(&input), Err(JpegError::InvalidData(_))));
+}
@@ -0,0 +1,136 @@
+use jxl_oxide::JxlImage;
+use jxl_oxide::image::BitDepth;
+use rawspeed_std::coord_common::{Dimensions2D, RowCount, RowIndex, RowLength};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum JpegXlError {
+    /// The decoder rejected the stream, with its own description of why.
+    InvalidData(String),
+    UnsupportedChannels(usize),
+    UnsupportedBitDepth(u32),
+    OutputDimensionsMismatch,
+}
+
+impl core::fmt::Display for JpegXlError {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            JpegXlError::InvalidData(reason) => {
+                write!(f, "JpegXlError(InvalidData({reason}))")
+            }
+            JpegXlError::UnsupportedChannels(num_channels) => {
+                write!(f, "JpegXlError(UnsupportedChannels({num_channels}))")
+            }
+            JpegXlError::UnsupportedBitDepth(bits) => {
+                write!(f, "JpegXlError(UnsupportedBitDepth({bits}))")
+            }
+            JpegXlError::OutputDimensionsMismatch => {
+                write!(f, "JpegXlError(OutputDimensionsMismatch)")
+            }
+        }
+    }
+}
+
+fn invalid_data(err: impl core::fmt::Display) -> JpegXlError {
+    JpegXlError::InvalidData(err.to_string())
+}
+
+/// A JPEG XL codestream or container, as found in the tiles of DNG 1.7.
+///
+/// Only integer samples of up to 16 bits are supported. They are output at
+/// the bit depth the image header declares, with all channels interleaved,
+/// i.e. each output row holds `width * num_channels` samples.
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct JpegXlDecompressor {
+    image: JxlImage,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    bits: u32,
+}
+
+impl JpegXlDecompressor {
+    #[inline]
+    pub fn new(input: &[u8]) -> Result<Self, JpegXlError> {
+        let image = JxlImage::builder().read(input).map_err(invalid_data)?;
+        let bits = match image.image_header().metadata.bit_depth {
+            BitDepth::IntegerSample { bits_per_sample } => bits_per_sample,
+            BitDepth::FloatSample {
+                bits_per_sample, ..
+            } => return Err(JpegXlError::UnsupportedBitDepth(bits_per_sample)),
+        };
+        if !(1..=16).contains(&bits) {
+            return Err(JpegXlError::UnsupportedBitDepth(bits));
+        }
+        let num_channels = image.pixel_format().channels();
+        if ![1, 3].contains(&num_channels) {
+            return Err(JpegXlError::UnsupportedChannels(num_channels));
+        }
+        let width = usize::try_from(image.width()).unwrap() * num_channels;
+        let height = usize::try_from(image.height()).unwrap();
+        let (Some(width), Some(height)) = (
+            core::num::NonZero::new(width),
+            core::num::NonZero::new(height),
+        ) else {
+            return Err(invalid_data("empty image"));
+        };
+        Ok(Self {
+            image,
+            dims: Dimensions2D::new(
+                RowLength::new(width),
+                RowCount::new(height),
+            ),
+            bits,
+        })
+    }
+
+    /// The dimensions of the buffer [`Self::decode`] expects.
+    #[inline]
+    pub const fn dims(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        self.dims
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn bits_per_sample(&self) -> u32 {
+        self.bits
+    }
+
+    #[inline(never)]
+    pub fn decode(
+        &self,
+        output: &mut Array2DRefMut<'_, u16>,
+    ) -> Result<(), JpegXlError> {
+        if output.dims() != self.dims {
+            return Err(JpegXlError::OutputDimensionsMismatch);
+        }
+        let render = self.image.render_frame(0).map_err(invalid_data)?;
+        let width = self.dims.row_len().get();
+        let mut samples = vec![0.0_f32; width * self.dims.row_count().get()];
+        if render.stream().write_to_buffer(&mut samples) != samples.len() {
+            return Err(invalid_data("unexpected sample count"));
+        }
+        // The renderer normalizes the samples to `[0.0, 1.0]`.
+        let max = u16::MAX >> (16 - self.bits);
+        for (row, src) in samples.chunks_exact(width).enumerate() {
+            let out = output.get_row_mut(RowIndex::new(row)).unwrap();
+            for (sample, src_sample) in out.iter_mut().zip(src) {
+                #[expect(
+                    clippy::float_arithmetic,
+                    clippy::cast_possible_truncation,
+                    clippy::cast_sign_loss
+                )]
+                {
+                    *sample = (src_sample.clamp(0.0, 1.0) * f32::from(max))
+                        .round() as u16;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,109 @@
+use rawspeed_std::coord_common::{RowLength, RowPitch};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+use zune_core::bit_depth::BitDepth;
+use zune_core::colorspace::ColorSpace;
+use zune_core::options::EncoderOptions;
+use zune_jpegxl::JxlSimpleEncoder;
+
+use super::{JpegXlDecompressor, JpegXlError};
+
+/// Losslessly encodes the samples, which are 8-bit unless `bits` is 16.
+fn encode(
+    samples: &[u16],
+    width: usize,
+    height: usize,
+    colorspace: ColorSpace,
+    bits: u32,
+) -> Vec<u8> {
+    let (data, depth): (Vec<u8>, _) = if bits == 16 {
+        let data = samples.iter().flat_map(|s| s.to_ne_bytes()).collect();
+        (data, BitDepth::Sixteen)
+    } else {
+        let data = samples.iter().map(|s| u8::try_from(*s).unwrap()).collect();
+        (data, BitDepth::Eight)
+    };
+    let options = EncoderOptions::new(width, height, colorspace, depth);
+    let mut out = vec![];
+    JxlSimpleEncoder::new(&data, options)
+        .encode(&mut out)
+        .unwrap();
+    out
+}
+
+fn samples(len: usize, bits: u32) -> Vec<u16> {
+    (0..len)
+        .map(|i| u16::try_from((i * 7919 + 17) % (1 << bits)).unwrap())
+        .collect()
+}
+
+fn decode(input: &[u8]) -> Result<Vec<u16>, JpegXlError> {
+    let decoder = JpegXlDecompressor::new(input)?;
+    let dims = decoder.dims();
+    let mut buf = vec![0; dims.row_len().get() * dims.row_count().get()];
+    let mut output = Array2DRefMut::new(
+        &mut buf,
+        dims.row_len(),
+        RowPitch::new(*dims.row_len()),
+    );
+    decoder.decode(&mut output)?;
+    Ok(buf)
+}
+
+#[test]
+fn grayscale_test() {
+    for bits in [8, 16] {
+        let samples = samples(13 * 7, bits);
+        let input = encode(&samples, 13, 7, ColorSpace::Luma, bits);
+        let decoder = JpegXlDecompressor::new(&input).unwrap();
+        assert_eq!(decoder.bits_per_sample(), bits);
+        assert_eq!(decoder.dims().row_len().get(), 13);
+        assert_eq!(decoder.dims().row_count().get(), 7);
+        assert_eq!(decode(&input), Ok(samples));
+    }
+}
+
+#[test]
+fn color_test() {
+    let samples = samples(5 * 3 * 4, 16);
+    let input = encode(&samples, 5, 4, ColorSpace::RGB, 16);
+    let decoder = JpegXlDecompressor::new(&input).unwrap();
+    assert_eq!(decoder.dims().row_len().get(), 5 * 3);
+    assert_eq!(decode(&input), Ok(samples));
+}
+
+#[test]
+fn unsupported_channels_test() {
+    let samples = samples(4 * 2 * 4, 8);
+    let input = encode(&samples, 4, 4, ColorSpace::LumaA, 8);
+    assert_eq!(
+        JpegXlDecompressor::new(&input).unwrap_err(),
+        JpegXlError::UnsupportedChannels(2)
+    );
+}
+
+#[test]
+fn output_dimensions_mismatch_test() {
+    let input = encode(&samples(8 * 8, 8), 8, 8, ColorSpace::Luma, 8);
+    let decoder = JpegXlDecompressor::new(&input).unwrap();
+    let mut buf = vec![0; 8 * 7];
+    let mut output = Array2DRefMut::new(
+        &mut buf,
+        RowLength::new(core::num::NonZero::new(8).unwrap()),
+        RowPitch::new(core::num::NonZero::new(8).unwrap()),
+    );
+    assert_eq!(
+        decoder.decode(&mut output),
+        Err(JpegXlError::OutputDimensionsMismatch)
+    );
+}
+
+#[test]
+fn invalid_data_test() {
+    assert!(matches!(
+        JpegXlDecompressor::new(&[0; 16]),
+        Err(JpegXlError::InvalidData(_))
+    ));
+    let mut input = encode(&samples(64 * 64, 8), 64, 64, ColorSpace::Luma, 8);
+    input.truncate(input.len() / 2);
+    assert!(matches!(decode(&input), Err(JpegXlError::InvalidData(_))));
+}
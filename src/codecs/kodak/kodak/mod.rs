@@ -0,0 +1,150 @@
+use rawspeed_std::coord_common::{Dimensions2D, RowIndex};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+
+/// The number of pixels of a segment, whose differences are stored
+/// together (the last one of a row may be shorter).
+const SEGMENT_LEN: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum KodakError {
+    InvalidWidth,
+    InvalidSample,
+    TruncatedData,
+    OutputDimensionsMismatch,
+}
+
+impl core::fmt::Display for KodakError {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            KodakError::InvalidWidth => write!(f, "KodakError(InvalidWidth)"),
+            KodakError::InvalidSample => write!(f, "KodakError(InvalidSample)"),
+            KodakError::TruncatedData => write!(f, "KodakError(TruncatedData)"),
+            KodakError::OutputDimensionsMismatch => {
+                write!(f, "KodakError(OutputDimensionsMismatch)")
+            }
+        }
+    }
+}
+
+fn next_byte<'a, I>(bytes: &mut I) -> Result<u64, KodakError>
+where
+    I: Iterator<Item = &'a u8>,
+{
+    bytes
+        .next()
+        .map(|byte| u64::from(*byte))
+        .ok_or(KodakError::TruncatedData)
+}
+
+/// The differences of a segment: first the lengths of all of them, as
+/// nibbles, then their bits, least significant first, in 16-bit
+/// big-endian words.
+fn decode_segment<'a, I>(
+    bytes: &mut I,
+    len: usize,
+) -> Result<[i32; SEGMENT_LEN], KodakError>
+where
+    I: Iterator<Item = &'a u8>,
+{
+    let mut lens = [0_u32; SEGMENT_LEN];
+    for pair in lens.chunks_exact_mut(2).take(len / 2) {
+        let byte = u32::try_from(next_byte(bytes)?).unwrap();
+        *pair.first_mut().unwrap() = byte & 0xF;
+        *pair.last_mut().unwrap() = byte >> 4;
+    }
+    let mut cache = 0_u64;
+    let mut fill_level = 0;
+    // Keeps the words that follow the lengths 32-bit aligned.
+    if len % 8 == 4 {
+        cache = (next_byte(bytes)? << 8) | next_byte(bytes)?;
+        fill_level = 16;
+    }
+    let mut diffs = [0; SEGMENT_LEN];
+    for (diff, diff_len) in diffs.iter_mut().zip(lens).take(len) {
+        if fill_level < diff_len {
+            for shift in [8, 0, 24, 16] {
+                cache |= next_byte(bytes)? << (fill_level + shift);
+            }
+            fill_level += 32;
+        }
+        let bits = i32::try_from(cache & ((1 << diff_len) - 1)).unwrap();
+        cache >>= diff_len;
+        fill_level -= diff_len;
+        *diff = if diff_len != 0 && bits >> (diff_len - 1) == 0 {
+            bits - (1 << diff_len) + 1
+        } else {
+            bits
+        };
+    }
+    Ok(diffs)
+}
+
+/// Kodak's 65000 compression, of DCR files.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+#[must_use]
+pub struct KodakDecompressor<'a> {
+    input: &'a [u8],
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    curve: Option<Vec<u16>>,
+}
+
+impl<'a> KodakDecompressor<'a> {
+    /// The decoded samples are looked up in the `curve`, if any
+    /// (clamping to its last entry).
+    #[inline]
+    pub fn new(
+        input: &'a [u8],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        curve: Option<Vec<u16>>,
+    ) -> Result<Self, KodakError> {
+        if !dims.row_len().get().is_multiple_of(4) {
+            return Err(KodakError::InvalidWidth);
+        }
+        Ok(Self {
+            input,
+            dims,
+            curve: curve.filter(|curve| !curve.is_empty()),
+        })
+    }
+
+    /// Each row is split into segments, whose samples are predicted from
+    /// the previous sample of the same color within the segment.
+    #[inline(never)]
+    pub fn decode(
+        &self,
+        output: &mut Array2DRefMut<'_, u16>,
+    ) -> Result<(), KodakError> {
+        if output.dims() != self.dims {
+            return Err(KodakError::OutputDimensionsMismatch);
+        }
+        let mut bytes = self.input.iter();
+        for row in 0..self.dims.row_count().get() {
+            let out = output.get_row_mut(RowIndex::new(row)).unwrap();
+            for segment in out.chunks_mut(SEGMENT_LEN) {
+                let diffs = decode_segment(&mut bytes, segment.len())?;
+                let mut preds = [0; 2];
+                for (index, (sample, diff)) in
+                    segment.iter_mut().zip(diffs).enumerate()
+                {
+                    let pred = preds.get_mut(index % 2).unwrap();
+                    *pred += diff;
+                    let value = u16::try_from(*pred)
+                        .map_err(|_err| KodakError::InvalidSample)?;
+                    *sample = match &self.curve {
+                        Some(curve) => *curve
+                            .get(usize::from(value))
+                            .unwrap_or_else(|| curve.last().unwrap()),
+                        None => value,
+                    };
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
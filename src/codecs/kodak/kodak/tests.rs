@@ -0,0 +1,184 @@
+use rawspeed_std::coord_common::{
+    Dimensions2D, RowCount, RowIndex, RowLength, RowPitch,
+};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+
+use super::{KodakDecompressor, KodakError, SEGMENT_LEN};
+
+fn diff_len(diff: i32) -> u32 {
+    32 - diff.unsigned_abs().leading_zeros()
+}
+
+/// Encodes a segment the way the decoder reads it: the 16-bit words are
+/// only fetched (two at a time) once the bits left run out.
+fn encode_segment(diffs: &[i32]) -> Vec<u8> {
+    let lens: Vec<u32> = diffs.iter().map(|diff| diff_len(*diff)).collect();
+    let mut out: Vec<u8> = lens
+        .chunks_exact(2)
+        .map(|pair| {
+            let [low, high] = [pair.first(), pair.last()]
+                .map(|len| u8::try_from(*len.unwrap()).unwrap());
+            low | (high << 4)
+        })
+        .collect();
+
+    let mut fill_level = 0;
+    let mut num_words = 0;
+    if diffs.len() % 8 == 4 {
+        fill_level = 16;
+        num_words = 1;
+    }
+    let mut words = vec![];
+    let mut cache = 0_u64;
+    let mut cache_len = 0;
+    for (diff, len) in diffs.iter().zip(&lens) {
+        if fill_level < *len {
+            fill_level += 32;
+            num_words += 2;
+        }
+        fill_level -= len;
+        let value = if *diff < 0 {
+            *diff + (1 << len) - 1
+        } else {
+            *diff
+        };
+        cache |= u64::from(u32::try_from(value).unwrap()) << cache_len;
+        cache_len += len;
+        while cache_len >= 16 {
+            words.push(u16::try_from(cache & 0xFFFF).unwrap());
+            cache >>= 16;
+            cache_len -= 16;
+        }
+    }
+    words.push(u16::try_from(cache).unwrap());
+    words.resize(num_words, 0);
+    for word in words {
+        out.extend(word.to_le_bytes().iter().rev());
+    }
+    out
+}
+
+fn encode(rows: &[Vec<u16>]) -> Vec<u8> {
+    let mut out = vec![];
+    for row in rows {
+        for segment in row.chunks(SEGMENT_LEN) {
+            let mut preds = [0; 2];
+            let diffs: Vec<i32> = segment
+                .iter()
+                .enumerate()
+                .map(|(index, sample)| {
+                    let pred = preds.get_mut(index % 2).unwrap();
+                    let diff = i32::from(*sample) - *pred;
+                    *pred = i32::from(*sample);
+                    diff
+                })
+                .collect();
+            out.extend(encode_segment(&diffs));
+        }
+    }
+    out
+}
+
+fn dims(
+    width: usize,
+    height: usize,
+) -> Dimensions2D<core::num::NonZero<usize>> {
+    Dimensions2D::new(
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowCount::new(core::num::NonZero::new(height).unwrap()),
+    )
+}
+
+/// Samples whose differences fit in the 15 bits that can be stored.
+fn test_image(width: usize, height: usize) -> Vec<Vec<u16>> {
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| {
+                    u16::try_from((row * 7919 + col * 104_729) % 0x7FFF)
+                        .unwrap()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn decode(
+    decompressor: &KodakDecompressor<'_>,
+    width: usize,
+    height: usize,
+) -> Result<Vec<Vec<u16>>, KodakError> {
+    let mut buf = vec![0; width * height];
+    let mut output = Array2DRefMut::new(
+        buf.as_mut_slice(),
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowPitch::new(core::num::NonZero::new(width).unwrap()),
+    );
+    decompressor.decode(&mut output)?;
+    Ok((0..height)
+        .map(|row| output.get_row(RowIndex::new(row)).unwrap().to_vec())
+        .collect())
+}
+
+#[test]
+fn round_trip_test() {
+    // Rows of a whole and a partial segment, whose lengths take both
+    // 32-bit aligned and unaligned byte counts.
+    for width in [4, 8, 12, 260, 264] {
+        let rows = test_image(width, 3);
+        let input = encode(&rows);
+        let decompressor =
+            KodakDecompressor::new(&input, dims(width, 3), None).unwrap();
+        assert_eq!(decode(&decompressor, width, 3), Ok(rows));
+    }
+}
+
+#[test]
+fn curve_test() {
+    let rows = vec![vec![0, 1, 2, 3, 4, 5, 6, 7]];
+    let input = encode(&rows);
+    let curve = vec![10, 20, 30, 40];
+    let decompressor =
+        KodakDecompressor::new(&input, dims(8, 1), Some(curve)).unwrap();
+    assert_eq!(
+        decode(&decompressor, 8, 1),
+        Ok(vec![vec![10, 20, 30, 40, 40, 40, 40, 40]])
+    );
+}
+
+#[test]
+fn invalid_width_test() {
+    assert_eq!(
+        KodakDecompressor::new(&[], dims(6, 1), None).unwrap_err(),
+        KodakError::InvalidWidth
+    );
+}
+
+#[test]
+fn invalid_sample_test() {
+    // A negative sample.
+    let input = encode_segment(&[-1, 0, 0, 0]);
+    let decompressor =
+        KodakDecompressor::new(&input, dims(4, 1), None).unwrap();
+    assert_eq!(decode(&decompressor, 4, 1), Err(KodakError::InvalidSample));
+}
+
+#[test]
+fn truncated_data_test() {
+    let input = encode(&test_image(8, 4));
+    let truncated = input.get(..input.len() - 1).unwrap();
+    let decompressor =
+        KodakDecompressor::new(truncated, dims(8, 4), None).unwrap();
+    assert_eq!(decode(&decompressor, 8, 4), Err(KodakError::TruncatedData));
+}
+
+#[test]
+fn output_dimensions_mismatch_test() {
+    let input = encode(&test_image(8, 2));
+    let decompressor =
+        KodakDecompressor::new(&input, dims(8, 2), None).unwrap();
+    assert_eq!(
+        decode(&decompressor, 4, 4),
+        Err(KodakError::OutputDimensionsMismatch)
+    );
+}
@@ -0,0 +1,670 @@
+use rawspeed_bitstream_bitstream_decoder::bitstreamer::BitStreamerBase;
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrderJPEG;
+use rawspeed_bitstream_bytestream_decoder::bytestreamer::ByteStreamer;
+use rawspeed_codecs_huffman::huffman::{
+    HuffmanCode, HuffmanError, HuffmanTable, MAX_DIFF_LENGTH,
+};
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_std::coord_common::{
+    ColIndex, Coord2D, Dimensions2D, RowCount, RowIndex, RowLength,
+};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+
+const MARKER_SOF0: u8 = 0xC0;
+const MARKER_SOF3: u8 = 0xC3;
+const MARKER_DHT: u8 = 0xC4;
+const MARKER_SOF15: u8 = 0xCF;
+const MARKER_RST0: u8 = 0xD0;
+const MARKER_SOI: u8 = 0xD8;
+const MARKER_EOI: u8 = 0xD9;
+const MARKER_SOS: u8 = 0xDA;
+const MARKER_DRI: u8 = 0xDD;
+
+const NUM_HUFFMAN_TABLES: usize = 4;
+const NUM_RESTART_MARKERS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum LJpegError {
+    UnexpectedEndOfInput,
+    MissingSOI,
+    UnexpectedMarker(u8),
+    UnsupportedFrameType(u8),
+    InvalidSegmentLength(u8),
+    InvalidFrameHeader,
+    UnsupportedSampling,
+    InvalidHuffmanTable,
+    MissingHuffmanTable(u8),
+    InvalidScanHeader,
+    InvalidPredictor(u8),
+    InvalidRestartMarker(u8),
+    InvalidHuffmanCode,
+    TruncatedData,
+    OutputDimensionsMismatch,
+}
+
+impl core::fmt::Display for LJpegError {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            LJpegError::UnexpectedEndOfInput => {
+                write!(f, "LJpegError(UnexpectedEndOfInput)")
+            }
+            LJpegError::MissingSOI => write!(f, "LJpegError(MissingSOI)"),
+            LJpegError::UnexpectedMarker(marker) => {
+                write!(f, "LJpegError(UnexpectedMarker(0x{marker:02x}))")
+            }
+            LJpegError::UnsupportedFrameType(marker) => {
+                write!(f, "LJpegError(UnsupportedFrameType(0x{marker:02x}))")
+            }
+            LJpegError::InvalidSegmentLength(marker) => {
+                write!(f, "LJpegError(InvalidSegmentLength(0x{marker:02x}))")
+            }
+            LJpegError::InvalidFrameHeader => {
+                write!(f, "LJpegError(InvalidFrameHeader)")
+            }
+            LJpegError::UnsupportedSampling => {
+                write!(f, "LJpegError(UnsupportedSampling)")
+            }
+            LJpegError::InvalidHuffmanTable => {
+                write!(f, "LJpegError(InvalidHuffmanTable)")
+            }
+            LJpegError::MissingHuffmanTable(index) => {
+                write!(f, "LJpegError(MissingHuffmanTable({index}))")
+            }
+            LJpegError::InvalidScanHeader => {
+                write!(f, "LJpegError(InvalidScanHeader)")
+            }
+            LJpegError::InvalidPredictor(predictor) => {
+                write!(f, "LJpegError(InvalidPredictor({predictor}))")
+            }
+            LJpegError::InvalidRestartMarker(index) => {
+                write!(f, "LJpegError(InvalidRestartMarker({index}))")
+            }
+            LJpegError::InvalidHuffmanCode => {
+                write!(f, "LJpegError(InvalidHuffmanCode)")
+            }
+            LJpegError::TruncatedData => write!(f, "LJpegError(TruncatedData)"),
+            LJpegError::OutputDimensionsMismatch => {
+                write!(f, "LJpegError(OutputDimensionsMismatch)")
+            }
+        }
+    }
+}
+
+impl From<HuffmanError> for LJpegError {
+    #[inline]
+    fn from(err: HuffmanError) -> Self {
+        if err == HuffmanError::EndOfStream {
+            LJpegError::TruncatedData
+        } else {
+            LJpegError::InvalidHuffmanCode
+        }
+    }
+}
+
+/// Big-endian reader over the marker segments.
+#[derive(Debug, Clone, Copy)]
+struct ByteReader<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    fn get_bytes(&mut self, len: usize) -> Result<&'a [u8], LJpegError> {
+        let (bytes, rest) = self
+            .input
+            .split_at_checked(len)
+            .ok_or(LJpegError::UnexpectedEndOfInput)?;
+        self.input = rest;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, LJpegError> {
+        Ok(*self.get_bytes(1)?.first().unwrap())
+    }
+
+    fn read_u16(&mut self) -> Result<u16, LJpegError> {
+        let bytes = self.get_bytes(size_of::<u16>())?;
+        Ok(ByteStreamer::new(bytes, Endianness::Big).read())
+    }
+
+    /// Reads a marker, skipping any preceding fill bytes.
+    fn read_marker(&mut self) -> Result<u8, LJpegError> {
+        if self.read_u8()? != 0xFF {
+            return Err(LJpegError::UnexpectedMarker(0));
+        }
+        loop {
+            match self.read_u8()? {
+                0xFF => {}
+                marker => return Ok(marker),
+            }
+        }
+    }
+
+    /// Reads the payload of the segment introduced by `marker`.
+    fn read_segment(&mut self, marker: u8) -> Result<Self, LJpegError> {
+        let len = usize::from(self.read_u16()?);
+        let len = len
+            .checked_sub(size_of::<u16>())
+            .ok_or(LJpegError::InvalidSegmentLength(marker))?;
+        Ok(Self {
+            input: self.get_bytes(len)?,
+        })
+    }
+
+    const fn is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+}
+
+/// Entropy-coded data stops at the first marker,
+/// i.e. `0xFF` that is not followed by a stuffed zero byte.
+fn split_entropy_coded_segment(input: &[u8]) -> (&[u8], &[u8]) {
+    let mut pos = 0;
+    while let Some(index) = input
+        .get(pos..)
+        .and_then(|rest| rest.iter().position(|byte| *byte == 0xFF))
+    {
+        let marker_pos = pos + index;
+        if input.get(marker_pos + 1) != Some(&0x00) {
+            return input.split_at(marker_pos);
+        }
+        pos = marker_pos + 2;
+    }
+    (input, &[])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub struct Component {
+    pub id: u8,
+    pub horizontal_sampling: u8,
+    pub vertical_sampling: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Frame {
+    pub precision: u8,
+    pub dims: Dimensions2D<core::num::NonZero<usize>>,
+    pub components: Vec<Component>,
+}
+
+impl Frame {
+    fn parse(mut segment: ByteReader<'_>) -> Result<Self, LJpegError> {
+        let precision = segment.read_u8()?;
+        let height = usize::from(segment.read_u16()?);
+        let width = usize::from(segment.read_u16()?);
+        let num_components = segment.read_u8()?;
+        if !(2..=16).contains(&precision) || num_components == 0 {
+            return Err(LJpegError::InvalidFrameHeader);
+        }
+        let mut components = Vec::with_capacity(num_components.into());
+        for _ in 0..num_components {
+            let id = segment.read_u8()?;
+            let sampling = segment.read_u8()?;
+            let _quantization_table = segment.read_u8()?;
+            components.push(Component {
+                id,
+                horizontal_sampling: sampling >> 4,
+                vertical_sampling: sampling & 0xF,
+            });
+        }
+        if !segment.is_empty() {
+            return Err(LJpegError::InvalidSegmentLength(MARKER_SOF3));
+        }
+        if components.iter().any(|component| {
+            !(1..=4).contains(&component.horizontal_sampling)
+                || !(1..=4).contains(&component.vertical_sampling)
+        }) {
+            return Err(LJpegError::InvalidFrameHeader);
+        }
+        // A zero height would need a DNL marker, which is not supported.
+        let (Some(width), Some(height)) = (
+            core::num::NonZero::new(width),
+            core::num::NonZero::new(height),
+        ) else {
+            return Err(LJpegError::InvalidFrameHeader);
+        };
+        Ok(Self {
+            precision,
+            dims: Dimensions2D::new(
+                RowLength::new(width),
+                RowCount::new(height),
+            ),
+            components,
+        })
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn is_subsampled(&self) -> bool {
+        self.components.iter().any(|component| {
+            component.horizontal_sampling != 1
+                || component.vertical_sampling != 1
+        })
+    }
+
+    /// The number of MCUs per row, and the number of MCU rows.
+    #[inline]
+    pub fn mcu_dims(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        let max_sampling = |sampling: fn(&Component) -> u8| {
+            usize::from(self.components.iter().map(sampling).max().unwrap())
+        };
+        let mcus_per_row = self
+            .dims
+            .row_len()
+            .get()
+            .div_ceil(max_sampling(|c| c.horizontal_sampling));
+        let mcu_rows = self
+            .dims
+            .row_count()
+            .get()
+            .div_ceil(max_sampling(|c| c.vertical_sampling));
+        Dimensions2D::new(
+            RowLength::new(core::num::NonZero::new(mcus_per_row).unwrap()),
+            RowCount::new(core::num::NonZero::new(mcu_rows).unwrap()),
+        )
+    }
+
+    /// The number of samples of all components in a single MCU.
+    #[inline]
+    #[must_use]
+    pub fn samples_per_mcu(&self) -> usize {
+        self.components
+            .iter()
+            .map(|component| {
+                usize::from(component.horizontal_sampling)
+                    * usize::from(component.vertical_sampling)
+            })
+            .sum()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Scan {
+    tables: Vec<HuffmanTable>,
+    predictor: u8,
+    point_transform: u8,
+}
+
+fn parse_huffman_tables(
+    mut segment: ByteReader<'_>,
+    tables: &mut [Option<HuffmanTable>; NUM_HUFFMAN_TABLES],
+) -> Result<(), LJpegError> {
+    while !segment.is_empty() {
+        let class_and_index = segment.read_u8()?;
+        // Lossless JPEG only uses DC tables.
+        let table = tables
+            .get_mut(usize::from(class_and_index))
+            .ok_or(LJpegError::InvalidHuffmanTable)?;
+        let counts: [u8; 16] = segment.get_bytes(16)?.try_into().unwrap();
+        let num_symbols = counts.iter().map(|count| usize::from(*count)).sum();
+        let symbols = segment.get_bytes(num_symbols)?;
+        if symbols.iter().any(|symbol| *symbol > MAX_DIFF_LENGTH) {
+            return Err(LJpegError::InvalidHuffmanTable);
+        }
+        let code = HuffmanCode::new(&counts, symbols)
+            .map_err(|_err| LJpegError::InvalidHuffmanTable)?;
+        *table = Some(
+            HuffmanTable::new(&code, HuffmanTable::DEFAULT_LUT_BITS)
+                .map_err(|_err| LJpegError::InvalidHuffmanTable)?,
+        );
+    }
+    Ok(())
+}
+
+fn parse_restart_interval(
+    mut segment: ByteReader<'_>,
+) -> Result<usize, LJpegError> {
+    let restart_interval = segment.read_u16()?;
+    if !segment.is_empty() {
+        return Err(LJpegError::InvalidSegmentLength(MARKER_DRI));
+    }
+    Ok(restart_interval.into())
+}
+
+impl Scan {
+    fn parse(
+        mut segment: ByteReader<'_>,
+        frame: &Frame,
+        tables: &[Option<HuffmanTable>; NUM_HUFFMAN_TABLES],
+    ) -> Result<Self, LJpegError> {
+        let num_components = segment.read_u8()?;
+        if usize::from(num_components) != frame.components.len() {
+            return Err(LJpegError::InvalidScanHeader);
+        }
+        let mut scan_tables = Vec::with_capacity(frame.components.len());
+        for component in &frame.components {
+            let id = segment.read_u8()?;
+            let table_index = segment.read_u8()? >> 4;
+            if id != component.id {
+                return Err(LJpegError::InvalidScanHeader);
+            }
+            let table = tables
+                .get(usize::from(table_index))
+                .and_then(Option::as_ref)
+                .ok_or(LJpegError::MissingHuffmanTable(table_index))?;
+            scan_tables.push(table.clone());
+        }
+        let predictor = segment.read_u8()?;
+        let _spectral_end = segment.read_u8()?;
+        let point_transform = segment.read_u8()? & 0xF;
+        if !segment.is_empty() {
+            return Err(LJpegError::InvalidSegmentLength(MARKER_SOS));
+        }
+        if !(1..=7).contains(&predictor) {
+            return Err(LJpegError::InvalidPredictor(predictor));
+        }
+        if point_transform >= frame.precision {
+            return Err(LJpegError::InvalidScanHeader);
+        }
+        Ok(Self {
+            tables: scan_tables,
+            predictor,
+            point_transform,
+        })
+    }
+}
+
+/// The reconstructed samples surrounding the one being predicted,
+/// named as in ITU T.81, H.1.2.1.
+#[derive(Debug, Clone, Copy)]
+struct Neighbours {
+    left: i32,
+    above: i32,
+    above_left: i32,
+}
+
+impl Neighbours {
+    const fn predict(self, predictor: u8) -> i32 {
+        let Self {
+            left: ra,
+            above: rb,
+            above_left: rc,
+        } = self;
+        match predictor {
+            1 => ra,
+            2 => rb,
+            3 => rc,
+            4 => ra + rb - rc,
+            5 => ra + ((rb - rc) >> 1),
+            6 => rb + ((ra - rc) >> 1),
+            _ => (ra + rb) >> 1,
+        }
+    }
+}
+
+/// Lossless JPEG (ITU T.81 process 14) decoder.
+///
+/// All components are stored interleaved, i.e. each output row
+/// holds `width * num_components` samples.
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct LJpegDecoder<'a> {
+    frame: Frame,
+    scan: Scan,
+    restart_interval: usize,
+    data: &'a [u8],
+}
+
+impl<'a> LJpegDecoder<'a> {
+    #[inline]
+    pub fn new(input: &'a [u8]) -> Result<Self, LJpegError> {
+        let mut reader = ByteReader { input };
+        if reader.read_marker() != Ok(MARKER_SOI) {
+            return Err(LJpegError::MissingSOI);
+        }
+        let mut frame = None;
+        let mut tables = [const { None }; NUM_HUFFMAN_TABLES];
+        let mut restart_interval = 0;
+        loop {
+            let marker = reader.read_marker()?;
+            match marker {
+                MARKER_SOF3 => {
+                    frame = Some(Frame::parse(reader.read_segment(marker)?)?);
+                }
+                MARKER_SOF0..=MARKER_SOF15
+                    if ![MARKER_DHT, 0xC8, 0xCC].contains(&marker) =>
+                {
+                    return Err(LJpegError::UnsupportedFrameType(marker));
+                }
+                MARKER_DHT => {
+                    parse_huffman_tables(
+                        reader.read_segment(marker)?,
+                        &mut tables,
+                    )?;
+                }
+                MARKER_DRI => {
+                    restart_interval =
+                        parse_restart_interval(reader.read_segment(marker)?)?;
+                }
+                MARKER_SOS => {
+                    let frame =
+                        frame.ok_or(LJpegError::UnexpectedMarker(marker))?;
+                    let scan = Scan::parse(
+                        reader.read_segment(marker)?,
+                        &frame,
+                        &tables,
+                    )?;
+                    return Ok(Self {
+                        frame,
+                        scan,
+                        restart_interval,
+                        data: reader.input,
+                    });
+                }
+                MARKER_SOI | MARKER_EOI | MARKER_RST0..=0xD7 => {
+                    return Err(LJpegError::UnexpectedMarker(marker));
+                }
+                // APPn, COM, DQT and friends carry nothing of interest.
+                _ => {
+                    let _ = reader.read_segment(marker)?;
+                }
+            }
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn predictor(&self) -> u8 {
+        self.scan.predictor
+    }
+
+    /// The dimensions of the buffer [`Self::decode`] expects.
+    #[inline]
+    pub fn output_dims(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        let width = self
+            .frame
+            .dims
+            .row_len()
+            .checked_mul(
+                core::num::NonZero::new(self.frame.components.len()).unwrap(),
+            )
+            .unwrap();
+        Dimensions2D::new(RowLength::new(width), self.frame.dims.row_count())
+    }
+
+    fn get_sample(
+        &self,
+        output: &Array2DRefMut<'_, u16>,
+        row: usize,
+        col: usize,
+        component: usize,
+    ) -> i32 {
+        let num_components = self.frame.components.len();
+        let sample = output[Coord2D::new(
+            RowIndex::new(row),
+            ColIndex::new(col * num_components + component),
+        )];
+        i32::from(sample >> self.scan.point_transform)
+    }
+
+    fn predict(
+        &self,
+        output: &Array2DRefMut<'_, u16>,
+        mcu: usize,
+        interval_start: usize,
+        component: usize,
+    ) -> i32 {
+        let width = self.frame.dims.row_len().get();
+        let (row, col) = (mcu / width, mcu % width);
+        // The first sample of each restart interval uses the default
+        // prediction, the rest of its first line is predicted horizontally,
+        // and the first sample of every other line vertically.
+        if mcu == interval_start {
+            return 1 << (self.frame.precision - self.scan.point_transform - 1);
+        }
+        if row == interval_start / width {
+            return self.get_sample(output, row, col - 1, component);
+        }
+        if col == 0 {
+            return self.get_sample(output, row - 1, col, component);
+        }
+        Neighbours {
+            left: self.get_sample(output, row, col - 1, component),
+            above: self.get_sample(output, row - 1, col, component),
+            above_left: self.get_sample(output, row - 1, col - 1, component),
+        }
+        .predict(self.scan.predictor)
+    }
+
+    fn decode_interval(
+        &self,
+        data: &[u8],
+        mcus: core::ops::Range<usize>,
+        output: &mut Array2DRefMut<'_, u16>,
+    ) -> Result<(), LJpegError> {
+        let mut bs = BitStreamerBase::<BitOrderJPEG>::try_from(data)
+            .map_err(|_err| LJpegError::TruncatedData)?;
+        let width = self.frame.dims.row_len().get();
+        let num_components = self.frame.components.len();
+        for mcu in mcus.clone() {
+            for (component, table) in self.scan.tables.iter().enumerate() {
+                let diff = table.decode_difference(&mut bs)?;
+                let prediction =
+                    self.predict(output, mcu, mcus.start, component);
+                // Reconstruction is performed modulo 2^16.
+                #[expect(
+                    clippy::cast_possible_truncation,
+                    clippy::cast_sign_loss
+                )]
+                let sample = (prediction + diff) as u16;
+                output[Coord2D::new(
+                    RowIndex::new(mcu / width),
+                    ColIndex::new((mcu % width) * num_components + component),
+                )] = sample << self.scan.point_transform;
+            }
+        }
+        Ok(())
+    }
+
+    fn skip_restart_marker(
+        data: &[u8],
+        index: usize,
+    ) -> Result<&[u8], LJpegError> {
+        let mut reader = ByteReader { input: data };
+        let index = u8::try_from(index % NUM_RESTART_MARKERS).unwrap();
+        if reader.read_marker() != Ok(MARKER_RST0 + index) {
+            return Err(LJpegError::InvalidRestartMarker(index));
+        }
+        Ok(reader.input)
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn point_transform(&self) -> u8 {
+        self.scan.point_transform
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn restart_interval(&self) -> usize {
+        self.restart_interval
+    }
+
+    /// Runs `f` on the entropy-coded segment of each restart interval,
+    /// along with the range of MCUs it holds.
+    fn for_each_interval<F>(&self, mut f: F) -> Result<(), LJpegError>
+    where
+        F: FnMut(&[u8], core::ops::Range<usize>) -> Result<(), LJpegError>,
+    {
+        let mcu_dims = self.frame.mcu_dims();
+        let num_mcus = mcu_dims.row_len().get() * mcu_dims.row_count().get();
+        let interval_len = match self.restart_interval {
+            0 => num_mcus,
+            len => len,
+        };
+        let mut data = self.data;
+        for (index, interval_start) in
+            (0..num_mcus).step_by(interval_len).enumerate()
+        {
+            if index != 0 {
+                data = Self::skip_restart_marker(data, index - 1)?;
+            }
+            let (segment, rest) = split_entropy_coded_segment(data);
+            let interval_end = (interval_start + interval_len).min(num_mcus);
+            f(segment, interval_start..interval_end)?;
+            data = rest;
+        }
+        Ok(())
+    }
+
+    #[inline(never)]
+    pub fn decode(
+        &self,
+        output: &mut Array2DRefMut<'_, u16>,
+    ) -> Result<(), LJpegError> {
+        if self.frame.is_subsampled() {
+            return Err(LJpegError::UnsupportedSampling);
+        }
+        if output.dims() != self.output_dims() {
+            return Err(LJpegError::OutputDimensionsMismatch);
+        }
+        self.for_each_interval(|segment, mcus| {
+            self.decode_interval(segment, mcus, output)
+        })
+    }
+
+    /// Decodes the differences of each MCU, without reconstructing the
+    /// samples, for formats whose prediction deviates from the standard.
+    ///
+    /// `f` is passed the [`Frame::samples_per_mcu`] differences of every
+    /// MCU in turn, those of each component in scan order.
+    #[inline(never)]
+    pub fn decode_differences<F>(&self, mut f: F) -> Result<(), LJpegError>
+    where
+        F: FnMut(&[i32]),
+    {
+        let mut diffs = vec![0; self.frame.samples_per_mcu()];
+        self.for_each_interval(|segment, mcus| {
+            let mut bs = BitStreamerBase::<BitOrderJPEG>::try_from(segment)
+                .map_err(|_err| LJpegError::TruncatedData)?;
+            for _ in mcus {
+                let mut diff = diffs.iter_mut();
+                for (component, table) in
+                    self.frame.components.iter().zip(&self.scan.tables)
+                {
+                    let num_samples =
+                        usize::from(component.horizontal_sampling)
+                            * usize::from(component.vertical_sampling);
+                    for sample in diff.by_ref().take(num_samples) {
+                        *sample = table.decode_difference(&mut bs)?;
+                    }
+                }
+                f(&diffs);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,140 @@
+use super::{Encoder, decode, find_marker, patch, samples};
+use crate::ljpeg::LJpegDecoder;
+
+#[test]
+fn all_predictors_test() {
+    for predictor in 1..=7 {
+        for num_components in 1..=4 {
+            let mut encoder = Encoder::new(7, 5, num_components);
+            encoder.predictor = predictor;
+            let input = samples(7 * 5 * num_components, encoder.precision);
+            assert_eq!(decode(&encoder.encode(&input)), Ok(input));
+        }
+    }
+}
+
+#[test]
+fn all_precisions_test() {
+    for precision in 2..=16 {
+        let mut encoder = Encoder::new(6, 3, 2);
+        encoder.precision = precision;
+        encoder.predictor = 4;
+        let input = samples(6 * 3 * 2, precision);
+        assert_eq!(decode(&encoder.encode(&input)), Ok(input));
+    }
+}
+
+#[test]
+fn largest_difference_test() {
+    let mut encoder = Encoder::new(4, 2, 1);
+    encoder.precision = 16;
+    let input = vec![0, 0x8000, 0, 0xFFFF, 0x8000, 0, 0x7FFF, 0xFFFF];
+    assert_eq!(decode(&encoder.encode(&input)), Ok(input));
+}
+
+#[test]
+fn point_transform_test() {
+    let mut encoder = Encoder::new(5, 4, 2);
+    encoder.predictor = 6;
+    encoder.point_transform = 3;
+    let input: Vec<u16> = samples(5 * 4 * 2, encoder.precision)
+        .into_iter()
+        .map(|sample| sample & !0x7)
+        .collect();
+    assert_eq!(decode(&encoder.encode(&input)), Ok(input));
+}
+
+#[test]
+fn row_aligned_restart_interval_test() {
+    for predictor in 1..=7 {
+        let mut encoder = Encoder::new(4, 20, 3);
+        encoder.predictor = predictor;
+        encoder.restart_interval = 4;
+        let input = samples(4 * 20 * 3, encoder.precision);
+        assert_eq!(decode(&encoder.encode(&input)), Ok(input));
+    }
+}
+
+#[test]
+fn unaligned_restart_interval_test() {
+    for restart_interval in [1, 3, 5, 11, 100] {
+        let mut encoder = Encoder::new(7, 6, 2);
+        encoder.predictor = 7;
+        encoder.restart_interval = restart_interval;
+        let input = samples(7 * 6 * 2, encoder.precision);
+        assert_eq!(decode(&encoder.encode(&input)), Ok(input));
+    }
+}
+
+#[test]
+fn unknown_segments_are_skipped_test() {
+    let mut encoder = Encoder::new(3, 3, 1);
+    encoder.with_app_segment = true;
+    let input = samples(3 * 3, encoder.precision);
+    assert_eq!(decode(&encoder.encode(&input)), Ok(input));
+}
+
+#[test]
+fn fill_bytes_before_marker_test() {
+    let encoder = Encoder::new(3, 3, 1);
+    let input = samples(3 * 3, encoder.precision);
+    let mut data = encoder.encode(&input);
+    data.splice(2..2, [0xFF, 0xFF]);
+    assert_eq!(decode(&data), Ok(input));
+}
+
+#[test]
+fn frame_test() {
+    let mut encoder = Encoder::new(3, 2, 2);
+    encoder.predictor = 5;
+    let data = encoder.encode(&samples(3 * 2 * 2, encoder.precision));
+    let decoder = LJpegDecoder::new(&data).unwrap();
+    assert_eq!(decoder.frame().precision, 12);
+    assert_eq!(decoder.frame().dims.row_len().get(), 3);
+    assert_eq!(decoder.frame().dims.row_count().get(), 2);
+    assert_eq!(decoder.frame().components.len(), 2);
+    assert_eq!(decoder.predictor(), 5);
+    assert_eq!(decoder.output_dims().row_len().get(), 6);
+}
+
+fn differences(input: &[u8]) -> Vec<Vec<i32>> {
+    let decoder = LJpegDecoder::new(input).unwrap();
+    let mut mcus = vec![];
+    decoder
+        .decode_differences(|diffs| mcus.push(diffs.to_vec()))
+        .unwrap();
+    mcus
+}
+
+#[test]
+fn decode_differences_test() {
+    let encoder = Encoder::new(6, 1, 2);
+    let input = samples(6 * 2, encoder.precision);
+    let mcus = differences(&encoder.encode(&input));
+    assert_eq!(mcus.len(), 6);
+    let mut prediction = [2048, 2048];
+    for (mcu, diffs) in input.chunks(2).zip(mcus) {
+        for ((sample, diff), pred) in mcu.iter().zip(diffs).zip(&mut prediction)
+        {
+            assert_eq!(i32::from(*sample), *pred + diff);
+            *pred = i32::from(*sample);
+        }
+    }
+}
+
+#[test]
+fn subsampled_differences_test() {
+    let encoder = Encoder::new(8, 8, 1);
+    let data = encoder.encode(&samples(8 * 8, encoder.precision));
+    let mut subsampled = data.clone();
+    let sof = find_marker(&subsampled, 0xC3);
+    patch(&mut subsampled, sof + 11, &[0x22]);
+    let decoder = LJpegDecoder::new(&subsampled).unwrap();
+    assert!(decoder.frame().is_subsampled());
+    assert_eq!(decoder.frame().samples_per_mcu(), 4);
+    assert_eq!(decoder.frame().mcu_dims().row_len().get(), 4);
+    assert_eq!(decoder.frame().mcu_dims().row_count().get(), 4);
+    let mcus = differences(&subsampled);
+    assert_eq!(mcus.len(), 16);
+    assert_eq!(mcus.concat(), differences(&data).concat());
+}
@@ -0,0 +1,214 @@
+use rawspeed_std::coord_common::{RowLength, RowPitch};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+
+use super::{COUNTS, Encoder, decode, find_marker, patch, samples};
+use crate::ljpeg::{LJpegDecoder, LJpegError};
+
+fn encoded() -> Vec<u8> {
+    let encoder = Encoder::new(8, 8, 1);
+    encoder.encode(&samples(8 * 8, encoder.precision))
+}
+
+#[test]
+fn missing_soi_test() {
+    assert_eq!(decode(&[]), Err(LJpegError::MissingSOI));
+    assert_eq!(decode(&[0xFF, 0xD9]), Err(LJpegError::MissingSOI));
+    assert_eq!(
+        decode(encoded().get(2..).unwrap()),
+        Err(LJpegError::MissingSOI)
+    );
+}
+
+#[test]
+fn truncated_header_test() {
+    let input = encoded();
+    let sos = find_marker(&input, 0xDA);
+    for len in 2..sos + 10 {
+        assert_eq!(
+            decode(input.get(..len).unwrap()),
+            Err(LJpegError::UnexpectedEndOfInput)
+        );
+    }
+}
+
+#[test]
+fn unsupported_frame_type_test() {
+    let mut input = encoded();
+    let sof = find_marker(&input, 0xC3);
+    patch(&mut input, sof + 1, &[0xC0]);
+    assert_eq!(decode(&input), Err(LJpegError::UnsupportedFrameType(0xC0)));
+}
+
+#[test]
+fn scan_without_frame_test() {
+    let mut input = encoded();
+    let sof = find_marker(&input, 0xC3);
+    patch(&mut input, sof + 1, &[0xE0]);
+    assert_eq!(decode(&input), Err(LJpegError::UnexpectedMarker(0xDA)));
+}
+
+#[test]
+fn unexpected_eoi_test() {
+    let mut input = encoded();
+    let sos = find_marker(&input, 0xDA);
+    input.splice(sos..sos, [0xFF, 0xD9]);
+    assert_eq!(decode(&input), Err(LJpegError::UnexpectedMarker(0xD9)));
+}
+
+#[test]
+fn invalid_precision_test() {
+    for precision in [0, 1, 17] {
+        let mut input = encoded();
+        let sof = find_marker(&input, 0xC3);
+        patch(&mut input, sof + 4, &[precision]);
+        assert_eq!(decode(&input), Err(LJpegError::InvalidFrameHeader));
+    }
+}
+
+#[test]
+fn zero_height_test() {
+    let mut input = encoded();
+    let sof = find_marker(&input, 0xC3);
+    patch(&mut input, sof + 5, &[0, 0]);
+    assert_eq!(decode(&input), Err(LJpegError::InvalidFrameHeader));
+}
+
+#[test]
+fn subsampling_test() {
+    let mut input = encoded();
+    let sof = find_marker(&input, 0xC3);
+    patch(&mut input, sof + 11, &[0x21]);
+    assert_eq!(decode(&input), Err(LJpegError::UnsupportedSampling));
+}
+
+#[test]
+fn invalid_sampling_test() {
+    for sampling in [0x01, 0x10, 0x51, 0x15] {
+        let mut input = encoded();
+        let sof = find_marker(&input, 0xC3);
+        patch(&mut input, sof + 11, &[sampling]);
+        assert_eq!(decode(&input), Err(LJpegError::InvalidFrameHeader));
+    }
+}
+
+#[test]
+fn invalid_predictor_test() {
+    for predictor in [0, 8] {
+        let mut encoder = Encoder::new(2, 2, 1);
+        encoder.predictor = predictor;
+        let input = encoder.encode(&samples(4, encoder.precision));
+        assert_eq!(
+            decode(&input),
+            Err(LJpegError::InvalidPredictor(predictor))
+        );
+    }
+}
+
+#[test]
+fn missing_huffman_table_test() {
+    let mut input = encoded();
+    let sos = find_marker(&input, 0xDA);
+    patch(&mut input, sos + 6, &[0x10]);
+    assert_eq!(decode(&input), Err(LJpegError::MissingHuffmanTable(1)));
+}
+
+#[test]
+fn ac_huffman_table_test() {
+    let mut input = encoded();
+    let dht = find_marker(&input, 0xC4);
+    patch(&mut input, dht + 4, &[0x10]);
+    assert_eq!(decode(&input), Err(LJpegError::InvalidHuffmanTable));
+}
+
+#[test]
+fn oversubscribed_huffman_table_test() {
+    let mut input = encoded();
+    let dht = find_marker(&input, 0xC4);
+    // Move three codes to length 1, keeping the symbol count.
+    patch(&mut input, dht + 5, &[3]);
+    patch(&mut input, dht + 9, &[COUNTS[4] - 3]);
+    assert_eq!(decode(&input), Err(LJpegError::InvalidHuffmanTable));
+}
+
+#[test]
+fn invalid_huffman_symbol_test() {
+    let mut input = encoded();
+    let dht = find_marker(&input, 0xC4);
+    patch(&mut input, dht + 21, &[17]);
+    assert_eq!(decode(&input), Err(LJpegError::InvalidHuffmanTable));
+}
+
+#[test]
+fn invalid_huffman_code_test() {
+    let mut input = encoded();
+    let sos = find_marker(&input, 0xDA);
+    // Codes 10001 and up are unused.
+    input.truncate(sos + 10);
+    input.extend([0xFF, 0x00, 0xFF, 0x00]);
+    assert_eq!(decode(&input), Err(LJpegError::InvalidHuffmanCode));
+}
+
+#[test]
+fn invalid_restart_marker_test() {
+    let mut encoder = Encoder::new(8, 8, 1);
+    encoder.restart_interval = 8;
+    let mut input = encoder.encode(&samples(8 * 8, encoder.precision));
+    let rst1 = find_marker(&input, 0xD1);
+    patch(&mut input, rst1 + 1, &[0xD2]);
+    assert_eq!(decode(&input), Err(LJpegError::InvalidRestartMarker(1)));
+}
+
+#[test]
+fn missing_restart_marker_test() {
+    let mut encoder = Encoder::new(8, 8, 1);
+    encoder.restart_interval = 8;
+    let mut input = encoder.encode(&samples(8 * 8, encoder.precision));
+    let rst0 = find_marker(&input, 0xD0);
+    input.truncate(rst0);
+    assert_eq!(decode(&input), Err(LJpegError::InvalidRestartMarker(0)));
+}
+
+#[test]
+fn truncated_data_test() {
+    let encoder = Encoder::new(64, 8, 1);
+    let mut input = encoder.encode(&samples(64 * 8, encoder.precision));
+    let sos = find_marker(&input, 0xDA);
+    input.truncate(sos + 10 + 4);
+    assert_eq!(decode(&input), Err(LJpegError::TruncatedData));
+}
+
+#[test]
+fn empty_data_test() {
+    let mut input = encoded();
+    let sos = find_marker(&input, 0xDA);
+    input.truncate(sos + 10);
+    assert_eq!(decode(&input), Err(LJpegError::TruncatedData));
+}
+
+#[test]
+fn output_dimensions_mismatch_test() {
+    let input = encoded();
+    let decoder = LJpegDecoder::new(&input).unwrap();
+    let mut storage = vec![0_u16; 8 * 9];
+    let mut output = Array2DRefMut::new(
+        &mut storage,
+        RowLength::new(core::num::NonZero::new(8).unwrap()),
+        RowPitch::new(core::num::NonZero::new(8).unwrap()),
+    );
+    assert_eq!(
+        decoder.decode(&mut output),
+        Err(LJpegError::OutputDimensionsMismatch)
+    );
+}
+
+#[test]
+fn display_test() {
+    assert_eq!(
+        LJpegError::UnsupportedFrameType(0xC0).to_string(),
+        "LJpegError(UnsupportedFrameType(0xc0))"
+    );
+    assert_eq!(
+        LJpegError::InvalidRestartMarker(3).to_string(),
+        "LJpegError(InvalidRestartMarker(3))"
+    );
+}
@@ -0,0 +1,221 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_std::coord_common::RowIndex;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+
+use super::{LJpegDecoder, LJpegError};
+
+/// All 17 difference categories, as 5-bit codes equal to the category.
+const COUNTS: [u8; 16] = [0, 0, 0, 0, 17, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+const CODE_LENGTH: u32 = 5;
+
+#[derive(Debug, Clone)]
+struct Encoder {
+    precision: u8,
+    width: usize,
+    height: usize,
+    num_components: usize,
+    predictor: u8,
+    point_transform: u8,
+    restart_interval: usize,
+    with_app_segment: bool,
+}
+
+impl Encoder {
+    const fn new(width: usize, height: usize, num_components: usize) -> Self {
+        Self {
+            precision: 12,
+            width,
+            height,
+            num_components,
+            predictor: 1,
+            point_transform: 0,
+            restart_interval: 0,
+            with_app_segment: false,
+        }
+    }
+
+    fn sample(&self, samples: &[u16], mcu: usize, component: usize) -> i32 {
+        let index = mcu * self.num_components + component;
+        i32::from(*samples.get(index).unwrap() >> self.point_transform)
+    }
+
+    fn predict(
+        &self,
+        samples: &[u16],
+        mcu: usize,
+        interval_start: usize,
+        component: usize,
+    ) -> i32 {
+        let (row, col) = (mcu / self.width, mcu % self.width);
+        if mcu == interval_start {
+            return 1 << (self.precision - self.point_transform - 1);
+        }
+        let ra = || self.sample(samples, mcu - 1, component);
+        let rb = || self.sample(samples, mcu - self.width, component);
+        let rc = || self.sample(samples, mcu - self.width - 1, component);
+        if row == interval_start / self.width {
+            return ra();
+        }
+        if col == 0 {
+            return rb();
+        }
+        match self.predictor {
+            1 => ra(),
+            2 => rb(),
+            3 => rc(),
+            4 => ra() + rb() - rc(),
+            5 => ra() + ((rb() - rc()) >> 1),
+            6 => rb() + ((ra() - rc()) >> 1),
+            _ => (ra() + rb()) >> 1,
+        }
+    }
+
+    fn encode_difference(bits: &mut BitStreamBuilder, diff: i32) {
+        let diff = match diff.rem_euclid(1 << 16) {
+            d if d >= 1 << 15 => d - (1 << 16),
+            d => d,
+        };
+        let category = 32 - diff.unsigned_abs().leading_zeros();
+        bits.put(category, CODE_LENGTH);
+        if category != 0 && category != 16 {
+            let value = if diff < 0 {
+                diff + (1 << category) - 1
+            } else {
+                diff
+            };
+            bits.put(value.try_into().unwrap(), category);
+        }
+    }
+
+    fn encode_interval(
+        &self,
+        samples: &[u16],
+        mcus: core::ops::Range<usize>,
+    ) -> Vec<u8> {
+        let mut bits = BitStreamBuilder::new();
+        for mcu in mcus.clone() {
+            for component in 0..self.num_components {
+                let prediction =
+                    self.predict(samples, mcu, mcus.start, component);
+                let diff = self.sample(samples, mcu, component) - prediction;
+                Self::encode_difference(&mut bits, diff);
+            }
+        }
+        bits.finish(BitOrder::JPEG)
+    }
+
+    fn segment(out: &mut Vec<u8>, marker: u8, payload: &[u8]) {
+        let len = u16::try_from(payload.len() + 2).unwrap();
+        out.extend([0xFF, marker]);
+        out.extend(be16(len));
+        out.extend(payload);
+    }
+
+    fn headers(&self) -> Vec<u8> {
+        let mut out = vec![0xFF, 0xD8];
+        if self.with_app_segment {
+            Self::segment(&mut out, 0xE1, b"Exif\0\0");
+        }
+        let mut sof = vec![self.precision];
+        sof.extend(be16(u16::try_from(self.height).unwrap()));
+        sof.extend(be16(u16::try_from(self.width).unwrap()));
+        sof.push(self.num_components.try_into().unwrap());
+        for id in 1..=self.num_components {
+            sof.extend([u8::try_from(id).unwrap(), 0x11, 0]);
+        }
+        Self::segment(&mut out, 0xC3, &sof);
+        let mut dht = vec![0x00];
+        dht.extend(COUNTS);
+        dht.extend(0..=16);
+        Self::segment(&mut out, 0xC4, &dht);
+        if self.restart_interval != 0 {
+            let interval = u16::try_from(self.restart_interval).unwrap();
+            Self::segment(&mut out, 0xDD, &be16(interval));
+        }
+        let mut sos = vec![self.num_components.try_into().unwrap()];
+        for id in 1..=self.num_components {
+            sos.extend([u8::try_from(id).unwrap(), 0x00]);
+        }
+        sos.extend([self.predictor, 0, self.point_transform]);
+        Self::segment(&mut out, 0xDA, &sos);
+        out
+    }
+
+    fn encode(&self, samples: &[u16]) -> Vec<u8> {
+        assert_eq!(
+            samples.len(),
+            self.width * self.height * self.num_components
+        );
+        let mut out = self.headers();
+        let num_mcus = self.width * self.height;
+        let interval_len = match self.restart_interval {
+            0 => num_mcus,
+            len => len,
+        };
+        for (index, start) in (0..num_mcus).step_by(interval_len).enumerate() {
+            if index != 0 {
+                out.extend([
+                    0xFF,
+                    0xD0 + u8::try_from((index - 1) % 8).unwrap(),
+                ]);
+            }
+            let end = (start + interval_len).min(num_mcus);
+            out.extend(self.encode_interval(samples, start..end));
+        }
+        out.extend([0xFF, 0xD9]);
+        out
+    }
+}
+
+/// Deterministic pseudo-random samples of the given precision.
+fn samples(count: usize, precision: u8) -> Vec<u16> {
+    let mut state = 0x1234_5678_u32;
+    core::iter::repeat_with(|| {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        u16::try_from((state >> 8) & ((1 << precision) - 1)).unwrap()
+    })
+    .take(count)
+    .collect()
+}
+
+fn decode(input: &[u8]) -> Result<Vec<u16>, LJpegError> {
+    let decoder = LJpegDecoder::new(input)?;
+    let dims = decoder.output_dims();
+    let mut storage =
+        vec![0_u16; dims.row_len().get() * dims.row_count().get()];
+    let mut output = rawspeed_std_ndslice::array2drefmut::Array2DRefMut::new(
+        &mut storage,
+        dims.row_len(),
+        rawspeed_std::coord_common::RowPitch::new(dims.row_len().val()),
+    );
+    decoder.decode(&mut output)?;
+    Ok((0..dims.row_count().get())
+        .flat_map(|row| output.get_row(RowIndex::new(row)).unwrap().to_vec())
+        .collect())
+}
+
+fn be16(val: u16) -> [u8; 2] {
+    [
+        (val >> 8).try_into().unwrap(),
+        (val & 0xFF).try_into().unwrap(),
+    ]
+}
+
+fn patch(input: &mut [u8], pos: usize, bytes: &[u8]) {
+    input
+        .get_mut(pos..pos + bytes.len())
+        .unwrap()
+        .copy_from_slice(bytes);
+}
+
+fn find_marker(input: &[u8], marker: u8) -> usize {
+    input
+        .windows(2)
+        .position(|window| window == [0xFF, marker])
+        .unwrap()
+}
+
+#[cfg(test)]
+mod decode;
+#[cfg(test)]
+mod errors;
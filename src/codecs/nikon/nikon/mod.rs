@@ -0,0 +1,369 @@
+use rawspeed_bitstream_bitstream_decoder::bitstreamer::{
+    BitStream as _, BitStreamerBase,
+};
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrderMSB;
+use rawspeed_bitstream_bytestream_decoder::bytestreamer::ByteStreamer;
+use rawspeed_codecs_huffman::huffman::{
+    HuffmanCode, HuffmanError, HuffmanTable,
+};
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_std::coord_common::{Dimensions2D, RowIndex};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+
+/// The code length histograms and symbols of the Nikon Huffman trees.
+///
+/// Each symbol holds the difference length in its low nibble, and the
+/// number of (implied) low bits of the difference in its high nibble.
+const TREES: [([u8; 16], &[u8]); 6] = [
+    // 12-bit lossy
+    (
+        [0, 1, 5, 1, 1, 1, 1, 1, 1, 2, 0, 0, 0, 0, 0, 0],
+        &[5, 4, 3, 6, 2, 7, 1, 0, 8, 9, 11, 10, 12, 0],
+    ),
+    // 12-bit lossy after split
+    (
+        [0, 1, 5, 1, 1, 1, 1, 1, 1, 2, 0, 0, 0, 0, 0, 0],
+        &[0x39, 0x5a, 0x38, 0x27, 0x16, 5, 4, 3, 2, 1, 0, 11, 12, 12],
+    ),
+    // 12-bit lossless
+    (
+        [0, 1, 4, 2, 3, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        &[5, 4, 6, 3, 7, 2, 8, 1, 9, 0, 10, 11, 12],
+    ),
+    // 14-bit lossy
+    (
+        [0, 1, 4, 3, 1, 1, 1, 1, 1, 2, 0, 0, 0, 0, 0, 0],
+        &[5, 6, 4, 7, 8, 3, 9, 2, 1, 0, 10, 11, 12, 13, 14],
+    ),
+    // 14-bit lossy after split
+    (
+        [0, 1, 5, 1, 1, 1, 1, 1, 1, 1, 2, 0, 0, 0, 0, 0],
+        &[8, 0x5c, 0x4b, 0x3a, 0x29, 7, 6, 5, 4, 3, 2, 1, 0, 13, 14],
+    ),
+    // 14-bit lossless
+    (
+        [0, 1, 4, 2, 2, 3, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0],
+        &[7, 6, 8, 5, 9, 4, 10, 3, 11, 12, 2, 0, 1, 13, 14],
+    ),
+];
+
+/// Metadata of the older bodies is preceded by a block of unknown purpose.
+const LEGACY_HEADER_LEN: usize = 2110;
+
+/// Where the row at which the lossy tree is switched is stored.
+const SPLIT_OFFSET: usize = 562;
+
+/// The largest (clamped) prediction that the curve is looked up with.
+const MAX_CURVE_INDEX: i32 = 0x3fff;
+
+const MAX_CURVE_LEN: usize = 0x4001;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum NikonError {
+    UnexpectedEndOfInput,
+    UnsupportedBitsPerSample(u32),
+    InvalidCurve,
+    OddWidth,
+    InvalidHuffmanCode,
+    TruncatedData,
+    OutputDimensionsMismatch,
+}
+
+impl core::fmt::Display for NikonError {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            NikonError::UnexpectedEndOfInput => {
+                write!(f, "NikonError(UnexpectedEndOfInput)")
+            }
+            NikonError::UnsupportedBitsPerSample(bits) => {
+                write!(f, "NikonError(UnsupportedBitsPerSample({bits}))")
+            }
+            NikonError::InvalidCurve => write!(f, "NikonError(InvalidCurve)"),
+            NikonError::OddWidth => write!(f, "NikonError(OddWidth)"),
+            NikonError::InvalidHuffmanCode => {
+                write!(f, "NikonError(InvalidHuffmanCode)")
+            }
+            NikonError::TruncatedData => write!(f, "NikonError(TruncatedData)"),
+            NikonError::OutputDimensionsMismatch => {
+                write!(f, "NikonError(OutputDimensionsMismatch)")
+            }
+        }
+    }
+}
+
+impl From<HuffmanError> for NikonError {
+    #[inline]
+    fn from(err: HuffmanError) -> Self {
+        if err == HuffmanError::EndOfStream {
+            NikonError::TruncatedData
+        } else {
+            NikonError::InvalidHuffmanCode
+        }
+    }
+}
+
+/// Reader over the (`MakerNote`-endian) decompression metadata.
+#[derive(Debug, Clone, Copy)]
+struct MetadataReader<'a> {
+    input: &'a [u8],
+    pos: usize,
+    endianness: Endianness,
+}
+
+impl<'a> MetadataReader<'a> {
+    fn get_bytes(&mut self, len: usize) -> Result<&'a [u8], NikonError> {
+        let bytes = self
+            .input
+            .get(self.pos..)
+            .and_then(|rest| rest.get(..len))
+            .ok_or(NikonError::UnexpectedEndOfInput)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, NikonError> {
+        Ok(*self.get_bytes(1)?.first().unwrap())
+    }
+
+    fn read_u16(&mut self) -> Result<u16, NikonError> {
+        let bytes = self.get_bytes(size_of::<u16>())?;
+        Ok(ByteStreamer::new(bytes, self.endianness).read())
+    }
+}
+
+/// The linearization curve, as a piecewise-linear function given by
+/// `csize` points evenly spaced over the `max` possible values.
+fn interpolate_curve(
+    reader: &mut MetadataReader<'_>,
+    csize: usize,
+    max: usize,
+) -> Result<Vec<u16>, NikonError> {
+    let step = max / (csize - 1);
+    if step == 0 || (csize - 1) * step != max {
+        return Err(NikonError::InvalidCurve);
+    }
+    let points = core::iter::repeat_with(|| reader.read_u16().map(u32::from))
+        .take(csize)
+        .collect::<Result<Vec<_>, _>>()?;
+    let step_u32 = u32::try_from(step).unwrap();
+    Ok(points
+        .windows(2)
+        .flat_map(|segment| {
+            let [a, b] = [segment.first(), segment.last()].map(|p| *p.unwrap());
+            (0..step_u32).map(move |i| {
+                u16::try_from((a * (step_u32 - i) + b * i) / step_u32).unwrap()
+            })
+        })
+        .collect())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+#[must_use]
+pub struct NikonMetadata {
+    /// Index of the Huffman tree in [`TREES`].
+    tree: usize,
+    /// The initial predictions of the first two samples of even/odd rows.
+    vpred: [[u16; 2]; 2],
+    curve: Vec<u16>,
+    /// The row from which the lossy samples use the next tree.
+    split: Option<usize>,
+}
+
+impl NikonMetadata {
+    /// Parses the decompression metadata (`MakerNote` tag `0x96`).
+    #[inline]
+    pub fn new(
+        input: &[u8],
+        endianness: Endianness,
+        bits: u32,
+    ) -> Result<Self, NikonError> {
+        if bits != 12 && bits != 14 {
+            return Err(NikonError::UnsupportedBitsPerSample(bits));
+        }
+        let mut reader = MetadataReader {
+            input,
+            pos: 0,
+            endianness,
+        };
+        let v0 = reader.read_u8()?;
+        let v1 = reader.read_u8()?;
+        if v0 == 0x49 || v1 == 0x58 {
+            reader.get_bytes(LEGACY_HEADER_LEN)?;
+        }
+        let is_lossless = v0 == 0x46;
+        let tree = match (is_lossless, bits) {
+            (false, 12) => 0,
+            (true, 12) => 2,
+            (false, _) => 3,
+            (true, _) => 5,
+        };
+        let mut vpred = [[0; 2]; 2];
+        // Stored as [even row, 0], [odd row, 0], [even row, 1], ...
+        for col in 0..2 {
+            for row_pred in &mut vpred {
+                *row_pred.get_mut(col).unwrap() = reader.read_u16()?;
+            }
+        }
+        let csize = usize::from(reader.read_u16()?);
+        // The Z-series curves only span a quarter of the sample range.
+        let curve_bits = if v0 == 0x44 && v1 == 0x40 {
+            bits - 2
+        } else {
+            bits
+        };
+        let max = (1_usize << curve_bits) & 0x7fff;
+        let mut split = None;
+        let curve = if v0 == 0x44 && (v1 == 0x20 || v1 == 0x40) && csize > 1 {
+            let curve = interpolate_curve(&mut reader, csize, max)?;
+            reader.pos = SPLIT_OFFSET;
+            split = Some(usize::from(reader.read_u16()?)).filter(|s| *s != 0);
+            curve
+        } else if !is_lossless {
+            if csize == 0 || csize > MAX_CURVE_LEN {
+                return Err(NikonError::InvalidCurve);
+            }
+            core::iter::repeat_with(|| reader.read_u16())
+                .take(csize)
+                .collect::<Result<_, _>>()?
+        } else {
+            (0..max).map(|v| u16::try_from(v).unwrap()).collect()
+        };
+        Ok(Self {
+            tree,
+            vpred,
+            curve,
+            split,
+        })
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn curve(&self) -> &[u16] {
+        &self.curve
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn split(&self) -> Option<usize> {
+        self.split
+    }
+
+    fn lookup(&self, pred: i32) -> u16 {
+        let index = usize::try_from(pred.clamp(0, MAX_CURVE_INDEX)).unwrap();
+        *self.curve.get(index.min(self.curve.len() - 1)).unwrap()
+    }
+}
+
+/// Reconstructs a difference of `len` bits, of which the low `shl` ones
+/// are implied, from its stored bits: values with the top bit clear
+/// are negative.
+fn extend_difference(len: u32, shl: u32, bits: u32) -> i32 {
+    if len == 0 {
+        return 0;
+    }
+    let diff = i32::try_from((((bits << 1) + 1) << shl) >> 1).unwrap();
+    if diff & (1 << (len - 1)) == 0 {
+        diff - (1 << len) + i32::from(shl == 0)
+    } else {
+        diff
+    }
+}
+
+fn decode_difference(
+    table: &HuffmanTable,
+    bs: &mut BitStreamerBase<'_, BitOrderMSB>,
+) -> Result<i32, NikonError> {
+    let symbol = table.decode_code_value(bs)?;
+    let len = u32::from(symbol & 0xF);
+    let shl = u32::from(symbol >> 4);
+    let nbits = len.checked_sub(shl).ok_or(NikonError::InvalidHuffmanCode)?;
+    if nbits == 0 {
+        return Ok(extend_difference(len, shl, 0));
+    }
+    bs.fill(nbits).map_err(|_err| NikonError::TruncatedData)?;
+    let bits = u32::try_from(bs.peek_bits_no_fill(nbits).zext()).unwrap();
+    bs.skip_bits_no_fill(nbits);
+    Ok(extend_difference(len, shl, bits))
+}
+
+fn make_table(tree: usize) -> HuffmanTable {
+    let (counts, symbols) = TREES.get(tree).unwrap();
+    let code = HuffmanCode::new(counts, symbols).unwrap();
+    HuffmanTable::new(&code, HuffmanTable::DEFAULT_LUT_BITS).unwrap()
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct NikonDecompressor<'a> {
+    input: &'a [u8],
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    metadata: NikonMetadata,
+}
+
+impl<'a> NikonDecompressor<'a> {
+    #[inline]
+    pub fn new(
+        input: &'a [u8],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        metadata: NikonMetadata,
+    ) -> Result<Self, NikonError> {
+        if !dims.row_len().get().is_multiple_of(2) {
+            return Err(NikonError::OddWidth);
+        }
+        Ok(Self {
+            input,
+            dims,
+            metadata,
+        })
+    }
+
+    #[inline]
+    pub const fn metadata(&self) -> &NikonMetadata {
+        &self.metadata
+    }
+
+    /// Each sample is predicted from the previous one of the same color,
+    /// the first two of each row from those two rows above.
+    #[inline(never)]
+    pub fn decode(
+        &self,
+        output: &mut Array2DRefMut<'_, u16>,
+    ) -> Result<(), NikonError> {
+        if output.dims() != self.dims {
+            return Err(NikonError::OutputDimensionsMismatch);
+        }
+        let mut bs = BitStreamerBase::<BitOrderMSB>::try_from(self.input)
+            .map_err(|_err| NikonError::TruncatedData)?;
+
+        let mut table = make_table(self.metadata.tree);
+        let mut vpred = self.metadata.vpred.map(|row| row.map(i32::from));
+        for row in 0..self.dims.row_count().get() {
+            if self.metadata.split == Some(row) {
+                table = make_table(self.metadata.tree + 1);
+            }
+            let vpred = vpred.get_mut(row % 2).unwrap();
+            let mut hpred = [0; 2];
+            let out = output.get_row_mut(RowIndex::new(row)).unwrap();
+            for (col, sample) in out.iter_mut().enumerate() {
+                let diff = decode_difference(&table, &mut bs)?;
+                let pred = hpred.get_mut(col % 2).unwrap();
+                if col < 2 {
+                    let vpred = vpred.get_mut(col).unwrap();
+                    *vpred += diff;
+                    *pred = *vpred;
+                } else {
+                    *pred += diff;
+                }
+                *sample = self.metadata.lookup(*pred);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
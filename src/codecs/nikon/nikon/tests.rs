@@ -0,0 +1,341 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_codecs_huffman::huffman::HuffmanCode;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_std::coord_common::{
+    Dimensions2D, RowCount, RowIndex, RowLength, RowPitch,
+};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+
+use super::{
+    NikonDecompressor, NikonError, NikonMetadata, SPLIT_OFFSET, TREES,
+    extend_difference,
+};
+
+/// Encodes the differences with the codes of the given tree,
+/// only ever using symbols without implied low bits.
+fn put_differences(bits: &mut BitStreamBuilder, tree: usize, diffs: &[i32]) {
+    let (counts, symbols) = TREES.get(tree).unwrap();
+    let codes = HuffmanCode::new(counts, symbols).unwrap().codes();
+    for diff in diffs {
+        let len = 32 - diff.unsigned_abs().leading_zeros();
+        let index = symbols.iter().position(|s| u32::from(*s) == len).unwrap();
+        let (code_len, code) = *codes.get(index).unwrap();
+        bits.put(code, code_len);
+        let value = if *diff < 0 {
+            *diff + (1 << len) - 1
+        } else {
+            *diff
+        };
+        bits.put(u32::try_from(value).unwrap(), len);
+    }
+}
+
+fn encode(tree: usize, diffs: &[i32]) -> Vec<u8> {
+    let mut bits = BitStreamBuilder::new();
+    put_differences(&mut bits, tree, diffs);
+    bits.finish(BitOrder::MSB)
+}
+
+/// The differences that produce the given (pre-curve) samples.
+fn differences(samples: &[Vec<i32>], vpred: [[i32; 2]; 2]) -> Vec<i32> {
+    let mut diffs = vec![];
+    for (row, values) in samples.iter().enumerate() {
+        for (col, value) in values.iter().enumerate() {
+            let above = match row.checked_sub(2) {
+                Some(row) => samples.get(row).unwrap().as_slice(),
+                None => vpred.get(row).unwrap().as_slice(),
+            };
+            let pred = match col.checked_sub(2) {
+                Some(col) => values.get(col),
+                None => above.get(col),
+            };
+            diffs.push(value - pred.unwrap());
+        }
+    }
+    diffs
+}
+
+fn be16(val: u16) -> [u8; 2] {
+    [
+        (val >> 8).try_into().unwrap(),
+        (val & 0xFF).try_into().unwrap(),
+    ]
+}
+
+#[derive(Debug)]
+struct Meta {
+    v0: u8,
+    v1: u8,
+    vpred: [[u16; 2]; 2],
+    curve: Vec<u16>,
+    split: u16,
+}
+
+impl Meta {
+    fn new(v0: u8, v1: u8) -> Self {
+        Self {
+            v0,
+            v1,
+            vpred: [[100, 200], [300, 400]],
+            curve: vec![],
+            split: 0,
+        }
+    }
+
+    fn build(&self) -> Vec<u8> {
+        let mut out = vec![self.v0, self.v1];
+        if self.v0 == 0x49 {
+            out.resize(out.len() + 2110, 0xFF);
+        }
+        for col in 0..2 {
+            for row in &self.vpred {
+                out.extend(be16(*row.get(col).unwrap()));
+            }
+        }
+        out.extend(be16(u16::try_from(self.curve.len()).unwrap()));
+        for point in &self.curve {
+            out.extend(be16(*point));
+        }
+        if self.split != 0 {
+            out.resize(SPLIT_OFFSET, 0);
+            out.extend(be16(self.split));
+        }
+        out
+    }
+
+    fn parse(&self, bits: u32) -> Result<NikonMetadata, NikonError> {
+        NikonMetadata::new(&self.build(), Endianness::Big, bits)
+    }
+}
+
+fn dims(
+    width: usize,
+    height: usize,
+) -> Dimensions2D<core::num::NonZero<usize>> {
+    Dimensions2D::new(
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowCount::new(core::num::NonZero::new(height).unwrap()),
+    )
+}
+
+fn decode(
+    data: &[u8],
+    metadata: NikonMetadata,
+    width: usize,
+    height: usize,
+) -> Result<Vec<u16>, NikonError> {
+    let decoder = NikonDecompressor::new(data, dims(width, height), metadata)?;
+    let mut buf = vec![0; width * height];
+    let mut output = Array2DRefMut::new(
+        buf.as_mut_slice(),
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowPitch::new(core::num::NonZero::new(width).unwrap()),
+    );
+    decoder.decode(&mut output)?;
+    assert_eq!(output.get_row_mut(RowIndex::new(0)).unwrap().len(), width);
+    Ok(buf)
+}
+
+fn test_samples(max: u32) -> Vec<Vec<i32>> {
+    (0..4)
+        .map(|row| {
+            (0..6)
+                .map(|col| {
+                    let v = (row * 977 + col * 313 + (col % 2) * 50) % max;
+                    i32::try_from(v).unwrap()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn check_round_trip(bits: u32, meta: &Meta, tree: usize) {
+    let max = 1 << bits;
+    let samples = test_samples(max);
+    let vpred = meta.vpred.map(|row| row.map(i32::from));
+    let data = encode(tree, &differences(&samples, vpred));
+    let metadata = meta.parse(bits).unwrap();
+    let expected: Vec<u16> = samples
+        .iter()
+        .flatten()
+        .map(|v| {
+            let index = usize::try_from(*v).unwrap().min(0x3fff);
+            *metadata
+                .curve()
+                .get(index.min(metadata.curve().len() - 1))
+                .unwrap()
+        })
+        .collect();
+    assert_eq!(decode(&data, metadata, 6, 4), Ok(expected));
+}
+
+#[test]
+fn lossless_12_bit_test() {
+    check_round_trip(12, &Meta::new(0x46, 0x30), 2);
+}
+
+#[test]
+fn lossless_14_bit_test() {
+    check_round_trip(14, &Meta::new(0x46, 0x30), 5);
+}
+
+#[test]
+fn lossy_12_bit_with_curve_test() {
+    let mut meta = Meta::new(0x44, 0x10);
+    meta.curve = (0..4096).map(|v| 4095 - v).collect();
+    check_round_trip(12, &meta, 0);
+}
+
+#[test]
+fn lossy_14_bit_with_curve_test() {
+    let mut meta = Meta::new(0x44, 0x10);
+    meta.curve = (0..0x4001).map(|v| v / 4).collect();
+    check_round_trip(14, &meta, 3);
+}
+
+#[test]
+fn legacy_header_test() {
+    let mut meta = Meta::new(0x49, 0x10);
+    meta.curve = (0..4096).collect();
+    check_round_trip(12, &meta, 0);
+}
+
+#[test]
+fn interpolated_curve_test() {
+    let mut meta = Meta::new(0x44, 0x20);
+    meta.curve = vec![0, 100, 1100, 1200, 4000];
+    meta.split = 1234;
+    let metadata = meta.parse(12).unwrap();
+    let curve = metadata.curve();
+    assert_eq!(curve.len(), 4096);
+    assert_eq!(curve.get(..3), Some([0, 0, 0].as_slice()));
+    assert_eq!(curve.get(1024), Some(&100));
+    assert_eq!(curve.get(1536), Some(&600));
+    assert_eq!(curve.get(3072), Some(&1200));
+    assert_eq!(curve.last(), Some(&3997));
+    assert_eq!(metadata.split(), Some(1234));
+}
+
+#[test]
+fn z_series_curve_test() {
+    // The curve spans 12 bits of the 14-bit samples.
+    let mut meta = Meta::new(0x44, 0x40);
+    meta.curve = vec![0, 100, 1100, 1200, 4000];
+    meta.split = 1234;
+    let metadata = meta.parse(14).unwrap();
+    let curve = metadata.curve();
+    assert_eq!(curve.len(), 4096);
+    assert_eq!(curve.get(1024), Some(&100));
+    assert_eq!(curve.get(1536), Some(&600));
+    assert_eq!(curve.get(3072), Some(&1200));
+    assert_eq!(curve.last(), Some(&3997));
+}
+
+#[test]
+fn split_test() {
+    let mut meta = Meta::new(0x44, 0x40);
+    meta.curve = vec![0, 1024];
+    meta.vpred = [[10, 20], [30, 40]];
+    meta.split = 2;
+    let samples: Vec<Vec<i32>> = (0..4)
+        .map(|row| (0..4).map(|col| 10 + row * 7 + col * 3).collect())
+        .collect();
+    let vpred = meta.vpred.map(|row| row.map(i32::from));
+    let diffs = differences(&samples, vpred);
+    let (before, after) = diffs.split_at(8);
+    let mut bits = BitStreamBuilder::new();
+    put_differences(&mut bits, 0, before);
+    put_differences(&mut bits, 1, after);
+    let expected: Vec<u16> = samples
+        .iter()
+        .flatten()
+        .map(|v| u16::try_from(*v).unwrap())
+        .collect();
+    assert_eq!(
+        decode(&bits.finish(BitOrder::MSB), meta.parse(12).unwrap(), 4, 4),
+        Ok(expected)
+    );
+}
+
+#[test]
+fn shifted_difference_test() {
+    // 0x16: 6 bits, of which the lowest one is implied (and set).
+    assert_eq!(extend_difference(6, 1, 0b1_0000), 33);
+    assert_eq!(extend_difference(6, 1, 0b0_1111), -33);
+    assert_eq!(extend_difference(6, 1, 0), -63);
+    assert_eq!(extend_difference(3, 0, 0b100), 4);
+    assert_eq!(extend_difference(3, 0, 0b011), -4);
+    assert_eq!(extend_difference(0, 0, 0), 0);
+}
+
+#[test]
+fn unsupported_bits_test() {
+    assert_eq!(
+        Meta::new(0x46, 0x30).parse(16),
+        Err(NikonError::UnsupportedBitsPerSample(16))
+    );
+}
+
+#[test]
+fn invalid_curve_test() {
+    let mut meta = Meta::new(0x44, 0x20);
+    meta.curve = vec![0, 1, 2, 3];
+    assert_eq!(meta.parse(12), Err(NikonError::InvalidCurve));
+    meta.v1 = 0x10;
+    meta.curve = vec![];
+    assert_eq!(meta.parse(12), Err(NikonError::InvalidCurve));
+}
+
+#[test]
+fn truncated_metadata_test() {
+    let mut meta = Meta::new(0x44, 0x10);
+    meta.curve = vec![0; 16];
+    let data = meta.build();
+    assert_eq!(
+        NikonMetadata::new(
+            data.get(..data.len() - 1).unwrap(),
+            Endianness::Big,
+            12
+        ),
+        Err(NikonError::UnexpectedEndOfInput)
+    );
+}
+
+#[test]
+fn odd_width_test() {
+    let metadata = Meta::new(0x46, 0x30).parse(12).unwrap();
+    assert_eq!(
+        NikonDecompressor::new(&[0; 4], dims(3, 2), metadata).map(|_| ()),
+        Err(NikonError::OddWidth)
+    );
+}
+
+#[test]
+fn truncated_data_test() {
+    let samples = test_samples(4096);
+    let diffs = differences(&samples, [[100, 200], [300, 400]]);
+    let data = encode(2, &diffs);
+    let metadata = Meta::new(0x46, 0x30).parse(12).unwrap();
+    assert_eq!(
+        decode(data.get(..4).unwrap(), metadata, 6, 4),
+        Err(NikonError::TruncatedData)
+    );
+}
+
+#[test]
+fn output_dimensions_mismatch_test() {
+    let metadata = Meta::new(0x46, 0x30).parse(12).unwrap();
+    let decoder =
+        NikonDecompressor::new(&[0; 4], dims(2, 2), metadata).unwrap();
+    let mut buf = vec![0; 8];
+    let mut output = Array2DRefMut::new(
+        buf.as_mut_slice(),
+        RowLength::new(core::num::NonZero::new(4).unwrap()),
+        RowPitch::new(core::num::NonZero::new(4).unwrap()),
+    );
+    assert_eq!(
+        decoder.decode(&mut output),
+        Err(NikonError::OutputDimensionsMismatch)
+    );
+}
@@ -0,0 +1,231 @@
+use rawspeed_bitstream_bitstream_decoder::bitstreamer::{
+    BitStream, BitStreamerBase,
+};
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrderMSB;
+use rawspeed_common_bitseq::bitseq::BitSeqConstraints;
+use rawspeed_std::coord_common::{Dimensions2D, RowIndex};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+
+/// The compressed data is preceded by a header that is of no use.
+const HEADER_LEN: usize = 7;
+
+/// The length of the zero run that escapes to a longer `high` value.
+const ESCAPE_LEN: u32 = 12;
+
+/// The distance under which the neighbours are averaged for prediction.
+const FLAT_THRESHOLD: i32 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OlympusError {
+    InvalidWidth,
+    TruncatedData,
+    OutputDimensionsMismatch,
+}
+
+impl core::fmt::Display for OlympusError {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            OlympusError::InvalidWidth => {
+                write!(f, "OlympusError(InvalidWidth)")
+            }
+            OlympusError::TruncatedData => {
+                write!(f, "OlympusError(TruncatedData)")
+            }
+            OlympusError::OutputDimensionsMismatch => {
+                write!(f, "OlympusError(OutputDimensionsMismatch)")
+            }
+        }
+    }
+}
+
+fn get_bits<B>(bs: &mut B, nbits: u32) -> Result<u32, OlympusError>
+where
+    B: BitStream,
+    B::T: BitSeqConstraints + core::fmt::Debug,
+    u64: From<B::T>,
+{
+    let bits = peek_bits(bs, nbits)?;
+    bs.skip_bits_no_fill(nbits);
+    Ok(bits)
+}
+
+fn peek_bits<B>(bs: &mut B, nbits: u32) -> Result<u32, OlympusError>
+where
+    B: BitStream,
+    B::T: BitSeqConstraints + core::fmt::Debug,
+    u64: From<B::T>,
+{
+    bs.fill(nbits).map_err(|_err| OlympusError::TruncatedData)?;
+    let bits = u64::from(bs.peek_bits_no_fill(nbits).zext());
+    Ok(u32::try_from(bits).unwrap())
+}
+
+/// The adaptive state of one of the two interleaved columns of a row:
+/// the last magnitude, a running average of the differences, and for
+/// how many samples the magnitude has stayed small.
+#[derive(Debug, Default, Clone, Copy)]
+struct Carry {
+    magnitude: i32,
+    average: i32,
+    small_run: i32,
+}
+
+impl Carry {
+    /// The number of low magnitude bits that are stored verbatim, which
+    /// grows with the previous magnitude.
+    const fn nbits(&self) -> u32 {
+        let extra = if self.small_run < 3 { 2 } else { 0 };
+        let mut nbits = 2 + extra;
+        while (self.magnitude >> (nbits + extra)) != 0 {
+            nbits += 1;
+        }
+        nbits
+    }
+
+    /// Decodes the next difference, along with the two low bits of the
+    /// sample, which are stored separately.
+    fn decode(
+        &mut self,
+        bs: &mut BitStreamerBase<'_, BitOrderMSB>,
+    ) -> Result<(i32, i32), OlympusError> {
+        let nbits = self.nbits();
+        let sign = get_bits(bs, 1)?;
+        let low = i32::try_from(get_bits(bs, 2)?).unwrap();
+        // The high bits of the magnitude are unary-coded, unless there
+        // are too many of them.
+        let zeros = peek_bits(bs, ESCAPE_LEN)?.leading_zeros()
+            - (u32::BITS - ESCAPE_LEN);
+        let high = if zeros == ESCAPE_LEN {
+            bs.skip_bits_no_fill(ESCAPE_LEN);
+            get_bits(bs, 16 - nbits)? >> 1
+        } else {
+            bs.skip_bits_no_fill(zeros + 1);
+            zeros
+        };
+        self.magnitude =
+            i32::try_from((high << nbits) | get_bits(bs, nbits)?).unwrap();
+        let magnitude = if sign == 0 {
+            self.magnitude
+        } else {
+            !self.magnitude
+        };
+        let diff = magnitude + self.average;
+        self.average = (diff * 3 + self.average) >> 5;
+        self.small_run = if self.magnitude > 16 {
+            0
+        } else {
+            self.small_run + 1
+        };
+        Ok((diff, low))
+    }
+}
+
+/// Predicts a sample from its neighbours of the same color: left (`w`),
+/// above (`n`) and above-left (`nw`).
+const fn predict(w: i32, n: i32, nw: i32) -> i32 {
+    if (w < nw && nw < n) || (n < nw && nw < w) {
+        if (w - nw).abs() > FLAT_THRESHOLD || (n - nw).abs() > FLAT_THRESHOLD {
+            w + n - nw
+        } else {
+            (w + n) >> 1
+        }
+    } else if (w - nw).abs() > (n - nw).abs() {
+        w
+    } else {
+        n
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+#[must_use]
+pub struct OlympusDecompressor<'a> {
+    input: &'a [u8],
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+}
+
+impl<'a> OlympusDecompressor<'a> {
+    /// The two columns of each pair are coded independently, hence the
+    /// width must be even.
+    #[inline]
+    pub fn new(
+        input: &'a [u8],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+    ) -> Result<Self, OlympusError> {
+        if !dims.row_len().get().is_multiple_of(2) {
+            return Err(OlympusError::InvalidWidth);
+        }
+        Ok(Self { input, dims })
+    }
+
+    fn sample(output: &Array2DRefMut<'_, u16>, row: usize, col: usize) -> i32 {
+        let row = output.get_row(RowIndex::new(row)).unwrap();
+        i32::from(*row.get(col).unwrap())
+    }
+
+    /// The prediction uses the nearest samples of the same color, which
+    /// are two rows and/or two columns away.
+    fn prediction(
+        output: &Array2DRefMut<'_, u16>,
+        row: usize,
+        col: usize,
+    ) -> i32 {
+        match (row < 2, col < 2) {
+            (true, true) => 0,
+            (true, false) => Self::sample(output, row, col - 2),
+            (false, true) => Self::sample(output, row - 2, col),
+            (false, false) => predict(
+                Self::sample(output, row, col - 2),
+                Self::sample(output, row - 2, col),
+                Self::sample(output, row - 2, col - 2),
+            ),
+        }
+    }
+
+    fn decode_row(
+        bs: &mut BitStreamerBase<'_, BitOrderMSB>,
+        output: &mut Array2DRefMut<'_, u16>,
+        row: usize,
+    ) -> Result<(), OlympusError> {
+        let mut carries = [Carry::default(); 2];
+        let width = output.dims().row_len().get();
+        for col in 0..width {
+            let carry = carries.get_mut(col % 2).unwrap();
+            let (diff, low) = carry.decode(bs)?;
+            let sample =
+                Self::prediction(output, row, col) + ((diff * 4) | low);
+            let out = output.get_row_mut(RowIndex::new(row)).unwrap();
+            *out.get_mut(col).unwrap() =
+                u16::try_from(sample & 0xFFFF).unwrap();
+        }
+        Ok(())
+    }
+
+    /// All the rows are coded in a single bitstream, but the adaptive
+    /// state is reset at the start of each row.
+    #[inline(never)]
+    pub fn decode(
+        &self,
+        output: &mut Array2DRefMut<'_, u16>,
+    ) -> Result<(), OlympusError> {
+        if output.dims() != self.dims {
+            return Err(OlympusError::OutputDimensionsMismatch);
+        }
+        let data = self
+            .input
+            .get(HEADER_LEN..)
+            .filter(|data| !data.is_empty())
+            .ok_or(OlympusError::TruncatedData)?;
+        let mut bs = BitStreamerBase::<BitOrderMSB>::try_from(data)
+            .map_err(|_err| OlympusError::TruncatedData)?;
+        for row in 0..self.dims.row_count().get() {
+            Self::decode_row(&mut bs, output, row)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
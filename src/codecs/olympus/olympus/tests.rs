@@ -0,0 +1,164 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_std::coord_common::{Dimensions2D, RowCount, RowLength, RowPitch};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+
+use super::{Carry, ESCAPE_LEN, OlympusDecompressor, OlympusError, predict};
+
+fn prediction(samples: &[Vec<u16>], row: usize, col: usize) -> i32 {
+    let sample = |y: usize, x: usize| {
+        i32::from(*samples.get(y).unwrap().get(x).unwrap())
+    };
+    match (row < 2, col < 2) {
+        (true, true) => 0,
+        (true, false) => sample(row, col - 2),
+        (false, true) => sample(row - 2, col),
+        (false, false) => predict(
+            sample(row, col - 2),
+            sample(row - 2, col),
+            sample(row - 2, col - 2),
+        ),
+    }
+}
+
+/// Encodes a difference the way [`Carry::decode`] decodes it.
+fn put_difference(bits: &mut BitStreamBuilder, carry: &mut Carry, value: i32) {
+    let nbits = carry.nbits();
+    let diff = value >> 2;
+    let unsigned = diff - carry.average;
+    let (sign, magnitude) = if unsigned < 0 {
+        (1, !unsigned)
+    } else {
+        (0, unsigned)
+    };
+    bits.put(sign, 1);
+    bits.put(u32::try_from(value & 3).unwrap(), 2);
+    let magnitude_bits = u32::try_from(magnitude).unwrap();
+    let high = magnitude_bits >> nbits;
+    if high < ESCAPE_LEN {
+        bits.put(1, high + 1);
+    } else {
+        bits.put(0, ESCAPE_LEN);
+        bits.put(high << 1, 16 - nbits);
+    }
+    bits.put(magnitude_bits & ((1 << nbits) - 1), nbits);
+    carry.magnitude = magnitude;
+    carry.average = (diff * 3 + carry.average) >> 5;
+    carry.small_run = if magnitude > 16 {
+        0
+    } else {
+        carry.small_run + 1
+    };
+}
+
+fn encode(samples: &[Vec<u16>]) -> Vec<u8> {
+    let mut bits = BitStreamBuilder::new();
+    for (row, row_samples) in samples.iter().enumerate() {
+        let mut carries = [Carry::default(); 2];
+        for (col, sample) in row_samples.iter().enumerate() {
+            let value = i32::from(*sample) - prediction(samples, row, col);
+            put_difference(&mut bits, carries.get_mut(col % 2).unwrap(), value);
+        }
+    }
+    let mut out = vec![0; 7];
+    out.extend(bits.finish(BitOrder::MSB));
+    out
+}
+
+fn dims(
+    width: usize,
+    height: usize,
+) -> Dimensions2D<core::num::NonZero<usize>> {
+    Dimensions2D::new(
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowCount::new(core::num::NonZero::new(height).unwrap()),
+    )
+}
+
+fn decode(
+    data: &[u8],
+    width: usize,
+    height: usize,
+) -> Result<Vec<u16>, OlympusError> {
+    let mut buf = vec![0; width * height];
+    let mut output = Array2DRefMut::new(
+        &mut buf,
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowPitch::new(core::num::NonZero::new(width).unwrap()),
+    );
+    OlympusDecompressor::new(data, dims(width, height))?.decode(&mut output)?;
+    Ok(buf)
+}
+
+#[test]
+fn predict_test() {
+    // Monotonic neighbourhoods: an edge, or a flat area.
+    assert_eq!(predict(100, 300, 200), 200);
+    assert_eq!(predict(100, 120, 110), 110);
+    assert_eq!(predict(300, 100, 200), 200);
+    // Otherwise, the neighbour that is furthest from `nw` is used.
+    assert_eq!(predict(100, 150, 90), 150);
+    assert_eq!(predict(100, 95, 200), 95);
+}
+
+#[test]
+fn round_trip_test() {
+    let samples: Vec<Vec<u16>> = (0_usize..5)
+        .map(|row| {
+            (0_usize..8)
+                .map(|col| {
+                    let sample = (row * 577 + col * 131 + col * col * 7) % 4096;
+                    u16::try_from(sample).unwrap()
+                })
+                .collect()
+        })
+        .collect();
+    assert_eq!(decode(&encode(&samples), 8, 5), Ok(samples.concat()));
+}
+
+#[test]
+fn escape_test() {
+    // Large jumps need more than 12 high bits.
+    let samples = vec![
+        vec![0, 4095, 4095, 0, 2, 4093],
+        vec![4095, 0, 1, 4094, 4095, 3],
+    ];
+    assert_eq!(decode(&encode(&samples), 6, 2), Ok(samples.concat()));
+}
+
+#[test]
+fn flat_test() {
+    let samples = vec![vec![256; 16]; 4];
+    let data = encode(&samples);
+    assert_eq!(decode(&data, 16, 4), Ok(samples.concat()));
+}
+
+#[test]
+fn invalid_width_test() {
+    assert_eq!(
+        OlympusDecompressor::new(&[0; 16], dims(3, 1)).map(|_| ()),
+        Err(OlympusError::InvalidWidth)
+    );
+}
+
+#[test]
+fn truncated_test() {
+    assert_eq!(decode(&[0; 7], 2, 1), Err(OlympusError::TruncatedData));
+    assert_eq!(decode(&[0; 8], 64, 64), Err(OlympusError::TruncatedData));
+}
+
+#[test]
+fn output_dimensions_mismatch_test() {
+    let mut buf = vec![0; 8];
+    let mut output = Array2DRefMut::new(
+        &mut buf,
+        RowLength::new(core::num::NonZero::new(4).unwrap()),
+        RowPitch::new(core::num::NonZero::new(4).unwrap()),
+    );
+    assert_eq!(
+        OlympusDecompressor::new(&[0; 16], dims(2, 4))
+            .unwrap()
+            .decode(&mut output),
+        Err(OlympusError::OutputDimensionsMismatch)
+    );
+}
@@ -0,0 +1,324 @@
+use rawspeed_bitstream_bitstream_decoder::bitstreamer::{
+    BitStream, BitStreamerBase,
+};
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrderLSB;
+use rawspeed_common_bitseq::bitseq::BitSeqConstraints;
+use rawspeed_std::coord_common::{Dimensions2D, RowIndex};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+use rawspeed_std_range_rotation::range_rotation::left_rotated_range::LeftRotatableRange as _;
+
+/// The input is split into blocks of this many bytes.
+const BLOCK_SIZE: usize = 0x4000;
+
+/// The blocks consist of packets of this many bytes, each of which
+/// holds a fixed number of pixels.
+const BYTES_PER_PACKET: usize = 16;
+
+const PACKETS_PER_BLOCK: usize = BLOCK_SIZE / BYTES_PER_PACKET;
+
+/// Each block is stored with its two sections swapped, the first section
+/// being this long.
+pub const SECTION_SPLIT_OFFSET: usize = 0x1FF8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PanasonicError {
+    InvalidWidth,
+    InvalidSectionSplitOffset,
+    UnsupportedBitsPerSample,
+    TruncatedData,
+    OutputDimensionsMismatch,
+}
+
+impl core::fmt::Display for PanasonicError {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            PanasonicError::InvalidWidth => {
+                write!(f, "PanasonicError(InvalidWidth)")
+            }
+            PanasonicError::InvalidSectionSplitOffset => {
+                write!(f, "PanasonicError(InvalidSectionSplitOffset)")
+            }
+            PanasonicError::UnsupportedBitsPerSample => {
+                write!(f, "PanasonicError(UnsupportedBitsPerSample)")
+            }
+            PanasonicError::TruncatedData => {
+                write!(f, "PanasonicError(TruncatedData)")
+            }
+            PanasonicError::OutputDimensionsMismatch => {
+                write!(f, "PanasonicError(OutputDimensionsMismatch)")
+            }
+        }
+    }
+}
+
+/// Undoes the swapping of the two sections of a block, i.e. the section
+/// that starts at `section_split_offset` is moved to the front.
+fn unrotate_block(block: &[u8], section_split_offset: usize) -> Vec<u8> {
+    (0..block.len())
+        .rotate_left(section_split_offset)
+        .into_iter()
+        .map(|index| *block.get(index).unwrap())
+        .collect()
+}
+
+/// The pixels of the given packet. The width is a multiple of the
+/// packet size, so a packet never straddles two rows.
+fn packet_pixels<'b>(
+    output: &'b mut Array2DRefMut<'_, u16>,
+    packet: usize,
+    pixels_per_packet: usize,
+) -> &'b mut [u16] {
+    let width = output.dims().row_len().get();
+    let pixel = packet * pixels_per_packet;
+    let row = output.get_row_mut(RowIndex::new(pixel / width)).unwrap();
+    let pixels = row.get_mut(pixel % width..).unwrap();
+    pixels.get_mut(..pixels_per_packet).unwrap()
+}
+
+fn check_width(
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    pixels_per_packet: usize,
+) -> Result<usize, PanasonicError> {
+    if !dims.row_len().get().is_multiple_of(pixels_per_packet) {
+        return Err(PanasonicError::InvalidWidth);
+    }
+    Ok(dims.row_len().get() * dims.row_count().get() / pixels_per_packet)
+}
+
+/// The bitstream of a (unrotated) block of the older scheme. The bits
+/// are consumed from the top down, and the bytes of each packet from
+/// its last one down to its first one.
+#[derive(Debug)]
+struct V4BitPump {
+    buf: Vec<u8>,
+    pos: u32,
+}
+
+impl V4BitPump {
+    /// The number of bits in a block.
+    const BLOCK_BITS: u32 = 0x2_0000;
+
+    /// Maps the byte index, as counted from the end of the block, onto
+    /// the byte within its packet, counted from the start of the block.
+    const BYTE_MASK: usize = (BLOCK_SIZE - 1) ^ (BYTES_PER_PACKET - 1);
+
+    fn new(block: &[u8], section_split_offset: usize) -> Self {
+        let mut buf = unrotate_block(block, section_split_offset);
+        // One more byte, so that reading the byte that follows the last
+        // one needs no special casing.
+        buf.resize(BLOCK_SIZE + 1, 0);
+        Self { buf, pos: 0 }
+    }
+
+    fn get_bits(&mut self, nbits: u32) -> u32 {
+        self.pos = self.pos.wrapping_sub(nbits) & (Self::BLOCK_BITS - 1);
+        let byte = usize::try_from(self.pos >> 3).unwrap() ^ Self::BYTE_MASK;
+        let lo = u32::from(*self.buf.get(byte).unwrap());
+        let hi = u32::from(*self.buf.get(byte + 1).unwrap());
+        ((lo | (hi << 8)) >> (self.pos & 7)) & ((1 << nbits) - 1)
+    }
+}
+
+/// The running state of one of the two interleaved colors of a packet.
+#[derive(Debug, Default, Clone, Copy)]
+struct V4Channel {
+    pred: i32,
+    nonzero: u32,
+}
+
+impl V4Channel {
+    /// Until a non-zero high byte is seen, the sample is stored as is.
+    /// Afterwards, each sample is a difference to the previous one,
+    /// scaled by `shift`.
+    fn decode(&mut self, pump: &mut V4BitPump, shift: u32, pixel: usize) {
+        if self.nonzero == 0 {
+            self.nonzero = pump.get_bits(8);
+            if self.nonzero != 0 || pixel > 11 {
+                let low = pump.get_bits(4);
+                self.pred = i32::try_from((self.nonzero << 4) | low).unwrap();
+            }
+            return;
+        }
+        let diff = i32::try_from(pump.get_bits(8)).unwrap();
+        if diff != 0 {
+            self.pred -= 0x80 << shift;
+            if self.pred < 0 || shift == 4 {
+                self.pred &= (1 << shift) - 1;
+            }
+            self.pred += diff << shift;
+        }
+    }
+}
+
+/// The older scheme: 14 pixels of up to 14 bits per packet, coded as
+/// differences to the previous pixel of the same color.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+#[must_use]
+pub struct PanasonicV4Decompressor<'a> {
+    input: &'a [u8],
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    section_split_offset: usize,
+    num_packets: usize,
+}
+
+impl<'a> PanasonicV4Decompressor<'a> {
+    const PIXELS_PER_PACKET: usize = 14;
+
+    /// Without swapped sections (a `section_split_offset` of 0), the
+    /// last block may be partial, otherwise it must be complete.
+    #[inline]
+    pub fn new(
+        input: &'a [u8],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        section_split_offset: usize,
+    ) -> Result<Self, PanasonicError> {
+        let num_packets = check_width(dims, Self::PIXELS_PER_PACKET)?;
+        if section_split_offset > BLOCK_SIZE {
+            return Err(PanasonicError::InvalidSectionSplitOffset);
+        }
+        let bytes_total = num_packets * BYTES_PER_PACKET;
+        let bytes_total = if section_split_offset == 0 {
+            bytes_total
+        } else {
+            bytes_total.next_multiple_of(BLOCK_SIZE)
+        };
+        let input = input
+            .get(..bytes_total)
+            .ok_or(PanasonicError::TruncatedData)?;
+        Ok(Self {
+            input,
+            dims,
+            section_split_offset,
+            num_packets,
+        })
+    }
+
+    fn decode_packet(pump: &mut V4BitPump, pixels: &mut [u16]) {
+        let mut channels = [V4Channel::default(); 2];
+        let mut shift = 0;
+        for (pixel, sample) in pixels.iter_mut().enumerate() {
+            // Every third pixel, the scale of the differences changes.
+            if pixel % 3 == 2 {
+                shift = 4 >> (3 - pump.get_bits(2));
+            }
+            let channel = channels.get_mut(pixel % 2).unwrap();
+            channel.decode(pump, shift, pixel);
+            *sample = u16::try_from(channel.pred).unwrap();
+        }
+    }
+
+    #[inline(never)]
+    pub fn decode(
+        &self,
+        output: &mut Array2DRefMut<'_, u16>,
+    ) -> Result<(), PanasonicError> {
+        if output.dims() != self.dims {
+            return Err(PanasonicError::OutputDimensionsMismatch);
+        }
+        for (index, block) in self.input.chunks(BLOCK_SIZE).enumerate() {
+            let mut pump = V4BitPump::new(block, self.section_split_offset);
+            let first = index * PACKETS_PER_BLOCK;
+            let last = (first + PACKETS_PER_BLOCK).min(self.num_packets);
+            for packet in first..last {
+                let pixels =
+                    packet_pixels(output, packet, Self::PIXELS_PER_PACKET);
+                Self::decode_packet(&mut pump, pixels);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn get_bits<B>(bs: &mut B, nbits: u32) -> Result<u32, PanasonicError>
+where
+    B: BitStream,
+    B::T: BitSeqConstraints + core::fmt::Debug,
+    u64: From<B::T>,
+{
+    bs.fill(nbits)
+        .map_err(|_err| PanasonicError::TruncatedData)?;
+    let bits = u64::from(bs.peek_bits_no_fill(nbits).zext());
+    bs.skip_bits_no_fill(nbits);
+    Ok(u32::try_from(bits).unwrap())
+}
+
+/// The newer scheme: plain 12-bit or 14-bit pixels, as many as fit into
+/// each packet, which is padded to its end.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+#[must_use]
+pub struct PanasonicV5Decompressor<'a> {
+    input: &'a [u8],
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    bps: u32,
+    num_packets: usize,
+}
+
+impl<'a> PanasonicV5Decompressor<'a> {
+    const fn pixels_per_packet(bps: u32) -> Option<usize> {
+        match bps {
+            12 => Some(10),
+            14 => Some(9),
+            _ => None,
+        }
+    }
+
+    /// The last block is padded, so all the blocks must be complete.
+    #[inline]
+    pub fn new(
+        input: &'a [u8],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        bps: u32,
+    ) -> Result<Self, PanasonicError> {
+        let pixels_per_packet = Self::pixels_per_packet(bps)
+            .ok_or(PanasonicError::UnsupportedBitsPerSample)?;
+        let num_packets = check_width(dims, pixels_per_packet)?;
+        let num_blocks = num_packets.div_ceil(PACKETS_PER_BLOCK);
+        let input = input
+            .get(..num_blocks * BLOCK_SIZE)
+            .ok_or(PanasonicError::TruncatedData)?;
+        Ok(Self {
+            input,
+            dims,
+            bps,
+            num_packets,
+        })
+    }
+
+    #[inline(never)]
+    pub fn decode(
+        &self,
+        output: &mut Array2DRefMut<'_, u16>,
+    ) -> Result<(), PanasonicError> {
+        if output.dims() != self.dims {
+            return Err(PanasonicError::OutputDimensionsMismatch);
+        }
+        let pixels_per_packet = Self::pixels_per_packet(self.bps).unwrap();
+        let padding_bits = u32::try_from(
+            BYTES_PER_PACKET * 8 - pixels_per_packet * self.bps as usize,
+        )
+        .unwrap();
+        for (index, block) in self.input.chunks(BLOCK_SIZE).enumerate() {
+            let block = unrotate_block(block, SECTION_SPLIT_OFFSET);
+            let mut bs =
+                BitStreamerBase::<BitOrderLSB>::try_from(block.as_slice())
+                    .map_err(|_err| PanasonicError::TruncatedData)?;
+            let first = index * PACKETS_PER_BLOCK;
+            let last = (first + PACKETS_PER_BLOCK).min(self.num_packets);
+            for packet in first..last {
+                for sample in packet_pixels(output, packet, pixels_per_packet) {
+                    *sample =
+                        u16::try_from(get_bits(&mut bs, self.bps)?).unwrap();
+                }
+                get_bits(&mut bs, padding_bits)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,308 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_std::coord_common::{Dimensions2D, RowCount, RowLength, RowPitch};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+
+use super::{
+    BLOCK_SIZE, BYTES_PER_PACKET, PanasonicError, PanasonicV4Decompressor,
+    PanasonicV5Decompressor, SECTION_SPLIT_OFFSET, V4BitPump, unrotate_block,
+};
+
+/// Swaps the sections of a block, the way they are stored.
+fn rotate_block(block: &[u8], section_split_offset: usize) -> Vec<u8> {
+    let split = block.len() - section_split_offset;
+    let mut out = block.get(split..).unwrap().to_vec();
+    out.extend(block.get(..split).unwrap());
+    out
+}
+
+/// Writes the bits in the order in which [`V4BitPump`] reads them.
+#[derive(Debug)]
+struct V4Writer {
+    buf: Vec<u8>,
+    pos: u32,
+}
+
+impl V4Writer {
+    fn new() -> Self {
+        Self {
+            buf: vec![0; BLOCK_SIZE + 1],
+            pos: 0,
+        }
+    }
+
+    fn put(&mut self, bits: u32, nbits: u32) {
+        self.pos = self.pos.wrapping_sub(nbits) & (V4BitPump::BLOCK_BITS - 1);
+        let byte =
+            usize::try_from(self.pos >> 3).unwrap() ^ V4BitPump::BYTE_MASK;
+        let word = bits << (self.pos & 7);
+        *self.buf.get_mut(byte).unwrap() |= u8::try_from(word & 0xFF).unwrap();
+        *self.buf.get_mut(byte + 1).unwrap() |=
+            u8::try_from(word >> 8).unwrap();
+    }
+}
+
+/// Encodes the packet without scaling the differences, so the first two
+/// samples must be at least 16, and the others must be within 127 of
+/// the previous sample of the same color, which must be at least 128.
+fn put_v4_packet(writer: &mut V4Writer, samples: &[u16]) {
+    for (pixel, sample) in samples.iter().enumerate() {
+        if pixel % 3 == 2 {
+            // A shift of 0.
+            writer.put(0, 2);
+        }
+        let sample = u32::from(*sample);
+        if pixel < 2 {
+            writer.put(sample >> 4, 8);
+            writer.put(sample & 0xF, 4);
+            continue;
+        }
+        let prev = u32::from(*samples.get(pixel - 2).unwrap());
+        let diff = if sample == prev {
+            0
+        } else {
+            sample + 0x80 - prev
+        };
+        writer.put(diff, 8);
+    }
+}
+
+fn encode_v4(samples: &[u16], section_split_offset: usize) -> Vec<u8> {
+    let packets: Vec<&[u16]> = samples.chunks(14).collect();
+    let mut out = vec![];
+    for block in packets.chunks(BLOCK_SIZE / BYTES_PER_PACKET) {
+        let mut writer = V4Writer::new();
+        for packet in block {
+            put_v4_packet(&mut writer, packet);
+        }
+        writer.buf.truncate(BLOCK_SIZE);
+        out.extend(rotate_block(&writer.buf, section_split_offset));
+    }
+    out
+}
+
+fn encode_v5(samples: &[u16], bps: u32, pixels_per_packet: usize) -> Vec<u8> {
+    let padding = 128 - u32::try_from(pixels_per_packet).unwrap() * bps;
+    let mut writer = BitStreamBuilder::new();
+    for packet in samples.chunks(pixels_per_packet) {
+        for sample in packet {
+            writer.put(u32::from(*sample), bps);
+        }
+        writer.put(0, padding);
+    }
+    let mut data = writer.finish(BitOrder::LSB);
+    data.resize(data.len().next_multiple_of(BLOCK_SIZE), 0);
+    data.chunks(BLOCK_SIZE)
+        .flat_map(|block| rotate_block(block, SECTION_SPLIT_OFFSET))
+        .collect()
+}
+
+fn dims(
+    width: usize,
+    height: usize,
+) -> Dimensions2D<core::num::NonZero<usize>> {
+    Dimensions2D::new(
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowCount::new(core::num::NonZero::new(height).unwrap()),
+    )
+}
+
+fn output(buf: &mut [u16], width: usize) -> Array2DRefMut<'_, u16> {
+    Array2DRefMut::new(
+        buf,
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowPitch::new(core::num::NonZero::new(width).unwrap()),
+    )
+}
+
+fn decode_v4(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    section_split_offset: usize,
+) -> Result<Vec<u16>, PanasonicError> {
+    let mut buf = vec![0; width * height];
+    PanasonicV4Decompressor::new(
+        data,
+        dims(width, height),
+        section_split_offset,
+    )?
+    .decode(&mut output(&mut buf, width))?;
+    Ok(buf)
+}
+
+fn decode_v5(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    bps: u32,
+) -> Result<Vec<u16>, PanasonicError> {
+    let mut buf = vec![0; width * height];
+    PanasonicV5Decompressor::new(data, dims(width, height), bps)?
+        .decode(&mut output(&mut buf, width))?;
+    Ok(buf)
+}
+
+/// Samples that vary slowly enough for [`put_v4_packet`].
+fn smooth_samples(len: usize) -> Vec<u16> {
+    (0_usize..len)
+        .map(|index| {
+            let sample = 0x800 + (index * 37) % 200 + (index % 2) * 0x400;
+            u16::try_from(sample).unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn unrotate_block_test() {
+    let block: Vec<u8> = (0..8).collect();
+    assert_eq!(unrotate_block(&block, 3), vec![3, 4, 5, 6, 7, 0, 1, 2]);
+    assert_eq!(unrotate_block(&block, 0), block);
+    assert_eq!(unrotate_block(&rotate_block(&block, 5), 5), block);
+}
+
+#[test]
+fn v4_round_trip_test() {
+    let samples = smooth_samples(28 * 3);
+    for section_split_offset in [0, SECTION_SPLIT_OFFSET] {
+        let data = encode_v4(&samples, section_split_offset);
+        assert_eq!(
+            decode_v4(&data, 28, 3, section_split_offset),
+            Ok(samples.clone())
+        );
+    }
+}
+
+#[test]
+fn v4_multiple_blocks_test() {
+    // More packets than fit into one block.
+    let samples = smooth_samples(14 * 1100);
+    let data = encode_v4(&samples, SECTION_SPLIT_OFFSET);
+    assert_eq!(data.len(), 2 * BLOCK_SIZE);
+    assert_eq!(
+        decode_v4(&data, 14 * 100, 11, SECTION_SPLIT_OFFSET),
+        Ok(samples)
+    );
+}
+
+#[test]
+fn v4_shift_test() {
+    let mut writer = V4Writer::new();
+    // The first two samples are stored as is.
+    writer.put(0x10, 8);
+    writer.put(0x1, 4);
+    writer.put(0x50, 8);
+    writer.put(0x2, 4);
+    // A shift of 2, where the scaled bias exceeds the previous sample, so
+    // only its low bits are kept: (0x101 & 0x3) + (0x90 << 2).
+    writer.put(2, 2);
+    writer.put(0x90, 8);
+    // An unchanged sample.
+    writer.put(0, 8);
+    // 0x241 - (0x80 << 2) + (0x81 << 2).
+    writer.put(0x81, 8);
+    // A shift of 4 always keeps just the low bits.
+    for pixel in 5_usize..14 {
+        if pixel % 3 == 2 {
+            writer.put(3, 2);
+        }
+        writer.put(0x10, 8);
+    }
+    writer.buf.truncate(BLOCK_SIZE);
+    let data = rotate_block(&writer.buf, SECTION_SPLIT_OFFSET);
+    let samples = decode_v4(&data, 14, 1, SECTION_SPLIT_OFFSET).unwrap();
+    assert_eq!(
+        samples.get(..6),
+        Some([0x101, 0x502, 0x241, 0x502, 0x245, 0x102].as_slice())
+    );
+}
+
+#[test]
+fn v5_round_trip_test() {
+    for (bps, pixels_per_packet) in [(12, 10), (14, 9)] {
+        let width = pixels_per_packet * 4;
+        let samples: Vec<u16> = (0_usize..width * 5)
+            .map(|index| u16::try_from((index * 2711) % (1 << bps)).unwrap())
+            .collect();
+        let data = encode_v5(&samples, bps, pixels_per_packet);
+        assert_eq!(decode_v5(&data, width, 5, bps), Ok(samples));
+    }
+}
+
+#[test]
+fn v5_multiple_blocks_test() {
+    let samples: Vec<u16> = (0_usize..10 * 1500)
+        .map(|index| u16::try_from(index % 4096).unwrap())
+        .collect();
+    let data = encode_v5(&samples, 12, 10);
+    assert_eq!(data.len(), 2 * BLOCK_SIZE);
+    assert_eq!(decode_v5(&data, 500, 30, 12), Ok(samples));
+}
+
+#[test]
+fn invalid_width_test() {
+    assert_eq!(
+        PanasonicV4Decompressor::new(&[0; BLOCK_SIZE], dims(15, 1), 0)
+            .map(|_| ()),
+        Err(PanasonicError::InvalidWidth)
+    );
+    assert_eq!(
+        PanasonicV5Decompressor::new(&[0; BLOCK_SIZE], dims(9, 1), 12)
+            .map(|_| ()),
+        Err(PanasonicError::InvalidWidth)
+    );
+}
+
+#[test]
+fn invalid_parameters_test() {
+    assert_eq!(
+        PanasonicV4Decompressor::new(
+            &[0; BLOCK_SIZE],
+            dims(14, 1),
+            BLOCK_SIZE + 1
+        )
+        .map(|_| ()),
+        Err(PanasonicError::InvalidSectionSplitOffset)
+    );
+    assert_eq!(
+        PanasonicV5Decompressor::new(&[0; BLOCK_SIZE], dims(10, 1), 10)
+            .map(|_| ()),
+        Err(PanasonicError::UnsupportedBitsPerSample)
+    );
+}
+
+#[test]
+fn truncated_test() {
+    // Without swapped sections, only the packets themselves are needed.
+    assert_eq!(
+        decode_v4(&[0; 31], 14, 2, 0),
+        Err(PanasonicError::TruncatedData)
+    );
+    assert_eq!(decode_v4(&[0; 32], 14, 2, 0).map(|_| ()), Ok(()));
+    // Otherwise, the whole block is.
+    assert_eq!(
+        decode_v4(&[0; 32], 14, 2, SECTION_SPLIT_OFFSET),
+        Err(PanasonicError::TruncatedData)
+    );
+    assert_eq!(
+        decode_v5(&[0; BLOCK_SIZE - 1], 10, 1, 12),
+        Err(PanasonicError::TruncatedData)
+    );
+}
+
+#[test]
+fn output_dimensions_mismatch_test() {
+    let mut buf = vec![0; 28];
+    assert_eq!(
+        PanasonicV4Decompressor::new(&[0; BLOCK_SIZE], dims(14, 2), 0)
+            .unwrap()
+            .decode(&mut output(&mut buf, 28)),
+        Err(PanasonicError::OutputDimensionsMismatch)
+    );
+    assert_eq!(
+        PanasonicV5Decompressor::new(&[0; BLOCK_SIZE], dims(10, 2), 12)
+            .unwrap()
+            .decode(&mut output(&mut buf, 28)),
+        Err(PanasonicError::OutputDimensionsMismatch)
+    );
+}
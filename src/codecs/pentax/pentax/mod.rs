@@ -0,0 +1,222 @@
+use rawspeed_bitstream_bitstream_decoder::bitstreamer::BitStreamerBase;
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrderMSB;
+use rawspeed_bitstream_bytestream_decoder::bytestreamer::ByteStreamer;
+use rawspeed_codecs_huffman::huffman::{
+    HuffmanCode, HuffmanError, HuffmanTable,
+};
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_std::coord_common::{Dimensions2D, RowIndex};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+
+/// The code length histogram and symbols of the code used by the cameras
+/// that do not store their own.
+const LEGACY_COUNTS: [u8; 16] =
+    [0, 2, 3, 1, 1, 1, 1, 1, 1, 2, 0, 0, 0, 0, 0, 0];
+const LEGACY_SYMBOLS: [u8; 13] = [3, 4, 2, 5, 1, 6, 0, 7, 8, 9, 10, 11, 12];
+
+/// The stored number of codes is offset by this much.
+const DEPTH_BIAS: usize = 12;
+
+/// The largest number of codes, i.e. of difference lengths.
+const MAX_DEPTH: usize = 15;
+
+/// The bytes of unknown purpose between the number of codes and the codes.
+const TABLE_HEADER_LEN: usize = 12;
+
+/// The codes are stored left-aligned in this many bits.
+const MAX_CODE_LEN: u32 = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PentaxError {
+    UnexpectedEndOfInput,
+    InvalidHuffmanTable,
+    OddWidth,
+    InvalidHuffmanCode,
+    ValueOutOfBounds,
+    TruncatedData,
+    OutputDimensionsMismatch,
+}
+
+impl core::fmt::Display for PentaxError {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            PentaxError::UnexpectedEndOfInput => {
+                write!(f, "PentaxError(UnexpectedEndOfInput)")
+            }
+            PentaxError::InvalidHuffmanTable => {
+                write!(f, "PentaxError(InvalidHuffmanTable)")
+            }
+            PentaxError::OddWidth => write!(f, "PentaxError(OddWidth)"),
+            PentaxError::InvalidHuffmanCode => {
+                write!(f, "PentaxError(InvalidHuffmanCode)")
+            }
+            PentaxError::ValueOutOfBounds => {
+                write!(f, "PentaxError(ValueOutOfBounds)")
+            }
+            PentaxError::TruncatedData => {
+                write!(f, "PentaxError(TruncatedData)")
+            }
+            PentaxError::OutputDimensionsMismatch => {
+                write!(f, "PentaxError(OutputDimensionsMismatch)")
+            }
+        }
+    }
+}
+
+impl From<HuffmanError> for PentaxError {
+    #[inline]
+    fn from(err: HuffmanError) -> Self {
+        if err == HuffmanError::EndOfStream {
+            PentaxError::TruncatedData
+        } else {
+            PentaxError::InvalidHuffmanCode
+        }
+    }
+}
+
+/// The code of the cameras without a `MakerNote` Huffman table.
+#[inline]
+#[must_use]
+pub fn legacy_code() -> HuffmanCode {
+    HuffmanCode::new(&LEGACY_COUNTS, &LEGACY_SYMBOLS).unwrap()
+}
+
+/// Reader over the (`MakerNote`-endian) Huffman table.
+#[derive(Debug, Clone, Copy)]
+struct TableReader<'a> {
+    input: &'a [u8],
+    pos: usize,
+    endianness: Endianness,
+}
+
+impl<'a> TableReader<'a> {
+    fn get_bytes(&mut self, len: usize) -> Result<&'a [u8], PentaxError> {
+        let bytes = self
+            .input
+            .get(self.pos..)
+            .and_then(|rest| rest.get(..len))
+            .ok_or(PentaxError::UnexpectedEndOfInput)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, PentaxError> {
+        Ok(*self.get_bytes(1)?.first().unwrap())
+    }
+
+    fn read_u16(&mut self) -> Result<u16, PentaxError> {
+        let bytes = self.get_bytes(size_of::<u16>())?;
+        Ok(ByteStreamer::new(bytes, self.endianness).read())
+    }
+}
+
+/// Parses the Huffman table (`MakerNote` tag `0x220`): the number of
+/// codes, then the (left-aligned) codes, then their lengths. The symbol
+/// of each code is its index, and the codes are canonical, so ordering
+/// the symbols by their code is enough to describe them.
+#[inline]
+pub fn parse_code(
+    table: &[u8],
+    endianness: Endianness,
+) -> Result<HuffmanCode, PentaxError> {
+    let mut reader = TableReader {
+        input: table,
+        pos: 0,
+        endianness,
+    };
+    let depth = usize::from(reader.read_u16()?) + DEPTH_BIAS;
+    if depth > MAX_DEPTH {
+        return Err(PentaxError::InvalidHuffmanTable);
+    }
+    reader.get_bytes(TABLE_HEADER_LEN)?;
+    let codes = core::iter::repeat_with(|| reader.read_u16())
+        .take(depth)
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut counts = [0; 16];
+    let mut keys = Vec::with_capacity(depth);
+    for code in codes {
+        let len = u32::from(reader.read_u8()?);
+        if !(1..=MAX_CODE_LEN).contains(&len) {
+            return Err(PentaxError::InvalidHuffmanTable);
+        }
+        *counts.get_mut(usize::try_from(len - 1).unwrap()).unwrap() += 1;
+        keys.push(u32::from(code) >> (MAX_CODE_LEN - len));
+    }
+    // Of equal codes, the last one is picked first.
+    let mut symbols = Vec::with_capacity(depth);
+    for _ in 0..depth {
+        let (symbol, key) = keys
+            .iter_mut()
+            .enumerate()
+            .rev()
+            .min_by_key(|(_, key)| **key)
+            .unwrap();
+        symbols.push(u8::try_from(symbol).unwrap());
+        *key = u32::MAX;
+    }
+    HuffmanCode::new(&counts, &symbols)
+        .map_err(|_err| PentaxError::InvalidHuffmanTable)
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct PentaxDecompressor<'a> {
+    input: &'a [u8],
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    code: HuffmanCode,
+}
+
+impl<'a> PentaxDecompressor<'a> {
+    #[inline]
+    pub fn new(
+        input: &'a [u8],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        code: HuffmanCode,
+    ) -> Result<Self, PentaxError> {
+        if !dims.row_len().get().is_multiple_of(2) {
+            return Err(PentaxError::OddWidth);
+        }
+        Ok(Self { input, dims, code })
+    }
+
+    /// Each sample is predicted from the previous one of the same color,
+    /// the first two of each row from those two rows above (or zero).
+    #[inline(never)]
+    pub fn decode(
+        &self,
+        output: &mut Array2DRefMut<'_, u16>,
+    ) -> Result<(), PentaxError> {
+        if output.dims() != self.dims {
+            return Err(PentaxError::OutputDimensionsMismatch);
+        }
+        let mut bs = BitStreamerBase::<BitOrderMSB>::try_from(self.input)
+            .map_err(|_err| PentaxError::TruncatedData)?;
+        let table =
+            HuffmanTable::new(&self.code, HuffmanTable::DEFAULT_LUT_BITS)
+                .map_err(|_err| PentaxError::InvalidHuffmanTable)?;
+
+        for row in 0..self.dims.row_count().get() {
+            let mut preds = [0; 2];
+            if let Some(above) = row.checked_sub(2) {
+                let above = output.get_row_mut(RowIndex::new(above)).unwrap();
+                for (pred, sample) in preds.iter_mut().zip(above) {
+                    *pred = i32::from(*sample);
+                }
+            }
+            let out = output.get_row_mut(RowIndex::new(row)).unwrap();
+            for (col, sample) in out.iter_mut().enumerate() {
+                let pred = preds.get_mut(col % 2).unwrap();
+                *pred += table.decode_difference(&mut bs)?;
+                *sample = u16::try_from(*pred)
+                    .map_err(|_err| PentaxError::ValueOutOfBounds)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,245 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_codecs_huffman::huffman::HuffmanCode;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_std::coord_common::{Dimensions2D, RowCount, RowLength, RowPitch};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+
+use super::{PentaxDecompressor, PentaxError, legacy_code, parse_code};
+
+/// Encodes the differences JPEG-style: the length of the difference,
+/// followed by its bits, negative ones being offset by one.
+fn encode(code: &HuffmanCode, diffs: &[i32]) -> Vec<u8> {
+    let codes = code.codes();
+    let mut bits = BitStreamBuilder::new();
+    for diff in diffs {
+        let len = 32 - diff.unsigned_abs().leading_zeros();
+        let index = code
+            .symbols()
+            .iter()
+            .position(|s| u32::from(*s) == len)
+            .unwrap();
+        let (code_len, bits_of_code) = *codes.get(index).unwrap();
+        bits.put(bits_of_code, code_len);
+        let value = if *diff < 0 {
+            *diff + (1 << len) - 1
+        } else {
+            *diff
+        };
+        bits.put(u32::try_from(value).unwrap(), len);
+    }
+    bits.finish(BitOrder::MSB)
+}
+
+/// The differences that produce the given samples.
+fn differences(samples: &[Vec<i32>]) -> Vec<i32> {
+    let mut diffs = vec![];
+    for (row, values) in samples.iter().enumerate() {
+        for (col, value) in values.iter().enumerate() {
+            let pred = match (col.checked_sub(2), row.checked_sub(2)) {
+                (Some(col), _) => *values.get(col).unwrap(),
+                (None, Some(row)) => {
+                    *samples.get(row).unwrap().get(col).unwrap()
+                }
+                (None, None) => 0,
+            };
+            diffs.push(value - pred);
+        }
+    }
+    diffs
+}
+
+/// The Huffman table of the `MakerNote` that describes the code.
+fn build_table(code: &HuffmanCode, endianness: Endianness) -> Vec<u8> {
+    let u16_bytes = |val: u16| {
+        let [hi, lo] = [val >> 8, val & 0xFF].map(|b| u8::try_from(b).unwrap());
+        match endianness {
+            Endianness::Little => [lo, hi],
+            Endianness::Big => [hi, lo],
+        }
+    };
+    let mut codes = code
+        .codes()
+        .into_iter()
+        .zip(code.symbols())
+        .map(|((len, bits), symbol)| (*symbol, len, bits))
+        .collect::<Vec<_>>();
+    codes.sort_unstable();
+    let mut out = u16_bytes(u16::try_from(codes.len() - 12).unwrap()).to_vec();
+    out.resize(out.len() + 12, 0xFF);
+    for (_, len, bits) in &codes {
+        out.extend(u16_bytes(u16::try_from(bits << (12 - len)).unwrap()));
+    }
+    for (_, len, _) in &codes {
+        out.push(u8::try_from(*len).unwrap());
+    }
+    out
+}
+
+fn dims(
+    width: usize,
+    height: usize,
+) -> Dimensions2D<core::num::NonZero<usize>> {
+    Dimensions2D::new(
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowCount::new(core::num::NonZero::new(height).unwrap()),
+    )
+}
+
+fn decode(
+    data: &[u8],
+    code: &HuffmanCode,
+    width: usize,
+    height: usize,
+) -> Result<Vec<u16>, PentaxError> {
+    let decoder =
+        PentaxDecompressor::new(data, dims(width, height), code.clone())?;
+    let mut buf = vec![0; width * height];
+    let mut output = Array2DRefMut::new(
+        buf.as_mut_slice(),
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowPitch::new(core::num::NonZero::new(width).unwrap()),
+    );
+    decoder.decode(&mut output)?;
+    Ok(buf)
+}
+
+fn test_samples() -> Vec<Vec<i32>> {
+    (0_i32..4)
+        .map(|row| {
+            (0_i32..6)
+                .map(|col| {
+                    (row * 977 + col * 313 + (col & 1) * 50).rem_euclid(0x1000)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn expected(samples: &[Vec<i32>]) -> Vec<u16> {
+    samples
+        .iter()
+        .flatten()
+        .map(|sample| u16::try_from(*sample).unwrap())
+        .collect()
+}
+
+#[test]
+fn legacy_round_trip_test() {
+    let samples = test_samples();
+    let data = encode(&legacy_code(), &differences(&samples));
+    assert_eq!(decode(&data, &legacy_code(), 6, 4), Ok(expected(&samples)));
+}
+
+#[test]
+fn parse_code_test() {
+    for endianness in [Endianness::Little, Endianness::Big] {
+        let table = build_table(&legacy_code(), endianness);
+        assert_eq!(parse_code(&table, endianness), Ok(legacy_code()));
+    }
+}
+
+#[test]
+fn parsed_code_round_trip_test() {
+    // Longer codes for the short differences.
+    let code = HuffmanCode::new(
+        &[0, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0],
+        &[10, 11, 8, 9, 6, 7, 4, 5, 2, 3, 1, 0, 12, 13, 14],
+    )
+    .unwrap();
+    let table = build_table(&code, Endianness::Big);
+    let parsed = parse_code(&table, Endianness::Big).unwrap();
+    assert_eq!(parsed, code);
+    let samples = test_samples();
+    let data = encode(&parsed, &differences(&samples));
+    assert_eq!(decode(&data, &parsed, 6, 4), Ok(expected(&samples)));
+}
+
+#[test]
+fn parse_code_errors_test() {
+    let table = build_table(&legacy_code(), Endianness::Little);
+    let mut deep = table.clone();
+    *deep.first_mut().unwrap() = 4;
+    assert_eq!(
+        parse_code(&deep, Endianness::Little),
+        Err(PentaxError::InvalidHuffmanTable)
+    );
+    let mut zero_len = table.clone();
+    *zero_len.last_mut().unwrap() = 0;
+    assert_eq!(
+        parse_code(&zero_len, Endianness::Little),
+        Err(PentaxError::InvalidHuffmanTable)
+    );
+    let mut too_long = table.clone();
+    *too_long.last_mut().unwrap() = 13;
+    assert_eq!(
+        parse_code(&too_long, Endianness::Little),
+        Err(PentaxError::InvalidHuffmanTable)
+    );
+    // All the lengths are one.
+    let mut over_subscribed = table.clone();
+    let lengths = over_subscribed.len() - 13;
+    over_subscribed.get_mut(lengths..).unwrap().fill(1);
+    assert_eq!(
+        parse_code(&over_subscribed, Endianness::Little),
+        Err(PentaxError::InvalidHuffmanTable)
+    );
+    assert_eq!(
+        parse_code(table.get(..table.len() - 1).unwrap(), Endianness::Little),
+        Err(PentaxError::UnexpectedEndOfInput)
+    );
+}
+
+#[test]
+fn odd_width_test() {
+    assert_eq!(
+        PentaxDecompressor::new(&[], dims(5, 2), legacy_code()).unwrap_err(),
+        PentaxError::OddWidth
+    );
+}
+
+#[test]
+fn value_out_of_bounds_test() {
+    let data = encode(&legacy_code(), &[-1, 0]);
+    assert_eq!(
+        decode(&data, &legacy_code(), 2, 1),
+        Err(PentaxError::ValueOutOfBounds)
+    );
+    // The predictions are not clamped.
+    let large = encode(&legacy_code(), &[0xFFF, 0, 0xFFF, 0]);
+    assert_eq!(
+        decode(&large, &legacy_code(), 4, 1),
+        Ok(vec![0xFFF, 0, 0x1FFE, 0])
+    );
+}
+
+#[test]
+fn truncated_test() {
+    // Past the end, the (zero) bits decode to zero differences.
+    let code = HuffmanCode::new(
+        &[0, 2, 3, 1, 1, 1, 1, 1, 1, 2, 0, 0, 0, 0, 0, 0],
+        &[0, 3, 4, 2, 5, 1, 6, 7, 8, 9, 10, 11, 12],
+    )
+    .unwrap();
+    assert_eq!(decode(&[0; 4], &code, 16, 1), Ok(vec![0; 16]));
+    assert_eq!(
+        decode(&[0; 4], &code, 64, 64),
+        Err(PentaxError::TruncatedData)
+    );
+}
+
+#[test]
+fn output_dimensions_mismatch_test() {
+    let decoder =
+        PentaxDecompressor::new(&[0; 4], dims(2, 2), legacy_code()).unwrap();
+    let mut buf = vec![0; 4];
+    let mut output = Array2DRefMut::new(
+        buf.as_mut_slice(),
+        RowLength::new(core::num::NonZero::new(4).unwrap()),
+        RowPitch::new(core::num::NonZero::new(4).unwrap()),
+    );
+    assert_eq!(
+        decoder.decode(&mut output),
+        Err(PentaxError::OutputDimensionsMismatch)
+    );
+}
@@ -0,0 +1,183 @@
+use rawspeed_bitstream_bitstream_decoder::bitstreamer::{
+    BitStream as _, BitStreamerBase,
+};
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrderMSB32;
+use rawspeed_std::coord_common::{ColIndex, Coord2D, Dimensions2D, RowIndex};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+
+/// The difference lengths are updated once every this many pixels.
+const BLOCK_LEN: usize = 8;
+
+/// The difference lengths, indexed by their (unary) code and one more bit.
+const DIFF_LENS: [u32; 10] = [8, 7, 6, 9, 11, 10, 5, 12, 14, 13];
+
+/// The longest unary code of a difference length.
+const MAX_LEN_CODE: u32 = 5;
+
+/// The pixels of this "length" are stored verbatim, in this many bits.
+const VERBATIM_LEN: u32 = 14;
+const VERBATIM_BITS: u32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PhaseOneError {
+    InvalidRowOffsets,
+    InvalidBitLength,
+    InvalidSample,
+    TruncatedData,
+    OutputDimensionsMismatch,
+}
+
+impl core::fmt::Display for PhaseOneError {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            PhaseOneError::InvalidRowOffsets => {
+                write!(f, "PhaseOneError(InvalidRowOffsets)")
+            }
+            PhaseOneError::InvalidBitLength => {
+                write!(f, "PhaseOneError(InvalidBitLength)")
+            }
+            PhaseOneError::InvalidSample => {
+                write!(f, "PhaseOneError(InvalidSample)")
+            }
+            PhaseOneError::TruncatedData => {
+                write!(f, "PhaseOneError(TruncatedData)")
+            }
+            PhaseOneError::OutputDimensionsMismatch => {
+                write!(f, "PhaseOneError(OutputDimensionsMismatch)")
+            }
+        }
+    }
+}
+
+fn get_bits(
+    bs: &mut BitStreamerBase<'_, BitOrderMSB32>,
+    nbits: u32,
+) -> Result<u32, PhaseOneError> {
+    bs.fill(nbits)
+        .map_err(|_err| PhaseOneError::TruncatedData)?;
+    let bits = bs.peek_bits_no_fill(nbits).zext();
+    bs.skip_bits_no_fill(nbits);
+    Ok(u32::try_from(bits).unwrap())
+}
+
+/// A difference length is coded as up to five zero bits, terminated by a
+/// one bit, then one more bit, unless the length is kept. The longest
+/// code is not terminated.
+fn update_len(
+    bs: &mut BitStreamerBase<'_, BitOrderMSB32>,
+    len: Option<u32>,
+) -> Result<Option<u32>, PhaseOneError> {
+    let mut code = 0;
+    while code < MAX_LEN_CODE && get_bits(bs, 1)? == 0 {
+        code += 1;
+    }
+    match code.checked_sub(1) {
+        None => Ok(len),
+        Some(code) => {
+            let index = usize::try_from(2 * code + get_bits(bs, 1)?).unwrap();
+            Ok(Some(*DIFF_LENS.get(index).unwrap()))
+        }
+    }
+}
+
+/// The even and odd pixels of a row are each predicted from the previous
+/// one of the same parity, starting from zero. The pixels past the last
+/// whole block are stored verbatim.
+fn decode_row(
+    bs: &mut BitStreamerBase<'_, BitOrderMSB32>,
+    output: &mut Array2DRefMut<'_, u16>,
+    row: usize,
+) -> Result<(), PhaseOneError> {
+    let width = output.row_length().get();
+    let blocks_end = width - width % BLOCK_LEN;
+    let mut lens = [None; 2];
+    let mut preds = [0_i32; 2];
+    for col in 0..width {
+        if col.is_multiple_of(BLOCK_LEN) {
+            if col == blocks_end {
+                lens = [Some(VERBATIM_LEN); 2];
+            } else {
+                for len in &mut lens {
+                    *len = update_len(bs, *len)?;
+                }
+            }
+        }
+        let parity = col % 2;
+        let len = lens
+            .get(parity)
+            .copied()
+            .flatten()
+            .ok_or(PhaseOneError::InvalidBitLength)?;
+        let pred = preds.get_mut(parity).unwrap();
+        if len == VERBATIM_LEN {
+            *pred = i32::try_from(get_bits(bs, VERBATIM_BITS)?).unwrap();
+        } else {
+            let diff = i32::try_from(get_bits(bs, len)?).unwrap();
+            *pred += diff + 1 - (1 << (len - 1));
+        }
+        output[Coord2D::new(RowIndex::new(row), ColIndex::new(col))] =
+            u16::try_from(*pred)
+                .map_err(|_err| PhaseOneError::InvalidSample)?;
+    }
+    Ok(())
+}
+
+/// The IIQ L (and IIQ S) compression: each row is coded separately, as a
+/// bitstream of 32-bit little-endian chunks, most significant bit first.
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct IiqDecompressor<'a> {
+    input: &'a [u8],
+    row_offsets: Vec<usize>,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+}
+
+impl<'a> IiqDecompressor<'a> {
+    /// Each row starts at its offset into `input`.
+    #[inline]
+    pub fn new(
+        input: &'a [u8],
+        row_offsets: &[usize],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+    ) -> Result<Self, PhaseOneError> {
+        if row_offsets.len() != dims.row_count().get()
+            || row_offsets.iter().any(|offset| *offset >= input.len())
+        {
+            return Err(PhaseOneError::InvalidRowOffsets);
+        }
+        Ok(Self {
+            input,
+            row_offsets: row_offsets.to_vec(),
+            dims,
+        })
+    }
+
+    #[inline(never)]
+    pub fn decode(
+        &self,
+        output: &mut Array2DRefMut<'_, u16>,
+    ) -> Result<(), PhaseOneError> {
+        if output.dims() != self.dims {
+            return Err(PhaseOneError::OutputDimensionsMismatch);
+        }
+        // The bitstreams are consumed in 32-bit chunks, and a row may
+        // start anywhere.
+        let mut data = self.input.to_vec();
+        data.resize(data.len() + size_of::<u32>(), 0);
+        for (row, offset) in self.row_offsets.iter().enumerate() {
+            let len =
+                (self.input.len() - offset).next_multiple_of(size_of::<u32>());
+            let input = data.get(*offset..offset + len).unwrap();
+            let mut bs = BitStreamerBase::<BitOrderMSB32>::try_from(input)
+                .map_err(|_err| PhaseOneError::TruncatedData)?;
+            decode_row(&mut bs, output, row)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,241 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_std::coord_common::{Dimensions2D, RowCount, RowLength, RowPitch};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+
+use super::{
+    BLOCK_LEN, DIFF_LENS, IiqDecompressor, MAX_LEN_CODE, PhaseOneError,
+    VERBATIM_BITS, VERBATIM_LEN,
+};
+
+fn dims(
+    width: usize,
+    height: usize,
+) -> Dimensions2D<core::num::NonZero<usize>> {
+    Dimensions2D::new(
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowCount::new(core::num::NonZero::new(height).unwrap()),
+    )
+}
+
+/// Mostly smooth, with a block of noise every third block.
+fn test_image(width: usize, height: usize) -> Vec<Vec<u16>> {
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| {
+                    let sample = if (col / BLOCK_LEN) % 3 == 2 {
+                        (row * 977 + col * 313 + 5) % 0x1_0000
+                    } else {
+                        8000 + (row * 3 + col * 7) % 40
+                    };
+                    u16::try_from(sample).unwrap()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Whether the difference can be coded in `len` bits.
+fn fits(diff: i32, len: u32) -> bool {
+    let half = 1 << (len - 1);
+    (1 - half..=half).contains(&diff)
+}
+
+/// The shortest length that fits all the differences of a block.
+fn block_len(diffs: &[i32]) -> u32 {
+    let mut lens = DIFF_LENS.to_vec();
+    lens.sort_unstable();
+    lens.into_iter()
+        .filter(|len| *len != VERBATIM_LEN)
+        .find(|len| diffs.iter().all(|diff| fits(*diff, *len)))
+        .unwrap_or(VERBATIM_LEN)
+}
+
+fn put_len(bits: &mut BitStreamBuilder, len: u32, prev: Option<u32>) {
+    if prev == Some(len) {
+        bits.put(1, 1);
+        return;
+    }
+    let index = DIFF_LENS.iter().position(|l| *l == len).unwrap();
+    let code = u32::try_from(index / 2 + 1).unwrap();
+    bits.put(0, code);
+    if code < MAX_LEN_CODE {
+        bits.put(1, 1);
+    }
+    bits.put(u32::try_from(index % 2).unwrap(), 1);
+}
+
+fn put_pixel(
+    bits: &mut BitStreamBuilder,
+    len: u32,
+    pred: &mut i32,
+    value: u16,
+) {
+    if len == VERBATIM_LEN {
+        bits.put(u32::from(value), VERBATIM_BITS);
+    } else {
+        let diff = i32::from(value) - *pred;
+        bits.put(u32::try_from(diff - 1 + (1 << (len - 1))).unwrap(), len);
+    }
+    *pred = i32::from(value);
+}
+
+fn encode_row(row: &[u16]) -> Vec<u8> {
+    let mut bits = BitStreamBuilder::new();
+    let blocks_end = row.len() - row.len() % BLOCK_LEN;
+    let mut lens = [None; 2];
+    let mut preds = [0; 2];
+    for (start, block) in row.chunks(BLOCK_LEN).enumerate() {
+        let start = start * BLOCK_LEN;
+        if start < blocks_end {
+            for (parity, len) in lens.iter_mut().enumerate() {
+                let mut pred = *preds.get(parity).unwrap();
+                let diffs = block
+                    .iter()
+                    .skip(parity)
+                    .step_by(2)
+                    .map(|value| {
+                        let diff = i32::from(*value) - pred;
+                        pred = i32::from(*value);
+                        diff
+                    })
+                    .collect::<Vec<_>>();
+                let new_len = block_len(&diffs);
+                put_len(&mut bits, new_len, *len);
+                *len = Some(new_len);
+            }
+        } else {
+            lens = [Some(VERBATIM_LEN); 2];
+        }
+        for (pos, value) in block.iter().enumerate() {
+            let parity = (start + pos) % 2;
+            let len = lens.get(parity).unwrap().unwrap();
+            put_pixel(&mut bits, len, preds.get_mut(parity).unwrap(), *value);
+        }
+    }
+    bits.finish(BitOrder::MSB32)
+}
+
+fn encode(image: &[Vec<u16>]) -> (Vec<u8>, Vec<usize>) {
+    let mut data = vec![];
+    let mut offsets = vec![];
+    for row in image {
+        offsets.push(data.len());
+        data.extend(encode_row(row));
+    }
+    (data, offsets)
+}
+
+fn decode(
+    input: &[u8],
+    offsets: &[usize],
+    width: usize,
+    height: usize,
+) -> Result<Vec<Vec<u16>>, PhaseOneError> {
+    let decompressor =
+        IiqDecompressor::new(input, offsets, dims(width, height))?;
+    let mut out = vec![0_u16; width * height];
+    let mut output = Array2DRefMut::new(
+        &mut out,
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowPitch::new(core::num::NonZero::new(width).unwrap()),
+    );
+    decompressor.decode(&mut output)?;
+    Ok(out.chunks(width).map(<[u16]>::to_vec).collect())
+}
+
+#[test]
+fn round_trip_test() {
+    for (width, height) in [(48, 4), (37, 3), (5, 2), (8, 1)] {
+        let image = test_image(width, height);
+        let (data, offsets) = encode(&image);
+        assert_eq!(decode(&data, &offsets, width, height), Ok(image));
+    }
+}
+
+#[test]
+fn unaligned_rows_test() {
+    // The rows need not start at a 32-bit boundary.
+    let image = test_image(24, 2);
+    let rows = image.iter().map(|row| encode_row(row)).collect::<Vec<_>>();
+    let mut data = vec![0xFF; 3];
+    let mut offsets = vec![];
+    for row in &rows {
+        offsets.push(data.len());
+        data.extend(row);
+        data.push(0xFF);
+    }
+    assert_eq!(decode(&data, &offsets, 24, 2), Ok(image));
+}
+
+#[test]
+fn shared_rows_test() {
+    let image = test_image(16, 1);
+    let (data, _) = encode(&image);
+    assert_eq!(
+        decode(&data, &[0, 0, 0], 16, 3),
+        Ok(vec![image.concat(); 3])
+    );
+}
+
+#[test]
+fn invalid_row_offsets_test() {
+    for offsets in [&[0][..], &[0, 4, 8], &[0, 8]] {
+        assert_eq!(
+            IiqDecompressor::new(&[0; 8], offsets, dims(8, 2)).unwrap_err(),
+            PhaseOneError::InvalidRowOffsets
+        );
+    }
+}
+
+#[test]
+fn invalid_bit_length_test() {
+    // The first block of a row must set the difference lengths.
+    let mut bits = BitStreamBuilder::new();
+    bits.put(1, 1);
+    assert_eq!(
+        decode(&bits.finish(BitOrder::MSB32), &[0], 8, 1),
+        Err(PhaseOneError::InvalidBitLength)
+    );
+}
+
+#[test]
+fn invalid_sample_test() {
+    // The smallest 5-bit difference, from the initial prediction of zero.
+    let mut bits = BitStreamBuilder::new();
+    for _ in 0..2 {
+        put_len(&mut bits, 5, None);
+    }
+    bits.put(0, 5);
+    assert_eq!(
+        decode(&bits.finish(BitOrder::MSB32), &[0], 8, 1),
+        Err(PhaseOneError::InvalidSample)
+    );
+}
+
+#[test]
+fn truncated_test() {
+    let image = test_image(512, 1);
+    let (mut data, offsets) = encode(&image);
+    data.truncate(8);
+    assert_eq!(
+        decode(&data, &offsets, 512, 1),
+        Err(PhaseOneError::TruncatedData)
+    );
+}
+
+#[test]
+fn output_dimensions_mismatch_test() {
+    let decompressor = IiqDecompressor::new(&[0; 4], &[0], dims(8, 1)).unwrap();
+    let mut out = vec![0_u16; 16];
+    let mut output = Array2DRefMut::new(
+        &mut out,
+        RowLength::new(core::num::NonZero::new(16).unwrap()),
+        RowPitch::new(core::num::NonZero::new(16).unwrap()),
+    );
+    assert_eq!(
+        decompressor.decode(&mut output),
+        Err(PhaseOneError::OutputDimensionsMismatch)
+    );
+}
@@ -0,0 +1,735 @@
+use rawspeed_bitstream_bitstream_decoder::bitstreamer::{
+    BitStream, BitStreamerBase,
+};
+use rawspeed_bitstream_bitstreams::bitstreams::{BitOrderMSB, BitOrderMSB32};
+use rawspeed_common_bitseq::bitseq::BitSeqConstraints;
+use rawspeed_std::coord_common::{ColIndex, Coord2D, Dimensions2D, RowIndex};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+
+/// The V0 and V2 rows are coded in blocks of this many pixels.
+const BLOCK_LEN: usize = 16;
+
+/// The longest V0 difference.
+const V0_MAX_DIFF_LEN: u32 = 16;
+
+/// The prediction of the first block of a V0 row.
+const V0_INITIAL_PRED: i32 = 128;
+
+/// The V1 difference lengths are prefix-coded: the length of each code,
+/// and the length of the difference that follows it, in code order.
+const V1_CODES: [(u32, u32); 14] = [
+    (3, 4),
+    (3, 7),
+    (2, 6),
+    (2, 5),
+    (4, 3),
+    (6, 0),
+    (7, 9),
+    (8, 10),
+    (9, 11),
+    (10, 12),
+    (10, 13),
+    (5, 1),
+    (4, 8),
+    (4, 2),
+];
+
+/// The length of the longest V1 code.
+const V1_LOOKUP_BITS: u32 = 10;
+
+/// The V2 header, and each of the V2 rows, are aligned to this many bytes.
+const V2_ALIGNMENT: usize = 16;
+
+/// The V2 optimization flags: the difference lengths are always coded,
+/// the motions are restricted, and the differences are not scaled.
+const V2_FLAG_SKIP: u32 = 1 << 0;
+const V2_FLAG_MV: u32 = 1 << 1;
+const V2_FLAG_QP: u32 = 1 << 2;
+const V2_FLAGS: u32 = V2_FLAG_SKIP | V2_FLAG_MV | V2_FLAG_QP;
+
+/// The V2 block whose pixels are predicted from the previous block.
+const V2_MOTION_NONE: u32 = 7;
+
+/// The V2 restricted motion, with no offset and no averaging.
+const V2_MOTION_UP: u32 = 3;
+
+/// The other V2 motions: the column offset of the reference pixels, and
+/// whether each is averaged with the next one of the same color.
+const V2_MOTIONS: [(isize, bool); 7] = [
+    (-4, false),
+    (-2, false),
+    (-2, true),
+    (0, false),
+    (0, true),
+    (2, false),
+    (4, false),
+];
+
+/// The scale is updated once every this many pixels.
+const V2_SCALE_PERIOD: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SamsungError {
+    InvalidDimensions,
+    InvalidBitsPerSample,
+    InvalidRowOffsets,
+    InvalidHeader,
+    InvalidBitLength,
+    InvalidPrediction,
+    InvalidSample,
+    TruncatedData,
+    OutputDimensionsMismatch,
+}
+
+impl core::fmt::Display for SamsungError {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SamsungError::InvalidDimensions => {
+                write!(f, "SamsungError(InvalidDimensions)")
+            }
+            SamsungError::InvalidBitsPerSample => {
+                write!(f, "SamsungError(InvalidBitsPerSample)")
+            }
+            SamsungError::InvalidRowOffsets => {
+                write!(f, "SamsungError(InvalidRowOffsets)")
+            }
+            SamsungError::InvalidHeader => {
+                write!(f, "SamsungError(InvalidHeader)")
+            }
+            SamsungError::InvalidBitLength => {
+                write!(f, "SamsungError(InvalidBitLength)")
+            }
+            SamsungError::InvalidPrediction => {
+                write!(f, "SamsungError(InvalidPrediction)")
+            }
+            SamsungError::InvalidSample => {
+                write!(f, "SamsungError(InvalidSample)")
+            }
+            SamsungError::TruncatedData => {
+                write!(f, "SamsungError(TruncatedData)")
+            }
+            SamsungError::OutputDimensionsMismatch => {
+                write!(f, "SamsungError(OutputDimensionsMismatch)")
+            }
+        }
+    }
+}
+
+fn get_bits<B>(bs: &mut B, nbits: u32) -> Result<u32, SamsungError>
+where
+    B: BitStream,
+    B::T: BitSeqConstraints + core::fmt::Debug,
+    u64: From<B::T>,
+{
+    if nbits == 0 {
+        return Ok(0);
+    }
+    bs.fill(nbits).map_err(|_err| SamsungError::TruncatedData)?;
+    let bits = u64::from(bs.peek_bits_no_fill(nbits).zext());
+    bs.skip_bits_no_fill(nbits);
+    Ok(u32::try_from(bits).unwrap())
+}
+
+/// A two's complement difference of `len` bits.
+fn get_signed_diff<B>(bs: &mut B, len: u32) -> Result<i32, SamsungError>
+where
+    B: BitStream,
+    B::T: BitSeqConstraints + core::fmt::Debug,
+    u64: From<B::T>,
+{
+    let diff = i32::try_from(get_bits(bs, len)?).unwrap();
+    if len != 0 && diff >> (len - 1) != 0 {
+        Ok(diff - (1 << len))
+    } else {
+        Ok(diff)
+    }
+}
+
+/// The bitstreams are consumed in 32-bit chunks.
+fn padded(input: &[u8]) -> Vec<u8> {
+    let mut data = input.to_vec();
+    data.resize(data.len().next_multiple_of(size_of::<u32>()), 0);
+    data
+}
+
+fn check_output_dims(
+    output: &Array2DRefMut<'_, u16>,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+) -> Result<(), SamsungError> {
+    if output.dims() == dims {
+        Ok(())
+    } else {
+        Err(SamsungError::OutputDimensionsMismatch)
+    }
+}
+
+fn pixel(output: &Array2DRefMut<'_, u16>, row: usize, col: usize) -> i32 {
+    i32::from(output[Coord2D::new(RowIndex::new(row), ColIndex::new(col))])
+}
+
+fn set_pixel(
+    output: &mut Array2DRefMut<'_, u16>,
+    row: usize,
+    col: usize,
+    val: u16,
+) {
+    output[Coord2D::new(RowIndex::new(row), ColIndex::new(col))] = val;
+}
+
+/// The V0 samples wrap around, as 16-bit integers.
+fn wrap_sample(val: i32) -> u16 {
+    u16::try_from(val & 0xFFFF).unwrap()
+}
+
+/// Applies an update of the length of the differences of a V0 block.
+fn update_v0_len(
+    bs: &mut BitStreamerBase<'_, BitOrderMSB32>,
+    len: u32,
+    op: u32,
+) -> Result<u32, SamsungError> {
+    let len = match op {
+        0 => Some(len),
+        1 => Some(len + 1),
+        2 => len.checked_sub(1),
+        _ => Some(get_bits(bs, 4)?),
+    };
+    len.filter(|len| *len <= V0_MAX_DIFF_LEN)
+        .ok_or(SamsungError::InvalidBitLength)
+}
+
+/// The even pixels of a block are predicted from the row above, and the
+/// odd ones from two rows above.
+fn decode_v0_upward(
+    bs: &mut BitStreamerBase<'_, BitOrderMSB32>,
+    output: &mut Array2DRefMut<'_, u16>,
+    (row, col): (usize, usize),
+    lens: [u32; 4],
+) -> Result<(), SamsungError> {
+    let width = output.row_length().get();
+    if row < 2 || col + BLOCK_LEN >= width {
+        return Err(SamsungError::InvalidPrediction);
+    }
+    for (parity, above) in [(0, row - 1), (1, row - 2)] {
+        for pos in (parity..BLOCK_LEN).step_by(2) {
+            let len = *lens.get(2 * parity + pos / 8).unwrap();
+            let diff = get_signed_diff(bs, len)?;
+            let pred = pixel(output, above, col + pos);
+            set_pixel(output, row, col + pos, wrap_sample(pred + diff));
+        }
+    }
+    Ok(())
+}
+
+/// All the pixels of a block are predicted from the last one of the same
+/// parity in the previous block.
+fn decode_v0_left(
+    bs: &mut BitStreamerBase<'_, BitOrderMSB32>,
+    output: &mut Array2DRefMut<'_, u16>,
+    (row, col): (usize, usize),
+    lens: [u32; 4],
+) -> Result<(), SamsungError> {
+    let width = output.row_length().get();
+    for parity in [0, 1] {
+        let pred = match col.checked_sub(2 - parity) {
+            Some(left) => pixel(output, row, left),
+            None => V0_INITIAL_PRED,
+        };
+        for pos in (parity..BLOCK_LEN).step_by(2) {
+            let len = *lens.get(2 * parity + pos / 8).unwrap();
+            let diff = get_signed_diff(bs, len)?;
+            if col + pos < width {
+                set_pixel(output, row, col + pos, wrap_sample(pred + diff));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Each block starts with its direction of prediction, and the updates
+/// of the lengths of the differences of each half of its even and odd
+/// pixels.
+fn decode_v0_row(
+    bs: &mut BitStreamerBase<'_, BitOrderMSB32>,
+    output: &mut Array2DRefMut<'_, u16>,
+    row: usize,
+) -> Result<(), SamsungError> {
+    let mut lens = [if row < 2 { 7 } else { 4 }; 4];
+    for col in (0..output.row_length().get()).step_by(BLOCK_LEN) {
+        let upward = get_bits(bs, 1)? != 0;
+        let mut ops = [0; 4];
+        for op in &mut ops {
+            *op = get_bits(bs, 2)?;
+        }
+        for (len, op) in lens.iter_mut().zip(ops) {
+            *len = update_v0_len(bs, *len, op)?;
+        }
+        if upward {
+            decode_v0_upward(bs, output, (row, col), lens)?;
+        } else {
+            decode_v0_left(bs, output, (row, col), lens)?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+#[must_use]
+pub struct SamsungV0Decompressor<'a> {
+    rows: Vec<&'a [u8]>,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+}
+
+impl<'a> SamsungV0Decompressor<'a> {
+    /// Each row starts at its offset into `input`, and ends where the next
+    /// one starts.
+    #[inline]
+    pub fn new(
+        input: &'a [u8],
+        row_offsets: &[usize],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+    ) -> Result<Self, SamsungError> {
+        if dims.row_len().get() < BLOCK_LEN {
+            return Err(SamsungError::InvalidDimensions);
+        }
+        if row_offsets.len() != dims.row_count().get() {
+            return Err(SamsungError::InvalidRowOffsets);
+        }
+        let ends = row_offsets.iter().skip(1).copied().chain([input.len()]);
+        let rows = row_offsets
+            .iter()
+            .zip(ends)
+            .map(|(start, end)| {
+                (*start < end)
+                    .then(|| input.get(*start..end))
+                    .flatten()
+                    .ok_or(SamsungError::InvalidRowOffsets)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { rows, dims })
+    }
+
+    /// The samples of each 2x2 block are decoded with the top right one
+    /// and the bottom left one swapped.
+    fn swap_samples(output: &mut Array2DRefMut<'_, u16>) {
+        let (width, height) =
+            (output.row_length().get(), output.num_rows().get());
+        for row in (0..height - 1).step_by(2) {
+            for col in (0..width - 1).step_by(2) {
+                let top_right = pixel(output, row, col + 1);
+                let bottom_left = pixel(output, row + 1, col);
+                set_pixel(output, row, col + 1, wrap_sample(bottom_left));
+                set_pixel(output, row + 1, col, wrap_sample(top_right));
+            }
+        }
+    }
+
+    #[inline(never)]
+    pub fn decode(
+        &self,
+        output: &mut Array2DRefMut<'_, u16>,
+    ) -> Result<(), SamsungError> {
+        check_output_dims(output, self.dims)?;
+        for (row, input) in self.rows.iter().enumerate() {
+            let data = padded(input);
+            let mut bs =
+                BitStreamerBase::<BitOrderMSB32>::try_from(data.as_slice())
+                    .map_err(|_err| SamsungError::TruncatedData)?;
+            decode_v0_row(&mut bs, output, row)?;
+        }
+        Self::swap_samples(output);
+        Ok(())
+    }
+}
+
+/// Maps each value of the next `V1_LOOKUP_BITS` bits to the length of its
+/// code and that of the difference.
+fn v1_lookup_table() -> Vec<(u32, u32)> {
+    V1_CODES
+        .iter()
+        .flat_map(|(code_len, diff_len)| {
+            core::iter::repeat_n(
+                (*code_len, *diff_len),
+                1 << (V1_LOOKUP_BITS - code_len),
+            )
+        })
+        .collect()
+}
+
+/// A difference whose first bit is clear is negative.
+fn decode_v1_diff(
+    bs: &mut BitStreamerBase<'_, BitOrderMSB>,
+    table: &[(u32, u32)],
+) -> Result<i32, SamsungError> {
+    bs.fill(V1_LOOKUP_BITS)
+        .map_err(|_err| SamsungError::TruncatedData)?;
+    let index = bs.peek_bits_no_fill(V1_LOOKUP_BITS).zext();
+    let (code_len, len) = *table.get(usize::try_from(index).unwrap()).unwrap();
+    bs.skip_bits_no_fill(code_len);
+    if len == 0 {
+        return Ok(0);
+    }
+    let diff = i32::try_from(get_bits(bs, len)?).unwrap();
+    if diff & (1 << (len - 1)) == 0 {
+        Ok(diff - (1 << len) + 1)
+    } else {
+        Ok(diff)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+#[must_use]
+pub struct SamsungV1Decompressor<'a> {
+    input: &'a [u8],
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    bits: u32,
+}
+
+impl<'a> SamsungV1Decompressor<'a> {
+    #[inline]
+    pub fn new(
+        input: &'a [u8],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        bits: u32,
+    ) -> Result<Self, SamsungError> {
+        if !dims.row_len().get().is_multiple_of(2) {
+            return Err(SamsungError::InvalidDimensions);
+        }
+        if !matches!(bits, 12 | 14) {
+            return Err(SamsungError::InvalidBitsPerSample);
+        }
+        Ok(Self { input, dims, bits })
+    }
+
+    /// Each sample is a difference to the previous one of the same color
+    /// in its row, the first two of which are predicted from two rows
+    /// above.
+    #[inline(never)]
+    pub fn decode(
+        &self,
+        output: &mut Array2DRefMut<'_, u16>,
+    ) -> Result<(), SamsungError> {
+        check_output_dims(output, self.dims)?;
+        let table = v1_lookup_table();
+        let data = padded(self.input);
+        let mut bs = BitStreamerBase::<BitOrderMSB>::try_from(data.as_slice())
+            .map_err(|_err| SamsungError::TruncatedData)?;
+        let max = 1 << self.bits;
+        for row in 0..self.dims.row_count().get() {
+            let mut preds = match row.checked_sub(2) {
+                Some(above) => {
+                    [pixel(output, above, 0), pixel(output, above, 1)]
+                }
+                None => [0, 0],
+            };
+            for col in 0..self.dims.row_len().get() {
+                let pred = preds.get_mut(col % 2).unwrap();
+                *pred += decode_v1_diff(&mut bs, &table)?;
+                if !(0..max).contains(pred) {
+                    return Err(SamsungError::InvalidSample);
+                }
+                set_pixel(output, row, col, u16::try_from(*pred).unwrap());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The fields of the V2 header that the decoding depends on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct V2Header {
+    bits: u32,
+    width: usize,
+    height: usize,
+    flags: u32,
+    initial_value: i32,
+}
+
+impl V2Header {
+    fn parse(input: &[u8]) -> Result<Self, SamsungError> {
+        let header = input
+            .get(..V2_ALIGNMENT)
+            .ok_or(SamsungError::TruncatedData)?;
+        let mut bs = BitStreamerBase::<BitOrderMSB32>::try_from(header)
+            .map_err(|_err| SamsungError::TruncatedData)?;
+        let mut field = |nbits| get_bits(&mut bs, nbits);
+        let _version = field(16)?;
+        let _format = field(4)?;
+        let bits = field(4)? + 1;
+        let _blocks_in_unit = field(4)?;
+        let _compression_ratio = field(4)?;
+        let width = usize::try_from(field(16)?).unwrap();
+        let height = usize::try_from(field(16)?).unwrap();
+        let _tile_width = field(16)?;
+        let _reserved_flags = field(4)?;
+        let flags = field(4)?;
+        let _overlap_width = field(8)?;
+        let _reserved_overlap = field(8)?;
+        let _inc = field(8)?;
+        let _reserved_initial_value = field(2)?;
+        let initial_value = i32::try_from(field(14)?).unwrap();
+        if flags & !V2_FLAGS != 0 {
+            return Err(SamsungError::InvalidHeader);
+        }
+        Ok(Self {
+            bits,
+            width,
+            height,
+            flags,
+            initial_value,
+        })
+    }
+}
+
+/// The state of the decoding of a V2 row, which is reset for each row.
+#[derive(Debug)]
+struct V2Row<'a, 'b> {
+    header: &'b V2Header,
+    bs: BitStreamerBase<'a, BitOrderMSB32>,
+    consumed_bits: usize,
+    row: usize,
+    motion: u32,
+    scale: i32,
+    /// The last two difference lengths of each color.
+    diff_lens: [[u32; 2]; 3],
+}
+
+impl<'a, 'b> V2Row<'a, 'b> {
+    fn new(
+        header: &'b V2Header,
+        input: &'a [u8],
+        row: usize,
+    ) -> Result<Self, SamsungError> {
+        let bs = BitStreamerBase::<BitOrderMSB32>::try_from(input)
+            .map_err(|_err| SamsungError::TruncatedData)?;
+        Ok(Self {
+            header,
+            bs,
+            consumed_bits: 0,
+            row,
+            motion: V2_MOTION_NONE,
+            scale: 0,
+            diff_lens: [[if row < 2 { 7 } else { 4 }; 2]; 3],
+        })
+    }
+
+    fn get_bits(&mut self, nbits: u32) -> Result<u32, SamsungError> {
+        self.consumed_bits += usize::try_from(nbits).unwrap();
+        get_bits(&mut self.bs, nbits)
+    }
+
+    fn get_signed_diff(&mut self, len: u32) -> Result<i32, SamsungError> {
+        self.consumed_bits += usize::try_from(len).unwrap();
+        get_signed_diff(&mut self.bs, len)
+    }
+
+    /// The number of bytes that the row took.
+    const fn len(&self) -> usize {
+        self.consumed_bits.div_ceil(8)
+    }
+
+    fn update_scale(&mut self, col: usize) -> Result<(), SamsungError> {
+        if self.header.flags & V2_FLAG_QP != 0
+            || !col.is_multiple_of(V2_SCALE_PERIOD)
+        {
+            return Ok(());
+        }
+        self.scale = match self.get_bits(2)? {
+            0 => self.scale,
+            1 => self.scale - 2,
+            2 => self.scale + 2,
+            _ => i32::try_from(self.get_bits(12)?).unwrap(),
+        };
+        Ok(())
+    }
+
+    /// Unless it is restricted, the motion is kept from the previous block
+    /// if the first bit is set.
+    fn update_motion(&mut self) -> Result<(), SamsungError> {
+        if self.header.flags & V2_FLAG_MV != 0 {
+            self.motion = if self.get_bits(1)? != 0 {
+                V2_MOTION_UP
+            } else {
+                V2_MOTION_NONE
+            };
+        } else if self.get_bits(1)? == 0 {
+            self.motion = self.get_bits(3)?;
+        } else {
+            // The motion is unchanged.
+        }
+        if self.row < 2 && self.motion != V2_MOTION_NONE {
+            return Err(SamsungError::InvalidPrediction);
+        }
+        Ok(())
+    }
+
+    /// The pixels of the block before their differences are applied: the
+    /// last ones of the same parity in the previous block, or those that
+    /// the motion points to. Those of the same color as the previous row
+    /// reference the row above, the others two rows above.
+    fn baseline(
+        &self,
+        output: &Array2DRefMut<'_, u16>,
+        col: usize,
+    ) -> Result<[i32; BLOCK_LEN], SamsungError> {
+        let mut baseline = [self.header.initial_value; BLOCK_LEN];
+        let Some(&(offset, average)) =
+            V2_MOTIONS.get(usize::try_from(self.motion).unwrap())
+        else {
+            if col != 0 {
+                for (pos, val) in baseline.iter_mut().enumerate() {
+                    *val = pixel(output, self.row, col - 2 + pos % 2);
+                }
+            }
+            return Ok(baseline);
+        };
+        let width = output.row_length().get();
+        for (pos, val) in baseline.iter_mut().enumerate() {
+            let (ref_row, ref_col) = if (self.row + pos) % 2 == 1 {
+                (self.row - 2, (col + pos).checked_add_signed(offset))
+            } else {
+                let side = if pos % 2 == 1 { -1 } else { 1 };
+                (self.row - 1, (col + pos).checked_add_signed(offset + side))
+            };
+            let last = if average { 2 } else { 0 };
+            let ref_col = ref_col
+                .filter(|ref_col| ref_col + last < width)
+                .ok_or(SamsungError::InvalidPrediction)?;
+            *val = if average {
+                (pixel(output, ref_row, ref_col)
+                    + pixel(output, ref_row, ref_col + 2)
+                    + 1)
+                    >> 1
+            } else {
+                pixel(output, ref_row, ref_col)
+            };
+        }
+        Ok(baseline)
+    }
+
+    /// The lengths of the differences of each quarter of the block, which
+    /// are coded relative to the last but one of the same color.
+    fn diff_lens(&mut self) -> Result<[u32; 4], SamsungError> {
+        let mut lens = [0; 4];
+        if self.header.flags & V2_FLAG_SKIP == 0 && self.get_bits(1)? != 0 {
+            return Ok(lens);
+        }
+        let mut ops = [0; 4];
+        for op in &mut ops {
+            *op = self.get_bits(2)?;
+        }
+        for (index, (len, op)) in lens.iter_mut().zip(ops).enumerate() {
+            // Green, blue, then red.
+            let color = if self.row % 2 == 1 {
+                index / 2
+            } else {
+                (index / 2 + 2) % 3
+            };
+            let [prev, last] = *self.diff_lens.get(color).unwrap();
+            let new_len = match op {
+                0 => Some(prev),
+                1 => Some(prev + 1),
+                2 => prev.checked_sub(1),
+                _ => Some(self.get_bits(4)?),
+            };
+            *len = new_len
+                .filter(|new_len| *new_len <= self.header.bits + 1)
+                .ok_or(SamsungError::InvalidBitLength)?;
+            *self.diff_lens.get_mut(color).unwrap() = [last, *len];
+        }
+        Ok(lens)
+    }
+
+    /// The differences of the even pixels of the block come first on even
+    /// rows, and last on odd rows.
+    fn decode_block(
+        &mut self,
+        output: &mut Array2DRefMut<'_, u16>,
+        col: usize,
+    ) -> Result<(), SamsungError> {
+        self.update_scale(col)?;
+        self.update_motion()?;
+        let baseline = self.baseline(output, col)?;
+        let lens = self.diff_lens()?;
+        let max = (1 << self.header.bits) - 1;
+        for index in 0..BLOCK_LEN {
+            let diff = self.get_signed_diff(*lens.get(index / 4).unwrap())?;
+            let pos = if self.row % 2 == 1 {
+                (index % 8) * 2 + 1 - index / 8
+            } else {
+                (index % 8) * 2 + index / 8
+            };
+            let val = *baseline.get(pos).unwrap()
+                + diff * (self.scale * 2 + 1)
+                + self.scale;
+            let val = u16::try_from(val.clamp(0, max)).unwrap();
+            set_pixel(output, self.row, col + pos, val);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+#[must_use]
+pub struct SamsungV2Decompressor<'a> {
+    input: &'a [u8],
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    header: V2Header,
+}
+
+impl<'a> SamsungV2Decompressor<'a> {
+    /// The data starts with a header, which must agree with the given
+    /// dimensions and bits per sample.
+    #[inline]
+    pub fn new(
+        input: &'a [u8],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        bits: u32,
+    ) -> Result<Self, SamsungError> {
+        if !matches!(bits, 12 | 14) {
+            return Err(SamsungError::InvalidBitsPerSample);
+        }
+        let header = V2Header::parse(input)?;
+        if header.bits != bits {
+            return Err(SamsungError::InvalidHeader);
+        }
+        if header.width != dims.row_len().get()
+            || header.height != dims.row_count().get()
+            || !header.width.is_multiple_of(BLOCK_LEN)
+        {
+            return Err(SamsungError::InvalidDimensions);
+        }
+        let input = input.get(V2_ALIGNMENT..).unwrap();
+        Ok(Self {
+            input,
+            dims,
+            header,
+        })
+    }
+
+    #[inline(never)]
+    pub fn decode(
+        &self,
+        output: &mut Array2DRefMut<'_, u16>,
+    ) -> Result<(), SamsungError> {
+        check_output_dims(output, self.dims)?;
+        let data = padded(self.input);
+        let mut start = 0;
+        for row in 0..self.header.height {
+            let input = data.get(start..).ok_or(SamsungError::TruncatedData)?;
+            let mut decoder = V2Row::new(&self.header, input, row)?;
+            for col in (0..self.header.width).step_by(BLOCK_LEN) {
+                decoder.decode_block(output, col)?;
+            }
+            start = (start + decoder.len()).next_multiple_of(V2_ALIGNMENT);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,730 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_std::coord_common::{Dimensions2D, RowCount, RowLength, RowPitch};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+
+use super::{
+    BLOCK_LEN, SamsungError, SamsungV0Decompressor, SamsungV1Decompressor,
+    SamsungV2Decompressor, V1_CODES, V1_LOOKUP_BITS, V2_ALIGNMENT, V2_FLAG_MV,
+    V2_FLAG_QP, V2_FLAG_SKIP, V2_MOTION_NONE, V2_MOTION_UP, V2_MOTIONS,
+};
+
+/// The bits, as MSB-first 32-bit words padded to the row alignment.
+fn finish_aligned(bits: BitStreamBuilder) -> Vec<u8> {
+    let mut out = bits.finish(BitOrder::MSB32);
+    out.resize(out.len().next_multiple_of(V2_ALIGNMENT), 0);
+    out
+}
+
+/// The length of the shortest two's complement representation of `diff`.
+fn signed_len(diff: i32) -> u32 {
+    match diff.cmp(&0) {
+        core::cmp::Ordering::Less => 33 - diff.leading_ones(),
+        core::cmp::Ordering::Greater => 33 - diff.leading_zeros(),
+        core::cmp::Ordering::Equal => 0,
+    }
+}
+
+fn signed_bits(diff: i32, len: u32) -> u32 {
+    if len == 0 {
+        0
+    } else {
+        u32::try_from(i64::from(diff) & ((1 << len) - 1)).unwrap()
+    }
+}
+
+fn dims(
+    width: usize,
+    height: usize,
+) -> Dimensions2D<core::num::NonZero<usize>> {
+    Dimensions2D::new(
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowCount::new(core::num::NonZero::new(height).unwrap()),
+    )
+}
+
+fn run(
+    width: usize,
+    height: usize,
+    decode: impl FnOnce(&mut Array2DRefMut<'_, u16>) -> Result<(), SamsungError>,
+) -> Result<Vec<Vec<u16>>, SamsungError> {
+    let mut buf = vec![0; width * height];
+    let mut output = Array2DRefMut::new(
+        buf.as_mut_slice(),
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowPitch::new(core::num::NonZero::new(width).unwrap()),
+    );
+    decode(&mut output)?;
+    Ok(buf.chunks(width).map(<[u16]>::to_vec).collect())
+}
+
+fn test_image(width: usize, height: usize, bits: u32) -> Vec<Vec<i32>> {
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| {
+                    let sample =
+                        (row * 977 + col * 313 + (col & 1) * 50) % (1 << bits);
+                    i32::try_from(sample).unwrap()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn samples(image: &[Vec<i32>]) -> Vec<Vec<u16>> {
+    image
+        .iter()
+        .map(|row| row.iter().map(|v| u16::try_from(*v).unwrap()).collect())
+        .collect()
+}
+
+fn at(image: &[Vec<i32>], row: usize, col: usize) -> i32 {
+    *image.get(row).unwrap().get(col).unwrap()
+}
+
+/// Writes the updates of the lengths, as explicit lengths unless they
+/// are unchanged.
+fn put_v0_lens(
+    bits: &mut BitStreamBuilder,
+    lens: &mut [u32; 4],
+    new: [u32; 4],
+) {
+    let explicit: [bool; 4] =
+        core::array::from_fn(|i| lens.get(i) != new.get(i));
+    for is_explicit in explicit {
+        bits.put(if is_explicit { 3 } else { 0 }, 2);
+    }
+    for (is_explicit, len) in explicit.into_iter().zip(new) {
+        if is_explicit {
+            bits.put(len, 4);
+        }
+    }
+    *lens = new;
+}
+
+/// Encodes each row of `image`, before its samples are swapped, with
+/// the blocks alternately predicted upward where that is possible.
+fn encode_v0(image: &[Vec<i32>]) -> (Vec<u8>, Vec<usize>) {
+    let width = image.first().unwrap().len();
+    let mut data = vec![];
+    let mut offsets = vec![];
+    for (row, values) in image.iter().enumerate() {
+        offsets.push(data.len());
+        let mut bits = BitStreamBuilder::new();
+        let mut lens = [if row < 2 { 7 } else { 4 }; 4];
+        for col in (0..width).step_by(BLOCK_LEN) {
+            let upward = row >= 2
+                && col + BLOCK_LEN < width
+                && (col / BLOCK_LEN).is_multiple_of(2);
+            let mut diffs = [[0; 8]; 2];
+            for pos in 0..BLOCK_LEN {
+                let parity = pos % 2;
+                let pred = if upward {
+                    at(image, row - 1 - parity, col + pos)
+                } else {
+                    col.checked_sub(2 - parity)
+                        .map_or(128, |left| at(image, row, left))
+                };
+                let sample = values.get(col + pos).copied().unwrap_or(pred);
+                *diffs.get_mut(parity).unwrap().get_mut(pos / 2).unwrap() =
+                    sample - pred;
+            }
+            let new_lens = core::array::from_fn(|i| {
+                let half = diffs.get(i / 2).unwrap();
+                let quarter = half.get((i % 2) * 4..(i % 2) * 4 + 4);
+                quarter
+                    .unwrap()
+                    .iter()
+                    .map(|diff| signed_len(*diff))
+                    .max()
+                    .unwrap()
+            });
+            bits.put(u32::from(upward), 1);
+            put_v0_lens(&mut bits, &mut lens, new_lens);
+            for (parity, half) in diffs.iter().enumerate() {
+                for (index, diff) in half.iter().enumerate() {
+                    let len = *lens.get(2 * parity + index / 4).unwrap();
+                    bits.put(signed_bits(*diff, len), len);
+                }
+            }
+        }
+        data.extend(bits.finish(BitOrder::MSB32));
+    }
+    (data, offsets)
+}
+
+/// The samples of each 2x2 block, with the top right one and the
+/// bottom left one swapped.
+fn swapped(image: &[Vec<i32>]) -> Vec<Vec<u16>> {
+    let mut out = samples(image);
+    for row in (0..out.len() - 1).step_by(2) {
+        let (top, bottom) = out.split_at_mut(row + 1);
+        let (top, bottom) =
+            (top.last_mut().unwrap(), bottom.first_mut().unwrap());
+        for col in (0..top.len() - 1).step_by(2) {
+            core::mem::swap(
+                top.get_mut(col + 1).unwrap(),
+                bottom.get_mut(col).unwrap(),
+            );
+        }
+    }
+    out
+}
+
+fn decode_v0(
+    data: &[u8],
+    offsets: &[usize],
+    width: usize,
+    height: usize,
+) -> Result<Vec<Vec<u16>>, SamsungError> {
+    let decoder =
+        SamsungV0Decompressor::new(data, offsets, dims(width, height))?;
+    run(width, height, |output| decoder.decode(output))
+}
+
+#[test]
+fn v0_round_trip_test() {
+    for (width, height) in [(64, 4), (40, 6), (16, 1)] {
+        let image = test_image(width, height, 12);
+        let (data, offsets) = encode_v0(&image);
+        assert_eq!(
+            decode_v0(&data, &offsets, width, height),
+            Ok(swapped(&image))
+        );
+    }
+}
+
+#[test]
+fn v0_invalid_dimensions_test() {
+    assert_eq!(
+        SamsungV0Decompressor::new(&[0; 4], &[0], dims(15, 1)).unwrap_err(),
+        SamsungError::InvalidDimensions
+    );
+}
+
+#[test]
+fn v0_invalid_row_offsets_test() {
+    for offsets in [&[0][..], &[0, 4, 8], &[4, 4], &[4, 0], &[0, 9]] {
+        assert_eq!(
+            SamsungV0Decompressor::new(&[0; 8], offsets, dims(16, 2))
+                .unwrap_err(),
+            SamsungError::InvalidRowOffsets
+        );
+    }
+}
+
+#[test]
+fn v0_invalid_prediction_test() {
+    // The first row can not be predicted upward.
+    let mut bits = BitStreamBuilder::new();
+    bits.put(1, 1);
+    bits.put(0, 8);
+    let data = bits.finish(BitOrder::MSB32);
+    assert_eq!(
+        decode_v0(&data, &[0], 32, 1),
+        Err(SamsungError::InvalidPrediction)
+    );
+}
+
+#[test]
+fn v0_invalid_bit_length_test() {
+    let mut bits = BitStreamBuilder::new();
+    // A first block with no differences, then a shorter length.
+    bits.put(0b0_11_11_11_11, 9);
+    bits.put(0, 16);
+    bits.put(0b0_10_00_00_00, 9);
+    let data = bits.finish(BitOrder::MSB32);
+    assert_eq!(
+        decode_v0(&data, &[0], 32, 1),
+        Err(SamsungError::InvalidBitLength)
+    );
+}
+
+#[test]
+fn v0_truncated_test() {
+    assert_eq!(
+        decode_v0(&[0; 4], &[0], 256, 1),
+        Err(SamsungError::TruncatedData)
+    );
+}
+
+/// The code of each difference length, given by its position in the
+/// lookup table.
+fn v1_code(len: u32) -> (u32, u32) {
+    let mut start = 0;
+    for (code_len, diff_len) in V1_CODES {
+        if diff_len == len {
+            return (start >> (V1_LOOKUP_BITS - code_len), code_len);
+        }
+        start += 1 << (V1_LOOKUP_BITS - code_len);
+    }
+    unreachable!()
+}
+
+fn encode_v1(image: &[Vec<i32>]) -> Vec<u8> {
+    let mut bits = BitStreamBuilder::new();
+    for (row, values) in image.iter().enumerate() {
+        for (col, sample) in values.iter().enumerate() {
+            let pred = match (col.checked_sub(2), row.checked_sub(2)) {
+                (Some(left), _) => at(image, row, left),
+                (None, Some(above)) => at(image, above, col),
+                (None, None) => 0,
+            };
+            let diff = sample - pred;
+            let len = 32 - diff.unsigned_abs().leading_zeros();
+            let (code, code_len) = v1_code(len);
+            bits.put(code, code_len);
+            let value = if diff < 0 {
+                diff + (1 << len) - 1
+            } else {
+                diff
+            };
+            bits.put(u32::try_from(value).unwrap(), len);
+        }
+    }
+    bits.finish(BitOrder::MSB)
+}
+
+fn decode_v1(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    bits: u32,
+) -> Result<Vec<Vec<u16>>, SamsungError> {
+    let decoder = SamsungV1Decompressor::new(data, dims(width, height), bits)?;
+    run(width, height, |output| decoder.decode(output))
+}
+
+#[test]
+fn v1_round_trip_test() {
+    for bits in [12, 14] {
+        let image = test_image(8, 4, bits);
+        assert_eq!(
+            decode_v1(&encode_v1(&image), 8, 4, bits),
+            Ok(samples(&image))
+        );
+    }
+}
+
+#[test]
+fn v1_invalid_parameters_test() {
+    assert_eq!(
+        SamsungV1Decompressor::new(&[], dims(7, 2), 12).unwrap_err(),
+        SamsungError::InvalidDimensions
+    );
+    assert_eq!(
+        SamsungV1Decompressor::new(&[], dims(8, 2), 10).unwrap_err(),
+        SamsungError::InvalidBitsPerSample
+    );
+}
+
+#[test]
+fn v1_invalid_sample_test() {
+    let negative = encode_v1(&[vec![-1, 0]]);
+    assert_eq!(
+        decode_v1(&negative, 2, 1, 12),
+        Err(SamsungError::InvalidSample)
+    );
+    let too_large = encode_v1(&[vec![0x1000, 0]]);
+    assert_eq!(
+        decode_v1(&too_large, 2, 1, 12),
+        Err(SamsungError::InvalidSample)
+    );
+    assert_eq!(decode_v1(&too_large, 2, 1, 14), Ok(vec![vec![0x1000, 0]]));
+}
+
+#[test]
+fn v1_truncated_test() {
+    // Past the end, the (zero) bits decode to small negative differences.
+    let data = encode_v1(&vec![vec![2048; 64]; 64]);
+    assert_eq!(
+        decode_v1(data.get(..16).unwrap(), 64, 64, 12),
+        Err(SamsungError::TruncatedData)
+    );
+}
+
+const V2_INITIAL_VALUE: u32 = 512;
+
+fn v2_header(
+    bits: u32,
+    width: usize,
+    height: usize,
+    flags: u32,
+) -> BitStreamBuilder {
+    let mut out = BitStreamBuilder::new();
+    for (value, nbits) in [
+        (1, 16),
+        (0, 4),
+        (bits - 1, 4),
+        (0, 4),
+        (0, 4),
+        (u32::try_from(width).unwrap(), 16),
+        (u32::try_from(height).unwrap(), 16),
+        (u32::try_from(width).unwrap(), 16),
+        (0, 4),
+        (flags, 4),
+        (0, 8),
+        (0, 8),
+        (0, 8),
+        (0, 2),
+        (V2_INITIAL_VALUE, 14),
+    ] {
+        out.put(value, nbits);
+    }
+    out
+}
+
+/// The baseline of the block with the given motion, if its reference
+/// pixels are in bounds.
+fn v2_baseline(
+    image: &[Vec<i32>],
+    (row, col): (usize, usize),
+    motion: u32,
+) -> Option<[i32; BLOCK_LEN]> {
+    let width = image.first().unwrap().len();
+    let Some(&(offset, average)) =
+        V2_MOTIONS.get(usize::try_from(motion).unwrap())
+    else {
+        return Some(core::array::from_fn(|pos| {
+            col.checked_sub(2 - pos % 2)
+                .map_or(i32::try_from(V2_INITIAL_VALUE).unwrap(), |left| {
+                    at(image, row, left)
+                })
+        }));
+    };
+    let mut out = [0; BLOCK_LEN];
+    for (pos, val) in out.iter_mut().enumerate() {
+        let (ref_row, ref_col) = if (row + pos) % 2 == 1 {
+            (row - 2, (col + pos).checked_add_signed(offset)?)
+        } else {
+            let side = if pos % 2 == 1 { -1 } else { 1 };
+            (row - 1, (col + pos).checked_add_signed(offset + side)?)
+        };
+        *val = if average {
+            if ref_col + 2 >= width {
+                return None;
+            }
+            (at(image, ref_row, ref_col) + at(image, ref_row, ref_col + 2) + 1)
+                >> 1
+        } else {
+            if ref_col >= width {
+                return None;
+            }
+            at(image, ref_row, ref_col)
+        };
+    }
+    Some(out)
+}
+
+/// Chooses a different motion for each block, if the first two rows
+/// are not involved and its reference pixels are in bounds.
+fn v2_motion(
+    image: &[Vec<i32>],
+    (row, col): (usize, usize),
+    flags: u32,
+) -> u32 {
+    if row < 2 {
+        return V2_MOTION_NONE;
+    }
+    let block = col / BLOCK_LEN;
+    let motion = if flags & V2_FLAG_MV != 0 {
+        if block.is_multiple_of(2) {
+            V2_MOTION_UP
+        } else {
+            V2_MOTION_NONE
+        }
+    } else {
+        u32::try_from((row * 3 + block) % 8).unwrap()
+    };
+    if v2_baseline(image, (row, col), motion).is_some() {
+        motion
+    } else {
+        V2_MOTION_NONE
+    }
+}
+
+fn put_v2_lens(
+    bits: &mut BitStreamBuilder,
+    state: &mut [[u32; 2]; 3],
+    row: usize,
+    lens: [u32; 4],
+) {
+    let mut explicit = vec![];
+    for (index, len) in lens.into_iter().enumerate() {
+        let color = if row % 2 == 1 {
+            index / 2
+        } else {
+            (index / 2 + 2) % 3
+        };
+        let [prev, last] = *state.get(color).unwrap();
+        let op = if len == prev {
+            0
+        } else if len == prev + 1 {
+            1
+        } else if len + 1 == prev {
+            2
+        } else {
+            explicit.push(len);
+            3
+        };
+        bits.put(op, 2);
+        *state.get_mut(color).unwrap() = [last, len];
+    }
+    for len in explicit {
+        bits.put(len, 4);
+    }
+}
+
+fn encode_v2_block(
+    bits: &mut BitStreamBuilder,
+    image: &[Vec<i32>],
+    (row, col): (usize, usize),
+    (flags, prev_motion): (u32, &mut u32),
+    state: &mut [[u32; 2]; 3],
+) {
+    if flags & V2_FLAG_QP == 0 && col.is_multiple_of(64) {
+        bits.put(0, 2);
+    }
+    let motion = v2_motion(image, (row, col), flags);
+    if flags & V2_FLAG_MV != 0 {
+        bits.put(u32::from(motion == V2_MOTION_UP), 1);
+    } else if motion == *prev_motion {
+        bits.put(1, 1);
+    } else {
+        bits.put(0, 1);
+        bits.put(motion, 3);
+    }
+    *prev_motion = motion;
+    let baseline = v2_baseline(image, (row, col), motion).unwrap();
+    let diffs: [i32; BLOCK_LEN] = core::array::from_fn(|index| {
+        let pos = if row % 2 == 1 {
+            (index % 8) * 2 + 1 - index / 8
+        } else {
+            (index % 8) * 2 + index / 8
+        };
+        at(image, row, col + pos) - *baseline.get(pos).unwrap()
+    });
+    let lens: [u32; 4] = core::array::from_fn(|quarter| {
+        let diffs = diffs.get(quarter * 4..quarter * 4 + 4).unwrap();
+        diffs.iter().map(|diff| signed_len(*diff)).max().unwrap()
+    });
+    if flags & V2_FLAG_SKIP == 0 {
+        let skip = lens == [0; 4];
+        bits.put(u32::from(skip), 1);
+        if skip {
+            return;
+        }
+    }
+    put_v2_lens(bits, state, row, lens);
+    for (index, diff) in diffs.into_iter().enumerate() {
+        let len = *lens.get(index / 4).unwrap();
+        bits.put(signed_bits(diff, len), len);
+    }
+}
+
+fn encode_v2(image: &[Vec<i32>], bits: u32, flags: u32) -> Vec<u8> {
+    let width = image.first().unwrap().len();
+    let mut out =
+        v2_header(bits, width, image.len(), flags).finish(BitOrder::MSB32);
+    for row in 0..image.len() {
+        let mut writer = BitStreamBuilder::new();
+        let mut motion = V2_MOTION_NONE;
+        let mut state = [[if row < 2 { 7 } else { 4 }; 2]; 3];
+        for col in (0..width).step_by(BLOCK_LEN) {
+            encode_v2_block(
+                &mut writer,
+                image,
+                (row, col),
+                (flags, &mut motion),
+                &mut state,
+            );
+        }
+        out.extend(finish_aligned(writer));
+    }
+    out
+}
+
+/// The first block of the first rows is predicted from the initial
+/// value, so these have no differences.
+fn v2_image(width: usize, height: usize, bits: u32) -> Vec<Vec<i32>> {
+    let mut image = test_image(width, height, bits);
+    for values in image.iter_mut().take(2) {
+        values
+            .get_mut(..BLOCK_LEN)
+            .unwrap()
+            .fill(i32::try_from(V2_INITIAL_VALUE).unwrap());
+    }
+    image
+}
+
+fn decode_v2(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    bits: u32,
+) -> Result<Vec<Vec<u16>>, SamsungError> {
+    let decoder = SamsungV2Decompressor::new(data, dims(width, height), bits)?;
+    run(width, height, |output| decoder.decode(output))
+}
+
+#[test]
+fn v2_round_trip_test() {
+    for bits in [12, 14] {
+        for flags in 0..8 {
+            let image = v2_image(64, 6, bits);
+            assert_eq!(
+                decode_v2(&encode_v2(&image, bits, flags), 64, 6, bits),
+                Ok(samples(&image)),
+                "bits: {bits}, flags: {flags}"
+            );
+        }
+    }
+}
+
+#[test]
+fn v2_scale_test() {
+    let mut data =
+        v2_header(12, 32, 2, V2_FLAG_SKIP | V2_FLAG_MV).finish(BitOrder::MSB32);
+    for (scale_code, scale) in [(3, Some(5)), (2, None)] {
+        let mut bits = BitStreamBuilder::new();
+        for col in [0, 16] {
+            if col == 0 {
+                bits.put(scale_code, 2);
+                if let Some(scale) = scale {
+                    bits.put(scale, 12);
+                }
+            }
+            // No motion, and no differences.
+            bits.put(0, 1);
+            bits.put(0xFF, 8);
+            bits.put(0, 16);
+        }
+        data.extend(finish_aligned(bits));
+    }
+    let rows = [[517, 522], [514, 516]]
+        .map(|halves| halves.map(|val| [val; 16]).concat());
+    assert_eq!(decode_v2(&data, 32, 2, 12), Ok(rows.to_vec()));
+}
+
+#[test]
+fn v2_invalid_header_test() {
+    let image = v2_image(32, 2, 12);
+    let data = encode_v2(&image, 12, 0);
+    assert_eq!(
+        SamsungV2Decompressor::new(&data, dims(32, 2), 10).unwrap_err(),
+        SamsungError::InvalidBitsPerSample
+    );
+    assert_eq!(
+        SamsungV2Decompressor::new(&data, dims(32, 2), 14).unwrap_err(),
+        SamsungError::InvalidHeader
+    );
+    let flags = v2_header(12, 32, 2, 8).finish(BitOrder::MSB32);
+    assert_eq!(
+        SamsungV2Decompressor::new(&flags, dims(32, 2), 12).unwrap_err(),
+        SamsungError::InvalidHeader
+    );
+    assert_eq!(
+        SamsungV2Decompressor::new(data.get(..15).unwrap(), dims(32, 2), 12)
+            .unwrap_err(),
+        SamsungError::TruncatedData
+    );
+}
+
+#[test]
+fn v2_invalid_dimensions_test() {
+    let data = encode_v2(&v2_image(32, 2, 12), 12, 0);
+    for (width, height) in [(48, 2), (32, 3)] {
+        assert_eq!(
+            SamsungV2Decompressor::new(&data, dims(width, height), 12)
+                .unwrap_err(),
+            SamsungError::InvalidDimensions
+        );
+    }
+    let unaligned = v2_header(12, 24, 2, 0).finish(BitOrder::MSB32);
+    assert_eq!(
+        SamsungV2Decompressor::new(&unaligned, dims(24, 2), 12).unwrap_err(),
+        SamsungError::InvalidDimensions
+    );
+}
+
+#[test]
+fn v2_invalid_prediction_test() {
+    // The first row can only be predicted from the left.
+    let mut row = BitStreamBuilder::new();
+    row.put(0, 2);
+    row.put(0, 1);
+    row.put(V2_MOTION_UP, 3);
+    let mut data = v2_header(12, 32, 2, 0).finish(BitOrder::MSB32);
+    data.extend(finish_aligned(row));
+    assert_eq!(
+        decode_v2(&data, 32, 2, 12),
+        Err(SamsungError::InvalidPrediction)
+    );
+    // The reference pixels of the last block are out of bounds.
+    let mut bits = BitStreamBuilder::new();
+    bits.put(0, 2);
+    bits.put(0, 1);
+    bits.put(6, 3);
+    let mut image = encode_v2(&v2_image(16, 2, 12), 12, 0);
+    image.extend(finish_aligned(bits));
+    let mut header = v2_header(12, 16, 3, 0).finish(BitOrder::MSB32);
+    header.extend(image.get(V2_ALIGNMENT..).unwrap());
+    assert_eq!(
+        decode_v2(&header, 16, 3, 12),
+        Err(SamsungError::InvalidPrediction)
+    );
+}
+
+#[test]
+fn v2_invalid_bit_length_test() {
+    let mut row = BitStreamBuilder::new();
+    row.put(0, 2);
+    row.put(0, 1);
+    row.put(0b11_00_00_00, 8);
+    row.put(14, 4);
+    let mut data =
+        v2_header(12, 16, 1, V2_FLAG_SKIP | V2_FLAG_MV).finish(BitOrder::MSB32);
+    data.extend(finish_aligned(row));
+    assert_eq!(
+        decode_v2(&data, 16, 1, 12),
+        Err(SamsungError::InvalidBitLength)
+    );
+}
+
+#[test]
+fn v2_truncated_test() {
+    let data = encode_v2(&v2_image(64, 6, 12), 12, 0);
+    assert_eq!(
+        decode_v2(data.get(..data.len() - 64).unwrap(), 64, 6, 12),
+        Err(SamsungError::TruncatedData)
+    );
+}
+
+#[test]
+fn output_dimensions_mismatch_test() {
+    let mut buf = vec![0; 64];
+    let mut output = Array2DRefMut::new(
+        buf.as_mut_slice(),
+        RowLength::new(core::num::NonZero::new(32).unwrap()),
+        RowPitch::new(core::num::NonZero::new(32).unwrap()),
+    );
+    let (v0_data, offsets) = encode_v0(&test_image(16, 4, 12));
+    let v0 =
+        SamsungV0Decompressor::new(&v0_data, &offsets, dims(16, 4)).unwrap();
+    assert_eq!(
+        v0.decode(&mut output),
+        Err(SamsungError::OutputDimensionsMismatch)
+    );
+    let v1 = SamsungV1Decompressor::new(&[], dims(16, 4), 12).unwrap();
+    assert_eq!(
+        v1.decode(&mut output),
+        Err(SamsungError::OutputDimensionsMismatch)
+    );
+    let v2_data = encode_v2(&v2_image(16, 4, 12), 12, 0);
+    let v2 = SamsungV2Decompressor::new(&v2_data, dims(16, 4), 12).unwrap();
+    assert_eq!(
+        v2.decode(&mut output),
+        Err(SamsungError::OutputDimensionsMismatch)
+    );
+}
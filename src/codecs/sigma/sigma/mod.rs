@@ -0,0 +1,326 @@
+use rawspeed_bitstream_bitstream_decoder::bitstreamer::BitStreamerBase;
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrderMSB;
+use rawspeed_bitstream_bytestream_decoder::bytestreamer::ByteStreamer;
+use rawspeed_codecs_huffman::huffman::{
+    HuffmanCode, HuffmanError, HuffmanTable,
+};
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_std::coord_common::{Dimensions2D, RowCount, RowIndex, RowLength};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+
+/// The red, green and blue layers of the Foveon sensor.
+pub const NUM_PLANES: usize = 3;
+
+/// The codes are stored left-aligned in a byte.
+const MAX_CODE_LEN: u32 = 8;
+
+/// The largest number of codes, i.e. of difference lengths.
+const MAX_CODES: usize = 16;
+
+/// Each plane but the first starts at a multiple of this many bytes
+/// from the previous one.
+const PLANE_ALIGNMENT: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SigmaError {
+    UnexpectedEndOfInput,
+    InvalidHuffmanTable,
+    InvalidPlaneDimensions,
+    InvalidHuffmanCode,
+    ValueOutOfBounds,
+    TruncatedData,
+    OutputDimensionsMismatch,
+}
+
+impl core::fmt::Display for SigmaError {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SigmaError::UnexpectedEndOfInput => {
+                write!(f, "SigmaError(UnexpectedEndOfInput)")
+            }
+            SigmaError::InvalidHuffmanTable => {
+                write!(f, "SigmaError(InvalidHuffmanTable)")
+            }
+            SigmaError::InvalidPlaneDimensions => {
+                write!(f, "SigmaError(InvalidPlaneDimensions)")
+            }
+            SigmaError::InvalidHuffmanCode => {
+                write!(f, "SigmaError(InvalidHuffmanCode)")
+            }
+            SigmaError::ValueOutOfBounds => {
+                write!(f, "SigmaError(ValueOutOfBounds)")
+            }
+            SigmaError::TruncatedData => {
+                write!(f, "SigmaError(TruncatedData)")
+            }
+            SigmaError::OutputDimensionsMismatch => {
+                write!(f, "SigmaError(OutputDimensionsMismatch)")
+            }
+        }
+    }
+}
+
+impl From<HuffmanError> for SigmaError {
+    #[inline]
+    fn from(err: HuffmanError) -> Self {
+        if err == HuffmanError::EndOfStream {
+            SigmaError::TruncatedData
+        } else {
+            SigmaError::InvalidHuffmanCode
+        }
+    }
+}
+
+/// Reader over the (little-endian) header of the image data.
+#[derive(Debug, Clone, Copy)]
+struct HeaderReader<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> HeaderReader<'a> {
+    fn get_bytes(&mut self, len: usize) -> Result<&'a [u8], SigmaError> {
+        let bytes = self
+            .input
+            .get(self.pos..)
+            .and_then(|rest| rest.get(..len))
+            .ok_or(SigmaError::UnexpectedEndOfInput)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, SigmaError> {
+        Ok(*self.get_bytes(1)?.first().unwrap())
+    }
+
+    fn read_u16(&mut self) -> Result<u16, SigmaError> {
+        let bytes = self.get_bytes(size_of::<u16>())?;
+        Ok(ByteStreamer::new(bytes, Endianness::Little).read())
+    }
+
+    fn read_u32(&mut self) -> Result<usize, SigmaError> {
+        let bytes = self.get_bytes(size_of::<u32>())?;
+        let val: u32 = ByteStreamer::new(bytes, Endianness::Little).read();
+        Ok(val.try_into().unwrap())
+    }
+}
+
+/// Parses the Huffman table: `(length, left-aligned code)` byte pairs,
+/// ended by a zero length. The symbol of each code is its index, i.e. the
+/// length of the difference that follows it. The codes must be canonical.
+fn parse_code(
+    reader: &mut HeaderReader<'_>,
+) -> Result<HuffmanCode, SigmaError> {
+    let mut codes = vec![];
+    loop {
+        let len = u32::from(reader.read_u8()?);
+        let code = u32::from(reader.read_u8()?);
+        if len == 0 {
+            break;
+        }
+        if len > MAX_CODE_LEN || codes.len() == MAX_CODES {
+            return Err(SigmaError::InvalidHuffmanTable);
+        }
+        codes.push((len, code >> (MAX_CODE_LEN - len)));
+    }
+    let mut counts = [0; 16];
+    for (len, _) in &codes {
+        *counts.get_mut(usize::try_from(len - 1).unwrap()).unwrap() += 1;
+    }
+    // Canonical codes are in increasing order once left-aligned.
+    let mut symbols: Vec<u8> = (0..codes.len())
+        .map(|symbol| u8::try_from(symbol).unwrap())
+        .collect();
+    symbols.sort_by_key(|symbol| {
+        let (len, code) = *codes.get(usize::from(*symbol)).unwrap();
+        code << (MAX_CODE_LEN - len)
+    });
+    let code = HuffmanCode::new(&counts, &symbols)
+        .map_err(|_err| SigmaError::InvalidHuffmanTable)?;
+    let canonical = code.codes().into_iter().zip(code.symbols()).all(
+        |(canonical, symbol)| {
+            *codes.get(usize::from(*symbol)).unwrap() == canonical
+        },
+    );
+    if !canonical {
+        return Err(SigmaError::InvalidHuffmanTable);
+    }
+    Ok(code)
+}
+
+/// The dimensions of a plane, and its [`Plane::shift`].
+type PlaneDims = (Dimensions2D<core::num::NonZero<usize>>, u32);
+
+#[derive(Debug, Clone, Copy)]
+struct Plane<'a> {
+    input: &'a [u8],
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    /// By how much the coordinates of the image are shifted down to get
+    /// those of the plane: the lower planes of the Quattro sensors only
+    /// have half the resolution.
+    shift: u32,
+    /// The prediction of the first two samples of the first two rows.
+    seed: u16,
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct TrueDecompressor<'a> {
+    planes: [Plane<'a>; NUM_PLANES],
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    code: HuffmanCode,
+}
+
+impl<'a> TrueDecompressor<'a> {
+    /// The plane dimensions stored by the Quattro sensors, which must be
+    /// those of the image, or half of them.
+    fn parse_quattro_dims(
+        reader: &mut HeaderReader<'_>,
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+    ) -> Result<[PlaneDims; NUM_PLANES], SigmaError> {
+        let (width, height) = (dims.row_len().get(), dims.row_count().get());
+        let mut plane_dims = [(dims, 0); NUM_PLANES];
+        for (plane_dim, shift) in &mut plane_dims {
+            let plane_width = usize::from(reader.read_u16()?);
+            let plane_height = usize::from(reader.read_u16()?);
+            *shift = if (plane_width, plane_height) == (width, height) {
+                0
+            } else if (plane_width, plane_height)
+                == (width.div_ceil(2), height.div_ceil(2))
+            {
+                1
+            } else {
+                return Err(SigmaError::InvalidPlaneDimensions);
+            };
+            *plane_dim = Dimensions2D::new(
+                RowLength::new(core::num::NonZero::new(plane_width).unwrap()),
+                RowCount::new(core::num::NonZero::new(plane_height).unwrap()),
+            );
+        }
+        Ok(plane_dims)
+    }
+
+    /// The data starts with the plane dimensions (for the Quattro
+    /// sensors only), the seeds of the planes, the Huffman table and the
+    /// sizes of the planes, which follow.
+    #[inline]
+    pub fn new(
+        input: &'a [u8],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        quattro: bool,
+    ) -> Result<Self, SigmaError> {
+        let mut reader = HeaderReader { input, pos: 0 };
+        let plane_dims = if quattro {
+            Self::parse_quattro_dims(&mut reader, dims)?
+        } else {
+            [(dims, 0); NUM_PLANES]
+        };
+        let mut seeds = [0; NUM_PLANES];
+        for seed in &mut seeds {
+            *seed = reader.read_u16()?;
+        }
+        reader.read_u16()?;
+        let code = parse_code(&mut reader)?;
+        let mut sizes = [0; NUM_PLANES];
+        for size in &mut sizes {
+            *size = reader.read_u32()?;
+        }
+        let mut offset = reader.pos;
+        let mut planes = [Plane {
+            input: &[],
+            dims,
+            shift: 0,
+            seed: 0,
+        }; NUM_PLANES];
+        for (((plane, (dims_of_plane, shift)), seed), size) in
+            planes.iter_mut().zip(plane_dims).zip(seeds).zip(sizes)
+        {
+            let plane_input = input
+                .get(offset..)
+                .and_then(|rest| rest.get(..size))
+                .ok_or(SigmaError::TruncatedData)?;
+            *plane = Plane {
+                input: plane_input,
+                dims: dims_of_plane,
+                shift,
+                seed,
+            };
+            offset += size.next_multiple_of(PLANE_ALIGNMENT);
+        }
+        Ok(Self { planes, dims, code })
+    }
+
+    /// Each sample is predicted from the previous one of the same column
+    /// parity, the first two of each row from the first two of the row
+    /// before the previous one (or from the seed).
+    fn decode_plane(
+        plane: &Plane<'_>,
+        table: &HuffmanTable,
+    ) -> Result<Vec<u16>, SigmaError> {
+        let mut bs = BitStreamerBase::<BitOrderMSB>::try_from(plane.input)
+            .map_err(|_err| SigmaError::TruncatedData)?;
+        let width = plane.dims.row_len().get();
+        let mut out = vec![0; width * plane.dims.row_count().get()];
+        let mut row_starts = [[i32::from(plane.seed); 2]; 2];
+        for (row, out_row) in out.chunks_exact_mut(width).enumerate() {
+            let starts = row_starts.get_mut(row % 2).unwrap();
+            let mut preds = [0; 2];
+            for (col, sample) in out_row.iter_mut().enumerate() {
+                let diff = table.decode_difference(&mut bs)?;
+                let pred = preds.get_mut(col % 2).unwrap();
+                if let Some(start) = starts.get_mut(col) {
+                    *start += diff;
+                    *pred = *start;
+                } else {
+                    *pred += diff;
+                }
+                *sample = u16::try_from(*pred)
+                    .map_err(|_err| SigmaError::ValueOutOfBounds)?;
+            }
+        }
+        Ok(out)
+    }
+
+    /// The output holds the samples of the planes interleaved, so its rows
+    /// are three times as long as those of the image.
+    #[inline(never)]
+    pub fn decode(
+        &self,
+        output: &mut Array2DRefMut<'_, u16>,
+    ) -> Result<(), SigmaError> {
+        let (width, height) =
+            (self.dims.row_len().get(), self.dims.row_count().get());
+        if output.dims().row_len().get() != width * NUM_PLANES
+            || output.dims().row_count() != self.dims.row_count()
+        {
+            return Err(SigmaError::OutputDimensionsMismatch);
+        }
+        let table =
+            HuffmanTable::new(&self.code, HuffmanTable::DEFAULT_LUT_BITS)
+                .map_err(|_err| SigmaError::InvalidHuffmanTable)?;
+        for (index, plane) in self.planes.iter().enumerate() {
+            let samples = Self::decode_plane(plane, &table)?;
+            let plane_width = plane.dims.row_len().get();
+            for row in 0..height {
+                let plane_row = samples
+                    .chunks_exact(plane_width)
+                    .nth(row >> plane.shift)
+                    .unwrap();
+                let out = output.get_row_mut(RowIndex::new(row)).unwrap();
+                for (col, pixel) in
+                    out.chunks_exact_mut(NUM_PLANES).take(width).enumerate()
+                {
+                    *pixel.get_mut(index).unwrap() =
+                        *plane_row.get(col >> plane.shift).unwrap();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,287 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_codecs_huffman::huffman::HuffmanCode;
+use rawspeed_std::coord_common::{Dimensions2D, RowCount, RowLength, RowPitch};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+
+use super::{NUM_PLANES, SigmaError, TrueDecompressor};
+
+const COUNTS: [u8; 16] = [0, 0, 4, 4, 4, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+const SYMBOLS: [u8; 13] = [2, 3, 1, 4, 0, 5, 6, 7, 8, 9, 10, 11, 12];
+
+fn code() -> HuffmanCode {
+    HuffmanCode::new(&COUNTS, &SYMBOLS).unwrap()
+}
+
+/// The `(length, left-aligned code)` pairs, in the order of the symbols.
+fn table(code: &HuffmanCode) -> Vec<u8> {
+    let mut codes: Vec<_> = code
+        .codes()
+        .into_iter()
+        .zip(code.symbols())
+        .map(|((len, bits), symbol)| (*symbol, len, bits))
+        .collect();
+    codes.sort_unstable();
+    let mut out = vec![];
+    for (_, len, bits) in codes {
+        out.push(u8::try_from(len).unwrap());
+        out.push(u8::try_from(bits << (8 - len)).unwrap());
+    }
+    out.extend([0, 0]);
+    out
+}
+
+/// Encodes a plane, each sample being predicted like the decoder does.
+fn encode_plane(
+    code: &HuffmanCode,
+    samples: &[Vec<i32>],
+    seed: i32,
+) -> Vec<u8> {
+    let codes = code.codes();
+    let mut bits = BitStreamBuilder::new();
+    for (row, values) in samples.iter().enumerate() {
+        for (col, value) in values.iter().enumerate() {
+            let pred = match (col.checked_sub(2), row.checked_sub(2)) {
+                (Some(col), _) => *values.get(col).unwrap(),
+                (None, Some(row)) => {
+                    *samples.get(row).unwrap().get(col).unwrap()
+                }
+                (None, None) => seed,
+            };
+            let diff = value - pred;
+            let len = 32 - diff.unsigned_abs().leading_zeros();
+            let index = code
+                .symbols()
+                .iter()
+                .position(|symbol| u32::from(*symbol) == len)
+                .unwrap();
+            let (code_len, bits_of_code) = *codes.get(index).unwrap();
+            bits.put(bits_of_code, code_len);
+            let diff_bits = if diff < 0 {
+                diff + (1 << len) - 1
+            } else {
+                diff
+            };
+            bits.put(u32::try_from(diff_bits).unwrap(), len);
+        }
+    }
+    bits.finish(BitOrder::MSB)
+}
+
+fn samples(width: usize, height: usize, plane: usize) -> Vec<Vec<i32>> {
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| {
+                    i32::try_from((row * 733 + col * 211 + plane * 97) % 0x1000)
+                        .unwrap()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+struct Image {
+    quattro_dims: Option<[(u16, u16); NUM_PLANES]>,
+    seeds: [u16; NUM_PLANES],
+    table: Vec<u8>,
+    planes: [Vec<u8>; NUM_PLANES],
+}
+
+impl Image {
+    fn new(planes: &[Vec<Vec<i32>>; NUM_PLANES]) -> Self {
+        let seeds = [512, 256, 0];
+        let code = code();
+        Self {
+            quattro_dims: None,
+            seeds,
+            table: table(&code),
+            planes: [0, 1, 2].map(|index| {
+                encode_plane(
+                    &code,
+                    planes.get(index).unwrap(),
+                    i32::from(*seeds.get(index).unwrap()),
+                )
+            }),
+        }
+    }
+
+    fn build(&self) -> Vec<u8> {
+        let mut out = vec![];
+        if let Some(dims) = self.quattro_dims {
+            for (width, height) in dims {
+                out.extend(width.to_le_bytes());
+                out.extend(height.to_le_bytes());
+            }
+        }
+        for seed in self.seeds {
+            out.extend(seed.to_le_bytes());
+        }
+        out.extend([0, 0]);
+        out.extend(&self.table);
+        for plane in &self.planes {
+            out.extend(u32::try_from(plane.len()).unwrap().to_le_bytes());
+        }
+        // Each plane is padded, rather than aligned within the data.
+        for plane in &self.planes {
+            let mut padded = plane.clone();
+            padded.resize(plane.len().next_multiple_of(16), 0xFF);
+            out.extend(padded);
+        }
+        out
+    }
+}
+
+fn dims(
+    width: usize,
+    height: usize,
+) -> Dimensions2D<core::num::NonZero<usize>> {
+    Dimensions2D::new(
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowCount::new(core::num::NonZero::new(height).unwrap()),
+    )
+}
+
+fn decode(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    quattro: bool,
+) -> Result<Vec<u16>, SigmaError> {
+    let decoder = TrueDecompressor::new(data, dims(width, height), quattro)?;
+    let row_len = width * NUM_PLANES;
+    let mut buf = vec![0; row_len * height];
+    let mut output = Array2DRefMut::new(
+        &mut buf,
+        RowLength::new(core::num::NonZero::new(row_len).unwrap()),
+        RowPitch::new(core::num::NonZero::new(row_len).unwrap()),
+    );
+    decoder.decode(&mut output)?;
+    Ok(buf)
+}
+
+/// The planes interleaved, each upscaled to the image size by `shifts`.
+fn interleave(
+    planes: &[Vec<Vec<i32>>; NUM_PLANES],
+    shifts: [u32; NUM_PLANES],
+    width: usize,
+    height: usize,
+) -> Vec<u16> {
+    let mut out = vec![];
+    for row in 0..height {
+        for col in 0..width {
+            for (plane, shift) in planes.iter().zip(shifts) {
+                let sample = *plane
+                    .get(row >> shift)
+                    .unwrap()
+                    .get(col >> shift)
+                    .unwrap();
+                out.push(u16::try_from(sample).unwrap());
+            }
+        }
+    }
+    out
+}
+
+#[test]
+fn true_test() {
+    let planes = [0, 1, 2].map(|plane| samples(5, 3, plane));
+    let image = Image::new(&planes);
+    assert_eq!(
+        decode(&image.build(), 5, 3, false),
+        Ok(interleave(&planes, [0; NUM_PLANES], 5, 3))
+    );
+}
+
+#[test]
+fn quattro_test() {
+    let planes = [samples(3, 2, 0), samples(3, 2, 1), samples(5, 3, 2)];
+    let mut image = Image::new(&planes);
+    image.quattro_dims = Some([(3, 2), (3, 2), (5, 3)]);
+    assert_eq!(
+        decode(&image.build(), 5, 3, true),
+        Ok(interleave(&planes, [1, 1, 0], 5, 3))
+    );
+}
+
+#[test]
+fn invalid_plane_dimensions_test() {
+    let planes = [0, 1, 2].map(|plane| samples(5, 3, plane));
+    let mut image = Image::new(&planes);
+    image.quattro_dims = Some([(5, 3), (2, 2), (5, 3)]);
+    assert_eq!(
+        decode(&image.build(), 5, 3, true),
+        Err(SigmaError::InvalidPlaneDimensions)
+    );
+}
+
+#[test]
+fn invalid_huffman_table_test() {
+    let planes = [0, 1, 2].map(|plane| samples(4, 2, plane));
+    let mut non_canonical = Image::new(&planes);
+    // Swap the codes of the first two symbols.
+    let [first, second] =
+        [1, 3].map(|index| *non_canonical.table.get(index).unwrap());
+    *non_canonical.table.get_mut(1).unwrap() = second;
+    *non_canonical.table.get_mut(3).unwrap() = first;
+    assert_eq!(
+        decode(&non_canonical.build(), 4, 2, false),
+        Err(SigmaError::InvalidHuffmanTable)
+    );
+    let mut too_long = Image::new(&planes);
+    *too_long.table.first_mut().unwrap() = 9;
+    assert_eq!(
+        decode(&too_long.build(), 4, 2, false),
+        Err(SigmaError::InvalidHuffmanTable)
+    );
+}
+
+#[test]
+fn truncated_test() {
+    let planes = [0, 1, 2].map(|plane| samples(4, 2, plane));
+    let data = Image::new(&planes).build();
+    assert_eq!(
+        decode(data.get(..data.len() - 17).unwrap(), 4, 2, false),
+        Err(SigmaError::TruncatedData)
+    );
+    assert_eq!(
+        decode(data.get(..20).unwrap(), 4, 2, false),
+        Err(SigmaError::UnexpectedEndOfInput)
+    );
+    // The plane holds the samples of a smaller image.
+    let mut short = Image::new(&planes);
+    short.planes = [0, 1, 2].map(|_| vec![]);
+    assert_eq!(
+        decode(&short.build(), 4, 2, false),
+        Err(SigmaError::TruncatedData)
+    );
+}
+
+#[test]
+fn value_out_of_bounds_test() {
+    let planes = [0, 1, 2].map(|plane| samples(4, 2, plane));
+    let mut image = Image::new(&planes);
+    image.seeds = [0; NUM_PLANES];
+    assert_eq!(
+        decode(&image.build(), 4, 2, false),
+        Err(SigmaError::ValueOutOfBounds)
+    );
+}
+
+#[test]
+fn output_dimensions_mismatch_test() {
+    let planes = [0, 1, 2].map(|plane| samples(4, 2, plane));
+    let data = Image::new(&planes).build();
+    let decoder = TrueDecompressor::new(&data, dims(4, 2), false).unwrap();
+    let mut buf = vec![0; 8];
+    let mut output = Array2DRefMut::new(
+        &mut buf,
+        RowLength::new(core::num::NonZero::new(4).unwrap()),
+        RowPitch::new(core::num::NonZero::new(4).unwrap()),
+    );
+    assert_eq!(
+        decoder.decode(&mut output),
+        Err(SigmaError::OutputDimensionsMismatch)
+    );
+}
@@ -0,0 +1,258 @@
+use rawspeed_bitstream_bitstream_decoder::bitstreamer::{
+    BitStream, BitStreamerBase,
+};
+use rawspeed_bitstream_bitstreams::bitstreams::{BitOrderLSB, BitOrderMSB};
+use rawspeed_common_bitseq::bitseq::BitSeqConstraints;
+use rawspeed_std::coord_common::{Dimensions2D, RowIndex};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+
+/// The number of entries of the tone curve.
+pub const CURVE_LEN: usize = 0x4001;
+
+/// The last of the curve knots, which is not stored.
+const CURVE_END: usize = 4095;
+
+/// The largest ARW1 sample.
+const ARW1_MAX_SAMPLE: i32 = 0xfff;
+
+/// The largest ARW2 sample, before the curve.
+const ARW2_MAX_SAMPLE: u32 = 0x7ff;
+
+/// The number of pixels (all of the same color) in an ARW2 block.
+const ARW2_BLOCK_LEN: usize = 16;
+
+/// ARW2 blocks are interleaved over groups of this many pixels.
+const ARW2_GROUP_LEN: usize = 2 * ARW2_BLOCK_LEN;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SonyError {
+    InvalidWidth,
+    InvalidCurve,
+    InvalidSample,
+    TruncatedData,
+    OutputDimensionsMismatch,
+}
+
+impl core::fmt::Display for SonyError {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SonyError::InvalidWidth => write!(f, "SonyError(InvalidWidth)"),
+            SonyError::InvalidCurve => write!(f, "SonyError(InvalidCurve)"),
+            SonyError::InvalidSample => write!(f, "SonyError(InvalidSample)"),
+            SonyError::TruncatedData => write!(f, "SonyError(TruncatedData)"),
+            SonyError::OutputDimensionsMismatch => {
+                write!(f, "SonyError(OutputDimensionsMismatch)")
+            }
+        }
+    }
+}
+
+fn get_bits<B>(bs: &mut B, nbits: u32) -> Result<u32, SonyError>
+where
+    B: BitStream,
+    B::T: BitSeqConstraints + core::fmt::Debug,
+    u64: From<B::T>,
+{
+    bs.fill(nbits).map_err(|_err| SonyError::TruncatedData)?;
+    let bits = u64::from(bs.peek_bits_no_fill(nbits).zext());
+    bs.skip_bits_no_fill(nbits);
+    Ok(u32::try_from(bits).unwrap())
+}
+
+/// Builds the tone curve from the four knots of the `SonyCurve` tag.
+///
+/// Between consecutive knots, the slope doubles, starting from 1.
+#[inline]
+#[must_use]
+pub fn make_curve(knots: [u16; 4]) -> Vec<u16> {
+    let mut bounds = [0, 0, 0, 0, 0, CURVE_END];
+    for (bound, knot) in bounds.iter_mut().skip(1).zip(knots) {
+        *bound = usize::from((knot >> 2) & 0xfff);
+    }
+    let mut curve: Vec<u16> =
+        (0..CURVE_LEN).map(|v| u16::try_from(v).unwrap()).collect();
+    for (slope, segment) in bounds.windows(2).enumerate() {
+        let [start, end] =
+            [segment.first(), segment.last()].map(|b| *b.unwrap());
+        for index in start + 1..=end {
+            let prev = *curve.get(index - 1).unwrap();
+            *curve.get_mut(index).unwrap() = prev + (1 << slope);
+        }
+    }
+    curve
+}
+
+/// The length of an ARW1 difference: a prefix code, where the longest
+/// lengths are unary-coded.
+fn decode_arw1_len(
+    bs: &mut BitStreamerBase<'_, BitOrderMSB>,
+) -> Result<u32, SonyError> {
+    let mut len = 4 - get_bits(bs, 2)?;
+    if len == 3 && get_bits(bs, 1)? != 0 {
+        len = 0;
+    }
+    if len == 4 {
+        while len < 17 && get_bits(bs, 1)? == 0 {
+            len += 1;
+        }
+    }
+    Ok(len)
+}
+
+fn decode_arw1_difference(
+    bs: &mut BitStreamerBase<'_, BitOrderMSB>,
+) -> Result<i32, SonyError> {
+    let len = decode_arw1_len(bs)?;
+    if len == 0 {
+        return Ok(0);
+    }
+    let diff = i32::try_from(get_bits(bs, len)?).unwrap();
+    if diff & (1 << (len - 1)) == 0 {
+        Ok(diff - (1 << len) + 1)
+    } else {
+        Ok(diff)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+#[must_use]
+pub struct Arw1Decompressor<'a> {
+    input: &'a [u8],
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+}
+
+impl<'a> Arw1Decompressor<'a> {
+    #[inline]
+    pub const fn new(
+        input: &'a [u8],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+    ) -> Self {
+        Self { input, dims }
+    }
+
+    /// The image is stored column by column, right to left, each column
+    /// holding its even rows, then its odd rows. Every sample is a
+    /// difference to the previous one.
+    #[inline(never)]
+    pub fn decode(
+        &self,
+        output: &mut Array2DRefMut<'_, u16>,
+    ) -> Result<(), SonyError> {
+        if output.dims() != self.dims {
+            return Err(SonyError::OutputDimensionsMismatch);
+        }
+        let mut bs = BitStreamerBase::<BitOrderMSB>::try_from(self.input)
+            .map_err(|_err| SonyError::TruncatedData)?;
+
+        let height = self.dims.row_count().get();
+        let mut sum = 0;
+        for col in (0..self.dims.row_len().get()).rev() {
+            for row in (0..height).step_by(2).chain((1..height).step_by(2)) {
+                sum += decode_arw1_difference(&mut bs)?;
+                if !(0..=ARW1_MAX_SAMPLE).contains(&sum) {
+                    return Err(SonyError::InvalidSample);
+                }
+                let out = output.get_row_mut(RowIndex::new(row)).unwrap();
+                *out.get_mut(col).unwrap() = u16::try_from(sum).unwrap();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decodes an ARW2 block: the maximum and minimum samples and where
+/// they are, followed by the others as 7-bit offsets from the minimum,
+/// scaled to span the range between the two.
+fn decode_arw2_block(
+    bs: &mut BitStreamerBase<'_, BitOrderLSB>,
+) -> Result<[u32; ARW2_BLOCK_LEN], SonyError> {
+    let max = get_bits(bs, 11)?;
+    let min = get_bits(bs, 11)?;
+    let imax = usize::try_from(get_bits(bs, 4)?).unwrap();
+    let imin = usize::try_from(get_bits(bs, 4)?).unwrap();
+    let shift = (0..4)
+        .find(|shift| (0x80 << shift) > max.saturating_sub(min))
+        .unwrap_or(4);
+    let mut samples = [0; ARW2_BLOCK_LEN];
+    for (index, sample) in samples.iter_mut().enumerate() {
+        *sample = if index == imax {
+            max
+        } else if index == imin {
+            min
+        } else {
+            ((get_bits(bs, 7)? << shift) + min).min(ARW2_MAX_SAMPLE)
+        };
+    }
+    Ok(samples)
+}
+
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+#[must_use]
+pub struct Arw2Decompressor<'a> {
+    input: &'a [u8],
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    curve: Vec<u16>,
+}
+
+impl<'a> Arw2Decompressor<'a> {
+    /// Each row takes one byte per pixel, and its samples are looked up
+    /// in the `curve` (see [`make_curve`]).
+    #[inline]
+    pub fn new(
+        input: &'a [u8],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        curve: Vec<u16>,
+    ) -> Result<Self, SonyError> {
+        let width = dims.row_len().get();
+        if !width.is_multiple_of(ARW2_GROUP_LEN) {
+            return Err(SonyError::InvalidWidth);
+        }
+        if curve.len() <= usize::try_from(ARW2_MAX_SAMPLE << 1).unwrap() {
+            return Err(SonyError::InvalidCurve);
+        }
+        if input.len() / width < dims.row_count().get() {
+            return Err(SonyError::TruncatedData);
+        }
+        Ok(Self { input, dims, curve })
+    }
+
+    /// Within each group of 32 pixels, the first block holds the even
+    /// pixels, and the second one the odd pixels.
+    #[inline(never)]
+    pub fn decode(
+        &self,
+        output: &mut Array2DRefMut<'_, u16>,
+    ) -> Result<(), SonyError> {
+        if output.dims() != self.dims {
+            return Err(SonyError::OutputDimensionsMismatch);
+        }
+        let width = self.dims.row_len().get();
+        for (row, input) in self
+            .input
+            .chunks_exact(width)
+            .take(self.dims.row_count().get())
+            .enumerate()
+        {
+            let mut bs = BitStreamerBase::<BitOrderLSB>::try_from(input)
+                .map_err(|_err| SonyError::TruncatedData)?;
+            let out = output.get_row_mut(RowIndex::new(row)).unwrap();
+            for block in 0..width / ARW2_BLOCK_LEN {
+                let first = (block / 2) * ARW2_GROUP_LEN + block % 2;
+                let samples = decode_arw2_block(&mut bs)?;
+                for (index, sample) in samples.iter().enumerate() {
+                    let curve_index = usize::try_from(sample << 1).unwrap();
+                    *out.get_mut(first + 2 * index).unwrap() =
+                        *self.curve.get(curve_index).unwrap();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
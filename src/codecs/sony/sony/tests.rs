@@ -0,0 +1,257 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_std::coord_common::{
+    Dimensions2D, RowCount, RowIndex, RowLength, RowPitch,
+};
+use rawspeed_std_ndslice::array2drefmut::Array2DRefMut;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+
+use super::{
+    Arw1Decompressor, Arw2Decompressor, CURVE_LEN, SonyError, make_curve,
+};
+
+fn put_arw1_difference(bits: &mut BitStreamBuilder, diff: i32) {
+    let len = 32 - diff.unsigned_abs().leading_zeros();
+    match len {
+        0 => bits.put(0b011, 3),
+        1 => bits.put(0b11, 2),
+        2 => bits.put(0b10, 2),
+        3 => bits.put(0b010, 3),
+        17 => bits.put(0, 15),
+        _ => {
+            bits.put(0, 2);
+            bits.put(1, len - 3);
+        }
+    }
+    if len != 0 {
+        let value = if diff < 0 {
+            diff + (1 << len) - 1
+        } else {
+            diff
+        };
+        bits.put(u32::try_from(value).unwrap(), len);
+    }
+}
+
+/// Encodes the samples column by column, right to left, even rows first.
+fn encode_arw1(samples: &[Vec<u16>]) -> Vec<u8> {
+    let mut bits = BitStreamBuilder::new();
+    let mut prev = 0;
+    let height = samples.len();
+    for col in (0..samples.first().unwrap().len()).rev() {
+        for row in (0..height).step_by(2).chain((1..height).step_by(2)) {
+            let sample =
+                i32::from(*samples.get(row).unwrap().get(col).unwrap());
+            put_arw1_difference(&mut bits, sample - prev);
+            prev = sample;
+        }
+    }
+    bits.finish(BitOrder::MSB)
+}
+
+fn put_arw2_block(bits: &mut BitStreamBuilder, samples: &[u32]) {
+    let max = *samples.iter().max().unwrap();
+    let min = *samples.iter().min().unwrap();
+    let imax = samples.iter().position(|s| *s == max).unwrap();
+    let imin = samples.iter().rposition(|s| *s == min).unwrap();
+    let shift = (0..4).find(|s| (0x80 << s) > max - min).unwrap_or(4);
+    bits.put(max, 11);
+    bits.put(min, 11);
+    bits.put(u32::try_from(imax).unwrap(), 4);
+    bits.put(u32::try_from(imin).unwrap(), 4);
+    for (index, sample) in samples.iter().enumerate() {
+        if index != imax && index != imin {
+            assert_eq!((sample - min) % (1 << shift), 0);
+            bits.put((sample - min) >> shift, 7);
+        }
+    }
+}
+
+/// Encodes rows of (pre-curve) samples, which must be representable.
+fn encode_arw2(samples: &[Vec<u32>]) -> Vec<u8> {
+    let mut bits = BitStreamBuilder::new();
+    for row in samples {
+        for group in row.chunks(32) {
+            for parity in 0..2 {
+                let block: Vec<u32> =
+                    group.iter().skip(parity).step_by(2).copied().collect();
+                put_arw2_block(&mut bits, &block);
+            }
+        }
+    }
+    bits.finish(BitOrder::LSB)
+}
+
+fn dims(
+    width: usize,
+    height: usize,
+) -> Dimensions2D<core::num::NonZero<usize>> {
+    Dimensions2D::new(
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowCount::new(core::num::NonZero::new(height).unwrap()),
+    )
+}
+
+fn output(buf: &mut [u16], width: usize) -> Array2DRefMut<'_, u16> {
+    Array2DRefMut::new(
+        buf,
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowPitch::new(core::num::NonZero::new(width).unwrap()),
+    )
+}
+
+fn decode_arw1(
+    data: &[u8],
+    width: usize,
+    height: usize,
+) -> Result<Vec<u16>, SonyError> {
+    let mut buf = vec![0; width * height];
+    Arw1Decompressor::new(data, dims(width, height))
+        .decode(&mut output(&mut buf, width))?;
+    Ok(buf)
+}
+
+fn identity_curve() -> Vec<u16> {
+    (0..CURVE_LEN).map(|v| u16::try_from(v).unwrap()).collect()
+}
+
+fn decode_arw2(
+    data: &[u8],
+    width: usize,
+    height: usize,
+) -> Result<Vec<u16>, SonyError> {
+    let decoder =
+        Arw2Decompressor::new(data, dims(width, height), identity_curve())?;
+    let mut buf = vec![0; width * height];
+    let mut out = output(&mut buf, width);
+    decoder.decode(&mut out)?;
+    assert_eq!(out.get_row_mut(RowIndex::new(0)).unwrap().len(), width);
+    Ok(buf)
+}
+
+#[test]
+fn curve_test() {
+    let curve = make_curve([400, 800, 1200, 1601]);
+    assert_eq!(curve.len(), CURVE_LEN);
+    assert_eq!(curve.get(..101), Some(identity_curve().get(..101).unwrap()));
+    assert_eq!(curve.get(200), Some(&300));
+    assert_eq!(curve.get(300), Some(&700));
+    assert_eq!(curve.get(400), Some(&1500));
+    assert_eq!(curve.get(401), Some(&1516));
+    assert_eq!(curve.get(4095), Some(&60620));
+    assert_eq!(curve.get(4096), Some(&4096));
+}
+
+#[test]
+fn arw1_test() {
+    let samples = vec![
+        vec![0, 1, 2, 3],
+        vec![4095, 4095, 0, 7],
+        vec![100, 1000, 3000, 3],
+        vec![5, 70, 600, 2048],
+    ];
+    assert_eq!(
+        decode_arw1(&encode_arw1(&samples), 4, 4),
+        Ok(samples.concat())
+    );
+}
+
+#[test]
+fn arw1_invalid_sample_test() {
+    let mut bits = BitStreamBuilder::new();
+    put_arw1_difference(&mut bits, 4000);
+    put_arw1_difference(&mut bits, 100);
+    assert_eq!(
+        decode_arw1(&bits.finish(BitOrder::MSB), 1, 2),
+        Err(SonyError::InvalidSample)
+    );
+    let mut negative = BitStreamBuilder::new();
+    put_arw1_difference(&mut negative, -1);
+    assert_eq!(
+        decode_arw1(&negative.finish(BitOrder::MSB), 1, 1),
+        Err(SonyError::InvalidSample)
+    );
+}
+
+#[test]
+fn arw1_truncated_test() {
+    assert_eq!(decode_arw1(&[], 8, 8), Err(SonyError::TruncatedData));
+}
+
+#[test]
+fn arw2_test() {
+    let samples: Vec<Vec<u32>> = (0..2)
+        .map(|row| {
+            (0..64)
+                .map(|col| match (row, col % 4) {
+                    (0, _) => 100 + col * 4,
+                    (_, 0) => 2040,
+                    (_, 1) => 32,
+                    (_, 2) => 1024 + (col % 3) * 16,
+                    _ => 32 + (col % 3) * 160,
+                })
+                .collect()
+        })
+        .collect();
+    let expected: Vec<u16> = samples
+        .iter()
+        .flatten()
+        .map(|s| u16::try_from(s * 2).unwrap())
+        .collect();
+    assert_eq!(decode_arw2(&encode_arw2(&samples), 64, 2), Ok(expected));
+}
+
+#[test]
+fn arw2_clamp_test() {
+    let mut bits = BitStreamBuilder::new();
+    for _ in 0..2 {
+        bits.put(2047, 11);
+        bits.put(2000, 11);
+        bits.put(0, 4);
+        bits.put(1, 4);
+        for _ in 0..14 {
+            bits.put(100, 7);
+        }
+    }
+    let decoded = decode_arw2(&bits.finish(BitOrder::LSB), 32, 1).unwrap();
+    assert_eq!(decoded.get(..4), Some([4094, 4094, 4000, 4000].as_slice()));
+    assert!(decoded.iter().skip(4).all(|s| *s == 4094));
+}
+
+#[test]
+fn arw2_invalid_width_test() {
+    assert_eq!(
+        Arw2Decompressor::new(&[0; 48], dims(48, 1), identity_curve())
+            .map(|_| ()),
+        Err(SonyError::InvalidWidth)
+    );
+}
+
+#[test]
+fn arw2_invalid_curve_test() {
+    assert_eq!(
+        Arw2Decompressor::new(&[0; 32], dims(32, 1), vec![0; 0xffe])
+            .map(|_| ()),
+        Err(SonyError::InvalidCurve)
+    );
+}
+
+#[test]
+fn arw2_truncated_test() {
+    assert_eq!(decode_arw2(&[0; 63], 32, 2), Err(SonyError::TruncatedData));
+}
+
+#[test]
+fn output_dimensions_mismatch_test() {
+    let mut buf = vec![0; 64];
+    let decoder =
+        Arw2Decompressor::new(&[0; 64], dims(32, 2), identity_curve()).unwrap();
+    assert_eq!(
+        decoder.decode(&mut output(&mut buf, 64)),
+        Err(SonyError::OutputDimensionsMismatch)
+    );
+    assert_eq!(
+        Arw1Decompressor::new(&[0; 4], dims(2, 2))
+            .decode(&mut output(&mut buf, 64)),
+        Err(SonyError::OutputDimensionsMismatch)
+    );
+}
@@ -103,6 +103,7 @@ impl_to_bytes!(u8);
 impl_to_bytes!(u16);
 impl_to_bytes!(u32);
 impl_to_bytes!(u64);
+impl_to_bytes!(f32);
 
 pub trait FromNeBytes {
     type BytesTy;
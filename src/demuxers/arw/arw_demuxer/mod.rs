@@ -0,0 +1,579 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_bitstream_bytestream_decoder::bytestreamer::ByteStreamer;
+use rawspeed_codecs_packed_decoder::packed_decoder::Unpacker;
+use rawspeed_codecs_sony::sony::{
+    Arw1Decompressor, Arw2Decompressor, make_curve,
+};
+use rawspeed_demuxers_common::{
+    camera_metadata::CameraMetadata,
+    tiff_utils::{
+        OwnedArray2D, get_root_string, get_strip, get_u32, get_usize, non_zero,
+        tiff_err,
+    },
+};
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::{
+    DataType, ImageRefMut, ProcurementRequest, RawDemuxer, RawDemuxerError,
+};
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_memory_nd_slice_procurement::ndsliceprocurement::NDSliceProcurementRequest;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::{
+    Cameras, Supported, blackareas::BlackArea,
+};
+use rawspeed_metadata_colorfilterarray::colorfilterarray::ColorVariant;
+use rawspeed_parsers_tiffparser::tiffparser::{
+    TiffEntry, TiffIFD, TiffParser, TiffRootIFD, TiffTag,
+};
+use rawspeed_std::coord_common::{
+    Coord2D, Dimensions2D, RowCount, RowLength, RowPitch,
+};
+use rawspeed_std_ndslice::{
+    array2dref::Array2DRef, offsetarray2dref::OffsetArray2DRef,
+};
+
+const COMPRESSION_NONE: u32 = 1;
+const COMPRESSION_SONY: u32 = 0x7FFF;
+
+/// ARW1 images are 8 rows taller than what their IFD says.
+const ARW1_EXTRA_ROWS: usize = 8;
+
+/// The camera levels are those of the 14-bit data.
+const LEVEL_BITS: u32 = 14;
+
+/// Pixel shift composites (ARQ) hold all four CFA samples of each pixel.
+const PIXEL_SHIFT_CPP: usize = 4;
+
+/// The length of the pad of the SR2 cipher, in 32-bit words.
+const PAD_LEN: usize = 128;
+
+type T = u16;
+
+fn read_u32(data: &[u8], endianness: Endianness) -> Option<u32> {
+    data.get(..size_of::<u32>())
+        .map(|bytes| ByteStreamer::new(bytes, endianness).read())
+}
+
+/// Sony's stream cipher, which is its own inverse: the input is XOR-ed
+/// with a keystream of 32-bit (big-endian) words, each the XOR of two
+/// of the previous 128 ones, which are seeded from the `key`.
+pub(crate) fn sony_decrypt(input: &[u8], mut key: u32) -> Vec<u8> {
+    let mut pad = [0_u32; PAD_LEN];
+    for word in pad.iter_mut().take(4) {
+        key = key.wrapping_mul(48_828_125).wrapping_add(1);
+        *word = key;
+    }
+    pad[3] = (pad[3] << 1) | ((pad[0] ^ pad[2]) >> 31);
+    for index in 4..PAD_LEN - 1 {
+        let word = |offset: usize| *pad.get(index - offset).unwrap();
+        *pad.get_mut(index).unwrap() =
+            ((word(4) ^ word(2)) << 1) | ((word(3) ^ word(1)) >> 31);
+    }
+    let mut out = Vec::with_capacity(input.len());
+    for (index, chunk) in (PAD_LEN - 1..).zip(input.chunks_exact(4)) {
+        let word =
+            |offset: usize| *pad.get((index + offset) % PAD_LEN).unwrap();
+        let next = word(1) ^ word(PAD_LEN / 2 + 1);
+        *pad.get_mut(index % PAD_LEN).unwrap() = next;
+        out.extend(chunk.iter().zip([24, 16, 8, 0]).map(|(byte, shift)| {
+            byte ^ u8::try_from((next >> shift) & 0xFF).unwrap()
+        }));
+    }
+    out
+}
+
+/// The SR2 private data, which `DNGPrivateData` points to, holds the
+/// location, length and key of an encrypted IFD. The decrypted IFD is
+/// returned along with the (zeroed) bytes preceding it, since its
+/// offsets are relative to the start of the file.
+fn decrypt_sr2_sub_ifd(
+    root: &TiffRootIFD<'_>,
+) -> Result<Option<(Vec<u8>, usize)>, String> {
+    let Some(entry) = root.get_entry_recursive(TiffTag::DNG_PRIVATE_DATA)
+    else {
+        return Ok(None);
+    };
+    let private_offset = read_u32(entry.data(), entry.endianness())
+        .ok_or("The ARW private data is truncated")?;
+    let private = TiffParser::parse_ifd_chain(
+        root.input(),
+        root.endianness(),
+        usize::try_from(private_offset).unwrap(),
+    )
+    .map_err(tiff_err)?;
+    let [Some(offset), Some(length), Some(key)] = [
+        TiffTag::SONY_SR2_SUB_IFD_OFFSET,
+        TiffTag::SONY_SR2_SUB_IFD_LENGTH,
+        TiffTag::SONY_SR2_SUB_IFD_KEY,
+    ]
+    .map(|tag| private.get_entry_recursive(tag)) else {
+        return Ok(None);
+    };
+    let offset = usize::try_from(offset.get_u32(0).map_err(tiff_err)?).unwrap();
+    // Only whole words are encrypted.
+    let length = usize::try_from(length.get_u32(0).map_err(tiff_err)?).unwrap()
+        / size_of::<u32>()
+        * size_of::<u32>();
+    let key = Some(key)
+        .filter(|key| key.count() == 4)
+        .and_then(|key| read_u32(key.data(), Endianness::Little))
+        .ok_or("The SR2 key is invalid")?;
+    let encrypted = root
+        .input()
+        .get(offset..)
+        .and_then(|rest| rest.get(..length))
+        .ok_or("The SR2 sub-IFD is truncated")?;
+    let mut data = vec![0; offset];
+    data.extend(sony_decrypt(encrypted, key));
+    Ok(Some((data, offset)))
+}
+
+/// The white balance is stored either in the GRBG or in the RGGB order.
+fn parse_wb_coeffs(sr2: &TiffRootIFD<'_>) -> Result<Option<[f32; 4]>, String> {
+    let (entry, indices) = if let Some(entry) =
+        sr2.get_entry_recursive(TiffTag::SONY_WB_GRBG_LEVELS)
+    {
+        (entry, [1, 0, 2])
+    } else if let Some(entry) =
+        sr2.get_entry_recursive(TiffTag::SONY_WB_RGGB_LEVELS)
+    {
+        (entry, [0, 1, 3])
+    } else {
+        return Ok(None);
+    };
+    if entry.count() != 4 {
+        return Ok(None);
+    }
+    let [red, green, blue] =
+        indices.map(|index| entry.get_f32(index).map_err(tiff_err));
+    Ok(Some([red?, green?, blue?, f32::NAN]))
+}
+
+/// Per-CFA-position black levels, scaled down like the camera ones.
+fn parse_black_levels(
+    sr2: &TiffRootIFD<'_>,
+    shift: u32,
+) -> Result<Option<OwnedArray2D<i32>>, String> {
+    let Some(entry) = sr2.get_entry_recursive(TiffTag::SONY_BLACK_LEVEL) else {
+        return Ok(None);
+    };
+    if entry.count() != 4 {
+        return Ok(None);
+    }
+    let levels = (0..4)
+        .map(|index| {
+            entry.get_u16(index).map(|level| i32::from(level >> shift))
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(tiff_err)?;
+    OwnedArray2D::new(levels, 2).map(Some)
+}
+
+/// The tone curve of the compressed ARW2 samples.
+fn parse_curve(ifd: &TiffIFD<'_>) -> Result<Vec<u16>, String> {
+    let entry = ifd
+        .get_entry(TiffTag::SONY_CURVE)
+        .ok_or("No tone curve found in ARW")?;
+    let mut knots = [0; 4];
+    for (index, knot) in knots.iter_mut().enumerate() {
+        *knot = entry.get_u16(index).map_err(tiff_err)?;
+    }
+    Ok(make_curve(knots))
+}
+
+/// Some bodies mark their 8-bit compressed data as 12-bit; they have
+/// a second `Make` tag, of exactly `SONY`.
+fn has_second_make(root: &TiffRootIFD<'_>) -> bool {
+    let ifds = root.get_ifds_with_tag(TiffTag::MAKE);
+    ifds.len() > 1
+        && ifds.iter().any(|ifd| {
+            ifd.get_entry(TiffTag::MAKE).map(TiffEntry::get_string)
+                == Some(Ok("SONY"))
+        })
+}
+
+#[derive(Debug)]
+enum Format<'a> {
+    Arw1(Arw1Decompressor<'a>),
+    Arw2(Arw2Decompressor<'a>),
+    Unpacked {
+        input: Array2DRef<'a, u8>,
+        order: BitOrder,
+        bits: u32,
+    },
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct ArwDemuxer<'a> {
+    metadata: CameraMetadata<'a>,
+    format: Format<'a>,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    cpp: core::num::NonZero<usize>,
+    /// By how much the camera levels are scaled down.
+    level_shift: u32,
+    black_levels: Option<OwnedArray2D<i32>>,
+    wb_coeffs: Option<[f32; 4]>,
+}
+
+impl<'a> ArwDemuxer<'a> {
+    fn parse_dims(
+        ifd: &TiffIFD<'_>,
+    ) -> Result<Dimensions2D<core::num::NonZero<usize>>, String> {
+        let width = get_usize(ifd, TiffTag::IMAGE_WIDTH)?.unwrap_or(0);
+        let height = get_usize(ifd, TiffTag::IMAGE_LENGTH)?.unwrap_or(0);
+        Ok(Dimensions2D::new(
+            RowLength::new(non_zero(width, "width")?),
+            RowCount::new(non_zero(height, "height")?),
+        ))
+    }
+
+    fn parse_unpacked(
+        strip: &'a [u8],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        order: BitOrder,
+        bits: u32,
+    ) -> Result<Format<'a>, String> {
+        let (width, height) = (dims.row_len().get(), dims.row_count().get());
+        let row_bits = width * usize::try_from(bits).unwrap();
+        let bytes_per_row = row_bits / 8;
+        // Rows are read in whole chunks of the bit order.
+        if !row_bits.is_multiple_of(8)
+            || !bytes_per_row.is_multiple_of(order.mcu_bytelen())
+        {
+            return Err(format!(
+                "Unsupported ARW row pitch: {}",
+                row_bits.div_ceil(8)
+            ));
+        }
+        let input = strip
+            .get(..bytes_per_row * height)
+            .ok_or("The ARW strip is too small")?;
+        let bytes_per_row = non_zero(bytes_per_row, "row pitch")?;
+        Ok(Format::Unpacked {
+            input: Array2DRef::new(
+                input,
+                RowLength::new(bytes_per_row),
+                RowPitch::new(bytes_per_row),
+            ),
+            order,
+            bits,
+        })
+    }
+
+    /// The compressed data is told apart from the packed one by its size:
+    /// ARW2 and packed data take exactly the given bits per pixel.
+    fn parse_compressed(
+        root: &TiffRootIFD<'_>,
+        ifd: &TiffIFD<'_>,
+        strip: &'a [u8],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+    ) -> Result<(Format<'a>, Dimensions2D<core::num::NonZero<usize>>), String>
+    {
+        let mut bits = get_u32(ifd, TiffTag::BITS_PER_SAMPLE)?.unwrap_or(0);
+        if has_second_make(root) {
+            bits = 8;
+        }
+        let (width, height) = (dims.row_len().get(), dims.row_count().get());
+        if strip.len() * 8 != width * height * usize::try_from(bits).unwrap() {
+            let dims = Dimensions2D::new(
+                dims.row_len(),
+                RowCount::new(non_zero(height + ARW1_EXTRA_ROWS, "height")?),
+            );
+            return Ok((
+                Format::Arw1(Arw1Decompressor::new(strip, dims)),
+                dims,
+            ));
+        }
+        let format = match bits {
+            8 => Arw2Decompressor::new(strip, dims, parse_curve(ifd)?)
+                .map(Format::Arw2)
+                .map_err(|err| err.to_string())?,
+            12 | 14 => Self::parse_unpacked(strip, dims, BitOrder::LSB, bits)?,
+            _ => return Err(format!("Unsupported bits per sample: {bits}")),
+        };
+        Ok((format, dims))
+    }
+
+    fn parse_cpp(
+        ifd: &TiffIFD<'_>,
+    ) -> Result<core::num::NonZero<usize>, String> {
+        let cpp = get_usize(ifd, TiffTag::SAMPLES_PER_PIXEL)?.unwrap_or(1);
+        if cpp != 1 && cpp != PIXEL_SHIFT_CPP {
+            return Err(format!("Unsupported samples per pixel: {cpp}"));
+        }
+        non_zero(cpp, "samples per pixel")
+    }
+
+    #[inline(never)]
+    pub fn new<F>(
+        root: &TiffRootIFD<'a>,
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<(Self, ProcurementRequest), String>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let ifd = root
+            .get_ifds_with_tag(TiffTag::STRIP_OFFSETS)
+            .into_iter()
+            .next()
+            .ok_or("No raw image found in ARW")?;
+        let dims = Self::parse_dims(ifd)?;
+        let compression =
+            get_u32(ifd, TiffTag::COMPRESSION)?.unwrap_or(COMPRESSION_NONE);
+        if compression != COMPRESSION_NONE && compression != COMPRESSION_SONY {
+            return Err(format!("Unsupported ARW compression: {compression}"));
+        }
+
+        let make = get_root_string(root, TiffTag::MAKE).unwrap_or("");
+        let model = get_root_string(root, TiffTag::MODEL).unwrap_or("");
+        let camera = cameras
+            .find(make, model, None)
+            .ok_or_else(|| format!("Unknown camera: {make} {model}"))?;
+        check_camera_support_fn(camera.supported)?;
+        let iso_speed = root
+            .get_entry_recursive(TiffTag::ISO_SPEED_RATINGS)
+            .map(TiffEntry::get_u32s)
+            .and_then(Result::ok)
+            .and_then(|isos| isos.first().copied());
+        let metadata = CameraMetadata::new(camera, make, model, iso_speed);
+
+        let strip = get_strip(root.input(), ifd)?;
+        let cpp = Self::parse_cpp(ifd)?;
+        let (format, dims) = if compression == COMPRESSION_SONY {
+            if cpp.get() != 1 {
+                return Err(format!(
+                    "Unsupported samples per pixel for compressed ARW: {cpp}"
+                ));
+            }
+            Self::parse_compressed(root, ifd, strip, dims)?
+        } else {
+            // Samples are stored in 16-bit containers, in the byte order
+            // of the file.
+            let order = match root.endianness() {
+                Endianness::Little => BitOrder::LSB,
+                Endianness::Big => BitOrder::MSB,
+            };
+            let sample_dims =
+                Dimensions2D::new(
+                    RowLength::new(dims.row_len().checked_mul(cpp).ok_or(
+                        "Overflow when computing per-row sample count",
+                    )?),
+                    dims.row_count(),
+                );
+            (
+                Self::parse_unpacked(strip, sample_dims, order, T::BITS)?,
+                dims,
+            )
+        };
+        let level_shift = match &format {
+            Format::Unpacked { bits, .. } if *bits < LEVEL_BITS => {
+                LEVEL_BITS - bits
+            }
+            Format::Arw1(_) | Format::Arw2(_) | Format::Unpacked { .. } => 0,
+        };
+        let (black_levels, wb_coeffs) = match decrypt_sr2_sub_ifd(root)? {
+            Some((data, offset)) => {
+                let sr2 = TiffParser::parse_ifd_chain(
+                    &data,
+                    root.endianness(),
+                    offset,
+                )
+                .map_err(tiff_err)?;
+                (
+                    parse_black_levels(&sr2, level_shift)?,
+                    parse_wb_coeffs(&sr2)?,
+                )
+            }
+            None => (None, None),
+        };
+        Ok((
+            Self {
+                metadata,
+                format,
+                dims,
+                cpp,
+                level_shift,
+                black_levels,
+                wb_coeffs,
+            },
+            NDSliceProcurementRequest::<T>::new(dims)
+                .set_cpp(cpp)
+                .into(),
+        ))
+    }
+}
+
+#[expect(clippy::missing_trait_methods)]
+impl RawDemuxer for ArwDemuxer<'_> {
+    #[inline]
+    fn make(&self) -> &str {
+        self.metadata.make
+    }
+
+    #[inline]
+    fn model(&self) -> &str {
+        self.metadata.model
+    }
+
+    #[inline]
+    fn mode(&self) -> Option<&str> {
+        self.metadata.mode()
+    }
+
+    #[inline]
+    fn canonical_make(&self) -> &str {
+        self.metadata.canonical_make()
+    }
+
+    #[inline]
+    fn canonical_model(&self) -> &str {
+        self.metadata.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_alias(&self) -> &str {
+        self.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_id(&self) -> String {
+        self.metadata.canonical_id()
+    }
+
+    #[inline]
+    fn iso_speed(&self) -> Option<u32> {
+        self.metadata.iso_speed
+    }
+
+    #[inline]
+    fn blacklevel(&self) -> Option<u16> {
+        self.metadata
+            .blacklevel()
+            .map(|level| level >> self.level_shift)
+    }
+
+    #[inline]
+    fn whitelevel(&self) -> Option<u16> {
+        self.metadata
+            .whitelevel()
+            .map(|level| level >> self.level_shift)
+    }
+
+    #[inline]
+    fn blacklevel_separate(&self) -> Option<Array2DRef<'_, i32>> {
+        self.black_levels.as_ref().map(OwnedArray2D::mat)
+    }
+
+    #[inline]
+    fn wb_coeffs(&self) -> Option<[f32; 4]> {
+        self.wb_coeffs
+    }
+
+    #[inline]
+    fn colormatrix(&self) -> Option<Array2DRef<'_, i16>> {
+        self.metadata.colormatrix()
+    }
+
+    #[inline]
+    fn is_cfa(&self) -> bool {
+        self.cpp.get() == 1 && self.metadata.is_cfa()
+    }
+
+    #[inline]
+    fn cfa(
+        &self,
+        origin: Coord2D,
+    ) -> Option<OffsetArray2DRef<'_, ColorVariant>> {
+        if !self.is_cfa() {
+            return None;
+        }
+        self.metadata.cfa(origin)
+    }
+
+    #[inline]
+    fn bpp(&self) -> usize {
+        let bpc = match self.datatype() {
+            DataType::U16 => size_of::<u16>(),
+            DataType::F32 => size_of::<f32>(),
+            DataType::U32 => size_of::<u32>(),
+            _ => unreachable!(),
+        };
+        bpc.checked_mul(self.cpp()).unwrap()
+    }
+
+    #[inline]
+    fn cpp(&self) -> usize {
+        self.cpp.get()
+    }
+
+    #[inline]
+    fn datatype(&self) -> DataType {
+        DataType::U16
+    }
+
+    #[inline]
+    fn dim_uncropped(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        self.dims
+    }
+
+    #[inline]
+    fn dim_cropped(&self) -> Option<Dimensions2D<core::num::NonZero<usize>>> {
+        self.metadata.dim_cropped(self.dim_uncropped())
+    }
+
+    #[inline]
+    fn crop_offset(&self) -> Option<Coord2D> {
+        self.metadata.crop_offset()
+    }
+
+    #[inline]
+    fn black_areas(&self) -> Option<&[BlackArea]> {
+        self.metadata.black_areas()
+    }
+
+    #[inline]
+    fn fuji_rotation_pos(&self) -> Option<u32> {
+        None
+    }
+
+    #[inline]
+    fn pixel_aspect_ratio(&self) -> Option<f64> {
+        None
+    }
+
+    #[inline]
+    fn bad_pixel_positions(&self) -> Vec<Coord2D> {
+        vec![]
+    }
+
+    #[inline(never)]
+    fn decode(
+        &self,
+        output: &mut ImageRefMut<'_>,
+    ) -> Result<(), RawDemuxerError> {
+        let output = output.as_u16()?;
+        let dims = Dimensions2D::new(
+            RowLength::new(self.dims.row_len().checked_mul(self.cpp).unwrap()),
+            self.dims.row_count(),
+        );
+        if output.dims() != dims {
+            return Err(RawDemuxerError::DecoderError(
+                "Output buffer dimensions differ from expected".to_owned(),
+            ));
+        }
+        match &self.format {
+            Format::Arw1(decompressor) => decompressor.decode(output),
+            Format::Arw2(decompressor) => decompressor.decode(output),
+            Format::Unpacked { input, order, bits } => {
+                Unpacker::new(*input, *order, *bits, output).unpack();
+                Ok(())
+            }
+        }
+        .map_err(|err| RawDemuxerError::DecoderError(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,199 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_codecs_sony::sony::make_curve;
+use rawspeed_parsers_tiffparser::tiffparser::TiffTag;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::pack;
+use rawspeed_utils_tiffbuilder::tiffbuilder::{Value, tag};
+
+use super::{
+    Arw, decode, decode_input, encode_arw1, encode_arw2, new_demuxer_err,
+};
+
+/// Distinct samples, spread over the given bit depth.
+fn rows(width: usize, height: usize, bits: u32) -> Vec<Vec<u16>> {
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| {
+                    let sample = (row * 1009 + col * 257 + 7) % (1 << bits);
+                    u16::try_from(sample).unwrap()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// 11-bit samples that vary little within each ARW2 block.
+fn arw2_rows(width: usize, height: usize) -> Vec<Vec<u16>> {
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| {
+                    let base = (row * 3 + (col / 32) * 2 + col % 2) * 150;
+                    u16::try_from(base + (col * 37) % 127).unwrap()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn apply_curve(rows: &[Vec<u16>]) -> Vec<Vec<u16>> {
+    let curve = make_curve([400, 800, 1200, 1600]);
+    rows.iter()
+        .map(|row| {
+            row.iter()
+                .map(|s| *curve.get(usize::from(*s) << 1).unwrap())
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn arw1_test() {
+    // The IFD is 8 rows short.
+    let rows = rows(4, 10, 12);
+    let data = encode_arw1(&rows);
+    assert_ne!(data.len() * 8, 4 * 2 * 12);
+    assert_eq!(decode(&Arw::compressed(4, 2, 12, data)), Ok(rows));
+}
+
+#[test]
+fn arw2_test() {
+    let rows = arw2_rows(64, 2);
+    let arw = Arw::compressed(64, 2, 8, encode_arw2(&rows));
+    assert_eq!(decode(&arw), Ok(apply_curve(&rows)));
+}
+
+#[test]
+fn arw2_second_make_test() {
+    // Marked as 12-bit, but the size is that of 8-bit data.
+    let rows = arw2_rows(32, 2);
+    let mut builder = Arw::compressed(32, 2, 12, encode_arw2(&rows)).builder();
+    builder
+        .ifd(2)
+        .push(tag(TiffTag::MAKE, Value::Ascii("SONY")));
+    assert_eq!(decode_input(&builder.build()), Ok(apply_curve(&rows)));
+}
+
+#[test]
+fn arw2_without_curve_test() {
+    let mut arw = Arw::compressed(32, 2, 8, vec![0; 64]);
+    arw.curve = None;
+    assert_eq!(new_demuxer_err(&arw.build()), "No tone curve found in ARW");
+}
+
+#[test]
+fn arw2_invalid_width_test() {
+    assert_eq!(
+        new_demuxer_err(&Arw::compressed(48, 1, 8, vec![0; 48]).build()),
+        "SonyError(InvalidWidth)"
+    );
+}
+
+#[test]
+fn packed_12_bit_test() {
+    let rows = rows(8, 2, 12);
+    let arw =
+        Arw::compressed(8, 2, 12, pack(&rows.concat(), 12, BitOrder::LSB));
+    assert_eq!(decode(&arw), Ok(rows));
+}
+
+#[test]
+fn packed_14_bit_test() {
+    let rows = rows(16, 2, 14);
+    let arw =
+        Arw::compressed(16, 2, 14, pack(&rows.concat(), 14, BitOrder::LSB));
+    assert_eq!(decode(&arw), Ok(rows));
+}
+
+#[test]
+fn odd_row_pitch_test() {
+    let rows = rows(6, 2, 12);
+    let arw =
+        Arw::compressed(6, 2, 12, pack(&rows.concat(), 12, BitOrder::LSB));
+    assert_eq!(decode(&arw), Ok(rows));
+}
+
+#[test]
+fn unsupported_row_pitch_test() {
+    let data = pack(&rows(5, 2, 12).concat(), 12, BitOrder::LSB);
+    assert_eq!(
+        new_demuxer_err(&Arw::compressed(5, 2, 12, data).build()),
+        "Unsupported ARW row pitch: 8"
+    );
+}
+
+#[test]
+fn unsupported_bits_test() {
+    assert_eq!(
+        new_demuxer_err(&Arw::compressed(32, 2, 10, vec![0; 80]).build()),
+        "Unsupported bits per sample: 10"
+    );
+}
+
+#[test]
+fn uncompressed_test() {
+    let rows = rows(4, 2, 14);
+    let data = rows.iter().flatten().flat_map(|v| [v & 0xFF, v >> 8]);
+    let data = data.map(|b| u8::try_from(b).unwrap()).collect();
+    assert_eq!(decode(&Arw::uncompressed(4, 2, data)), Ok(rows));
+}
+
+#[test]
+fn pixel_shift_test() {
+    let rows = rows(4 * 4, 2, 14);
+    let data = rows.iter().flatten().flat_map(|v| [v & 0xFF, v >> 8]);
+    let data = data.map(|b| u8::try_from(b).unwrap()).collect();
+    let mut builder = Arw::uncompressed(4, 2, data).builder();
+    builder
+        .ifd(1)
+        .push(tag(TiffTag::SAMPLES_PER_PIXEL, Value::Short(vec![4])));
+    assert_eq!(decode_input(&builder.build()), Ok(rows));
+}
+
+#[test]
+fn unsupported_samples_per_pixel_test() {
+    let mut builder = Arw::uncompressed(4, 2, vec![0; 16 * 3]).builder();
+    builder
+        .ifd(1)
+        .push(tag(TiffTag::SAMPLES_PER_PIXEL, Value::Short(vec![3])));
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "Unsupported samples per pixel: 3"
+    );
+    let mut compressed = Arw::compressed(8, 2, 12, vec![0; 24 * 4]).builder();
+    compressed
+        .ifd(1)
+        .push(tag(TiffTag::SAMPLES_PER_PIXEL, Value::Short(vec![4])));
+    assert_eq!(
+        new_demuxer_err(&compressed.build()),
+        "Unsupported samples per pixel for compressed ARW: 4"
+    );
+}
+
+#[test]
+fn strip_too_small_test() {
+    assert_eq!(
+        new_demuxer_err(&Arw::uncompressed(4, 2, vec![0; 12]).build()),
+        "The ARW strip is too small"
+    );
+}
+
+#[test]
+fn unsupported_compression_test() {
+    let mut arw = Arw::uncompressed(4, 2, vec![0; 16]);
+    arw.compression = 7;
+    assert_eq!(
+        new_demuxer_err(&arw.build()),
+        "Unsupported ARW compression: 7"
+    );
+}
+
+#[test]
+fn no_raw_image_test() {
+    let mut builder = Arw::uncompressed(4, 2, vec![0; 16]).builder();
+    builder.remove(TiffTag::STRIP_OFFSETS);
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "No raw image found in ARW"
+    );
+}
@@ -0,0 +1,188 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_std::coord_common::{ColIndex, Coord2D, RowIndex};
+use rawspeed_utils_tiffbuilder::tiffbuilder::{Value, tag};
+
+use super::{
+    Arw, ArwDemuxer, KEY, new_demuxer_err, parse_cameras, sony_decrypt,
+};
+
+fn uncompressed(model: &'static str) -> Arw {
+    let mut arw = Arw::uncompressed(4, 2, vec![0; 16]);
+    arw.model = model;
+    arw
+}
+
+fn packed_12_bit() -> Arw {
+    Arw::compressed(8, 2, 12, vec![0; 24])
+}
+
+macro_rules! with_demuxer {
+    ($arw:expr, |$demuxer:ident| $body:block) => {{
+        let input = $arw.build();
+        let root = TiffParser::parse(&input).unwrap();
+        let cameras = parse_cameras();
+        let ($demuxer, _) = ArwDemuxer::new(
+            &root,
+            &cameras,
+            DecodeableCamera::new_unless_unsupported,
+        )
+        .unwrap();
+        $body
+    }};
+}
+
+#[test]
+fn camera_from_cameras_xml_test() {
+    with_demuxer!(uncompressed("ILCE-0"), |demuxer| {
+        assert_eq!(demuxer.make(), "SONY");
+        assert_eq!(demuxer.model(), "ILCE-0");
+        assert_eq!(demuxer.mode(), None);
+        assert_eq!(demuxer.canonical_make(), "Sony");
+        assert_eq!(demuxer.canonical_model(), "ILCE-0");
+        assert_eq!(demuxer.canonical_id(), "Sony ILCE-0");
+        assert_eq!(demuxer.iso_speed(), Some(100));
+    });
+}
+
+#[test]
+fn camera_levels_test() {
+    with_demuxer!(uncompressed("ILCE-0"), |demuxer| {
+        assert_eq!(demuxer.blacklevel(), Some(512));
+        assert_eq!(demuxer.whitelevel(), Some(16300));
+        assert!(demuxer.is_cfa());
+    });
+}
+
+#[test]
+fn pixel_shift_is_not_cfa_test() {
+    let mut builder = Arw::uncompressed(4, 2, vec![0; 16 * 4]).builder();
+    builder
+        .ifd(1)
+        .push(tag(TiffTag::SAMPLES_PER_PIXEL, Value::Short(vec![4])));
+    with_demuxer!(builder, |demuxer| {
+        assert_eq!(demuxer.cpp(), 4);
+        assert_eq!(demuxer.bpp(), 4 * 2);
+        assert_eq!(demuxer.dim_uncropped().row_len().get(), 4);
+        assert!(!demuxer.is_cfa());
+        assert!(
+            demuxer
+                .cfa(Coord2D::new(RowIndex::new(0), ColIndex::new(0)))
+                .is_none()
+        );
+    });
+}
+
+#[test]
+fn packed_12_bit_levels_test() {
+    with_demuxer!(packed_12_bit(), |demuxer| {
+        assert_eq!(demuxer.blacklevel(), Some(128));
+        assert_eq!(demuxer.whitelevel(), Some(4075));
+    });
+}
+
+#[test]
+fn grbg_white_balance_test() {
+    let mut arw = uncompressed("ILCE-0");
+    arw.sr2 = vec![tag(
+        TiffTag::SONY_WB_GRBG_LEVELS,
+        Value::Short(vec![1024, 2048, 1536, 1024]),
+    )];
+    with_demuxer!(arw, |demuxer| {
+        let [red, green, blue, fourth] = demuxer.wb_coeffs().unwrap();
+        assert_eq!(
+            [red, green, blue].map(f32::to_bits),
+            [
+                2048.0_f32.to_bits(),
+                1024.0_f32.to_bits(),
+                1536.0_f32.to_bits()
+            ]
+        );
+        assert!(fourth.is_nan());
+        assert!(demuxer.blacklevel_separate().is_none());
+    });
+}
+
+#[test]
+fn rggb_white_balance_test() {
+    let mut arw = uncompressed("ILCE-0");
+    arw.sr2 = vec![tag(
+        TiffTag::SONY_WB_RGGB_LEVELS,
+        Value::Short(vec![2048, 1024, 1020, 1536]),
+    )];
+    with_demuxer!(arw, |demuxer| {
+        let [red, green, blue, _] = demuxer.wb_coeffs().unwrap();
+        assert_eq!(
+            [red, green, blue].map(f32::to_bits),
+            [
+                2048.0_f32.to_bits(),
+                1024.0_f32.to_bits(),
+                1536.0_f32.to_bits()
+            ]
+        );
+    });
+}
+
+#[test]
+fn black_levels_test() {
+    let mut arw = packed_12_bit();
+    arw.sr2 = vec![tag(
+        TiffTag::SONY_BLACK_LEVEL,
+        Value::Short(vec![512, 516, 520, 524]),
+    )];
+    with_demuxer!(arw, |demuxer| {
+        let levels = demuxer.blacklevel_separate().unwrap();
+        let level = |row, col| {
+            levels[Coord2D::new(RowIndex::new(row), ColIndex::new(col))]
+        };
+        assert_eq!(
+            [level(0, 0), level(0, 1), level(1, 0), level(1, 1)],
+            [128, 129, 130, 131]
+        );
+        assert!(demuxer.wb_coeffs().is_none());
+    });
+}
+
+#[test]
+fn no_private_data_test() {
+    with_demuxer!(uncompressed("ILCE-0"), |demuxer| {
+        assert!(demuxer.wb_coeffs().is_none());
+        assert!(demuxer.blacklevel_separate().is_none());
+    });
+}
+
+#[test]
+fn invalid_key_test() {
+    let mut arw = uncompressed("ILCE-0");
+    arw.sr2 = vec![tag(TiffTag::SONY_BLACK_LEVEL, Value::Short(vec![0; 4]))];
+    let mut builder = arw.builder();
+    builder.set(TiffTag::SONY_SR2_SUB_IFD_KEY, &Value::Undefined(vec![0; 2]));
+    assert_eq!(new_demuxer_err(&builder.build()), "The SR2 key is invalid");
+}
+
+#[test]
+fn decrypt_test() {
+    let keystream = sony_decrypt(&[0; 8], KEY);
+    assert_eq!(keystream, [20, 197, 233, 187, 162, 50, 220, 125]);
+    let data: Vec<u8> = (0..=255).collect();
+    assert_eq!(sony_decrypt(&sony_decrypt(&data, KEY), KEY), data);
+    // Trailing bytes of a partial word are dropped.
+    assert_eq!(sony_decrypt(&[0; 7], KEY).len(), 4);
+}
+
+#[test]
+fn unknown_camera_test() {
+    assert_eq!(
+        new_demuxer_err(&uncompressed("ILCE-999").build()),
+        "Unknown camera: SONY ILCE-999"
+    );
+}
+
+#[test]
+fn unsupported_camera_test() {
+    assert_eq!(
+        new_demuxer_err(&uncompressed("Unsupported").build()),
+        "This camera is not supported (explicit)"
+    );
+}
@@ -0,0 +1,261 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::Cameras;
+use rawspeed_metadata_xmlparser::xmlparser;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_std::coord_common::RowIndex;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+use rawspeed_utils_tiffbuilder::tiffbuilder::{TiffBuilder, Value, tag};
+
+use super::{ArwDemuxer, COMPRESSION_NONE, COMPRESSION_SONY, sony_decrypt};
+
+/// The key that the SR2 sub-IFD of the test files is encrypted with.
+const KEY: u32 = 0x1234_5678;
+
+const CAMERAS: &str = "
+    <Cameras>
+        <Camera make=\"SONY\" model=\"ILCE-0\">
+            <ID make=\"Sony\" model=\"ILCE-0\">Sony ILCE-0</ID>
+            <CFA width=\"2\" height=\"2\">
+                <Color x=\"0\" y=\"0\">RED</Color>
+                <Color x=\"1\" y=\"0\">GREEN</Color>
+                <Color x=\"0\" y=\"1\">GREEN</Color>
+                <Color x=\"1\" y=\"1\">BLUE</Color>
+            </CFA>
+            <Sensor black=\"512\" white=\"16300\"/>
+        </Camera>
+        <Camera make=\"SONY\" model=\"Unsupported\" supported=\"no\">
+        </Camera>
+    </Cameras>";
+
+/// Encodes the samples column by column, right to left, even rows first.
+fn encode_arw1(rows: &[Vec<u16>]) -> Vec<u8> {
+    let mut bits = BitStreamBuilder::new();
+    let mut prev = 0;
+    let height = rows.len();
+    for col in (0..rows.first().unwrap().len()).rev() {
+        for row in (0..height).step_by(2).chain((1..height).step_by(2)) {
+            let sample = i32::from(*rows.get(row).unwrap().get(col).unwrap());
+            let diff = sample - prev;
+            prev = sample;
+            let len = 32 - diff.unsigned_abs().leading_zeros();
+            match len {
+                0 => bits.put(0b011, 3),
+                1 => bits.put(0b11, 2),
+                2 => bits.put(0b10, 2),
+                3 => bits.put(0b010, 3),
+                _ => {
+                    bits.put(0, 2);
+                    bits.put(1, len - 3);
+                }
+            }
+            if len != 0 {
+                let value = if diff < 0 {
+                    diff + (1 << len) - 1
+                } else {
+                    diff
+                };
+                bits.put(u32::try_from(value).unwrap(), len);
+            }
+        }
+    }
+    bits.finish(BitOrder::MSB)
+}
+
+/// Encodes rows of 11-bit samples, where the samples of each block
+/// must be within 128 of each other.
+fn encode_arw2(rows: &[Vec<u16>]) -> Vec<u8> {
+    let mut bits = BitStreamBuilder::new();
+    for row in rows {
+        for group in row.chunks(32) {
+            for parity in 0..2 {
+                let block: Vec<u32> = group
+                    .iter()
+                    .skip(parity)
+                    .step_by(2)
+                    .map(|s| u32::from(*s))
+                    .collect();
+                let max = *block.iter().max().unwrap();
+                let min = *block.iter().min().unwrap();
+                assert!(max - min < 0x80);
+                let imax = block.iter().position(|s| *s == max).unwrap();
+                let imin = block.iter().rposition(|s| *s == min).unwrap();
+                bits.put(max, 11);
+                bits.put(min, 11);
+                bits.put(u32::try_from(imax).unwrap(), 4);
+                bits.put(u32::try_from(imin).unwrap(), 4);
+                for (index, sample) in block.iter().enumerate() {
+                    if index != imax && index != imin {
+                        bits.put(sample - min, 7);
+                    }
+                }
+            }
+        }
+    }
+    bits.finish(BitOrder::LSB)
+}
+
+#[derive(Debug)]
+struct Arw {
+    model: &'static str,
+    width: u32,
+    height: u32,
+    bits: u16,
+    compression: u16,
+    data: Vec<u8>,
+    curve: Option<Vec<u16>>,
+    /// The entries of the encrypted SR2 sub-IFD, if any.
+    sr2: Vec<(u16, Value)>,
+}
+
+impl Arw {
+    fn compressed(width: u32, height: u32, bits: u16, data: Vec<u8>) -> Self {
+        Self {
+            model: "ILCE-0",
+            width,
+            height,
+            bits,
+            compression: u16::try_from(COMPRESSION_SONY).unwrap(),
+            data,
+            curve: Some(vec![400, 800, 1200, 1600]),
+            sr2: vec![],
+        }
+    }
+
+    fn uncompressed(width: u32, height: u32, data: Vec<u8>) -> Self {
+        Self {
+            model: "ILCE-0",
+            width,
+            height,
+            bits: 16,
+            compression: u16::try_from(COMPRESSION_NONE).unwrap(),
+            data,
+            curve: None,
+            sr2: vec![],
+        }
+    }
+
+    /// IFD0 holds `SubIFDs` (the raw IFD1), the EXIF IFD2 and the SR2
+    /// private IFD3, which points to the SR2 sub-IFD4. The raw data is
+    /// preceded by a blob of padding.
+    fn builder(&self) -> TiffBuilder {
+        let mut builder = TiffBuilder::new(Endianness::Little);
+        builder.ifd(0).extend([
+            tag(TiffTag::MAKE, Value::Ascii("SONY")),
+            tag(TiffTag::MODEL, Value::Ascii(self.model)),
+            tag(TiffTag::SUB_IFDS, Value::IFDOffset(1)),
+            tag(TiffTag::EXIF_IFD_POINTER, Value::IFDOffset(2)),
+        ]);
+        builder.ifd(1).extend([
+            tag(TiffTag::IMAGE_WIDTH, Value::Long(vec![self.width])),
+            tag(TiffTag::IMAGE_LENGTH, Value::Long(vec![self.height])),
+            tag(TiffTag::BITS_PER_SAMPLE, Value::Short(vec![self.bits])),
+            tag(TiffTag::COMPRESSION, Value::Short(vec![self.compression])),
+            tag(TiffTag::STRIP_OFFSETS, Value::BlobOffsets(vec![1])),
+            tag(TiffTag::STRIP_BYTE_COUNTS, Value::BlobSizes(vec![1])),
+        ]);
+        if let Some(curve) = &self.curve {
+            builder
+                .ifd(1)
+                .push(tag(TiffTag::SONY_CURVE, Value::Short(curve.clone())));
+        }
+        builder
+            .ifd(2)
+            .push(tag(TiffTag::ISO_SPEED_RATINGS, Value::Short(vec![100])));
+        if !self.sr2.is_empty() {
+            builder
+                .ifd(0)
+                .push(tag(TiffTag::DNG_PRIVATE_DATA, Value::IFDOffset(3)));
+            builder.ifd(3).extend([
+                tag(TiffTag::SONY_SR2_SUB_IFD_OFFSET, Value::IFDOffset(4)),
+                tag(TiffTag::SONY_SR2_SUB_IFD_LENGTH, Value::Long(vec![0])),
+                tag(
+                    TiffTag::SONY_SR2_SUB_IFD_KEY,
+                    Value::Undefined(vec![0x78, 0x56, 0x34, 0x12]),
+                ),
+            ]);
+            *builder.ifd(4) = self.sr2.clone();
+        }
+        builder.blobs.push(vec![0; 4]);
+        builder.blobs.push(self.data.clone());
+        builder
+    }
+
+    /// Encrypts the SR2 sub-IFD, along with the padding that follows it
+    /// up to a whole number of words.
+    fn build(&self) -> Vec<u8> {
+        let mut builder = self.builder();
+        let input = builder.build();
+        if self.sr2.is_empty() {
+            return input;
+        }
+        let root = TiffParser::parse(&input).unwrap();
+        let private = root
+            .get_entry_recursive(TiffTag::DNG_PRIVATE_DATA)
+            .unwrap()
+            .get_u32(0)
+            .unwrap();
+        let private = TiffParser::parse_ifd_chain(
+            &input,
+            Endianness::Little,
+            usize::try_from(private).unwrap(),
+        )
+        .unwrap();
+        let offset = private
+            .get_entry_recursive(TiffTag::SONY_SR2_SUB_IFD_OFFSET)
+            .unwrap()
+            .get_u32(0)
+            .unwrap();
+        let offset = usize::try_from(offset).unwrap();
+        let blobs_len: usize = builder.blobs.iter().map(Vec::len).sum();
+        let length = (input.len() - blobs_len - offset).next_multiple_of(4);
+        builder.set(
+            TiffTag::SONY_SR2_SUB_IFD_LENGTH,
+            &Value::Long(vec![u32::try_from(length).unwrap()]),
+        );
+        let mut encrypted_input = builder.build();
+        let range = encrypted_input.get_mut(offset..offset + length).unwrap();
+        let encrypted = sony_decrypt(range, KEY);
+        range.copy_from_slice(&encrypted);
+        encrypted_input
+    }
+}
+
+fn parse_cameras() -> Cameras<'static> {
+    xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap()
+}
+
+fn new_demuxer_err(input: &[u8]) -> String {
+    let root = TiffParser::parse(input).unwrap();
+    let cameras = parse_cameras();
+    ArwDemuxer::new(&root, &cameras, DecodeableCamera::new_unless_unsupported)
+        .unwrap_err()
+}
+
+fn decode(arw: &Arw) -> Result<Vec<Vec<u16>>, String> {
+    decode_input(&arw.build())
+}
+
+fn decode_input(input: &[u8]) -> Result<Vec<Vec<u16>>, String> {
+    let root = TiffParser::parse(input).unwrap();
+    let cameras = parse_cameras();
+    let (demuxer, request) = ArwDemuxer::new(
+        &root,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )?;
+    let mut output_buf = request.fulfill().unwrap();
+    let mut image = output_buf.get_mut();
+    demuxer.decode(&mut image).map_err(|err| err.to_string())?;
+    let output = image.as_u16().unwrap();
+    Ok((0..output.num_rows().get())
+        .map(|row| output.get_row(RowIndex::new(row)).unwrap().to_vec())
+        .collect())
+}
+
+#[cfg(test)]
+mod data;
+#[cfg(test)]
+mod metadata;
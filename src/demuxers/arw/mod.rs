@@ -0,0 +1,2 @@
+pub mod arw_demuxer;
+pub mod srf_demuxer;
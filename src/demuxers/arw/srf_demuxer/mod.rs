@@ -0,0 +1,298 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_bitstream_bytestream_decoder::bytestreamer::ByteStreamer;
+use rawspeed_codecs_packed_decoder::packed_decoder::Unpacker;
+use rawspeed_demuxers_common::{
+    camera_metadata::CameraMetadata,
+    tiff_utils::{OwnedArray2D, get_root_string, get_usize, non_zero},
+};
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::{
+    DataType, ImageRefMut, ProcurementRequest, RawDemuxer, RawDemuxerError,
+};
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_memory_nd_slice_procurement::ndsliceprocurement::NDSliceProcurementRequest;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::{
+    Cameras, Supported, blackareas::BlackArea,
+};
+use rawspeed_metadata_colorfilterarray::colorfilterarray::ColorVariant;
+use rawspeed_parsers_tiffparser::tiffparser::{
+    TiffEntry, TiffIFD, TiffRootIFD, TiffTag,
+};
+use rawspeed_std::coord_common::{Coord2D, Dimensions2D, RowCount, RowLength};
+use rawspeed_std_ndslice::{
+    array2dref::Array2DRef, offsetarray2dref::OffsetArray2DRef,
+};
+
+use crate::arw_demuxer::sony_decrypt;
+
+/// The SRF layout is fixed: the encrypted data, the encrypted header
+/// holding its key, and the table holding the key of that header are
+/// at these offsets.
+const DATA_OFFSET: usize = 862_144;
+const KEY_OFFSET: usize = 200_896;
+const HEAD_OFFSET: usize = 164_600;
+const HEAD_LEN: usize = 40;
+
+/// Where the (little-endian) key of the data is, in the header.
+const HEAD_KEY_OFFSET: usize = 22;
+
+type T = u16;
+
+/// The byte at the key offset is the index of the 32-bit (big-endian)
+/// key of the header, which in turn holds the key of the data.
+fn derive_key(input: &[u8]) -> Option<u32> {
+    let index = usize::from(*input.get(KEY_OFFSET)?);
+    let head_key = input
+        .get(KEY_OFFSET + index * size_of::<u32>()..)
+        .and_then(|data| data.get(..size_of::<u32>()))
+        .map(|bytes| ByteStreamer::new(bytes, Endianness::Big).read())?;
+    let head =
+        sony_decrypt(input.get(HEAD_OFFSET..)?.get(..HEAD_LEN)?, head_key);
+    head.get(HEAD_KEY_OFFSET..)
+        .and_then(|data| data.get(..size_of::<u32>()))
+        .map(|bytes| ByteStreamer::new(bytes, Endianness::Little).read())
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct SrfDemuxer<'a> {
+    metadata: CameraMetadata<'a>,
+    /// The decrypted 16-bit (big-endian) samples.
+    data: OwnedArray2D<u8>,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+}
+
+impl<'a> SrfDemuxer<'a> {
+    /// SRF files have no strips: only the size of the image is stored.
+    fn find_raw<'b>(root: &'b TiffRootIFD<'a>) -> Option<&'b TiffIFD<'a>> {
+        if !root.get_ifds_with_tag(TiffTag::STRIP_OFFSETS).is_empty() {
+            return None;
+        }
+        root.get_ifds_with_tag(TiffTag::IMAGE_WIDTH)
+            .into_iter()
+            .next()
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn is_srf(root: &TiffRootIFD<'_>) -> bool {
+        SrfDemuxer::find_raw(root).is_some()
+    }
+
+    fn decrypt_data(
+        input: &[u8],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+    ) -> Result<OwnedArray2D<u8>, String> {
+        let (width, height) = (dims.row_len().get(), dims.row_count().get());
+        let bytes_per_row = width * size_of::<T>();
+        // The data is decrypted in 32-bit words.
+        if !(bytes_per_row * height).is_multiple_of(size_of::<u32>()) {
+            return Err(format!(
+                "Unsupported SRF dimensions: {width}x{height}"
+            ));
+        }
+        let key = derive_key(input).ok_or("The SRF key is truncated")?;
+        let encrypted = input
+            .get(DATA_OFFSET..)
+            .and_then(|data| data.get(..bytes_per_row * height))
+            .ok_or("The SRF data is truncated")?;
+        OwnedArray2D::new(sony_decrypt(encrypted, key), bytes_per_row)
+    }
+
+    #[inline(never)]
+    pub fn new<F>(
+        root: &TiffRootIFD<'a>,
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<(Self, ProcurementRequest), String>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let ifd = Self::find_raw(root).ok_or("No raw image found in SRF")?;
+        let width = get_usize(ifd, TiffTag::IMAGE_WIDTH)?.unwrap_or(0);
+        let height = get_usize(ifd, TiffTag::IMAGE_LENGTH)?.unwrap_or(0);
+        let dims = Dimensions2D::new(
+            RowLength::new(non_zero(width, "width")?),
+            RowCount::new(non_zero(height, "height")?),
+        );
+
+        let make = get_root_string(root, TiffTag::MAKE).unwrap_or("");
+        let model = get_root_string(root, TiffTag::MODEL).unwrap_or("");
+        let camera = cameras
+            .find(make, model, None)
+            .ok_or_else(|| format!("Unknown camera: {make} {model}"))?;
+        check_camera_support_fn(camera.supported)?;
+        let iso_speed = root
+            .get_entry_recursive(TiffTag::ISO_SPEED_RATINGS)
+            .map(TiffEntry::get_u32s)
+            .and_then(Result::ok)
+            .and_then(|isos| isos.first().copied());
+        let metadata = CameraMetadata::new(camera, make, model, iso_speed);
+
+        let data = Self::decrypt_data(root.input(), dims)?;
+        Ok((
+            Self {
+                metadata,
+                data,
+                dims,
+            },
+            NDSliceProcurementRequest::<T>::new(dims).into(),
+        ))
+    }
+}
+
+#[expect(clippy::missing_trait_methods)]
+impl RawDemuxer for SrfDemuxer<'_> {
+    #[inline]
+    fn make(&self) -> &str {
+        self.metadata.make
+    }
+
+    #[inline]
+    fn model(&self) -> &str {
+        self.metadata.model
+    }
+
+    #[inline]
+    fn mode(&self) -> Option<&str> {
+        self.metadata.mode()
+    }
+
+    #[inline]
+    fn canonical_make(&self) -> &str {
+        self.metadata.canonical_make()
+    }
+
+    #[inline]
+    fn canonical_model(&self) -> &str {
+        self.metadata.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_alias(&self) -> &str {
+        self.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_id(&self) -> String {
+        self.metadata.canonical_id()
+    }
+
+    #[inline]
+    fn iso_speed(&self) -> Option<u32> {
+        self.metadata.iso_speed
+    }
+
+    #[inline]
+    fn blacklevel(&self) -> Option<u16> {
+        self.metadata.blacklevel()
+    }
+
+    #[inline]
+    fn whitelevel(&self) -> Option<u16> {
+        self.metadata.whitelevel()
+    }
+
+    #[inline]
+    fn blacklevel_separate(&self) -> Option<Array2DRef<'_, i32>> {
+        None
+    }
+
+    #[inline]
+    fn wb_coeffs(&self) -> Option<[f32; 4]> {
+        None
+    }
+
+    #[inline]
+    fn colormatrix(&self) -> Option<Array2DRef<'_, i16>> {
+        self.metadata.colormatrix()
+    }
+
+    #[inline]
+    fn is_cfa(&self) -> bool {
+        self.metadata.is_cfa()
+    }
+
+    #[inline]
+    fn cfa(
+        &self,
+        origin: Coord2D,
+    ) -> Option<OffsetArray2DRef<'_, ColorVariant>> {
+        self.metadata.cfa(origin)
+    }
+
+    #[inline]
+    fn bpp(&self) -> usize {
+        let bpc = match self.datatype() {
+            DataType::U16 => size_of::<u16>(),
+            DataType::F32 => size_of::<f32>(),
+            DataType::U32 => size_of::<u32>(),
+            _ => unreachable!(),
+        };
+        bpc.checked_mul(self.cpp()).unwrap()
+    }
+
+    #[inline]
+    fn cpp(&self) -> usize {
+        1
+    }
+
+    #[inline]
+    fn datatype(&self) -> DataType {
+        DataType::U16
+    }
+
+    #[inline]
+    fn dim_uncropped(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        self.dims
+    }
+
+    #[inline]
+    fn dim_cropped(&self) -> Option<Dimensions2D<core::num::NonZero<usize>>> {
+        self.metadata.dim_cropped(self.dim_uncropped())
+    }
+
+    #[inline]
+    fn crop_offset(&self) -> Option<Coord2D> {
+        self.metadata.crop_offset()
+    }
+
+    #[inline]
+    fn black_areas(&self) -> Option<&[BlackArea]> {
+        self.metadata.black_areas()
+    }
+
+    #[inline]
+    fn fuji_rotation_pos(&self) -> Option<u32> {
+        None
+    }
+
+    #[inline]
+    fn pixel_aspect_ratio(&self) -> Option<f64> {
+        None
+    }
+
+    #[inline]
+    fn bad_pixel_positions(&self) -> Vec<Coord2D> {
+        vec![]
+    }
+
+    #[inline(never)]
+    fn decode(
+        &self,
+        output: &mut ImageRefMut<'_>,
+    ) -> Result<(), RawDemuxerError> {
+        let output = output.as_u16()?;
+        if output.dims() != self.dims {
+            return Err(RawDemuxerError::DecoderError(
+                "Output buffer dimensions differ from expected".to_owned(),
+            ));
+        }
+        Unpacker::new(self.data.mat(), BitOrder::MSB, T::BITS, output).unpack();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,55 @@
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_utils_tiffbuilder::tiffbuilder::{TiffBuilder, Value, tag};
+
+use super::{KEY_OFFSET, Srf, SrfDemuxer, decode, new_demuxer_err, rows};
+
+#[test]
+fn decrypt_test() {
+    let rows = rows(6, 3);
+    assert_eq!(decode(&Srf::new(&rows).build()), Ok(rows));
+}
+
+#[test]
+fn odd_width_test() {
+    let rows = rows(5, 4);
+    assert_eq!(decode(&Srf::new(&rows).build()), Ok(rows));
+}
+
+#[test]
+fn unsupported_dimensions_test() {
+    assert_eq!(
+        new_demuxer_err(&Srf::new(&rows(5, 3)).build()),
+        "Unsupported SRF dimensions: 5x3"
+    );
+}
+
+#[test]
+fn truncated_test() {
+    let mut data = Srf::new(&rows(6, 3)).build();
+    data.pop();
+    assert_eq!(new_demuxer_err(&data), "The SRF data is truncated");
+    let mut key = Srf::new(&rows(6, 3)).build();
+    key.truncate(KEY_OFFSET);
+    assert_eq!(new_demuxer_err(&key), "The SRF key is truncated");
+}
+
+#[test]
+fn is_srf_test() {
+    let srf = Srf::new(&rows(6, 3)).build();
+    assert!(SrfDemuxer::is_srf(&TiffParser::parse(&srf).unwrap()));
+    let mut builder = TiffBuilder::new(Endianness::Little);
+    builder.ifd(0).extend([
+        tag(TiffTag::MAKE, Value::Ascii("SONY")),
+        tag(TiffTag::IMAGE_WIDTH, Value::Long(vec![6])),
+        tag(TiffTag::STRIP_OFFSETS, Value::BlobOffsets(vec![0])),
+    ]);
+    builder.blobs = vec![vec![0; 4]];
+    let arw = builder.build();
+    assert!(!SrfDemuxer::is_srf(&TiffParser::parse(&arw).unwrap()));
+    builder.remove(TiffTag::IMAGE_WIDTH);
+    builder.remove(TiffTag::STRIP_OFFSETS);
+    let no_size = builder.build();
+    assert!(!SrfDemuxer::is_srf(&TiffParser::parse(&no_size).unwrap()));
+    assert_eq!(new_demuxer_err(&no_size), "No raw image found in SRF");
+}
@@ -0,0 +1,41 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_parsers_tiffparser::tiffparser::TiffParser;
+
+use super::{Srf, SrfDemuxer, new_demuxer_err, parse_cameras, rows};
+
+#[test]
+fn camera_from_cameras_xml_test() {
+    let input = Srf::new(&rows(6, 2)).build();
+    let root = TiffParser::parse(&input).unwrap();
+    let cameras = parse_cameras();
+    let (demuxer, _) = SrfDemuxer::new(
+        &root,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )
+    .unwrap();
+    assert_eq!(demuxer.make(), "SONY");
+    assert_eq!(demuxer.model(), "DSC-F828");
+    assert_eq!(demuxer.canonical_id(), "Sony DSC-F828");
+    assert_eq!(demuxer.iso_speed(), Some(64));
+    assert_eq!(demuxer.whitelevel(), Some(16383));
+    assert!(demuxer.is_cfa());
+    assert!(demuxer.wb_coeffs().is_none());
+}
+
+#[test]
+fn unknown_camera_test() {
+    let mut srf = Srf::new(&rows(6, 2));
+    srf.model = "DSC-F000";
+    assert_eq!(
+        new_demuxer_err(&srf.build()),
+        "Unknown camera: SONY DSC-F000"
+    );
+    let mut unsupported = Srf::new(&rows(6, 2));
+    unsupported.model = "Unsupported";
+    assert_eq!(
+        new_demuxer_err(&unsupported.build()),
+        "This camera is not supported (explicit)"
+    );
+}
@@ -0,0 +1,147 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::Cameras;
+use rawspeed_metadata_xmlparser::xmlparser;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_std::coord_common::RowIndex;
+use rawspeed_utils_tiffbuilder::tiffbuilder::{TiffBuilder, Value, tag};
+
+use super::{
+    DATA_OFFSET, HEAD_KEY_OFFSET, HEAD_LEN, HEAD_OFFSET, KEY_OFFSET,
+    SrfDemuxer, sony_decrypt,
+};
+
+/// The keys that the header and the data of the test files are
+/// encrypted with.
+const HEAD_KEY: u32 = 0x0BAD_F00D;
+const DATA_KEY: u32 = 0x1234_5678;
+
+/// The index of the header key, in the table at the key offset.
+const KEY_INDEX: u8 = 3;
+
+const CAMERAS: &str = "
+    <Cameras>
+        <Camera make=\"SONY\" model=\"DSC-F828\">
+            <ID make=\"Sony\" model=\"DSC-F828\">Sony DSC-F828</ID>
+            <CFA width=\"2\" height=\"2\">
+                <Color x=\"0\" y=\"0\">RED</Color>
+                <Color x=\"1\" y=\"0\">GREEN</Color>
+                <Color x=\"0\" y=\"1\">GREEN</Color>
+                <Color x=\"1\" y=\"1\">BLUE</Color>
+            </CFA>
+            <Sensor black=\"0\" white=\"16383\"/>
+        </Camera>
+        <Camera make=\"SONY\" model=\"Unsupported\" supported=\"no\">
+        </Camera>
+    </Cameras>";
+
+fn rows(width: usize, height: usize) -> Vec<Vec<u16>> {
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| {
+                    u16::try_from((row * 4099 + col * 1013 + 7) % 0x4000)
+                        .unwrap()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn write(out: &mut [u8], offset: usize, data: &[u8]) {
+    out.get_mut(offset..offset + data.len())
+        .unwrap()
+        .copy_from_slice(data);
+}
+
+#[derive(Debug)]
+struct Srf {
+    model: &'static str,
+    width: u32,
+    height: u32,
+    rows: Vec<Vec<u16>>,
+}
+
+impl Srf {
+    fn new(rows: &[Vec<u16>]) -> Self {
+        Self {
+            model: "DSC-F828",
+            width: u32::try_from(rows.first().unwrap().len()).unwrap(),
+            height: u32::try_from(rows.len()).unwrap(),
+            rows: rows.to_vec(),
+        }
+    }
+
+    fn tiff(&self) -> Vec<u8> {
+        let mut builder = TiffBuilder::new(Endianness::Big);
+        builder.ifd(0).extend([
+            tag(TiffTag::MAKE, Value::Ascii("SONY")),
+            tag(TiffTag::MODEL, Value::Ascii(self.model)),
+            tag(TiffTag::EXIF_IFD_POINTER, Value::IFDOffset(1)),
+            tag(TiffTag::SUB_IFDS, Value::IFDOffset(2)),
+        ]);
+        builder
+            .ifd(1)
+            .push(tag(TiffTag::ISO_SPEED_RATINGS, Value::Short(vec![64])));
+        builder.ifd(2).extend([
+            tag(TiffTag::IMAGE_WIDTH, Value::Long(vec![self.width])),
+            tag(TiffTag::IMAGE_LENGTH, Value::Long(vec![self.height])),
+        ]);
+        builder.build()
+    }
+
+    fn build(&self) -> Vec<u8> {
+        let data: Vec<u8> = self
+            .rows
+            .concat()
+            .into_iter()
+            .flat_map(|sample| sample.to_le_bytes().into_iter().rev())
+            .collect();
+        let mut out = self.tiff();
+        out.resize(DATA_OFFSET + data.len(), 0);
+        let key_table = KEY_OFFSET + usize::from(KEY_INDEX) * size_of::<u32>();
+        write(&mut out, KEY_OFFSET, &[KEY_INDEX]);
+        let head_key: Vec<u8> =
+            HEAD_KEY.to_le_bytes().into_iter().rev().collect();
+        write(&mut out, key_table, &head_key);
+        let mut head = vec![0x5A; HEAD_LEN];
+        write(&mut head, HEAD_KEY_OFFSET, &DATA_KEY.to_le_bytes());
+        write(&mut out, HEAD_OFFSET, &sony_decrypt(&head, HEAD_KEY));
+        write(&mut out, DATA_OFFSET, &sony_decrypt(&data, DATA_KEY));
+        out
+    }
+}
+
+fn parse_cameras() -> Cameras<'static> {
+    xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap()
+}
+
+fn new_demuxer_err(input: &[u8]) -> String {
+    let root = TiffParser::parse(input).unwrap();
+    let cameras = parse_cameras();
+    SrfDemuxer::new(&root, &cameras, DecodeableCamera::new_unless_unsupported)
+        .unwrap_err()
+}
+
+fn decode(input: &[u8]) -> Result<Vec<Vec<u16>>, String> {
+    let root = TiffParser::parse(input).unwrap();
+    let cameras = parse_cameras();
+    let (demuxer, request) = SrfDemuxer::new(
+        &root,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )?;
+    let mut output_buf = request.fulfill().unwrap();
+    let mut image = output_buf.get_mut();
+    demuxer.decode(&mut image).map_err(|err| err.to_string())?;
+    let output = image.as_u16().unwrap();
+    Ok((0..output.num_rows().get())
+        .map(|row| output.get_row(RowIndex::new(row)).unwrap().to_vec())
+        .collect())
+}
+
+#[cfg(test)]
+mod data;
+#[cfg(test)]
+mod metadata;
@@ -0,0 +1,202 @@
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::{
+    Camera, Hints,
+    blackareas::BlackArea,
+    crop::{Height, Width},
+};
+use rawspeed_metadata_colorfilterarray::colorfilterarray::ColorVariant;
+use rawspeed_std::coord_common::{
+    ColIndex, Coord2D, CoordOffset2D, Dimensions2D, RowCount, RowIndex,
+    RowLength,
+};
+use rawspeed_std_ndslice::{
+    array2dref::Array2DRef, offsetarray2dref::OffsetArray2DRef,
+};
+
+#[inline]
+#[must_use]
+pub fn get_hint_with_name<'a>(
+    hints: &Hints<'a>,
+    name: &str,
+) -> Option<&'a str> {
+    hints
+        .iter()
+        .find(|hint| **(hint.name) == name)
+        .map(|hint| **hint.value)
+}
+
+/// The properties of a raw that are described by its `cameras.xml` entry,
+/// for demuxers of formats that do not (reliably) store them in the file.
+/// Self-describing formats may lack an entry, and then only know the make,
+/// model and ISO speed they read from the file.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+#[must_use]
+pub struct CameraMetadata<'a> {
+    pub camera: Option<&'a Camera<'a>>,
+    pub make: &'a str,
+    pub model: &'a str,
+    pub iso_speed: Option<u32>,
+}
+
+impl<'a> CameraMetadata<'a> {
+    #[inline]
+    pub const fn new(
+        camera: &'a Camera<'a>,
+        make: &'a str,
+        model: &'a str,
+        iso_speed: Option<u32>,
+    ) -> Self {
+        Self {
+            camera: Some(camera),
+            make,
+            model,
+            iso_speed,
+        }
+    }
+
+    #[inline]
+    pub const fn without_camera(
+        make: &'a str,
+        model: &'a str,
+        iso_speed: Option<u32>,
+    ) -> Self {
+        Self {
+            camera: None,
+            make,
+            model,
+            iso_speed,
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn hint(&self, name: &str) -> Option<&'a str> {
+        get_hint_with_name(self.camera?.hints.as_ref()?, name)
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn mode(&self) -> Option<&'a str> {
+        self.camera?.mode.map(|v| &***v)
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn canonical_make(&self) -> &'a str {
+        self.camera
+            .and_then(|camera| camera.id)
+            .map_or(self.make, |id| id.make.as_ref())
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn canonical_model(&self) -> &'a str {
+        self.camera
+            .and_then(|camera| camera.id)
+            .map_or(self.model, |id| id.model.as_ref())
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn canonical_id(&self) -> String {
+        self.camera.and_then(|camera| camera.id).map_or_else(
+            || format!("{} {}", self.make, self.model),
+            |id| id.value.to_string(),
+        )
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn blacklevel(&self) -> Option<u16> {
+        let sensor = self.camera?.sensors.get_for_iso(self.iso_speed)?;
+        (**sensor.black).try_into().ok()
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn whitelevel(&self) -> Option<u16> {
+        let sensor = self.camera?.sensors.get_for_iso(self.iso_speed)?;
+        (**sensor.white).try_into().ok()
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn colormatrix(&self) -> Option<Array2DRef<'a, i16>> {
+        self.camera?
+            .colormatrices
+            .as_ref()
+            .map(|mat| mat.value.mat())
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn is_cfa(&self) -> bool {
+        self.camera.is_some_and(|camera| camera.cfa.is_some())
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn cfa(
+        &self,
+        origin: Coord2D,
+    ) -> Option<OffsetArray2DRef<'a, ColorVariant>> {
+        const ZERO_POINT: Coord2D =
+            Coord2D::new(RowIndex::new(0), ColIndex::new(0));
+        let offset = (origin - ZERO_POINT)?;
+        let cfa = self.camera?.cfa.as_ref().map(|cfa| cfa.mat())?;
+        Some(OffsetArray2DRef::new(cfa, offset))
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn crop_offset(&self) -> Option<Coord2D> {
+        Some(*self.camera?.crop?.pos)
+    }
+
+    /// The size of the crop of an image of the given (uncropped) size,
+    /// where non-positive crop sizes are relative to the image size.
+    #[inline]
+    #[must_use]
+    pub fn dim_cropped(
+        &self,
+        dim_uncropped: Dimensions2D<core::num::NonZero<usize>>,
+    ) -> Option<Dimensions2D<core::num::NonZero<usize>>> {
+        const ZERO_POINT: Coord2D =
+            Coord2D::new(RowIndex::new(0), ColIndex::new(0));
+        let crop = self.camera?.crop?;
+        let crop_xy = (*crop.pos - ZERO_POINT)?;
+        let dim_remaining = Coord2D::new(
+            RowIndex::new(dim_uncropped.row_count().get()),
+            ColIndex::new(dim_uncropped.row_len().get()),
+        );
+        let dim_remaining = (dim_remaining - crop_xy)?;
+        let crop_dim = CoordOffset2D::new(
+            match crop.dim.height() {
+                Height::Relative(offset) => offset,
+                Height::Absolute(size) => {
+                    (RowIndex::new(*size) - dim_remaining.row())?
+                }
+                _ => unreachable!(),
+            },
+            match crop.dim.width() {
+                Width::Relative(offset) => offset,
+                Width::Absolute(size) => {
+                    (ColIndex::new(*size) - dim_remaining.col())?
+                }
+                _ => unreachable!(),
+            },
+        );
+        let crop_wh = (-crop_dim)?;
+        let cropped_dim = (dim_remaining - crop_wh)?;
+        Some(Dimensions2D::new(
+            RowLength::new(core::num::NonZero::new(*cropped_dim.col())?),
+            RowCount::new(core::num::NonZero::new(*cropped_dim.row())?),
+        ))
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn black_areas(&self) -> Option<&'a [BlackArea]> {
+        self.camera?.blackareas.as_ref().map(|f| &*f.value.areas)
+    }
+}
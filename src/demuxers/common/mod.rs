@@ -0,0 +1,2 @@
+pub mod camera_metadata;
+pub mod tiff_utils;
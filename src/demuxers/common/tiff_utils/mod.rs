@@ -0,0 +1,118 @@
+use rawspeed_parsers_tiffparser::tiffparser::{
+    TiffEntry, TiffIFD, TiffParserError, TiffRootIFD, TiffTag,
+};
+use rawspeed_std::coord_common::{RowLength, RowPitch};
+use rawspeed_std_ndslice::array2dref::Array2DRef;
+
+#[inline]
+#[must_use]
+pub fn tiff_err(err: TiffParserError) -> String {
+    err.to_string()
+}
+
+#[inline]
+pub fn non_zero(
+    val: usize,
+    what: &str,
+) -> Result<core::num::NonZero<usize>, String> {
+    core::num::NonZero::new(val).ok_or_else(|| format!("The {what} is zero"))
+}
+
+#[inline]
+pub fn get_u32(ifd: &TiffIFD<'_>, tag: TiffTag) -> Result<Option<u32>, String> {
+    ifd.get_entry(tag)
+        .map(|entry| entry.get_u32(0))
+        .transpose()
+        .map_err(tiff_err)
+}
+
+#[inline]
+pub fn get_usize(
+    ifd: &TiffIFD<'_>,
+    tag: TiffTag,
+) -> Result<Option<usize>, String> {
+    Ok(get_u32(ifd, tag)?.map(|val| val.try_into().unwrap()))
+}
+
+#[inline]
+#[must_use]
+pub fn get_root_string<'a>(
+    root: &TiffRootIFD<'a>,
+    tag: TiffTag,
+) -> Option<&'a str> {
+    root.get_entry_recursive(tag)
+        .and_then(|entry| entry.get_string().ok())
+        .map(str::trim)
+}
+
+/// The bytes of an image stored as a single strip
+/// (or as several strips that are contiguous in the file).
+#[inline]
+pub fn get_strip<'a>(
+    input: &'a [u8],
+    ifd: &TiffIFD<'_>,
+) -> Result<&'a [u8], String> {
+    let offsets = ifd
+        .get_required_entry(TiffTag::STRIP_OFFSETS)
+        .and_then(TiffEntry::get_u32s)
+        .map_err(tiff_err)?;
+    let counts = ifd
+        .get_required_entry(TiffTag::STRIP_BYTE_COUNTS)
+        .and_then(TiffEntry::get_u32s)
+        .map_err(tiff_err)?;
+    let Some(first) = offsets.first().filter(|_| offsets.len() == counts.len())
+    else {
+        return Err("The strip list is invalid".to_owned());
+    };
+    let start: usize = (*first).try_into().unwrap();
+    let mut end = start;
+    for (offset, count) in offsets.iter().zip(&counts) {
+        if usize::try_from(*offset).unwrap() != end {
+            return Err("The strips are not contiguous".to_owned());
+        }
+        end = end
+            .checked_add((*count).try_into().unwrap())
+            .ok_or("Overflow when computing the strip size")?;
+    }
+    input
+        .get(start..end)
+        .filter(|strip| !strip.is_empty())
+        .ok_or_else(|| "The strip data is truncated".to_owned())
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct OwnedArray2D<E> {
+    data: Vec<E>,
+    row_length: RowLength<core::num::NonZero<usize>>,
+}
+
+impl<E> OwnedArray2D<E> {
+    #[inline]
+    pub fn new(data: Vec<E>, row_length: usize) -> Result<Self, String> {
+        let row_length = non_zero(row_length, "row length")?;
+        if data.is_empty() || !data.len().is_multiple_of(row_length.get()) {
+            return Err("The array dimensions are invalid".to_owned());
+        }
+        Ok(Self {
+            data,
+            row_length: RowLength::new(row_length),
+        })
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn data(&self) -> &[E] {
+        &self.data
+    }
+
+    #[inline]
+    pub const fn mat(&self) -> Array2DRef<'_, E> {
+        Array2DRef::new(
+            self.data.as_slice(),
+            self.row_length,
+            RowPitch::new(self.row_length.val()),
+        )
+    }
+}
@@ -0,0 +1,589 @@
+use rawspeed_codecs_ljpeg::ljpeg::{Frame, LJpegDecoder};
+use rawspeed_demuxers_common::{
+    camera_metadata::CameraMetadata,
+    tiff_utils::{get_root_string, get_strip, non_zero, tiff_err},
+};
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::{
+    DataType, ImageRefMut, ProcurementRequest, RawDemuxer, RawDemuxerError,
+};
+use rawspeed_memory_nd_slice_procurement::ndsliceprocurement::NDSliceProcurementRequest;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::{
+    Cameras, Supported, blackareas::BlackArea,
+};
+use rawspeed_metadata_colorfilterarray::colorfilterarray::ColorVariant;
+use rawspeed_parsers_tiffparser::tiffparser::{
+    TiffEntry, TiffIFD, TiffRootIFD, TiffTag,
+};
+use rawspeed_std::coord_common::{
+    ColIndex, Coord2D, Dimensions2D, RowCount, RowIndex, RowLength,
+};
+use rawspeed_std_ndslice::{
+    array2dref::Array2DRef, array2drefmut::Array2DRefMut,
+    offsetarray2dref::OffsetArray2DRef,
+};
+
+/// The raw image of a (post-D30) CR2 lives in the fourth IFD of the chain.
+const RAW_IFD_INDEX: usize = 3;
+
+type T = u16;
+
+/// How the luma samples of an sRAW/mRAW MCU are subsampled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Subsampling {
+    /// 4:2:2, i.e. two pixels per MCU.
+    H2V1,
+    /// 4:2:0, i.e. a 2x2 block of pixels per MCU.
+    H2V2,
+}
+
+impl Subsampling {
+    fn parse(frame: &Frame) -> Result<Option<Self>, String> {
+        if !frame.is_subsampled() {
+            return Ok(None);
+        }
+        let sampling: Vec<_> = frame
+            .components
+            .iter()
+            .map(|c| (c.horizontal_sampling, c.vertical_sampling))
+            .collect();
+        match sampling.as_slice() {
+            [(2, 1), (1, 1), (1, 1)] => Ok(Some(Self::H2V1)),
+            [(2, 2), (1, 1), (1, 1)] => Ok(Some(Self::H2V2)),
+            _ => Err(format!("Unsupported CR2 subsampling: {sampling:?}")),
+        }
+    }
+
+    const fn mode(self) -> &'static str {
+        match self {
+            Self::H2V2 => "sRaw1",
+            Self::H2V1 => "sRaw2",
+        }
+    }
+
+    const fn dims(self) -> (usize, usize) {
+        match self {
+            Self::H2V1 => (2, 1),
+            Self::H2V2 => (2, 2),
+        }
+    }
+}
+
+/// The block of the output that is covered by the samples of a single MCU.
+///
+/// Plain raws store `N` horizontally adjacent CFA samples per MCU,
+/// sRAW/mRAW store `h*v` luma samples and one Cb and one Cr sample
+/// for a `h`x`v` block of (three-component) pixels.
+#[derive(Debug, Clone, Copy)]
+struct McuBlock {
+    subsampling: Option<Subsampling>,
+    /// In output elements.
+    width: usize,
+    height: usize,
+}
+
+impl McuBlock {
+    const SRAW_CPP: usize = 3;
+
+    const fn new(frame: &Frame, subsampling: Option<Subsampling>) -> Self {
+        match subsampling {
+            Some(subsampling) => {
+                let (h, v) = subsampling.dims();
+                Self {
+                    subsampling: Some(subsampling),
+                    width: h * Self::SRAW_CPP,
+                    height: v,
+                }
+            }
+            None => Self {
+                subsampling,
+                width: frame.components.len(),
+                height: 1,
+            },
+        }
+    }
+
+    const fn cpp(&self) -> usize {
+        match self.subsampling {
+            Some(_) => Self::SRAW_CPP,
+            None => 1,
+        }
+    }
+
+    /// Writes the (reconstructed) samples of an MCU to the output,
+    /// with `pos` being the top-left corner of its block.
+    ///
+    /// The chroma of sRAW/mRAW is replicated to all the pixels of the block,
+    /// it is left as-is, in YCbCr.
+    fn store(
+        &self,
+        samples: &[T],
+        pos: Coord2D,
+        output: &mut Array2DRefMut<'_, T>,
+    ) {
+        let mut put = |row: usize, col: usize, sample: T| {
+            output[Coord2D::new(
+                RowIndex::new(*pos.row() + row),
+                ColIndex::new(*pos.col() + col),
+            )] = sample;
+        };
+        let Some(subsampling) = self.subsampling else {
+            for (col, sample) in samples.iter().enumerate() {
+                put(0, col, *sample);
+            }
+            return;
+        };
+        let (h, v) = subsampling.dims();
+        let (luma, chroma) = samples.split_at(h * v);
+        for (index, y) in luma.iter().enumerate() {
+            let (row, col) = (index / h, (index % h) * Self::SRAW_CPP);
+            put(row, col, *y);
+            for (offset, c) in chroma.iter().enumerate() {
+                put(row, col + 1 + offset, *c);
+            }
+        }
+    }
+}
+
+/// Iterates over the top-left corners of the MCU blocks in decoding order:
+/// the output is split into vertical slices, each of which is filled
+/// in row-major order before moving on to the next one.
+#[derive(Debug)]
+struct McuPositions<'a> {
+    block: McuBlock,
+    height: usize,
+    slice_widths: &'a [usize],
+    slice: usize,
+    slice_col: usize,
+    row: usize,
+    col: usize,
+}
+
+impl<'a> McuPositions<'a> {
+    const fn new(
+        block: McuBlock,
+        height: usize,
+        slice_widths: &'a [usize],
+    ) -> Self {
+        Self {
+            block,
+            height,
+            slice_widths,
+            slice: 0,
+            slice_col: 0,
+            row: 0,
+            col: 0,
+        }
+    }
+}
+
+#[expect(clippy::missing_trait_methods)]
+impl Iterator for McuPositions<'_> {
+    type Item = Coord2D;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let slice_width = *self.slice_widths.get(self.slice)?;
+        let pos = Coord2D::new(
+            RowIndex::new(self.row),
+            ColIndex::new(self.slice_col + self.col),
+        );
+        self.col += self.block.width;
+        if self.col == slice_width {
+            self.col = 0;
+            self.row += self.block.height;
+        }
+        if self.row == self.height {
+            self.row = 0;
+            self.slice += 1;
+            self.slice_col += slice_width;
+        }
+        Some(pos)
+    }
+}
+
+/// The widths of the vertical slices the image is stored in,
+/// per the `CANON_CR2_SLICE` tag: `[count, width, last width]`
+/// describes `count` slices of `width`, followed by one of `last width`.
+///
+/// Together, the slices must be exactly as wide as a JPEG row.
+fn parse_slice_widths(
+    ifd: &TiffIFD<'_>,
+    block: McuBlock,
+    mcus_per_row: usize,
+) -> Result<Vec<usize>, String> {
+    let row_len = mcus_per_row
+        .checked_mul(block.width)
+        .ok_or("Overflow when computing the CR2 row length")?;
+    let Some(entry) = ifd.get_entry(TiffTag::CANON_CR2_SLICE) else {
+        return Ok(vec![row_len]);
+    };
+    let slicing = entry.get_u32s().map_err(tiff_err)?;
+    let [count, width, last_width] = slicing.as_slice() else {
+        return Err("The CR2 slice tag is invalid".to_owned());
+    };
+    let [count, width, last_width] =
+        [*count, *width, *last_width].map(|v| usize::try_from(v).unwrap());
+    if [width, last_width]
+        .iter()
+        .any(|w| *w == 0 || !w.is_multiple_of(block.width))
+    {
+        return Err("The CR2 slice widths are invalid".to_owned());
+    }
+    let total_width = count
+        .checked_mul(width)
+        .and_then(|w| w.checked_add(last_width))
+        .ok_or("Overflow when computing the CR2 slice widths")?;
+    if total_width != row_len {
+        return Err("The CR2 slices do not match the image size".to_owned());
+    }
+    let mut slice_widths = vec![width; count];
+    slice_widths.push(last_width);
+    Ok(slice_widths)
+}
+
+/// Canon predicts every sample from the previous one of the same component,
+/// and the first MCU of each (JPEG) row from the first MCU of the row above,
+/// regardless of the slicing.
+#[derive(Debug)]
+struct Predictor {
+    num_samples: Vec<usize>,
+    pred: Vec<T>,
+    row_pred: Vec<T>,
+}
+
+impl Predictor {
+    fn new(frame: &Frame) -> Self {
+        let initial_pred: T = 1 << (frame.precision - 1);
+        Self {
+            num_samples: frame
+                .components
+                .iter()
+                .map(|c| {
+                    usize::from(c.horizontal_sampling)
+                        * usize::from(c.vertical_sampling)
+                })
+                .collect(),
+            pred: vec![initial_pred; frame.components.len()],
+            row_pred: vec![initial_pred; frame.components.len()],
+        }
+    }
+
+    fn reconstruct(
+        &mut self,
+        diffs: &[i32],
+        is_row_start: bool,
+        out: &mut [T],
+    ) {
+        if is_row_start {
+            self.pred.clone_from(&self.row_pred);
+        }
+        let mut samples = diffs.iter().zip(out);
+        for ((pred, row_pred), num_samples) in self
+            .pred
+            .iter_mut()
+            .zip(&mut self.row_pred)
+            .zip(&self.num_samples)
+        {
+            for (index, (diff, sample)) in
+                samples.by_ref().take(*num_samples).enumerate()
+            {
+                // Reconstruction is performed modulo 2^16.
+                #[expect(
+                    clippy::cast_possible_truncation,
+                    clippy::cast_sign_loss
+                )]
+                let diff = *diff as T;
+                *pred = pred.wrapping_add(diff);
+                *sample = *pred;
+                if is_row_start && index == 0 {
+                    *row_pred = *pred;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct Cr2Demuxer<'a> {
+    metadata: CameraMetadata<'a>,
+    decoder: LJpegDecoder<'a>,
+    block: McuBlock,
+    slice_widths: Vec<usize>,
+    /// In output elements.
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+}
+
+impl<'a> Cr2Demuxer<'a> {
+    fn parse_decoder(
+        root: &TiffRootIFD<'a>,
+        ifd: &TiffIFD<'a>,
+    ) -> Result<LJpegDecoder<'a>, String> {
+        let decoder = LJpegDecoder::new(get_strip(root.input(), ifd)?)
+            .map_err(|err| err.to_string())?;
+        if decoder.predictor() != 1
+            || decoder.point_transform() != 0
+            || decoder.restart_interval() != 0
+        {
+            return Err("Unsupported CR2 LJpeg parameters".to_owned());
+        }
+        Ok(decoder)
+    }
+
+    fn compute_dims(
+        block: McuBlock,
+        frame: &Frame,
+        slice_widths: &[usize],
+    ) -> Result<Dimensions2D<core::num::NonZero<usize>>, String> {
+        // The slices are as wide as a JPEG row, so each MCU row of the frame
+        // makes up `block.height` output rows.
+        let width = non_zero(slice_widths.iter().sum(), "width")?;
+        let height = frame
+            .mcu_dims()
+            .row_count()
+            .get()
+            .checked_mul(block.height)
+            .ok_or("Overflow when computing the CR2 image height")?;
+        Ok(Dimensions2D::new(
+            RowLength::new(width),
+            RowCount::new(non_zero(height, "height")?),
+        ))
+    }
+
+    #[inline(never)]
+    pub fn new<F>(
+        root: &TiffRootIFD<'a>,
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<(Self, ProcurementRequest), String>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let ifd = root
+            .ifds()
+            .get(RAW_IFD_INDEX)
+            .ok_or("No raw image found in CR2")?;
+        let decoder = Self::parse_decoder(root, ifd)?;
+        let frame = decoder.frame();
+        let subsampling = Subsampling::parse(frame)?;
+        let block = McuBlock::new(frame, subsampling);
+        let slice_widths =
+            parse_slice_widths(ifd, block, frame.mcu_dims().row_len().get())?;
+        let dims = Self::compute_dims(block, frame, &slice_widths)?;
+
+        let make = get_root_string(root, TiffTag::MAKE).unwrap_or("");
+        let model = get_root_string(root, TiffTag::MODEL).unwrap_or("");
+        let camera = cameras
+            .find(make, model, subsampling.map(Subsampling::mode))
+            .ok_or_else(|| format!("Unknown camera: {make} {model}"))?;
+        check_camera_support_fn(camera.supported)?;
+
+        let iso_speed = root
+            .get_entry_recursive(TiffTag::ISO_SPEED_RATINGS)
+            .map(TiffEntry::get_u32s)
+            .and_then(Result::ok)
+            .and_then(|isos| isos.first().copied());
+        Ok((
+            Self {
+                metadata: CameraMetadata::new(camera, make, model, iso_speed),
+                decoder,
+                block,
+                slice_widths,
+                dims,
+            },
+            NDSliceProcurementRequest::<T>::new(pixel_dims(dims, block.cpp()))
+                .set_cpp(core::num::NonZero::new(block.cpp()).unwrap())
+                .into(),
+        ))
+    }
+}
+
+/// The dimensions of the image in pixels, given those in output elements.
+fn pixel_dims(
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    cpp: usize,
+) -> Dimensions2D<core::num::NonZero<usize>> {
+    let width = dims.row_len().get() / cpp;
+    Dimensions2D::new(
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        dims.row_count(),
+    )
+}
+
+#[expect(clippy::missing_trait_methods)]
+impl RawDemuxer for Cr2Demuxer<'_> {
+    #[inline]
+    fn make(&self) -> &str {
+        self.metadata.make
+    }
+
+    #[inline]
+    fn model(&self) -> &str {
+        self.metadata.model
+    }
+
+    #[inline]
+    fn mode(&self) -> Option<&str> {
+        self.metadata.mode()
+    }
+
+    #[inline]
+    fn canonical_make(&self) -> &str {
+        self.metadata.canonical_make()
+    }
+
+    #[inline]
+    fn canonical_model(&self) -> &str {
+        self.metadata.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_alias(&self) -> &str {
+        self.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_id(&self) -> String {
+        self.metadata.canonical_id()
+    }
+
+    #[inline]
+    fn iso_speed(&self) -> Option<u32> {
+        self.metadata.iso_speed
+    }
+
+    #[inline]
+    fn blacklevel(&self) -> Option<u16> {
+        self.metadata.blacklevel()
+    }
+
+    #[inline]
+    fn whitelevel(&self) -> Option<u16> {
+        self.metadata.whitelevel()
+    }
+
+    #[inline]
+    fn blacklevel_separate(&self) -> Option<Array2DRef<'_, i32>> {
+        None
+    }
+
+    #[inline]
+    fn wb_coeffs(&self) -> Option<[f32; 4]> {
+        None
+    }
+
+    #[inline]
+    fn colormatrix(&self) -> Option<Array2DRef<'_, i16>> {
+        self.metadata.colormatrix()
+    }
+
+    #[inline]
+    fn is_cfa(&self) -> bool {
+        self.block.subsampling.is_none() && self.metadata.is_cfa()
+    }
+
+    #[inline]
+    fn cfa(
+        &self,
+        origin: Coord2D,
+    ) -> Option<OffsetArray2DRef<'_, ColorVariant>> {
+        if !self.is_cfa() {
+            return None;
+        }
+        self.metadata.cfa(origin)
+    }
+
+    #[inline]
+    fn bpp(&self) -> usize {
+        let bpc = match self.datatype() {
+            DataType::U16 => size_of::<u16>(),
+            DataType::F32 => size_of::<f32>(),
+            DataType::U32 => size_of::<u32>(),
+            _ => unreachable!(),
+        };
+        bpc.checked_mul(self.cpp()).unwrap()
+    }
+
+    #[inline]
+    fn cpp(&self) -> usize {
+        self.block.cpp()
+    }
+
+    #[inline]
+    fn datatype(&self) -> DataType {
+        DataType::U16
+    }
+
+    #[inline]
+    fn dim_uncropped(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        pixel_dims(self.dims, self.cpp())
+    }
+
+    #[inline]
+    fn dim_cropped(&self) -> Option<Dimensions2D<core::num::NonZero<usize>>> {
+        self.metadata.dim_cropped(self.dim_uncropped())
+    }
+
+    #[inline]
+    fn crop_offset(&self) -> Option<Coord2D> {
+        self.metadata.crop_offset()
+    }
+
+    #[inline]
+    fn black_areas(&self) -> Option<&[BlackArea]> {
+        self.metadata.black_areas()
+    }
+
+    #[inline]
+    fn fuji_rotation_pos(&self) -> Option<u32> {
+        None
+    }
+
+    #[inline]
+    fn pixel_aspect_ratio(&self) -> Option<f64> {
+        None
+    }
+
+    #[inline]
+    fn bad_pixel_positions(&self) -> Vec<Coord2D> {
+        vec![]
+    }
+
+    #[inline(never)]
+    fn decode(
+        &self,
+        output: &mut ImageRefMut<'_>,
+    ) -> Result<(), RawDemuxerError> {
+        let output = output.as_u16()?;
+        if output.dims() != self.dims {
+            return Err(RawDemuxerError::DecoderError(
+                "Output buffer dimensions differ from expected".to_owned(),
+            ));
+        }
+
+        let frame = self.decoder.frame();
+        let mcus_per_row = frame.mcu_dims().row_len().get();
+        let mut predictor = Predictor::new(frame);
+        let mut samples = vec![0; frame.samples_per_mcu()];
+        let mut positions = McuPositions::new(
+            self.block,
+            self.dims.row_count().get(),
+            &self.slice_widths,
+        )
+        .enumerate();
+        self.decoder
+            .decode_differences(|diffs| {
+                let Some((mcu, pos)) = positions.next() else {
+                    return;
+                };
+                let is_row_start = mcu.is_multiple_of(mcus_per_row);
+                predictor.reconstruct(diffs, is_row_start, &mut samples);
+                self.block.store(&samples, pos, output);
+            })
+            .map_err(|err| RawDemuxerError::DecoderError(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,172 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::{
+    RawDemuxer as _, RawDemuxerError,
+};
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_parsers_tiffparser::tiffparser::TiffParser;
+
+use super::{
+    Cr2Demuxer, Encoder, cr2, decode, mcus, new_demuxer_err, parse_cameras,
+};
+
+const MODEL: &str = "Canon EOS Test";
+
+#[test]
+fn unsliced_test() {
+    let input = mcus(8, 2, 100);
+    let ljpeg = Encoder::new(&[(1, 1), (1, 1)], 4).encode(&input);
+    assert_eq!(
+        decode(&cr2(MODEL, ljpeg, None).build()),
+        vec![
+            vec![0, 1, 100, 101, 200, 201, 300, 301],
+            vec![400, 401, 500, 501, 600, 601, 700, 701],
+        ]
+    );
+}
+
+#[test]
+fn sliced_test() {
+    // A 2-component 4x2 frame stored as two slices 4 samples wide:
+    // each slice holds a full JPEG row, split into two output rows.
+    let input = mcus(8, 2, 100);
+    let ljpeg = Encoder::new(&[(1, 1), (1, 1)], 4).encode(&input);
+    assert_eq!(
+        decode(&cr2(MODEL, ljpeg, Some([1, 4, 4])).build()),
+        vec![
+            vec![0, 1, 100, 101, 400, 401, 500, 501],
+            vec![200, 201, 300, 301, 600, 601, 700, 701],
+        ]
+    );
+}
+
+#[test]
+fn uneven_slices_test() {
+    // The JPEG rows (of 5 MCUs) do not line up with the slices.
+    let input = mcus(10, 2, 100);
+    let ljpeg = Encoder::new(&[(1, 1), (1, 1)], 5).encode(&input);
+    assert_eq!(
+        decode(&cr2(MODEL, ljpeg, Some([2, 4, 2])).build()),
+        vec![
+            vec![0, 1, 100, 101, 400, 401, 500, 501, 800, 801],
+            vec![200, 201, 300, 301, 600, 601, 700, 701, 900, 901],
+        ]
+    );
+}
+
+#[test]
+fn sraw_h2v1_test() {
+    // Y0 Y1 Cb Cr per MCU, for two pixels.
+    let input = mcus(4, 4, 10);
+    let ljpeg = Encoder::new(&[(2, 1), (1, 1), (1, 1)], 2).encode(&input);
+    assert_eq!(
+        decode(&cr2(MODEL, ljpeg, None).build()),
+        vec![
+            vec![0, 2, 3, 1, 2, 3, 10, 12, 13, 11, 12, 13],
+            vec![20, 22, 23, 21, 22, 23, 30, 32, 33, 31, 32, 33],
+        ]
+    );
+}
+
+#[test]
+fn sraw_h2v2_sliced_test() {
+    // Y00 Y01 Y10 Y11 Cb Cr per MCU, for a 2x2 block of pixels,
+    // with each of the two slices being a single MCU wide.
+    let input = mcus(4, 6, 10);
+    let ljpeg = Encoder::new(&[(2, 2), (1, 1), (1, 1)], 2).encode(&input);
+    assert_eq!(
+        decode(&cr2(MODEL, ljpeg, Some([1, 6, 6])).build()),
+        vec![
+            vec![0, 4, 5, 1, 4, 5, 20, 24, 25, 21, 24, 25],
+            vec![2, 4, 5, 3, 4, 5, 22, 24, 25, 23, 24, 25],
+            vec![10, 14, 15, 11, 14, 15, 30, 34, 35, 31, 34, 35],
+            vec![12, 14, 15, 13, 14, 15, 32, 34, 35, 33, 34, 35],
+        ]
+    );
+}
+
+#[test]
+fn wrapping_reconstruction_test() {
+    let input = vec![vec![0xFFFF, 0], vec![0, 0xFFFF]];
+    let ljpeg = Encoder::new(&[(1, 1), (1, 1)], 2).encode(&input);
+    assert_eq!(
+        decode(&cr2(MODEL, ljpeg, None).build()),
+        vec![vec![0xFFFF, 0, 0, 0xFFFF]]
+    );
+}
+
+#[test]
+fn no_raw_ifd_test() {
+    let input = mcus(2, 2, 100);
+    let ljpeg = Encoder::new(&[(1, 1), (1, 1)], 2).encode(&input);
+    let mut builder = cr2(MODEL, ljpeg, None);
+    builder.chain_len = 3;
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "No raw image found in CR2"
+    );
+}
+
+#[test]
+fn unsupported_predictor_test() {
+    let input = mcus(2, 2, 100);
+    let mut encoder = Encoder::new(&[(1, 1), (1, 1)], 2);
+    encoder.predictor = 2;
+    let ljpeg = encoder.encode(&input);
+    assert_eq!(
+        new_demuxer_err(&cr2(MODEL, ljpeg, None).build()),
+        "Unsupported CR2 LJpeg parameters"
+    );
+}
+
+#[test]
+fn unsupported_subsampling_test() {
+    let input = mcus(2, 4, 10);
+    let ljpeg = Encoder::new(&[(1, 2), (1, 1), (1, 1)], 2).encode(&input);
+    assert_eq!(
+        new_demuxer_err(&cr2(MODEL, ljpeg, None).build()),
+        "Unsupported CR2 subsampling: [(1, 2), (1, 1), (1, 1)]"
+    );
+}
+
+#[test]
+fn invalid_slices_test() {
+    let input = mcus(8, 2, 100);
+    let ljpeg = Encoder::new(&[(1, 1), (1, 1)], 4).encode(&input);
+    for (slices, err) in [
+        ([1, 4, 0], "The CR2 slice widths are invalid"),
+        ([1, 3, 5], "The CR2 slice widths are invalid"),
+        ([1, 4, 2], "The CR2 slices do not match the image size"),
+        (
+            [u32::MAX, 4, 4],
+            "The CR2 slices do not match the image size",
+        ),
+    ] {
+        assert_eq!(
+            new_demuxer_err(&cr2(MODEL, ljpeg.clone(), Some(slices)).build()),
+            err
+        );
+    }
+}
+
+#[test]
+fn truncated_data_test() {
+    let mut ljpeg = Encoder::new(&[(1, 1), (1, 1)], 4).encode(&mcus(8, 2, 100));
+    // Keep just the headers, up to and including the scan header.
+    let sos = ljpeg.windows(2).position(|w| w == [0xFF, 0xDA]).unwrap();
+    ljpeg.truncate(sos + 12);
+    let input = cr2(MODEL, ljpeg, None).build();
+    let root = TiffParser::parse(&input).unwrap();
+    let cameras = parse_cameras();
+    let (demuxer, request) = Cr2Demuxer::new(
+        &root,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )
+    .unwrap();
+    let mut output_buf = request.fulfill().unwrap();
+    assert_eq!(
+        demuxer.decode(&mut output_buf.get_mut()),
+        Err(RawDemuxerError::DecoderError(
+            "LJpegError(TruncatedData)".to_owned()
+        ))
+    );
+}
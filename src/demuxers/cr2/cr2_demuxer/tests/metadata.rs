@@ -0,0 +1,133 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_colorfilterarray::colorfilterarray::ColorVariant;
+use rawspeed_parsers_tiffparser::tiffparser::TiffParser;
+use rawspeed_std::coord_common::{
+    ColIndex, Coord2D, Dimensions2D, RowCount, RowIndex, RowLength,
+};
+use rawspeed_utils_tiffbuilder::tiffbuilder::TiffBuilder;
+
+use super::{Cr2Demuxer, Encoder, cr2, mcus, new_demuxer_err, parse_cameras};
+
+fn plain(model: &'static str) -> TiffBuilder {
+    let ljpeg = Encoder::new(&[(1, 1), (1, 1)], 4).encode(&mcus(8, 2, 100));
+    cr2(model, ljpeg, Some([1, 4, 4]))
+}
+
+fn sraw(sampling: (u8, u8)) -> TiffBuilder {
+    let samples_per_mcu = usize::from(sampling.0 * sampling.1) + 2;
+    let ljpeg = Encoder::new(&[sampling, (1, 1), (1, 1)], 2).encode(&mcus(
+        4,
+        samples_per_mcu,
+        10,
+    ));
+    cr2("Canon EOS Test", ljpeg, None)
+}
+
+fn dims(
+    width: usize,
+    height: usize,
+) -> Dimensions2D<core::num::NonZero<usize>> {
+    Dimensions2D::new(
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowCount::new(core::num::NonZero::new(height).unwrap()),
+    )
+}
+
+macro_rules! with_demuxer {
+    ($builder:expr, |$demuxer:ident| $body:block) => {{
+        let input = $builder.build();
+        let root = TiffParser::parse(&input).unwrap();
+        let cameras = parse_cameras();
+        let ($demuxer, _) = Cr2Demuxer::new(
+            &root,
+            &cameras,
+            DecodeableCamera::new_unless_unsupported,
+        )
+        .unwrap();
+        $body
+    }};
+}
+
+#[test]
+fn camera_from_cameras_xml_test() {
+    with_demuxer!(plain("Canon EOS Test"), |demuxer| {
+        assert_eq!(demuxer.make(), "Canon");
+        assert_eq!(demuxer.model(), "Canon EOS Test");
+        assert_eq!(demuxer.mode(), None);
+        assert_eq!(demuxer.canonical_make(), "Canon");
+        assert_eq!(demuxer.canonical_model(), "EOS Test");
+        assert_eq!(demuxer.canonical_id(), "Canon EOS Test");
+        assert_eq!(demuxer.iso_speed(), Some(400));
+        assert_eq!(demuxer.blacklevel(), Some(128));
+        assert_eq!(demuxer.whitelevel(), Some(4000));
+    });
+}
+
+#[test]
+fn unknown_camera_test() {
+    assert_eq!(
+        new_demuxer_err(&plain("Canon EOS Other").build()),
+        "Unknown camera: Canon Canon EOS Other"
+    );
+}
+
+#[test]
+fn unsupported_camera_test() {
+    assert_eq!(
+        new_demuxer_err(&plain("Canon EOS Unsupported").build()),
+        "This camera is not supported (explicit)"
+    );
+}
+
+#[test]
+fn plain_layout_test() {
+    with_demuxer!(plain("Canon EOS Test"), |demuxer| {
+        assert_eq!(demuxer.cpp(), 1);
+        assert_eq!(demuxer.bpp(), 2);
+        assert_eq!(demuxer.dim_uncropped(), dims(8, 2));
+        assert_eq!(demuxer.dim_cropped(), Some(dims(6, 1)));
+        assert_eq!(
+            demuxer.crop_offset(),
+            Some(Coord2D::new(RowIndex::new(1), ColIndex::new(2)))
+        );
+        assert!(demuxer.is_cfa());
+        let cfa = demuxer
+            .cfa(Coord2D::new(RowIndex::new(0), ColIndex::new(0)))
+            .unwrap();
+        assert_eq!(
+            cfa[Coord2D::new(RowIndex::new(1), ColIndex::new(1))],
+            ColorVariant::Blue
+        );
+    });
+}
+
+#[test]
+fn sraw1_layout_test() {
+    with_demuxer!(sraw((2, 2)), |demuxer| {
+        assert_eq!(demuxer.mode(), Some("sRaw1"));
+        assert_eq!(demuxer.whitelevel(), Some(15000));
+        assert_eq!(demuxer.cpp(), 3);
+        assert_eq!(demuxer.bpp(), 6);
+        assert_eq!(demuxer.dim_uncropped(), dims(4, 4));
+        assert_eq!(demuxer.dim_cropped(), Some(dims(4, 4)));
+        assert!(!demuxer.is_cfa());
+        assert!(
+            demuxer
+                .cfa(Coord2D::new(RowIndex::new(0), ColIndex::new(0)))
+                .is_none()
+        );
+    });
+}
+
+#[test]
+fn sraw2_layout_test() {
+    with_demuxer!(sraw((2, 1)), |demuxer| {
+        assert_eq!(demuxer.mode(), Some("sRaw2"));
+        assert_eq!(demuxer.whitelevel(), Some(16000));
+        assert_eq!(demuxer.cpp(), 3);
+        assert_eq!(demuxer.dim_uncropped(), dims(4, 2));
+        assert_eq!(demuxer.dim_cropped(), None);
+        assert!(!demuxer.is_cfa());
+    });
+}
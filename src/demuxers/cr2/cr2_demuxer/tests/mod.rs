@@ -0,0 +1,231 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::Cameras;
+use rawspeed_metadata_xmlparser::xmlparser;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_std::coord_common::RowIndex;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+use rawspeed_utils_tiffbuilder::tiffbuilder::{TiffBuilder, Value, tag};
+
+use super::{Cr2Demuxer, RAW_IFD_INDEX};
+
+/// All 17 difference categories, as 5-bit codes equal to the category.
+const COUNTS: [u8; 16] = [0, 0, 0, 0, 17, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+const CODE_LENGTH: u32 = 5;
+
+const CAMERAS: &str = "
+    <Cameras>
+        <Camera make=\"Canon\" model=\"Canon EOS Test\">
+            <ID make=\"Canon\" model=\"EOS Test\">Canon EOS Test</ID>
+            <CFA width=\"2\" height=\"2\">
+                <Color x=\"0\" y=\"0\">RED</Color>
+                <Color x=\"1\" y=\"0\">GREEN</Color>
+                <Color x=\"0\" y=\"1\">GREEN</Color>
+                <Color x=\"1\" y=\"1\">BLUE</Color>
+            </CFA>
+            <Crop x=\"2\" y=\"1\" width=\"0\" height=\"0\"/>
+            <Sensor black=\"128\" white=\"4000\"/>
+        </Camera>
+        <Camera make=\"Canon\" model=\"Canon EOS Test\" mode=\"sRaw1\">
+            <Crop x=\"0\" y=\"0\" width=\"0\" height=\"0\"/>
+            <Sensor black=\"0\" white=\"15000\"/>
+        </Camera>
+        <Camera make=\"Canon\" model=\"Canon EOS Test\" mode=\"sRaw2\">
+            <Sensor black=\"0\" white=\"16000\"/>
+        </Camera>
+        <Camera make=\"Canon\" model=\"Canon EOS Unsupported\" supported=\"no\">
+        </Camera>
+    </Cameras>";
+
+fn be16(val: u16) -> [u8; 2] {
+    [
+        (val >> 8).try_into().unwrap(),
+        (val & 0xFF).try_into().unwrap(),
+    ]
+}
+
+/// Encodes MCUs (given as their samples, in decoding order)
+/// the way Canon predicts them.
+#[derive(Debug, Clone)]
+struct Encoder {
+    /// The `(horizontal, vertical)` sampling factors of each component.
+    sampling: Vec<(u8, u8)>,
+    mcus_per_row: usize,
+    predictor: u8,
+}
+
+impl Encoder {
+    const PRECISION: u8 = 14;
+
+    fn new(sampling: &[(u8, u8)], mcus_per_row: usize) -> Self {
+        Self {
+            sampling: sampling.to_vec(),
+            mcus_per_row,
+            predictor: 1,
+        }
+    }
+
+    fn encode_difference(bits: &mut BitStreamBuilder, diff: i32) {
+        let diff = match diff.rem_euclid(1 << 16) {
+            d if d >= 1 << 15 => d - (1 << 16),
+            d => d,
+        };
+        let category = 32 - diff.unsigned_abs().leading_zeros();
+        bits.put(category, CODE_LENGTH);
+        if category != 0 && category != 16 {
+            let value = if diff < 0 {
+                diff + (1 << category) - 1
+            } else {
+                diff
+            };
+            bits.put(value.try_into().unwrap(), category);
+        }
+    }
+
+    fn segment(out: &mut Vec<u8>, marker: u8, payload: &[u8]) {
+        let len = u16::try_from(payload.len() + 2).unwrap();
+        out.extend([0xFF, marker]);
+        out.extend(be16(len));
+        out.extend(payload);
+    }
+
+    fn headers(&self, num_mcus: usize) -> Vec<u8> {
+        let max_h = self.sampling.iter().map(|s| s.0).max().unwrap();
+        let max_v = self.sampling.iter().map(|s| s.1).max().unwrap();
+        let width = self.mcus_per_row * usize::from(max_h);
+        let height = num_mcus / self.mcus_per_row * usize::from(max_v);
+        let mut out = vec![0xFF, 0xD8];
+        let mut sof = vec![Self::PRECISION];
+        sof.extend(be16(u16::try_from(height).unwrap()));
+        sof.extend(be16(u16::try_from(width).unwrap()));
+        sof.push(self.sampling.len().try_into().unwrap());
+        for (id, (h, v)) in (1..).zip(&self.sampling) {
+            sof.extend([id, (h << 4) | v, 0]);
+        }
+        Self::segment(&mut out, 0xC3, &sof);
+        let mut dht = vec![0x00];
+        dht.extend(COUNTS);
+        dht.extend(0..=16);
+        Self::segment(&mut out, 0xC4, &dht);
+        let mut sos = vec![self.sampling.len().try_into().unwrap()];
+        for id in (1..).take(self.sampling.len()) {
+            sos.extend([id, 0x00]);
+        }
+        sos.extend([self.predictor, 0, 0]);
+        Self::segment(&mut out, 0xDA, &sos);
+        out
+    }
+
+    fn encode(&self, mcus: &[Vec<u16>]) -> Vec<u8> {
+        let initial_pred = 1_i32 << (Self::PRECISION - 1);
+        let mut row_pred = vec![initial_pred; self.sampling.len()];
+        let mut pred = row_pred.clone();
+        let mut bits = BitStreamBuilder::new();
+        for (index, mcu) in mcus.iter().enumerate() {
+            let is_row_start = index.is_multiple_of(self.mcus_per_row);
+            if is_row_start {
+                pred.clone_from(&row_pred);
+            }
+            let mut samples = mcu.iter();
+            for (component, (h, v)) in self.sampling.iter().enumerate() {
+                let pred = pred.get_mut(component).unwrap();
+                for sample in samples.by_ref().take(usize::from(h * v)) {
+                    Self::encode_difference(
+                        &mut bits,
+                        i32::from(*sample) - *pred,
+                    );
+                    *pred = i32::from(*sample);
+                }
+            }
+            if is_row_start {
+                let mut offset = 0;
+                for (component, (h, v)) in self.sampling.iter().enumerate() {
+                    *row_pred.get_mut(component).unwrap() =
+                        i32::from(*mcu.get(offset).unwrap());
+                    offset += usize::from(h * v);
+                }
+            }
+        }
+        let mut out = self.headers(mcus.len());
+        out.extend(bits.finish(BitOrder::JPEG));
+        out.extend([0xFF, 0xD9]);
+        out
+    }
+}
+
+/// MCUs whose samples are `base * mcu + sample`, which allows to
+/// tell the samples apart in the output.
+fn mcus(count: usize, samples_per_mcu: usize, base: u16) -> Vec<Vec<u16>> {
+    (0..count)
+        .map(|mcu| {
+            (0..samples_per_mcu)
+                .map(|sample| {
+                    base * u16::try_from(mcu).unwrap()
+                        + u16::try_from(sample).unwrap()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn cr2(
+    model: &'static str,
+    ljpeg: Vec<u8>,
+    slices: Option<[u32; 3]>,
+) -> TiffBuilder {
+    let mut builder = TiffBuilder::new(Endianness::Little);
+    builder.ifd(0).extend([
+        tag(TiffTag::MAKE, Value::Ascii("Canon")),
+        tag(TiffTag::MODEL, Value::Ascii(model)),
+        tag(TiffTag::ISO_SPEED_RATINGS, Value::Short(vec![400])),
+    ]);
+    builder.ifd(RAW_IFD_INDEX).extend([
+        tag(TiffTag::COMPRESSION, Value::Short(vec![6])),
+        tag(TiffTag::STRIP_OFFSETS, Value::BlobOffsets(vec![0])),
+        tag(TiffTag::STRIP_BYTE_COUNTS, Value::BlobSizes(vec![0])),
+    ]);
+    if let Some(slices) = slices {
+        builder
+            .ifd(RAW_IFD_INDEX)
+            .push(tag(TiffTag::CANON_CR2_SLICE, Value::Long(slices.to_vec())));
+    }
+    builder.chain_len = RAW_IFD_INDEX + 1;
+    builder.blobs.push(ljpeg);
+    builder
+}
+
+fn parse_cameras() -> Cameras<'static> {
+    xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap()
+}
+
+fn new_demuxer_err(input: &[u8]) -> String {
+    let root = TiffParser::parse(input).unwrap();
+    let cameras = parse_cameras();
+    Cr2Demuxer::new(&root, &cameras, DecodeableCamera::new_unless_unsupported)
+        .unwrap_err()
+}
+
+fn decode(input: &[u8]) -> Vec<Vec<u16>> {
+    let root = TiffParser::parse(input).unwrap();
+    let cameras = parse_cameras();
+    let (demuxer, request) = Cr2Demuxer::new(
+        &root,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )
+    .unwrap();
+    let mut output_buf = request.fulfill().unwrap();
+    let mut image = output_buf.get_mut();
+    demuxer.decode(&mut image).unwrap();
+    let output = image.as_u16().unwrap();
+    (0..output.num_rows().get())
+        .map(|row| output.get_row(RowIndex::new(row)).unwrap().to_vec())
+        .collect()
+}
+
+#[cfg(test)]
+mod data;
+#[cfg(test)]
+mod metadata;
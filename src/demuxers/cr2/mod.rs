@@ -0,0 +1 @@
+pub mod cr2_demuxer;
@@ -0,0 +1,392 @@
+use rawspeed_bitstream_bytestream_decoder::bytestreamer::ByteStreamer;
+use rawspeed_codecs_crx::crx::{CrxDecompressor, CrxHeader};
+use rawspeed_demuxers_common::{
+    camera_metadata::CameraMetadata,
+    tiff_utils::{get_root_string, tiff_err},
+};
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::{
+    DataType, ImageRefMut, ProcurementRequest, RawDemuxer, RawDemuxerError,
+};
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_memory_nd_slice_procurement::ndsliceprocurement::NDSliceProcurementRequest;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::{
+    Cameras, Supported, blackareas::BlackArea,
+};
+use rawspeed_metadata_colorfilterarray::colorfilterarray::ColorVariant;
+use rawspeed_parsers_isobmffparser::isobmffparser::{
+    FourCC, IsoBmffParser, IsoBmffParserError, IsoBox, UUID, find_box,
+};
+use rawspeed_parsers_tiffparser::tiffparser::{TiffEntry, TiffParser, TiffTag};
+use rawspeed_std::coord_common::{Coord2D, Dimensions2D};
+use rawspeed_std_ndslice::{
+    array2dref::Array2DRef, offsetarray2dref::OffsetArray2DRef,
+};
+
+/// The major brand of the 'ftyp' box.
+const CR3_BRAND: &[u8] = b"crx ";
+
+/// The 'uuid' box of the movie that holds the Canon metadata.
+const CANON_UUID: [u8; 16] = [
+    0x85, 0xC0, 0xB6, 0x87, 0x82, 0x0F, 0x11, 0xE0, 0x81, 0x11, 0xF4, 0xCE,
+    0x46, 0x2B, 0x6A, 0x48,
+];
+
+/// The TIFFs of the camera (with the make and the model), and of the EXIF.
+const CMT1: FourCC = *b"CMT1";
+const CMT2: FourCC = *b"CMT2";
+
+/// The boxes from a track to its sample tables.
+const SAMPLE_TABLE_PATH: [FourCC; 3] = [*b"mdia", *b"minf", *b"stbl"];
+
+/// The sample descriptions follow the version, the flags and their count.
+const STSD_HEADER_SIZE: usize = 8;
+
+/// The image tracks are described by a 'CRAW' entry, whose boxes follow
+/// its fixed fields. Those of the raw images include the CRX header.
+const CRAW: FourCC = *b"CRAW";
+const CRAW_HEADER_SIZE: usize = 82;
+const CMP1: FourCC = *b"CMP1";
+
+/// The version and the flags of the sample size and offset tables.
+const FULL_BOX_HEADER_SIZE: usize = 4;
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    data.get(pos..pos + size_of::<u32>())
+        .map(|bytes| ByteStreamer::new(bytes, Endianness::Big).read())
+}
+
+fn read_u64(data: &[u8], pos: usize) -> Option<u64> {
+    data.get(pos..pos + size_of::<u64>())
+        .map(|bytes| ByteStreamer::new(bytes, Endianness::Big).read())
+}
+
+fn bmff_err(err: IsoBmffParserError) -> String {
+    err.to_string()
+}
+
+type T = u16;
+
+/// A raw image track: its CRX header, and its first sample.
+#[derive(Debug)]
+struct Track<'a> {
+    header: CrxHeader,
+    sample: &'a [u8],
+}
+
+impl<'a> Track<'a> {
+    /// The size of the first sample: either that of all of them, or the
+    /// first one of the table.
+    fn sample_size(stsz: &[u8]) -> Option<usize> {
+        let size = read_u32(stsz, FULL_BOX_HEADER_SIZE)?;
+        let size = if size == 0 {
+            read_u32(stsz, FULL_BOX_HEADER_SIZE + 8)?
+        } else {
+            size
+        };
+        Some(usize::try_from(size).unwrap())
+    }
+
+    /// The offset of the first chunk, from either the 64-bit or the 32-bit
+    /// table.
+    fn chunk_offset(stbl: &[IsoBox<'_>]) -> Option<usize> {
+        let first = FULL_BOX_HEADER_SIZE + size_of::<u32>();
+        let offset = if let Ok(co64) = find_box(stbl, *b"co64") {
+            read_u64(co64.payload(), first)?
+        } else {
+            let stco = find_box(stbl, *b"stco").ok()?;
+            u64::from(read_u32(stco.payload(), first)?)
+        };
+        usize::try_from(offset).ok()
+    }
+
+    /// The tracks of other than raw images (the previews, and the
+    /// metadata) lack the CRX header.
+    fn parse(
+        input: &'a [u8],
+        trak: &IsoBox<'_>,
+    ) -> Result<Option<Self>, String> {
+        let mut stbl = trak.children(0).map_err(bmff_err)?;
+        for kind in SAMPLE_TABLE_PATH {
+            stbl = find_box(&stbl, kind)
+                .and_then(|b| b.children(0))
+                .map_err(bmff_err)?;
+        }
+        let entries = find_box(&stbl, *b"stsd")
+            .and_then(|descriptions| descriptions.children(STSD_HEADER_SIZE))
+            .map_err(bmff_err)?;
+        let Ok(craw) = find_box(&entries, CRAW) else {
+            return Ok(None);
+        };
+        let boxes = craw.children(CRAW_HEADER_SIZE).map_err(bmff_err)?;
+        let Ok(cmp1) = find_box(&boxes, CMP1) else {
+            return Ok(None);
+        };
+        let header =
+            CrxHeader::parse(cmp1.payload()).map_err(|err| err.to_string())?;
+        let stsz = find_box(&stbl, *b"stsz").map_err(bmff_err)?;
+        let (Some(size), Some(offset)) =
+            (Self::sample_size(stsz.payload()), Self::chunk_offset(&stbl))
+        else {
+            return Err("The CR3 sample tables are truncated".to_owned());
+        };
+        let sample = input
+            .get(offset..)
+            .and_then(|sample| sample.get(..size))
+            .ok_or("The raw sample is out of bounds")?;
+        Ok(Some(Self { header, sample }))
+    }
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct Cr3Demuxer<'a> {
+    metadata: CameraMetadata<'a>,
+    decompressor: CrxDecompressor<'a>,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+}
+
+impl<'a> Cr3Demuxer<'a> {
+    /// The Canon metadata: the TIFFs of the camera, and of the EXIF.
+    fn parse_metadata(
+        moov: &[IsoBox<'a>],
+    ) -> Result<(&'a [u8], &'a [u8]), String> {
+        let canon = moov
+            .iter()
+            .find(|b| b.kind() == UUID && b.user_type() == Some(CANON_UUID))
+            .ok_or("No Canon metadata found in CR3")?;
+        let boxes = canon.children(0).map_err(bmff_err)?;
+        let (Ok(cmt1), Ok(cmt2)) =
+            (find_box(&boxes, CMT1), find_box(&boxes, CMT2))
+        else {
+            return Err("No Canon metadata found in CR3".to_owned());
+        };
+        Ok((cmt1.payload(), cmt2.payload()))
+    }
+
+    /// The raw image is that of the largest of the raw image tracks.
+    fn parse_track(
+        input: &'a [u8],
+        moov: &[IsoBox<'_>],
+    ) -> Result<Track<'a>, String> {
+        let area = |raw: &Track<'_>| raw.header.width * raw.header.height;
+        let mut raw: Option<Track<'a>> = None;
+        for trak in moov.iter().filter(|b| b.kind() == *b"trak") {
+            let Some(track) = Track::parse(input, trak)? else {
+                continue;
+            };
+            if raw
+                .as_ref()
+                .is_none_or(|largest| area(largest) < area(&track))
+            {
+                raw = Some(track);
+            }
+        }
+        raw.ok_or_else(|| "No raw image found in CR3".to_owned())
+    }
+
+    #[inline(never)]
+    pub fn new<F>(
+        input: &'a [u8],
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<(Self, ProcurementRequest), String>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let boxes = IsoBmffParser::parse(input).map_err(bmff_err)?;
+        let ftyp = find_box(&boxes, *b"ftyp").map_err(bmff_err)?;
+        if !ftyp.payload().starts_with(CR3_BRAND) {
+            return Err("Not a CR3 file".to_owned());
+        }
+        let moov = find_box(&boxes, *b"moov")
+            .and_then(|moov| moov.children(0))
+            .map_err(bmff_err)?;
+        let (cmt1, cmt2) = Self::parse_metadata(&moov)?;
+        let camera_tiff = TiffParser::parse(cmt1).map_err(tiff_err)?;
+        let exif = TiffParser::parse(cmt2).map_err(tiff_err)?;
+
+        let make = get_root_string(&camera_tiff, TiffTag::MAKE).unwrap_or("");
+        let model = get_root_string(&camera_tiff, TiffTag::MODEL).unwrap_or("");
+        let camera = cameras
+            .find(make, model, None)
+            .ok_or_else(|| format!("Unknown camera: {make} {model}"))?;
+        check_camera_support_fn(camera.supported)?;
+        let iso_speed = exif
+            .get_entry_recursive(TiffTag::ISO_SPEED_RATINGS)
+            .map(TiffEntry::get_u32s)
+            .and_then(Result::ok)
+            .and_then(|isos| isos.first().copied());
+        let metadata = CameraMetadata::new(camera, make, model, iso_speed);
+
+        let track = Self::parse_track(input, &moov)?;
+        let decompressor = CrxDecompressor::new(track.header, track.sample)
+            .map_err(|err| err.to_string())?;
+        let dims = decompressor.dims();
+        Ok((
+            Self {
+                metadata,
+                decompressor,
+                dims,
+            },
+            NDSliceProcurementRequest::<T>::new(dims).into(),
+        ))
+    }
+}
+
+#[expect(clippy::missing_trait_methods)]
+impl RawDemuxer for Cr3Demuxer<'_> {
+    #[inline]
+    fn make(&self) -> &str {
+        self.metadata.make
+    }
+
+    #[inline]
+    fn model(&self) -> &str {
+        self.metadata.model
+    }
+
+    #[inline]
+    fn mode(&self) -> Option<&str> {
+        self.metadata.mode()
+    }
+
+    #[inline]
+    fn canonical_make(&self) -> &str {
+        self.metadata.canonical_make()
+    }
+
+    #[inline]
+    fn canonical_model(&self) -> &str {
+        self.metadata.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_alias(&self) -> &str {
+        self.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_id(&self) -> String {
+        self.metadata.canonical_id()
+    }
+
+    #[inline]
+    fn iso_speed(&self) -> Option<u32> {
+        self.metadata.iso_speed
+    }
+
+    #[inline]
+    fn blacklevel(&self) -> Option<u16> {
+        self.metadata.blacklevel()
+    }
+
+    #[inline]
+    fn whitelevel(&self) -> Option<u16> {
+        self.metadata.whitelevel()
+    }
+
+    #[inline]
+    fn blacklevel_separate(&self) -> Option<Array2DRef<'_, i32>> {
+        None
+    }
+
+    #[inline]
+    fn wb_coeffs(&self) -> Option<[f32; 4]> {
+        None
+    }
+
+    #[inline]
+    fn colormatrix(&self) -> Option<Array2DRef<'_, i16>> {
+        self.metadata.colormatrix()
+    }
+
+    #[inline]
+    fn is_cfa(&self) -> bool {
+        self.metadata.is_cfa()
+    }
+
+    #[inline]
+    fn cfa(
+        &self,
+        origin: Coord2D,
+    ) -> Option<OffsetArray2DRef<'_, ColorVariant>> {
+        self.metadata.cfa(origin)
+    }
+
+    #[inline]
+    fn bpp(&self) -> usize {
+        let bpc = match self.datatype() {
+            DataType::U16 => size_of::<u16>(),
+            DataType::F32 => size_of::<f32>(),
+            DataType::U32 => size_of::<u32>(),
+            _ => unreachable!(),
+        };
+        bpc.checked_mul(self.cpp()).unwrap()
+    }
+
+    #[inline]
+    fn cpp(&self) -> usize {
+        1
+    }
+
+    #[inline]
+    fn datatype(&self) -> DataType {
+        DataType::U16
+    }
+
+    #[inline]
+    fn dim_uncropped(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        self.dims
+    }
+
+    #[inline]
+    fn dim_cropped(&self) -> Option<Dimensions2D<core::num::NonZero<usize>>> {
+        self.metadata.dim_cropped(self.dim_uncropped())
+    }
+
+    #[inline]
+    fn crop_offset(&self) -> Option<Coord2D> {
+        self.metadata.crop_offset()
+    }
+
+    #[inline]
+    fn black_areas(&self) -> Option<&[BlackArea]> {
+        self.metadata.black_areas()
+    }
+
+    #[inline]
+    fn fuji_rotation_pos(&self) -> Option<u32> {
+        None
+    }
+
+    #[inline]
+    fn pixel_aspect_ratio(&self) -> Option<f64> {
+        None
+    }
+
+    #[inline]
+    fn bad_pixel_positions(&self) -> Vec<Coord2D> {
+        vec![]
+    }
+
+    #[inline(never)]
+    fn decode(
+        &self,
+        output: &mut ImageRefMut<'_>,
+    ) -> Result<(), RawDemuxerError> {
+        let output = output.as_u16()?;
+        if output.dims() != self.dims {
+            return Err(RawDemuxerError::DecoderError(
+                "Output buffer dimensions differ from expected".to_owned(),
+            ));
+        }
+        self.decompressor
+            .decode(output)
+            .map_err(|err| RawDemuxerError::DecoderError(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests;
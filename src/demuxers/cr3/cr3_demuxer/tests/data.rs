@@ -0,0 +1,99 @@
+use super::{Cr3, Track, be32, be64, decode, new_demuxer_err};
+
+/// Overwrites the payload of the last box of the kind, from `pos`.
+fn patch(input: &mut [u8], kind: &[u8; 4], pos: usize, bytes: &[u8]) {
+    let start = input
+        .windows(kind.len())
+        .rposition(|window| window == kind)
+        .unwrap()
+        + kind.len()
+        + pos;
+    input
+        .get_mut(start..start + bytes.len())
+        .unwrap()
+        .copy_from_slice(bytes);
+}
+
+#[test]
+fn decode_test() {
+    // The samples of the empty subbands are all at the median.
+    assert_eq!(
+        decode(&Cr3::flat(44, 46).build()),
+        Ok(vec![vec![0x2000; 44]; 46])
+    );
+}
+
+#[test]
+fn largest_track_test() {
+    let cr3 = Cr3::new(vec![
+        Track::raw(48, 46),
+        Track::preview(),
+        Track::raw(44, 44),
+    ]);
+    assert_eq!(decode(&cr3.build()), Ok(vec![vec![0x2000; 48]; 46]));
+}
+
+#[test]
+fn chunk_offsets_test() {
+    let mut track = Track::raw(44, 44);
+    track.co64 = false;
+    let cr3 = Cr3::new(vec![Track::preview(), track]);
+    assert_eq!(decode(&cr3.build()), Ok(vec![vec![0x2000; 44]; 44]));
+}
+
+#[test]
+fn no_raw_image_test() {
+    let cr3 = Cr3::new(vec![Track::preview()]);
+    assert_eq!(new_demuxer_err(&cr3.build()), "No raw image found in CR3");
+}
+
+#[test]
+fn truncated_sample_tables_test() {
+    // Without a common sample size, that of each sample follows.
+    let mut input = Cr3::flat(44, 44).build();
+    patch(&mut input, b"stsz", 4, &be32(0));
+    assert_eq!(
+        new_demuxer_err(&input),
+        "The CR3 sample tables are truncated"
+    );
+}
+
+#[test]
+fn sample_out_of_bounds_test() {
+    let mut input = Cr3::flat(44, 44).build();
+    patch(&mut input, b"co64", 8, &be64(0xFFFF_FFFF));
+    assert_eq!(new_demuxer_err(&input), "The raw sample is out of bounds");
+}
+
+#[test]
+fn invalid_crx_test() {
+    let mut invalid_header = Cr3::flat(44, 44);
+    invalid_header.tracks.last_mut().unwrap().cmp1 = Some(vec![0; 32]);
+    assert_eq!(
+        new_demuxer_err(&invalid_header.build()),
+        "CrxError(InvalidImageHeader)"
+    );
+    let mut invalid_sample = Cr3::flat(44, 44);
+    *invalid_sample
+        .tracks
+        .last_mut()
+        .unwrap()
+        .sample
+        .first_mut()
+        .unwrap() = 0;
+    assert_eq!(
+        new_demuxer_err(&invalid_sample.build()),
+        "CrxError(InvalidTileHeader)"
+    );
+}
+
+#[test]
+fn invalid_container_test() {
+    // The movie follows the 16-byte file type box.
+    let mut input = Cr3::flat(44, 44).build();
+    input.truncate(20);
+    assert_eq!(
+        new_demuxer_err(&input),
+        "IsoBmffParserError(TruncatedBox(16))"
+    );
+}
@@ -0,0 +1,74 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+
+use super::{Cr3, Cr3Demuxer, new_demuxer_err, parse_cameras};
+
+macro_rules! with_demuxer {
+    ($cr3:expr, |$demuxer:ident| $body:block) => {{
+        let input = $cr3.build();
+        let cameras = parse_cameras();
+        let ($demuxer, _) = Cr3Demuxer::new(
+            &input,
+            &cameras,
+            DecodeableCamera::new_unless_unsupported,
+        )
+        .unwrap();
+        $body
+    }};
+}
+
+#[test]
+fn camera_from_cameras_xml_test() {
+    with_demuxer!(Cr3::flat(44, 44), |demuxer| {
+        assert_eq!(demuxer.make(), "Canon");
+        assert_eq!(demuxer.model(), "Canon EOS R0");
+        assert_eq!(demuxer.mode(), None);
+        assert_eq!(demuxer.canonical_make(), "Canon");
+        assert_eq!(demuxer.canonical_id(), "Canon EOS R0");
+        assert_eq!(demuxer.blacklevel(), Some(512));
+        assert_eq!(demuxer.whitelevel(), Some(16383));
+        assert!(demuxer.is_cfa());
+    });
+}
+
+#[test]
+fn exif_test() {
+    // The ISO is that of the EXIF, and there is no white balance.
+    with_demuxer!(Cr3::flat(44, 44), |demuxer| {
+        assert_eq!(demuxer.iso_speed(), Some(400));
+        assert!(demuxer.wb_coeffs().is_none());
+    });
+}
+
+#[test]
+fn unknown_camera_test() {
+    let mut unknown = Cr3::flat(44, 44);
+    unknown.model = "Canon EOS R1";
+    assert_eq!(
+        new_demuxer_err(&unknown.build()),
+        "Unknown camera: Canon Canon EOS R1"
+    );
+    let mut unsupported = Cr3::flat(44, 44);
+    unsupported.model = "Unsupported";
+    assert_eq!(
+        new_demuxer_err(&unsupported.build()),
+        "This camera is not supported (explicit)"
+    );
+}
+
+#[test]
+fn not_cr3_test() {
+    let mut cr3 = Cr3::flat(44, 44);
+    cr3.brand = b"isom";
+    assert_eq!(new_demuxer_err(&cr3.build()), "Not a CR3 file");
+}
+
+#[test]
+fn no_canon_metadata_test() {
+    let mut cr3 = Cr3::flat(44, 44);
+    cr3.canon_uuid = [0; 16];
+    assert_eq!(
+        new_demuxer_err(&cr3.build()),
+        "No Canon metadata found in CR3"
+    );
+}
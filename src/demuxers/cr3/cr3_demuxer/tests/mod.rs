@@ -0,0 +1,247 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::Cameras;
+use rawspeed_metadata_xmlparser::xmlparser;
+use rawspeed_parsers_tiffparser::tiffparser::TiffTag;
+use rawspeed_std::coord_common::RowIndex;
+use rawspeed_utils_tiffbuilder::tiffbuilder::{TiffBuilder, Value, tag};
+
+use super::{CANON_UUID, CRAW_HEADER_SIZE, Cr3Demuxer, STSD_HEADER_SIZE};
+
+const CAMERAS: &str = "
+    <Cameras>
+        <Camera make=\"Canon\" model=\"Canon EOS R0\">
+            <ID make=\"Canon\" model=\"EOS R0\">Canon EOS R0</ID>
+            <CFA width=\"2\" height=\"2\">
+                <Color x=\"0\" y=\"0\">RED</Color>
+                <Color x=\"1\" y=\"0\">GREEN</Color>
+                <Color x=\"0\" y=\"1\">GREEN</Color>
+                <Color x=\"1\" y=\"1\">BLUE</Color>
+            </CFA>
+            <Sensor black=\"512\" white=\"16383\"/>
+        </Camera>
+        <Camera make=\"Canon\" model=\"Unsupported\" supported=\"no\">
+        </Camera>
+    </Cameras>";
+
+/// The CR3 containers are big-endian.
+fn be16(val: u16) -> [u8; 2] {
+    let mut bytes = val.to_le_bytes();
+    bytes.reverse();
+    bytes
+}
+
+fn be32(val: u32) -> [u8; 4] {
+    let mut bytes = val.to_le_bytes();
+    bytes.reverse();
+    bytes
+}
+
+fn be64(val: u64) -> [u8; 8] {
+    let mut bytes = val.to_le_bytes();
+    bytes.reverse();
+    bytes
+}
+
+fn to_u32(val: usize) -> u32 {
+    u32::try_from(val).unwrap()
+}
+
+fn iso_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut out = be32(to_u32(payload.len() + 8)).to_vec();
+    out.extend(kind);
+    out.extend(payload);
+    out
+}
+
+fn container(kind: &[u8; 4], children: &[Vec<u8>]) -> Vec<u8> {
+    iso_box(kind, &children.concat())
+}
+
+/// The CRX header of an image of a single tile, whose planes are losslessly
+/// coded.
+fn cmp1(width: usize, height: usize) -> Vec<u8> {
+    let mut out = be16(0x100).to_vec();
+    out.extend([0; 6]);
+    for dim in [width, height, width, height] {
+        out.extend(be32(to_u32(dim)));
+    }
+    out.extend([14, 0x40, 0, 0]);
+    out.extend(be32(to_u32(FLAT_HEADERS_SIZE)));
+    out
+}
+
+/// The tile, plane and (empty) subband headers of a flat image.
+const FLAT_HEADERS_SIZE: usize = 12 + 4 * (12 + 12);
+
+fn flat_sample() -> Vec<u8> {
+    let header = |signature: u16, size: u32, flags: u32| {
+        [be16(signature).to_vec(), be16(8).to_vec()]
+            .into_iter()
+            .chain([be32(size).to_vec(), be32(flags).to_vec()])
+            .flatten()
+            .collect::<Vec<_>>()
+    };
+    let mut out = header(0xFF01, 0, 0);
+    for plane in 0..4 {
+        out.extend(header(0xFF02, 0, (plane << 28) | 0x800_0000));
+        out.extend(header(0xFF03, 0, 0));
+    }
+    out
+}
+
+#[derive(Debug, Clone)]
+struct Track {
+    /// The CRX header of the raw image tracks.
+    cmp1: Option<Vec<u8>>,
+    sample: Vec<u8>,
+    /// Whether the chunk offset is stored as 64 bits.
+    co64: bool,
+}
+
+impl Track {
+    fn raw(width: usize, height: usize) -> Self {
+        Self {
+            cmp1: Some(cmp1(width, height)),
+            sample: flat_sample(),
+            co64: true,
+        }
+    }
+
+    fn preview() -> Self {
+        Self {
+            cmp1: None,
+            sample: vec![0xFF, 0xD8],
+            co64: true,
+        }
+    }
+
+    fn stsz(&self) -> Vec<u8> {
+        let mut payload = vec![0; 4];
+        payload.extend(be32(to_u32(self.sample.len())));
+        payload.extend(be32(1));
+        iso_box(b"stsz", &payload)
+    }
+
+    fn chunk_offsets(&self, offset: usize) -> Vec<u8> {
+        let mut payload = vec![0; 4];
+        payload.extend(be32(1));
+        if self.co64 {
+            payload.extend(be64(u64::try_from(offset).unwrap()));
+            iso_box(b"co64", &payload)
+        } else {
+            payload.extend(be32(to_u32(offset)));
+            iso_box(b"stco", &payload)
+        }
+    }
+
+    fn build(&self, offset: usize) -> Vec<u8> {
+        let mut craw = vec![0; CRAW_HEADER_SIZE];
+        if let Some(cmp1) = &self.cmp1 {
+            craw.extend(iso_box(b"CMP1", cmp1));
+        }
+        let mut stsd = vec![0; STSD_HEADER_SIZE];
+        stsd.extend(iso_box(b"CRAW", &craw));
+        let stbl = container(
+            b"stbl",
+            &[
+                iso_box(b"stsd", &stsd),
+                self.stsz(),
+                self.chunk_offsets(offset),
+            ],
+        );
+        let minf = container(b"minf", &[stbl]);
+        container(b"trak", &[container(b"mdia", &[minf])])
+    }
+}
+
+#[derive(Debug)]
+struct Cr3 {
+    brand: &'static [u8; 4],
+    model: &'static str,
+    canon_uuid: [u8; 16],
+    tracks: Vec<Track>,
+}
+
+impl Cr3 {
+    fn new(tracks: Vec<Track>) -> Self {
+        Self {
+            brand: b"crx ",
+            model: "Canon EOS R0",
+            canon_uuid: CANON_UUID,
+            tracks,
+        }
+    }
+
+    fn flat(width: usize, height: usize) -> Self {
+        Self::new(vec![Track::preview(), Track::raw(width, height)])
+    }
+
+    fn metadata(&self) -> Vec<u8> {
+        let mut cmt1 = TiffBuilder::new(Endianness::Little);
+        cmt1.ifd(0).extend([
+            tag(TiffTag::MAKE, Value::Ascii("Canon")),
+            tag(TiffTag::MODEL, Value::Ascii(self.model)),
+        ]);
+        let mut cmt2 = TiffBuilder::new(Endianness::Little);
+        cmt2.ifd(0)
+            .push(tag(TiffTag::ISO_SPEED_RATINGS, Value::Short(vec![400])));
+        let mut payload = self.canon_uuid.to_vec();
+        payload.extend(iso_box(b"CMT1", &cmt1.build()));
+        payload.extend(iso_box(b"CMT2", &cmt2.build()));
+        iso_box(b"uuid", &payload)
+    }
+
+    /// The movie, given the offset of the samples.
+    fn moov(&self, mut offset: usize) -> Vec<u8> {
+        let mut children = vec![self.metadata()];
+        for track in &self.tracks {
+            children.push(track.build(offset));
+            offset += track.sample.len();
+        }
+        container(b"moov", &children)
+    }
+
+    fn build(&self) -> Vec<u8> {
+        let mut out =
+            iso_box(b"ftyp", &[self.brand.to_vec(), vec![0; 4]].concat());
+        let samples = self.tracks.iter().map(|track| track.sample.clone());
+        let mdat = iso_box(b"mdat", &samples.collect::<Vec<_>>().concat());
+        let moov_len = self.moov(0).len();
+        out.extend(self.moov(out.len() + moov_len + 8));
+        out.extend(mdat);
+        out
+    }
+}
+
+fn parse_cameras() -> Cameras<'static> {
+    xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap()
+}
+
+fn new_demuxer_err(input: &[u8]) -> String {
+    let cameras = parse_cameras();
+    Cr3Demuxer::new(input, &cameras, DecodeableCamera::new_unless_unsupported)
+        .unwrap_err()
+}
+
+fn decode(input: &[u8]) -> Result<Vec<Vec<u16>>, String> {
+    let cameras = parse_cameras();
+    let (demuxer, request) = Cr3Demuxer::new(
+        input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )?;
+    let mut output_buf = request.fulfill().unwrap();
+    let mut image = output_buf.get_mut();
+    demuxer.decode(&mut image).map_err(|err| err.to_string())?;
+    let output = image.as_u16().unwrap();
+    Ok((0..output.num_rows().get())
+        .map(|row| output.get_row(RowIndex::new(row)).unwrap().to_vec())
+        .collect())
+}
+
+#[cfg(test)]
+mod data;
+#[cfg(test)]
+mod metadata;
@@ -0,0 +1 @@
+pub mod cr3_demuxer;
@@ -0,0 +1,401 @@
+use rawspeed_bitstream_bytestream_decoder::bytestreamer::ByteStreamer;
+use rawspeed_codecs_crw::crw::CrwDecompressor;
+use rawspeed_demuxers_common::{
+    camera_metadata::CameraMetadata, tiff_utils::non_zero,
+};
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::{
+    DataType, ImageRefMut, ProcurementRequest, RawDemuxer, RawDemuxerError,
+};
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_memory_nd_slice_procurement::ndsliceprocurement::NDSliceProcurementRequest;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::{
+    Cameras, Supported, blackareas::BlackArea,
+};
+use rawspeed_metadata_colorfilterarray::colorfilterarray::ColorVariant;
+use rawspeed_std::coord_common::{Coord2D, Dimensions2D, RowCount, RowLength};
+use rawspeed_std_ndslice::{
+    array2dref::Array2DRef, offsetarray2dref::OffsetArray2DRef,
+};
+
+/// The (little-endian) byte order and the length of the header, which the
+/// signature follows. The root heap takes the rest of the file.
+const BYTE_ORDER: &[u8] = b"II";
+const SIGNATURE_OFFSET: usize = 6;
+const SIGNATURE: &[u8] = b"HEAPCCDR";
+
+/// The offset of the records of a heap is its last word. The records are
+/// preceded by their number, and each is a tag, and the size and offset
+/// (within the heap) of its data.
+const RECORD_LEN: usize = 10;
+const RECORD_SIZE_OFFSET: usize = 2;
+const RECORD_OFFSET_OFFSET: usize = 6;
+
+/// Bits 14-15 of a tag are where its data is: in the heap, or in the
+/// record itself, instead of the size and offset.
+const TAG_LOCATION_MASK: u16 = 0xc000;
+const LOCATION_IN_RECORD: u16 = 0x4000;
+
+/// Bits 11-13 of a tag are the type of its data, two of which are heaps.
+const TAG_TYPE_MASK: u16 = 0x3800;
+const TYPES_HEAP: [u16; 2] = [0x2800, 0x3000];
+
+/// Bounds the recursion into the heaps of malformed files.
+const MAX_HEAP_DEPTH: usize = 8;
+
+/// The NUL-terminated make, followed by the NUL-terminated model.
+const TAG_MAKE_MODEL: u16 = 0x080a;
+/// The (logarithmic) ISO index is the third 16-bit word.
+const TAG_SHOT_INFO: u16 = 0x102a;
+const SHOT_INFO_ISO_OFFSET: usize = 4;
+/// The sensor width and height follow a 16-bit word.
+const TAG_SENSOR_INFO: u16 = 0x1031;
+const SENSOR_INFO_DIMS_OFFSET: usize = 2;
+/// The index of the Huffman tables of the raw data.
+const TAG_DECODER_TABLE: u16 = 0x1835;
+const TAG_RAW_DATA: u16 = 0x2005;
+
+type T = u16;
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..)
+        .and_then(|rest| rest.get(..size_of::<u16>()))
+        .map(|bytes| ByteStreamer::new(bytes, Endianness::Little).read())
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<usize> {
+    data.get(offset..)
+        .and_then(|rest| rest.get(..size_of::<u32>()))
+        .map(|bytes| {
+            let val: u32 = ByteStreamer::new(bytes, Endianness::Little).read();
+            val.try_into().unwrap()
+        })
+}
+
+/// The ISO index is logarithmic: the ISO doubles every 32 steps, and is
+/// 50 at the index 128.
+#[expect(
+    clippy::float_arithmetic,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+fn iso_from_index(index: u16) -> u32 {
+    // Saturates.
+    ((f64::from(index) / 32.0 - 4.0).exp2() * 50.0).round() as u32
+}
+
+/// The records of all the heaps of a file, with the heaps flattened.
+#[derive(Debug)]
+struct Records<'a> {
+    records: Vec<(u16, &'a [u8])>,
+}
+
+impl<'a> Records<'a> {
+    fn parse(input: &'a [u8]) -> Result<Self, String> {
+        if !input.starts_with(BYTE_ORDER)
+            || input
+                .get(SIGNATURE_OFFSET..)
+                .and_then(|rest| rest.get(..SIGNATURE.len()))
+                != Some(SIGNATURE)
+        {
+            return Err("Not a CRW file".to_owned());
+        }
+        let header_len = read_u32(input, BYTE_ORDER.len())
+            .ok_or("The CRW header is truncated")?;
+        let heap = input
+            .get(header_len..)
+            .ok_or("The CRW header is truncated")?;
+        let mut records = Self { records: vec![] };
+        records.parse_heap(heap, 0)?;
+        Ok(records)
+    }
+
+    fn parse_heap(
+        &mut self,
+        heap: &'a [u8],
+        depth: usize,
+    ) -> Result<(), String> {
+        const TRUNCATED: &str = "The CRW heap is truncated";
+        if depth == MAX_HEAP_DEPTH {
+            return Err("The CRW heaps are nested too deeply".to_owned());
+        }
+        let records_offset = heap
+            .len()
+            .checked_sub(size_of::<u32>())
+            .and_then(|offset| read_u32(heap, offset))
+            .ok_or(TRUNCATED)?;
+        let num_records = read_u16(heap, records_offset).ok_or(TRUNCATED)?;
+        let records = heap
+            .get(records_offset + size_of::<u16>()..)
+            .and_then(|rest| rest.get(..usize::from(num_records) * RECORD_LEN))
+            .ok_or(TRUNCATED)?;
+        for record in records.chunks_exact(RECORD_LEN) {
+            let tag = read_u16(record, 0).unwrap();
+            let data = if tag & TAG_LOCATION_MASK == LOCATION_IN_RECORD {
+                record.get(RECORD_SIZE_OFFSET..).unwrap()
+            } else {
+                let size = read_u32(record, RECORD_SIZE_OFFSET).unwrap();
+                let offset = read_u32(record, RECORD_OFFSET_OFFSET).unwrap();
+                heap.get(offset..)
+                    .and_then(|rest| rest.get(..size))
+                    .ok_or("The CRW record is truncated")?
+            };
+            if TYPES_HEAP.contains(&(tag & TAG_TYPE_MASK)) {
+                self.parse_heap(data, depth + 1)?;
+            } else {
+                self.records.push((tag & !TAG_LOCATION_MASK, data));
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self, tag: u16) -> Option<&'a [u8]> {
+        self.records
+            .iter()
+            .find(|(record_tag, _)| *record_tag == tag)
+            .map(|(_, data)| *data)
+    }
+}
+
+/// The make and model, without their NUL terminators.
+fn parse_make_model(data: &[u8]) -> Option<(&str, &str)> {
+    let mut strings = data
+        .split(|byte| *byte == 0)
+        .map(|string| core::str::from_utf8(string).ok());
+    Some((strings.next()??, strings.next()??))
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct CrwDemuxer<'a> {
+    metadata: CameraMetadata<'a>,
+    decompressor: CrwDecompressor<'a>,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+}
+
+impl<'a> CrwDemuxer<'a> {
+    fn parse_dims(
+        records: &Records<'_>,
+    ) -> Result<Dimensions2D<core::num::NonZero<usize>>, String> {
+        let sensor_info = records
+            .get(TAG_SENSOR_INFO)
+            .ok_or("The CRW sensor info is missing")?;
+        let [width, height] = [0, 1].map(|index| {
+            read_u16(
+                sensor_info,
+                SENSOR_INFO_DIMS_OFFSET + index * size_of::<u16>(),
+            )
+        });
+        let (width, height) = width
+            .zip(height)
+            .ok_or("The CRW sensor info is truncated")?;
+        Ok(Dimensions2D::new(
+            RowLength::new(non_zero(width.into(), "width")?),
+            RowCount::new(non_zero(height.into(), "height")?),
+        ))
+    }
+
+    #[inline(never)]
+    pub fn new<F>(
+        input: &'a [u8],
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<(Self, ProcurementRequest), String>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let records = Records::parse(input)?;
+
+        let (make, model) = records
+            .get(TAG_MAKE_MODEL)
+            .and_then(parse_make_model)
+            .unwrap_or(("", ""));
+        let camera = cameras
+            .find(make, model, None)
+            .ok_or_else(|| format!("Unknown camera: {make} {model}"))?;
+        check_camera_support_fn(camera.supported)?;
+        let iso_speed = records
+            .get(TAG_SHOT_INFO)
+            .and_then(|shot_info| read_u16(shot_info, SHOT_INFO_ISO_OFFSET))
+            .map(iso_from_index);
+        let metadata = CameraMetadata::new(camera, make, model, iso_speed);
+
+        let dims = Self::parse_dims(&records)?;
+        let table = records
+            .get(TAG_DECODER_TABLE)
+            .and_then(|table| read_u32(table, 0))
+            .ok_or("The CRW decoder table is missing")?;
+        let raw = records
+            .get(TAG_RAW_DATA)
+            .ok_or("No raw image found in CRW")?;
+        // Only the older bodies do not store the low bits.
+        let low_bits = metadata.hint("no_decompressed_lowbits").is_none();
+        let decompressor = CrwDecompressor::new(
+            raw,
+            dims,
+            table.try_into().unwrap(),
+            low_bits,
+        )
+        .map_err(|err| err.to_string())?;
+        Ok((
+            Self {
+                metadata,
+                decompressor,
+                dims,
+            },
+            NDSliceProcurementRequest::<T>::new(dims).into(),
+        ))
+    }
+}
+
+#[expect(clippy::missing_trait_methods)]
+impl RawDemuxer for CrwDemuxer<'_> {
+    #[inline]
+    fn make(&self) -> &str {
+        self.metadata.make
+    }
+
+    #[inline]
+    fn model(&self) -> &str {
+        self.metadata.model
+    }
+
+    #[inline]
+    fn mode(&self) -> Option<&str> {
+        self.metadata.mode()
+    }
+
+    #[inline]
+    fn canonical_make(&self) -> &str {
+        self.metadata.canonical_make()
+    }
+
+    #[inline]
+    fn canonical_model(&self) -> &str {
+        self.metadata.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_alias(&self) -> &str {
+        self.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_id(&self) -> String {
+        self.metadata.canonical_id()
+    }
+
+    #[inline]
+    fn iso_speed(&self) -> Option<u32> {
+        self.metadata.iso_speed
+    }
+
+    #[inline]
+    fn blacklevel(&self) -> Option<u16> {
+        self.metadata.blacklevel()
+    }
+
+    #[inline]
+    fn whitelevel(&self) -> Option<u16> {
+        self.metadata.whitelevel()
+    }
+
+    #[inline]
+    fn blacklevel_separate(&self) -> Option<Array2DRef<'_, i32>> {
+        None
+    }
+
+    #[inline]
+    fn wb_coeffs(&self) -> Option<[f32; 4]> {
+        None
+    }
+
+    #[inline]
+    fn colormatrix(&self) -> Option<Array2DRef<'_, i16>> {
+        self.metadata.colormatrix()
+    }
+
+    #[inline]
+    fn is_cfa(&self) -> bool {
+        self.metadata.is_cfa()
+    }
+
+    #[inline]
+    fn cfa(
+        &self,
+        origin: Coord2D,
+    ) -> Option<OffsetArray2DRef<'_, ColorVariant>> {
+        self.metadata.cfa(origin)
+    }
+
+    #[inline]
+    fn bpp(&self) -> usize {
+        let bpc = match self.datatype() {
+            DataType::U16 => size_of::<u16>(),
+            DataType::F32 => size_of::<f32>(),
+            DataType::U32 => size_of::<u32>(),
+            _ => unreachable!(),
+        };
+        bpc.checked_mul(self.cpp()).unwrap()
+    }
+
+    #[inline]
+    fn cpp(&self) -> usize {
+        1
+    }
+
+    #[inline]
+    fn datatype(&self) -> DataType {
+        DataType::U16
+    }
+
+    #[inline]
+    fn dim_uncropped(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        self.dims
+    }
+
+    #[inline]
+    fn dim_cropped(&self) -> Option<Dimensions2D<core::num::NonZero<usize>>> {
+        self.metadata.dim_cropped(self.dim_uncropped())
+    }
+
+    #[inline]
+    fn crop_offset(&self) -> Option<Coord2D> {
+        self.metadata.crop_offset()
+    }
+
+    #[inline]
+    fn black_areas(&self) -> Option<&[BlackArea]> {
+        self.metadata.black_areas()
+    }
+
+    #[inline]
+    fn fuji_rotation_pos(&self) -> Option<u32> {
+        None
+    }
+
+    #[inline]
+    fn pixel_aspect_ratio(&self) -> Option<f64> {
+        None
+    }
+
+    #[inline]
+    fn bad_pixel_positions(&self) -> Vec<Coord2D> {
+        vec![]
+    }
+
+    #[inline(never)]
+    fn decode(
+        &self,
+        output: &mut ImageRefMut<'_>,
+    ) -> Result<(), RawDemuxerError> {
+        let output = output.as_u16()?;
+        self.decompressor
+            .decode(output)
+            .map_err(|err| RawDemuxerError::DecoderError(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,52 @@
+use super::{Crw, decode, low_bits, new_demuxer_err};
+
+#[test]
+fn low_bits_test() {
+    let input = Crw::new(16, 12).build();
+    let low_bits = low_bits(16, 12);
+    let expected: Vec<u16> = (0..16 * 12)
+        .map(|index: usize| {
+            let byte = *low_bits.get(index / 4).unwrap();
+            (512 << 2) | ((u16::from(byte) >> (2 * (index % 4))) & 3)
+        })
+        .collect();
+    assert_eq!(
+        decode(&input),
+        Ok(expected.chunks(16).map(<[u16]>::to_vec).collect())
+    );
+}
+
+#[test]
+fn no_low_bits_test() {
+    let mut crw = Crw::new(16, 8);
+    crw.model = "Canon PowerShot Pro70";
+    crw.low_bits = false;
+    assert_eq!(decode(&crw.build()), Ok(vec![vec![512; 16]; 8]));
+}
+
+#[test]
+fn invalid_decoder_table_test() {
+    let mut crw = Crw::new(16, 8);
+    crw.table = 3;
+    assert_eq!(new_demuxer_err(&crw.build()), "CrwError(InvalidTable(3))");
+}
+
+#[test]
+fn invalid_dimensions_test() {
+    let crw = Crw::new(12, 8);
+    assert_eq!(new_demuxer_err(&crw.build()), "CrwError(InvalidDimensions)");
+}
+
+#[test]
+fn truncated_test() {
+    let mut input = Crw::new(16, 8).build();
+    // The records now start past the end of the heap.
+    let len = input.len();
+    input.truncate(len - 1);
+    assert_eq!(new_demuxer_err(&input), "The CRW heap is truncated");
+    assert_eq!(
+        new_demuxer_err(b"II\x1a\0\0\0HEAPCCDR"),
+        "The CRW header is truncated"
+    );
+    assert_eq!(new_demuxer_err(b"MM\0\0\0\x1aHEAPCCDR"), "Not a CRW file");
+}
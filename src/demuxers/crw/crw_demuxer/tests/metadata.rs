@@ -0,0 +1,66 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+
+use super::{Crw, CrwDemuxer, new_demuxer_err, parse_cameras};
+
+#[test]
+fn camera_from_make_model_test() {
+    let input = Crw::new(16, 8).build();
+    let cameras = parse_cameras();
+    let (demuxer, _) = CrwDemuxer::new(
+        &input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )
+    .unwrap();
+    assert_eq!(demuxer.make(), "Canon");
+    assert_eq!(demuxer.model(), "Canon EOS 10D");
+    assert_eq!(demuxer.canonical_id(), "Canon EOS 10D");
+    assert_eq!(demuxer.iso_speed(), Some(100));
+    assert!(demuxer.is_cfa());
+    let dims = demuxer.dim_uncropped();
+    assert_eq!((dims.row_len().get(), dims.row_count().get()), (16, 8));
+}
+
+#[test]
+fn sensor_for_iso_test() {
+    let cameras = parse_cameras();
+    for (iso_index, iso, black, white) in [
+        (Some(160), Some(100), 128, 4000),
+        (Some(192), Some(200), 128, 4000),
+        (Some(224), Some(400), 256, 4095),
+        (None, None, 256, 4095),
+    ] {
+        let mut crw = Crw::new(16, 8);
+        crw.iso_index = iso_index;
+        let input = crw.build();
+        let (demuxer, _) = CrwDemuxer::new(
+            &input,
+            &cameras,
+            DecodeableCamera::new_unless_unsupported,
+        )
+        .unwrap();
+        assert_eq!(demuxer.iso_speed(), iso);
+        assert_eq!(demuxer.blacklevel(), Some(black));
+        assert_eq!(demuxer.whitelevel(), Some(white));
+    }
+}
+
+#[test]
+fn unknown_camera_test() {
+    let mut crw = Crw::new(16, 8);
+    crw.model = "Canon EOS D30";
+    assert_eq!(
+        new_demuxer_err(&crw.build()),
+        "Unknown camera: Canon Canon EOS D30"
+    );
+    let mut unsupported = Crw::new(16, 8);
+    unsupported.model = "Unsupported";
+    assert_eq!(
+        new_demuxer_err(&unsupported.build()),
+        "This camera is not supported (explicit)"
+    );
+    let mut anonymous = Crw::new(16, 8);
+    anonymous.make_model = false;
+    assert_eq!(new_demuxer_err(&anonymous.build()), "Unknown camera:  ");
+}
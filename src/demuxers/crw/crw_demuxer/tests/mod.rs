@@ -0,0 +1,206 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::Cameras;
+use rawspeed_metadata_xmlparser::xmlparser;
+use rawspeed_std::coord_common::RowIndex;
+
+use super::CrwDemuxer;
+
+const CAMERAS: &str = "
+    <Cameras>
+        <Camera make=\"Canon\" model=\"Canon EOS 10D\">
+            <ID make=\"Canon\" model=\"EOS 10D\">Canon EOS 10D</ID>
+            <CFA width=\"2\" height=\"2\">
+                <Color x=\"0\" y=\"0\">RED</Color>
+                <Color x=\"1\" y=\"0\">GREEN</Color>
+                <Color x=\"0\" y=\"1\">GREEN</Color>
+                <Color x=\"1\" y=\"1\">BLUE</Color>
+            </CFA>
+            <Sensor black=\"128\" white=\"4000\" iso_list=\"100 200\"/>
+            <Sensor black=\"256\" white=\"4095\"/>
+        </Camera>
+        <Camera make=\"Canon\" model=\"Canon PowerShot Pro70\">
+            <ID make=\"Canon\" model=\"PowerShot Pro70\">Canon PowerShot Pro70</ID>
+            <Sensor black=\"0\" white=\"1023\"/>
+            <Hints>
+                <Hint name=\"no_decompressed_lowbits\" value=\"\"/>
+            </Hints>
+        </Camera>
+        <Camera make=\"Canon\" model=\"Unsupported\" supported=\"no\">
+        </Camera>
+    </Cameras>";
+
+/// The high bits of the test files are all 512, the prediction each row
+/// starts from: every block of decoder table 0 is then the (5-bit) code of
+/// a zero first difference and the (9-bit) end-of-block code.
+const FLAT_BLOCK: u32 = 0b11110_111111011;
+const FLAT_BLOCK_BITS: u32 = 14;
+
+/// The padding between the low and the high bits.
+const LOW_BITS_PADDING: usize = 514;
+
+const TAG_MAKE_MODEL: u16 = 0x080a;
+const TAG_SHOT_INFO: u16 = 0x102a;
+const TAG_SENSOR_INFO: u16 = 0x1031;
+/// The decoder table is stored in its record.
+const TAG_DECODER_TABLE_IN_RECORD: u16 = 0x5835;
+const TAG_RAW_DATA: u16 = 0x2005;
+const TAG_IMAGE_PROPS: u16 = 0x300a;
+
+fn u16_bytes(val: usize) -> [u8; 2] {
+    u16::try_from(val).unwrap().to_le_bytes()
+}
+
+fn u32_bytes(val: usize) -> [u8; 4] {
+    u32::try_from(val).unwrap().to_le_bytes()
+}
+
+/// The data of the records, followed by the records and their offset.
+fn heap(records: &[(u16, Vec<u8>)]) -> Vec<u8> {
+    let mut out = vec![];
+    let mut entries = vec![];
+    for (tag, data) in records {
+        let mut entry = tag.to_le_bytes().to_vec();
+        if tag & 0xc000 == 0x4000 {
+            let mut inline = data.clone();
+            inline.resize(8, 0);
+            entry.extend(inline);
+        } else {
+            entry.extend(u32_bytes(data.len()));
+            entry.extend(u32_bytes(out.len()));
+            out.extend(data);
+        }
+        entries.push(entry);
+    }
+    let records_offset = out.len();
+    out.extend(u16_bytes(entries.len()));
+    out.extend(entries.concat());
+    out.extend(u32_bytes(records_offset));
+    out
+}
+
+/// The low bits of the test files, which make the samples differ.
+fn low_bits(width: usize, height: usize) -> Vec<u8> {
+    (0..width * height / 4)
+        .map(|index| u8::try_from(index * 37 % 256).unwrap())
+        .collect()
+}
+
+fn flat_high_bits(num_blocks: usize) -> Vec<u8> {
+    let mut bits: Vec<bool> = vec![];
+    for _ in 0..num_blocks {
+        bits.extend(
+            (0..FLAT_BLOCK_BITS)
+                .rev()
+                .map(|bit| (FLAT_BLOCK >> bit) & 1 == 1),
+        );
+    }
+    bits.chunks(8)
+        .map(|byte| {
+            (0..8).fold(0_u8, |acc, index| {
+                (acc << 1) | u8::from(*byte.get(index).unwrap_or(&false))
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+struct Crw {
+    model: &'static str,
+    iso_index: Option<usize>,
+    table: usize,
+    width: usize,
+    height: usize,
+    low_bits: bool,
+    make_model: bool,
+}
+
+impl Crw {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            model: "Canon EOS 10D",
+            // ISO 100.
+            iso_index: Some(160),
+            table: 0,
+            width,
+            height,
+            low_bits: true,
+            make_model: true,
+        }
+    }
+
+    fn raw_data(&self) -> Vec<u8> {
+        let mut out = vec![];
+        if self.low_bits {
+            out.extend(low_bits(self.width, self.height));
+            out.resize(out.len() + LOW_BITS_PADDING, 0);
+        }
+        out.extend(flat_high_bits(self.width * self.height / 64));
+        out
+    }
+
+    fn build(&self) -> Vec<u8> {
+        let mut props = vec![];
+        if self.make_model {
+            props.push((
+                TAG_MAKE_MODEL,
+                format!("Canon\0{}\0", self.model).into_bytes(),
+            ));
+        }
+        if let Some(iso_index) = self.iso_index {
+            let mut shot_info = vec![0; 4];
+            shot_info.extend(u16_bytes(iso_index));
+            props.push((TAG_SHOT_INFO, shot_info));
+        }
+        let mut sensor_info = u16_bytes(0).to_vec();
+        sensor_info.extend(u16_bytes(self.width));
+        sensor_info.extend(u16_bytes(self.height));
+        props.push((TAG_SENSOR_INFO, sensor_info));
+        props.push((
+            TAG_DECODER_TABLE_IN_RECORD,
+            u32_bytes(self.table).to_vec(),
+        ));
+        let root = heap(&[
+            (TAG_RAW_DATA, self.raw_data()),
+            (TAG_IMAGE_PROPS, heap(&props)),
+        ]);
+        let mut out = b"II".to_vec();
+        out.extend(u32_bytes(26));
+        out.extend(b"HEAPCCDR");
+        out.extend(u32_bytes(0x0001_0002));
+        out.resize(26, 0);
+        out.extend(root);
+        out
+    }
+}
+
+fn parse_cameras() -> Cameras<'static> {
+    xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap()
+}
+
+fn new_demuxer_err(input: &[u8]) -> String {
+    let cameras = parse_cameras();
+    CrwDemuxer::new(input, &cameras, DecodeableCamera::new_unless_unsupported)
+        .unwrap_err()
+}
+
+fn decode(input: &[u8]) -> Result<Vec<Vec<u16>>, String> {
+    let cameras = parse_cameras();
+    let (demuxer, request) = CrwDemuxer::new(
+        input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )?;
+    let mut output_buf = request.fulfill().unwrap();
+    let mut image = output_buf.get_mut();
+    demuxer.decode(&mut image).map_err(|err| err.to_string())?;
+    let output = image.as_u16().unwrap();
+    Ok((0..output.num_rows().get())
+        .map(|row| output.get_row(RowIndex::new(row)).unwrap().to_vec())
+        .collect())
+}
+
+#[cfg(test)]
+mod data;
+#[cfg(test)]
+mod metadata;
@@ -0,0 +1 @@
+pub mod crw_demuxer;
@@ -0,0 +1,978 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_codecs_deflate::deflate::{DeflateDecompressor, Predictor};
+use rawspeed_codecs_jpeg::jpeg::JpegDecompressor;
+use rawspeed_codecs_jpegxl::jpegxl::JpegXlDecompressor;
+use rawspeed_codecs_packed_decoder::packed_decoder::Unpacker;
+use rawspeed_demuxers_common::{
+    camera_metadata::CameraMetadata,
+    tiff_utils::{
+        OwnedArray2D, get_root_string, get_u32, get_usize, non_zero, tiff_err,
+    },
+};
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::{
+    DataType, ImageRefMut, ProcurementRequest, RawDemuxer, RawDemuxerError,
+};
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_memory_nd_slice_procurement::ndsliceprocurement::NDSliceProcurementRequest;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::{
+    Cameras, Supported, blackareas::BlackArea,
+};
+use rawspeed_metadata_colorfilterarray::colorfilterarray::{
+    ColorFilterArray, ColorVariant,
+};
+use rawspeed_parsers_tiffparser::tiffparser::{
+    TiffEntry, TiffIFD, TiffRootIFD, TiffTag,
+};
+use rawspeed_std::coord_common::{
+    ColIndex, Coord2D, Dimensions2D, RowCount, RowIndex, RowLength, RowPitch,
+};
+use rawspeed_std_ndslice::{
+    array2dref::Array2DRef, array2drefmut::Array2DRefMut,
+    offsetarray2dref::OffsetArray2DRef,
+};
+
+const PHOTOMETRIC_CFA: u32 = 32803;
+const PHOTOMETRIC_LINEAR_RAW: u32 = 34892;
+const COMPRESSION_NONE: u32 = 1;
+const COMPRESSION_DEFLATE: u32 = 8;
+const COMPRESSION_LOSSY_JPEG: u32 = 34892;
+const COMPRESSION_JPEG_XL: u32 = 52546;
+const SAMPLE_FORMAT_FLOAT: u32 = 3;
+const CFA_LAYOUT_RECTANGULAR: u32 = 1;
+const ILLUMINANT_D65: u32 = 21;
+
+type T = u16;
+
+#[expect(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn round_to_usize(val: f32) -> Result<usize, String> {
+    if !val.is_finite() || val < 0.0 {
+        return Err(format!("The value {val} is out of range"));
+    }
+    // Saturates, out-of-range values are rejected by the bounds checks.
+    Ok((val.round() as u32).try_into().unwrap())
+}
+
+/// Strips are handled as tiles spanning the full image width.
+#[derive(Debug)]
+struct Tiles<'a> {
+    /// In pixels.
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    cpp: core::num::NonZero<usize>,
+    /// The number of tiles across the image.
+    across: usize,
+    bytes_per_row: core::num::NonZero<usize>,
+    data: Vec<&'a [u8]>,
+}
+
+impl<'a> Tiles<'a> {
+    fn parse(
+        input: &'a [u8],
+        ifd: &TiffIFD<'a>,
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        cpp: core::num::NonZero<usize>,
+        bits: u32,
+        compression: u32,
+    ) -> Result<Self, String> {
+        let (tile_width, tile_height, offsets_tag, counts_tag) =
+            if ifd.has_entry(TiffTag::TILE_OFFSETS) {
+                (
+                    get_usize(ifd, TiffTag::TILE_WIDTH)?,
+                    get_usize(ifd, TiffTag::TILE_LENGTH)?,
+                    TiffTag::TILE_OFFSETS,
+                    TiffTag::TILE_BYTE_COUNTS,
+                )
+            } else {
+                (
+                    Some(dims.row_len().get()),
+                    Some(
+                        get_usize(ifd, TiffTag::ROWS_PER_STRIP)?
+                            .map_or(dims.row_count().get(), |rows| {
+                                rows.min(dims.row_count().get())
+                            }),
+                    ),
+                    TiffTag::STRIP_OFFSETS,
+                    TiffTag::STRIP_BYTE_COUNTS,
+                )
+            };
+        let tile_width = non_zero(tile_width.unwrap_or(0), "tile width")?;
+        let tile_height = non_zero(tile_height.unwrap_or(0), "tile height")?;
+        let offsets = ifd
+            .get_required_entry(offsets_tag)
+            .and_then(TiffEntry::get_u32s)
+            .map_err(tiff_err)?;
+        let counts = ifd
+            .get_required_entry(counts_tag)
+            .and_then(TiffEntry::get_u32s)
+            .map_err(tiff_err)?;
+
+        let tiles_per_row = dims.row_len().get().div_ceil(tile_width.get());
+        let tiles_per_col = dims.row_count().get().div_ceil(tile_height.get());
+        if offsets.len() != counts.len()
+            || offsets.len() != tiles_per_row * tiles_per_col
+        {
+            return Err("The tile/strip count is invalid".to_owned());
+        }
+
+        let bytes_per_row = tile_width
+            .get()
+            .checked_mul(cpp.get())
+            .and_then(|samples| samples.checked_mul(bits.try_into().unwrap()))
+            .map(|bits| bits.div_ceil(8))
+            .ok_or("Overflow when computing per-row byte count")?;
+        let bytes_per_row = non_zero(bytes_per_row, "row byte count")?;
+
+        let mut data = Vec::with_capacity(offsets.len());
+        for (index, (offset, count)) in offsets.iter().zip(&counts).enumerate()
+        {
+            let first_row = (index / tiles_per_row) * tile_height.get();
+            let num_rows =
+                tile_height.get().min(dims.row_count().get() - first_row);
+            let offset: usize = (*offset).try_into().unwrap();
+            let count: usize = (*count).try_into().unwrap();
+            // Compressed tiles span their byte count.
+            let len = if compression == COMPRESSION_NONE {
+                num_rows
+                    .checked_mul(bytes_per_row.get())
+                    .ok_or("Overflow when computing per-tile byte count")?
+            } else {
+                count
+            };
+            let tile = input
+                .get(offset..)
+                .and_then(|tile| tile.get(..len))
+                .filter(|_| count >= len)
+                .ok_or("The tile/strip data is truncated")?;
+            data.push(tile);
+        }
+
+        Ok(Self {
+            dims: Dimensions2D::new(
+                RowLength::new(tile_width),
+                RowCount::new(tile_height),
+            ),
+            cpp,
+            across: tiles_per_row,
+            bytes_per_row,
+            data,
+        })
+    }
+
+    fn pos(&self, index: usize) -> Coord2D {
+        Coord2D::new(
+            RowIndex::new((index / self.across) * self.dims.row_count().get()),
+            ColIndex::new((index % self.across) * self.dims.row_len().get()),
+        )
+    }
+
+    /// The number of samples in each row of a tile.
+    fn row_len(&self) -> RowLength<core::num::NonZero<usize>> {
+        RowLength::new(self.dims.row_len().checked_mul(self.cpp).unwrap())
+    }
+
+    /// The number of rows of the tile at `pos` that lie within the image.
+    fn num_rows(&self, pos: Coord2D, height: usize) -> usize {
+        self.dims.row_count().get().min(height - *pos.row())
+    }
+}
+
+/// How the samples of the tiles are stored.
+#[derive(Debug)]
+enum Format<'a> {
+    Uncompressed(BitOrder),
+    Deflate(Vec<DeflateDecompressor<'a>>),
+    LossyJpeg(Vec<JpegDecompressor<'a>>),
+    JpegXl(Vec<JpegXlDecompressor>),
+}
+
+impl<'a> Format<'a> {
+    fn parse(
+        tiles: &Tiles<'a>,
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        compression: u32,
+        bits: u32,
+        order: BitOrder,
+        predictor: Predictor,
+        endianness: Endianness,
+    ) -> Result<Self, String> {
+        // Compressed tiles are stored whole, even at the image edges,
+        // but strips need not be.
+        let check_dims = |index, tile_dims: Dimensions2D<_>| {
+            let num_rows =
+                tiles.num_rows(tiles.pos(index), dims.row_count().get());
+            if tile_dims.row_len() != tiles.row_len()
+                || tile_dims.row_count().get() < num_rows
+            {
+                return Err(
+                    "The tile/strip dimensions differ from those of its data"
+                        .to_owned(),
+                );
+            }
+            Ok(())
+        };
+        Ok(match compression {
+            COMPRESSION_DEFLATE => {
+                let mut decoders = Vec::with_capacity(tiles.data.len());
+                for (index, tile) in tiles.data.iter().enumerate() {
+                    // Only the rows within the image are decoded.
+                    let num_rows = tiles
+                        .num_rows(tiles.pos(index), dims.row_count().get());
+                    let tile_dims = Dimensions2D::new(
+                        tiles.row_len(),
+                        RowCount::new(non_zero(num_rows, "tile height")?),
+                    );
+                    let decoder = DeflateDecompressor::new(
+                        tile, tile_dims, tiles.cpp, bits, predictor, endianness,
+                    )
+                    .map_err(|err| err.to_string())?;
+                    decoders.push(decoder);
+                }
+                Format::Deflate(decoders)
+            }
+            COMPRESSION_LOSSY_JPEG => {
+                let mut decoders = Vec::with_capacity(tiles.data.len());
+                for (index, tile) in tiles.data.iter().enumerate() {
+                    let decoder = JpegDecompressor::new(tile)
+                        .map_err(|err| err.to_string())?;
+                    check_dims(index, decoder.dims())?;
+                    decoders.push(decoder);
+                }
+                Format::LossyJpeg(decoders)
+            }
+            COMPRESSION_JPEG_XL => {
+                let mut decoders = Vec::with_capacity(tiles.data.len());
+                for (index, tile) in tiles.data.iter().enumerate() {
+                    let decoder = JpegXlDecompressor::new(tile)
+                        .map_err(|err| err.to_string())?;
+                    check_dims(index, decoder.dims())?;
+                    if decoder.bits_per_sample() != bits {
+                        return Err(
+                            "The tile/strip bit depth differs from that of \
+                             the image"
+                                .to_owned(),
+                        );
+                    }
+                    decoders.push(decoder);
+                }
+                Format::JpegXl(decoders)
+            }
+            _ => Format::Uncompressed(order),
+        })
+    }
+}
+
+fn parse_predictor(ifd: &TiffIFD<'_>) -> Result<Predictor, String> {
+    Ok(match get_u32(ifd, TiffTag::PREDICTOR)?.unwrap_or(1) {
+        1 => Predictor::None,
+        2 => Predictor::Horizontal,
+        3 => Predictor::FloatingPoint,
+        predictor => {
+            return Err(format!("Unsupported DNG predictor: {predictor}"));
+        }
+    })
+}
+
+fn is_main_raw_ifd(ifd: &TiffIFD<'_>) -> bool {
+    matches!(get_u32(ifd, TiffTag::NEW_SUBFILE_TYPE), Ok(None | Some(0)))
+        && matches!(
+            get_u32(ifd, TiffTag::PHOTOMETRIC_INTERPRETATION),
+            Ok(Some(PHOTOMETRIC_CFA | PHOTOMETRIC_LINEAR_RAW))
+        )
+}
+
+fn parse_cfa_color(color: u8) -> Result<ColorVariant, String> {
+    Ok(match color {
+        0 => ColorVariant::Red,
+        1 => ColorVariant::Green,
+        2 => ColorVariant::Blue,
+        3 => ColorVariant::Cyan,
+        4 => ColorVariant::Magenta,
+        5 => ColorVariant::Yellow,
+        _ => return Err(format!("Unsupported CFA color: {color}")),
+    })
+}
+
+fn parse_cfa(ifd: &TiffIFD<'_>) -> Result<Option<ColorFilterArray>, String> {
+    if get_u32(ifd, TiffTag::PHOTOMETRIC_INTERPRETATION)?
+        != Some(PHOTOMETRIC_CFA)
+    {
+        return Ok(None);
+    }
+    if !matches!(
+        get_u32(ifd, TiffTag::CFA_LAYOUT)?,
+        None | Some(CFA_LAYOUT_RECTANGULAR)
+    ) {
+        return Err("Unsupported CFA layout".to_owned());
+    }
+    let dim = ifd
+        .get_required_entry(TiffTag::CFA_REPEAT_PATTERN_DIM)
+        .and_then(TiffEntry::get_u16s)
+        .map_err(tiff_err)?;
+    let [rows, cols] = dim.as_slice() else {
+        return Err("The CFA pattern dimensions are invalid".to_owned());
+    };
+    let pattern = ifd
+        .get_required_entry(TiffTag::CFA_PATTERN)
+        .map_err(tiff_err)?;
+    if pattern.len() != usize::from(*rows) * usize::from(*cols) {
+        return Err("The CFA pattern size is invalid".to_owned());
+    }
+    let colors = (0..pattern.len())
+        .map(|i| parse_cfa_color(pattern.get_u8(i).map_err(tiff_err)?))
+        .collect::<Result<Vec<_>, _>>()?;
+    let row_length = non_zero(usize::from(*cols), "CFA row length")?;
+    Ok(Some(ColorFilterArray::new(
+        colors,
+        RowLength::new(row_length),
+    )))
+}
+
+/// Each of the `cpp` components has black levels of its own, which are
+/// interleaved like the samples.
+#[expect(clippy::cast_possible_truncation)]
+fn parse_black_levels(
+    ifd: &TiffIFD<'_>,
+    cpp: core::num::NonZero<usize>,
+) -> Result<Option<OwnedArray2D<i32>>, String> {
+    let Some(entry) = ifd.get_entry(TiffTag::BLACK_LEVEL) else {
+        return Ok(None);
+    };
+    let (rows, cols) = match ifd.get_entry(TiffTag::BLACK_LEVEL_REPEAT_DIM) {
+        Some(dim) => (
+            usize::from(dim.get_u16(0).map_err(tiff_err)?),
+            usize::from(dim.get_u16(1).map_err(tiff_err)?),
+        ),
+        None => (1, 1),
+    };
+    let cols = cols * cpp.get();
+    if entry.len() != rows * cols {
+        return Err("The black level count is invalid".to_owned());
+    }
+    let levels = (0..entry.len())
+        .map(|i| entry.get_f32(i).map(|val| val.round() as i32))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(tiff_err)?;
+    OwnedArray2D::new(levels, cols).map(Some)
+}
+
+fn parse_linearization_table(
+    ifd: &TiffIFD<'_>,
+) -> Result<Option<Vec<T>>, String> {
+    let Some(entry) = ifd.get_entry(TiffTag::LINEARIZATION_TABLE) else {
+        return Ok(None);
+    };
+    let table = entry.get_u16s().map_err(tiff_err)?;
+    if table.is_empty() {
+        return Err("The linearization table is empty".to_owned());
+    }
+    Ok(Some(table))
+}
+
+/// Maps the samples through the table, whose last entry also covers the
+/// samples past its end.
+fn linearize(table: &[T], samples: &mut [T]) {
+    let last = *table.last().unwrap();
+    for sample in samples {
+        *sample = table.get(usize::from(*sample)).copied().unwrap_or(last);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Crop {
+    pos: Coord2D,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+}
+
+fn get_f32_pair(
+    ifd: &TiffIFD<'_>,
+    tag: TiffTag,
+) -> Result<Option<(usize, usize)>, String> {
+    let Some(entry) = ifd.get_entry(tag) else {
+        return Ok(None);
+    };
+    let first = entry.get_f32(0).map_err(tiff_err)?;
+    let second = entry.get_f32(1).map_err(tiff_err)?;
+    Ok(Some((round_to_usize(first)?, round_to_usize(second)?)))
+}
+
+fn parse_crop(
+    ifd: &TiffIFD<'_>,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+) -> Result<Crop, String> {
+    let (width, height) = (dims.row_len().get(), dims.row_count().get());
+    let (top, left, bottom, right) = match ifd.get_entry(TiffTag::ACTIVE_AREA) {
+        Some(entry) => {
+            let area = entry.get_u32s().map_err(tiff_err)?;
+            let area = area
+                .into_iter()
+                .map(|val| usize::try_from(val).unwrap())
+                .collect::<Vec<_>>();
+            let [top, left, bottom, right] = area.as_slice() else {
+                return Err("The active area is invalid".to_owned());
+            };
+            (*top, *left, *bottom, *right)
+        }
+        None => (0, 0, height, width),
+    };
+    if top >= bottom || left >= right || bottom > height || right > width {
+        return Err("The active area is invalid".to_owned());
+    }
+    let (area_width, area_height) = (right - left, bottom - top);
+
+    let (x, y) =
+        get_f32_pair(ifd, TiffTag::DEFAULT_CROP_ORIGIN)?.unwrap_or((0, 0));
+    let (crop_width, crop_height) =
+        get_f32_pair(ifd, TiffTag::DEFAULT_CROP_SIZE)?.unwrap_or((
+            area_width - x.min(area_width),
+            area_height - y.min(area_height),
+        ));
+    if x + crop_width > area_width || y + crop_height > area_height {
+        return Err("The default crop is invalid".to_owned());
+    }
+    Ok(Crop {
+        pos: Coord2D::new(RowIndex::new(top + y), ColIndex::new(left + x)),
+        dims: Dimensions2D::new(
+            RowLength::new(non_zero(crop_width, "crop width")?),
+            RowCount::new(non_zero(crop_height, "crop height")?),
+        ),
+    })
+}
+
+#[expect(clippy::cast_possible_truncation, clippy::float_arithmetic)]
+fn parse_colormatrix(
+    root: &TiffRootIFD<'_>,
+) -> Result<Option<OwnedArray2D<i16>>, String> {
+    let candidates = [
+        (TiffTag::COLOR_MATRIX1, TiffTag::CALIBRATION_ILLUMINANT1),
+        (TiffTag::COLOR_MATRIX2, TiffTag::CALIBRATION_ILLUMINANT2),
+    ];
+    let is_d65 = |illuminant| {
+        root.get_entry_recursive(illuminant)
+            .is_some_and(|entry| entry.get_u32(0) == Ok(ILLUMINANT_D65))
+    };
+    let Some(entry) = candidates
+        .iter()
+        .find(|(_, illuminant)| is_d65(*illuminant))
+        .or(candidates.first())
+        .and_then(|(matrix, _)| root.get_entry_recursive(*matrix))
+    else {
+        return Ok(None);
+    };
+    let values = (0..entry.len())
+        .map(|i| {
+            let val = (entry.get_f32(i).map_err(tiff_err)? * 10000.0).round();
+            if !(f32::from(i16::MIN)..=f32::from(i16::MAX)).contains(&val) {
+                return Err("The color matrix is out of range".to_owned());
+            }
+            Ok(val as i16)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    OwnedArray2D::new(values, 3).map(Some)
+}
+
+#[expect(clippy::float_arithmetic)]
+fn parse_wb_coeffs(root: &TiffRootIFD<'_>) -> Result<Option<[f32; 4]>, String> {
+    let Some(entry) = root.get_entry_recursive(TiffTag::AS_SHOT_NEUTRAL) else {
+        return Ok(None);
+    };
+    let neutral = entry.get_f32s().map_err(tiff_err)?;
+    if !(3..=4).contains(&neutral.len()) || neutral.iter().any(|v| *v <= 0.0) {
+        return Ok(None);
+    }
+    let mut coeffs = [f32::NAN; 4];
+    for (coeff, val) in coeffs.iter_mut().zip(neutral) {
+        *coeff = 1.0 / val;
+    }
+    Ok(Some(coeffs))
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct DngDemuxer<'a> {
+    metadata: CameraMetadata<'a>,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    bits: u32,
+    float: bool,
+    tiles: Tiles<'a>,
+    format: Format<'a>,
+    cfa: Option<ColorFilterArray>,
+    linearization: Option<Vec<T>>,
+    black_levels: Option<OwnedArray2D<i32>>,
+    whitelevel: u16,
+    crop: Crop,
+    colormatrix: Option<OwnedArray2D<i16>>,
+    wb_coeffs: Option<[f32; 4]>,
+}
+
+impl<'a> DngDemuxer<'a> {
+    #[inline]
+    #[must_use]
+    pub fn is_dng(root: &TiffRootIFD<'_>) -> bool {
+        root.get_entry_recursive(TiffTag::DNG_VERSION).is_some()
+    }
+
+    fn parse_dims(
+        ifd: &TiffIFD<'_>,
+    ) -> Result<Dimensions2D<core::num::NonZero<usize>>, String> {
+        let width = get_usize(ifd, TiffTag::IMAGE_WIDTH)?.unwrap_or(0);
+        let height = get_usize(ifd, TiffTag::IMAGE_LENGTH)?.unwrap_or(0);
+        Ok(Dimensions2D::new(
+            RowLength::new(non_zero(width, "width")?),
+            RowCount::new(non_zero(height, "height")?),
+        ))
+    }
+
+    /// Returns the compression, the bits per sample, and whether the
+    /// samples are floating-point.
+    fn parse_format(ifd: &TiffIFD<'_>) -> Result<(u32, u32, bool), String> {
+        let compression =
+            get_u32(ifd, TiffTag::COMPRESSION)?.unwrap_or(COMPRESSION_NONE);
+        if ![
+            COMPRESSION_NONE,
+            COMPRESSION_DEFLATE,
+            COMPRESSION_LOSSY_JPEG,
+            COMPRESSION_JPEG_XL,
+        ]
+        .contains(&compression)
+        {
+            return Err(format!("Unsupported DNG compression: {compression}"));
+        }
+        // Floating-point samples are only ever deflated.
+        let float =
+            get_u32(ifd, TiffTag::SAMPLE_FORMAT)? == Some(SAMPLE_FORMAT_FLOAT);
+        if float && compression != COMPRESSION_DEFLATE {
+            return Err(format!(
+                "Unsupported DNG compression for floating-point samples: \
+                 {compression}"
+            ));
+        }
+        let bits = get_u32(ifd, TiffTag::BITS_PER_SAMPLE)?.unwrap_or(0);
+        let supported = match compression {
+            _ if float => [16, 24, 32].contains(&bits),
+            // Lossy JPEG is always baseline, i.e. 8-bit.
+            COMPRESSION_LOSSY_JPEG => bits == 8,
+            COMPRESSION_DEFLATE => [8, 16].contains(&bits),
+            _ => (1..=T::BITS).contains(&bits),
+        };
+        if !supported {
+            return Err(format!("Unsupported bits per sample: {bits}"));
+        }
+        Ok((compression, bits, float))
+    }
+
+    /// CFA images have a single component, linear ones up to four.
+    fn parse_cpp(
+        ifd: &TiffIFD<'_>,
+    ) -> Result<core::num::NonZero<usize>, String> {
+        let cpp = get_usize(ifd, TiffTag::SAMPLES_PER_PIXEL)?.unwrap_or(1);
+        let max = match get_u32(ifd, TiffTag::PHOTOMETRIC_INTERPRETATION)? {
+            Some(PHOTOMETRIC_LINEAR_RAW) => 4,
+            _ => 1,
+        };
+        if !(1..=max).contains(&cpp) {
+            return Err(format!("Unsupported samples per pixel: {cpp}"));
+        }
+        non_zero(cpp, "samples per pixel")
+    }
+
+    #[inline(never)]
+    pub fn new<F>(
+        root: &TiffRootIFD<'a>,
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<(Self, ProcurementRequest), String>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        if !Self::is_dng(root) {
+            return Err("Not a DNG".to_owned());
+        }
+
+        let make = get_root_string(root, TiffTag::MAKE).unwrap_or("");
+        let model = get_root_string(root, TiffTag::MODEL)
+            .or_else(|| get_root_string(root, TiffTag::UNIQUE_CAMERA_MODEL))
+            .unwrap_or("");
+        let iso_speed = root
+            .get_entry_recursive(TiffTag::ISO_SPEED_RATINGS)
+            .and_then(|entry| entry.get_u32(0).ok());
+        let metadata = match cameras.find(make, model, Some("dng")) {
+            Some(camera) => {
+                check_camera_support_fn(camera.supported)?;
+                CameraMetadata::new(camera, make, model, iso_speed)
+            }
+            None => CameraMetadata::without_camera(make, model, iso_speed),
+        };
+
+        let ifd = root
+            .get_ifds_with_tag(TiffTag::PHOTOMETRIC_INTERPRETATION)
+            .into_iter()
+            .find(|ifd| is_main_raw_ifd(ifd))
+            .ok_or("No raw image found in DNG")?;
+
+        let dims = Self::parse_dims(ifd)?;
+        let (compression, bits, float) = Self::parse_format(ifd)?;
+        let cpp = Self::parse_cpp(ifd)?;
+        // Only full-width little-endian samples are stored in LSB order,
+        // everything else is bit-packed MSB-first.
+        let order =
+            if bits == T::BITS && root.endianness() == Endianness::Little {
+                BitOrder::LSB
+            } else {
+                BitOrder::MSB
+            };
+        let linearization = parse_linearization_table(ifd)?;
+        let whitelevel =
+            match (get_u32(ifd, TiffTag::WHITE_LEVEL)?, &linearization) {
+                (Some(white), _) => white
+                    .try_into()
+                    .map_err(|_err| "The white level is out of range")?,
+                // Floating-point samples are normalized.
+                (None, _) if float => 1,
+                (None, Some(table)) => *table.iter().max().unwrap(),
+                (None, None) => T::MAX >> (T::BITS - bits),
+            };
+        let tiles =
+            Tiles::parse(root.input(), ifd, dims, cpp, bits, compression)?;
+        let format = Format::parse(
+            &tiles,
+            dims,
+            compression,
+            bits,
+            order,
+            parse_predictor(ifd)?,
+            root.endianness(),
+        )?;
+
+        Ok((
+            Self {
+                metadata,
+                dims,
+                bits,
+                float,
+                tiles,
+                format,
+                cfa: parse_cfa(ifd)?,
+                linearization,
+                black_levels: parse_black_levels(ifd, cpp)?,
+                whitelevel,
+                crop: parse_crop(ifd, dims)?,
+                colormatrix: parse_colormatrix(root)?,
+                wb_coeffs: parse_wb_coeffs(root)?,
+            },
+            if float {
+                NDSliceProcurementRequest::<f32>::new(dims)
+                    .set_cpp(cpp)
+                    .into()
+            } else {
+                NDSliceProcurementRequest::<T>::new(dims)
+                    .set_cpp(cpp)
+                    .into()
+            },
+        ))
+    }
+
+    /// Decodes the integer samples of a tile into a buffer of its own.
+    fn decode_tile(&self, index: usize) -> Result<Vec<T>, String> {
+        let mut buf = self.decode_raw_tile(index)?;
+        if let Some(table) = &self.linearization {
+            linearize(table, &mut buf);
+        }
+        Ok(buf)
+    }
+
+    /// Decodes the samples of a tile as they are stored.
+    fn decode_raw_tile(&self, index: usize) -> Result<Vec<T>, String> {
+        let tile_width = self.tiles.row_len().val();
+        Ok(match &self.format {
+            Format::Uncompressed(order) => {
+                let tile = *self.tiles.data.get(index).unwrap();
+                let num_rows = self.tiles.num_rows(
+                    self.tiles.pos(index),
+                    self.dims.row_count().get(),
+                );
+                let mut buf = vec![0; tile_width.get() * num_rows];
+                Unpacker::new(
+                    Array2DRef::new(
+                        tile,
+                        RowLength::new(self.tiles.bytes_per_row),
+                        RowPitch::new(self.tiles.bytes_per_row),
+                    ),
+                    *order,
+                    self.bits,
+                    &mut Array2DRefMut::new(
+                        buf.as_mut_slice(),
+                        RowLength::new(tile_width),
+                        RowPitch::new(tile_width),
+                    ),
+                )
+                .unpack();
+                buf
+            }
+            Format::Deflate(decoders) => {
+                let decoder = decoders.get(index).unwrap();
+                decode_compressed(decoder.dims(), |decoded| {
+                    decoder.decode(decoded)
+                })?
+            }
+            Format::LossyJpeg(decoders) => {
+                let decoder = decoders.get(index).unwrap();
+                decode_compressed(decoder.dims(), |decoded| {
+                    decoder.decode(decoded)
+                })?
+            }
+            Format::JpegXl(decoders) => {
+                let decoder = decoders.get(index).unwrap();
+                decode_compressed(decoder.dims(), |decoded| {
+                    decoder.decode(decoded)
+                })?
+            }
+        })
+    }
+
+    /// Decodes the floating-point samples of a tile into a buffer of its own.
+    fn decode_float_tile(&self, index: usize) -> Result<Vec<f32>, String> {
+        let Format::Deflate(decoders) = &self.format else {
+            unreachable!()
+        };
+        let decoder = decoders.get(index).unwrap();
+        decode_compressed(decoder.dims(), |decoded| decoder.decode_f32(decoded))
+    }
+
+    /// Copies the part of a decoded tile that lies within the image.
+    fn copy_tile<S>(
+        &self,
+        index: usize,
+        buf: &[S],
+        output: &mut Array2DRefMut<'_, S>,
+    ) where
+        S: Copy,
+    {
+        let pos = self.tiles.pos(index);
+        let cpp = self.tiles.cpp.get();
+        let tile_width = self.tiles.row_len().get();
+        let num_rows = self.tiles.num_rows(pos, self.dims.row_count().get());
+        let first_col = *pos.col() * cpp;
+        let num_cols =
+            tile_width.min(self.dims.row_len().get() * cpp - first_col);
+        for (row, src) in
+            buf.chunks_exact(tile_width).take(num_rows).enumerate()
+        {
+            let dst = output[RowIndex::new(*pos.row() + row)]
+                .get_mut(first_col..first_col + num_cols)
+                .unwrap();
+            dst.copy_from_slice(src.get(..num_cols).unwrap());
+        }
+    }
+}
+
+/// Decodes a whole compressed tile into a buffer of its own.
+fn decode_compressed<S, E, F>(
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    decode: F,
+) -> Result<Vec<S>, String>
+where
+    S: Copy + Default,
+    E: core::fmt::Display,
+    F: FnOnce(&mut Array2DRefMut<'_, S>) -> Result<(), E>,
+{
+    let mut buf =
+        vec![S::default(); dims.row_len().get() * dims.row_count().get()];
+    decode(&mut Array2DRefMut::new(
+        buf.as_mut_slice(),
+        dims.row_len(),
+        RowPitch::new(*dims.row_len()),
+    ))
+    .map_err(|err| err.to_string())?;
+    Ok(buf)
+}
+
+#[expect(clippy::missing_trait_methods)]
+impl RawDemuxer for DngDemuxer<'_> {
+    #[inline]
+    fn make(&self) -> &str {
+        self.metadata.make
+    }
+
+    #[inline]
+    fn model(&self) -> &str {
+        self.metadata.model
+    }
+
+    #[inline]
+    fn mode(&self) -> Option<&str> {
+        self.metadata.mode()
+    }
+
+    #[inline]
+    fn canonical_make(&self) -> &str {
+        self.metadata.canonical_make()
+    }
+
+    #[inline]
+    fn canonical_model(&self) -> &str {
+        self.metadata.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_alias(&self) -> &str {
+        self.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_id(&self) -> String {
+        self.metadata.canonical_id()
+    }
+
+    #[inline]
+    fn iso_speed(&self) -> Option<u32> {
+        self.metadata.iso_speed
+    }
+
+    #[inline]
+    fn blacklevel(&self) -> Option<u16> {
+        let levels = self.black_levels.as_ref()?.data();
+        let first = *levels.first()?;
+        if levels.iter().any(|level| *level != first) {
+            return None;
+        }
+        first.try_into().ok()
+    }
+
+    #[inline]
+    fn whitelevel(&self) -> Option<u16> {
+        Some(self.whitelevel)
+    }
+
+    #[inline]
+    fn blacklevel_separate(&self) -> Option<Array2DRef<'_, i32>> {
+        self.black_levels.as_ref().map(OwnedArray2D::mat)
+    }
+
+    #[inline]
+    fn wb_coeffs(&self) -> Option<[f32; 4]> {
+        self.wb_coeffs
+    }
+
+    #[inline]
+    fn colormatrix(&self) -> Option<Array2DRef<'_, i16>> {
+        self.colormatrix.as_ref().map(OwnedArray2D::mat)
+    }
+
+    #[inline]
+    fn is_cfa(&self) -> bool {
+        self.cfa.is_some()
+    }
+
+    #[inline]
+    fn cfa(
+        &self,
+        origin: Coord2D,
+    ) -> Option<OffsetArray2DRef<'_, ColorVariant>> {
+        const ZERO_POINT: Coord2D =
+            Coord2D::new(RowIndex::new(0), ColIndex::new(0));
+        let offset = (origin - ZERO_POINT)?;
+        let cfa = self.cfa.as_ref().map(ColorFilterArray::mat)?;
+        Some(OffsetArray2DRef::new(cfa, offset))
+    }
+
+    #[inline]
+    fn bpp(&self) -> usize {
+        let bpc = match self.datatype() {
+            DataType::U16 => size_of::<u16>(),
+            DataType::F32 => size_of::<f32>(),
+            DataType::U32 => size_of::<u32>(),
+            _ => unreachable!(),
+        };
+        bpc.checked_mul(self.cpp()).unwrap()
+    }
+
+    #[inline]
+    fn cpp(&self) -> usize {
+        self.tiles.cpp.get()
+    }
+
+    #[inline]
+    fn datatype(&self) -> DataType {
+        if self.float {
+            DataType::F32
+        } else {
+            DataType::U16
+        }
+    }
+
+    #[inline]
+    fn dim_uncropped(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        self.dims
+    }
+
+    #[inline]
+    fn dim_cropped(&self) -> Option<Dimensions2D<core::num::NonZero<usize>>> {
+        Some(self.crop.dims)
+    }
+
+    #[inline]
+    fn crop_offset(&self) -> Option<Coord2D> {
+        Some(self.crop.pos)
+    }
+
+    #[inline]
+    fn black_areas(&self) -> Option<&[BlackArea]> {
+        None
+    }
+
+    #[inline]
+    fn fuji_rotation_pos(&self) -> Option<u32> {
+        None
+    }
+
+    #[inline]
+    fn pixel_aspect_ratio(&self) -> Option<f64> {
+        None
+    }
+
+    #[inline]
+    fn bad_pixel_positions(&self) -> Vec<Coord2D> {
+        vec![]
+    }
+
+    #[inline(never)]
+    fn decode(
+        &self,
+        output: &mut ImageRefMut<'_>,
+    ) -> Result<(), RawDemuxerError> {
+        let dims = Dimensions2D::new(
+            RowLength::new(
+                self.dims.row_len().checked_mul(self.tiles.cpp).unwrap(),
+            ),
+            self.dims.row_count(),
+        );
+        if output.dims() != dims {
+            return Err(RawDemuxerError::DecoderError(
+                "Output buffer dimensions differ from expected".to_owned(),
+            ));
+        }
+
+        if self.float {
+            let output = output.as_f32()?;
+            for index in 0..self.tiles.data.len() {
+                let buf = self
+                    .decode_float_tile(index)
+                    .map_err(RawDemuxerError::DecoderError)?;
+                self.copy_tile(index, &buf, output);
+            }
+        } else {
+            let output = output.as_u16()?;
+            for index in 0..self.tiles.data.len() {
+                let buf = self
+                    .decode_tile(index)
+                    .map_err(RawDemuxerError::DecoderError)?;
+                self.copy_tile(index, &buf, output);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,529 @@
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::{
+    DataType, ProcurementRequest, RawDemuxer as _,
+};
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_memory_nd_slice_procurement::ndsliceprocurement::NDSliceProcurementRequest;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_std::coord_common::RowIndex;
+use zune_core::bit_depth::BitDepth;
+use zune_core::colorspace::ColorSpace;
+use zune_core::options::EncoderOptions;
+use zune_jpegxl::JxlSimpleEncoder;
+
+use super::{
+    TiffBuilder, Value, decode, dng_ifd0, new_demuxer_err, parse_cameras,
+    raw_ifd, tag,
+};
+use crate::dng_demuxer::{
+    COMPRESSION_DEFLATE, COMPRESSION_JPEG_XL, COMPRESSION_LOSSY_JPEG,
+    DngDemuxer, SAMPLE_FORMAT_FLOAT,
+};
+
+/// A DNG whose raw image is stored as the given tiles, in row-major order.
+fn tiled_dng(
+    width: u32,
+    height: u32,
+    bits: u16,
+    compression: u32,
+    tile_dims: (u32, u32),
+    tiles: Vec<Vec<u8>>,
+) -> TiffBuilder {
+    let mut builder = TiffBuilder::new(Endianness::Little);
+    *builder.ifd(0) = dng_ifd0();
+    builder
+        .ifd(0)
+        .extend(raw_ifd(width, height, bits).into_iter().filter(
+            |(entry_tag, _)| {
+                ![
+                    TiffTag::COMPRESSION,
+                    TiffTag::STRIP_OFFSETS,
+                    TiffTag::STRIP_BYTE_COUNTS,
+                    TiffTag::ROWS_PER_STRIP,
+                ]
+                .iter()
+                .any(|strip_tag| strip_tag.val() == *entry_tag)
+            },
+        ));
+    let blobs: Vec<usize> = (0..tiles.len()).collect();
+    builder.ifd(0).extend([
+        tag(TiffTag::COMPRESSION, Value::Long(vec![compression])),
+        tag(TiffTag::TILE_WIDTH, Value::Long(vec![tile_dims.0])),
+        tag(TiffTag::TILE_LENGTH, Value::Long(vec![tile_dims.1])),
+        tag(TiffTag::TILE_OFFSETS, Value::BlobOffsets(blobs.clone())),
+        tag(TiffTag::TILE_BYTE_COUNTS, Value::BlobSizes(blobs)),
+    ]);
+    builder.blobs = tiles;
+    builder
+}
+
+/// Losslessly encodes the grayscale samples, which are 8-bit unless `bits`
+/// is 16.
+fn jpeg_xl(samples: &[u16], width: usize, height: usize, bits: u32) -> Vec<u8> {
+    let (data, depth): (Vec<u8>, _) = if bits == 16 {
+        let data = samples.iter().flat_map(|s| s.to_ne_bytes()).collect();
+        (data, BitDepth::Sixteen)
+    } else {
+        let data = samples.iter().map(|s| u8::try_from(*s).unwrap()).collect();
+        (data, BitDepth::Eight)
+    };
+    let options = EncoderOptions::new(width, height, ColorSpace::Luma, depth);
+    let mut out = vec![];
+    JxlSimpleEncoder::new(&data, options)
+        .encode(&mut out)
+        .unwrap();
+    out
+}
+
+fn be16(val: usize) -> [u8; 2] {
+    let val = u16::try_from(val).unwrap();
+    [
+        (val >> 8).try_into().unwrap(),
+        (val & 0xFF).try_into().unwrap(),
+    ]
+}
+
+fn segment(out: &mut Vec<u8>, marker: u8, payload: &[u8]) {
+    out.extend([0xFF, marker]);
+    out.extend(be16(payload.len() + 2));
+    out.extend(payload);
+}
+
+/// A grayscale baseline JPEG of a single flat 8x8 block.
+///
+/// Both Huffman tables hold a single 1-bit code, for the DC category 1 and
+/// for EOB respectively, so the scan is just a DC coefficient of 1.
+/// The value of the block is then set by the DC quantizer, as one of
+/// `8 * (v - 128)` yields `v`. Quantizers are unsigned, so `value` is at
+/// least 128.
+fn flat_jpeg(value: u8) -> Vec<u8> {
+    let mut out = vec![0xFF, 0xD8];
+    // 16-bit quantizers.
+    let mut dqt = vec![0x10];
+    dqt.extend(be16(8 * usize::from(value - 128)));
+    dqt.extend([0, 1].repeat(63));
+    segment(&mut out, 0xDB, &dqt);
+    let mut sof = vec![8];
+    sof.extend(be16(8));
+    sof.extend(be16(8));
+    sof.extend([1, 1, 0x11, 0]);
+    segment(&mut out, 0xC0, &sof);
+    for (class, symbol) in [(0x00, 1), (0x10, 0x00)] {
+        let mut dht = vec![class, 1];
+        dht.extend([0; 15]);
+        dht.push(symbol);
+        segment(&mut out, 0xC4, &dht);
+    }
+    segment(&mut out, 0xDA, &[1, 1, 0x00, 0, 63, 0]);
+    // The DC category 1 code and its difference bit, then EOB, padded
+    // with 1s.
+    out.push(0b0101_1111);
+    out.extend([0xFF, 0xD9]);
+    out
+}
+
+/// Deflates a tile of 16-bit samples, `cpp` per pixel, stored with the
+/// horizontal predictor in little-endian byte order.
+fn deflate_ints(samples: &[u16], width: usize, cpp: usize) -> Vec<u8> {
+    let mut data = vec![];
+    for row in samples.chunks_exact(width * cpp) {
+        for (col, sample) in row.iter().enumerate() {
+            let prev = col
+                .checked_sub(cpp)
+                .map_or(0, |prev| *row.get(prev).unwrap());
+            data.extend(sample.wrapping_sub(prev).to_le_bytes());
+        }
+    }
+    compress_to_vec_zlib(&data, 6)
+}
+
+/// Narrows a float, which must be a small normal number, to `bits`.
+fn narrow_float(val: f32, bits: u32) -> u32 {
+    let (exp_bits, mant_bits) = match bits {
+        16 => (5, 10),
+        24 => (7, 16),
+        _ => return val.to_bits(),
+    };
+    let exp = ((val.to_bits() >> 23) & 0xFF) + (1 << (exp_bits - 1)) - 1 - 127;
+    let mant = (val.to_bits() & 0x7F_FFFF) >> (23 - mant_bits);
+    (exp << mant_bits) | mant
+}
+
+/// Deflates a tile of floats, stored with the floating-point predictor.
+fn deflate_floats(samples: &[f32], width: usize, bits: u32) -> Vec<u8> {
+    let len = bits / 8;
+    let mut data = vec![];
+    for row in samples.chunks_exact(width) {
+        let mut prev = 0_u8;
+        for plane in (0..len).rev() {
+            for sample in row {
+                let byte = (narrow_float(*sample, bits) >> (8 * plane)) & 0xFF;
+                let byte = u8::try_from(byte).unwrap();
+                data.push(byte.wrapping_sub(prev));
+                prev = byte;
+            }
+        }
+    }
+    compress_to_vec_zlib(&data, 6)
+}
+
+fn decode_float(input: &[u8]) -> Vec<f32> {
+    let root = TiffParser::parse(input).unwrap();
+    let cameras = parse_cameras();
+    let (demuxer, request) = DngDemuxer::new(
+        &root,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )
+    .unwrap();
+    assert_eq!(demuxer.datatype(), DataType::F32);
+    assert_eq!(request.datatype(), DataType::F32);
+    assert_eq!(demuxer.bpp(), 4);
+    let mut output_buf = request.fulfill().unwrap();
+    let mut image = output_buf.get_mut();
+    demuxer.decode(&mut image).unwrap();
+    let output = image.as_f32().unwrap();
+    (0..output.num_rows().get())
+        .flat_map(|row| output.get_row(RowIndex::new(row)).unwrap().to_vec())
+        .collect()
+}
+
+/// A 6x3 image stored as four deflated 4x2 tiles clipped to it, and the
+/// value of each of its pixels given that of the tile samples.
+fn deflated_dng<S, F>(
+    bits: u16,
+    predictor: u16,
+    float: bool,
+    tiles: &[Vec<S>],
+    deflate: F,
+) -> (TiffBuilder, Vec<S>)
+where
+    S: Copy,
+    F: Fn(&[S]) -> Vec<u8>,
+{
+    let mut builder = tiled_dng(
+        6,
+        3,
+        bits,
+        COMPRESSION_DEFLATE,
+        (4, 2),
+        tiles.iter().map(|tile| deflate(tile)).collect(),
+    );
+    builder
+        .ifd(0)
+        .push(tag(TiffTag::PREDICTOR, Value::Short(vec![predictor])));
+    if float {
+        builder.ifd(0).push(tag(
+            TiffTag::SAMPLE_FORMAT,
+            Value::Short(vec![SAMPLE_FORMAT_FLOAT.try_into().unwrap()]),
+        ));
+    }
+    let expected = (0..3)
+        .flat_map(|row| (0..6).map(move |col| (row, col)))
+        .map(|(row, col)| {
+            let tile = tiles.get((row / 2) * 2 + col / 4).unwrap();
+            *tile.get((row % 2) * 4 + col % 4).unwrap()
+        })
+        .collect();
+    (builder, expected)
+}
+
+fn samples(len: usize, bits: u32) -> Vec<u16> {
+    (0..len)
+        .map(|i| u16::try_from((i * 7919 + 17) % (1 << bits)).unwrap())
+        .collect()
+}
+
+#[test]
+fn lossy_jpeg_tiles_test() {
+    // A 12x10 image, stored as four 8x8 tiles clipped to it.
+    let values = [130, 150, 200, 255];
+    let input = tiled_dng(
+        12,
+        10,
+        8,
+        COMPRESSION_LOSSY_JPEG,
+        (8, 8),
+        values.iter().map(|value| flat_jpeg(*value)).collect(),
+    )
+    .build();
+    let expected: Vec<u16> = (0..10)
+        .flat_map(|row| {
+            (0..12).map(move |col| {
+                let tile = (row / 8) * 2 + col / 8;
+                u16::from(*values.get(tile).unwrap())
+            })
+        })
+        .collect();
+    assert_eq!(decode(&input), expected);
+}
+
+#[test]
+fn lossy_jpeg_linearization_test() {
+    // A gamma-like curve, shorter than the 8-bit code range.
+    let table: Vec<u16> = (0..200_u16).map(|code| code * code / 4).collect();
+    let values = [130, 150, 200, 255];
+    let mut builder = tiled_dng(
+        12,
+        10,
+        8,
+        COMPRESSION_LOSSY_JPEG,
+        (8, 8),
+        values.iter().map(|value| flat_jpeg(*value)).collect(),
+    );
+    builder.ifd(0).push(tag(
+        TiffTag::LINEARIZATION_TABLE,
+        Value::Short(table.clone()),
+    ));
+    let input = builder.build();
+    let expected: Vec<u16> = (0..10)
+        .flat_map(|row| {
+            (0..12).map(move |col| {
+                let tile = (row / 8) * 2 + col / 8;
+                usize::from(*values.get(tile).unwrap())
+            })
+        })
+        .map(|code| *table.get(code).unwrap_or(table.last().unwrap()))
+        .collect();
+    assert_eq!(decode(&input), expected);
+
+    let root = TiffParser::parse(&input).unwrap();
+    let cameras = parse_cameras();
+    let (demuxer, _) = DngDemuxer::new(
+        &root,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )
+    .unwrap();
+    assert_eq!(demuxer.whitelevel(), Some(199 * 199 / 4));
+}
+
+#[test]
+fn jpeg_xl_tiles_test() {
+    // A 6x3 image, stored as four 4x2 tiles clipped to it.
+    for bits in [8, 16] {
+        let tiles: Vec<Vec<u16>> =
+            (0..4).map(|tile| samples(4 * 2 + tile, bits)).collect();
+        let input = tiled_dng(
+            6,
+            3,
+            bits.try_into().unwrap(),
+            COMPRESSION_JPEG_XL,
+            (4, 2),
+            tiles
+                .iter()
+                .map(|tile| jpeg_xl(tile.get(..4 * 2).unwrap(), 4, 2, bits))
+                .collect(),
+        )
+        .build();
+        let expected: Vec<u16> = (0..3)
+            .flat_map(|row| (0..6).map(move |col| (row, col)))
+            .map(|(row, col)| {
+                let tile = tiles.get((row / 2) * 2 + col / 4).unwrap();
+                *tile.get((row % 2) * 4 + col % 4).unwrap()
+            })
+            .collect();
+        assert_eq!(decode(&input), expected);
+    }
+}
+
+#[test]
+fn jpeg_xl_strips_test() {
+    // The last strip is shorter.
+    let samples = samples(3 * 8, 8);
+    let mut builder = tiled_dng(3, 8, 8, COMPRESSION_JPEG_XL, (3, 3), vec![]);
+    builder.remove(TiffTag::TILE_WIDTH);
+    builder.remove(TiffTag::TILE_LENGTH);
+    builder.remove(TiffTag::TILE_OFFSETS);
+    builder.remove(TiffTag::TILE_BYTE_COUNTS);
+    builder.ifd(0).extend([
+        tag(TiffTag::ROWS_PER_STRIP, Value::Long(vec![3])),
+        tag(TiffTag::STRIP_OFFSETS, Value::BlobOffsets(vec![0, 1, 2])),
+        tag(TiffTag::STRIP_BYTE_COUNTS, Value::BlobSizes(vec![0, 1, 2])),
+    ]);
+    builder.blobs = samples
+        .chunks(3 * 3)
+        .map(|strip| jpeg_xl(strip, 3, strip.len() / 3, 8))
+        .collect();
+    assert_eq!(decode(&builder.build()), samples);
+}
+
+#[test]
+fn compressed_tile_mismatch_test() {
+    let dims_mismatch =
+        "The tile/strip dimensions differ from those of its data";
+    // The tile is too narrow, too short, and of a different bit depth.
+    for (tile_dims, height, bits, err) in [
+        ((3, 2), 2, 8, dims_mismatch),
+        ((4, 2), 3, 8, dims_mismatch),
+        (
+            (4, 2),
+            2,
+            16,
+            "The tile/strip bit depth differs from that of the image",
+        ),
+    ] {
+        let (tile_width, tile_height) = tile_dims;
+        let tile = jpeg_xl(
+            &samples(tile_width * tile_height, 8),
+            tile_width,
+            tile_height,
+            8,
+        );
+        let input = tiled_dng(
+            4,
+            height,
+            bits,
+            COMPRESSION_JPEG_XL,
+            (4, height),
+            vec![tile],
+        )
+        .build();
+        assert_eq!(new_demuxer_err(&input), err);
+    }
+}
+
+#[test]
+fn invalid_compressed_tile_test() {
+    for (compression, err) in [
+        (COMPRESSION_LOSSY_JPEG, "JpegError(InvalidData("),
+        (COMPRESSION_JPEG_XL, "JpegXlError(InvalidData("),
+    ] {
+        let input =
+            tiled_dng(8, 8, 8, compression, (8, 8), vec![vec![0; 16]]).build();
+        assert!(new_demuxer_err(&input).starts_with(err));
+    }
+}
+
+#[test]
+fn lossy_jpeg_bits_test() {
+    let input = tiled_dng(
+        8,
+        8,
+        16,
+        COMPRESSION_LOSSY_JPEG,
+        (8, 8),
+        vec![flat_jpeg(128)],
+    )
+    .build();
+    assert_eq!(new_demuxer_err(&input), "Unsupported bits per sample: 16");
+}
+
+#[test]
+fn deflate_tiles_test() {
+    let tiles: Vec<Vec<u16>> =
+        (0..4).map(|tile| samples(4 * 2 + tile, 16)).collect();
+    let (input, expected) = deflated_dng(16, 2, false, &tiles, |tile| {
+        deflate_ints(tile.get(..4 * 2).unwrap(), 4, 1)
+    });
+    assert_eq!(decode(&input.build()), expected);
+}
+
+#[test]
+fn deflate_interleaved_tiles_test() {
+    // A 3x3 image of three components, stored as four 2x2 tiles clipped
+    // to it.
+    let tiles: Vec<Vec<u16>> =
+        (0..4).map(|tile| samples(2 * 2 * 3 + tile, 16)).collect();
+    let mut input = tiled_dng(
+        3,
+        3,
+        16,
+        COMPRESSION_DEFLATE,
+        (2, 2),
+        tiles
+            .iter()
+            .map(|tile| deflate_ints(tile.get(..2 * 2 * 3).unwrap(), 2, 3))
+            .collect(),
+    );
+    input.set(
+        TiffTag::PHOTOMETRIC_INTERPRETATION,
+        &Value::Short(vec![34892]),
+    );
+    input.set(TiffTag::SAMPLES_PER_PIXEL, &Value::Short(vec![3]));
+    input
+        .ifd(0)
+        .push(tag(TiffTag::PREDICTOR, Value::Short(vec![2])));
+    let expected: Vec<u16> = (0..3)
+        .flat_map(|row| (0..3 * 3).map(move |col| (row, col)))
+        .map(|(row, col)| {
+            let tile = tiles.get((row / 2) * 2 + col / (2 * 3)).unwrap();
+            *tile.get((row % 2) * 2 * 3 + col % (2 * 3)).unwrap()
+        })
+        .collect();
+    assert_eq!(decode(&input.build()), expected);
+}
+
+#[test]
+fn deflate_float_tiles_test() {
+    for bits in [16, 24, 32] {
+        let tiles: Vec<Vec<f32>> = (0..4_u16)
+            .map(|tile| {
+                (0..4 * 2_u16)
+                    .map(|i| f32::from(tile * 8 + i + 1) * 0.375)
+                    .collect()
+            })
+            .collect();
+        let (input, expected) =
+            deflated_dng(bits.try_into().unwrap(), 3, true, &tiles, |tile| {
+                deflate_floats(tile, 4, bits)
+            });
+        let to_bits =
+            |samples: &[f32]| samples.iter().map(|s| s.to_bits()).collect();
+        let decoded: Vec<u32> = to_bits(&decode_float(&input.build()));
+        assert_eq!(decoded, to_bits(&expected));
+    }
+}
+
+#[test]
+fn deflate_float_errors_test() {
+    let tiles = vec![vec![1.0_f32; 4 * 2]; 4];
+    let (builder, _) =
+        deflated_dng(32, 3, true, &tiles, |tile| deflate_floats(tile, 4, 32));
+    let input = builder.build();
+    let root = TiffParser::parse(&input).unwrap();
+    let cameras = parse_cameras();
+    let (demuxer, request) = DngDemuxer::new(
+        &root,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )
+    .unwrap();
+    // Integer storage cannot hold the samples.
+    let mut output_buf = ProcurementRequest::from(NDSliceProcurementRequest::<
+        u16,
+    >::new(request.dims()))
+    .fulfill()
+    .unwrap();
+    assert_eq!(
+        demuxer
+            .decode(&mut output_buf.get_mut())
+            .unwrap_err()
+            .to_string(),
+        "RawDemuxerError(DecoderError(Output buffer element type differs \
+         from expected))"
+    );
+}
+
+#[test]
+fn unsupported_deflate_test() {
+    let tiles = vec![vec![1.0_f32; 4 * 2]; 4];
+    for (bits, predictor, compression, err) in [
+        (32, 5, COMPRESSION_DEFLATE, "Unsupported DNG predictor: 5"),
+        (8, 3, COMPRESSION_DEFLATE, "Unsupported bits per sample: 8"),
+        (
+            32,
+            3,
+            COMPRESSION_JPEG_XL,
+            "Unsupported DNG compression for floating-point samples: 52546",
+        ),
+    ] {
+        let (mut input, _) =
+            deflated_dng(bits, predictor, true, &tiles, |tile| {
+                deflate_floats(tile, 4, 32)
+            });
+        input.set(TiffTag::COMPRESSION, &Value::Long(vec![compression]));
+        assert_eq!(new_demuxer_err(&input.build()), err);
+    }
+}
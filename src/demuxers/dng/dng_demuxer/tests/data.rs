@@ -0,0 +1,210 @@
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_parsers_tiffparser::tiffparser::TiffTag;
+
+use super::{
+    TiffBuilder, Value, decode, dng_ifd0, new_demuxer_err, raw_ifd, simple_dng,
+    tag,
+};
+
+#[test]
+fn uncompressed_16bit_le_test() {
+    let input = simple_dng(
+        Endianness::Little,
+        2,
+        2,
+        16,
+        vec![0x01, 0x10, 0x02, 0x20, 0x03, 0x30, 0x04, 0x40],
+    )
+    .build();
+    assert_eq!(decode(&input), vec![0x1001, 0x2002, 0x3003, 0x4004]);
+}
+
+#[test]
+fn uncompressed_16bit_be_test() {
+    let input = simple_dng(
+        Endianness::Big,
+        2,
+        2,
+        16,
+        vec![0x10, 0x01, 0x20, 0x02, 0x30, 0x03, 0x40, 0x04],
+    )
+    .build();
+    assert_eq!(decode(&input), vec![0x1001, 0x2002, 0x3003, 0x4004]);
+}
+
+#[test]
+fn uncompressed_12bit_packed_test() {
+    let input = simple_dng(
+        Endianness::Little,
+        2,
+        2,
+        12,
+        vec![0x12, 0x34, 0x56, 0xAB, 0xCD, 0xEF],
+    )
+    .build();
+    assert_eq!(decode(&input), vec![0x123, 0x456, 0xABC, 0xDEF]);
+}
+
+#[test]
+fn linear_raw_interleaved_test() {
+    let mut builder =
+        simple_dng(Endianness::Little, 2, 2, 8, (1..=2 * 2 * 3).collect());
+    builder.set(
+        TiffTag::PHOTOMETRIC_INTERPRETATION,
+        &Value::Short(vec![34892]),
+    );
+    builder.set(TiffTag::SAMPLES_PER_PIXEL, &Value::Short(vec![3]));
+    assert_eq!(
+        decode(&builder.build()),
+        (1..=2 * 2 * 3).collect::<Vec<u16>>()
+    );
+}
+
+#[test]
+fn multiple_strips_test() {
+    let mut builder = simple_dng(Endianness::Little, 1, 3, 8, vec![1, 2]);
+    builder.blobs.push(vec![3]);
+    for (entry_tag, value) in builder.ifd(0).iter_mut() {
+        match TiffTag::new(*entry_tag) {
+            TiffTag::ROWS_PER_STRIP => *value = Value::Long(vec![2]),
+            TiffTag::STRIP_OFFSETS => *value = Value::BlobOffsets(vec![0, 1]),
+            TiffTag::STRIP_BYTE_COUNTS => *value = Value::BlobSizes(vec![0, 1]),
+            _ => {}
+        }
+    }
+    assert_eq!(decode(&builder.build()), vec![1, 2, 3]);
+}
+
+#[test]
+fn tiles_are_clipped_to_image_test() {
+    // A 3x3 image, stored as four 2x2 tiles.
+    let mut builder = TiffBuilder::new(Endianness::Little);
+    *builder.ifd(0) = dng_ifd0();
+    builder.ifd(0).extend(raw_ifd(3, 3, 8).into_iter().filter(
+        |(entry_tag, _)| {
+            ![
+                TiffTag::STRIP_OFFSETS,
+                TiffTag::STRIP_BYTE_COUNTS,
+                TiffTag::ROWS_PER_STRIP,
+            ]
+            .iter()
+            .any(|strip_tag| strip_tag.val() == *entry_tag)
+        },
+    ));
+    builder.ifd(0).extend([
+        tag(TiffTag::TILE_WIDTH, Value::Long(vec![2])),
+        tag(TiffTag::TILE_LENGTH, Value::Long(vec![2])),
+        tag(TiffTag::TILE_OFFSETS, Value::BlobOffsets(vec![0, 1, 2, 3])),
+        tag(
+            TiffTag::TILE_BYTE_COUNTS,
+            Value::BlobSizes(vec![0, 1, 2, 3]),
+        ),
+    ]);
+    builder.blobs = vec![
+        vec![11, 12, 21, 22],
+        vec![13, 0, 23, 0],
+        vec![31, 32],
+        vec![33, 0],
+    ];
+    assert_eq!(
+        decode(&builder.build()),
+        vec![11, 12, 13, 21, 22, 23, 31, 32, 33]
+    );
+}
+
+#[test]
+fn raw_sub_ifd_is_preferred_over_preview_test() {
+    let mut builder = TiffBuilder::new(Endianness::Big);
+    *builder.ifd(0) = dng_ifd0();
+    builder.ifd(0).extend([
+        tag(TiffTag::NEW_SUBFILE_TYPE, Value::Long(vec![1])),
+        tag(TiffTag::IMAGE_WIDTH, Value::Long(vec![1])),
+        tag(TiffTag::IMAGE_LENGTH, Value::Long(vec![1])),
+        tag(TiffTag::BITS_PER_SAMPLE, Value::Short(vec![8])),
+        tag(TiffTag::PHOTOMETRIC_INTERPRETATION, Value::Short(vec![2])),
+        tag(TiffTag::STRIP_OFFSETS, Value::BlobOffsets(vec![1])),
+        tag(TiffTag::STRIP_BYTE_COUNTS, Value::BlobSizes(vec![1])),
+        tag(TiffTag::SUB_IFDS, Value::IFDOffset(1)),
+    ]);
+    *builder.ifd(1) = raw_ifd(2, 1, 8);
+    builder.blobs = vec![vec![7, 9], vec![0xFF]];
+    assert_eq!(decode(&builder.build()), vec![7, 9]);
+}
+
+#[test]
+fn not_a_dng_test() {
+    let mut builder = simple_dng(Endianness::Little, 1, 1, 8, vec![0]);
+    builder.remove(TiffTag::DNG_VERSION);
+    assert_eq!(new_demuxer_err(&builder.build()), "Not a DNG");
+}
+
+#[test]
+fn no_raw_image_test() {
+    let mut builder = simple_dng(Endianness::Little, 1, 1, 8, vec![0]);
+    for (entry_tag, value) in builder.ifd(0).iter_mut() {
+        if *entry_tag == TiffTag::PHOTOMETRIC_INTERPRETATION.val() {
+            *value = Value::Short(vec![2]);
+        }
+    }
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "No raw image found in DNG"
+    );
+}
+
+#[test]
+fn unsupported_compression_test() {
+    let mut builder = simple_dng(Endianness::Little, 1, 1, 8, vec![0]);
+    for (entry_tag, value) in builder.ifd(0).iter_mut() {
+        if *entry_tag == TiffTag::COMPRESSION.val() {
+            *value = Value::Short(vec![7]);
+        }
+    }
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "Unsupported DNG compression: 7"
+    );
+}
+
+#[test]
+fn truncated_strip_test() {
+    let input = simple_dng(Endianness::Little, 2, 2, 16, vec![0; 6]).build();
+    assert_eq!(new_demuxer_err(&input), "The tile/strip data is truncated");
+}
+
+#[test]
+fn tile_size_overflow_test() {
+    let mut builder =
+        simple_dng(Endianness::Little, 1, u32::MAX, 16, vec![0; 2]);
+    for strip_tag in [
+        TiffTag::ROWS_PER_STRIP,
+        TiffTag::STRIP_OFFSETS,
+        TiffTag::STRIP_BYTE_COUNTS,
+    ] {
+        builder.remove(strip_tag);
+    }
+    builder.ifd(0).extend([
+        tag(TiffTag::TILE_WIDTH, Value::Long(vec![u32::MAX])),
+        tag(TiffTag::TILE_LENGTH, Value::Long(vec![u32::MAX])),
+        tag(TiffTag::TILE_OFFSETS, Value::BlobOffsets(vec![0])),
+        tag(TiffTag::TILE_BYTE_COUNTS, Value::BlobSizes(vec![0])),
+    ]);
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "Overflow when computing per-tile byte count"
+    );
+}
+
+#[test]
+fn strip_count_mismatch_test() {
+    let mut builder = simple_dng(Endianness::Little, 1, 2, 8, vec![0, 0]);
+    for (entry_tag, value) in builder.ifd(0).iter_mut() {
+        if *entry_tag == TiffTag::ROWS_PER_STRIP.val() {
+            *value = Value::Long(vec![1]);
+        }
+    }
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "The tile/strip count is invalid"
+    );
+}
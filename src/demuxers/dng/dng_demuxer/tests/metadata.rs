@@ -0,0 +1,340 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_colorfilterarray::colorfilterarray::ColorVariant;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_std::coord_common::{
+    ColIndex, Coord2D, Dimensions2D, RowCount, RowIndex, RowLength,
+};
+
+use super::{
+    DngDemuxer, Entries, TiffBuilder, Value, new_demuxer_err, parse_cameras,
+    simple_dng, tag,
+};
+
+fn with_tags(entries: Entries) -> TiffBuilder {
+    let mut builder = simple_dng(Endianness::Little, 4, 4, 8, vec![0; 16]);
+    builder
+        .ifd(0)
+        .retain(|(entry_tag, _)| entries.iter().all(|(t, _)| t != entry_tag));
+    builder.ifd(0).extend(entries);
+    builder
+}
+
+macro_rules! with_demuxer {
+    ($builder:expr, |$demuxer:ident| $body:block) => {{
+        let input = $builder.build();
+        let root = TiffParser::parse(&input).unwrap();
+        let cameras = parse_cameras();
+        let ($demuxer, _) = DngDemuxer::new(
+            &root,
+            &cameras,
+            DecodeableCamera::new_unless_unsupported,
+        )
+        .unwrap();
+        $body
+    }};
+}
+
+#[test]
+fn camera_from_cameras_xml_test() {
+    with_demuxer!(with_tags(vec![]), |demuxer| {
+        assert_eq!(demuxer.make(), "Make");
+        assert_eq!(demuxer.model(), "Model");
+        assert_eq!(demuxer.mode(), Some("dng"));
+        assert_eq!(demuxer.canonical_make(), "Canonical Make");
+        assert_eq!(demuxer.canonical_model(), "Canonical Model");
+        assert_eq!(demuxer.canonical_id(), "Canonical ID");
+    });
+}
+
+#[test]
+fn unknown_camera_test() {
+    let builder = with_tags(vec![
+        tag(TiffTag::MAKE, Value::Ascii("Other Make")),
+        tag(TiffTag::MODEL, Value::Ascii("Other Model ")),
+    ]);
+    with_demuxer!(builder, |demuxer| {
+        assert_eq!(demuxer.make(), "Other Make");
+        assert_eq!(demuxer.model(), "Other Model");
+        assert_eq!(demuxer.mode(), None);
+        assert_eq!(demuxer.canonical_make(), "Other Make");
+        assert_eq!(demuxer.canonical_id(), "Other Make Other Model");
+    });
+}
+
+#[test]
+fn unsupported_camera_test() {
+    let builder =
+        with_tags(vec![tag(TiffTag::MAKE, Value::Ascii("Unsupported Make"))]);
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "This camera is not supported (explicit)"
+    );
+}
+
+#[test]
+fn default_levels_and_crop_test() {
+    with_demuxer!(with_tags(vec![]), |demuxer| {
+        assert_eq!(demuxer.whitelevel(), Some(255));
+        assert_eq!(demuxer.blacklevel(), None);
+        assert!(demuxer.blacklevel_separate().is_none());
+        assert_eq!(demuxer.iso_speed(), None);
+        assert_eq!(
+            demuxer.crop_offset(),
+            Some(Coord2D::new(RowIndex::new(0), ColIndex::new(0)))
+        );
+        assert_eq!(demuxer.dim_cropped(), Some(demuxer.dim_uncropped()));
+        assert!(demuxer.colormatrix().is_none());
+        assert_eq!(demuxer.wb_coeffs(), None);
+    });
+}
+
+#[test]
+fn cfa_test() {
+    with_demuxer!(with_tags(vec![]), |demuxer| {
+        assert!(demuxer.is_cfa());
+        let cfa = demuxer
+            .cfa(Coord2D::new(RowIndex::new(0), ColIndex::new(0)))
+            .unwrap();
+        let colors = [(0, 0), (0, 1), (1, 0), (1, 1)].map(|(row, col)| {
+            cfa[Coord2D::new(RowIndex::new(row), ColIndex::new(col))]
+        });
+        assert_eq!(
+            colors,
+            [
+                ColorVariant::Red,
+                ColorVariant::Green,
+                ColorVariant::Green,
+                ColorVariant::Blue
+            ]
+        );
+    });
+}
+
+#[test]
+fn linear_raw_is_not_cfa_test() {
+    let builder = with_tags(vec![tag(
+        TiffTag::PHOTOMETRIC_INTERPRETATION,
+        Value::Short(vec![34892]),
+    )]);
+    with_demuxer!(builder, |demuxer| {
+        assert!(!demuxer.is_cfa());
+    });
+}
+
+#[test]
+fn linear_raw_components_test() {
+    let mut builder = simple_dng(Endianness::Little, 4, 2, 8, vec![0; 24]);
+    builder.set(
+        TiffTag::PHOTOMETRIC_INTERPRETATION,
+        &Value::Short(vec![34892]),
+    );
+    builder.set(TiffTag::SAMPLES_PER_PIXEL, &Value::Short(vec![3]));
+    builder.ifd(0).extend([
+        tag(TiffTag::BLACK_LEVEL, Value::Short(vec![1, 2, 3])),
+        tag(TiffTag::DEFAULT_CROP_ORIGIN, Value::Short(vec![1, 0])),
+        tag(TiffTag::DEFAULT_CROP_SIZE, Value::Short(vec![2, 2])),
+    ]);
+    with_demuxer!(builder, |demuxer| {
+        assert_eq!(demuxer.cpp(), 3);
+        assert_eq!(demuxer.bpp(), 3 * 2);
+        // The geometry is in pixels, not samples.
+        assert_eq!(demuxer.dim_uncropped().row_len().get(), 4);
+        assert_eq!(demuxer.crop_offset().unwrap().col(), ColIndex::new(1));
+        assert_eq!(demuxer.dim_cropped().unwrap().row_len().get(), 2);
+        let levels = demuxer.blacklevel_separate().unwrap();
+        assert_eq!(levels.get_row(RowIndex::new(0)).unwrap(), &[1, 2, 3]);
+    });
+}
+
+#[test]
+fn unsupported_samples_per_pixel_test() {
+    let cfa =
+        with_tags(vec![tag(TiffTag::SAMPLES_PER_PIXEL, Value::Short(vec![3]))]);
+    assert_eq!(
+        new_demuxer_err(&cfa.build()),
+        "Unsupported samples per pixel: 3"
+    );
+    let linear = with_tags(vec![
+        tag(
+            TiffTag::PHOTOMETRIC_INTERPRETATION,
+            Value::Short(vec![34892]),
+        ),
+        tag(TiffTag::SAMPLES_PER_PIXEL, Value::Short(vec![5])),
+    ]);
+    assert_eq!(
+        new_demuxer_err(&linear.build()),
+        "Unsupported samples per pixel: 5"
+    );
+}
+
+#[test]
+fn invalid_cfa_color_test() {
+    let builder = with_tags(vec![tag(
+        TiffTag::CFA_PATTERN,
+        Value::Byte(vec![0, 1, 1, 9]),
+    )]);
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "Unsupported CFA color: 9"
+    );
+}
+
+#[test]
+fn uniform_black_level_test() {
+    let builder = with_tags(vec![
+        tag(TiffTag::BLACK_LEVEL, Value::Short(vec![16])),
+        tag(TiffTag::WHITE_LEVEL, Value::Short(vec![200])),
+    ]);
+    with_demuxer!(builder, |demuxer| {
+        assert_eq!(demuxer.blacklevel(), Some(16));
+        assert_eq!(demuxer.whitelevel(), Some(200));
+    });
+}
+
+#[test]
+fn black_level_repeat_dim_test() {
+    let builder = with_tags(vec![
+        tag(TiffTag::BLACK_LEVEL_REPEAT_DIM, Value::Short(vec![2, 2])),
+        tag(
+            TiffTag::BLACK_LEVEL,
+            Value::Rational(vec![(1, 1), (5, 2), (3, 1), (4, 1)]),
+        ),
+    ]);
+    with_demuxer!(builder, |demuxer| {
+        assert_eq!(demuxer.blacklevel(), None);
+        let levels = demuxer.blacklevel_separate().unwrap();
+        assert_eq!(levels.row_length().val().get(), 2);
+        assert_eq!(levels.get_row(RowIndex::new(0)).unwrap(), &[1, 3]);
+        assert_eq!(levels.get_row(RowIndex::new(1)).unwrap(), &[3, 4]);
+    });
+}
+
+#[test]
+fn empty_linearization_table_test() {
+    let builder = with_tags(vec![tag(
+        TiffTag::LINEARIZATION_TABLE,
+        Value::Short(vec![]),
+    )]);
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "The linearization table is empty"
+    );
+}
+
+#[test]
+fn black_level_count_mismatch_test() {
+    let builder = with_tags(vec![
+        tag(TiffTag::BLACK_LEVEL_REPEAT_DIM, Value::Short(vec![2, 2])),
+        tag(TiffTag::BLACK_LEVEL, Value::Short(vec![1, 2])),
+    ]);
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "The black level count is invalid"
+    );
+}
+
+#[test]
+fn active_area_and_default_crop_test() {
+    let builder = with_tags(vec![
+        tag(TiffTag::ACTIVE_AREA, Value::Long(vec![1, 1, 4, 4])),
+        tag(TiffTag::DEFAULT_CROP_ORIGIN, Value::Short(vec![1, 0])),
+        tag(
+            TiffTag::DEFAULT_CROP_SIZE,
+            Value::Rational(vec![(2, 1), (3, 1)]),
+        ),
+    ]);
+    with_demuxer!(builder, |demuxer| {
+        assert_eq!(
+            demuxer.crop_offset(),
+            Some(Coord2D::new(RowIndex::new(1), ColIndex::new(2)))
+        );
+        assert_eq!(
+            demuxer.dim_cropped(),
+            Some(Dimensions2D::new(
+                RowLength::new(core::num::NonZero::new(2).unwrap()),
+                RowCount::new(core::num::NonZero::new(3).unwrap()),
+            ))
+        );
+    });
+}
+
+#[test]
+fn invalid_default_crop_test() {
+    let builder = with_tags(vec![tag(
+        TiffTag::DEFAULT_CROP_SIZE,
+        Value::Short(vec![5, 1]),
+    )]);
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "The default crop is invalid"
+    );
+}
+
+#[test]
+fn colormatrix_prefers_d65_test() {
+    let builder = with_tags(vec![
+        tag(TiffTag::CALIBRATION_ILLUMINANT1, Value::Short(vec![17])),
+        tag(TiffTag::CALIBRATION_ILLUMINANT2, Value::Short(vec![21])),
+        tag(TiffTag::COLOR_MATRIX1, Value::SRational(vec![(1, 1); 9])),
+        tag(
+            TiffTag::COLOR_MATRIX2,
+            Value::SRational(vec![
+                (1, 2),
+                (-1, 4),
+                (0, 1),
+                (0, 1),
+                (1, 1),
+                (0, 1),
+                (0, 1),
+                (0, 1),
+                (1, 1),
+            ]),
+        ),
+    ]);
+    with_demuxer!(builder, |demuxer| {
+        let matrix = demuxer.colormatrix().unwrap();
+        assert_eq!(
+            matrix.get_row(RowIndex::new(0)).unwrap(),
+            &[5000, -2500, 0]
+        );
+        assert_eq!(matrix.num_rows().val().get(), 3);
+    });
+}
+
+#[test]
+fn colormatrix_fallback_test() {
+    let builder = with_tags(vec![tag(
+        TiffTag::COLOR_MATRIX1,
+        Value::SRational(vec![(1, 10); 9]),
+    )]);
+    with_demuxer!(builder, |demuxer| {
+        let matrix = demuxer.colormatrix().unwrap();
+        assert_eq!(matrix.get_row(RowIndex::new(2)).unwrap(), &[1000; 3]);
+    });
+}
+
+#[test]
+fn as_shot_neutral_test() {
+    let builder = with_tags(vec![tag(
+        TiffTag::AS_SHOT_NEUTRAL,
+        Value::Rational(vec![(1, 2), (1, 1), (1, 4)]),
+    )]);
+    with_demuxer!(builder, |demuxer| {
+        let wb = demuxer.wb_coeffs().unwrap();
+        assert_eq!(wb.get(..3).unwrap(), &[2.0, 1.0, 4.0]);
+        assert!(wb.get(3).unwrap().is_nan());
+    });
+}
+
+#[test]
+fn iso_speed_test() {
+    let builder = with_tags(vec![tag(
+        TiffTag::ISO_SPEED_RATINGS,
+        Value::Short(vec![400]),
+    )]);
+    with_demuxer!(builder, |demuxer| {
+        assert_eq!(demuxer.iso_speed(), Some(400));
+    });
+}
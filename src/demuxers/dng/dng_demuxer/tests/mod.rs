@@ -0,0 +1,103 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::Cameras;
+use rawspeed_metadata_xmlparser::xmlparser;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_std::coord_common::RowIndex;
+use rawspeed_utils_tiffbuilder::tiffbuilder::{
+    Entries, TiffBuilder, Value, tag,
+};
+
+use super::DngDemuxer;
+
+const CAMERAS: &str = "
+    <Cameras>
+        <Camera make=\"Unrelated Make\" model=\"Unrelated Model\">
+        </Camera>
+        <Camera make=\"Unsupported Make\" model=\"Model\" mode=\"dng\" supported=\"no\">
+        </Camera>
+        <Camera make=\"Make\" model=\"Model\" mode=\"dng\">
+            <ID make=\"Canonical Make\" model=\"Canonical Model\">Canonical ID</ID>
+        </Camera>
+    </Cameras>";
+
+/// A minimal single-strip raw IFD, 2x2 RGGB CFA, `bits` per sample.
+fn raw_ifd(width: u32, height: u32, bits: u16) -> Entries {
+    vec![
+        tag(TiffTag::NEW_SUBFILE_TYPE, Value::Long(vec![0])),
+        tag(TiffTag::IMAGE_WIDTH, Value::Long(vec![width])),
+        tag(TiffTag::IMAGE_LENGTH, Value::Long(vec![height])),
+        tag(TiffTag::BITS_PER_SAMPLE, Value::Short(vec![bits])),
+        tag(TiffTag::COMPRESSION, Value::Short(vec![1])),
+        tag(
+            TiffTag::PHOTOMETRIC_INTERPRETATION,
+            Value::Short(vec![32803]),
+        ),
+        tag(TiffTag::STRIP_OFFSETS, Value::BlobOffsets(vec![0])),
+        tag(TiffTag::SAMPLES_PER_PIXEL, Value::Short(vec![1])),
+        tag(TiffTag::ROWS_PER_STRIP, Value::Long(vec![height])),
+        tag(TiffTag::STRIP_BYTE_COUNTS, Value::BlobSizes(vec![0])),
+        tag(TiffTag::CFA_REPEAT_PATTERN_DIM, Value::Short(vec![2, 2])),
+        tag(TiffTag::CFA_PATTERN, Value::Byte(vec![0, 1, 1, 2])),
+    ]
+}
+
+fn dng_ifd0() -> Entries {
+    vec![
+        tag(TiffTag::MAKE, Value::Ascii("Make")),
+        tag(TiffTag::MODEL, Value::Ascii("Model")),
+        tag(TiffTag::DNG_VERSION, Value::Byte(vec![1, 4, 0, 0])),
+    ]
+}
+
+/// A DNG where IFD0 is the raw image itself.
+fn simple_dng(
+    endianness: Endianness,
+    width: u32,
+    height: u32,
+    bits: u16,
+    data: Vec<u8>,
+) -> TiffBuilder {
+    let mut builder = TiffBuilder::new(endianness);
+    *builder.ifd(0) = dng_ifd0();
+    builder.ifd(0).extend(raw_ifd(width, height, bits));
+    builder.blobs.push(data);
+    builder
+}
+
+fn parse_cameras() -> Cameras<'static> {
+    xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap()
+}
+
+fn new_demuxer_err(input: &[u8]) -> String {
+    let root = TiffParser::parse(input).unwrap();
+    let cameras = parse_cameras();
+    DngDemuxer::new(&root, &cameras, DecodeableCamera::new_unless_unsupported)
+        .unwrap_err()
+}
+
+fn decode(input: &[u8]) -> Vec<u16> {
+    let root = TiffParser::parse(input).unwrap();
+    let cameras = parse_cameras();
+    let (demuxer, request) = DngDemuxer::new(
+        &root,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )
+    .unwrap();
+    let mut output_buf = request.fulfill().unwrap();
+    let mut image = output_buf.get_mut();
+    demuxer.decode(&mut image).unwrap();
+    let output = image.as_u16().unwrap();
+    (0..output.num_rows().get())
+        .flat_map(|row| output.get_row(RowIndex::new(row)).unwrap().to_vec())
+        .collect()
+}
+
+#[cfg(test)]
+mod compressed;
+#[cfg(test)]
+mod data;
+#[cfg(test)]
+mod metadata;
@@ -0,0 +1 @@
+pub mod dng_demuxer;
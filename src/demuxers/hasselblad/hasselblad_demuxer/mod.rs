@@ -0,0 +1,364 @@
+use rawspeed_codecs_hasselblad::hasselblad::HasselbladDecompressor;
+use rawspeed_demuxers_common::{
+    camera_metadata::CameraMetadata,
+    tiff_utils::{get_root_string, get_strip, get_usize, non_zero, tiff_err},
+};
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::{
+    DataType, ImageRefMut, ProcurementRequest, RawDemuxer, RawDemuxerError,
+};
+use rawspeed_memory_nd_slice_procurement::ndsliceprocurement::NDSliceProcurementRequest;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::{
+    Camera, Cameras, Supported, blackareas::BlackArea,
+};
+use rawspeed_metadata_colorfilterarray::colorfilterarray::ColorVariant;
+use rawspeed_parsers_tiffparser::tiffparser::{
+    TiffEntry, TiffIFD, TiffParser, TiffRootIFD, TiffTag,
+};
+use rawspeed_std::coord_common::{Coord2D, Dimensions2D, RowCount, RowLength};
+use rawspeed_std_ndslice::{
+    array2dref::Array2DRef, offsetarray2dref::OffsetArray2DRef,
+};
+
+/// Which some bodies repeat at the start of the model name.
+const MODEL_PREFIX: &str = "Hasselblad ";
+
+type T = u16;
+
+/// The `MakerNote` is a bare IFD in the byte order of the file, whose
+/// offsets are relative to the start of the file.
+fn parse_makernote<'a>(
+    root: &TiffRootIFD<'a>,
+) -> Result<Option<TiffRootIFD<'a>>, String> {
+    let Some(entry) = root.get_entry_recursive(TiffTag::MAKER_NOTE) else {
+        return Ok(None);
+    };
+    TiffParser::parse_ifd_chain(
+        root.input(),
+        root.endianness(),
+        entry.data_offset(),
+    )
+    .map(Some)
+    .map_err(tiff_err)
+}
+
+/// The offset of the prediction each row starts from, which the sensors
+/// that need one store in the `MakerNote`, or else `cameras.xml` knows.
+fn parse_pixel_base_offset(
+    makernote: Option<&TiffRootIFD<'_>>,
+    metadata: &CameraMetadata<'_>,
+) -> Result<i32, String> {
+    if let Some(entry) = makernote.and_then(|makernote| {
+        makernote.get_entry_recursive(TiffTag::HASSELBLAD_PIXEL_BASE_OFFSET)
+    }) {
+        return entry.get_i32(0).map_err(tiff_err);
+    }
+    match metadata.hint("pixelBaseOffset") {
+        None => Ok(0),
+        Some(hint) => hint
+            .parse()
+            .map_err(|_err| format!("Invalid pixelBaseOffset hint: {hint}")),
+    }
+}
+
+#[expect(clippy::float_arithmetic)]
+fn parse_wb_coeffs(root: &TiffRootIFD<'_>) -> Result<Option<[f32; 4]>, String> {
+    let Some(entry) = root.get_entry_recursive(TiffTag::AS_SHOT_NEUTRAL) else {
+        return Ok(None);
+    };
+    let neutral = entry.get_f32s().map_err(tiff_err)?;
+    if neutral.len() != 3 || neutral.iter().any(|v| *v <= 0.0) {
+        return Ok(None);
+    }
+    let mut coeffs = [f32::NAN; 4];
+    for (coeff, val) in coeffs.iter_mut().zip(neutral) {
+        *coeff = 1.0 / val;
+    }
+    Ok(Some(coeffs))
+}
+
+/// The names under which `cameras.xml` may list the camera: Hasselblad
+/// is inconsistent about repeating the make in `Model`, and some backs
+/// name only themselves there, and the camera in `UniqueCameraModel`.
+fn model_candidates<'a>(root: &TiffRootIFD<'a>) -> Vec<&'a str> {
+    let mut candidates = vec![];
+    for tag in [TiffTag::MODEL, TiffTag::UNIQUE_CAMERA_MODEL] {
+        let Some(model) = get_root_string(root, tag) else {
+            continue;
+        };
+        candidates.push(model);
+        if let Some(stripped) = model.strip_prefix(MODEL_PREFIX) {
+            candidates.push(stripped);
+        }
+    }
+    candidates
+}
+
+/// The first candidate that `cameras.xml` lists, preferring the entries
+/// specific to the bit depth, as it is the only mode some backs differ
+/// by.
+fn find_camera<'a>(
+    cameras: &'a Cameras<'a>,
+    make: &str,
+    candidates: &[&str],
+    mode: &str,
+) -> Option<&'a Camera<'a>> {
+    [Some(mode), None].into_iter().find_map(|mode| {
+        candidates
+            .iter()
+            .find_map(|model| cameras.find(make, model, mode))
+    })
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct HasselbladDemuxer<'a> {
+    metadata: CameraMetadata<'a>,
+    decompressor: HasselbladDecompressor<'a>,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    wb_coeffs: Option<[f32; 4]>,
+}
+
+impl<'a> HasselbladDemuxer<'a> {
+    fn parse_dims(
+        ifd: &TiffIFD<'_>,
+    ) -> Result<Dimensions2D<core::num::NonZero<usize>>, String> {
+        let width = get_usize(ifd, TiffTag::IMAGE_WIDTH)?.unwrap_or(0);
+        let height = get_usize(ifd, TiffTag::IMAGE_LENGTH)?.unwrap_or(0);
+        Ok(Dimensions2D::new(
+            RowLength::new(non_zero(width, "width")?),
+            RowCount::new(non_zero(height, "height")?),
+        ))
+    }
+
+    /// The raw image is the largest of the strips; the others are
+    /// previews.
+    fn find_raw_ifd<'b>(
+        root: &'b TiffRootIFD<'a>,
+    ) -> Result<
+        (&'b TiffIFD<'a>, Dimensions2D<core::num::NonZero<usize>>),
+        String,
+    > {
+        let mut raw = None;
+        for ifd in root.get_ifds_with_tag(TiffTag::STRIP_OFFSETS) {
+            let dims = Self::parse_dims(ifd)?;
+            let area = dims.row_len().get() * dims.row_count().get();
+            if raw.is_none_or(|(_, _, max_area)| area > max_area) {
+                raw = Some((ifd, dims, area));
+            }
+        }
+        raw.map(|(ifd, dims, _)| (ifd, dims))
+            .ok_or_else(|| "No raw image found in 3FR".to_owned())
+    }
+
+    #[inline(never)]
+    pub fn new<F>(
+        root: &TiffRootIFD<'a>,
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<(Self, ProcurementRequest), String>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let (ifd, dims) = Self::find_raw_ifd(root)?;
+        let strip = get_strip(root.input(), ifd)?;
+        let makernote = parse_makernote(root)?;
+        // The pixel base offset is only known once the camera is, so the
+        // frame is parsed without it first.
+        let precision = HasselbladDecompressor::new(strip, 0)
+            .map_err(|err| err.to_string())?
+            .precision();
+
+        let make = get_root_string(root, TiffTag::MAKE).unwrap_or("");
+        let model = get_root_string(root, TiffTag::MODEL).unwrap_or("");
+        let mode = format!("{precision}bit");
+        let camera = find_camera(cameras, make, &model_candidates(root), &mode)
+            .ok_or_else(|| format!("Unknown camera: {make} {model}"))?;
+        check_camera_support_fn(camera.supported)?;
+        let iso_speed = root
+            .get_entry_recursive(TiffTag::ISO_SPEED_RATINGS)
+            .map(TiffEntry::get_u32s)
+            .and_then(Result::ok)
+            .and_then(|isos| isos.first().copied());
+        let metadata = CameraMetadata::new(camera, make, model, iso_speed);
+
+        let pixel_base_offset =
+            parse_pixel_base_offset(makernote.as_ref(), &metadata)?;
+        let decompressor =
+            HasselbladDecompressor::new(strip, pixel_base_offset)
+                .map_err(|err| err.to_string())?;
+        if decompressor.dims() != dims {
+            return Err(
+                "The 3FR image dimensions differ from those of its data"
+                    .to_owned(),
+            );
+        }
+        let wb_coeffs = parse_wb_coeffs(root)?;
+        Ok((
+            Self {
+                metadata,
+                decompressor,
+                dims,
+                wb_coeffs,
+            },
+            NDSliceProcurementRequest::<T>::new(dims).into(),
+        ))
+    }
+}
+
+#[expect(clippy::missing_trait_methods)]
+impl RawDemuxer for HasselbladDemuxer<'_> {
+    #[inline]
+    fn make(&self) -> &str {
+        self.metadata.make
+    }
+
+    #[inline]
+    fn model(&self) -> &str {
+        self.metadata.model
+    }
+
+    #[inline]
+    fn mode(&self) -> Option<&str> {
+        self.metadata.mode()
+    }
+
+    #[inline]
+    fn canonical_make(&self) -> &str {
+        self.metadata.canonical_make()
+    }
+
+    #[inline]
+    fn canonical_model(&self) -> &str {
+        self.metadata.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_alias(&self) -> &str {
+        self.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_id(&self) -> String {
+        self.metadata.canonical_id()
+    }
+
+    #[inline]
+    fn iso_speed(&self) -> Option<u32> {
+        self.metadata.iso_speed
+    }
+
+    #[inline]
+    fn blacklevel(&self) -> Option<u16> {
+        self.metadata.blacklevel()
+    }
+
+    #[inline]
+    fn whitelevel(&self) -> Option<u16> {
+        self.metadata.whitelevel()
+    }
+
+    #[inline]
+    fn blacklevel_separate(&self) -> Option<Array2DRef<'_, i32>> {
+        None
+    }
+
+    #[inline]
+    fn wb_coeffs(&self) -> Option<[f32; 4]> {
+        self.wb_coeffs
+    }
+
+    #[inline]
+    fn colormatrix(&self) -> Option<Array2DRef<'_, i16>> {
+        self.metadata.colormatrix()
+    }
+
+    #[inline]
+    fn is_cfa(&self) -> bool {
+        self.metadata.is_cfa()
+    }
+
+    #[inline]
+    fn cfa(
+        &self,
+        origin: Coord2D,
+    ) -> Option<OffsetArray2DRef<'_, ColorVariant>> {
+        self.metadata.cfa(origin)
+    }
+
+    #[inline]
+    fn bpp(&self) -> usize {
+        let bpc = match self.datatype() {
+            DataType::U16 => size_of::<u16>(),
+            DataType::F32 => size_of::<f32>(),
+            DataType::U32 => size_of::<u32>(),
+            _ => unreachable!(),
+        };
+        bpc.checked_mul(self.cpp()).unwrap()
+    }
+
+    #[inline]
+    fn cpp(&self) -> usize {
+        1
+    }
+
+    #[inline]
+    fn datatype(&self) -> DataType {
+        DataType::U16
+    }
+
+    #[inline]
+    fn dim_uncropped(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        self.dims
+    }
+
+    #[inline]
+    fn dim_cropped(&self) -> Option<Dimensions2D<core::num::NonZero<usize>>> {
+        self.metadata.dim_cropped(self.dim_uncropped())
+    }
+
+    #[inline]
+    fn crop_offset(&self) -> Option<Coord2D> {
+        self.metadata.crop_offset()
+    }
+
+    #[inline]
+    fn black_areas(&self) -> Option<&[BlackArea]> {
+        self.metadata.black_areas()
+    }
+
+    #[inline]
+    fn fuji_rotation_pos(&self) -> Option<u32> {
+        None
+    }
+
+    #[inline]
+    fn pixel_aspect_ratio(&self) -> Option<f64> {
+        None
+    }
+
+    #[inline]
+    fn bad_pixel_positions(&self) -> Vec<Coord2D> {
+        vec![]
+    }
+
+    #[inline(never)]
+    fn decode(
+        &self,
+        output: &mut ImageRefMut<'_>,
+    ) -> Result<(), RawDemuxerError> {
+        let output = output.as_u16()?;
+        if output.dims() != self.dims {
+            return Err(RawDemuxerError::DecoderError(
+                "Output buffer dimensions differ from expected".to_owned(),
+            ));
+        }
+        self.decompressor
+            .decode(output)
+            .map_err(|err| RawDemuxerError::DecoderError(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests;
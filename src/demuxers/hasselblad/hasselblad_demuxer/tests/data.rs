@@ -0,0 +1,93 @@
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_parsers_tiffparser::tiffparser::TiffTag;
+use rawspeed_utils_tiffbuilder::tiffbuilder::{Value, tag};
+
+use super::{ThreeFr, compress, decode, new_demuxer_err};
+
+fn rows(width: usize, height: usize) -> Vec<Vec<u16>> {
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| {
+                    let sample = (row * 977 + col * 313 + 5) % (1 << 16);
+                    u16::try_from(sample).unwrap()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn compressed(rows: &[Vec<u16>], initial_prediction: i32) -> ThreeFr {
+    let width = u32::try_from(rows.first().unwrap().len()).unwrap();
+    let height = u32::try_from(rows.len()).unwrap();
+    ThreeFr::new(width, height, compress(rows, 16, initial_prediction))
+}
+
+#[test]
+fn decode_test() {
+    let rows = rows(6, 4);
+    assert_eq!(decode(&compressed(&rows, 0x8000)), Ok(rows));
+}
+
+#[test]
+fn makernote_pixel_base_offset_test() {
+    let rows = rows(6, 4);
+    for endianness in [Endianness::Little, Endianness::Big] {
+        let mut three_fr = compressed(&rows, 0x8000 - 5);
+        three_fr.endianness = endianness;
+        three_fr.set_makernote(vec![tag(
+            TiffTag::HASSELBLAD_PIXEL_BASE_OFFSET,
+            Value::SLong(vec![-5]),
+        )]);
+        assert_eq!(decode(&three_fr), Ok(rows.clone()));
+    }
+}
+
+#[test]
+fn hint_pixel_base_offset_test() {
+    let rows = rows(4, 2);
+    let mut three_fr = compressed(&rows, 0x8000 - 3);
+    three_fr.model = "CFV-0";
+    assert_eq!(decode(&three_fr), Ok(rows.clone()));
+    // The `MakerNote` takes precedence.
+    let mut overridden = compressed(&rows, 0x8000 + 7);
+    overridden.model = "CFV-0";
+    overridden.set_makernote(vec![tag(
+        TiffTag::HASSELBLAD_PIXEL_BASE_OFFSET,
+        Value::Short(vec![7]),
+    )]);
+    assert_eq!(decode(&overridden), Ok(rows));
+}
+
+#[test]
+fn no_raw_image_test() {
+    let mut builder = ThreeFr::new(2, 2, vec![]).builder();
+    builder.remove(TiffTag::STRIP_OFFSETS);
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "No raw image found in 3FR"
+    );
+}
+
+#[test]
+fn dimensions_mismatch_test() {
+    let mut three_fr = compressed(&rows(6, 4), 0x8000);
+    three_fr.height = 5;
+    assert_eq!(
+        new_demuxer_err(&three_fr.build()),
+        "The 3FR image dimensions differ from those of its data"
+    );
+}
+
+#[test]
+fn invalid_data_test() {
+    let three_fr = ThreeFr::new(6, 4, vec![0; 16]);
+    assert_eq!(
+        new_demuxer_err(&three_fr.build()),
+        "HasselbladError(MissingSOI)"
+    );
+    // The data runs out mid-row.
+    let mut data = compress(&rows(64, 16), 16, 0x8000);
+    data.truncate(data.len() / 2);
+    decode(&ThreeFr::new(64, 16, data)).unwrap_err();
+}
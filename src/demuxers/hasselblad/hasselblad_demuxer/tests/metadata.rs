@@ -0,0 +1,105 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_parsers_tiffparser::tiffparser::TiffParser;
+
+use super::{
+    HasselbladDemuxer, ThreeFr, compress, new_demuxer_err, parse_cameras,
+};
+
+fn flat(model: &'static str, precision: u8) -> ThreeFr {
+    let initial_prediction = 0x8000;
+    let rows = vec![vec![0x8000; 4]; 2];
+    let mut three_fr =
+        ThreeFr::new(4, 2, compress(&rows, precision, initial_prediction));
+    three_fr.model = model;
+    three_fr
+}
+
+macro_rules! with_demuxer {
+    ($three_fr:expr, |$demuxer:ident| $body:block) => {{
+        let input = $three_fr.build();
+        let root = TiffParser::parse(&input).unwrap();
+        let cameras = parse_cameras();
+        let ($demuxer, _) = HasselbladDemuxer::new(
+            &root,
+            &cameras,
+            DecodeableCamera::new_unless_unsupported,
+        )
+        .unwrap();
+        $body
+    }};
+}
+
+#[test]
+fn camera_from_cameras_xml_test() {
+    with_demuxer!(flat("Hasselblad H0D", 16), |demuxer| {
+        assert_eq!(demuxer.make(), "Hasselblad");
+        assert_eq!(demuxer.model(), "Hasselblad H0D");
+        assert_eq!(demuxer.mode(), None);
+        assert_eq!(demuxer.canonical_model(), "H0D");
+        assert_eq!(demuxer.canonical_id(), "Hasselblad H0D");
+        assert_eq!(demuxer.iso_speed(), Some(100));
+        assert_eq!(demuxer.whitelevel(), Some(0xFFFF));
+        assert!(demuxer.is_cfa());
+        assert!(demuxer.wb_coeffs().is_none());
+    });
+}
+
+#[test]
+fn model_quirks_test() {
+    // The make may be repeated in the model name.
+    with_demuxer!(flat("Hasselblad CFV-0", 16), |demuxer| {
+        assert_eq!(demuxer.model(), "Hasselblad CFV-0");
+        assert_eq!(demuxer.canonical_id(), "Hasselblad CFV-0");
+    });
+    // The camera may only be named by `UniqueCameraModel`.
+    let mut three_fr = flat("Back", 16);
+    three_fr.unique_model = Some("Hasselblad H0D");
+    with_demuxer!(three_fr, |demuxer| {
+        assert_eq!(demuxer.model(), "Back");
+        assert_eq!(demuxer.canonical_id(), "Hasselblad H0D");
+    });
+}
+
+#[test]
+fn mode_test() {
+    with_demuxer!(flat("CFV-0", 14), |demuxer| {
+        assert_eq!(demuxer.mode(), Some("14bit"));
+        assert_eq!(demuxer.whitelevel(), Some(16383));
+    });
+    with_demuxer!(flat("CFV-0", 16), |demuxer| {
+        assert_eq!(demuxer.mode(), None);
+        assert_eq!(demuxer.whitelevel(), Some(0xFFFF));
+    });
+}
+
+#[test]
+fn wb_coeffs_test() {
+    let mut three_fr = flat("Hasselblad H0D", 16);
+    three_fr.as_shot_neutral = Some(vec![(1, 2), (1, 1), (1, 4)]);
+    with_demuxer!(three_fr, |demuxer| {
+        let [red, green, blue, fourth] = demuxer.wb_coeffs().unwrap();
+        assert_eq!(
+            [red, green, blue].map(f32::to_bits),
+            [2.0_f32, 1.0, 4.0].map(f32::to_bits)
+        );
+        assert!(fourth.is_nan());
+    });
+    let mut invalid = flat("Hasselblad H0D", 16);
+    invalid.as_shot_neutral = Some(vec![(1, 2), (0, 1), (1, 4)]);
+    with_demuxer!(invalid, |demuxer| {
+        assert!(demuxer.wb_coeffs().is_none());
+    });
+}
+
+#[test]
+fn unknown_camera_test() {
+    assert_eq!(
+        new_demuxer_err(&flat("Hasselblad H1D", 16).build()),
+        "Unknown camera: Hasselblad Hasselblad H1D"
+    );
+    assert_eq!(
+        new_demuxer_err(&flat("Unsupported", 16).build()),
+        "This camera is not supported (explicit)"
+    );
+}
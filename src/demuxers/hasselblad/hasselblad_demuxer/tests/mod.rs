@@ -0,0 +1,233 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::Cameras;
+use rawspeed_metadata_xmlparser::xmlparser;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_std::coord_common::RowIndex;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+use rawspeed_utils_tiffbuilder::tiffbuilder::{
+    Entries, TiffBuilder, Value, tag,
+};
+
+use super::HasselbladDemuxer;
+
+const CAMERAS: &str = "
+    <Cameras>
+        <Camera make=\"Hasselblad\" model=\"Hasselblad H0D\">
+            <ID make=\"Hasselblad\" model=\"H0D\">Hasselblad H0D</ID>
+            <CFA width=\"2\" height=\"2\">
+                <Color x=\"0\" y=\"0\">RED</Color>
+                <Color x=\"1\" y=\"0\">GREEN</Color>
+                <Color x=\"0\" y=\"1\">GREEN</Color>
+                <Color x=\"1\" y=\"1\">BLUE</Color>
+            </CFA>
+            <Sensor black=\"0\" white=\"65535\"/>
+        </Camera>
+        <Camera make=\"Hasselblad\" model=\"CFV-0\">
+            <ID make=\"Hasselblad\" model=\"CFV-0\">Hasselblad CFV-0</ID>
+            <Sensor black=\"0\" white=\"65535\"/>
+            <Hints>
+                <Hint name=\"pixelBaseOffset\" value=\"-3\"/>
+            </Hints>
+        </Camera>
+        <Camera make=\"Hasselblad\" model=\"CFV-0\" mode=\"14bit\">
+            <ID make=\"Hasselblad\" model=\"CFV-0\">Hasselblad CFV-0</ID>
+            <Sensor black=\"0\" white=\"16383\"/>
+        </Camera>
+        <Camera make=\"Hasselblad\" model=\"Unsupported\" supported=\"no\">
+        </Camera>
+    </Cameras>";
+
+/// All 17 difference lengths, as 5-bit codes equal to the length.
+const COUNTS: [u8; 16] = [0, 0, 0, 0, 17, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+const CODE_LENGTH: u32 = 5;
+
+fn segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+    let len = u16::try_from(payload.len() + 2).unwrap();
+    let mut out = vec![0xFF, marker];
+    out.extend(len.to_le_bytes().iter().rev());
+    out.extend(payload);
+    out
+}
+
+/// Encodes the rows as Hasselblad's lossless JPEG: the lengths of the
+/// differences of each pair of samples, then the differences.
+fn compress(
+    rows: &[Vec<u16>],
+    precision: u8,
+    initial_prediction: i32,
+) -> Vec<u8> {
+    let width = u16::try_from(rows.first().unwrap().len()).unwrap();
+    let height = u16::try_from(rows.len()).unwrap();
+    let mut out = vec![0xFF, 0xD8];
+    let mut dht = vec![0];
+    dht.extend(COUNTS);
+    dht.extend(0..=16);
+    out.extend(segment(0xC4, &dht));
+    let mut sof = vec![precision];
+    sof.extend(height.to_le_bytes().iter().rev());
+    sof.extend(width.to_le_bytes().iter().rev());
+    sof.extend([1, 0, 0x11, 0]);
+    out.extend(segment(0xC3, &sof));
+    out.extend(segment(0xDA, &[1, 0, 0, 8, 0, 0]));
+
+    let mut bits = BitStreamBuilder::new();
+    for row in rows {
+        let mut preds = [initial_prediction; 2];
+        for pair in row.chunks_exact(2) {
+            let mut diffs = [0; 2];
+            for ((diff, sample), pred) in
+                diffs.iter_mut().zip(pair).zip(&mut preds)
+            {
+                *diff = i32::from(*sample) - *pred;
+                *pred = i32::from(*sample);
+            }
+            let lens =
+                diffs.map(|diff| 32 - diff.unsigned_abs().leading_zeros());
+            for len in lens {
+                bits.put(len, CODE_LENGTH);
+            }
+            for (diff, len) in diffs.into_iter().zip(lens) {
+                let value = if diff < 0 {
+                    diff + (1 << len) - 1
+                } else {
+                    diff
+                };
+                bits.put(u32::try_from(value).unwrap(), len);
+            }
+        }
+    }
+    out.extend(bits.finish(BitOrder::MSB32));
+    out
+}
+
+#[derive(Debug)]
+struct ThreeFr {
+    endianness: Endianness,
+    model: &'static str,
+    unique_model: Option<&'static str>,
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+    makernote: Option<Vec<u8>>,
+    as_shot_neutral: Option<Vec<(u32, u32)>>,
+}
+
+impl ThreeFr {
+    fn new(width: u32, height: u32, data: Vec<u8>) -> Self {
+        Self {
+            endianness: Endianness::Little,
+            model: "Hasselblad H0D",
+            unique_model: None,
+            width,
+            height,
+            data,
+            makernote: None,
+            as_shot_neutral: None,
+        }
+    }
+
+    /// The first IFD holds a preview, and the raw image is in its sub-IFD.
+    fn builder(&self) -> TiffBuilder {
+        let mut builder = TiffBuilder::new(self.endianness);
+        builder.ifd(0).extend([
+            tag(TiffTag::MAKE, Value::Ascii("Hasselblad")),
+            tag(TiffTag::MODEL, Value::Ascii(self.model)),
+            tag(TiffTag::IMAGE_WIDTH, Value::Long(vec![2])),
+            tag(TiffTag::IMAGE_LENGTH, Value::Long(vec![2])),
+            tag(TiffTag::STRIP_OFFSETS, Value::BlobOffsets(vec![1])),
+            tag(TiffTag::STRIP_BYTE_COUNTS, Value::BlobSizes(vec![1])),
+            tag(TiffTag::SUB_IFDS, Value::IFDOffset(2)),
+            tag(TiffTag::EXIF_IFD_POINTER, Value::IFDOffset(1)),
+        ]);
+        if let Some(unique_model) = self.unique_model {
+            builder.ifd(0).push(tag(
+                TiffTag::UNIQUE_CAMERA_MODEL,
+                Value::Ascii(unique_model),
+            ));
+        }
+        if let Some(neutral) = &self.as_shot_neutral {
+            builder.ifd(0).push(tag(
+                TiffTag::AS_SHOT_NEUTRAL,
+                Value::Rational(neutral.clone()),
+            ));
+        }
+        builder
+            .ifd(1)
+            .push(tag(TiffTag::ISO_SPEED_RATINGS, Value::Short(vec![100])));
+        if let Some(makernote) = &self.makernote {
+            builder.ifd(1).push(tag(
+                TiffTag::MAKER_NOTE,
+                Value::Undefined(makernote.clone()),
+            ));
+        }
+        builder.ifd(2).extend([
+            tag(TiffTag::IMAGE_WIDTH, Value::Long(vec![self.width])),
+            tag(TiffTag::IMAGE_LENGTH, Value::Long(vec![self.height])),
+            tag(TiffTag::STRIP_OFFSETS, Value::BlobOffsets(vec![0])),
+            tag(TiffTag::STRIP_BYTE_COUNTS, Value::BlobSizes(vec![0])),
+        ]);
+        builder.blobs = vec![self.data.clone(), vec![0; 12]];
+        builder
+    }
+
+    fn build(&self) -> Vec<u8> {
+        self.builder().build()
+    }
+
+    /// Sets the `MakerNote`, whose offsets are relative to the start of
+    /// the file, and thus depend on where it is stored.
+    fn set_makernote(&mut self, entries: Entries) {
+        let mut builder = TiffBuilder::new(self.endianness);
+        *builder.ifd(0) = entries;
+        self.makernote = Some(builder.build_after(&[]));
+        let input = self.build();
+        let root = TiffParser::parse(&input).unwrap();
+        let offset = root
+            .get_entry_recursive(TiffTag::MAKER_NOTE)
+            .unwrap()
+            .data_offset();
+        let makernote = builder.build_after(&vec![0; offset]);
+        self.makernote = Some(makernote.get(offset..).unwrap().to_vec());
+    }
+}
+
+fn parse_cameras() -> Cameras<'static> {
+    xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap()
+}
+
+fn new_demuxer_err(input: &[u8]) -> String {
+    let root = TiffParser::parse(input).unwrap();
+    let cameras = parse_cameras();
+    HasselbladDemuxer::new(
+        &root,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )
+    .unwrap_err()
+}
+
+fn decode(three_fr: &ThreeFr) -> Result<Vec<Vec<u16>>, String> {
+    let input = three_fr.build();
+    let root = TiffParser::parse(&input).unwrap();
+    let cameras = parse_cameras();
+    let (demuxer, request) = HasselbladDemuxer::new(
+        &root,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )?;
+    let mut output_buf = request.fulfill().unwrap();
+    let mut image = output_buf.get_mut();
+    demuxer.decode(&mut image).map_err(|err| err.to_string())?;
+    let output = image.as_u16().unwrap();
+    Ok((0..output.num_rows().get())
+        .map(|row| output.get_row(RowIndex::new(row)).unwrap().to_vec())
+        .collect())
+}
+
+#[cfg(test)]
+mod data;
+#[cfg(test)]
+mod metadata;
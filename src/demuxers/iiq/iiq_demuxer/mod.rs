@@ -0,0 +1,684 @@
+use rawspeed_bitstream_bytestream_decoder::bytestreamer::ByteStreamer;
+use rawspeed_codecs_phaseone::phaseone::IiqDecompressor;
+use rawspeed_demuxers_common::{
+    camera_metadata::CameraMetadata,
+    tiff_utils::{get_root_string, non_zero},
+};
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::{
+    DataType, ImageRefMut, ProcurementRequest, RawDemuxer, RawDemuxerError,
+};
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_memory_nd_slice_procurement::ndsliceprocurement::NDSliceProcurementRequest;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::{
+    Cameras, Supported, blackareas::BlackArea,
+};
+use rawspeed_metadata_colorfilterarray::colorfilterarray::ColorVariant;
+use rawspeed_parsers_tiffparser::tiffparser::{
+    TiffEntry, TiffRootIFD, TiffTag,
+};
+use rawspeed_std::coord_common::{
+    ColIndex, Coord2D, Dimensions2D, RowCount, RowIndex, RowLength,
+};
+use rawspeed_std_ndslice::{
+    array2dref::Array2DRef, array2drefmut::Array2DRefMut,
+    offsetarray2dref::OffsetArray2DRef,
+};
+
+const IIQ_MAGIC: &[u8] = b"IIII";
+
+/// The IIQ header follows the TIFF one, and the offsets of the IIQ
+/// directory are relative to its start.
+const IIQ_HEADER_OFFSET: usize = 8;
+
+/// The offset of the IIQ directory is stored here, in the IIQ header, and
+/// that of the sensor correction directory in the sensor correction data.
+const DIRECTORY_POINTER: usize = 8;
+
+/// The IIQ directory entries: a tag, a type, a length, and either the
+/// value or the offset of the data. The sensor correction directory
+/// entries lack the type.
+const IIQ_ENTRY_SIZE: usize = 16;
+const CORRECTION_ENTRY_SIZE: usize = 12;
+
+/// The IIQ directory tags.
+const IIQ_WB_COEFFS: u32 = 0x107;
+const IIQ_WIDTH: u32 = 0x108;
+const IIQ_HEIGHT: u32 = 0x109;
+const IIQ_FORMAT: u32 = 0x10E;
+const IIQ_RAW_DATA: u32 = 0x10F;
+const IIQ_SENSOR_CORRECTION: u32 = 0x110;
+const IIQ_STRIP_OFFSETS: u32 = 0x21C;
+const IIQ_BLACK_LEVEL: u32 = 0x21D;
+const IIQ_SPLIT_COL: u32 = 0x222;
+const IIQ_SPLIT_ROW: u32 = 0x224;
+const IIQ_COLUMN_BLACK_OFFSETS: u32 = 0x225;
+
+/// The sensor correction directory tags.
+const IIQ_QUADRANT_MULTIPLIERS: u32 = 0x431;
+
+const FORMAT_IIQ_L: u32 = 3;
+const FORMAT_IIQ_S: u32 = 5;
+
+/// The samples are of 14 bits, but the black levels are given in 16-bit
+/// units.
+const BLACK_LEVEL_SHIFT: u32 = 2;
+
+/// The quadrant multipliers are fixed-point, with this scale.
+const MULTIPLIER_SCALE: u64 = 10000;
+
+/// The number of control points of the quadrant curves, besides the
+/// first and the last one, which are fixed.
+const NUM_CONTROL_POINTS: usize = 7;
+
+type T = u16;
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    data.get(pos..)
+        .and_then(|data| data.get(..size_of::<u32>()))
+        .map(|bytes| ByteStreamer::new(bytes, Endianness::Little).read())
+}
+
+fn read_usize(data: &[u8], pos: usize) -> Option<usize> {
+    read_u32(data, pos).map(|val| usize::try_from(val).unwrap())
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    tag: u32,
+    len: u32,
+    data: u32,
+}
+
+/// A directory stored at the offset found at [`DIRECTORY_POINTER`]: the
+/// number of entries, four unknown bytes, then the entries. Everything is
+/// little-endian, and the offsets are relative to the start of `input`.
+#[derive(Debug)]
+struct Directory<'a> {
+    input: &'a [u8],
+    entries: Vec<Entry>,
+    what: &'static str,
+}
+
+impl<'a> Directory<'a> {
+    fn parse(
+        input: &'a [u8],
+        entry_size: usize,
+        what: &'static str,
+    ) -> Result<Self, String> {
+        let truncated = || format!("The {what} directory is truncated");
+        let offset =
+            read_usize(input, DIRECTORY_POINTER).ok_or_else(truncated)?;
+        let count = read_usize(input, offset).ok_or_else(truncated)?;
+        let data = count
+            .checked_mul(entry_size)
+            .and_then(|len| {
+                input.get(offset + 2 * size_of::<u32>()..)?.get(..len)
+            })
+            .ok_or_else(truncated)?;
+        let entries = data
+            .chunks_exact(entry_size)
+            .map(|entry| Entry {
+                tag: read_u32(entry, 0).unwrap(),
+                len: read_u32(entry, entry_size - 2 * size_of::<u32>())
+                    .unwrap(),
+                data: read_u32(entry, entry_size - size_of::<u32>()).unwrap(),
+            })
+            .collect();
+        Ok(Self {
+            input,
+            entries,
+            what,
+        })
+    }
+
+    fn get(&self, tag: u32) -> Option<Entry> {
+        self.entries.iter().find(|entry| entry.tag == tag).copied()
+    }
+
+    fn value(&self, tag: u32) -> Option<u32> {
+        self.get(tag).map(|entry| entry.data)
+    }
+
+    fn data(&self, tag: u32) -> Result<Option<&'a [u8]>, String> {
+        let Some(entry) = self.get(tag) else {
+            return Ok(None);
+        };
+        let offset = usize::try_from(entry.data).unwrap();
+        let len = usize::try_from(entry.len).unwrap();
+        self.input
+            .get(offset..)
+            .and_then(|data| data.get(..len))
+            .map(Some)
+            .ok_or_else(|| {
+                format!(
+                    "The {} data of tag 0x{tag:X} is out of bounds",
+                    self.what
+                )
+            })
+    }
+}
+
+/// Evaluates the natural cubic spline through the given control points,
+/// which must start at zero and end at the largest 16-bit value, at each
+/// 16-bit value.
+#[expect(
+    clippy::float_arithmetic,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+fn spline_curve(points: &[(u32, u32)]) -> Vec<u16> {
+    let xs = points
+        .iter()
+        .map(|(x, _)| f64::from(*x))
+        .collect::<Vec<_>>();
+    let ys = points
+        .iter()
+        .map(|(_, y)| f64::from(*y))
+        .collect::<Vec<_>>();
+    let at = |values: &[f64], index: usize| *values.get(index).unwrap();
+    let segments = points.len() - 1;
+    let h = (0..segments)
+        .map(|i| at(&xs, i + 1) - at(&xs, i))
+        .collect::<Vec<_>>();
+    let slope = |i: usize| (at(&ys, i + 1) - at(&ys, i)) / at(&h, i);
+    // The second derivatives, zero at both ends, solve a tridiagonal
+    // system.
+    let mut upper = vec![0.0; points.len()];
+    let mut rhs = vec![0.0; points.len()];
+    for i in 1..segments {
+        let (h_lower, h_upper) = (at(&h, i - 1), at(&h, i));
+        let pivot = 2.0 * (h_lower + h_upper) - h_lower * at(&upper, i - 1);
+        *upper.get_mut(i).unwrap() = h_upper / pivot;
+        *rhs.get_mut(i).unwrap() = (6.0 * (slope(i) - slope(i - 1))
+            - h_lower * at(&rhs, i - 1))
+            / pivot;
+    }
+    let mut second = vec![0.0; points.len()];
+    for i in (1..segments).rev() {
+        *second.get_mut(i).unwrap() =
+            at(&rhs, i) - at(&upper, i) * at(&second, i + 1);
+    }
+    let mut curve = Vec::with_capacity(usize::from(u16::MAX) + 1);
+    for i in 0..segments {
+        let (x0, x1, h_i) = (at(&xs, i), at(&xs, i + 1), at(&h, i));
+        let (m0, m1) = (at(&second, i), at(&second, i + 1));
+        let (y0, y1) = (at(&ys, i), at(&ys, i + 1));
+        let end = if i + 1 == segments {
+            points.last().unwrap().0 + 1
+        } else {
+            points.get(i + 1).unwrap().0
+        };
+        for x in points.get(i).unwrap().0..end {
+            let (left, right) = (f64::from(x) - x0, x1 - f64::from(x));
+            let y = m0 * right.powi(3) / (6.0 * h_i)
+                + m1 * left.powi(3) / (6.0 * h_i)
+                + (y0 / h_i - m0 * h_i / 6.0) * right
+                + (y1 / h_i - m1 * h_i / 6.0) * left;
+            curve.push(y.clamp(0.0, f64::from(u16::MAX)).round() as u16);
+        }
+    }
+    curve
+}
+
+/// The shared abscissas of the control points, then, for each quadrant in
+/// row-major order, the multiplier of each of their ordinates.
+fn parse_quadrant_curves(data: &[u8]) -> Result<Vec<Vec<u16>>, String> {
+    let values = (0..NUM_CONTROL_POINTS * 5)
+        .map(|index| read_u32(data, index * size_of::<u32>()))
+        .collect::<Option<Vec<_>>>()
+        .ok_or("The IIQ quadrant multipliers are truncated")?;
+    let (xs, multipliers) = values.split_at(NUM_CONTROL_POINTS);
+    let max = u32::from(u16::MAX);
+    let increasing = [0]
+        .iter()
+        .chain(xs)
+        .chain([&max])
+        .is_sorted_by(|a, b| a < b);
+    if !increasing {
+        return Err("Invalid IIQ quadrant control points".to_owned());
+    }
+    Ok(multipliers
+        .chunks_exact(NUM_CONTROL_POINTS)
+        .map(|multipliers| {
+            let points = xs.iter().zip(multipliers).map(|(x, multiplier)| {
+                let y =
+                    u64::from(*multiplier) * u64::from(*x) / MULTIPLIER_SCALE;
+                (*x, u32::try_from(y.min(u64::from(max))).unwrap())
+            });
+            let points = [(0, 0)]
+                .into_iter()
+                .chain(points)
+                .chain([(max, max)])
+                .collect::<Vec<_>>();
+            spline_curve(&points)
+        })
+        .collect())
+}
+
+/// The corrections of the sensor, which is read out in quadrants.
+#[derive(Debug, Default)]
+struct Corrections {
+    split_row: usize,
+    split_col: usize,
+    black_level: u16,
+    /// The offsets of each column, above and below the split row.
+    column_black_offsets: Option<Vec<[i32; 2]>>,
+    /// The linearisation curve of each quadrant, in row-major order.
+    quadrant_curves: Option<Vec<Vec<u16>>>,
+}
+
+impl Corrections {
+    fn parse_column_black_offsets(
+        data: &[u8],
+        width: usize,
+    ) -> Result<Vec<[i32; 2]>, String> {
+        let data = data
+            .get(..width * 2 * size_of::<u16>())
+            .ok_or("The IIQ column black offsets are truncated")?;
+        Ok(data
+            .chunks_exact(2 * size_of::<u16>())
+            .map(|offsets| {
+                [0, size_of::<u16>()].map(|pos| {
+                    let bytes = offsets.get(pos..pos + size_of::<u16>());
+                    let offset: i16 =
+                        ByteStreamer::new(bytes.unwrap(), Endianness::Little)
+                            .read();
+                    i32::from(offset) >> BLACK_LEVEL_SHIFT
+                })
+            })
+            .collect())
+    }
+
+    /// The sensor correction data is a directory of its own, of which only
+    /// the quadrant multipliers are used. It extends to the end of the
+    /// IIQ data.
+    fn parse_quadrant_curves(
+        header: &[u8],
+        directory: &Directory<'_>,
+    ) -> Result<Option<Vec<Vec<u16>>>, String> {
+        let Some(offset) = directory.value(IIQ_SENSOR_CORRECTION) else {
+            return Ok(None);
+        };
+        let data = header
+            .get(usize::try_from(offset).unwrap()..)
+            .ok_or("The IIQ sensor correction data is out of bounds")?;
+        let corrections = Directory::parse(
+            data,
+            CORRECTION_ENTRY_SIZE,
+            "IIQ sensor correction",
+        )?;
+        let count = corrections
+            .entries
+            .iter()
+            .filter(|entry| entry.tag == IIQ_QUADRANT_MULTIPLIERS)
+            .count();
+        if count > 1 {
+            return Err("Duplicate IIQ quadrant multipliers".to_owned());
+        }
+        corrections
+            .data(IIQ_QUADRANT_MULTIPLIERS)?
+            .map(parse_quadrant_curves)
+            .transpose()
+    }
+
+    fn parse(
+        header: &[u8],
+        directory: &Directory<'_>,
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+    ) -> Result<Self, String> {
+        let (width, height) = (dims.row_len().get(), dims.row_count().get());
+        let black_level = directory
+            .value(IIQ_BLACK_LEVEL)
+            .map_or(0, |level| level >> BLACK_LEVEL_SHIFT);
+        let column_black_offsets = directory
+            .data(IIQ_COLUMN_BLACK_OFFSETS)?
+            .map(|data| Self::parse_column_black_offsets(data, width))
+            .transpose()?;
+        let quadrant_curves = Self::parse_quadrant_curves(header, directory)?;
+        let split = |tag| {
+            directory
+                .value(tag)
+                .map(|split| usize::try_from(split).unwrap())
+        };
+        let (split_row, split_col) =
+            match (split(IIQ_SPLIT_ROW), split(IIQ_SPLIT_COL)) {
+                (Some(row), Some(col)) if row <= height && col <= width => {
+                    (row, col)
+                }
+                (None, None)
+                    if column_black_offsets.is_none()
+                        && quadrant_curves.is_none() =>
+                {
+                    (0, 0)
+                }
+                (_, _) => return Err("Invalid IIQ sensor split".to_owned()),
+            };
+        Ok(Self {
+            split_row,
+            split_col,
+            black_level: u16::try_from(black_level)
+                .map_err(|_err| "Invalid IIQ black level")?,
+            column_black_offsets,
+            quadrant_curves,
+        })
+    }
+
+    fn apply(&self, output: &mut Array2DRefMut<'_, u16>) {
+        let (width, height) =
+            (output.row_length().get(), output.num_rows().get());
+        for row in 0..height {
+            let below = usize::from(row >= self.split_row);
+            for col in 0..width {
+                let right = usize::from(col >= self.split_col);
+                let pos = Coord2D::new(RowIndex::new(row), ColIndex::new(col));
+                let mut pixel = i32::from(output[pos]);
+                if let Some(offsets) = &self.column_black_offsets {
+                    pixel += offsets.get(col).unwrap().get(below).unwrap();
+                }
+                let mut pixel =
+                    u16::try_from(pixel.clamp(0, i32::from(u16::MAX))).unwrap();
+                if let Some(curves) = &self.quadrant_curves {
+                    // The curves apply to the signal above the black level.
+                    if let Some(signal) = pixel.checked_sub(self.black_level) {
+                        let curve = curves.get(2 * below + right).unwrap();
+                        pixel = curve
+                            .get(usize::from(signal))
+                            .unwrap()
+                            .saturating_add(self.black_level);
+                    }
+                }
+                output[pos] = pixel;
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct IiqDemuxer<'a> {
+    metadata: CameraMetadata<'a>,
+    decompressor: IiqDecompressor<'a>,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    corrections: Corrections,
+    black_level: Option<u16>,
+    wb_coeffs: Option<[f32; 4]>,
+}
+
+impl<'a> IiqDemuxer<'a> {
+    /// Phase One raw files are TIFFs whose IIQ header follows the TIFF one.
+    #[inline]
+    #[must_use]
+    pub fn is_iiq(root: &TiffRootIFD<'_>) -> bool {
+        let make = get_root_string(root, TiffTag::MAKE).unwrap_or("");
+        make.starts_with("Phase One")
+            && root
+                .input()
+                .get(IIQ_HEADER_OFFSET..)
+                .is_some_and(|header| header.starts_with(IIQ_MAGIC))
+    }
+
+    fn parse_dims(
+        directory: &Directory<'_>,
+    ) -> Result<Dimensions2D<core::num::NonZero<usize>>, String> {
+        let get = |tag| {
+            directory
+                .value(tag)
+                .map_or(0, |len| usize::try_from(len).unwrap())
+        };
+        Ok(Dimensions2D::new(
+            RowLength::new(non_zero(get(IIQ_WIDTH), "width")?),
+            RowCount::new(non_zero(get(IIQ_HEIGHT), "height")?),
+        ))
+    }
+
+    /// The strip offset table holds the offset of each row into the raw
+    /// data.
+    fn parse_decompressor(
+        directory: &Directory<'a>,
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+    ) -> Result<IiqDecompressor<'a>, String> {
+        let (Some(raw), Some(table)) = (
+            directory.data(IIQ_RAW_DATA)?,
+            directory.value(IIQ_STRIP_OFFSETS),
+        ) else {
+            return Err("No raw image found in IIQ".to_owned());
+        };
+        let table = usize::try_from(table).unwrap();
+        let row_offsets = (0..dims.row_count().get())
+            .map(|row| {
+                read_usize(directory.input, table + row * size_of::<u32>())
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or("The IIQ strip offset table is truncated")?;
+        IiqDecompressor::new(raw, &row_offsets, dims)
+            .map_err(|err| err.to_string())
+    }
+
+    /// The white balance is stored as three floats.
+    fn parse_wb_coeffs(
+        directory: &Directory<'_>,
+    ) -> Result<Option<[f32; 4]>, String> {
+        let Some(data) = directory
+            .data(IIQ_WB_COEFFS)?
+            .filter(|data| data.len() == 3 * size_of::<f32>())
+        else {
+            return Ok(None);
+        };
+        let mut bs = ByteStreamer::new(data, Endianness::Little);
+        let [red, green, blue] = [(); 3].map(|()| bs.read::<f32>());
+        Ok(Some([red, green, blue, f32::NAN]))
+    }
+
+    #[inline(never)]
+    pub fn new<F>(
+        root: &TiffRootIFD<'a>,
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<(Self, ProcurementRequest), String>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        if !Self::is_iiq(root) {
+            return Err("Not an IIQ file".to_owned());
+        }
+        let header = root.input().get(IIQ_HEADER_OFFSET..).unwrap();
+        let directory = Directory::parse(header, IIQ_ENTRY_SIZE, "IIQ")?;
+        let format = directory.value(IIQ_FORMAT).unwrap_or(0);
+        if !matches!(format, FORMAT_IIQ_L | FORMAT_IIQ_S) {
+            return Err(format!("Unsupported IIQ raw format: {format}"));
+        }
+        let dims = Self::parse_dims(&directory)?;
+
+        let make = get_root_string(root, TiffTag::MAKE).unwrap_or("");
+        let model = get_root_string(root, TiffTag::MODEL).unwrap_or("");
+        let camera = cameras
+            .find(make, model, None)
+            .ok_or_else(|| format!("Unknown camera: {make} {model}"))?;
+        check_camera_support_fn(camera.supported)?;
+        let iso_speed = root
+            .get_entry_recursive(TiffTag::ISO_SPEED_RATINGS)
+            .map(TiffEntry::get_u32s)
+            .and_then(Result::ok)
+            .and_then(|isos| isos.first().copied());
+        let metadata = CameraMetadata::new(camera, make, model, iso_speed);
+
+        let decompressor = Self::parse_decompressor(&directory, dims)?;
+        let corrections = Corrections::parse(header, &directory, dims)?;
+        let black_level = directory
+            .value(IIQ_BLACK_LEVEL)
+            .map(|_level| corrections.black_level);
+        let wb_coeffs = Self::parse_wb_coeffs(&directory)?;
+        Ok((
+            Self {
+                metadata,
+                decompressor,
+                dims,
+                corrections,
+                black_level,
+                wb_coeffs,
+            },
+            NDSliceProcurementRequest::<T>::new(dims).into(),
+        ))
+    }
+}
+
+#[expect(clippy::missing_trait_methods)]
+impl RawDemuxer for IiqDemuxer<'_> {
+    #[inline]
+    fn make(&self) -> &str {
+        self.metadata.make
+    }
+
+    #[inline]
+    fn model(&self) -> &str {
+        self.metadata.model
+    }
+
+    #[inline]
+    fn mode(&self) -> Option<&str> {
+        self.metadata.mode()
+    }
+
+    #[inline]
+    fn canonical_make(&self) -> &str {
+        self.metadata.canonical_make()
+    }
+
+    #[inline]
+    fn canonical_model(&self) -> &str {
+        self.metadata.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_alias(&self) -> &str {
+        self.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_id(&self) -> String {
+        self.metadata.canonical_id()
+    }
+
+    #[inline]
+    fn iso_speed(&self) -> Option<u32> {
+        self.metadata.iso_speed
+    }
+
+    /// The black level of the IIQ directory takes precedence.
+    #[inline]
+    fn blacklevel(&self) -> Option<u16> {
+        self.black_level.or_else(|| self.metadata.blacklevel())
+    }
+
+    #[inline]
+    fn whitelevel(&self) -> Option<u16> {
+        self.metadata.whitelevel()
+    }
+
+    #[inline]
+    fn blacklevel_separate(&self) -> Option<Array2DRef<'_, i32>> {
+        None
+    }
+
+    #[inline]
+    fn wb_coeffs(&self) -> Option<[f32; 4]> {
+        self.wb_coeffs
+    }
+
+    #[inline]
+    fn colormatrix(&self) -> Option<Array2DRef<'_, i16>> {
+        self.metadata.colormatrix()
+    }
+
+    #[inline]
+    fn is_cfa(&self) -> bool {
+        self.metadata.is_cfa()
+    }
+
+    #[inline]
+    fn cfa(
+        &self,
+        origin: Coord2D,
+    ) -> Option<OffsetArray2DRef<'_, ColorVariant>> {
+        self.metadata.cfa(origin)
+    }
+
+    #[inline]
+    fn bpp(&self) -> usize {
+        let bpc = match self.datatype() {
+            DataType::U16 => size_of::<u16>(),
+            DataType::F32 => size_of::<f32>(),
+            DataType::U32 => size_of::<u32>(),
+            _ => unreachable!(),
+        };
+        bpc.checked_mul(self.cpp()).unwrap()
+    }
+
+    #[inline]
+    fn cpp(&self) -> usize {
+        1
+    }
+
+    #[inline]
+    fn datatype(&self) -> DataType {
+        DataType::U16
+    }
+
+    #[inline]
+    fn dim_uncropped(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        self.dims
+    }
+
+    #[inline]
+    fn dim_cropped(&self) -> Option<Dimensions2D<core::num::NonZero<usize>>> {
+        self.metadata.dim_cropped(self.dim_uncropped())
+    }
+
+    #[inline]
+    fn crop_offset(&self) -> Option<Coord2D> {
+        self.metadata.crop_offset()
+    }
+
+    #[inline]
+    fn black_areas(&self) -> Option<&[BlackArea]> {
+        self.metadata.black_areas()
+    }
+
+    #[inline]
+    fn fuji_rotation_pos(&self) -> Option<u32> {
+        None
+    }
+
+    #[inline]
+    fn pixel_aspect_ratio(&self) -> Option<f64> {
+        None
+    }
+
+    #[inline]
+    fn bad_pixel_positions(&self) -> Vec<Coord2D> {
+        vec![]
+    }
+
+    #[inline(never)]
+    fn decode(
+        &self,
+        output: &mut ImageRefMut<'_>,
+    ) -> Result<(), RawDemuxerError> {
+        let output = output.as_u16()?;
+        if output.dims() != self.dims {
+            return Err(RawDemuxerError::DecoderError(
+                "Output buffer dimensions differ from expected".to_owned(),
+            ));
+        }
+        self.decompressor
+            .decode(output)
+            .map_err(|err| RawDemuxerError::DecoderError(err.to_string()))?;
+        self.corrections.apply(output);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
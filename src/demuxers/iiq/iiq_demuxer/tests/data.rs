@@ -0,0 +1,204 @@
+use super::{
+    Field, Iiq, decode, new_demuxer_err, quadrant_field, quadrant_multipliers,
+};
+use crate::iiq_demuxer::{
+    FORMAT_IIQ_L, FORMAT_IIQ_S, IIQ_BLACK_LEVEL, IIQ_COLUMN_BLACK_OFFSETS,
+    IIQ_QUADRANT_MULTIPLIERS, IIQ_RAW_DATA, IIQ_SPLIT_COL, IIQ_SPLIT_ROW,
+    spline_curve,
+};
+
+fn rows(width: usize, height: usize) -> Vec<Vec<u16>> {
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| u16::try_from(row * 977 + col * 313 + 5).unwrap())
+                .collect()
+        })
+        .collect()
+}
+
+fn split(iiq: &mut Iiq, row: u32, col: u32) {
+    iiq.fields.extend([
+        (IIQ_SPLIT_ROW, Field::Value(row)),
+        (IIQ_SPLIT_COL, Field::Value(col)),
+    ]);
+}
+
+/// The offsets of each column, above and below the split row, in 16-bit
+/// units.
+fn column_black_offsets(offsets: &[[i16; 2]]) -> (u32, Field) {
+    let data = offsets.iter().flatten().flat_map(|o| o.to_le_bytes());
+    (IIQ_COLUMN_BLACK_OFFSETS, Field::Data(data.collect()))
+}
+
+#[test]
+fn decode_test() {
+    for format in [FORMAT_IIQ_L, FORMAT_IIQ_S] {
+        let rows = rows(5, 3);
+        let mut iiq = Iiq::new(&rows);
+        iiq.format = format;
+        assert_eq!(decode(&iiq), Ok(rows));
+    }
+}
+
+#[test]
+fn column_black_offsets_test() {
+    let mut iiq = Iiq::flat(4, 2, 1000);
+    split(&mut iiq, 1, 2);
+    iiq.fields.push(column_black_offsets(&[
+        [40, -40],
+        [0, 4],
+        [-8000, 1],
+        [0x7FFF, 0],
+    ]));
+    assert_eq!(
+        decode(&iiq),
+        Ok(vec![vec![1010, 1000, 0, 9191], vec![990, 1001, 1000, 1000]])
+    );
+}
+
+#[test]
+fn truncated_column_black_offsets_test() {
+    let mut iiq = Iiq::flat(4, 2, 1000);
+    split(&mut iiq, 1, 2);
+    iiq.fields.push(column_black_offsets(&[[0, 0]; 3]));
+    assert_eq!(
+        new_demuxer_err(&iiq.build()),
+        "The IIQ column black offsets are truncated"
+    );
+}
+
+#[test]
+fn quadrant_curves_test() {
+    // The curves pass through their control points, above the black
+    // level; the multipliers of the bottom right quadrant are 1.5.
+    let mut iiq = Iiq::new(&[vec![1100, 1200, 2100, 50], vec![3100; 4]]);
+    split(&mut iiq, 1, 2);
+    iiq.fields.push((IIQ_BLACK_LEVEL, Field::Value(400)));
+    iiq.corrections
+        .push(quadrant_field([10000, 10000, 10000, 15000]));
+    assert_eq!(
+        decode(&iiq),
+        Ok(vec![
+            vec![1100, 1200, 2100, 50],
+            vec![3100, 3100, 4600, 4600]
+        ])
+    );
+}
+
+#[test]
+fn identity_curve_test() {
+    let points = [(0, 0), (1000, 1000), (30000, 30000), (0xFFFF, 0xFFFF)];
+    let curve = spline_curve(&points);
+    assert_eq!(curve.len(), 0x1_0000);
+    assert!(curve.iter().enumerate().all(|(x, y)| usize::from(*y) == x));
+}
+
+#[test]
+fn saturated_curve_test() {
+    let points = [(0, 0), (100, 0xFFFF), (200, 0), (0xFFFF, 0xFFFF)];
+    let curve = spline_curve(&points);
+    assert_eq!(curve.get(100), Some(&0xFFFF));
+    assert_eq!(curve.get(200), Some(&0));
+    assert_eq!(curve.get(0xFFFF), Some(&0xFFFF));
+}
+
+#[test]
+fn invalid_quadrant_multipliers_test() {
+    let mut duplicate = Iiq::flat(4, 2, 1000);
+    split(&mut duplicate, 1, 2);
+    duplicate.corrections = vec![quadrant_field([10000; 4]); 2];
+    assert_eq!(
+        new_demuxer_err(&duplicate.build()),
+        "Duplicate IIQ quadrant multipliers"
+    );
+    let mut unsorted = Iiq::flat(4, 2, 1000);
+    split(&mut unsorted, 1, 2);
+    unsorted.corrections.push((
+        IIQ_QUADRANT_MULTIPLIERS,
+        quadrant_multipliers([1, 2, 3, 4, 5, 5, 6], [10000; 4]),
+    ));
+    assert_eq!(
+        new_demuxer_err(&unsorted.build()),
+        "Invalid IIQ quadrant control points"
+    );
+    let mut truncated = Iiq::flat(4, 2, 1000);
+    split(&mut truncated, 1, 2);
+    truncated
+        .corrections
+        .push((IIQ_QUADRANT_MULTIPLIERS, Field::Data(vec![0; 136])));
+    assert_eq!(
+        new_demuxer_err(&truncated.build()),
+        "The IIQ quadrant multipliers are truncated"
+    );
+}
+
+#[test]
+fn invalid_split_test() {
+    // The corrections need the split, and it must be within the image.
+    let mut missing = Iiq::flat(4, 2, 1000);
+    missing.corrections.push(quadrant_field([10000; 4]));
+    assert_eq!(
+        new_demuxer_err(&missing.build()),
+        "Invalid IIQ sensor split"
+    );
+    let mut outside = Iiq::flat(4, 2, 1000);
+    split(&mut outside, 3, 2);
+    outside.corrections.push(quadrant_field([10000; 4]));
+    assert_eq!(
+        new_demuxer_err(&outside.build()),
+        "Invalid IIQ sensor split"
+    );
+}
+
+#[test]
+fn unsupported_format_test() {
+    let mut iiq = Iiq::flat(4, 2, 0);
+    iiq.format = 1;
+    assert_eq!(
+        new_demuxer_err(&iiq.build()),
+        "Unsupported IIQ raw format: 1"
+    );
+}
+
+#[test]
+fn invalid_dimensions_test() {
+    let mut iiq = Iiq::flat(4, 2, 0);
+    iiq.height = 0;
+    assert_eq!(new_demuxer_err(&iiq.build()), "The height is zero");
+}
+
+#[test]
+fn no_raw_image_test() {
+    let iiq = Iiq::flat(4, 2, 0);
+    let fields = iiq
+        .fields()
+        .into_iter()
+        .filter(|(tag, _)| *tag != IIQ_RAW_DATA)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        new_demuxer_err(&iiq.build_with(&fields)),
+        "No raw image found in IIQ"
+    );
+}
+
+#[test]
+fn invalid_row_offsets_test() {
+    let mut iiq = Iiq::flat(4, 2, 0);
+    iiq.row_offsets.pop();
+    iiq.row_offsets.push(16);
+    assert_eq!(
+        new_demuxer_err(&iiq.build()),
+        "PhaseOneError(InvalidRowOffsets)"
+    );
+}
+
+#[test]
+fn truncated_strip_offsets_test() {
+    let mut iiq = Iiq::flat(4, 2, 0);
+    iiq.height = 0xFFFF;
+    assert_eq!(
+        new_demuxer_err(&iiq.build()),
+        "The IIQ strip offset table is truncated"
+    );
+}
@@ -0,0 +1,110 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_parsers_tiffparser::tiffparser::TiffParser;
+
+use super::{Field, Iiq, IiqDemuxer, new_demuxer_err, parse_cameras};
+use crate::iiq_demuxer::{IIQ_BLACK_LEVEL, IIQ_WB_COEFFS};
+
+macro_rules! with_demuxer {
+    ($iiq:expr, |$demuxer:ident| $body:block) => {{
+        let input = $iiq.build();
+        let root = TiffParser::parse(&input).unwrap();
+        let cameras = parse_cameras();
+        let ($demuxer, _) = IiqDemuxer::new(
+            &root,
+            &cameras,
+            DecodeableCamera::new_unless_unsupported,
+        )
+        .unwrap();
+        $body
+    }};
+}
+
+fn wb_coeffs(coeffs: &[f32]) -> (u32, Field) {
+    let data = coeffs.iter().flat_map(|coeff| coeff.to_le_bytes());
+    (IIQ_WB_COEFFS, Field::Data(data.collect()))
+}
+
+#[test]
+fn camera_from_cameras_xml_test() {
+    with_demuxer!(Iiq::flat(4, 2, 0), |demuxer| {
+        assert_eq!(demuxer.make(), "Phase One A/S");
+        assert_eq!(demuxer.model(), "IQ0");
+        assert_eq!(demuxer.mode(), None);
+        assert_eq!(demuxer.canonical_make(), "Phase One");
+        assert_eq!(demuxer.canonical_id(), "Phase One IQ0");
+        assert_eq!(demuxer.iso_speed(), Some(100));
+        assert_eq!(demuxer.blacklevel(), Some(64));
+        assert_eq!(demuxer.whitelevel(), Some(16383));
+        assert!(demuxer.is_cfa());
+    });
+}
+
+#[test]
+fn black_level_test() {
+    // The black level is given in 16-bit units.
+    let mut iiq = Iiq::flat(4, 2, 0);
+    iiq.fields.push((IIQ_BLACK_LEVEL, Field::Value(1030)));
+    with_demuxer!(iiq, |demuxer| {
+        assert_eq!(demuxer.blacklevel(), Some(257));
+    });
+}
+
+#[test]
+fn wb_coeffs_test() {
+    let mut iiq = Iiq::flat(4, 2, 0);
+    iiq.fields.push(wb_coeffs(&[2.0, 1.0, 1.5]));
+    with_demuxer!(iiq, |demuxer| {
+        let [red, green, blue, fourth] = demuxer.wb_coeffs().unwrap();
+        assert_eq!(
+            [red, green, blue].map(f32::to_bits),
+            [2.0_f32, 1.0, 1.5].map(f32::to_bits)
+        );
+        assert!(fourth.is_nan());
+    });
+    let mut short = Iiq::flat(4, 2, 0);
+    short.fields.push(wb_coeffs(&[2.0, 1.0]));
+    with_demuxer!(short, |demuxer| {
+        assert!(demuxer.wb_coeffs().is_none());
+    });
+    with_demuxer!(Iiq::flat(4, 2, 0), |demuxer| {
+        assert!(demuxer.wb_coeffs().is_none());
+    });
+}
+
+#[test]
+fn unknown_camera_test() {
+    let mut unknown = Iiq::flat(4, 2, 0);
+    unknown.model = "IQ1";
+    assert_eq!(
+        new_demuxer_err(&unknown.build()),
+        "Unknown camera: Phase One A/S IQ1"
+    );
+    let mut unsupported = Iiq::flat(4, 2, 0);
+    unsupported.model = "Unsupported";
+    assert_eq!(
+        new_demuxer_err(&unsupported.build()),
+        "This camera is not supported (explicit)"
+    );
+}
+
+#[test]
+fn not_iiq_test() {
+    let mut iiq = Iiq::flat(4, 2, 0);
+    iiq.magic = b"MMMM";
+    let input = iiq.build();
+    let root = TiffParser::parse(&input).unwrap();
+    assert!(!IiqDemuxer::is_iiq(&root));
+    assert_eq!(new_demuxer_err(&input), "Not an IIQ file");
+}
+
+#[test]
+fn truncated_directory_test() {
+    // The offset of the directory, which follows the TIFF and IIQ magics.
+    let mut input = Iiq::flat(4, 2, 0).build();
+    input
+        .get_mut(16..20)
+        .unwrap()
+        .copy_from_slice(&0xFFFF_FFF0_u32.to_le_bytes());
+    assert_eq!(new_demuxer_err(&input), "The IIQ directory is truncated");
+}
@@ -0,0 +1,233 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::Cameras;
+use rawspeed_metadata_xmlparser::xmlparser;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_std::coord_common::RowIndex;
+use rawspeed_utils_tiffbuilder::tiffbuilder::{TiffBuilder, Value, tag};
+
+use super::{
+    CORRECTION_ENTRY_SIZE, FORMAT_IIQ_L, IIQ_ENTRY_SIZE, IIQ_FORMAT,
+    IIQ_HEIGHT, IIQ_QUADRANT_MULTIPLIERS, IIQ_RAW_DATA, IIQ_SENSOR_CORRECTION,
+    IIQ_STRIP_OFFSETS, IIQ_WIDTH, IiqDemuxer,
+};
+
+const CAMERAS: &str = "
+    <Cameras>
+        <Camera make=\"Phase One A/S\" model=\"IQ0\">
+            <ID make=\"Phase One\" model=\"IQ0\">Phase One IQ0</ID>
+            <CFA width=\"2\" height=\"2\">
+                <Color x=\"0\" y=\"0\">RED</Color>
+                <Color x=\"1\" y=\"0\">GREEN</Color>
+                <Color x=\"0\" y=\"1\">GREEN</Color>
+                <Color x=\"1\" y=\"1\">BLUE</Color>
+            </CFA>
+            <Sensor black=\"64\" white=\"16383\"/>
+        </Camera>
+        <Camera make=\"Phase One A/S\" model=\"Unsupported\" supported=\"no\">
+        </Camera>
+    </Cameras>";
+
+/// A directory field: either a value, or data stored elsewhere.
+#[derive(Debug, Clone)]
+enum Field {
+    Value(u32),
+    Data(Vec<u8>),
+}
+
+fn to_u32(val: usize) -> u32 {
+    u32::try_from(val).unwrap()
+}
+
+/// A header, with the offset of the directory at its end, then the data
+/// of the fields, then the directory itself.
+fn directory(
+    magic: &[u8],
+    fields: &[(u32, Field)],
+    entry_size: usize,
+) -> Vec<u8> {
+    let mut out = magic.to_vec();
+    out.extend([0; 4]);
+    let mut entries = vec![];
+    for (tag, field) in fields {
+        match field {
+            Field::Value(val) => entries.push((*tag, 4, *val)),
+            Field::Data(data) => {
+                entries.push((*tag, to_u32(data.len()), to_u32(out.len())));
+                out.extend(data);
+            }
+        }
+    }
+    let offset = to_u32(out.len()).to_le_bytes();
+    out.get_mut(8..12).unwrap().copy_from_slice(&offset);
+    out.extend(to_u32(entries.len()).to_le_bytes());
+    out.extend([0; 4]);
+    for (tag, len, data) in entries {
+        out.extend(tag.to_le_bytes());
+        if entry_size == IIQ_ENTRY_SIZE {
+            out.extend(1_u32.to_le_bytes());
+        }
+        out.extend(len.to_le_bytes());
+        out.extend(data.to_le_bytes());
+    }
+    out
+}
+
+fn le_bytes<const N: usize>(
+    values: impl IntoIterator<Item = [u8; N]>,
+) -> Vec<u8> {
+    values.into_iter().flatten().collect()
+}
+
+/// The rows of fewer than eight pixels are stored verbatim, as 16-bit
+/// samples, packed into 32-bit little-endian words.
+fn verbatim(rows: &[Vec<u16>]) -> (Vec<u8>, Vec<u32>) {
+    let mut data = vec![];
+    let mut offsets = vec![];
+    for row in rows {
+        assert!(row.len() < 8);
+        offsets.push(to_u32(data.len()));
+        let mut samples = row.clone();
+        samples.resize(row.len().next_multiple_of(2), 0);
+        for pair in samples.chunks(2) {
+            let word = (u32::from(*pair.first().unwrap()) << 16)
+                | u32::from(*pair.get(1).unwrap());
+            data.extend(word.to_le_bytes());
+        }
+    }
+    (data, offsets)
+}
+
+#[derive(Debug)]
+struct Iiq {
+    magic: &'static [u8],
+    model: &'static str,
+    width: u32,
+    height: u32,
+    format: u32,
+    raw: Vec<u8>,
+    row_offsets: Vec<u32>,
+    fields: Vec<(u32, Field)>,
+    corrections: Vec<(u32, Field)>,
+}
+
+impl Iiq {
+    fn new(rows: &[Vec<u16>]) -> Self {
+        let (raw, row_offsets) = verbatim(rows);
+        Self {
+            magic: b"IIII",
+            model: "IQ0",
+            width: to_u32(rows.first().unwrap().len()),
+            height: to_u32(rows.len()),
+            format: FORMAT_IIQ_L,
+            raw,
+            row_offsets,
+            fields: vec![],
+            corrections: vec![],
+        }
+    }
+
+    fn flat(width: usize, height: usize, value: u16) -> Self {
+        Self::new(&vec![vec![value; width]; height])
+    }
+
+    fn fields(&self) -> Vec<(u32, Field)> {
+        let mut fields = vec![
+            (IIQ_FORMAT, Field::Value(self.format)),
+            (IIQ_WIDTH, Field::Value(self.width)),
+            (IIQ_HEIGHT, Field::Value(self.height)),
+            (IIQ_RAW_DATA, Field::Data(self.raw.clone())),
+            (
+                IIQ_STRIP_OFFSETS,
+                Field::Data(le_bytes(
+                    self.row_offsets.iter().map(|o| o.to_le_bytes()),
+                )),
+            ),
+        ];
+        fields.extend(self.fields.iter().cloned());
+        if !self.corrections.is_empty() {
+            let data =
+                directory(&[0; 8], &self.corrections, CORRECTION_ENTRY_SIZE);
+            fields.push((IIQ_SENSOR_CORRECTION, Field::Data(data)));
+        }
+        fields
+    }
+
+    fn build(&self) -> Vec<u8> {
+        self.build_with(&self.fields())
+    }
+
+    /// The IIQ data follows the TIFF header, and the TIFF IFDs follow it.
+    fn build_with(&self, fields: &[(u32, Field)]) -> Vec<u8> {
+        let mut magic = self.magic.to_vec();
+        magic.extend(b"RAW ");
+        let iiq = directory(&magic, fields, IIQ_ENTRY_SIZE);
+        let mut prefix = b"II*\0".to_vec();
+        prefix.extend(to_u32(8 + iiq.len()).to_le_bytes());
+        prefix.extend(iiq);
+        let mut builder = TiffBuilder::new(Endianness::Little);
+        builder.ifd(0).extend([
+            tag(TiffTag::MAKE, Value::Ascii("Phase One A/S")),
+            tag(TiffTag::MODEL, Value::Ascii(self.model)),
+            tag(TiffTag::EXIF_IFD_POINTER, Value::IFDOffset(1)),
+        ]);
+        builder
+            .ifd(1)
+            .push(tag(TiffTag::ISO_SPEED_RATINGS, Value::Short(vec![100])));
+        builder.build_after(&prefix)
+    }
+}
+
+/// The quadrant multipliers: the shared abscissas, then the multipliers of
+/// each quadrant.
+fn quadrant_multipliers(xs: [u32; 7], multipliers: [u32; 4]) -> Field {
+    let values = xs
+        .into_iter()
+        .chain(multipliers.into_iter().flat_map(|mul| [mul; 7]));
+    Field::Data(le_bytes(values.map(u32::to_le_bytes)))
+}
+
+fn quadrant_field(multipliers: [u32; 4]) -> (u32, Field) {
+    (
+        IIQ_QUADRANT_MULTIPLIERS,
+        quadrant_multipliers(
+            [1000, 2000, 3000, 4000, 5000, 6000, 7000],
+            multipliers,
+        ),
+    )
+}
+
+fn parse_cameras() -> Cameras<'static> {
+    xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap()
+}
+
+fn new_demuxer_err(input: &[u8]) -> String {
+    let root = TiffParser::parse(input).unwrap();
+    let cameras = parse_cameras();
+    IiqDemuxer::new(&root, &cameras, DecodeableCamera::new_unless_unsupported)
+        .unwrap_err()
+}
+
+fn decode(iiq: &Iiq) -> Result<Vec<Vec<u16>>, String> {
+    let input = iiq.build();
+    let root = TiffParser::parse(&input).unwrap();
+    let cameras = parse_cameras();
+    let (demuxer, request) = IiqDemuxer::new(
+        &root,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )?;
+    let mut output_buf = request.fulfill().unwrap();
+    let mut image = output_buf.get_mut();
+    demuxer.decode(&mut image).map_err(|err| err.to_string())?;
+    let output = image.as_u16().unwrap();
+    Ok((0..output.num_rows().get())
+        .map(|row| output.get_row(RowIndex::new(row)).unwrap().to_vec())
+        .collect())
+}
+
+#[cfg(test)]
+mod data;
+#[cfg(test)]
+mod metadata;
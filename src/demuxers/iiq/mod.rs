@@ -0,0 +1 @@
+pub mod iiq_demuxer;
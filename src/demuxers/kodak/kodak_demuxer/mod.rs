@@ -0,0 +1,446 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_bitstream_bytestream_decoder::bytestreamer::ByteStreamer;
+use rawspeed_codecs_kodak::kodak::KodakDecompressor;
+use rawspeed_codecs_packed_decoder::packed_decoder::Unpacker;
+use rawspeed_demuxers_common::{
+    camera_metadata::CameraMetadata,
+    tiff_utils::{
+        get_root_string, get_strip, get_u32, get_usize, non_zero, tiff_err,
+    },
+};
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::{
+    DataType, ImageRefMut, ProcurementRequest, RawDemuxer, RawDemuxerError,
+};
+use rawspeed_memory_nd_slice_procurement::ndsliceprocurement::NDSliceProcurementRequest;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::{
+    Cameras, Supported, blackareas::BlackArea,
+};
+use rawspeed_metadata_colorfilterarray::colorfilterarray::ColorVariant;
+use rawspeed_parsers_tiffparser::tiffparser::{
+    TiffEntry, TiffIFD, TiffParser, TiffRootIFD, TiffTag,
+};
+use rawspeed_std::coord_common::{
+    Coord2D, Dimensions2D, RowCount, RowLength, RowPitch,
+};
+use rawspeed_std_ndslice::{
+    array2dref::Array2DRef, offsetarray2dref::OffsetArray2DRef,
+};
+
+const COMPRESSION_KODAK_65000: u32 = 65000;
+
+/// The bits per sample of KDC files.
+const KDC_BITS: u32 = 12;
+
+/// The data offset of KDC files is the sum of two of these values.
+const KDC_OFFSET_MIN_COUNT: usize = 13;
+
+/// The DCR white balance set in software: the RGB levels, relative to
+/// this unity, follow the first 40 bytes of a 72-byte record.
+const WB_INFO_LEN: usize = 72;
+const WB_INFO_LEVELS_OFFSET: usize = 40;
+const WB_UNITY: f32 = 2048.0;
+
+type T = u16;
+
+/// The Kodak IFD that the given tag points to, whose offsets are relative
+/// to the start of the file.
+fn parse_kodak_ifd<'a>(
+    root: &TiffRootIFD<'a>,
+    tag: TiffTag,
+) -> Result<Option<TiffRootIFD<'a>>, String> {
+    let Some(entry) = root.get_entry_recursive(tag) else {
+        return Ok(None);
+    };
+    let offset = entry.get_u32(0).map_err(tiff_err)?;
+    TiffParser::parse_ifd_chain(
+        root.input(),
+        root.endianness(),
+        offset.try_into().unwrap(),
+    )
+    .map(Some)
+    .map_err(tiff_err)
+}
+
+/// The Kodak IFD has the ISO of the shot even when the Exif one is
+/// missing.
+fn parse_iso_speed(
+    root: &TiffRootIFD<'_>,
+    kodak_ifd: Option<&TiffRootIFD<'_>>,
+) -> Option<u32> {
+    kodak_ifd
+        .and_then(|kodak_ifd| kodak_ifd.get_entry_recursive(TiffTag::KODAK_ISO))
+        .or_else(|| root.get_entry_recursive(TiffTag::ISO_SPEED_RATINGS))
+        .map(TiffEntry::get_u32s)
+        .and_then(Result::ok)
+        .and_then(|isos| isos.first().copied())
+        .filter(|iso| *iso != 0)
+}
+
+/// The curve that maps the DCR samples back to linear values.
+fn parse_curve(
+    kodak_ifd: &TiffRootIFD<'_>,
+) -> Result<Option<Vec<u16>>, String> {
+    kodak_ifd
+        .get_entry_recursive(TiffTag::KODAK_LINEARIZATION)
+        .map(TiffEntry::get_u16s)
+        .transpose()
+        .map_err(tiff_err)
+}
+
+#[expect(clippy::float_arithmetic)]
+fn parse_dcr_wb_coeffs(kodak_ifd: &TiffRootIFD<'_>) -> Option<[f32; 4]> {
+    let entry = kodak_ifd.get_entry_recursive(TiffTag::KODAK_WB_INFO)?;
+    let data = entry.data();
+    if data.len() != WB_INFO_LEN {
+        return None;
+    }
+    let mut coeffs = [f32::NAN; 4];
+    for (index, coeff) in coeffs.iter_mut().take(3).enumerate() {
+        let offset = WB_INFO_LEVELS_OFFSET + index * size_of::<u16>();
+        let bytes = data.get(offset..offset + size_of::<u16>())?;
+        let level: u16 = ByteStreamer::new(bytes, entry.endianness()).read();
+        if level == 0 {
+            return None;
+        }
+        *coeff = WB_UNITY / f32::from(level);
+    }
+    Some(coeffs)
+}
+
+fn parse_kdc_wb_coeffs(
+    kodak_ifd: &TiffRootIFD<'_>,
+) -> Result<Option<[f32; 4]>, String> {
+    let Some(entry) = kodak_ifd.get_entry_recursive(TiffTag::KODAK_KDC_WB)
+    else {
+        return Ok(None);
+    };
+    if entry.count() != 3 {
+        return Ok(None);
+    }
+    let [red, green, blue] =
+        [0, 1, 2].map(|index| entry.get_f32(index).map_err(tiff_err));
+    Ok(Some([red?, green?, blue?, f32::NAN]))
+}
+
+#[derive(Debug)]
+enum Format<'a> {
+    Dcr(KodakDecompressor<'a>),
+    Kdc(Array2DRef<'a, u8>),
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct KodakDemuxer<'a> {
+    metadata: CameraMetadata<'a>,
+    format: Format<'a>,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    wb_coeffs: Option<[f32; 4]>,
+}
+
+impl<'a> KodakDemuxer<'a> {
+    fn parse_dims(
+        ifd: &TiffIFD<'_>,
+        width_tag: TiffTag,
+        height_tag: TiffTag,
+    ) -> Result<Dimensions2D<core::num::NonZero<usize>>, String> {
+        let width = get_usize(ifd, width_tag)?.unwrap_or(0);
+        let height = get_usize(ifd, height_tag)?.unwrap_or(0);
+        Ok(Dimensions2D::new(
+            RowLength::new(non_zero(width, "width")?),
+            RowCount::new(non_zero(height, "height")?),
+        ))
+    }
+
+    fn parse_dcr(
+        root: &TiffRootIFD<'a>,
+        ifd: &TiffIFD<'_>,
+        kodak_ifd: Option<&TiffRootIFD<'_>>,
+    ) -> Result<(Format<'a>, Dimensions2D<core::num::NonZero<usize>>), String>
+    {
+        let dims =
+            Self::parse_dims(ifd, TiffTag::IMAGE_WIDTH, TiffTag::IMAGE_LENGTH)?;
+        let curve = kodak_ifd.map(parse_curve).transpose()?.flatten();
+        let strip = get_strip(root.input(), ifd)?;
+        let decompressor = KodakDecompressor::new(strip, dims, curve)
+            .map_err(|err| err.to_string())?;
+        Ok((Format::Dcr(decompressor), dims))
+    }
+
+    /// The dimensions are those of the sensor, in the Kodak IFD, and the
+    /// samples are bit-packed, most significant bit first.
+    fn parse_kdc(
+        root: &TiffRootIFD<'a>,
+        kodak_ifd: &TiffRootIFD<'_>,
+    ) -> Result<(Format<'a>, Dimensions2D<core::num::NonZero<usize>>), String>
+    {
+        let ifd = kodak_ifd
+            .get_ifds_with_tag(TiffTag::KODAK_KDC_SENSOR_WIDTH)
+            .into_iter()
+            .next()
+            .ok_or("The KDC sensor size is missing")?;
+        let dims = Self::parse_dims(
+            ifd,
+            TiffTag::KODAK_KDC_SENSOR_WIDTH,
+            TiffTag::KODAK_KDC_SENSOR_HEIGHT,
+        )?;
+        let offsets = root
+            .get_entry_recursive(TiffTag::KODAK_KDC_OFFSET)
+            .map(TiffEntry::get_u32s)
+            .transpose()
+            .map_err(tiff_err)?
+            .filter(|offsets| offsets.len() >= KDC_OFFSET_MIN_COUNT)
+            .ok_or("The KDC data offset is missing")?;
+        let offset = [4, 12]
+            .map(|index| usize::try_from(*offsets.get(index).unwrap()).unwrap())
+            .into_iter()
+            .try_fold(0_usize, usize::checked_add)
+            .ok_or("Overflow when computing the KDC data offset")?;
+
+        let (width, height) = (dims.row_len().get(), dims.row_count().get());
+        let row_bits = width * usize::try_from(KDC_BITS).unwrap();
+        let bytes_per_row = row_bits / 8;
+        // Rows are read in whole chunks of the bit order.
+        if !row_bits.is_multiple_of(8)
+            || !bytes_per_row.is_multiple_of(BitOrder::MSB.mcu_bytelen())
+        {
+            return Err(format!("Unsupported KDC width: {width}"));
+        }
+        let input = root
+            .input()
+            .get(offset..)
+            .and_then(|input| input.get(..bytes_per_row * height))
+            .ok_or("The KDC data is truncated")?;
+        let bytes_per_row = non_zero(bytes_per_row, "row pitch")?;
+        Ok((
+            Format::Kdc(Array2DRef::new(
+                input,
+                RowLength::new(bytes_per_row),
+                RowPitch::new(bytes_per_row),
+            )),
+            dims,
+        ))
+    }
+
+    /// DCR files store the raw image in an IFD of their own compression,
+    /// whereas KDC files only point to it from their second Kodak IFD.
+    #[inline(never)]
+    pub fn new<F>(
+        root: &TiffRootIFD<'a>,
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<(Self, ProcurementRequest), String>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let dcr_ifd = root
+            .get_ifds_with_tag(TiffTag::COMPRESSION)
+            .into_iter()
+            .find(|ifd| {
+                get_u32(ifd, TiffTag::COMPRESSION)
+                    == Ok(Some(COMPRESSION_KODAK_65000))
+            });
+        let (format, dims, kodak_ifd) = if let Some(ifd) = dcr_ifd {
+            let kodak_ifd = parse_kodak_ifd(root, TiffTag::KODAK_IFD)?;
+            let (format, dims) =
+                Self::parse_dcr(root, ifd, kodak_ifd.as_ref())?;
+            (format, dims, kodak_ifd)
+        } else if let Some(kodak_ifd) =
+            parse_kodak_ifd(root, TiffTag::KODAK_IFD2)?
+        {
+            let (format, dims) = Self::parse_kdc(root, &kodak_ifd)?;
+            (format, dims, Some(kodak_ifd))
+        } else {
+            return Err("No raw image found in DCR or KDC".to_owned());
+        };
+
+        let make = get_root_string(root, TiffTag::MAKE).unwrap_or("");
+        let model = get_root_string(root, TiffTag::MODEL).unwrap_or("");
+        let camera = cameras
+            .find(make, model, None)
+            .ok_or_else(|| format!("Unknown camera: {make} {model}"))?;
+        check_camera_support_fn(camera.supported)?;
+        let iso_speed = parse_iso_speed(root, kodak_ifd.as_ref());
+        let metadata = CameraMetadata::new(camera, make, model, iso_speed);
+
+        let wb_coeffs = match (&format, &kodak_ifd) {
+            (Format::Dcr(_), Some(kodak_ifd)) => parse_dcr_wb_coeffs(kodak_ifd),
+            (Format::Kdc(_), Some(kodak_ifd)) => {
+                parse_kdc_wb_coeffs(kodak_ifd)?
+            }
+            (_, None) => None,
+        };
+        Ok((
+            Self {
+                metadata,
+                format,
+                dims,
+                wb_coeffs,
+            },
+            NDSliceProcurementRequest::<T>::new(dims).into(),
+        ))
+    }
+}
+
+#[expect(clippy::missing_trait_methods)]
+impl RawDemuxer for KodakDemuxer<'_> {
+    #[inline]
+    fn make(&self) -> &str {
+        self.metadata.make
+    }
+
+    #[inline]
+    fn model(&self) -> &str {
+        self.metadata.model
+    }
+
+    #[inline]
+    fn mode(&self) -> Option<&str> {
+        self.metadata.mode()
+    }
+
+    #[inline]
+    fn canonical_make(&self) -> &str {
+        self.metadata.canonical_make()
+    }
+
+    #[inline]
+    fn canonical_model(&self) -> &str {
+        self.metadata.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_alias(&self) -> &str {
+        self.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_id(&self) -> String {
+        self.metadata.canonical_id()
+    }
+
+    #[inline]
+    fn iso_speed(&self) -> Option<u32> {
+        self.metadata.iso_speed
+    }
+
+    #[inline]
+    fn blacklevel(&self) -> Option<u16> {
+        self.metadata.blacklevel()
+    }
+
+    #[inline]
+    fn whitelevel(&self) -> Option<u16> {
+        self.metadata.whitelevel()
+    }
+
+    #[inline]
+    fn blacklevel_separate(&self) -> Option<Array2DRef<'_, i32>> {
+        None
+    }
+
+    #[inline]
+    fn wb_coeffs(&self) -> Option<[f32; 4]> {
+        self.wb_coeffs
+    }
+
+    #[inline]
+    fn colormatrix(&self) -> Option<Array2DRef<'_, i16>> {
+        self.metadata.colormatrix()
+    }
+
+    #[inline]
+    fn is_cfa(&self) -> bool {
+        self.metadata.is_cfa()
+    }
+
+    #[inline]
+    fn cfa(
+        &self,
+        origin: Coord2D,
+    ) -> Option<OffsetArray2DRef<'_, ColorVariant>> {
+        self.metadata.cfa(origin)
+    }
+
+    #[inline]
+    fn bpp(&self) -> usize {
+        let bpc = match self.datatype() {
+            DataType::U16 => size_of::<u16>(),
+            DataType::F32 => size_of::<f32>(),
+            DataType::U32 => size_of::<u32>(),
+            _ => unreachable!(),
+        };
+        bpc.checked_mul(self.cpp()).unwrap()
+    }
+
+    #[inline]
+    fn cpp(&self) -> usize {
+        1
+    }
+
+    #[inline]
+    fn datatype(&self) -> DataType {
+        DataType::U16
+    }
+
+    #[inline]
+    fn dim_uncropped(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        self.dims
+    }
+
+    #[inline]
+    fn dim_cropped(&self) -> Option<Dimensions2D<core::num::NonZero<usize>>> {
+        self.metadata.dim_cropped(self.dim_uncropped())
+    }
+
+    #[inline]
+    fn crop_offset(&self) -> Option<Coord2D> {
+        self.metadata.crop_offset()
+    }
+
+    #[inline]
+    fn black_areas(&self) -> Option<&[BlackArea]> {
+        self.metadata.black_areas()
+    }
+
+    #[inline]
+    fn fuji_rotation_pos(&self) -> Option<u32> {
+        None
+    }
+
+    #[inline]
+    fn pixel_aspect_ratio(&self) -> Option<f64> {
+        None
+    }
+
+    #[inline]
+    fn bad_pixel_positions(&self) -> Vec<Coord2D> {
+        vec![]
+    }
+
+    #[inline(never)]
+    fn decode(
+        &self,
+        output: &mut ImageRefMut<'_>,
+    ) -> Result<(), RawDemuxerError> {
+        let output = output.as_u16()?;
+        if output.dims() != self.dims {
+            return Err(RawDemuxerError::DecoderError(
+                "Output buffer dimensions differ from expected".to_owned(),
+            ));
+        }
+        match &self.format {
+            Format::Dcr(decompressor) => decompressor
+                .decode(output)
+                .map_err(|err| RawDemuxerError::DecoderError(err.to_string())),
+            Format::Kdc(input) => {
+                Unpacker::new(*input, BitOrder::MSB, KDC_BITS, output).unpack();
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,99 @@
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_parsers_tiffparser::tiffparser::TiffTag;
+use rawspeed_utils_tiffbuilder::tiffbuilder::{Value, tag};
+
+use super::{Dcr, Kdc, compress, decode, new_demuxer_err, rows};
+
+#[test]
+fn dcr_test() {
+    let rows = rows(8, 4);
+    for endianness in [Endianness::Little, Endianness::Big] {
+        let mut dcr = Dcr::new(&rows);
+        dcr.endianness = endianness;
+        assert_eq!(decode(&dcr.build()), Ok(rows.clone()));
+    }
+}
+
+#[test]
+fn dcr_curve_test() {
+    let mut dcr = Dcr::new(&[vec![0, 1, 2, 3, 4, 5, 6, 7]]);
+    dcr.kodak_ifd = Some(vec![tag(
+        TiffTag::KODAK_LINEARIZATION,
+        Value::Short(vec![10, 20, 30, 40]),
+    )]);
+    assert_eq!(
+        decode(&dcr.build()),
+        Ok(vec![vec![10, 20, 30, 40, 40, 40, 40, 40]])
+    );
+}
+
+#[test]
+fn dcr_invalid_data_test() {
+    let mut dcr = Dcr::new(&rows(8, 4));
+    dcr.width = 6;
+    assert_eq!(new_demuxer_err(&dcr.build()), "KodakError(InvalidWidth)");
+    let mut truncated = Dcr::new(&rows(8, 4));
+    truncated.data = compress(&rows(8, 3));
+    assert_eq!(
+        decode(&truncated.build()),
+        Err("RawDemuxerError(DecoderError(KodakError(TruncatedData)))"
+            .to_owned())
+    );
+}
+
+#[test]
+fn kdc_test() {
+    let rows = rows(8, 3);
+    assert_eq!(decode(&Kdc::new(&rows).build()), Ok(rows));
+}
+
+#[test]
+fn kdc_odd_row_pitch_test() {
+    let rows = rows(4, 3);
+    assert_eq!(decode(&Kdc::new(&rows).build()), Ok(rows));
+}
+
+#[test]
+fn kdc_unsupported_width_test() {
+    assert_eq!(
+        new_demuxer_err(&Kdc::new(&rows(3, 3)).build()),
+        "Unsupported KDC width: 3"
+    );
+}
+
+#[test]
+fn kdc_truncated_test() {
+    let mut builder = Kdc::new(&rows(8, 3)).builder();
+    builder.set(TiffTag::KODAK_KDC_SENSOR_HEIGHT, &Value::Long(vec![4]));
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "The KDC data is truncated"
+    );
+}
+
+#[test]
+fn kdc_missing_entries_test() {
+    let kdc = Kdc::new(&rows(8, 3));
+    let mut no_offset = kdc.builder();
+    no_offset.remove(TiffTag::KODAK_KDC_OFFSET);
+    assert_eq!(
+        new_demuxer_err(&no_offset.build()),
+        "The KDC data offset is missing"
+    );
+    let mut no_size = kdc.builder();
+    no_size.remove(TiffTag::KODAK_KDC_SENSOR_WIDTH);
+    assert_eq!(
+        new_demuxer_err(&no_size.build()),
+        "The KDC sensor size is missing"
+    );
+}
+
+#[test]
+fn no_raw_image_test() {
+    let mut builder = Dcr::new(&rows(8, 4)).builder();
+    builder.remove(TiffTag::COMPRESSION);
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "No raw image found in DCR or KDC"
+    );
+}
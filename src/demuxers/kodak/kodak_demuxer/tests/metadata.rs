@@ -0,0 +1,119 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_utils_tiffbuilder::tiffbuilder::{Value, tag};
+
+use super::{
+    Dcr, Kdc, KodakDemuxer, new_demuxer_err, parse_cameras, rows, wb_info,
+};
+
+macro_rules! with_demuxer {
+    ($input:expr, |$demuxer:ident| $body:block) => {{
+        let input = $input;
+        let root = TiffParser::parse(&input).unwrap();
+        let cameras = parse_cameras();
+        let ($demuxer, _) = KodakDemuxer::new(
+            &root,
+            &cameras,
+            DecodeableCamera::new_unless_unsupported,
+        )
+        .unwrap();
+        $body
+    }};
+}
+
+fn assert_wb_coeffs(wb_coeffs: Option<[f32; 4]>, expected: [f32; 3]) {
+    let [red, green, blue, fourth] = wb_coeffs.unwrap();
+    assert_eq!(
+        [red, green, blue].map(f32::to_bits),
+        expected.map(f32::to_bits)
+    );
+    assert!(fourth.is_nan());
+}
+
+#[test]
+fn dcr_camera_from_cameras_xml_test() {
+    with_demuxer!(Dcr::new(&rows(8, 2)).build(), |demuxer| {
+        assert_eq!(demuxer.make(), "Kodak");
+        assert_eq!(demuxer.model(), "DCS Pro 14N");
+        assert_eq!(demuxer.canonical_id(), "Kodak DCS Pro 14N");
+        assert_eq!(demuxer.iso_speed(), Some(100));
+        assert_eq!(demuxer.whitelevel(), Some(4095));
+        assert!(demuxer.is_cfa());
+        assert!(demuxer.wb_coeffs().is_none());
+    });
+}
+
+#[test]
+fn dcr_kodak_ifd_test() {
+    for endianness in [Endianness::Little, Endianness::Big] {
+        let mut dcr = Dcr::new(&rows(8, 2));
+        dcr.endianness = endianness;
+        dcr.kodak_ifd = Some(vec![
+            tag(TiffTag::KODAK_ISO, Value::Long(vec![400])),
+            tag(
+                TiffTag::KODAK_WB_INFO,
+                Value::Undefined(wb_info(endianness, [1024, 2048, 512])),
+            ),
+        ]);
+        with_demuxer!(dcr.build(), |demuxer| {
+            assert_eq!(demuxer.iso_speed(), Some(400));
+            assert_wb_coeffs(demuxer.wb_coeffs(), [2.0, 1.0, 4.0]);
+        });
+    }
+}
+
+#[test]
+fn dcr_invalid_wb_info_test() {
+    let mut dcr = Dcr::new(&rows(8, 2));
+    dcr.kodak_ifd = Some(vec![tag(
+        TiffTag::KODAK_WB_INFO,
+        Value::Undefined(wb_info(Endianness::Little, [1024, 0, 512])),
+    )]);
+    with_demuxer!(dcr.build(), |demuxer| {
+        assert!(demuxer.wb_coeffs().is_none());
+    });
+    let mut short = Dcr::new(&rows(8, 2));
+    short.kodak_ifd = Some(vec![tag(
+        TiffTag::KODAK_WB_INFO,
+        Value::Undefined(vec![1; 40]),
+    )]);
+    with_demuxer!(short.build(), |demuxer| {
+        assert!(demuxer.wb_coeffs().is_none());
+    });
+}
+
+#[test]
+fn kdc_metadata_test() {
+    let mut kdc = Kdc::new(&rows(8, 2));
+    with_demuxer!(kdc.build(), |demuxer| {
+        assert_eq!(demuxer.make(), "EASTMAN KODAK COMPANY");
+        assert_eq!(demuxer.canonical_id(), "Kodak EasyShare Z1015 IS");
+        assert_eq!(demuxer.iso_speed(), Some(80));
+        assert!(demuxer.wb_coeffs().is_none());
+    });
+    kdc.kodak_ifd.push(tag(
+        TiffTag::KODAK_KDC_WB,
+        Value::Rational(vec![(3, 2), (1, 1), (5, 4)]),
+    ));
+    with_demuxer!(kdc.build(), |demuxer| {
+        assert_wb_coeffs(demuxer.wb_coeffs(), [1.5, 1.0, 1.25]);
+    });
+}
+
+#[test]
+fn unknown_camera_test() {
+    let mut dcr = Dcr::new(&rows(8, 2));
+    dcr.model = "DCS 000";
+    assert_eq!(
+        new_demuxer_err(&dcr.build()),
+        "Unknown camera: Kodak DCS 000"
+    );
+    let mut unsupported = Dcr::new(&rows(8, 2));
+    unsupported.model = "Unsupported";
+    assert_eq!(
+        new_demuxer_err(&unsupported.build()),
+        "This camera is not supported (explicit)"
+    );
+}
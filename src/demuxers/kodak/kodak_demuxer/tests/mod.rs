@@ -0,0 +1,272 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::Cameras;
+use rawspeed_metadata_xmlparser::xmlparser;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_std::coord_common::RowIndex;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::pack;
+use rawspeed_utils_tiffbuilder::tiffbuilder::{
+    Entries, TiffBuilder, Value, tag,
+};
+
+use super::{COMPRESSION_KODAK_65000, KodakDemuxer};
+
+const CAMERAS: &str = "
+    <Cameras>
+        <Camera make=\"Kodak\" model=\"DCS Pro 14N\">
+            <ID make=\"Kodak\" model=\"DCS Pro 14N\">Kodak DCS Pro 14N</ID>
+            <CFA width=\"2\" height=\"2\">
+                <Color x=\"0\" y=\"0\">RED</Color>
+                <Color x=\"1\" y=\"0\">GREEN</Color>
+                <Color x=\"0\" y=\"1\">GREEN</Color>
+                <Color x=\"1\" y=\"1\">BLUE</Color>
+            </CFA>
+            <Sensor black=\"0\" white=\"4095\"/>
+        </Camera>
+        <Camera make=\"EASTMAN KODAK COMPANY\"
+                model=\"KODAK EASYSHARE Z1015 IS DIGITAL CAMERA\">
+            <ID make=\"Kodak\" model=\"EasyShare Z1015 IS\">Kodak EasyShare Z1015 IS</ID>
+            <Sensor black=\"0\" white=\"4095\"/>
+        </Camera>
+        <Camera make=\"Kodak\" model=\"Unsupported\" supported=\"no\">
+        </Camera>
+    </Cameras>";
+
+const DCR_MODEL: &str = "DCS Pro 14N";
+const KDC_MODEL: &str = "KODAK EASYSHARE Z1015 IS DIGITAL CAMERA";
+
+fn diff_len(diff: i32) -> u32 {
+    32 - diff.unsigned_abs().leading_zeros()
+}
+
+/// The lengths of the differences of a segment, then their bits, in
+/// as many 16-bit words as the decoder reads.
+fn encode_segment(diffs: &[i32]) -> Vec<u8> {
+    let lens: Vec<u32> = diffs.iter().map(|diff| diff_len(*diff)).collect();
+    let mut out: Vec<u8> = lens
+        .chunks_exact(2)
+        .map(|pair| {
+            let [low, high] = [pair.first(), pair.last()]
+                .map(|len| u8::try_from(*len.unwrap()).unwrap());
+            low | (high << 4)
+        })
+        .collect();
+    let (mut fill_level, mut num_words) = if diffs.len() % 8 == 4 {
+        (16, 1)
+    } else {
+        (0, 0)
+    };
+    let mut words = vec![];
+    let mut cache = 0_u64;
+    let mut cache_len = 0;
+    for (diff, len) in diffs.iter().zip(&lens) {
+        if fill_level < *len {
+            fill_level += 32;
+            num_words += 2;
+        }
+        fill_level -= len;
+        let value = if *diff < 0 {
+            *diff + (1 << len) - 1
+        } else {
+            *diff
+        };
+        cache |= u64::from(u32::try_from(value).unwrap()) << cache_len;
+        cache_len += len;
+        while cache_len >= 16 {
+            words.push(u16::try_from(cache & 0xFFFF).unwrap());
+            cache >>= 16;
+            cache_len -= 16;
+        }
+    }
+    words.push(u16::try_from(cache).unwrap());
+    words.resize(num_words, 0);
+    for word in words {
+        out.extend(word.to_le_bytes().iter().rev());
+    }
+    out
+}
+
+/// Encodes rows of at most one segment with Kodak's 65000 compression.
+fn compress(rows: &[Vec<u16>]) -> Vec<u8> {
+    let mut out = vec![];
+    for row in rows {
+        let mut preds = [0; 2];
+        let diffs: Vec<i32> = row
+            .iter()
+            .enumerate()
+            .map(|(index, sample)| {
+                let pred = preds.get_mut(index % 2).unwrap();
+                let diff = i32::from(*sample) - *pred;
+                *pred = i32::from(*sample);
+                diff
+            })
+            .collect();
+        out.extend(encode_segment(&diffs));
+    }
+    out
+}
+
+fn rows(width: usize, height: usize) -> Vec<Vec<u16>> {
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| {
+                    u16::try_from((row * 977 + col * 313 + 5) % 0x1000).unwrap()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The record of the white balance set in software, with the given
+/// levels.
+fn wb_info(endianness: Endianness, levels: [u16; 3]) -> Vec<u8> {
+    let mut info = vec![0; 40];
+    for level in levels {
+        match endianness {
+            Endianness::Little => info.extend(level.to_le_bytes()),
+            Endianness::Big => info.extend(level.to_le_bytes().iter().rev()),
+        }
+    }
+    info.resize(72, 0);
+    info
+}
+
+#[derive(Debug)]
+struct Dcr {
+    endianness: Endianness,
+    model: &'static str,
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+    kodak_ifd: Option<Entries>,
+}
+
+impl Dcr {
+    fn new(rows: &[Vec<u16>]) -> Self {
+        Self {
+            endianness: Endianness::Little,
+            model: DCR_MODEL,
+            width: u32::try_from(rows.first().unwrap().len()).unwrap(),
+            height: u32::try_from(rows.len()).unwrap(),
+            data: compress(rows),
+            kodak_ifd: None,
+        }
+    }
+
+    fn builder(&self) -> TiffBuilder {
+        let compression = u16::try_from(COMPRESSION_KODAK_65000).unwrap();
+        let mut builder = TiffBuilder::new(self.endianness);
+        builder.ifd(0).extend([
+            tag(TiffTag::MAKE, Value::Ascii("Kodak")),
+            tag(TiffTag::MODEL, Value::Ascii(self.model)),
+            tag(TiffTag::IMAGE_WIDTH, Value::Long(vec![self.width])),
+            tag(TiffTag::IMAGE_LENGTH, Value::Long(vec![self.height])),
+            tag(TiffTag::COMPRESSION, Value::Short(vec![compression])),
+            tag(TiffTag::STRIP_OFFSETS, Value::BlobOffsets(vec![0])),
+            tag(TiffTag::STRIP_BYTE_COUNTS, Value::BlobSizes(vec![0])),
+            tag(TiffTag::EXIF_IFD_POINTER, Value::IFDOffset(1)),
+        ]);
+        builder
+            .ifd(1)
+            .push(tag(TiffTag::ISO_SPEED_RATINGS, Value::Short(vec![100])));
+        if let Some(kodak_ifd) = &self.kodak_ifd {
+            builder
+                .ifd(0)
+                .push(tag(TiffTag::KODAK_IFD, Value::IFDOffset(2)));
+            builder.ifd(2).clone_from(kodak_ifd);
+        }
+        builder.blobs = vec![self.data.clone()];
+        builder
+    }
+
+    fn build(&self) -> Vec<u8> {
+        self.builder().build()
+    }
+}
+
+#[derive(Debug)]
+struct Kdc {
+    data: Vec<u8>,
+    kodak_ifd: Entries,
+}
+
+impl Kdc {
+    fn new(rows: &[Vec<u16>]) -> Self {
+        let width = u32::try_from(rows.first().unwrap().len()).unwrap();
+        let height = u32::try_from(rows.len()).unwrap();
+        Self {
+            data: pack(&rows.concat(), 12, BitOrder::MSB),
+            kodak_ifd: vec![
+                tag(TiffTag::KODAK_KDC_SENSOR_WIDTH, Value::Long(vec![width])),
+                tag(
+                    TiffTag::KODAK_KDC_SENSOR_HEIGHT,
+                    Value::Long(vec![height]),
+                ),
+            ],
+        }
+    }
+
+    /// The data offset is split over the two values it is the sum of,
+    /// once the layout of the file is known.
+    fn builder(&self) -> TiffBuilder {
+        let mut builder = TiffBuilder::new(Endianness::Little);
+        builder.ifd(0).extend([
+            tag(TiffTag::MAKE, Value::Ascii("EASTMAN KODAK COMPANY")),
+            tag(TiffTag::MODEL, Value::Ascii(KDC_MODEL)),
+            tag(TiffTag::KODAK_KDC_OFFSET, Value::Long(vec![0; 13])),
+            tag(TiffTag::KODAK_IFD2, Value::IFDOffset(1)),
+            tag(TiffTag::EXIF_IFD_POINTER, Value::IFDOffset(2)),
+        ]);
+        builder.ifd(1).clone_from(&self.kodak_ifd);
+        builder
+            .ifd(2)
+            .push(tag(TiffTag::ISO_SPEED_RATINGS, Value::Short(vec![80])));
+        builder.blobs = vec![self.data.clone()];
+        let offset = builder.build().len() - self.data.len();
+        let mut offsets = vec![0; 13];
+        *offsets.get_mut(4).unwrap() = 8;
+        *offsets.get_mut(12).unwrap() = u32::try_from(offset - 8).unwrap();
+        builder.set(TiffTag::KODAK_KDC_OFFSET, &Value::Long(offsets));
+        builder
+    }
+
+    fn build(&self) -> Vec<u8> {
+        self.builder().build()
+    }
+}
+
+fn parse_cameras() -> Cameras<'static> {
+    xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap()
+}
+
+fn new_demuxer_err(input: &[u8]) -> String {
+    let root = TiffParser::parse(input).unwrap();
+    let cameras = parse_cameras();
+    KodakDemuxer::new(&root, &cameras, DecodeableCamera::new_unless_unsupported)
+        .unwrap_err()
+}
+
+fn decode(input: &[u8]) -> Result<Vec<Vec<u16>>, String> {
+    let root = TiffParser::parse(input).unwrap();
+    let cameras = parse_cameras();
+    let (demuxer, request) = KodakDemuxer::new(
+        &root,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )?;
+    let mut output_buf = request.fulfill().unwrap();
+    let mut image = output_buf.get_mut();
+    demuxer.decode(&mut image).map_err(|err| err.to_string())?;
+    let output = image.as_u16().unwrap();
+    Ok((0..output.num_rows().get())
+        .map(|row| output.get_row(RowIndex::new(row)).unwrap().to_vec())
+        .collect())
+}
+
+#[cfg(test)]
+mod data;
+#[cfg(test)]
+mod metadata;
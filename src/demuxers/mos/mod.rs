@@ -0,0 +1 @@
+pub mod mos_demuxer;
@@ -0,0 +1,562 @@
+use rawspeed_bitstream_bytestream_decoder::bytestreamer::ByteStreamer;
+use rawspeed_codecs_ljpeg::ljpeg::LJpegDecoder;
+use rawspeed_demuxers_common::{
+    camera_metadata::CameraMetadata,
+    tiff_utils::{get_root_string, get_u32, get_usize, non_zero, tiff_err},
+};
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::{
+    DataType, ImageRefMut, ProcurementRequest, RawDemuxer, RawDemuxerError,
+};
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_memory_nd_slice_procurement::ndsliceprocurement::NDSliceProcurementRequest;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::{
+    Cameras, Supported, blackareas::BlackArea,
+};
+use rawspeed_metadata_colorfilterarray::colorfilterarray::ColorVariant;
+use rawspeed_parsers_tiffparser::tiffparser::{
+    TiffEntry, TiffIFD, TiffRootIFD, TiffTag,
+};
+use rawspeed_std::coord_common::{
+    Coord2D, Dimensions2D, RowCount, RowIndex, RowLength, RowPitch,
+};
+use rawspeed_std_ndslice::{
+    array2dref::Array2DRef, array2drefmut::Array2DRefMut,
+    offsetarray2dref::OffsetArray2DRef,
+};
+
+const COMPRESSION_NONE: u32 = 1;
+const COMPRESSION_LJPEG: u32 = 7;
+const COMPRESSION_LEAF_LJPEG: u32 = 99;
+
+/// The makes of the backs whose metadata is not recognizable otherwise.
+const MOS_MAKES: [&str; 2] = ["Leaf", "Mamiya"];
+
+const PKTS_MAGIC: &[u8] = b"PKTS";
+/// The magic, a version, the NUL-padded name and the payload size.
+const PKTS_HEADER_LEN: usize = 52;
+const PKTS_NAME_OFFSET: usize = 8;
+const PKTS_NAME_LEN: usize = 40;
+const PKTS_SIZE_OFFSET: usize = 48;
+/// Packets nest, but not deeper than this in practice.
+const MAX_PKTS_DEPTH: usize = 8;
+const NEUTRALS_PKTS_NAME: &[u8] = b"NeutObj_neutrals";
+
+type T = u16;
+
+/// The value of a `tiff:` property of the XMP packet, which is where
+/// some backs store the make and the model instead of the IFDs.
+fn get_xmp_string<'a>(root: &TiffRootIFD<'a>, name: &str) -> Option<&'a str> {
+    let xmp = root.get_entry_recursive(TiffTag::XMP)?.data();
+    let xmp = core::str::from_utf8(xmp).ok()?;
+    let open = format!("<tiff:{name}>");
+    let close = format!("</tiff:{name}>");
+    let (_, rest) = xmp.split_once(open.as_str())?;
+    let (val, _) = rest.split_once(close.as_str())?;
+    Some(val.trim())
+}
+
+fn get_make_model<'a>(root: &TiffRootIFD<'a>) -> (&'a str, &'a str) {
+    let get = |tag, name| {
+        get_root_string(root, tag)
+            .filter(|val| !val.is_empty())
+            .or_else(|| get_xmp_string(root, name))
+            .unwrap_or("")
+    };
+    (get(TiffTag::MAKE, "Make"), get(TiffTag::MODEL, "Model"))
+}
+
+/// Walks the chain of `PKTS` packets, and those nested in their payloads,
+/// for the payload of the packet with the given name.
+fn find_packet<'a>(
+    mut data: &'a [u8],
+    endianness: Endianness,
+    name: &[u8],
+    depth: usize,
+) -> Option<&'a [u8]> {
+    while data.starts_with(PKTS_MAGIC) {
+        let header = data.get(..PKTS_HEADER_LEN)?;
+        let packet_name = header
+            .get(PKTS_NAME_OFFSET..PKTS_NAME_OFFSET + PKTS_NAME_LEN)
+            .unwrap()
+            .split(|byte| *byte == 0)
+            .next()
+            .unwrap();
+        let size: u32 = ByteStreamer::new(
+            header.get(PKTS_SIZE_OFFSET..).unwrap(),
+            endianness,
+        )
+        .read();
+        let (payload, rest) = data
+            .get(PKTS_HEADER_LEN..)
+            .unwrap()
+            .split_at_checked(size.try_into().unwrap())?;
+        if packet_name == name {
+            return Some(payload);
+        }
+        if depth < MAX_PKTS_DEPTH
+            && let Some(found) =
+                find_packet(payload, endianness, name, depth + 1)
+        {
+            return Some(found);
+        }
+        data = rest;
+    }
+    None
+}
+
+/// The neutrals are the white balance as text: the scale they are
+/// relative to, followed by one value per color.
+#[expect(clippy::float_arithmetic, clippy::cast_precision_loss)]
+fn parse_wb_coeffs(root: &TiffRootIFD<'_>) -> Option<[f32; 4]> {
+    let metadata = root.get_entry_recursive(TiffTag::LEAF_METADATA)?.data();
+    let neutrals =
+        find_packet(metadata, root.endianness(), NEUTRALS_PKTS_NAME, 0)?;
+    let neutrals = core::str::from_utf8(neutrals).ok()?;
+    let neutrals: Vec<i32> = neutrals
+        .trim_end_matches('\0')
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .ok()?;
+    let [scale, red, green, blue] = neutrals.get(..4)?.try_into().unwrap();
+    if [scale, red, green, blue].iter().any(|val| *val <= 0) {
+        return None;
+    }
+    let coeff = |val: i32| scale as f32 / val as f32;
+    Some([coeff(red), coeff(green), coeff(blue), f32::NAN])
+}
+
+/// The image is stored as bands of rows spanning its full width, as
+/// either strips or tiles.
+#[derive(Debug)]
+struct Bands<'a> {
+    rows: core::num::NonZero<usize>,
+    data: Vec<&'a [u8]>,
+}
+
+impl<'a> Bands<'a> {
+    fn parse(
+        input: &'a [u8],
+        ifd: &TiffIFD<'a>,
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+    ) -> Result<Self, String> {
+        let (rows, offsets_tag, counts_tag) =
+            if ifd.has_entry(TiffTag::TILE_OFFSETS) {
+                let width = get_usize(ifd, TiffTag::TILE_WIDTH)?;
+                if width != Some(dims.row_len().get()) {
+                    return Err("Unsupported MOS tile width".to_owned());
+                }
+                (
+                    get_usize(ifd, TiffTag::TILE_LENGTH)?,
+                    TiffTag::TILE_OFFSETS,
+                    TiffTag::TILE_BYTE_COUNTS,
+                )
+            } else {
+                (
+                    get_usize(ifd, TiffTag::ROWS_PER_STRIP)?,
+                    TiffTag::STRIP_OFFSETS,
+                    TiffTag::STRIP_BYTE_COUNTS,
+                )
+            };
+        let rows = non_zero(
+            rows.map_or(dims.row_count().get(), |rows| {
+                rows.min(dims.row_count().get())
+            }),
+            "tile/strip height",
+        )?;
+        let offsets = ifd
+            .get_required_entry(offsets_tag)
+            .and_then(TiffEntry::get_u32s)
+            .map_err(tiff_err)?;
+        let counts = ifd
+            .get_required_entry(counts_tag)
+            .and_then(TiffEntry::get_u32s)
+            .map_err(tiff_err)?;
+        if offsets.len() != counts.len()
+            || offsets.len() != dims.row_count().get().div_ceil(rows.get())
+        {
+            return Err("The MOS tile/strip count is invalid".to_owned());
+        }
+
+        let data = offsets
+            .iter()
+            .zip(&counts)
+            .map(|(offset, count)| {
+                let offset: usize = (*offset).try_into().unwrap();
+                let count: usize = (*count).try_into().unwrap();
+                input
+                    .get(offset..)
+                    .and_then(|band| band.get(..count))
+                    .filter(|band| !band.is_empty())
+                    .ok_or_else(|| {
+                        "The MOS tile/strip data is truncated".to_owned()
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { rows, data })
+    }
+
+    /// The number of rows of each band, the last one may be shorter.
+    fn row_counts(
+        &self,
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+    ) -> impl Iterator<Item = usize> {
+        let height = dims.row_count().get();
+        (0..height)
+            .step_by(self.rows.get())
+            .map(move |first_row| self.rows.get().min(height - first_row))
+    }
+}
+
+#[derive(Debug)]
+enum Format<'a> {
+    /// 16-bit samples, in the byte order of the file.
+    Uncompressed(Endianness),
+    /// One lossless JPEG per band.
+    LJpeg(Vec<LJpegDecoder<'a>>),
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct MosDemuxer<'a> {
+    metadata: CameraMetadata<'a>,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    bands: Bands<'a>,
+    format: Format<'a>,
+    wb_coeffs: Option<[f32; 4]>,
+}
+
+impl<'a> MosDemuxer<'a> {
+    /// Leaf and Mamiya backs store their metadata in `PKTS` packets
+    /// within the TIFF structure.
+    #[inline]
+    #[must_use]
+    pub fn is_mos(root: &TiffRootIFD<'_>) -> bool {
+        root.get_entry_recursive(TiffTag::LEAF_METADATA).is_some()
+            || MOS_MAKES.contains(&get_make_model(root).0)
+    }
+
+    fn parse_dims(
+        ifd: &TiffIFD<'_>,
+    ) -> Result<Dimensions2D<core::num::NonZero<usize>>, String> {
+        let width = get_usize(ifd, TiffTag::IMAGE_WIDTH)?.unwrap_or(0);
+        let height = get_usize(ifd, TiffTag::IMAGE_LENGTH)?.unwrap_or(0);
+        Ok(Dimensions2D::new(
+            RowLength::new(non_zero(width, "width")?),
+            RowCount::new(non_zero(height, "height")?),
+        ))
+    }
+
+    /// The raw image is the tiled one, or else the one with a CFA.
+    fn find_raw_ifd<'b>(
+        root: &'b TiffRootIFD<'a>,
+    ) -> Result<&'b TiffIFD<'a>, String> {
+        root.get_ifds_with_tag(TiffTag::TILE_OFFSETS)
+            .into_iter()
+            .chain(
+                root.get_ifds_with_tag(TiffTag::CFA_PATTERN)
+                    .into_iter()
+                    .filter(|ifd| ifd.has_entry(TiffTag::STRIP_OFFSETS)),
+            )
+            .next()
+            .ok_or_else(|| "No raw image found in MOS".to_owned())
+    }
+
+    fn parse_format(
+        root: &TiffRootIFD<'a>,
+        ifd: &TiffIFD<'_>,
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        bands: &Bands<'a>,
+    ) -> Result<Format<'a>, String> {
+        match get_u32(ifd, TiffTag::COMPRESSION)?.unwrap_or(COMPRESSION_NONE) {
+            COMPRESSION_NONE => {
+                let bits =
+                    get_u32(ifd, TiffTag::BITS_PER_SAMPLE)?.unwrap_or(T::BITS);
+                if bits != T::BITS {
+                    return Err(format!("Unsupported bits per sample: {bits}"));
+                }
+                let row_size = dims.row_len().get() * size_of::<T>();
+                if bands
+                    .data
+                    .iter()
+                    .zip(bands.row_counts(dims))
+                    .any(|(band, rows)| band.len() < rows * row_size)
+                {
+                    return Err(
+                        "The MOS tile/strip data is truncated".to_owned()
+                    );
+                }
+                Ok(Format::Uncompressed(root.endianness()))
+            }
+            COMPRESSION_LJPEG | COMPRESSION_LEAF_LJPEG => {
+                let mut decoders = Vec::with_capacity(bands.data.len());
+                for (band, rows) in
+                    bands.data.iter().zip(bands.row_counts(dims))
+                {
+                    let decoder = LJpegDecoder::new(band)
+                        .map_err(|err| err.to_string())?;
+                    let band_dims = Dimensions2D::new(
+                        dims.row_len(),
+                        RowCount::new(non_zero(rows, "tile/strip height")?),
+                    );
+                    if decoder.output_dims() != band_dims {
+                        return Err("The MOS tile/strip dimensions differ \
+                                    from those of its data"
+                            .to_owned());
+                    }
+                    decoders.push(decoder);
+                }
+                Ok(Format::LJpeg(decoders))
+            }
+            compression => {
+                Err(format!("Unsupported MOS compression: {compression}"))
+            }
+        }
+    }
+
+    #[inline(never)]
+    pub fn new<F>(
+        root: &TiffRootIFD<'a>,
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<(Self, ProcurementRequest), String>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let ifd = Self::find_raw_ifd(root)?;
+        let dims = Self::parse_dims(ifd)?;
+
+        let (make, model) = get_make_model(root);
+        let camera = cameras
+            .find(make, model, None)
+            .ok_or_else(|| format!("Unknown camera: {make} {model}"))?;
+        check_camera_support_fn(camera.supported)?;
+        let iso_speed = root
+            .get_entry_recursive(TiffTag::ISO_SPEED_RATINGS)
+            .and_then(|entry| entry.get_u32(0).ok());
+        let metadata = CameraMetadata::new(camera, make, model, iso_speed);
+
+        let bands = Bands::parse(root.input(), ifd, dims)?;
+        let format = Self::parse_format(root, ifd, dims, &bands)?;
+        Ok((
+            Self {
+                metadata,
+                dims,
+                bands,
+                format,
+                wb_coeffs: parse_wb_coeffs(root),
+            },
+            NDSliceProcurementRequest::<T>::new(dims).into(),
+        ))
+    }
+
+    fn decode_uncompressed(
+        &self,
+        endianness: Endianness,
+        output: &mut Array2DRefMut<'_, T>,
+    ) {
+        let mut first_row = 0;
+        for (band, rows) in
+            self.bands.data.iter().zip(self.bands.row_counts(self.dims))
+        {
+            let mut bs = ByteStreamer::new(band, endianness);
+            for row in first_row..first_row + rows {
+                for sample in &mut output[RowIndex::new(row)] {
+                    *sample = bs.read();
+                }
+            }
+            first_row += rows;
+        }
+    }
+
+    fn decode_ljpeg(
+        &self,
+        decoders: &[LJpegDecoder<'_>],
+        output: &mut Array2DRefMut<'_, T>,
+    ) -> Result<(), String> {
+        let width = self.dims.row_len().val();
+        let mut first_row = 0;
+        for decoder in decoders {
+            let rows = decoder.output_dims().row_count().get();
+            let mut buf = vec![0; width.get() * rows];
+            let mut decoded = Array2DRefMut::new(
+                buf.as_mut_slice(),
+                RowLength::new(width),
+                RowPitch::new(width),
+            );
+            decoder
+                .decode(&mut decoded)
+                .map_err(|err| err.to_string())?;
+            for row in 0..rows {
+                output[RowIndex::new(first_row + row)]
+                    .copy_from_slice(&decoded[RowIndex::new(row)]);
+            }
+            first_row += rows;
+        }
+        Ok(())
+    }
+}
+
+#[expect(clippy::missing_trait_methods)]
+impl RawDemuxer for MosDemuxer<'_> {
+    #[inline]
+    fn make(&self) -> &str {
+        self.metadata.make
+    }
+
+    #[inline]
+    fn model(&self) -> &str {
+        self.metadata.model
+    }
+
+    #[inline]
+    fn mode(&self) -> Option<&str> {
+        self.metadata.mode()
+    }
+
+    #[inline]
+    fn canonical_make(&self) -> &str {
+        self.metadata.canonical_make()
+    }
+
+    #[inline]
+    fn canonical_model(&self) -> &str {
+        self.metadata.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_alias(&self) -> &str {
+        self.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_id(&self) -> String {
+        self.metadata.canonical_id()
+    }
+
+    #[inline]
+    fn iso_speed(&self) -> Option<u32> {
+        self.metadata.iso_speed
+    }
+
+    #[inline]
+    fn blacklevel(&self) -> Option<u16> {
+        self.metadata.blacklevel()
+    }
+
+    #[inline]
+    fn whitelevel(&self) -> Option<u16> {
+        self.metadata.whitelevel()
+    }
+
+    #[inline]
+    fn blacklevel_separate(&self) -> Option<Array2DRef<'_, i32>> {
+        None
+    }
+
+    #[inline]
+    fn wb_coeffs(&self) -> Option<[f32; 4]> {
+        self.wb_coeffs
+    }
+
+    #[inline]
+    fn colormatrix(&self) -> Option<Array2DRef<'_, i16>> {
+        self.metadata.colormatrix()
+    }
+
+    #[inline]
+    fn is_cfa(&self) -> bool {
+        self.metadata.is_cfa()
+    }
+
+    #[inline]
+    fn cfa(
+        &self,
+        origin: Coord2D,
+    ) -> Option<OffsetArray2DRef<'_, ColorVariant>> {
+        self.metadata.cfa(origin)
+    }
+
+    #[inline]
+    fn bpp(&self) -> usize {
+        let bpc = match self.datatype() {
+            DataType::U16 => size_of::<u16>(),
+            DataType::F32 => size_of::<f32>(),
+            DataType::U32 => size_of::<u32>(),
+            _ => unreachable!(),
+        };
+        bpc.checked_mul(self.cpp()).unwrap()
+    }
+
+    #[inline]
+    fn cpp(&self) -> usize {
+        1
+    }
+
+    #[inline]
+    fn datatype(&self) -> DataType {
+        DataType::U16
+    }
+
+    #[inline]
+    fn dim_uncropped(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        self.dims
+    }
+
+    #[inline]
+    fn dim_cropped(&self) -> Option<Dimensions2D<core::num::NonZero<usize>>> {
+        self.metadata.dim_cropped(self.dim_uncropped())
+    }
+
+    #[inline]
+    fn crop_offset(&self) -> Option<Coord2D> {
+        self.metadata.crop_offset()
+    }
+
+    #[inline]
+    fn black_areas(&self) -> Option<&[BlackArea]> {
+        self.metadata.black_areas()
+    }
+
+    #[inline]
+    fn fuji_rotation_pos(&self) -> Option<u32> {
+        None
+    }
+
+    #[inline]
+    fn pixel_aspect_ratio(&self) -> Option<f64> {
+        None
+    }
+
+    #[inline]
+    fn bad_pixel_positions(&self) -> Vec<Coord2D> {
+        vec![]
+    }
+
+    #[inline(never)]
+    fn decode(
+        &self,
+        output: &mut ImageRefMut<'_>,
+    ) -> Result<(), RawDemuxerError> {
+        let output = output.as_u16()?;
+        if output.dims() != self.dims {
+            return Err(RawDemuxerError::DecoderError(
+                "Output buffer dimensions differ from expected".to_owned(),
+            ));
+        }
+        match &self.format {
+            Format::Uncompressed(endianness) => {
+                self.decode_uncompressed(*endianness, output);
+                Ok(())
+            }
+            Format::LJpeg(decoders) => self
+                .decode_ljpeg(decoders, output)
+                .map_err(RawDemuxerError::DecoderError),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,127 @@
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_parsers_tiffparser::tiffparser::TiffTag;
+use rawspeed_utils_tiffbuilder::tiffbuilder::Value;
+
+use super::{Mos, compress, decode, new_demuxer_err, rows, uncompressed};
+use crate::mos_demuxer::{COMPRESSION_LEAF_LJPEG, COMPRESSION_LJPEG};
+
+#[test]
+fn uncompressed_test() {
+    let rows = rows(6, 4);
+    for endianness in [Endianness::Little, Endianness::Big] {
+        assert_eq!(
+            decode(&Mos::uncompressed(&rows, endianness)),
+            Ok(rows.clone())
+        );
+    }
+}
+
+#[test]
+fn uncompressed_bands_test() {
+    // The last band is shorter.
+    let rows = rows(4, 5);
+    let bands: Vec<Vec<u8>> = rows
+        .chunks(2)
+        .map(|band| uncompressed(band, Endianness::Big))
+        .collect();
+    for tiled in [false, true] {
+        let mut mos = Mos::uncompressed(&rows, Endianness::Big);
+        mos.band_rows = Some(2);
+        mos.tiled = tiled;
+        mos.bands.clone_from(&bands);
+        assert_eq!(decode(&mos), Ok(rows.clone()));
+    }
+}
+
+#[test]
+fn ljpeg_test() {
+    let rows = rows(8, 6);
+    for compression in [COMPRESSION_LJPEG, COMPRESSION_LEAF_LJPEG] {
+        for num_components in [1, 2] {
+            let mut mos = Mos::new(8, 6, compression);
+            mos.tiled = true;
+            mos.band_rows = Some(4);
+            mos.bands = rows
+                .chunks(4)
+                .map(|band| compress(band, num_components))
+                .collect();
+            assert_eq!(decode(&mos), Ok(rows.clone()));
+        }
+    }
+}
+
+#[test]
+fn ljpeg_dimensions_mismatch_test() {
+    let mut mos = Mos::new(8, 6, COMPRESSION_LJPEG);
+    mos.bands = vec![compress(&rows(8, 5), 1)];
+    assert_eq!(
+        new_demuxer_err(&mos.build()),
+        "The MOS tile/strip dimensions differ from those of its data"
+    );
+    mos.bands = vec![vec![0; 16]];
+    assert_eq!(new_demuxer_err(&mos.build()), "LJpegError(MissingSOI)");
+}
+
+#[test]
+fn truncated_data_test() {
+    let mut mos = Mos::uncompressed(&rows(6, 4), Endianness::Little);
+    mos.bands.first_mut().unwrap().pop();
+    assert_eq!(
+        new_demuxer_err(&mos.build()),
+        "The MOS tile/strip data is truncated"
+    );
+    // The data runs out mid-band.
+    let mut ljpeg = Mos::new(64, 16, COMPRESSION_LJPEG);
+    let mut data = compress(&rows(64, 16), 1);
+    data.truncate(data.len() / 2);
+    ljpeg.bands = vec![data];
+    decode(&ljpeg).unwrap_err();
+}
+
+#[test]
+fn invalid_layout_test() {
+    let rows = rows(6, 4);
+    let mut mos = Mos::uncompressed(&rows, Endianness::Little);
+    mos.band_rows = Some(2);
+    assert_eq!(
+        new_demuxer_err(&mos.build()),
+        "The MOS tile/strip count is invalid"
+    );
+    let mut tiled = Mos::uncompressed(&rows, Endianness::Little);
+    tiled.tiled = true;
+    let mut builder = tiled.builder();
+    builder.set(TiffTag::TILE_WIDTH, &Value::Long(vec![4]));
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "Unsupported MOS tile width"
+    );
+}
+
+#[test]
+fn unsupported_format_test() {
+    let rows = rows(6, 4);
+    let mut mos = Mos::uncompressed(&rows, Endianness::Little);
+    // PackBits.
+    mos.compression = 0x8005;
+    assert_eq!(
+        new_demuxer_err(&mos.build()),
+        "Unsupported MOS compression: 32773"
+    );
+    let mut builder = Mos::uncompressed(&rows, Endianness::Little).builder();
+    builder.set(TiffTag::BITS_PER_SAMPLE, &Value::Short(vec![12]));
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "Unsupported bits per sample: 12"
+    );
+}
+
+#[test]
+fn no_raw_image_test() {
+    let mut builder =
+        Mos::uncompressed(&rows(2, 2), Endianness::Little).builder();
+    builder.remove(TiffTag::CFA_PATTERN);
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "No raw image found in MOS"
+    );
+}
@@ -0,0 +1,144 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_parsers_tiffparser::tiffparser::TiffParser;
+
+use super::{Mos, MosDemuxer, new_demuxer_err, packet, parse_cameras, rows};
+
+fn flat(endianness: Endianness) -> Mos {
+    Mos::uncompressed(&rows(4, 2), endianness)
+}
+
+fn xmp(make: &str, model: &str) -> String {
+    format!(
+        "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF><rdf:Description>\
+         <tiff:Make>{make}</tiff:Make> <tiff:Model> {model} </tiff:Model>\
+         </rdf:Description></rdf:RDF></x:xmpmeta>"
+    )
+}
+
+macro_rules! with_demuxer {
+    ($mos:expr, |$demuxer:ident| $body:block) => {{
+        let input = $mos.build();
+        let root = TiffParser::parse(&input).unwrap();
+        let cameras = parse_cameras();
+        let ($demuxer, _) = MosDemuxer::new(
+            &root,
+            &cameras,
+            DecodeableCamera::new_unless_unsupported,
+        )
+        .unwrap();
+        $body
+    }};
+}
+
+fn is_mos(mos: &Mos) -> bool {
+    let input = mos.build();
+    MosDemuxer::is_mos(&TiffParser::parse(&input).unwrap())
+}
+
+#[test]
+fn camera_from_cameras_xml_test() {
+    with_demuxer!(flat(Endianness::Big), |demuxer| {
+        assert_eq!(demuxer.make(), "Leaf");
+        assert_eq!(demuxer.model(), "Aptus 0");
+        assert_eq!(demuxer.mode(), None);
+        assert_eq!(demuxer.canonical_id(), "Leaf Aptus 0");
+        assert_eq!(demuxer.iso_speed(), Some(50));
+        assert_eq!(demuxer.whitelevel(), Some(16383));
+        assert!(demuxer.is_cfa());
+        assert!(demuxer.wb_coeffs().is_none());
+    });
+}
+
+#[test]
+fn xmp_make_model_test() {
+    let mut mos = flat(Endianness::Big);
+    mos.make = None;
+    mos.model = None;
+    mos.xmp = Some(xmp("Mamiya", "ZD0"));
+    with_demuxer!(mos, |demuxer| {
+        assert_eq!(demuxer.make(), "Mamiya");
+        assert_eq!(demuxer.model(), "ZD0");
+        assert_eq!(demuxer.blacklevel(), Some(32));
+    });
+    // The IFDs take precedence.
+    let mut both = flat(Endianness::Big);
+    both.xmp = Some(xmp("Mamiya", "ZD0"));
+    with_demuxer!(both, |demuxer| {
+        assert_eq!(demuxer.canonical_id(), "Leaf Aptus 0");
+    });
+}
+
+#[test]
+fn wb_coeffs_test() {
+    for endianness in [Endianness::Little, Endianness::Big] {
+        let neutrals = packet("NeutObj_neutrals", b"12 6 12 3\0", endianness);
+        let mut mos = flat(endianness);
+        mos.leaf_metadata = Some(
+            [
+                packet("JPEG_preview_data", &[0; 5], endianness),
+                packet("NeutObj", &neutrals, endianness),
+            ]
+            .concat(),
+        );
+        with_demuxer!(mos, |demuxer| {
+            let [red, green, blue, fourth] = demuxer.wb_coeffs().unwrap();
+            assert_eq!(
+                [red, green, blue].map(f32::to_bits),
+                [2.0_f32, 1.0, 4.0].map(f32::to_bits)
+            );
+            assert!(fourth.is_nan());
+        });
+    }
+}
+
+#[test]
+fn invalid_wb_coeffs_test() {
+    for payload in [&b"12 6 0 3"[..], b"12 6 12", b"12 six 12 3"] {
+        let mut mos = flat(Endianness::Big);
+        mos.leaf_metadata =
+            Some(packet("NeutObj_neutrals", payload, Endianness::Big));
+        with_demuxer!(mos, |demuxer| {
+            assert!(demuxer.wb_coeffs().is_none());
+        });
+    }
+    // A packet that overruns the metadata is ignored.
+    let mut mos = flat(Endianness::Big);
+    let mut truncated =
+        packet("NeutObj_neutrals", b"12 6 12 3", Endianness::Big);
+    truncated.pop();
+    mos.leaf_metadata = Some(truncated);
+    with_demuxer!(mos, |demuxer| {
+        assert!(demuxer.wb_coeffs().is_none());
+    });
+}
+
+#[test]
+fn is_mos_test() {
+    assert!(is_mos(&flat(Endianness::Big)));
+    let mut mamiya = flat(Endianness::Big);
+    mamiya.make = None;
+    mamiya.xmp = Some(xmp("Mamiya", "ZD0"));
+    assert!(is_mos(&mamiya));
+    let mut other = flat(Endianness::Big);
+    other.make = Some("Other");
+    assert!(!is_mos(&other));
+    other.leaf_metadata = Some(vec![]);
+    assert!(is_mos(&other));
+}
+
+#[test]
+fn unknown_camera_test() {
+    let mut mos = flat(Endianness::Big);
+    mos.model = Some("Aptus 1");
+    assert_eq!(
+        new_demuxer_err(&mos.build()),
+        "Unknown camera: Leaf Aptus 1"
+    );
+    mos.model = Some("Unsupported");
+    assert_eq!(
+        new_demuxer_err(&mos.build()),
+        "This camera is not supported (explicit)"
+    );
+}
@@ -0,0 +1,298 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::Cameras;
+use rawspeed_metadata_xmlparser::xmlparser;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_std::coord_common::RowIndex;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+use rawspeed_utils_tiffbuilder::tiffbuilder::{TiffBuilder, Value, tag};
+
+use super::{COMPRESSION_NONE, MosDemuxer};
+
+const CAMERAS: &str = "
+    <Cameras>
+        <Camera make=\"Leaf\" model=\"Aptus 0\">
+            <ID make=\"Leaf\" model=\"Aptus 0\">Leaf Aptus 0</ID>
+            <CFA width=\"2\" height=\"2\">
+                <Color x=\"0\" y=\"0\">RED</Color>
+                <Color x=\"1\" y=\"0\">GREEN</Color>
+                <Color x=\"0\" y=\"1\">GREEN</Color>
+                <Color x=\"1\" y=\"1\">BLUE</Color>
+            </CFA>
+            <Sensor black=\"0\" white=\"16383\"/>
+        </Camera>
+        <Camera make=\"Mamiya\" model=\"ZD0\">
+            <ID make=\"Mamiya\" model=\"ZD0\">Mamiya ZD0</ID>
+            <Sensor black=\"32\" white=\"4095\"/>
+        </Camera>
+        <Camera make=\"Leaf\" model=\"Unsupported\" supported=\"no\">
+        </Camera>
+    </Cameras>";
+
+/// All 17 difference categories, as 5-bit codes equal to the category.
+const COUNTS: [u8; 16] = [0, 0, 0, 0, 17, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+const CODE_LENGTH: u32 = 5;
+const PRECISION: u32 = 16;
+
+fn be16(val: usize) -> [u8; 2] {
+    let val = u16::try_from(val).unwrap();
+    [
+        (val >> 8).try_into().unwrap(),
+        (val & 0xFF).try_into().unwrap(),
+    ]
+}
+
+fn segment(out: &mut Vec<u8>, marker: u8, payload: &[u8]) {
+    out.extend([0xFF, marker]);
+    out.extend(be16(payload.len() + 2));
+    out.extend(payload);
+}
+
+fn encode_difference(bits: &mut BitStreamBuilder, diff: i32) {
+    let diff = match diff.rem_euclid(1 << 16) {
+        d if d >= 1 << 15 => d - (1 << 16),
+        d => d,
+    };
+    let category = 32 - diff.unsigned_abs().leading_zeros();
+    bits.put(category, CODE_LENGTH);
+    if category != 0 && category != 16 {
+        let value = if diff < 0 {
+            diff + (1 << category) - 1
+        } else {
+            diff
+        };
+        bits.put(value.try_into().unwrap(), category);
+    }
+}
+
+/// Encodes the rows as a lossless JPEG with the given number of
+/// interleaved components, each predicted from its left neighbour.
+fn compress(rows: &[Vec<u16>], num_components: usize) -> Vec<u8> {
+    let width = rows.first().unwrap().len() / num_components;
+    let mut out = vec![0xFF, 0xD8];
+    let mut sof = vec![u8::try_from(PRECISION).unwrap()];
+    sof.extend(be16(rows.len()));
+    sof.extend(be16(width));
+    sof.push(num_components.try_into().unwrap());
+    for id in (1..).take(num_components) {
+        sof.extend([id, 0x11, 0]);
+    }
+    segment(&mut out, 0xC3, &sof);
+    let mut dht = vec![0x00];
+    dht.extend(COUNTS);
+    dht.extend(0..=16);
+    segment(&mut out, 0xC4, &dht);
+    let mut sos = vec![num_components.try_into().unwrap()];
+    for id in (1..).take(num_components) {
+        sos.extend([id, 0x00]);
+    }
+    sos.extend([1, 0, 0]);
+    segment(&mut out, 0xDA, &sos);
+
+    let mut bits = BitStreamBuilder::new();
+    for (row_index, row) in rows.iter().enumerate() {
+        for (col, sample) in row.iter().enumerate() {
+            let pred = if col >= num_components {
+                row.get(col - num_components).copied().map(i32::from)
+            } else if row_index > 0 {
+                rows.get(row_index - 1)
+                    .and_then(|above| above.get(col))
+                    .copied()
+                    .map(i32::from)
+            } else {
+                Some(1 << (PRECISION - 1))
+            };
+            encode_difference(&mut bits, i32::from(*sample) - pred.unwrap());
+        }
+    }
+    out.extend(bits.finish(BitOrder::JPEG));
+    out.extend([0xFF, 0xD9]);
+    out
+}
+
+/// A `PKTS` packet in the given byte order.
+fn packet(name: &str, payload: &[u8], endianness: Endianness) -> Vec<u8> {
+    let mut out = b"PKTS\0\0\0\x01".to_vec();
+    let mut padded_name = name.as_bytes().to_vec();
+    padded_name.resize(40, 0);
+    out.extend(padded_name);
+    let size = u32::try_from(payload.len()).unwrap();
+    match endianness {
+        Endianness::Little => out.extend(size.to_le_bytes()),
+        Endianness::Big => out.extend(size.to_le_bytes().into_iter().rev()),
+    }
+    out.extend(payload);
+    out
+}
+
+fn rows(width: usize, height: usize) -> Vec<Vec<u16>> {
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| {
+                    let sample = (row * 7919 + col * 2131 + 17) % (1 << 16);
+                    u16::try_from(sample).unwrap()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn uncompressed(rows: &[Vec<u16>], endianness: Endianness) -> Vec<u8> {
+    rows.iter()
+        .flatten()
+        .flat_map(|sample| match endianness {
+            Endianness::Little => sample.to_le_bytes(),
+            Endianness::Big => {
+                let [low, high] = sample.to_le_bytes();
+                [high, low]
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+struct Mos {
+    endianness: Endianness,
+    make: Option<&'static str>,
+    model: Option<&'static str>,
+    xmp: Option<String>,
+    leaf_metadata: Option<Vec<u8>>,
+    width: u32,
+    height: u32,
+    compression: u32,
+    /// The rows per strip, or the height of the tiles.
+    band_rows: Option<u32>,
+    tiled: bool,
+    bands: Vec<Vec<u8>>,
+}
+
+impl Mos {
+    fn new(width: u32, height: u32, compression: u32) -> Self {
+        Self {
+            endianness: Endianness::Big,
+            make: Some("Leaf"),
+            model: Some("Aptus 0"),
+            xmp: None,
+            leaf_metadata: None,
+            width,
+            height,
+            compression,
+            band_rows: None,
+            tiled: false,
+            bands: vec![],
+        }
+    }
+
+    /// An uncompressed image, stored as a single strip.
+    fn uncompressed(rows: &[Vec<u16>], endianness: Endianness) -> Self {
+        let width = u32::try_from(rows.first().unwrap().len()).unwrap();
+        let height = u32::try_from(rows.len()).unwrap();
+        let mut mos = Self::new(width, height, COMPRESSION_NONE);
+        mos.endianness = endianness;
+        mos.bands = vec![uncompressed(rows, endianness)];
+        mos
+    }
+
+    /// The first IFD holds a preview, and the raw image is in its sub-IFD.
+    fn builder(&self) -> TiffBuilder {
+        let mut builder = TiffBuilder::new(self.endianness);
+        builder.ifd(0).extend([
+            tag(TiffTag::IMAGE_WIDTH, Value::Long(vec![1])),
+            tag(TiffTag::IMAGE_LENGTH, Value::Long(vec![1])),
+            tag(TiffTag::ISO_SPEED_RATINGS, Value::Short(vec![50])),
+            tag(TiffTag::SUB_IFDS, Value::IFDOffset(1)),
+        ]);
+        if let Some(make) = self.make {
+            builder.ifd(0).push(tag(TiffTag::MAKE, Value::Ascii(make)));
+        }
+        if let Some(model) = self.model {
+            builder
+                .ifd(0)
+                .push(tag(TiffTag::MODEL, Value::Ascii(model)));
+        }
+        if let Some(xmp) = &self.xmp {
+            builder
+                .ifd(0)
+                .push(tag(TiffTag::XMP, Value::Byte(xmp.as_bytes().to_vec())));
+        }
+        if let Some(metadata) = &self.leaf_metadata {
+            builder.ifd(0).push(tag(
+                TiffTag::LEAF_METADATA,
+                Value::Undefined(metadata.clone()),
+            ));
+        }
+        let blobs: Vec<usize> = (0..self.bands.len()).collect();
+        builder.ifd(1).extend([
+            tag(TiffTag::IMAGE_WIDTH, Value::Long(vec![self.width])),
+            tag(TiffTag::IMAGE_LENGTH, Value::Long(vec![self.height])),
+            tag(TiffTag::BITS_PER_SAMPLE, Value::Short(vec![16])),
+            tag(TiffTag::COMPRESSION, Value::Long(vec![self.compression])),
+            tag(TiffTag::CFA_PATTERN, Value::Byte(vec![0, 1, 1, 2])),
+        ]);
+        if self.tiled {
+            builder.ifd(1).extend([
+                tag(TiffTag::TILE_WIDTH, Value::Long(vec![self.width])),
+                tag(
+                    TiffTag::TILE_LENGTH,
+                    Value::Long(vec![self.band_rows.unwrap_or(self.height)]),
+                ),
+                tag(TiffTag::TILE_OFFSETS, Value::BlobOffsets(blobs.clone())),
+                tag(TiffTag::TILE_BYTE_COUNTS, Value::BlobSizes(blobs)),
+            ]);
+        } else {
+            builder.ifd(1).extend([
+                tag(TiffTag::STRIP_OFFSETS, Value::BlobOffsets(blobs.clone())),
+                tag(TiffTag::STRIP_BYTE_COUNTS, Value::BlobSizes(blobs)),
+            ]);
+            if let Some(rows) = self.band_rows {
+                builder.ifd(1).push(tag(
+                    TiffTag::ROWS_PER_STRIP,
+                    Value::Long(vec![rows]),
+                ));
+            }
+        }
+        builder.blobs.clone_from(&self.bands);
+        builder
+    }
+
+    fn build(&self) -> Vec<u8> {
+        self.builder().build()
+    }
+}
+
+fn parse_cameras() -> Cameras<'static> {
+    xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap()
+}
+
+fn new_demuxer_err(input: &[u8]) -> String {
+    let root = TiffParser::parse(input).unwrap();
+    let cameras = parse_cameras();
+    MosDemuxer::new(&root, &cameras, DecodeableCamera::new_unless_unsupported)
+        .unwrap_err()
+}
+
+fn decode(mos: &Mos) -> Result<Vec<Vec<u16>>, String> {
+    let input = mos.build();
+    let root = TiffParser::parse(&input).unwrap();
+    let cameras = parse_cameras();
+    let (demuxer, request) = MosDemuxer::new(
+        &root,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )?;
+    let mut output_buf = request.fulfill().unwrap();
+    let mut image = output_buf.get_mut();
+    demuxer.decode(&mut image).map_err(|err| err.to_string())?;
+    let output = image.as_u16().unwrap();
+    Ok((0..output.num_rows().get())
+        .map(|row| output.get_row(RowIndex::new(row)).unwrap().to_vec())
+        .collect())
+}
+
+#[cfg(test)]
+mod data;
+#[cfg(test)]
+mod metadata;
@@ -0,0 +1 @@
+pub mod mrw_demuxer;
@@ -0,0 +1,408 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_bitstream_bytestream_decoder::bytestreamer::ByteStreamer;
+use rawspeed_codecs_packed_decoder::packed_decoder::Unpacker;
+use rawspeed_demuxers_common::{
+    camera_metadata::CameraMetadata,
+    tiff_utils::{get_root_string, non_zero, tiff_err},
+};
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::{
+    DataType, ImageRefMut, ProcurementRequest, RawDemuxer, RawDemuxerError,
+};
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_memory_nd_slice_procurement::ndsliceprocurement::NDSliceProcurementRequest;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::{
+    Cameras, Supported, blackareas::BlackArea,
+};
+use rawspeed_metadata_colorfilterarray::colorfilterarray::ColorVariant;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffEntry, TiffParser, TiffTag};
+use rawspeed_std::coord_common::{
+    Coord2D, Dimensions2D, RowCount, RowLength, RowPitch,
+};
+use rawspeed_std_ndslice::{
+    array2dref::Array2DRef, offsetarray2dref::OffsetArray2DRef,
+};
+
+const MRW_MAGIC: &[u8] = b"\0MRM";
+
+/// The blocks start after the magic and the (big-endian) length of all
+/// of them, and are followed by the raw data.
+const BLOCKS_OFFSET: usize = 8;
+
+/// Each block is a 4-byte tag, its length, and its data.
+const BLOCK_PRD: &[u8] = b"\0PRD";
+const BLOCK_TTW: &[u8] = b"\0TTW";
+const BLOCK_WBG: &[u8] = b"\0WBG";
+
+/// The sensor height and width follow the version of the PRD block, and
+/// the sample size and storage method follow the image height and width.
+const PRD_SENSOR_SIZE_OFFSET: usize = 8;
+const PRD_SAMPLE_SIZE_OFFSET: usize = 16;
+const PRD_LEN: usize = 23;
+
+const STORAGE_UNPACKED: u8 = 0x52;
+const STORAGE_PACKED: u8 = 0x59;
+
+/// The RGGB levels of the WBG block follow its 4 scale bytes.
+const WBG_LEVELS_OFFSET: usize = 4;
+
+type T = u16;
+
+fn read_u16(data: &[u8]) -> Option<u16> {
+    data.get(..size_of::<u16>())
+        .map(|bytes| ByteStreamer::new(bytes, Endianness::Big).read())
+}
+
+fn read_u32(data: &[u8]) -> Option<usize> {
+    data.get(..size_of::<u32>()).map(|bytes| {
+        let val: u32 = ByteStreamer::new(bytes, Endianness::Big).read();
+        val.try_into().unwrap()
+    })
+}
+
+/// The blocks of an MRW file, and the raw data that follows them.
+#[derive(Debug)]
+struct Blocks<'a> {
+    blocks: Vec<(&'a [u8], &'a [u8])>,
+    raw: &'a [u8],
+}
+
+impl<'a> Blocks<'a> {
+    fn parse(input: &'a [u8]) -> Result<Self, String> {
+        const TRUNCATED: &str = "The MRW blocks are truncated";
+        if !input.starts_with(MRW_MAGIC) {
+            return Err("Not an MRW file".to_owned());
+        }
+        let blocks_len = input.get(MRW_MAGIC.len()..).and_then(read_u32);
+        let (mut rest, raw) = input
+            .get(BLOCKS_OFFSET..)
+            .zip(blocks_len)
+            .and_then(|(data, len)| data.split_at_checked(len))
+            .ok_or(TRUNCATED)?;
+        let mut blocks = vec![];
+        while !rest.is_empty() {
+            let (tag, data) =
+                rest.split_at_checked(MRW_MAGIC.len()).ok_or(TRUNCATED)?;
+            let len = read_u32(data).ok_or(TRUNCATED)?;
+            let (data, next) = data
+                .get(size_of::<u32>()..)
+                .and_then(|data| data.split_at_checked(len))
+                .ok_or(TRUNCATED)?;
+            blocks.push((tag, data));
+            rest = next;
+        }
+        Ok(Self { blocks, raw })
+    }
+
+    fn get(&self, tag: &[u8]) -> Option<&'a [u8]> {
+        self.blocks
+            .iter()
+            .find(|(block_tag, _)| *block_tag == tag)
+            .map(|(_, data)| *data)
+    }
+}
+
+/// The layout of the raw data, from the PRD block.
+#[derive(Debug, Clone, Copy)]
+struct Prd {
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    bits: u32,
+    packed: bool,
+}
+
+impl Prd {
+    fn parse(data: &[u8]) -> Result<Self, String> {
+        if data.len() < PRD_LEN {
+            return Err("The MRW PRD block is truncated".to_owned());
+        }
+        let sensor_size = data.get(PRD_SENSOR_SIZE_OFFSET..).unwrap();
+        let height = read_u16(sensor_size).unwrap();
+        let width = read_u16(sensor_size.get(size_of::<u16>()..).unwrap());
+        let dims = Dimensions2D::new(
+            RowLength::new(non_zero(width.unwrap().into(), "width")?),
+            RowCount::new(non_zero(height.into(), "height")?),
+        );
+        let [bits, storage] = [0, 1]
+            .map(|index| *data.get(PRD_SAMPLE_SIZE_OFFSET + index).unwrap());
+        if bits != 12 && bits != 16 {
+            return Err(format!("Unsupported MRW sample size: {bits}"));
+        }
+        let packed = match storage {
+            STORAGE_UNPACKED => false,
+            STORAGE_PACKED if bits == 12 => true,
+            _ => {
+                return Err(format!(
+                    "Unsupported MRW storage method: {storage:#x}"
+                ));
+            }
+        };
+        Ok(Self {
+            dims,
+            bits: bits.into(),
+            packed,
+        })
+    }
+}
+
+/// The levels are stored in RGGB order, unless the camera is known to
+/// swap them.
+fn parse_wb_coeffs(data: &[u8], swapped: bool) -> Option<[f32; 4]> {
+    let level = |index: usize| {
+        data.get(WBG_LEVELS_OFFSET + index * size_of::<u16>()..)
+            .and_then(read_u16)
+            .map(f32::from)
+    };
+    let indices = if swapped { [2, 0, 1] } else { [0, 1, 3] };
+    let [red, green, blue] = indices.map(level);
+    Some([red?, green?, blue?, f32::NAN])
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct MrwDemuxer<'a> {
+    metadata: CameraMetadata<'a>,
+    input: Array2DRef<'a, u8>,
+    bits: u32,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    wb_coeffs: Option<[f32; 4]>,
+}
+
+impl<'a> MrwDemuxer<'a> {
+    /// The packed samples are 12-bit, and the others take 16 bits; both
+    /// are stored most significant bit first.
+    fn parse_data(
+        raw: &'a [u8],
+        prd: &Prd,
+    ) -> Result<(Array2DRef<'a, u8>, u32), String> {
+        let bits = if prd.packed { prd.bits } else { T::BITS };
+        let (width, height) =
+            (prd.dims.row_len().get(), prd.dims.row_count().get());
+        let row_bits = width * usize::try_from(bits).unwrap();
+        let bytes_per_row = row_bits / 8;
+        // Rows are read in whole chunks of the bit order.
+        if !row_bits.is_multiple_of(8)
+            || !bytes_per_row.is_multiple_of(BitOrder::MSB.mcu_bytelen())
+        {
+            return Err(format!("Unsupported MRW width: {width}"));
+        }
+        let input = raw
+            .get(..bytes_per_row * height)
+            .ok_or("The MRW raw data is truncated")?;
+        let bytes_per_row = non_zero(bytes_per_row, "row pitch")?;
+        Ok((
+            Array2DRef::new(
+                input,
+                RowLength::new(bytes_per_row),
+                RowPitch::new(bytes_per_row),
+            ),
+            bits,
+        ))
+    }
+
+    #[inline(never)]
+    pub fn new<F>(
+        input: &'a [u8],
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<(Self, ProcurementRequest), String>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let blocks = Blocks::parse(input)?;
+        let prd = Prd::parse(
+            blocks
+                .get(BLOCK_PRD)
+                .ok_or("The MRW PRD block is missing")?,
+        )?;
+        // The offsets of the TTW block are relative to its start.
+        let ttw = blocks
+            .get(BLOCK_TTW)
+            .ok_or("The MRW TTW block is missing")?;
+        let root = TiffParser::parse(ttw).map_err(tiff_err)?;
+
+        let make = get_root_string(&root, TiffTag::MAKE).unwrap_or("");
+        let model = get_root_string(&root, TiffTag::MODEL).unwrap_or("");
+        let camera = cameras
+            .find(make, model, None)
+            .ok_or_else(|| format!("Unknown camera: {make} {model}"))?;
+        check_camera_support_fn(camera.supported)?;
+        let iso_speed = root
+            .get_entry_recursive(TiffTag::ISO_SPEED_RATINGS)
+            .map(TiffEntry::get_u32s)
+            .and_then(Result::ok)
+            .and_then(|isos| isos.first().copied());
+        let metadata = CameraMetadata::new(camera, make, model, iso_speed);
+
+        let (data, bits) = Self::parse_data(blocks.raw, &prd)?;
+        let swapped_wb = metadata.hint("swapped_wb").is_some();
+        let wb_coeffs = blocks
+            .get(BLOCK_WBG)
+            .and_then(|wbg| parse_wb_coeffs(wbg, swapped_wb));
+        Ok((
+            Self {
+                metadata,
+                input: data,
+                bits,
+                dims: prd.dims,
+                wb_coeffs,
+            },
+            NDSliceProcurementRequest::<T>::new(prd.dims).into(),
+        ))
+    }
+}
+
+#[expect(clippy::missing_trait_methods)]
+impl RawDemuxer for MrwDemuxer<'_> {
+    #[inline]
+    fn make(&self) -> &str {
+        self.metadata.make
+    }
+
+    #[inline]
+    fn model(&self) -> &str {
+        self.metadata.model
+    }
+
+    #[inline]
+    fn mode(&self) -> Option<&str> {
+        self.metadata.mode()
+    }
+
+    #[inline]
+    fn canonical_make(&self) -> &str {
+        self.metadata.canonical_make()
+    }
+
+    #[inline]
+    fn canonical_model(&self) -> &str {
+        self.metadata.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_alias(&self) -> &str {
+        self.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_id(&self) -> String {
+        self.metadata.canonical_id()
+    }
+
+    #[inline]
+    fn iso_speed(&self) -> Option<u32> {
+        self.metadata.iso_speed
+    }
+
+    #[inline]
+    fn blacklevel(&self) -> Option<u16> {
+        self.metadata.blacklevel()
+    }
+
+    #[inline]
+    fn whitelevel(&self) -> Option<u16> {
+        self.metadata.whitelevel()
+    }
+
+    #[inline]
+    fn blacklevel_separate(&self) -> Option<Array2DRef<'_, i32>> {
+        None
+    }
+
+    #[inline]
+    fn wb_coeffs(&self) -> Option<[f32; 4]> {
+        self.wb_coeffs
+    }
+
+    #[inline]
+    fn colormatrix(&self) -> Option<Array2DRef<'_, i16>> {
+        self.metadata.colormatrix()
+    }
+
+    #[inline]
+    fn is_cfa(&self) -> bool {
+        self.metadata.is_cfa()
+    }
+
+    #[inline]
+    fn cfa(
+        &self,
+        origin: Coord2D,
+    ) -> Option<OffsetArray2DRef<'_, ColorVariant>> {
+        self.metadata.cfa(origin)
+    }
+
+    #[inline]
+    fn bpp(&self) -> usize {
+        let bpc = match self.datatype() {
+            DataType::U16 => size_of::<u16>(),
+            DataType::F32 => size_of::<f32>(),
+            DataType::U32 => size_of::<u32>(),
+            _ => unreachable!(),
+        };
+        bpc.checked_mul(self.cpp()).unwrap()
+    }
+
+    #[inline]
+    fn cpp(&self) -> usize {
+        1
+    }
+
+    #[inline]
+    fn datatype(&self) -> DataType {
+        DataType::U16
+    }
+
+    #[inline]
+    fn dim_uncropped(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        self.dims
+    }
+
+    #[inline]
+    fn dim_cropped(&self) -> Option<Dimensions2D<core::num::NonZero<usize>>> {
+        self.metadata.dim_cropped(self.dim_uncropped())
+    }
+
+    #[inline]
+    fn crop_offset(&self) -> Option<Coord2D> {
+        self.metadata.crop_offset()
+    }
+
+    #[inline]
+    fn black_areas(&self) -> Option<&[BlackArea]> {
+        self.metadata.black_areas()
+    }
+
+    #[inline]
+    fn fuji_rotation_pos(&self) -> Option<u32> {
+        None
+    }
+
+    #[inline]
+    fn pixel_aspect_ratio(&self) -> Option<f64> {
+        None
+    }
+
+    #[inline]
+    fn bad_pixel_positions(&self) -> Vec<Coord2D> {
+        vec![]
+    }
+
+    #[inline(never)]
+    fn decode(
+        &self,
+        output: &mut ImageRefMut<'_>,
+    ) -> Result<(), RawDemuxerError> {
+        let output = output.as_u16()?;
+        if output.dims() != self.dims {
+            return Err(RawDemuxerError::DecoderError(
+                "Output buffer dimensions differ from expected".to_owned(),
+            ));
+        }
+        Unpacker::new(self.input, BitOrder::MSB, self.bits, output).unpack();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,95 @@
+use super::{Mrw, be32, block, decode, new_demuxer_err, rows};
+
+#[test]
+fn packed_test() {
+    let rows = rows(8, 3);
+    assert_eq!(decode(&Mrw::new(&rows, true).build()), Ok(rows));
+}
+
+#[test]
+fn packed_odd_row_pitch_test() {
+    let rows = rows(6, 3);
+    assert_eq!(decode(&Mrw::new(&rows, true).build()), Ok(rows));
+}
+
+#[test]
+fn unpacked_test() {
+    let rows = rows(6, 3);
+    assert_eq!(decode(&Mrw::new(&rows, false).build()), Ok(rows));
+}
+
+#[test]
+fn unsupported_layout_test() {
+    let mut bits = Mrw::new(&rows(8, 2), false);
+    bits.bits = 10;
+    assert_eq!(
+        new_demuxer_err(&bits.build()),
+        "Unsupported MRW sample size: 10"
+    );
+    let mut storage = Mrw::new(&rows(8, 2), false);
+    storage.storage = 0x42;
+    assert_eq!(
+        new_demuxer_err(&storage.build()),
+        "Unsupported MRW storage method: 0x42"
+    );
+    // Only 12-bit samples are packed.
+    let mut packed = Mrw::new(&rows(8, 2), true);
+    packed.bits = 16;
+    assert_eq!(
+        new_demuxer_err(&packed.build()),
+        "Unsupported MRW storage method: 0x59"
+    );
+    assert_eq!(
+        new_demuxer_err(&Mrw::new(&rows(3, 2), true).build()),
+        "Unsupported MRW width: 3"
+    );
+}
+
+#[test]
+fn truncated_test() {
+    let mut data = Mrw::new(&rows(8, 3), true);
+    data.data.pop();
+    assert_eq!(
+        new_demuxer_err(&data.build()),
+        "The MRW raw data is truncated"
+    );
+    let mrw = Mrw::new(&rows(8, 3), true).build();
+    assert_eq!(
+        new_demuxer_err(mrw.get(..20).unwrap()),
+        "The MRW blocks are truncated"
+    );
+}
+
+#[test]
+fn missing_blocks_test() {
+    let mrw = Mrw::new(&rows(8, 3), true);
+    for (tag, error) in [
+        (b"\0PRD", "The MRW PRD block is missing"),
+        (b"\0TTW", "The MRW TTW block is missing"),
+    ] {
+        let mut blocks = vec![];
+        if tag != b"\0PRD" {
+            blocks.extend(block(b"\0PRD", &mrw.prd()));
+        }
+        if tag != b"\0TTW" {
+            blocks.extend(block(b"\0TTW", &mrw.ttw()));
+        }
+        let mut input = b"\0MRM".to_vec();
+        input.extend(be32(blocks.len()));
+        input.extend(blocks);
+        input.extend(&mrw.data);
+        assert_eq!(new_demuxer_err(&input), error);
+    }
+    let mut prd = mrw.prd();
+    prd.truncate(20);
+    let mut input = b"\0MRM".to_vec();
+    let blocks = block(b"\0PRD", &prd);
+    input.extend(be32(blocks.len()));
+    input.extend(blocks);
+    assert_eq!(new_demuxer_err(&input), "The MRW PRD block is truncated");
+}
+
+#[test]
+fn not_mrw_test() {
+    assert_eq!(new_demuxer_err(b"\0MRW\0\0\0\0"), "Not an MRW file");
+}
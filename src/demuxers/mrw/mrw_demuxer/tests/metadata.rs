@@ -0,0 +1,80 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+
+use super::{Mrw, MrwDemuxer, new_demuxer_err, parse_cameras, rows};
+
+macro_rules! with_demuxer {
+    ($input:expr, |$demuxer:ident| $body:block) => {{
+        let input = $input;
+        let cameras = parse_cameras();
+        let ($demuxer, _) = MrwDemuxer::new(
+            &input,
+            &cameras,
+            DecodeableCamera::new_unless_unsupported,
+        )
+        .unwrap();
+        $body
+    }};
+}
+
+fn assert_wb_coeffs(wb_coeffs: Option<[f32; 4]>, expected: [f32; 3]) {
+    let [red, green, blue, fourth] = wb_coeffs.unwrap();
+    assert_eq!(
+        [red, green, blue].map(f32::to_bits),
+        expected.map(f32::to_bits)
+    );
+    assert!(fourth.is_nan());
+}
+
+#[test]
+fn camera_from_cameras_xml_test() {
+    for endianness in [Endianness::Little, Endianness::Big] {
+        let mut mrw = Mrw::new(&rows(8, 2), true);
+        mrw.endianness = endianness;
+        with_demuxer!(mrw.build(), |demuxer| {
+            assert_eq!(demuxer.make(), "KONICA MINOLTA");
+            assert_eq!(demuxer.model(), "DYNAX 5D");
+            assert_eq!(demuxer.canonical_id(), "Konica Minolta Dynax 5D");
+            assert_eq!(demuxer.iso_speed(), Some(200));
+            assert_eq!(demuxer.whitelevel(), Some(4095));
+            assert!(demuxer.is_cfa());
+            assert!(demuxer.wb_coeffs().is_none());
+        });
+    }
+}
+
+#[test]
+fn wb_coeffs_test() {
+    let mut mrw = Mrw::new(&rows(8, 2), true);
+    mrw.wbg = Some([600, 256, 257, 400]);
+    with_demuxer!(mrw.build(), |demuxer| {
+        assert_wb_coeffs(demuxer.wb_coeffs(), [600.0, 256.0, 400.0]);
+    });
+}
+
+#[test]
+fn swapped_wb_coeffs_test() {
+    let mut mrw = Mrw::new(&rows(8, 2), true);
+    mrw.model = "DiMAGE A200";
+    mrw.wbg = Some([256, 400, 600, 256]);
+    with_demuxer!(mrw.build(), |demuxer| {
+        assert_wb_coeffs(demuxer.wb_coeffs(), [600.0, 256.0, 400.0]);
+    });
+}
+
+#[test]
+fn unknown_camera_test() {
+    let mut mrw = Mrw::new(&rows(8, 2), true);
+    mrw.model = "DYNAX 0D";
+    assert_eq!(
+        new_demuxer_err(&mrw.build()),
+        "Unknown camera: KONICA MINOLTA DYNAX 0D"
+    );
+    let mut unsupported = Mrw::new(&rows(8, 2), true);
+    unsupported.model = "Unsupported";
+    assert_eq!(
+        new_demuxer_err(&unsupported.build()),
+        "This camera is not supported (explicit)"
+    );
+}
@@ -0,0 +1,171 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::Cameras;
+use rawspeed_metadata_xmlparser::xmlparser;
+use rawspeed_parsers_tiffparser::tiffparser::TiffTag;
+use rawspeed_std::coord_common::RowIndex;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::pack;
+use rawspeed_utils_tiffbuilder::tiffbuilder::{TiffBuilder, Value, tag};
+
+use super::{MrwDemuxer, STORAGE_PACKED, STORAGE_UNPACKED};
+
+const CAMERAS: &str = "
+    <Cameras>
+        <Camera make=\"KONICA MINOLTA\" model=\"DYNAX 5D\">
+            <ID make=\"Konica Minolta\" model=\"Dynax 5D\">Konica Minolta Dynax 5D</ID>
+            <CFA width=\"2\" height=\"2\">
+                <Color x=\"0\" y=\"0\">RED</Color>
+                <Color x=\"1\" y=\"0\">GREEN</Color>
+                <Color x=\"0\" y=\"1\">GREEN</Color>
+                <Color x=\"1\" y=\"1\">BLUE</Color>
+            </CFA>
+            <Sensor black=\"0\" white=\"4095\"/>
+        </Camera>
+        <Camera make=\"KONICA MINOLTA\" model=\"DiMAGE A200\">
+            <ID make=\"Konica Minolta\" model=\"DiMAGE A200\">Konica Minolta DiMAGE A200</ID>
+            <Sensor black=\"0\" white=\"4095\"/>
+            <Hints>
+                <Hint name=\"swapped_wb\" value=\"\"/>
+            </Hints>
+        </Camera>
+        <Camera make=\"KONICA MINOLTA\" model=\"Unsupported\" supported=\"no\">
+        </Camera>
+    </Cameras>";
+
+fn be16(val: u16) -> impl Iterator<Item = u8> {
+    val.to_le_bytes().into_iter().rev()
+}
+
+fn be32(val: usize) -> impl Iterator<Item = u8> {
+    u32::try_from(val).unwrap().to_le_bytes().into_iter().rev()
+}
+
+fn block(tag: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut out = tag.to_vec();
+    out.extend(be32(data.len()));
+    out.extend(data);
+    out
+}
+
+fn rows(width: usize, height: usize) -> Vec<Vec<u16>> {
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| {
+                    u16::try_from((row * 977 + col * 313 + 5) % 0x1000).unwrap()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+struct Mrw {
+    endianness: Endianness,
+    model: &'static str,
+    width: u16,
+    height: u16,
+    bits: u8,
+    storage: u8,
+    data: Vec<u8>,
+    wbg: Option<[u16; 4]>,
+}
+
+impl Mrw {
+    fn new(rows: &[Vec<u16>], packed: bool) -> Self {
+        let (storage, data) = if packed {
+            (STORAGE_PACKED, pack(&rows.concat(), 12, BitOrder::MSB))
+        } else {
+            (
+                STORAGE_UNPACKED,
+                rows.concat().into_iter().flat_map(be16).collect(),
+            )
+        };
+        Self {
+            endianness: Endianness::Big,
+            model: "DYNAX 5D",
+            width: u16::try_from(rows.first().unwrap().len()).unwrap(),
+            height: u16::try_from(rows.len()).unwrap(),
+            bits: 12,
+            storage,
+            data,
+            wbg: None,
+        }
+    }
+
+    fn prd(&self) -> Vec<u8> {
+        let mut prd = b"21810002".to_vec();
+        for _ in 0..2 {
+            prd.extend(be16(self.height).chain(be16(self.width)));
+        }
+        prd.extend([self.bits, self.storage, 0, 0, 0, 0, 1]);
+        prd
+    }
+
+    fn ttw(&self) -> Vec<u8> {
+        let mut builder = TiffBuilder::new(self.endianness);
+        builder.ifd(0).extend([
+            tag(TiffTag::MAKE, Value::Ascii("KONICA MINOLTA")),
+            tag(TiffTag::MODEL, Value::Ascii(self.model)),
+            tag(TiffTag::EXIF_IFD_POINTER, Value::IFDOffset(1)),
+        ]);
+        builder
+            .ifd(1)
+            .push(tag(TiffTag::ISO_SPEED_RATINGS, Value::Short(vec![200])));
+        builder.build()
+    }
+
+    fn blocks(&self) -> Vec<u8> {
+        let mut blocks = block(b"\0PRD", &self.prd());
+        blocks.extend(block(b"\0TTW", &self.ttw()));
+        if let Some(levels) = self.wbg {
+            let mut wbg = vec![0; 4];
+            wbg.extend(levels.into_iter().flat_map(be16));
+            blocks.extend(block(b"\0WBG", &wbg));
+        }
+        blocks.extend(block(b"\0PAD", &[0; 8]));
+        blocks
+    }
+
+    fn build(&self) -> Vec<u8> {
+        let blocks = self.blocks();
+        let mut out = b"\0MRM".to_vec();
+        out.extend(be32(blocks.len()));
+        out.extend(blocks);
+        out.extend(&self.data);
+        out
+    }
+}
+
+fn parse_cameras() -> Cameras<'static> {
+    xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap()
+}
+
+fn new_demuxer_err(input: &[u8]) -> String {
+    let cameras = parse_cameras();
+    MrwDemuxer::new(input, &cameras, DecodeableCamera::new_unless_unsupported)
+        .unwrap_err()
+}
+
+fn decode(input: &[u8]) -> Result<Vec<Vec<u16>>, String> {
+    let cameras = parse_cameras();
+    let (demuxer, request) = MrwDemuxer::new(
+        input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )?;
+    let mut output_buf = request.fulfill().unwrap();
+    let mut image = output_buf.get_mut();
+    demuxer.decode(&mut image).map_err(|err| err.to_string())?;
+    let output = image.as_u16().unwrap();
+    Ok((0..output.num_rows().get())
+        .map(|row| output.get_row(RowIndex::new(row)).unwrap().to_vec())
+        .collect())
+}
+
+#[cfg(test)]
+mod data;
+#[cfg(test)]
+mod metadata;
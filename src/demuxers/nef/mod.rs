@@ -0,0 +1 @@
+pub mod nef_demuxer;
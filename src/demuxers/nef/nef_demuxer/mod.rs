@@ -0,0 +1,449 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_codecs_nikon::nikon::{NikonDecompressor, NikonMetadata};
+use rawspeed_codecs_packed_decoder::packed_decoder::Unpacker;
+use rawspeed_demuxers_common::{
+    camera_metadata::CameraMetadata,
+    tiff_utils::{
+        OwnedArray2D, get_root_string, get_strip, get_u32, get_usize, non_zero,
+        tiff_err,
+    },
+};
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::{
+    DataType, ImageRefMut, ProcurementRequest, RawDemuxer, RawDemuxerError,
+};
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_memory_nd_slice_procurement::ndsliceprocurement::NDSliceProcurementRequest;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::{
+    Cameras, Supported, blackareas::BlackArea,
+};
+use rawspeed_metadata_colorfilterarray::colorfilterarray::ColorVariant;
+use rawspeed_parsers_tiffparser::tiffparser::{
+    TiffEntry, TiffIFD, TiffParser, TiffRootIFD, TiffTag,
+};
+use rawspeed_std::coord_common::{
+    Coord2D, Dimensions2D, RowCount, RowLength, RowPitch,
+};
+use rawspeed_std_ndslice::{
+    array2dref::Array2DRef, offsetarray2dref::OffsetArray2DRef,
+};
+
+const COMPRESSION_NONE: u32 = 1;
+const COMPRESSION_NIKON: u32 = 34713;
+
+/// Most `MakerNote`s start with this signature, a version and two bytes
+/// of padding, followed by a complete TIFF (with its own byte order).
+const MAKERNOTE_SIGNATURE: &[u8] = b"Nikon\0";
+const MAKERNOTE_TIFF_OFFSET: usize = 10;
+
+/// The black levels are stored as 14-bit values.
+const BLACK_LEVEL_BITS: u32 = 14;
+
+type T = u16;
+
+fn parse_makernote<'a>(
+    root: &TiffRootIFD<'a>,
+) -> Result<Option<TiffRootIFD<'a>>, String> {
+    let Some(entry) = root.get_entry_recursive(TiffTag::MAKER_NOTE) else {
+        return Ok(None);
+    };
+    let data = entry.data();
+    let makernote = if data.starts_with(MAKERNOTE_SIGNATURE) {
+        let tiff = data
+            .get(MAKERNOTE_TIFF_OFFSET..)
+            .ok_or("The NEF MakerNote is truncated")?;
+        TiffParser::parse(tiff)
+    } else {
+        TiffParser::parse_ifd_chain(data, root.endianness(), 0)
+    };
+    makernote.map(Some).map_err(tiff_err)
+}
+
+/// The white balance is stored as the red and blue multipliers,
+/// optionally followed by the green one.
+fn parse_wb_coeffs(
+    makernote: &TiffRootIFD<'_>,
+) -> Result<Option<[f32; 4]>, String> {
+    let Some(entry) =
+        makernote.get_entry_recursive(TiffTag::NIKON_WB_RB_LEVELS)
+    else {
+        return Ok(None);
+    };
+    if entry.count() != 4 {
+        return Ok(None);
+    }
+    let [red, blue, green] =
+        [0, 1, 2].map(|index| entry.get_f32(index).map_err(tiff_err));
+    let green = green?;
+    Ok(Some([
+        red?,
+        if green == 0.0 { 1.0 } else { green },
+        blue?,
+        f32::NAN,
+    ]))
+}
+
+/// Per-CFA-position black levels, in the sample bit depth.
+fn parse_black_levels(
+    makernote: &TiffRootIFD<'_>,
+    bits: u32,
+) -> Result<Option<OwnedArray2D<i32>>, String> {
+    let Some(entry) = makernote.get_entry_recursive(TiffTag::NIKON_BLACK_LEVEL)
+    else {
+        return Ok(None);
+    };
+    if entry.count() != 4 || bits > BLACK_LEVEL_BITS {
+        return Ok(None);
+    }
+    let levels = (0..4)
+        .map(|index| {
+            entry
+                .get_u16(index)
+                .map(|level| i32::from(level >> (BLACK_LEVEL_BITS - bits)))
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(tiff_err)?;
+    OwnedArray2D::new(levels, 2).map(Some)
+}
+
+fn get_bit_order_hint(
+    metadata: &CameraMetadata<'_>,
+) -> Result<BitOrder, String> {
+    match metadata.hint("msb_override") {
+        None | Some("true") => Ok(BitOrder::MSB),
+        Some("false") => Ok(BitOrder::LSB),
+        Some(hint) => Err(format!("Invalid msb_override hint: {hint}")),
+    }
+}
+
+#[derive(Debug)]
+enum Format<'a> {
+    Compressed(NikonDecompressor<'a>),
+    Uncompressed {
+        input: Array2DRef<'a, u8>,
+        order: BitOrder,
+        bits: u32,
+    },
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct NefDemuxer<'a> {
+    metadata: CameraMetadata<'a>,
+    format: Format<'a>,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    black_levels: Option<OwnedArray2D<i32>>,
+    wb_coeffs: Option<[f32; 4]>,
+}
+
+impl<'a> NefDemuxer<'a> {
+    fn parse_dims(
+        ifd: &TiffIFD<'_>,
+    ) -> Result<Dimensions2D<core::num::NonZero<usize>>, String> {
+        let width = get_usize(ifd, TiffTag::IMAGE_WIDTH)?.unwrap_or(0);
+        let height = get_usize(ifd, TiffTag::IMAGE_LENGTH)?.unwrap_or(0);
+        Ok(Dimensions2D::new(
+            RowLength::new(non_zero(width, "width")?),
+            RowCount::new(non_zero(height, "height")?),
+        ))
+    }
+
+    fn parse_compressed(
+        strip: &'a [u8],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        bits: u32,
+        makernote: Option<&TiffRootIFD<'_>>,
+    ) -> Result<Format<'a>, String> {
+        let entry = makernote
+            .and_then(|makernote| {
+                makernote
+                    .get_entry_recursive(TiffTag::NIKON_LINEARIZATION_TABLE)
+            })
+            .ok_or("No decompression metadata found in NEF")?;
+        let metadata =
+            NikonMetadata::new(entry.data(), entry.endianness(), bits)
+                .map_err(|err| err.to_string())?;
+        NikonDecompressor::new(strip, dims, metadata)
+            .map(Format::Compressed)
+            .map_err(|err| err.to_string())
+    }
+
+    /// Samples are either stored in 16-bit containers, in the byte order
+    /// of the file, or bit-packed, with optional padding at the end
+    /// of each row.
+    fn parse_uncompressed(
+        strip: &'a [u8],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        bits: u32,
+        endianness: Endianness,
+        metadata: &CameraMetadata<'_>,
+    ) -> Result<Format<'a>, String> {
+        let (width, height) = (dims.row_len().get(), dims.row_count().get());
+        let bytes_per_row = strip.len() / height;
+        let (order, bits) = if bytes_per_row == width * size_of::<T>() {
+            let order = match endianness {
+                Endianness::Little => BitOrder::LSB,
+                Endianness::Big => BitOrder::MSB,
+            };
+            (order, T::BITS)
+        } else if bytes_per_row * 8 >= width * usize::try_from(bits).unwrap() {
+            (get_bit_order_hint(metadata)?, bits)
+        } else {
+            return Err("The NEF strip is too small".to_owned());
+        };
+        // Rows are read in whole chunks of the bit order.
+        if !bytes_per_row.is_multiple_of(order.mcu_bytelen()) {
+            return Err(format!("Unsupported NEF row pitch: {bytes_per_row}"));
+        }
+        let bytes_per_row = non_zero(bytes_per_row, "row pitch")?;
+        Ok(Format::Uncompressed {
+            input: Array2DRef::new(
+                strip.get(..bytes_per_row.get() * height).unwrap(),
+                RowLength::new(bytes_per_row),
+                RowPitch::new(bytes_per_row),
+            ),
+            order,
+            bits,
+        })
+    }
+
+    #[inline(never)]
+    pub fn new<F>(
+        root: &TiffRootIFD<'a>,
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<(Self, ProcurementRequest), String>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let ifd = root
+            .get_ifds_with_tag(TiffTag::CFA_PATTERN)
+            .into_iter()
+            .next()
+            .ok_or("No raw image found in NEF")?;
+        let dims = Self::parse_dims(ifd)?;
+        let bits = get_u32(ifd, TiffTag::BITS_PER_SAMPLE)?.unwrap_or(0);
+        if !(1..=T::BITS).contains(&bits) {
+            return Err(format!("Unsupported bits per sample: {bits}"));
+        }
+        let compression =
+            get_u32(ifd, TiffTag::COMPRESSION)?.unwrap_or(COMPRESSION_NONE);
+        let mode = match compression {
+            COMPRESSION_NONE => format!("{bits}bit-uncompressed"),
+            COMPRESSION_NIKON => format!("{bits}bit-compressed"),
+            _ => {
+                return Err(format!(
+                    "Unsupported NEF compression: {compression}"
+                ));
+            }
+        };
+
+        let make = get_root_string(root, TiffTag::MAKE).unwrap_or("");
+        let model = get_root_string(root, TiffTag::MODEL).unwrap_or("");
+        let camera = cameras
+            .find(make, model, Some(&mode))
+            .or_else(|| cameras.find(make, model, None))
+            .ok_or_else(|| format!("Unknown camera: {make} {model}"))?;
+        check_camera_support_fn(camera.supported)?;
+        let iso_speed = root
+            .get_entry_recursive(TiffTag::ISO_SPEED_RATINGS)
+            .map(TiffEntry::get_u32s)
+            .and_then(Result::ok)
+            .and_then(|isos| isos.first().copied());
+        let metadata = CameraMetadata::new(camera, make, model, iso_speed);
+
+        let makernote = parse_makernote(root)?;
+        let strip = get_strip(root.input(), ifd)?;
+        let format = if compression == COMPRESSION_NIKON {
+            Self::parse_compressed(strip, dims, bits, makernote.as_ref())?
+        } else {
+            Self::parse_uncompressed(
+                strip,
+                dims,
+                bits,
+                root.endianness(),
+                &metadata,
+            )?
+        };
+        let (black_levels, wb_coeffs) = match makernote {
+            Some(makernote) => (
+                parse_black_levels(&makernote, bits)?,
+                parse_wb_coeffs(&makernote)?,
+            ),
+            None => (None, None),
+        };
+        Ok((
+            Self {
+                metadata,
+                format,
+                dims,
+                black_levels,
+                wb_coeffs,
+            },
+            NDSliceProcurementRequest::<T>::new(dims).into(),
+        ))
+    }
+}
+
+#[expect(clippy::missing_trait_methods)]
+impl RawDemuxer for NefDemuxer<'_> {
+    #[inline]
+    fn make(&self) -> &str {
+        self.metadata.make
+    }
+
+    #[inline]
+    fn model(&self) -> &str {
+        self.metadata.model
+    }
+
+    #[inline]
+    fn mode(&self) -> Option<&str> {
+        self.metadata.mode()
+    }
+
+    #[inline]
+    fn canonical_make(&self) -> &str {
+        self.metadata.canonical_make()
+    }
+
+    #[inline]
+    fn canonical_model(&self) -> &str {
+        self.metadata.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_alias(&self) -> &str {
+        self.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_id(&self) -> String {
+        self.metadata.canonical_id()
+    }
+
+    #[inline]
+    fn iso_speed(&self) -> Option<u32> {
+        self.metadata.iso_speed
+    }
+
+    #[inline]
+    fn blacklevel(&self) -> Option<u16> {
+        self.metadata.blacklevel()
+    }
+
+    #[inline]
+    fn whitelevel(&self) -> Option<u16> {
+        self.metadata.whitelevel()
+    }
+
+    #[inline]
+    fn blacklevel_separate(&self) -> Option<Array2DRef<'_, i32>> {
+        self.black_levels.as_ref().map(OwnedArray2D::mat)
+    }
+
+    #[inline]
+    fn wb_coeffs(&self) -> Option<[f32; 4]> {
+        self.wb_coeffs
+    }
+
+    #[inline]
+    fn colormatrix(&self) -> Option<Array2DRef<'_, i16>> {
+        self.metadata.colormatrix()
+    }
+
+    #[inline]
+    fn is_cfa(&self) -> bool {
+        self.metadata.is_cfa()
+    }
+
+    #[inline]
+    fn cfa(
+        &self,
+        origin: Coord2D,
+    ) -> Option<OffsetArray2DRef<'_, ColorVariant>> {
+        self.metadata.cfa(origin)
+    }
+
+    #[inline]
+    fn bpp(&self) -> usize {
+        let bpc = match self.datatype() {
+            DataType::U16 => size_of::<u16>(),
+            DataType::F32 => size_of::<f32>(),
+            DataType::U32 => size_of::<u32>(),
+            _ => unreachable!(),
+        };
+        bpc.checked_mul(self.cpp()).unwrap()
+    }
+
+    #[inline]
+    fn cpp(&self) -> usize {
+        1
+    }
+
+    #[inline]
+    fn datatype(&self) -> DataType {
+        DataType::U16
+    }
+
+    #[inline]
+    fn dim_uncropped(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        self.dims
+    }
+
+    #[inline]
+    fn dim_cropped(&self) -> Option<Dimensions2D<core::num::NonZero<usize>>> {
+        self.metadata.dim_cropped(self.dim_uncropped())
+    }
+
+    #[inline]
+    fn crop_offset(&self) -> Option<Coord2D> {
+        self.metadata.crop_offset()
+    }
+
+    #[inline]
+    fn black_areas(&self) -> Option<&[BlackArea]> {
+        self.metadata.black_areas()
+    }
+
+    #[inline]
+    fn fuji_rotation_pos(&self) -> Option<u32> {
+        None
+    }
+
+    #[inline]
+    fn pixel_aspect_ratio(&self) -> Option<f64> {
+        None
+    }
+
+    #[inline]
+    fn bad_pixel_positions(&self) -> Vec<Coord2D> {
+        vec![]
+    }
+
+    #[inline(never)]
+    fn decode(
+        &self,
+        output: &mut ImageRefMut<'_>,
+    ) -> Result<(), RawDemuxerError> {
+        let output = output.as_u16()?;
+        if output.dims() != self.dims {
+            return Err(RawDemuxerError::DecoderError(
+                "Output buffer dimensions differ from expected".to_owned(),
+            ));
+        }
+        match &self.format {
+            Format::Compressed(decompressor) => decompressor
+                .decode(output)
+                .map_err(|err| RawDemuxerError::DecoderError(err.to_string())),
+            Format::Uncompressed { input, order, bits } => {
+                Unpacker::new(*input, *order, *bits, output).unpack();
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,132 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_parsers_tiffparser::tiffparser::TiffTag;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::pack;
+
+use super::{Nef, decode, new_demuxer_err};
+
+/// Distinct samples, spread over the given bit depth.
+fn rows(width: usize, height: usize, bits: u32) -> Vec<Vec<u16>> {
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| {
+                    let sample = (row * 1009 + col * 257 + 7) % (1 << bits);
+                    u16::try_from(sample).unwrap()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn compressed_test() {
+    let rows = rows(6, 4, 12);
+    assert_eq!(decode(&Nef::compressed(&rows)), Ok(rows));
+}
+
+#[test]
+fn compressed_without_metadata_test() {
+    let mut nef = Nef::compressed(&rows(6, 4, 12));
+    nef.makernote.clear();
+    assert_eq!(
+        new_demuxer_err(&nef.build()),
+        "No decompression metadata found in NEF"
+    );
+}
+
+#[test]
+fn compressed_truncated_test() {
+    let mut nef = Nef::compressed(&rows(6, 4, 12));
+    nef.data.truncate(4);
+    assert_eq!(
+        decode(&nef),
+        Err("RawDemuxerError(DecoderError(NikonError(TruncatedData)))"
+            .to_owned())
+    );
+}
+
+#[test]
+fn uncompressed_little_endian_test() {
+    let rows = rows(4, 2, 12);
+    let data = rows.iter().flatten().flat_map(|v| [v & 0xFF, v >> 8]);
+    let data = data.map(|b| u8::try_from(b).unwrap()).collect();
+    assert_eq!(decode(&Nef::uncompressed(4, 2, 12, data)), Ok(rows));
+}
+
+#[test]
+fn uncompressed_big_endian_test() {
+    let rows = rows(4, 2, 14);
+    let data = rows.iter().flatten().flat_map(|v| [v >> 8, v & 0xFF]);
+    let data = data.map(|b| u8::try_from(b).unwrap()).collect();
+    let mut nef = Nef::uncompressed(4, 2, 14, data);
+    nef.endianness = Endianness::Big;
+    assert_eq!(decode(&nef), Ok(rows));
+}
+
+#[test]
+fn packed_12_bit_test() {
+    let rows = rows(8, 3, 12);
+    let data = pack(&rows.concat(), 12, BitOrder::MSB);
+    assert_eq!(decode(&Nef::uncompressed(8, 3, 12, data)), Ok(rows));
+}
+
+#[test]
+fn packed_14_bit_padded_test() {
+    let rows = rows(8, 2, 14);
+    let data = rows
+        .iter()
+        .flat_map(|row| {
+            let mut packed = pack(row, 14, BitOrder::MSB);
+            packed.resize(20, 0);
+            packed
+        })
+        .collect();
+    assert_eq!(decode(&Nef::uncompressed(8, 2, 14, data)), Ok(rows));
+}
+
+#[test]
+fn packed_lsb_hint_test() {
+    let rows = rows(8, 2, 12);
+    let mut nef =
+        Nef::uncompressed(8, 2, 12, pack(&rows.concat(), 12, BitOrder::LSB));
+    nef.model = "NIKON D001";
+    assert_eq!(decode(&nef), Ok(rows));
+}
+
+#[test]
+fn strip_too_small_test() {
+    let data = pack(&rows(8, 2, 12).concat(), 12, BitOrder::MSB);
+    let data = data.get(..20).unwrap().to_vec();
+    assert_eq!(
+        new_demuxer_err(&Nef::uncompressed(8, 2, 12, data).build()),
+        "The NEF strip is too small"
+    );
+}
+
+#[test]
+fn odd_row_pitch_test() {
+    let rows = rows(6, 2, 12);
+    let data = pack(&rows.concat(), 12, BitOrder::MSB);
+    assert_eq!(decode(&Nef::uncompressed(6, 2, 12, data)), Ok(rows));
+}
+
+#[test]
+fn unsupported_compression_test() {
+    let mut nef = Nef::uncompressed(4, 2, 12, vec![0; 16]);
+    nef.compression = 7;
+    assert_eq!(
+        new_demuxer_err(&nef.build()),
+        "Unsupported NEF compression: 7"
+    );
+}
+
+#[test]
+fn no_raw_image_test() {
+    let mut builder = Nef::uncompressed(4, 2, 12, vec![0; 16]).builder();
+    builder.remove(TiffTag::CFA_PATTERN);
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "No raw image found in NEF"
+    );
+}
@@ -0,0 +1,142 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_std::coord_common::{
+    ColIndex, Coord2D, Dimensions2D, RowCount, RowIndex, RowLength,
+};
+use rawspeed_utils_tiffbuilder::tiffbuilder::{Value, tag};
+
+use super::{Nef, NefDemuxer, new_demuxer_err, parse_cameras};
+
+fn uncompressed(model: &'static str, bits: u16) -> Nef {
+    let mut nef = Nef::uncompressed(8, 2, bits, vec![0; 32]);
+    nef.model = model;
+    nef
+}
+
+macro_rules! with_demuxer {
+    ($nef:expr, |$demuxer:ident| $body:block) => {{
+        let input = $nef.build();
+        let root = TiffParser::parse(&input).unwrap();
+        let cameras = parse_cameras();
+        let ($demuxer, _) = NefDemuxer::new(
+            &root,
+            &cameras,
+            DecodeableCamera::new_unless_unsupported,
+        )
+        .unwrap();
+        $body
+    }};
+}
+
+#[test]
+fn camera_from_cameras_xml_test() {
+    with_demuxer!(uncompressed("NIKON D000", 12), |demuxer| {
+        assert_eq!(demuxer.make(), "NIKON CORPORATION");
+        assert_eq!(demuxer.model(), "NIKON D000");
+        assert_eq!(demuxer.mode(), None);
+        assert_eq!(demuxer.canonical_make(), "Nikon");
+        assert_eq!(demuxer.canonical_model(), "D000");
+        assert_eq!(demuxer.canonical_id(), "Nikon D000");
+        assert_eq!(demuxer.iso_speed(), Some(200));
+        assert_eq!(demuxer.blacklevel(), Some(150));
+        assert_eq!(demuxer.whitelevel(), Some(4095));
+    });
+}
+
+#[test]
+fn image_layout_test() {
+    with_demuxer!(uncompressed("NIKON D000", 12), |demuxer| {
+        assert!(demuxer.is_cfa());
+        assert_eq!(demuxer.cpp(), 1);
+        assert_eq!(
+            demuxer.dim_cropped(),
+            Some(Dimensions2D::new(
+                RowLength::new(core::num::NonZero::new(4).unwrap()),
+                RowCount::new(core::num::NonZero::new(2).unwrap()),
+            ))
+        );
+    });
+}
+
+#[test]
+fn camera_mode_test() {
+    let rows = vec![vec![0; 4]; 2];
+    let mut nef = Nef::compressed(&rows);
+    nef.bits = 14;
+    // The metadata describes a lossless 14-bit image.
+    with_demuxer!(nef, |demuxer| {
+        assert_eq!(demuxer.mode(), Some("14bit-compressed"));
+        assert_eq!(demuxer.blacklevel(), Some(600));
+        assert_eq!(demuxer.whitelevel(), Some(16383));
+    });
+}
+
+#[test]
+fn white_balance_test() {
+    let mut nef = uncompressed("NIKON D000", 12);
+    nef.makernote = vec![tag(
+        TiffTag::NIKON_WB_RB_LEVELS,
+        Value::Rational(vec![(2, 1), (3, 2), (0, 1), (0, 1)]),
+    )];
+    with_demuxer!(nef, |demuxer| {
+        let [red, green, blue, fourth] = demuxer.wb_coeffs().unwrap();
+        assert_eq!(
+            [red, green, blue].map(f32::to_bits),
+            [2.0_f32.to_bits(), 1.0_f32.to_bits(), 1.5_f32.to_bits()]
+        );
+        assert!(fourth.is_nan());
+    });
+}
+
+#[test]
+fn black_levels_test() {
+    let mut nef = uncompressed("NIKON D000", 12);
+    nef.makernote = vec![tag(
+        TiffTag::NIKON_BLACK_LEVEL,
+        Value::Short(vec![600, 604, 608, 612]),
+    )];
+    with_demuxer!(nef, |demuxer| {
+        let levels = demuxer.blacklevel_separate().unwrap();
+        let level = |row, col| {
+            levels[Coord2D::new(RowIndex::new(row), ColIndex::new(col))]
+        };
+        assert_eq!(
+            [level(0, 0), level(0, 1), level(1, 0), level(1, 1)],
+            [150, 151, 152, 153]
+        );
+    });
+}
+
+#[test]
+fn no_makernote_test() {
+    let mut builder = uncompressed("NIKON D000", 12).builder();
+    builder.remove(TiffTag::MAKER_NOTE);
+    let input = builder.build();
+    let root = TiffParser::parse(&input).unwrap();
+    let cameras = parse_cameras();
+    let (demuxer, _) = NefDemuxer::new(
+        &root,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )
+    .unwrap();
+    assert!(demuxer.wb_coeffs().is_none());
+    assert!(demuxer.blacklevel_separate().is_none());
+}
+
+#[test]
+fn unknown_camera_test() {
+    assert_eq!(
+        new_demuxer_err(&uncompressed("NIKON D999", 12).build()),
+        "Unknown camera: NIKON CORPORATION NIKON D999"
+    );
+}
+
+#[test]
+fn unsupported_camera_test() {
+    assert_eq!(
+        new_demuxer_err(&uncompressed("NIKON Unsupported", 12).build()),
+        "This camera is not supported (explicit)"
+    );
+}
@@ -0,0 +1,211 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_codecs_huffman::huffman::HuffmanCode;
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::Cameras;
+use rawspeed_metadata_xmlparser::xmlparser;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_std::coord_common::RowIndex;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+use rawspeed_utils_tiffbuilder::tiffbuilder::{TiffBuilder, Value, tag};
+
+use super::{COMPRESSION_NIKON, COMPRESSION_NONE, NefDemuxer};
+
+/// The 12-bit lossless Nikon tree.
+const COUNTS: [u8; 16] = [0, 1, 4, 2, 3, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+const SYMBOLS: [u8; 13] = [5, 4, 6, 3, 7, 2, 8, 1, 9, 0, 10, 11, 12];
+
+/// The initial vertical predictions of the compressed test data.
+const VPRED: [u16; 4] = [100, 200, 300, 400];
+
+const CAMERAS: &str = "
+    <Cameras>
+        <Camera make=\"NIKON CORPORATION\" model=\"NIKON D000\">
+            <ID make=\"Nikon\" model=\"D000\">Nikon D000</ID>
+            <CFA width=\"2\" height=\"2\">
+                <Color x=\"0\" y=\"0\">RED</Color>
+                <Color x=\"1\" y=\"0\">GREEN</Color>
+                <Color x=\"0\" y=\"1\">GREEN</Color>
+                <Color x=\"1\" y=\"1\">BLUE</Color>
+            </CFA>
+            <Crop x=\"2\" y=\"0\" width=\"-2\" height=\"0\"/>
+            <Sensor black=\"150\" white=\"4095\"/>
+        </Camera>
+        <Camera make=\"NIKON CORPORATION\" model=\"NIKON D000\" mode=\"14bit-compressed\">
+            <ID make=\"Nikon\" model=\"D000\">Nikon D000</ID>
+            <Sensor black=\"600\" white=\"16383\"/>
+        </Camera>
+        <Camera make=\"NIKON CORPORATION\" model=\"NIKON D001\">
+            <Hints>
+                <Hint name=\"msb_override\" value=\"false\"/>
+            </Hints>
+        </Camera>
+        <Camera make=\"NIKON CORPORATION\" model=\"NIKON Unsupported\" supported=\"no\">
+        </Camera>
+    </Cameras>";
+
+fn be16(val: u16) -> [u8; 2] {
+    [
+        (val >> 8).try_into().unwrap(),
+        (val & 0xFF).try_into().unwrap(),
+    ]
+}
+
+/// Encodes the rows with the 12-bit lossless tree, starting from
+/// the [`VPRED`] predictions.
+fn compress(rows: &[Vec<u16>]) -> Vec<u8> {
+    let codes = HuffmanCode::new(&COUNTS, &SYMBOLS).unwrap().codes();
+    let mut writer = BitStreamBuilder::new();
+    for (row, samples) in rows.iter().enumerate() {
+        for (col, sample) in samples.iter().enumerate() {
+            let pred = match (row.checked_sub(2), col.checked_sub(2)) {
+                (_, Some(col)) => samples.get(col),
+                (Some(row), None) => rows.get(row).unwrap().get(col),
+                (None, None) => VPRED.get(col * 2 + row),
+            };
+            let diff = i32::from(*sample) - i32::from(*pred.unwrap());
+            let len = 32 - diff.unsigned_abs().leading_zeros();
+            let index =
+                SYMBOLS.iter().position(|s| u32::from(*s) == len).unwrap();
+            let (code_len, code) = *codes.get(index).unwrap();
+            writer.put(code, code_len);
+            let value = if diff < 0 {
+                diff + (1 << len) - 1
+            } else {
+                diff
+            };
+            writer.put(u32::try_from(value).unwrap(), len);
+        }
+    }
+    writer.finish(BitOrder::MSB)
+}
+
+/// The lossless decompression metadata, without a curve.
+fn lossless_metadata() -> Vec<u8> {
+    let mut meta = vec![0x46, 0x30];
+    for pred in VPRED {
+        meta.extend(be16(pred));
+    }
+    meta.extend(be16(0));
+    meta
+}
+
+/// A big-endian `MakerNote` (with the Nikon signature).
+fn makernote(entries: Vec<(u16, Value)>) -> Vec<u8> {
+    let mut builder = TiffBuilder::new(Endianness::Big);
+    *builder.ifd(0) = entries;
+    let mut out = b"Nikon\0\x02\x10\0\0".to_vec();
+    out.extend(builder.build());
+    out
+}
+
+#[derive(Debug)]
+struct Nef {
+    endianness: Endianness,
+    model: &'static str,
+    width: u32,
+    height: u32,
+    bits: u16,
+    compression: u16,
+    data: Vec<u8>,
+    makernote: Vec<(u16, Value)>,
+}
+
+impl Nef {
+    fn compressed(rows: &[Vec<u16>]) -> Self {
+        Self {
+            endianness: Endianness::Little,
+            model: "NIKON D000",
+            width: u32::try_from(rows.first().unwrap().len()).unwrap(),
+            height: u32::try_from(rows.len()).unwrap(),
+            bits: 12,
+            compression: u16::try_from(COMPRESSION_NIKON).unwrap(),
+            data: compress(rows),
+            makernote: vec![tag(
+                TiffTag::NIKON_LINEARIZATION_TABLE,
+                Value::Undefined(lossless_metadata()),
+            )],
+        }
+    }
+
+    fn uncompressed(width: u32, height: u32, bits: u16, data: Vec<u8>) -> Self {
+        Self {
+            endianness: Endianness::Little,
+            model: "NIKON D000",
+            width,
+            height,
+            bits,
+            compression: u16::try_from(COMPRESSION_NONE).unwrap(),
+            data,
+            makernote: vec![],
+        }
+    }
+
+    fn builder(&self) -> TiffBuilder {
+        let mut builder = TiffBuilder::new(self.endianness);
+        builder.ifd(0).extend([
+            tag(TiffTag::MAKE, Value::Ascii("NIKON CORPORATION")),
+            tag(TiffTag::MODEL, Value::Ascii(self.model)),
+            tag(TiffTag::SUB_IFDS, Value::IFDOffset(1)),
+            tag(TiffTag::EXIF_IFD_POINTER, Value::IFDOffset(2)),
+        ]);
+        builder.ifd(1).extend([
+            tag(TiffTag::IMAGE_WIDTH, Value::Long(vec![self.width])),
+            tag(TiffTag::IMAGE_LENGTH, Value::Long(vec![self.height])),
+            tag(TiffTag::BITS_PER_SAMPLE, Value::Short(vec![self.bits])),
+            tag(TiffTag::COMPRESSION, Value::Short(vec![self.compression])),
+            tag(TiffTag::CFA_REPEAT_PATTERN_DIM, Value::Short(vec![2, 2])),
+            tag(TiffTag::CFA_PATTERN, Value::Byte(vec![0, 1, 1, 2])),
+            tag(TiffTag::STRIP_OFFSETS, Value::BlobOffsets(vec![0])),
+            tag(TiffTag::STRIP_BYTE_COUNTS, Value::BlobSizes(vec![0])),
+        ]);
+        builder.ifd(2).extend([
+            tag(TiffTag::ISO_SPEED_RATINGS, Value::Short(vec![200])),
+            tag(
+                TiffTag::MAKER_NOTE,
+                Value::Undefined(makernote(self.makernote.clone())),
+            ),
+        ]);
+        builder.blobs.push(self.data.clone());
+        builder
+    }
+
+    fn build(&self) -> Vec<u8> {
+        self.builder().build()
+    }
+}
+
+fn parse_cameras() -> Cameras<'static> {
+    xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap()
+}
+
+fn new_demuxer_err(input: &[u8]) -> String {
+    let root = TiffParser::parse(input).unwrap();
+    let cameras = parse_cameras();
+    NefDemuxer::new(&root, &cameras, DecodeableCamera::new_unless_unsupported)
+        .unwrap_err()
+}
+
+fn decode(nef: &Nef) -> Result<Vec<Vec<u16>>, String> {
+    let input = nef.build();
+    let root = TiffParser::parse(&input).unwrap();
+    let cameras = parse_cameras();
+    let (demuxer, request) = NefDemuxer::new(
+        &root,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )?;
+    let mut output_buf = request.fulfill().unwrap();
+    let mut image = output_buf.get_mut();
+    demuxer.decode(&mut image).map_err(|err| err.to_string())?;
+    let output = image.as_u16().unwrap();
+    Ok((0..output.num_rows().get())
+        .map(|row| output.get_row(RowIndex::new(row)).unwrap().to_vec())
+        .collect())
+}
+
+#[cfg(test)]
+mod data;
+#[cfg(test)]
+mod metadata;
@@ -0,0 +1 @@
+pub mod orf_demuxer;
@@ -0,0 +1,632 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_codecs_olympus::olympus::OlympusDecompressor;
+use rawspeed_codecs_packed_decoder::packed_decoder::Unpacker;
+use rawspeed_demuxers_common::{
+    camera_metadata::CameraMetadata,
+    tiff_utils::{
+        OwnedArray2D, get_root_string, get_u32, get_usize, non_zero, tiff_err,
+    },
+};
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::{
+    DataType, ImageRefMut, ProcurementRequest, RawDemuxer, RawDemuxerError,
+};
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_memory_nd_slice_procurement::ndsliceprocurement::NDSliceProcurementRequest;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::{
+    Cameras, Supported, blackareas::BlackArea,
+};
+use rawspeed_metadata_colorfilterarray::colorfilterarray::ColorVariant;
+use rawspeed_parsers_tiffparser::tiffparser::{
+    TiffDataType, TiffEntry, TiffIFD, TiffParser, TiffRootIFD, TiffTag,
+};
+use rawspeed_std::coord_common::{
+    ColIndex, Coord2D, Dimensions2D, RowCount, RowIndex, RowLength, RowPitch,
+};
+use rawspeed_std_ndslice::{
+    array2dref::Array2DRef, offsetarray2dref::OffsetArray2DRef,
+};
+
+const COMPRESSION_NONE: u32 = 1;
+
+/// The `MakerNote` of newer cameras starts with this signature and the
+/// byte order of its IFD, which follows at a fixed offset. All offsets
+/// are relative to the start of the `MakerNote`.
+const MAKERNOTE_SIGNATURE: &[u8] = b"OLYMPUS\0";
+const MAKERNOTE_BYTE_ORDER_OFFSET: usize = 8;
+const MAKERNOTE_IFD_OFFSET: usize = 12;
+
+/// The `MakerNote` of older cameras has a shorter signature, and its
+/// offsets are relative to the start of the file.
+const OLD_MAKERNOTE_SIGNATURE: &[u8] = b"OLYMP\0";
+const OLD_MAKERNOTE_IFD_OFFSET: usize = 8;
+
+/// The white balance multipliers are relative to a green one of 256.
+const WB_GREEN: f32 = 256.0;
+
+/// The bit depth of all the uncompressed layouts.
+const PACKED_BITS: u32 = 12;
+
+/// In the packed layout "with control", a control byte follows every
+/// group of this many pixels.
+const CONTROL_GROUP_LEN: usize = 10;
+
+/// The second field of the interlaced layout starts at a multiple of this.
+const FIELD_ALIGNMENT: usize = 2048;
+
+type T = u16;
+
+/// The raw image may be stored in several strips, which are read as one:
+/// some uncompressed layouts have padding between the strips.
+fn get_slices<'a>(
+    input: &'a [u8],
+    ifd: &TiffIFD<'_>,
+) -> Result<(&'a [u8], usize), String> {
+    let offsets = ifd
+        .get_required_entry(TiffTag::STRIP_OFFSETS)
+        .and_then(TiffEntry::get_u32s)
+        .map_err(tiff_err)?;
+    let counts = ifd
+        .get_required_entry(TiffTag::STRIP_BYTE_COUNTS)
+        .and_then(TiffEntry::get_u32s)
+        .map_err(tiff_err)?;
+    let Some(first) = offsets.first().filter(|_| offsets.len() == counts.len())
+    else {
+        return Err("The strip list is invalid".to_owned());
+    };
+    let start = usize::try_from(*first).unwrap();
+    let mut end = start;
+    for (offset, count) in offsets.iter().zip(&counts) {
+        let offset = usize::try_from(*offset).unwrap();
+        if *count == 0 {
+            return Err("The ORF strip is empty".to_owned());
+        }
+        if offset < end {
+            return Err("The ORF strips overlap".to_owned());
+        }
+        end = offset
+            .checked_add(usize::try_from(*count).unwrap())
+            .ok_or("Overflow when computing the strip size")?;
+    }
+    let strips = input.get(start..end).ok_or("The strip data is truncated")?;
+    Ok((strips, offsets.len()))
+}
+
+fn parse_makernote<'a>(
+    root: &TiffRootIFD<'a>,
+) -> Result<Option<TiffRootIFD<'a>>, String> {
+    let Some(entry) = root.get_entry_recursive(TiffTag::MAKER_NOTE) else {
+        return Ok(None);
+    };
+    let data = entry.data();
+    let makernote = if data.starts_with(MAKERNOTE_SIGNATURE) {
+        let endianness = match data
+            .get(MAKERNOTE_BYTE_ORDER_OFFSET..MAKERNOTE_BYTE_ORDER_OFFSET + 2)
+        {
+            Some(b"II") => Endianness::Little,
+            Some(b"MM") => Endianness::Big,
+            Some(_) | None => {
+                return Err(
+                    "The ORF MakerNote byte order is invalid".to_owned()
+                );
+            }
+        };
+        TiffParser::parse_ifd_chain(data, endianness, MAKERNOTE_IFD_OFFSET)
+    } else if data.starts_with(OLD_MAKERNOTE_SIGNATURE) {
+        TiffParser::parse_ifd_chain(
+            root.input(),
+            root.endianness(),
+            entry.data_offset() + OLD_MAKERNOTE_IFD_OFFSET,
+        )
+    } else {
+        return Ok(None);
+    };
+    makernote.map(Some).map_err(tiff_err)
+}
+
+/// The `ImageProcessing` sub-IFD of the `MakerNote` is either pointed to,
+/// or (in older cameras) embedded as an `UNDEFINED` value.
+fn parse_image_processing<'a>(
+    makernote: &TiffRootIFD<'a>,
+) -> Result<Option<TiffRootIFD<'a>>, String> {
+    let Some(entry) =
+        makernote.get_entry_recursive(TiffTag::OLYMPUS_IMAGE_PROCESSING)
+    else {
+        return Ok(None);
+    };
+    let offset = if entry.datatype() == TiffDataType::Undefined {
+        entry.data_offset()
+    } else {
+        usize::try_from(entry.get_u32(0).map_err(tiff_err)?).unwrap()
+    };
+    TiffParser::parse_ifd_chain(
+        makernote.input(),
+        makernote.endianness(),
+        offset,
+    )
+    .map(Some)
+    .map_err(tiff_err)
+}
+
+/// Older cameras store the red and blue multipliers directly in the
+/// `MakerNote`, newer ones in its `ImageProcessing` sub-IFD.
+fn parse_wb_coeffs(
+    makernote: &TiffRootIFD<'_>,
+    image_processing: Option<&TiffRootIFD<'_>>,
+) -> Result<Option<[f32; 4]>, String> {
+    let (red, blue) = if let (Some(red), Some(blue)) = (
+        makernote.get_entry_recursive(TiffTag::OLYMPUS_RED_MULTIPLIER),
+        makernote.get_entry_recursive(TiffTag::OLYMPUS_BLUE_MULTIPLIER),
+    ) {
+        (red.get_f32(0), blue.get_f32(0))
+    } else if let Some(levels) = image_processing
+        .and_then(|ifd| ifd.get_entry_recursive(TiffTag::OLYMPUS_WB_RB_LEVELS))
+        .filter(|levels| matches!(levels.count(), 2 | 4))
+    {
+        (levels.get_f32(0), levels.get_f32(1))
+    } else {
+        return Ok(None);
+    };
+    Ok(Some([
+        red.map_err(tiff_err)?,
+        WB_GREEN,
+        blue.map_err(tiff_err)?,
+        f32::NAN,
+    ]))
+}
+
+/// The black levels are stored in the RGGB order, and are rearranged to
+/// match the CFA of the camera.
+#[expect(clippy::wildcard_enum_match_arm)]
+fn parse_black_levels(
+    image_processing: &TiffRootIFD<'_>,
+    cfa: OffsetArray2DRef<'_, ColorVariant>,
+) -> Result<Option<OwnedArray2D<i32>>, String> {
+    let Some(entry) =
+        image_processing.get_entry_recursive(TiffTag::OLYMPUS_BLACK_LEVEL2)
+    else {
+        return Ok(None);
+    };
+    if entry.count() != 4
+        || cfa.row_length().get() < 2
+        || cfa.num_rows().get() < 2
+    {
+        return Ok(None);
+    }
+    let mut levels = Vec::with_capacity(4);
+    for index in 0..4 {
+        let position =
+            Coord2D::new(RowIndex::new(index / 2), ColIndex::new(index % 2));
+        let rggb_index = match cfa[position] {
+            ColorVariant::Red => 0,
+            ColorVariant::Green => {
+                if index < 2 {
+                    1
+                } else {
+                    2
+                }
+            }
+            ColorVariant::Blue => 3,
+            color => {
+                return Err(format!("Unexpected CFA color: {color:?}"));
+            }
+        };
+        levels.push(i32::from(entry.get_u16(rggb_index).map_err(tiff_err)?));
+    }
+    OwnedArray2D::new(levels, 2).map(Some)
+}
+
+#[derive(Debug)]
+enum Format<'a> {
+    Compressed(OlympusDecompressor<'a>),
+    Unpacked {
+        input: Array2DRef<'a, u8>,
+        order: BitOrder,
+        bits: u32,
+        /// By how much the (left-aligned) samples are shifted down.
+        shift: u32,
+    },
+    /// 12-bit rows that are not contiguous in the file, copied out of it.
+    Gathered {
+        input: OwnedArray2D<u8>,
+        order: BitOrder,
+    },
+}
+
+/// Copies each row of `row_bytes` bytes, found at the offset given by
+/// `row_offset`.
+fn gather_rows(
+    strips: &[u8],
+    height: usize,
+    row_bytes: usize,
+    row_offset: impl Fn(usize) -> usize,
+) -> Result<OwnedArray2D<u8>, String> {
+    let mut data = Vec::with_capacity(row_bytes * height);
+    for row in 0..height {
+        let start = row_offset(row);
+        let bytes = strips
+            .get(start..)
+            .and_then(|rest| rest.get(..row_bytes))
+            .ok_or("The ORF strip is too small")?;
+        data.extend(bytes);
+    }
+    OwnedArray2D::new(data, row_bytes)
+}
+
+/// The uncompressed layouts are told apart from the compressed one, and
+/// from each other, by their size.
+fn parse_uncompressed(
+    strips: &[u8],
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    endianness: Endianness,
+) -> Result<Option<Format<'_>>, String> {
+    let (width, height) = (dims.row_len().get(), dims.row_count().get());
+    let row_bytes = width * 3 / 2;
+    let size = strips.len();
+    if width.is_multiple_of(CONTROL_GROUP_LEN)
+        && size == height * (row_bytes + width / CONTROL_GROUP_LEN)
+    {
+        // Little-endian 12-bit data, where every group of pixels is
+        // followed by a control byte.
+        let group_bytes = CONTROL_GROUP_LEN * 3 / 2;
+        let data: Vec<u8> = strips
+            .chunks_exact(group_bytes + 1)
+            .flat_map(|group| group.iter().take(group_bytes))
+            .copied()
+            .collect();
+        let input =
+            gather_rows(&data, height, row_bytes, |row| row * row_bytes)?;
+        return Ok(Some(Format::Gathered {
+            input,
+            order: BitOrder::LSB,
+        }));
+    }
+    if size == height * row_bytes {
+        let order = BitOrder::MSB32;
+        // Rows are read in whole chunks of the bit order.
+        if !row_bytes.is_multiple_of(order.mcu_bytelen()) {
+            return Err(format!("Unsupported ORF row pitch: {row_bytes}"));
+        }
+        let row_bytes = non_zero(row_bytes, "row pitch")?;
+        return Ok(Some(Format::Unpacked {
+            input: Array2DRef::new(
+                strips,
+                RowLength::new(row_bytes),
+                RowPitch::new(row_bytes),
+            ),
+            order,
+            bits: PACKED_BITS,
+            shift: 0,
+        }));
+    }
+    if size == height * width * size_of::<T>() {
+        // 16-bit containers, where the big-endian ones are left-aligned.
+        let (order, shift) = match endianness {
+            Endianness::Little => (BitOrder::LSB, 0),
+            Endianness::Big => (BitOrder::MSB, T::BITS - PACKED_BITS),
+        };
+        let container_bytes = non_zero(width * size_of::<T>(), "row pitch")?;
+        return Ok(Some(Format::Unpacked {
+            input: Array2DRef::new(
+                strips,
+                RowLength::new(container_bytes),
+                RowPitch::new(container_bytes),
+            ),
+            order,
+            bits: T::BITS,
+            shift,
+        }));
+    }
+    if size > height * row_bytes {
+        // Big-endian 12-bit data, stored as two fields: the even rows,
+        // then the odd rows.
+        let half = height.div_ceil(2);
+        let second_field =
+            (half * row_bytes / FIELD_ALIGNMENT + 1) * FIELD_ALIGNMENT;
+        let input = gather_rows(strips, height, row_bytes, |row| {
+            let field = if row % 2 == 0 { 0 } else { second_field };
+            field + row / 2 * row_bytes
+        })?;
+        return Ok(Some(Format::Gathered {
+            input,
+            order: BitOrder::MSB,
+        }));
+    }
+    Ok(None)
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct OrfDemuxer<'a> {
+    metadata: CameraMetadata<'a>,
+    format: Format<'a>,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    black_levels: Option<OwnedArray2D<i32>>,
+    wb_coeffs: Option<[f32; 4]>,
+}
+
+impl<'a> OrfDemuxer<'a> {
+    fn parse_dims(
+        ifd: &TiffIFD<'_>,
+    ) -> Result<Dimensions2D<core::num::NonZero<usize>>, String> {
+        let width = get_usize(ifd, TiffTag::IMAGE_WIDTH)?.unwrap_or(0);
+        let height = get_usize(ifd, TiffTag::IMAGE_LENGTH)?.unwrap_or(0);
+        // Samples come in pairs of the same row.
+        if !width.is_multiple_of(2) {
+            return Err(format!("Unsupported ORF width: {width}"));
+        }
+        Ok(Dimensions2D::new(
+            RowLength::new(non_zero(width, "width")?),
+            RowCount::new(non_zero(height, "height")?),
+        ))
+    }
+
+    fn parse_format(
+        root: &TiffRootIFD<'a>,
+        ifd: &TiffIFD<'_>,
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+    ) -> Result<Format<'a>, String> {
+        let (strips, num_strips) = get_slices(root.input(), ifd)?;
+        if let Some(format) =
+            parse_uncompressed(strips, dims, root.endianness())?
+        {
+            return Ok(format);
+        }
+        if num_strips != 1 {
+            return Err(format!(
+                "Unsupported ORF strip count for compressed data: {num_strips}"
+            ));
+        }
+        OlympusDecompressor::new(strips, dims)
+            .map(Format::Compressed)
+            .map_err(|err| err.to_string())
+    }
+
+    #[inline(never)]
+    pub fn new<F>(
+        root: &TiffRootIFD<'a>,
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<(Self, ProcurementRequest), String>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let ifd = root
+            .get_ifds_with_tag(TiffTag::STRIP_OFFSETS)
+            .into_iter()
+            .next()
+            .ok_or("No raw image found in ORF")?;
+        let compression =
+            get_u32(ifd, TiffTag::COMPRESSION)?.unwrap_or(COMPRESSION_NONE);
+        if compression != COMPRESSION_NONE {
+            return Err(format!("Unsupported ORF compression: {compression}"));
+        }
+        let dims = Self::parse_dims(ifd)?;
+
+        let make = get_root_string(root, TiffTag::MAKE).unwrap_or("");
+        let model = get_root_string(root, TiffTag::MODEL).unwrap_or("");
+        let camera = cameras
+            .find(make, model, None)
+            .ok_or_else(|| format!("Unknown camera: {make} {model}"))?;
+        check_camera_support_fn(camera.supported)?;
+        let iso_speed = root
+            .get_entry_recursive(TiffTag::ISO_SPEED_RATINGS)
+            .map(TiffEntry::get_u32s)
+            .and_then(Result::ok)
+            .and_then(|isos| isos.first().copied());
+        let metadata = CameraMetadata::new(camera, make, model, iso_speed);
+
+        let format = Self::parse_format(root, ifd, dims)?;
+        let (black_levels, wb_coeffs) = match parse_makernote(root)? {
+            Some(makernote) => {
+                let image_processing = parse_image_processing(&makernote)?;
+                let black_levels = match (
+                    &image_processing,
+                    metadata
+                        .cfa(Coord2D::new(RowIndex::new(0), ColIndex::new(0))),
+                ) {
+                    (Some(image_processing), Some(cfa)) => {
+                        parse_black_levels(image_processing, cfa)?
+                    }
+                    (None, _) | (_, None) => None,
+                };
+                (
+                    black_levels,
+                    parse_wb_coeffs(&makernote, image_processing.as_ref())?,
+                )
+            }
+            None => (None, None),
+        };
+        Ok((
+            Self {
+                metadata,
+                format,
+                dims,
+                black_levels,
+                wb_coeffs,
+            },
+            NDSliceProcurementRequest::<T>::new(dims).into(),
+        ))
+    }
+}
+
+#[expect(clippy::missing_trait_methods)]
+impl RawDemuxer for OrfDemuxer<'_> {
+    #[inline]
+    fn make(&self) -> &str {
+        self.metadata.make
+    }
+
+    #[inline]
+    fn model(&self) -> &str {
+        self.metadata.model
+    }
+
+    #[inline]
+    fn mode(&self) -> Option<&str> {
+        self.metadata.mode()
+    }
+
+    #[inline]
+    fn canonical_make(&self) -> &str {
+        self.metadata.canonical_make()
+    }
+
+    #[inline]
+    fn canonical_model(&self) -> &str {
+        self.metadata.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_alias(&self) -> &str {
+        self.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_id(&self) -> String {
+        self.metadata.canonical_id()
+    }
+
+    #[inline]
+    fn iso_speed(&self) -> Option<u32> {
+        self.metadata.iso_speed
+    }
+
+    #[inline]
+    fn blacklevel(&self) -> Option<u16> {
+        self.metadata.blacklevel()
+    }
+
+    #[inline]
+    fn whitelevel(&self) -> Option<u16> {
+        self.metadata.whitelevel()
+    }
+
+    #[inline]
+    fn blacklevel_separate(&self) -> Option<Array2DRef<'_, i32>> {
+        self.black_levels.as_ref().map(OwnedArray2D::mat)
+    }
+
+    #[inline]
+    fn wb_coeffs(&self) -> Option<[f32; 4]> {
+        self.wb_coeffs
+    }
+
+    #[inline]
+    fn colormatrix(&self) -> Option<Array2DRef<'_, i16>> {
+        self.metadata.colormatrix()
+    }
+
+    #[inline]
+    fn is_cfa(&self) -> bool {
+        self.metadata.is_cfa()
+    }
+
+    #[inline]
+    fn cfa(
+        &self,
+        origin: Coord2D,
+    ) -> Option<OffsetArray2DRef<'_, ColorVariant>> {
+        self.metadata.cfa(origin)
+    }
+
+    #[inline]
+    fn bpp(&self) -> usize {
+        let bpc = match self.datatype() {
+            DataType::U16 => size_of::<u16>(),
+            DataType::F32 => size_of::<f32>(),
+            DataType::U32 => size_of::<u32>(),
+            _ => unreachable!(),
+        };
+        bpc.checked_mul(self.cpp()).unwrap()
+    }
+
+    #[inline]
+    fn cpp(&self) -> usize {
+        1
+    }
+
+    #[inline]
+    fn datatype(&self) -> DataType {
+        DataType::U16
+    }
+
+    #[inline]
+    fn dim_uncropped(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        self.dims
+    }
+
+    #[inline]
+    fn dim_cropped(&self) -> Option<Dimensions2D<core::num::NonZero<usize>>> {
+        self.metadata.dim_cropped(self.dim_uncropped())
+    }
+
+    #[inline]
+    fn crop_offset(&self) -> Option<Coord2D> {
+        self.metadata.crop_offset()
+    }
+
+    #[inline]
+    fn black_areas(&self) -> Option<&[BlackArea]> {
+        self.metadata.black_areas()
+    }
+
+    #[inline]
+    fn fuji_rotation_pos(&self) -> Option<u32> {
+        None
+    }
+
+    #[inline]
+    fn pixel_aspect_ratio(&self) -> Option<f64> {
+        None
+    }
+
+    #[inline]
+    fn bad_pixel_positions(&self) -> Vec<Coord2D> {
+        vec![]
+    }
+
+    #[inline(never)]
+    fn decode(
+        &self,
+        output: &mut ImageRefMut<'_>,
+    ) -> Result<(), RawDemuxerError> {
+        let output = output.as_u16()?;
+        if output.dims() != self.dims {
+            return Err(RawDemuxerError::DecoderError(
+                "Output buffer dimensions differ from expected".to_owned(),
+            ));
+        }
+        match &self.format {
+            Format::Compressed(decompressor) => decompressor
+                .decode(output)
+                .map_err(|err| RawDemuxerError::DecoderError(err.to_string())),
+            Format::Unpacked {
+                input,
+                order,
+                bits,
+                shift,
+            } => {
+                Unpacker::new(*input, *order, *bits, output).unpack();
+                if *shift != 0 {
+                    for row in 0..self.dims.row_count().get() {
+                        let row = output.get_row_mut(RowIndex::new(row));
+                        for sample in row.unwrap() {
+                            *sample >>= shift;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            Format::Gathered { input, order } => {
+                Unpacker::new(input.mat(), *order, PACKED_BITS, output)
+                    .unpack();
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,176 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_parsers_tiffparser::tiffparser::TiffTag;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::pack;
+use rawspeed_utils_tiffbuilder::tiffbuilder::Value;
+
+use super::{Orf, compress, decode, new_demuxer_err};
+
+/// Distinct 12-bit samples.
+fn rows(width: usize, height: usize) -> Vec<Vec<u16>> {
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| {
+                    let sample = (row * 1009 + col * 257 + 7) % 4096;
+                    u16::try_from(sample).unwrap()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Swaps the bytes of each 32-bit word, turning MSB-first data into the
+/// MSB32 order.
+fn swap_words(data: &[u8]) -> Vec<u8> {
+    data.chunks_exact(4)
+        .flat_map(|word| word.iter().rev().copied())
+        .collect()
+}
+
+/// Smooth 12-bit samples, which compress to less than 12 bits each.
+fn smooth_rows() -> Vec<Vec<u16>> {
+    vec![
+        vec![
+            100, 200, 102, 203, 104, 206, 4000, 209, 4001, 212, 4003, 215,
+        ],
+        vec![50, 60, 51, 62, 52, 64, 53, 66, 54, 68, 55, 70],
+    ]
+}
+
+#[test]
+fn compressed_test() {
+    let rows = smooth_rows();
+    let data = compress(&rows);
+    assert!(data.len() < 12 * 2 * 3 / 2);
+    assert_eq!(decode(&Orf::new(12, 2, data)), Ok(rows));
+}
+
+#[test]
+fn packed_12_bit_test() {
+    let rows = rows(8, 2);
+    let data = swap_words(&pack(&rows.concat(), 12, BitOrder::MSB));
+    assert_eq!(decode(&Orf::new(8, 2, data)), Ok(rows));
+}
+
+#[test]
+fn unsupported_row_pitch_test() {
+    assert_eq!(
+        new_demuxer_err(&Orf::new(2, 2, vec![0; 6]).build()),
+        "Unsupported ORF row pitch: 3"
+    );
+}
+
+#[test]
+fn packed_with_control_test() {
+    let rows = rows(20, 2);
+    let mut data = vec![];
+    for group in pack(&rows.concat(), 12, BitOrder::LSB).chunks(15) {
+        data.extend(group);
+        data.push(0xFF);
+    }
+    assert_eq!(decode(&Orf::new(20, 2, data)), Ok(rows));
+}
+
+#[test]
+fn unpacked_little_endian_test() {
+    let rows = rows(4, 2);
+    assert_eq!(
+        decode(&Orf::new(4, 2, pack(&rows.concat(), 16, BitOrder::LSB))),
+        Ok(rows)
+    );
+}
+
+#[test]
+fn unpacked_big_endian_test() {
+    let rows = rows(4, 2);
+    let left_aligned: Vec<u16> = rows.concat().iter().map(|s| s << 4).collect();
+    let mut orf = Orf::new(4, 2, pack(&left_aligned, 16, BitOrder::MSB));
+    orf.endianness = Endianness::Big;
+    assert_eq!(decode(&orf), Ok(rows));
+}
+
+#[test]
+fn interlaced_test() {
+    let rows = rows(4, 3);
+    let [even, odd] = [0, 1].map(|parity| {
+        let field: Vec<u16> = rows
+            .iter()
+            .skip(parity)
+            .step_by(2)
+            .flatten()
+            .copied()
+            .collect();
+        pack(&field, 12, BitOrder::MSB)
+    });
+    let mut data = even;
+    data.resize(2048, 0);
+    data.extend(odd);
+    assert_eq!(decode(&Orf::new(4, 3, data)), Ok(rows));
+}
+
+#[test]
+fn interlaced_truncated_test() {
+    assert_eq!(
+        new_demuxer_err(&Orf::new(4, 3, vec![0; 2050]).build()),
+        "The ORF strip is too small"
+    );
+}
+
+#[test]
+fn padded_strips_test() {
+    let rows = rows(8, 2);
+    let data = swap_words(&pack(&rows.concat(), 12, BitOrder::MSB));
+    let (first, rest) = data.split_at(8);
+    let (padding, second) = rest.split_at(4);
+    let mut orf = Orf::new(8, 2, first.to_vec());
+    orf.blobs.extend([padding.to_vec(), second.to_vec()]);
+    assert_eq!(decode(&orf), Ok(rows));
+}
+
+#[test]
+fn overlapping_strips_test() {
+    let mut builder = Orf::new(8, 2, vec![0; 24]).builder();
+    builder.set(TiffTag::STRIP_OFFSETS, &Value::Long(vec![100, 104]));
+    builder.set(TiffTag::STRIP_BYTE_COUNTS, &Value::Long(vec![12, 12]));
+    assert_eq!(new_demuxer_err(&builder.build()), "The ORF strips overlap");
+}
+
+#[test]
+fn compressed_strip_count_test() {
+    let data = compress(&smooth_rows());
+    let mut orf = Orf::new(12, 2, data.get(..4).unwrap().to_vec());
+    orf.blobs.extend([vec![], data.get(4..).unwrap().to_vec()]);
+    assert_eq!(
+        new_demuxer_err(&orf.build()),
+        "Unsupported ORF strip count for compressed data: 2"
+    );
+}
+
+#[test]
+fn unsupported_width_test() {
+    assert_eq!(
+        new_demuxer_err(&Orf::new(3, 2, vec![0; 12]).build()),
+        "Unsupported ORF width: 3"
+    );
+}
+
+#[test]
+fn unsupported_compression_test() {
+    let mut orf = Orf::new(4, 2, vec![0; 16]);
+    orf.compression = 7;
+    assert_eq!(
+        new_demuxer_err(&orf.build()),
+        "Unsupported ORF compression: 7"
+    );
+}
+
+#[test]
+fn no_raw_image_test() {
+    let mut builder = Orf::new(4, 2, vec![0; 16]).builder();
+    builder.remove(TiffTag::STRIP_OFFSETS);
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "No raw image found in ORF"
+    );
+}
@@ -0,0 +1,172 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_std::coord_common::{ColIndex, Coord2D, RowIndex};
+use rawspeed_utils_tiffbuilder::tiffbuilder::{
+    Entries, TiffBuilder, Value, tag,
+};
+
+use super::{Orf, OrfDemuxer, new_demuxer_err, parse_cameras};
+
+fn unpacked(model: &'static str) -> Orf {
+    let mut orf = Orf::new(4, 2, vec![0; 16]);
+    orf.model = model;
+    orf
+}
+
+/// A `MakerNote` of newer cameras, with the given `ImageProcessing`
+/// sub-IFD.
+fn makernote(image_processing: Entries) -> Vec<u8> {
+    let mut builder = TiffBuilder::new(Endianness::Big);
+    builder
+        .ifd(0)
+        .push(tag(TiffTag::OLYMPUS_IMAGE_PROCESSING, Value::IFDOffset(1)));
+    *builder.ifd(1) = image_processing;
+    builder.build_after(b"OLYMPUS\0MM\0\x03")
+}
+
+fn image_processing() -> Entries {
+    vec![
+        tag(TiffTag::OLYMPUS_WB_RB_LEVELS, Value::Short(vec![512, 384])),
+        tag(
+            TiffTag::OLYMPUS_BLACK_LEVEL2,
+            Value::Short(vec![250, 251, 252, 253]),
+        ),
+    ]
+}
+
+macro_rules! with_demuxer {
+    ($orf:expr, |$demuxer:ident| $body:block) => {{
+        let input = $orf.build();
+        let root = TiffParser::parse(&input).unwrap();
+        let cameras = parse_cameras();
+        let ($demuxer, _) = OrfDemuxer::new(
+            &root,
+            &cameras,
+            DecodeableCamera::new_unless_unsupported,
+        )
+        .unwrap();
+        $body
+    }};
+}
+
+fn levels(demuxer: &OrfDemuxer<'_>) -> [i32; 4] {
+    let levels = demuxer.blacklevel_separate().unwrap();
+    let level =
+        |row, col| levels[Coord2D::new(RowIndex::new(row), ColIndex::new(col))];
+    [level(0, 0), level(0, 1), level(1, 0), level(1, 1)]
+}
+
+fn assert_wb_coeffs(demuxer: &OrfDemuxer<'_>, expected: [f32; 3]) {
+    let [red, green, blue, fourth] = demuxer.wb_coeffs().unwrap();
+    assert_eq!(
+        [red, green, blue].map(f32::to_bits),
+        expected.map(f32::to_bits)
+    );
+    assert!(fourth.is_nan());
+}
+
+#[test]
+fn camera_from_cameras_xml_test() {
+    with_demuxer!(unpacked("E-M0"), |demuxer| {
+        assert_eq!(demuxer.make(), "OLYMPUS IMAGING CORP.");
+        assert_eq!(demuxer.model(), "E-M0");
+        assert_eq!(demuxer.mode(), None);
+        assert_eq!(demuxer.canonical_make(), "Olympus");
+        assert_eq!(demuxer.canonical_id(), "Olympus E-M0");
+        assert_eq!(demuxer.iso_speed(), Some(200));
+        assert_eq!(demuxer.blacklevel(), Some(256));
+        assert_eq!(demuxer.whitelevel(), Some(4095));
+        assert!(demuxer.is_cfa());
+    });
+}
+
+#[test]
+fn image_processing_test() {
+    let mut orf = unpacked("E-M0");
+    orf.makernote = Some(makernote(image_processing()));
+    with_demuxer!(orf, |demuxer| {
+        assert_wb_coeffs(&demuxer, [512.0, 256.0, 384.0]);
+        assert_eq!(levels(&demuxer), [250, 251, 252, 253]);
+    });
+}
+
+#[test]
+fn black_levels_follow_cfa_test() {
+    // GR/BG: each position takes the (RGGB-ordered) level of its color.
+    let mut orf = unpacked("E-M9");
+    orf.makernote = Some(makernote(image_processing()));
+    with_demuxer!(orf, |demuxer| {
+        assert_eq!(levels(&demuxer), [251, 250, 253, 252]);
+    });
+}
+
+#[test]
+fn embedded_image_processing_test() {
+    // The sub-IFD is stored in place, and only has inline values.
+    let mut sub_ifd = TiffBuilder::new(Endianness::Little);
+    sub_ifd.ifd(0).push(tag(
+        TiffTag::OLYMPUS_WB_RB_LEVELS,
+        Value::Short(vec![300, 400]),
+    ));
+    let mut builder = TiffBuilder::new(Endianness::Little);
+    builder.ifd(0).push(tag(
+        TiffTag::OLYMPUS_IMAGE_PROCESSING,
+        Value::Undefined(sub_ifd.build_after(&[])),
+    ));
+    let mut orf = unpacked("E-M0");
+    orf.makernote = Some(builder.build_after(b"OLYMPUS\0II\x03\0"));
+    with_demuxer!(orf, |demuxer| {
+        assert_wb_coeffs(&demuxer, [300.0, 256.0, 400.0]);
+        assert!(demuxer.blacklevel_separate().is_none());
+    });
+}
+
+#[test]
+fn old_makernote_test() {
+    let mut builder = TiffBuilder::new(Endianness::Little);
+    builder.ifd(0).extend([
+        tag(TiffTag::OLYMPUS_RED_MULTIPLIER, Value::Short(vec![480])),
+        tag(TiffTag::OLYMPUS_BLUE_MULTIPLIER, Value::Short(vec![320])),
+    ]);
+    let mut orf = unpacked("E-M0");
+    orf.makernote = Some(builder.build_after(b"OLYMP\0\x01\0"));
+    with_demuxer!(orf, |demuxer| {
+        assert_wb_coeffs(&demuxer, [480.0, 256.0, 320.0]);
+    });
+}
+
+#[test]
+fn no_makernote_test() {
+    with_demuxer!(unpacked("E-M0"), |demuxer| {
+        assert!(demuxer.wb_coeffs().is_none());
+        assert!(demuxer.blacklevel_separate().is_none());
+    });
+}
+
+#[test]
+fn invalid_makernote_byte_order_test() {
+    let mut orf = unpacked("E-M0");
+    orf.makernote = Some(b"OLYMPUS\0XX\x03\0".to_vec());
+    assert_eq!(
+        new_demuxer_err(&orf.build()),
+        "The ORF MakerNote byte order is invalid"
+    );
+}
+
+#[test]
+fn unknown_camera_test() {
+    assert_eq!(
+        new_demuxer_err(&unpacked("E-M99").build()),
+        "Unknown camera: OLYMPUS IMAGING CORP. E-M99"
+    );
+}
+
+#[test]
+fn unsupported_camera_test() {
+    assert_eq!(
+        new_demuxer_err(&unpacked("Unsupported").build()),
+        "This camera is not supported (explicit)"
+    );
+}
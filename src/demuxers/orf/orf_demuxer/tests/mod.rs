@@ -0,0 +1,194 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::Cameras;
+use rawspeed_metadata_xmlparser::xmlparser;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_std::coord_common::RowIndex;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+use rawspeed_utils_tiffbuilder::tiffbuilder::{TiffBuilder, Value, tag};
+
+use super::{COMPRESSION_NONE, OrfDemuxer};
+
+const CAMERAS: &str = "
+    <Cameras>
+        <Camera make=\"OLYMPUS IMAGING CORP.\" model=\"E-M0\">
+            <ID make=\"Olympus\" model=\"E-M0\">Olympus E-M0</ID>
+            <CFA width=\"2\" height=\"2\">
+                <Color x=\"0\" y=\"0\">RED</Color>
+                <Color x=\"1\" y=\"0\">GREEN</Color>
+                <Color x=\"0\" y=\"1\">GREEN</Color>
+                <Color x=\"1\" y=\"1\">BLUE</Color>
+            </CFA>
+            <Sensor black=\"256\" white=\"4095\"/>
+        </Camera>
+        <Camera make=\"OLYMPUS IMAGING CORP.\" model=\"E-M9\">
+            <ID make=\"Olympus\" model=\"E-M9\">Olympus E-M9</ID>
+            <CFA width=\"2\" height=\"2\">
+                <Color x=\"0\" y=\"0\">GREEN</Color>
+                <Color x=\"1\" y=\"0\">RED</Color>
+                <Color x=\"0\" y=\"1\">BLUE</Color>
+                <Color x=\"1\" y=\"1\">GREEN</Color>
+            </CFA>
+        </Camera>
+        <Camera make=\"OLYMPUS IMAGING CORP.\" model=\"Unsupported\"
+                supported=\"no\">
+        </Camera>
+    </Cameras>";
+
+/// The adaptive state of the Olympus compression, see the decompressor.
+#[derive(Debug, Default, Clone, Copy)]
+struct Carry {
+    magnitude: i32,
+    average: i32,
+    small_run: i32,
+}
+
+impl Carry {
+    fn put(&mut self, bits: &mut BitStreamBuilder, value: i32) {
+        let extra = if self.small_run < 3 { 2 } else { 0 };
+        let mut nbits = 2 + extra;
+        while (self.magnitude >> (nbits + extra)) != 0 {
+            nbits += 1;
+        }
+        let diff = value >> 2;
+        let unsigned = diff - self.average;
+        let (sign, magnitude) = if unsigned < 0 {
+            (1, !unsigned)
+        } else {
+            (0, unsigned)
+        };
+        bits.put(sign, 1);
+        bits.put(u32::try_from(value & 3).unwrap(), 2);
+        let magnitude_bits = u32::try_from(magnitude).unwrap();
+        let high = magnitude_bits >> nbits;
+        if high < 12 {
+            bits.put(1, high + 1);
+        } else {
+            bits.put(0, 12);
+            bits.put(high << 1, 16 - nbits);
+        }
+        bits.put(magnitude_bits & ((1 << nbits) - 1), nbits);
+        self.magnitude = magnitude;
+        self.average = (diff * 3 + self.average) >> 5;
+        self.small_run = if magnitude > 16 {
+            0
+        } else {
+            self.small_run + 1
+        };
+    }
+}
+
+/// Encodes (at most two) rows, where samples are predicted from the one
+/// two columns to the left.
+fn compress(rows: &[Vec<u16>]) -> Vec<u8> {
+    assert!(rows.len() <= 2);
+    let mut bits = BitStreamBuilder::new();
+    for samples in rows {
+        let mut carries = [Carry::default(); 2];
+        for (col, sample) in samples.iter().enumerate() {
+            let pred = col
+                .checked_sub(2)
+                .map_or(0, |left| i32::from(*samples.get(left).unwrap()));
+            carries
+                .get_mut(col % 2)
+                .unwrap()
+                .put(&mut bits, i32::from(*sample) - pred);
+        }
+    }
+    let mut out = vec![0; 7];
+    out.extend(bits.finish(BitOrder::MSB));
+    out
+}
+
+#[derive(Debug)]
+struct Orf {
+    endianness: Endianness,
+    model: &'static str,
+    width: u32,
+    height: u32,
+    compression: u16,
+    /// The strips (the even blobs), and the padding between them.
+    blobs: Vec<Vec<u8>>,
+    makernote: Option<Vec<u8>>,
+}
+
+impl Orf {
+    fn new(width: u32, height: u32, data: Vec<u8>) -> Self {
+        Self {
+            endianness: Endianness::Little,
+            model: "E-M0",
+            width,
+            height,
+            compression: u16::try_from(COMPRESSION_NONE).unwrap(),
+            blobs: vec![data],
+            makernote: None,
+        }
+    }
+
+    fn builder(&self) -> TiffBuilder {
+        let mut builder = TiffBuilder::new(self.endianness);
+        builder.magic = TiffParser::MAGIC_ORF;
+        let strips: Vec<usize> = (0..self.blobs.len()).step_by(2).collect();
+        builder.ifd(0).extend([
+            tag(TiffTag::MAKE, Value::Ascii("OLYMPUS IMAGING CORP.")),
+            tag(TiffTag::MODEL, Value::Ascii(self.model)),
+            tag(TiffTag::IMAGE_WIDTH, Value::Long(vec![self.width])),
+            tag(TiffTag::IMAGE_LENGTH, Value::Long(vec![self.height])),
+            tag(TiffTag::COMPRESSION, Value::Short(vec![self.compression])),
+            tag(TiffTag::STRIP_OFFSETS, Value::BlobOffsets(strips.clone())),
+            tag(TiffTag::STRIP_BYTE_COUNTS, Value::BlobSizes(strips)),
+            tag(TiffTag::EXIF_IFD_POINTER, Value::IFDOffset(1)),
+        ]);
+        builder
+            .ifd(1)
+            .push(tag(TiffTag::ISO_SPEED_RATINGS, Value::Short(vec![200])));
+        if let Some(makernote) = &self.makernote {
+            builder.ifd(1).push(tag(
+                TiffTag::MAKER_NOTE,
+                Value::Undefined(makernote.clone()),
+            ));
+        }
+        builder.blobs.clone_from(&self.blobs);
+        builder
+    }
+
+    fn build(&self) -> Vec<u8> {
+        self.builder().build()
+    }
+}
+
+fn parse_cameras() -> Cameras<'static> {
+    xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap()
+}
+
+fn new_demuxer_err(input: &[u8]) -> String {
+    let root = TiffParser::parse(input).unwrap();
+    let cameras = parse_cameras();
+    OrfDemuxer::new(&root, &cameras, DecodeableCamera::new_unless_unsupported)
+        .unwrap_err()
+}
+
+fn decode(orf: &Orf) -> Result<Vec<Vec<u16>>, String> {
+    let input = orf.build();
+    let root = TiffParser::parse(&input).unwrap();
+    let cameras = parse_cameras();
+    let (demuxer, request) = OrfDemuxer::new(
+        &root,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )?;
+    let mut output_buf = request.fulfill().unwrap();
+    let mut image = output_buf.get_mut();
+    demuxer.decode(&mut image).map_err(|err| err.to_string())?;
+    let output = image.as_u16().unwrap();
+    Ok((0..output.num_rows().get())
+        .map(|row| output.get_row(RowIndex::new(row)).unwrap().to_vec())
+        .collect())
+}
+
+#[cfg(test)]
+mod data;
+#[cfg(test)]
+mod metadata;
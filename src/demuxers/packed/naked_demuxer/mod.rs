@@ -1,21 +1,22 @@
 use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
 use rawspeed_codecs_packed_decoder::packed_decoder::Unpacker;
-use rawspeed_demuxers_rawdemuxer::rawdemuxer::{RawDemuxer, RawDemuxerError};
+use rawspeed_demuxers_common::camera_metadata::{
+    CameraMetadata, get_hint_with_name,
+};
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::{
+    ImageRefMut, ProcurementRequest, RawDemuxer, RawDemuxerError,
+};
 use rawspeed_memory_nd_slice_procurement::ndsliceprocurement::NDSliceProcurementRequest;
 use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
 use rawspeed_metadata_camerasxml_parser::camerasxml_parser::{
-    Camera, Cameras, Hints, Supported,
-    blackareas::BlackArea,
-    crop::{Height, Width},
+    Camera, Cameras, Supported, blackareas::BlackArea,
 };
 use rawspeed_metadata_colorfilterarray::colorfilterarray::ColorVariant;
 use rawspeed_std::coord_common::{
-    ColIndex, Coord2D, CoordOffset2D, Dimensions2D, RowCount, RowIndex,
-    RowLength, RowPitch,
+    Coord2D, Dimensions2D, RowCount, RowLength, RowPitch,
 };
 use rawspeed_std_ndslice::{
-    array2dref::Array2DRef, array2drefmut::Array2DRefMut,
-    offsetarray2dref::OffsetArray2DRef,
+    array2dref::Array2DRef, offsetarray2dref::OffsetArray2DRef,
 };
 
 fn parse_as_bitorder(s: &str) -> Option<BitOrder> {
@@ -28,13 +29,6 @@ fn parse_as_bitorder(s: &str) -> Option<BitOrder> {
     }
 }
 
-fn get_hint_with_name<'a>(hints: &Hints<'a>, name: &str) -> Option<&'a str> {
-    hints
-        .iter()
-        .find(|hint| **(hint.name) == name)
-        .map(|hint| **hint.value)
-}
-
 fn match_camera_by_filesize<'a>(
     camera: &'a Camera<'a>,
     input_len: usize,
@@ -89,9 +83,10 @@ fn guess_bits(
 #[non_exhaustive]
 #[must_use]
 pub struct NakedDemuxer<'a> {
-    camera: &'a Camera<'a>,
+    metadata: CameraMetadata<'a>,
     input: Array2DRef<'a, u8>,
     dims: Dimensions2D<core::num::NonZero<usize>>,
+    cpp: core::num::NonZero<usize>,
     order: BitOrder,
     bits: u64,
 }
@@ -105,7 +100,7 @@ impl<'a> NakedDemuxer<'a> {
         input: &'a [u8],
         cameras: &'a Cameras<'a>,
         check_camera_support_fn: F,
-    ) -> Result<(Self, NDSliceProcurementRequest<T>), String>
+    ) -> Result<(Self, ProcurementRequest), String>
     where
         F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
     {
@@ -144,6 +139,22 @@ impl<'a> NakedDemuxer<'a> {
             return Err("The width/height is invalid".to_owned());
         };
 
+        // The components of each pixel are interleaved.
+        let cpp: core::num::NonZero<usize> =
+            match get_hint_with_name(hints, "cpp").map(str::parse) {
+                Some(Ok(cpp)) => cpp,
+                None => core::num::NonZero::<usize>::MIN,
+                _ => return Err("The component count is invalid".to_owned()),
+            };
+        let Some(sample_count) = core::num::NonZero::<u64>::checked_mul(
+            col_count,
+            cpp.try_into().unwrap(),
+        ) else {
+            return Err(
+                "Overflow when computing per-row sample count".to_owned()
+            );
+        };
+
         let input_bytes_per_row = compute_pitch(input.len(), row_count)?;
         let src = Array2DRef::new(
             input,
@@ -159,7 +170,7 @@ impl<'a> NakedDemuxer<'a> {
 
         let bits = match get_hint_with_name(hints, "bits").map(str::parse) {
             Some(Ok(bits)) => bits,
-            None => guess_bits(input_bytes_per_row.get(), col_count)?,
+            None => guess_bits(input_bytes_per_row.get(), sample_count)?,
             _ => return Err("The bitwidth is invalid".to_owned()),
         };
 
@@ -176,13 +187,21 @@ impl<'a> NakedDemuxer<'a> {
         );
         Ok((
             Self {
-                camera,
+                metadata: CameraMetadata::new(
+                    camera,
+                    camera.make.as_ref(),
+                    camera.model.as_ref(),
+                    None,
+                ),
                 input: src,
                 dims,
+                cpp,
                 order,
                 bits,
             },
-            NDSliceProcurementRequest::new(dims),
+            NDSliceProcurementRequest::<T>::new(dims)
+                .set_cpp(cpp)
+                .into(),
         ))
     }
 }
@@ -191,31 +210,27 @@ impl<'a> NakedDemuxer<'a> {
 impl RawDemuxer for NakedDemuxer<'_> {
     #[inline]
     fn make(&self) -> &str {
-        self.camera.make.as_ref()
+        self.metadata.make
     }
 
     #[inline]
     fn model(&self) -> &str {
-        self.camera.model.as_ref()
+        self.metadata.model
     }
 
     #[inline]
     fn mode(&self) -> Option<&str> {
-        self.camera.mode.map(|v| &***v)
+        self.metadata.mode()
     }
 
     #[inline]
     fn canonical_make(&self) -> &str {
-        self.camera
-            .id
-            .map_or_else(|| self.make(), |id| id.make.as_ref())
+        self.metadata.canonical_make()
     }
 
     #[inline]
     fn canonical_model(&self) -> &str {
-        self.camera
-            .id
-            .map_or_else(|| self.model(), |id| id.model.as_ref())
+        self.metadata.canonical_model()
     }
 
     #[inline]
@@ -225,54 +240,42 @@ impl RawDemuxer for NakedDemuxer<'_> {
 
     #[inline]
     fn canonical_id(&self) -> String {
-        self.camera.id.map_or_else(
-            || format!("{} {}", self.make(), self.model()),
-            |id| id.value.to_string(),
-        )
+        self.metadata.canonical_id()
     }
 
     #[inline]
     fn iso_speed(&self) -> Option<u32> {
-        None
+        self.metadata.iso_speed
     }
 
     #[inline]
     fn blacklevel(&self) -> Option<u16> {
-        self.camera.sensors.get_for_iso(self.iso_speed()).map(|s| {
-            let val = **s.black;
-            val.try_into().unwrap()
-        })
+        self.metadata.blacklevel()
     }
 
     #[inline]
     fn whitelevel(&self) -> Option<u16> {
-        self.camera.sensors.get_for_iso(self.iso_speed()).map(|s| {
-            let val = **s.white;
-            val.try_into().unwrap()
-        })
+        self.metadata.whitelevel()
     }
 
     #[inline]
-    fn blacklevel_separate(&self) -> Option<()> {
+    fn blacklevel_separate(&self) -> Option<Array2DRef<'_, i32>> {
         None
     }
 
     #[inline]
-    fn wb_coeffs(&self) -> Option<()> {
+    fn wb_coeffs(&self) -> Option<[f32; 4]> {
         None
     }
 
     #[inline]
     fn colormatrix(&self) -> Option<Array2DRef<'_, i16>> {
-        self.camera
-            .colormatrices
-            .as_ref()
-            .map(|mat| mat.value.mat())
+        self.metadata.colormatrix()
     }
 
     #[inline]
     fn is_cfa(&self) -> bool {
-        self.camera.cfa.is_some()
+        self.metadata.is_cfa()
     }
 
     #[inline]
@@ -280,11 +283,7 @@ impl RawDemuxer for NakedDemuxer<'_> {
         &self,
         origin: Coord2D,
     ) -> Option<OffsetArray2DRef<'_, ColorVariant>> {
-        const ZERO_POINT: Coord2D =
-            Coord2D::new(RowIndex::new(0), ColIndex::new(0));
-        let offset = (origin - ZERO_POINT)?;
-        let cfa = self.camera.cfa.as_ref().map(|cfa| cfa.mat())?;
-        Some(OffsetArray2DRef::new(cfa, offset))
+        self.metadata.cfa(origin)
     }
 
     #[inline]
@@ -293,6 +292,12 @@ impl RawDemuxer for NakedDemuxer<'_> {
             rawspeed_demuxers_rawdemuxer::rawdemuxer::DataType::U16 => {
                 size_of::<u16>()
             }
+            rawspeed_demuxers_rawdemuxer::rawdemuxer::DataType::F32 => {
+                size_of::<f32>()
+            }
+            rawspeed_demuxers_rawdemuxer::rawdemuxer::DataType::U32 => {
+                size_of::<u32>()
+            }
             _ => unreachable!(),
         };
         bpc.checked_mul(self.cpp()).unwrap()
@@ -300,7 +305,7 @@ impl RawDemuxer for NakedDemuxer<'_> {
 
     #[inline]
     fn cpp(&self) -> usize {
-        1
+        self.cpp.get()
     }
 
     #[inline]
@@ -313,54 +318,19 @@ impl RawDemuxer for NakedDemuxer<'_> {
         self.dims
     }
 
-    #[expect(clippy::unwrap_in_result)]
     #[inline]
     fn dim_cropped(&self) -> Option<Dimensions2D<core::num::NonZero<usize>>> {
-        const ZERO_POINT: Coord2D =
-            Coord2D::new(RowIndex::new(0), ColIndex::new(0));
-        let crop_pos = self.crop_offset().unwrap_or(ZERO_POINT);
-        let crop_xy = (crop_pos - ZERO_POINT).unwrap();
-        let dim_remaining = Coord2D::new(
-            RowIndex::new(self.dim_uncropped().row_count().get()),
-            ColIndex::new(self.dim_uncropped().row_len().get()),
-        );
-        let dim_remaining = (dim_remaining - crop_xy).unwrap();
-        let crop_dim = CoordOffset2D::new(
-            match self.camera.crop?.dim.height() {
-                Height::Relative(offset) => offset,
-                Height::Absolute(size) => {
-                    let cropped_dim = RowIndex::new(*size);
-                    (cropped_dim - dim_remaining.row()).unwrap()
-                }
-                _ => unreachable!(),
-            },
-            match self.camera.crop?.dim.width() {
-                Width::Relative(offset) => offset,
-                Width::Absolute(size) => {
-                    let cropped_dim = ColIndex::new(*size);
-                    (cropped_dim - dim_remaining.col()).unwrap()
-                }
-                _ => unreachable!(),
-            },
-        );
-        let crop_wh = (-crop_dim).unwrap();
-        let cropped_dim = (dim_remaining - crop_wh).unwrap();
-        Some(Dimensions2D::new(
-            RowLength::new(
-                core::num::NonZero::new(*cropped_dim.col()).unwrap(),
-            ),
-            RowCount::new(core::num::NonZero::new(*cropped_dim.row()).unwrap()),
-        ))
+        self.metadata.dim_cropped(self.dims)
     }
 
     #[inline]
     fn crop_offset(&self) -> Option<Coord2D> {
-        Some(*self.camera.crop?.pos)
+        self.metadata.crop_offset()
     }
 
     #[inline]
     fn black_areas(&self) -> Option<&[BlackArea]> {
-        self.camera.blackareas.as_ref().map(|f| &*f.value.areas)
+        self.metadata.black_areas()
     }
 
     #[inline]
@@ -381,9 +351,14 @@ impl RawDemuxer for NakedDemuxer<'_> {
     #[inline(never)]
     fn decode(
         &self,
-        output: &mut Array2DRefMut<'_, u16>,
+        output: &mut ImageRefMut<'_>,
     ) -> Result<(), RawDemuxerError> {
-        if output.dims() != self.dims {
+        let output = output.as_u16()?;
+        let dims = Dimensions2D::new(
+            RowLength::new(self.dims.row_len().checked_mul(self.cpp).unwrap()),
+            self.dims.row_count(),
+        );
+        if output.dims() != dims {
             return Err(RawDemuxerError::DecoderError(
                 "Output buffer dimensions differ from expected".to_owned(),
             ));
@@ -2,7 +2,7 @@ macro_rules! impl_generic_tests {
     ($bitorder:expr) => {
         use crate::naked_demuxer::NakedDemuxer;
         use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
-        use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxerError;
+        use rawspeed_demuxers_rawdemuxer::rawdemuxer::{ImageRefMut, RawDemuxerError};
         use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
         use rawspeed_metadata_camerasxml_parser::camerasxml_parser::Cameras;
         use rawspeed_metadata_xmlparser::xmlparser;
@@ -368,11 +368,11 @@ macro_rules! impl_generic_tests {
                 for test_height in 1..(2 * width * height) {
                     let mut output_storage =
                         vec![0_u16; test_height * test_width];
-                    let mut output = Array2DRefMut::new(
+                    let mut output = ImageRefMut::U16(Array2DRefMut::new(
                         &mut output_storage,
                         RowLength::new(core::num::NonZero::new(test_width).unwrap()),
                         RowPitch::new(core::num::NonZero::new(test_width).unwrap()),
-                    );
+                    ));
                     let res = res.decode(&mut output);
                     if test_width == width && test_height == height {
                     let _ = res.unwrap();
@@ -652,6 +652,64 @@ macro_rules! impl_generic_tests {
             let res = NakedDemuxer::new(&input, &cameras, DecodeableCamera::new_unless_unsupported);
             let _ = res.unwrap();
         }
+
+        #[test]
+        fn cpp_test() {
+            let cameras = concat!(
+                "
+                <Cameras>
+                    <Camera make=\"Make\" model=\"Model\">
+                    <Hints>
+                        <Hint name=\"filesize\" value=\"8\"/>
+                        <Hint name=\"full_width\" value=\"2\"/>
+                        <Hint name=\"full_height\" value=\"2\"/>
+                        <Hint name=\"cpp\" value=\"2\"/>
+                        <Hint name=\"order\" value=\"",
+                $bitorder,
+                "\"/>
+                    </Hints>
+                    </Camera>
+                </Cameras>"
+            );
+            let cameras = xmlparser::parse_str::<Cameras<'_>>(cameras).unwrap();
+            let input = vec![0_u8; 8];
+            let (res, out_buf_request) = NakedDemuxer::new(&input, &cameras, DecodeableCamera::new_unless_unsupported)
+            .unwrap();
+            assert_eq!(res.cpp(), 2);
+            assert_eq!(res.bpp(), 4);
+            assert_eq!(res.dim_uncropped().row_len().get(), 2);
+            assert_eq!(out_buf_request.dims(), res.dim_uncropped());
+            assert_eq!(out_buf_request.cpp().get(), 2);
+            let mut output_buf = out_buf_request.fulfill().unwrap();
+            let mut image = output_buf.get_mut();
+            res.decode(&mut image).unwrap();
+            let output = image.as_u16().unwrap();
+            assert_eq!(output.row_length().get(), 4);
+        }
+
+        #[test]
+        fn invalid_cpp_hint_test() {
+            let cameras = concat!(
+                "
+                <Cameras>
+                    <Camera make=\"Make\" model=\"Model\">
+                    <Hints>
+                        <Hint name=\"filesize\" value=\"8\"/>
+                        <Hint name=\"full_width\" value=\"4\"/>
+                        <Hint name=\"full_height\" value=\"2\"/>
+                        <Hint name=\"cpp\" value=\"0\"/>
+                        <Hint name=\"order\" value=\"",
+                $bitorder,
+                "\"/>
+                    </Hints>
+                    </Camera>
+                </Cameras>"
+            );
+            let cameras = xmlparser::parse_str::<Cameras<'_>>(cameras).unwrap();
+            let input = vec![0_u8; 8];
+            let res = NakedDemuxer::new(&input, &cameras, DecodeableCamera::new_unless_unsupported);
+            assert_eq!(res.unwrap_err(), "The component count is invalid");
+        }
     };
 }
 
@@ -34,8 +34,9 @@ fn unpack_8bytes_into_2_rows_of_4_elts_at_8bpc_test() {
         )
         .unwrap();
         let mut output_buf = out_buf_request.fulfill().unwrap();
-        let mut output = output_buf.get_mut();
-        res.decode(&mut output).unwrap();
+        let mut image = output_buf.get_mut();
+        res.decode(&mut image).unwrap();
+        let output = image.as_u16().unwrap();
         for row in 1..=2 {
             for col in 1..=4 {
                 assert_eq!(
@@ -84,8 +85,9 @@ fn unpack_8bytes_into_2_rows_of_2_elts_at_16bpc_test() {
         )
         .unwrap();
         let mut output_buf = out_buf_request.fulfill().unwrap();
-        let mut output = output_buf.get_mut();
-        res.decode(&mut output).unwrap();
+        let mut image = output_buf.get_mut();
+        res.decode(&mut image).unwrap();
+        let output = image.as_u16().unwrap();
         for row in 1..=2 {
             for col in 1..=2 {
                 assert_eq!(
@@ -0,0 +1 @@
+pub mod pef_demuxer;
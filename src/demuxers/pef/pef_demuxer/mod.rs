@@ -0,0 +1,473 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_codecs_packed_decoder::packed_decoder::Unpacker;
+use rawspeed_codecs_pentax::pentax::{
+    PentaxDecompressor, legacy_code, parse_code,
+};
+use rawspeed_demuxers_common::{
+    camera_metadata::CameraMetadata,
+    tiff_utils::{
+        OwnedArray2D, get_root_string, get_strip, get_u32, get_usize, non_zero,
+        tiff_err,
+    },
+};
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::{
+    DataType, ImageRefMut, ProcurementRequest, RawDemuxer, RawDemuxerError,
+};
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_memory_nd_slice_procurement::ndsliceprocurement::NDSliceProcurementRequest;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::{
+    Cameras, Supported, blackareas::BlackArea,
+};
+use rawspeed_metadata_colorfilterarray::colorfilterarray::ColorVariant;
+use rawspeed_parsers_tiffparser::tiffparser::{
+    TiffDataType, TiffEntry, TiffIFD, TiffParser, TiffRootIFD, TiffTag,
+};
+use rawspeed_std::coord_common::{
+    Coord2D, Dimensions2D, RowCount, RowLength, RowPitch,
+};
+use rawspeed_std_ndslice::{
+    array2dref::Array2DRef, offsetarray2dref::OffsetArray2DRef,
+};
+
+const COMPRESSION_NONE: u32 = 1;
+/// `PackBits` in name only: the samples are stored uncompressed.
+const COMPRESSION_PACKBITS: u32 = 0x8005;
+const COMPRESSION_PENTAX: u32 = 0xFFFF;
+
+/// Pixel shift composites hold all four CFA samples of each pixel.
+const PIXEL_SHIFT_CPP: usize = 4;
+
+/// The `MakerNote` of older cameras starts with this signature and
+/// (usually) the byte order of its IFD, which follows it. All offsets
+/// are relative to the start of the file.
+const AOC_MAKERNOTE_SIGNATURE: &[u8] = b"AOC\0";
+const AOC_MAKERNOTE_IFD_OFFSET: usize = 6;
+
+/// The `MakerNote` of newer cameras has a longer signature, and its
+/// offsets are relative to the start of the `MakerNote`.
+const MAKERNOTE_SIGNATURE: &[u8] = b"PENTAX \0";
+const MAKERNOTE_IFD_OFFSET: usize = 10;
+
+type T = u16;
+
+fn parse_byte_order(bytes: Option<&[u8]>) -> Option<Endianness> {
+    match bytes {
+        Some(b"II") => Some(Endianness::Little),
+        Some(b"MM") => Some(Endianness::Big),
+        Some(_) | None => None,
+    }
+}
+
+fn parse_makernote<'a>(
+    root: &TiffRootIFD<'a>,
+) -> Result<Option<TiffRootIFD<'a>>, String> {
+    let Some(entry) = root.get_entry_recursive(TiffTag::MAKER_NOTE) else {
+        return Ok(None);
+    };
+    let data = entry.data();
+    let byte_order = |ifd_offset: usize| {
+        parse_byte_order(data.get(ifd_offset - 2..ifd_offset))
+    };
+    let makernote = if data.starts_with(AOC_MAKERNOTE_SIGNATURE) {
+        let endianness = byte_order(AOC_MAKERNOTE_IFD_OFFSET)
+            .unwrap_or_else(|| root.endianness());
+        TiffParser::parse_ifd_chain(
+            root.input(),
+            endianness,
+            entry.data_offset() + AOC_MAKERNOTE_IFD_OFFSET,
+        )
+    } else if data.starts_with(MAKERNOTE_SIGNATURE) {
+        let endianness = byte_order(MAKERNOTE_IFD_OFFSET)
+            .ok_or("The PEF MakerNote byte order is invalid")?;
+        TiffParser::parse_ifd_chain(data, endianness, MAKERNOTE_IFD_OFFSET)
+    } else {
+        return Ok(None);
+    };
+    makernote.map(Some).map_err(tiff_err)
+}
+
+/// The white balance is stored as the RGGB levels.
+fn parse_wb_coeffs(
+    makernote: &TiffRootIFD<'_>,
+) -> Result<Option<[f32; 4]>, String> {
+    let Some(entry) = makernote.get_entry_recursive(TiffTag::PENTAX_WB_LEVELS)
+    else {
+        return Ok(None);
+    };
+    if entry.count() != 4 {
+        return Ok(None);
+    }
+    let [red, green, blue] =
+        [0, 1, 3].map(|index| entry.get_f32(index).map_err(tiff_err));
+    Ok(Some([red?, green?, blue?, f32::NAN]))
+}
+
+/// Per-CFA-position black levels.
+fn parse_black_levels(
+    makernote: &TiffRootIFD<'_>,
+) -> Result<Option<OwnedArray2D<i32>>, String> {
+    let Some(entry) =
+        makernote.get_entry_recursive(TiffTag::PENTAX_BLACK_LEVEL)
+    else {
+        return Ok(None);
+    };
+    if entry.count() != 4 {
+        return Ok(None);
+    }
+    let levels = (0..4)
+        .map(|index| entry.get_u16(index).map(i32::from))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(tiff_err)?;
+    OwnedArray2D::new(levels, 2).map(Some)
+}
+
+#[derive(Debug)]
+enum Format<'a> {
+    Compressed(PentaxDecompressor<'a>),
+    Uncompressed {
+        input: Array2DRef<'a, u8>,
+        bits: u32,
+    },
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct PefDemuxer<'a> {
+    metadata: CameraMetadata<'a>,
+    format: Format<'a>,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    cpp: core::num::NonZero<usize>,
+    black_levels: Option<OwnedArray2D<i32>>,
+    wb_coeffs: Option<[f32; 4]>,
+}
+
+impl<'a> PefDemuxer<'a> {
+    fn parse_dims(
+        ifd: &TiffIFD<'_>,
+    ) -> Result<Dimensions2D<core::num::NonZero<usize>>, String> {
+        let width = get_usize(ifd, TiffTag::IMAGE_WIDTH)?.unwrap_or(0);
+        let height = get_usize(ifd, TiffTag::IMAGE_LENGTH)?.unwrap_or(0);
+        Ok(Dimensions2D::new(
+            RowLength::new(non_zero(width, "width")?),
+            RowCount::new(non_zero(height, "height")?),
+        ))
+    }
+
+    fn parse_cpp(
+        ifd: &TiffIFD<'_>,
+    ) -> Result<core::num::NonZero<usize>, String> {
+        let cpp = get_usize(ifd, TiffTag::SAMPLES_PER_PIXEL)?.unwrap_or(1);
+        if cpp != 1 && cpp != PIXEL_SHIFT_CPP {
+            return Err(format!("Unsupported samples per pixel: {cpp}"));
+        }
+        non_zero(cpp, "samples per pixel")
+    }
+
+    /// Cameras that store no Huffman table use a fixed one.
+    fn parse_compressed(
+        strip: &'a [u8],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        makernote: Option<&TiffRootIFD<'_>>,
+    ) -> Result<Format<'a>, String> {
+        let code = match makernote.and_then(|makernote| {
+            makernote.get_entry_recursive(TiffTag::PENTAX_HUFFMAN_TABLE)
+        }) {
+            Some(entry) => {
+                if entry.datatype() != TiffDataType::Undefined {
+                    return Err("The PEF Huffman table has an unexpected type"
+                        .to_owned());
+                }
+                parse_code(entry.data(), entry.endianness())
+                    .map_err(|err| err.to_string())?
+            }
+            None => legacy_code(),
+        };
+        PentaxDecompressor::new(strip, dims, code)
+            .map(Format::Compressed)
+            .map_err(|err| err.to_string())
+    }
+
+    /// The samples are bit-packed, most significant bit first.
+    fn parse_uncompressed(
+        strip: &'a [u8],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        bits: u32,
+    ) -> Result<Format<'a>, String> {
+        let (width, height) = (dims.row_len().get(), dims.row_count().get());
+        let row_bits = width * usize::try_from(bits).unwrap();
+        if !row_bits.is_multiple_of(8) {
+            return Err(format!("Unsupported PEF row length: {row_bits} bits"));
+        }
+        let bytes_per_row = row_bits / 8;
+        // Rows are read in whole chunks of the bit order.
+        if !bytes_per_row.is_multiple_of(BitOrder::MSB.mcu_bytelen()) {
+            return Err(format!("Unsupported PEF row pitch: {bytes_per_row}"));
+        }
+        let input = strip
+            .get(..bytes_per_row * height)
+            .ok_or("The PEF strip is too small")?;
+        let bytes_per_row = non_zero(bytes_per_row, "row pitch")?;
+        Ok(Format::Uncompressed {
+            input: Array2DRef::new(
+                input,
+                RowLength::new(bytes_per_row),
+                RowPitch::new(bytes_per_row),
+            ),
+            bits,
+        })
+    }
+
+    #[inline(never)]
+    pub fn new<F>(
+        root: &TiffRootIFD<'a>,
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<(Self, ProcurementRequest), String>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let ifd = root
+            .get_ifds_with_tag(TiffTag::STRIP_OFFSETS)
+            .into_iter()
+            .next()
+            .ok_or("No raw image found in PEF")?;
+        let compression =
+            get_u32(ifd, TiffTag::COMPRESSION)?.unwrap_or(COMPRESSION_NONE);
+        if !matches!(
+            compression,
+            COMPRESSION_NONE | COMPRESSION_PACKBITS | COMPRESSION_PENTAX
+        ) {
+            return Err(format!("Unsupported PEF compression: {compression}"));
+        }
+        let dims = Self::parse_dims(ifd)?;
+
+        let make = get_root_string(root, TiffTag::MAKE).unwrap_or("");
+        let model = get_root_string(root, TiffTag::MODEL).unwrap_or("");
+        let camera = cameras
+            .find(make, model, None)
+            .ok_or_else(|| format!("Unknown camera: {make} {model}"))?;
+        check_camera_support_fn(camera.supported)?;
+        let iso_speed = root
+            .get_entry_recursive(TiffTag::ISO_SPEED_RATINGS)
+            .map(TiffEntry::get_u32s)
+            .and_then(Result::ok)
+            .and_then(|isos| isos.first().copied());
+        let metadata = CameraMetadata::new(camera, make, model, iso_speed);
+
+        let makernote = parse_makernote(root)?;
+        let strip = get_strip(root.input(), ifd)?;
+        let cpp = Self::parse_cpp(ifd)?;
+        let format = if compression == COMPRESSION_PENTAX {
+            if cpp.get() != 1 {
+                return Err(format!(
+                    "Unsupported samples per pixel for compressed PEF: {cpp}"
+                ));
+            }
+            Self::parse_compressed(strip, dims, makernote.as_ref())?
+        } else {
+            let bits = get_u32(ifd, TiffTag::BITS_PER_SAMPLE)?.unwrap_or(0);
+            if !(1..=T::BITS).contains(&bits) {
+                return Err(format!("Unsupported bits per sample: {bits}"));
+            }
+            let sample_dims =
+                Dimensions2D::new(
+                    RowLength::new(dims.row_len().checked_mul(cpp).ok_or(
+                        "Overflow when computing per-row sample count",
+                    )?),
+                    dims.row_count(),
+                );
+            Self::parse_uncompressed(strip, sample_dims, bits)?
+        };
+        let (black_levels, wb_coeffs) = match makernote {
+            Some(makernote) => (
+                parse_black_levels(&makernote)?,
+                parse_wb_coeffs(&makernote)?,
+            ),
+            None => (None, None),
+        };
+        Ok((
+            Self {
+                metadata,
+                format,
+                dims,
+                cpp,
+                black_levels,
+                wb_coeffs,
+            },
+            NDSliceProcurementRequest::<T>::new(dims)
+                .set_cpp(cpp)
+                .into(),
+        ))
+    }
+}
+
+#[expect(clippy::missing_trait_methods)]
+impl RawDemuxer for PefDemuxer<'_> {
+    #[inline]
+    fn make(&self) -> &str {
+        self.metadata.make
+    }
+
+    #[inline]
+    fn model(&self) -> &str {
+        self.metadata.model
+    }
+
+    #[inline]
+    fn mode(&self) -> Option<&str> {
+        self.metadata.mode()
+    }
+
+    #[inline]
+    fn canonical_make(&self) -> &str {
+        self.metadata.canonical_make()
+    }
+
+    #[inline]
+    fn canonical_model(&self) -> &str {
+        self.metadata.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_alias(&self) -> &str {
+        self.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_id(&self) -> String {
+        self.metadata.canonical_id()
+    }
+
+    #[inline]
+    fn iso_speed(&self) -> Option<u32> {
+        self.metadata.iso_speed
+    }
+
+    #[inline]
+    fn blacklevel(&self) -> Option<u16> {
+        self.metadata.blacklevel()
+    }
+
+    #[inline]
+    fn whitelevel(&self) -> Option<u16> {
+        self.metadata.whitelevel()
+    }
+
+    #[inline]
+    fn blacklevel_separate(&self) -> Option<Array2DRef<'_, i32>> {
+        self.black_levels.as_ref().map(OwnedArray2D::mat)
+    }
+
+    #[inline]
+    fn wb_coeffs(&self) -> Option<[f32; 4]> {
+        self.wb_coeffs
+    }
+
+    #[inline]
+    fn colormatrix(&self) -> Option<Array2DRef<'_, i16>> {
+        self.metadata.colormatrix()
+    }
+
+    #[inline]
+    fn is_cfa(&self) -> bool {
+        self.cpp.get() == 1 && self.metadata.is_cfa()
+    }
+
+    #[inline]
+    fn cfa(
+        &self,
+        origin: Coord2D,
+    ) -> Option<OffsetArray2DRef<'_, ColorVariant>> {
+        if !self.is_cfa() {
+            return None;
+        }
+        self.metadata.cfa(origin)
+    }
+
+    #[inline]
+    fn bpp(&self) -> usize {
+        let bpc = match self.datatype() {
+            DataType::U16 => size_of::<u16>(),
+            DataType::F32 => size_of::<f32>(),
+            DataType::U32 => size_of::<u32>(),
+            _ => unreachable!(),
+        };
+        bpc.checked_mul(self.cpp()).unwrap()
+    }
+
+    #[inline]
+    fn cpp(&self) -> usize {
+        self.cpp.get()
+    }
+
+    #[inline]
+    fn datatype(&self) -> DataType {
+        DataType::U16
+    }
+
+    #[inline]
+    fn dim_uncropped(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        self.dims
+    }
+
+    #[inline]
+    fn dim_cropped(&self) -> Option<Dimensions2D<core::num::NonZero<usize>>> {
+        self.metadata.dim_cropped(self.dim_uncropped())
+    }
+
+    #[inline]
+    fn crop_offset(&self) -> Option<Coord2D> {
+        self.metadata.crop_offset()
+    }
+
+    #[inline]
+    fn black_areas(&self) -> Option<&[BlackArea]> {
+        self.metadata.black_areas()
+    }
+
+    #[inline]
+    fn fuji_rotation_pos(&self) -> Option<u32> {
+        None
+    }
+
+    #[inline]
+    fn pixel_aspect_ratio(&self) -> Option<f64> {
+        None
+    }
+
+    #[inline]
+    fn bad_pixel_positions(&self) -> Vec<Coord2D> {
+        vec![]
+    }
+
+    #[inline(never)]
+    fn decode(
+        &self,
+        output: &mut ImageRefMut<'_>,
+    ) -> Result<(), RawDemuxerError> {
+        let output = output.as_u16()?;
+        let dims = Dimensions2D::new(
+            RowLength::new(self.dims.row_len().checked_mul(self.cpp).unwrap()),
+            self.dims.row_count(),
+        );
+        if output.dims() != dims {
+            return Err(RawDemuxerError::DecoderError(
+                "Output buffer dimensions differ from expected".to_owned(),
+            ));
+        }
+        match &self.format {
+            Format::Compressed(decompressor) => decompressor
+                .decode(output)
+                .map_err(|err| RawDemuxerError::DecoderError(err.to_string())),
+            Format::Uncompressed { input, bits } => {
+                Unpacker::new(*input, BitOrder::MSB, *bits, output).unpack();
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
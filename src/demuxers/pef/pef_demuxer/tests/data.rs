@@ -0,0 +1,218 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_codecs_huffman::huffman::HuffmanCode;
+use rawspeed_codecs_pentax::pentax::legacy_code;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_parsers_tiffparser::tiffparser::TiffTag;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::pack;
+use rawspeed_utils_tiffbuilder::tiffbuilder::{Value, tag};
+
+use super::{
+    COMPRESSION_PACKBITS, COMPRESSION_PENTAX, Pef, compress, decode,
+    huffman_table, makernote, new_demuxer_err,
+};
+
+fn rows(width: usize, height: usize, bits: u32) -> Vec<Vec<u16>> {
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| {
+                    let sample = (row * 977 + col * 313 + 5) % (1 << bits);
+                    u16::try_from(sample).unwrap()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn compressed(rows: &[Vec<u16>], code: &HuffmanCode) -> Pef {
+    let width = u32::try_from(rows.first().unwrap().len()).unwrap();
+    let height = u32::try_from(rows.len()).unwrap();
+    let mut pef = Pef::new(width, height, compress(code, rows));
+    pef.compression = u16::try_from(COMPRESSION_PENTAX).unwrap();
+    pef
+}
+
+/// A code other than the legacy one, with longer codes for the short
+/// differences.
+fn custom_code() -> HuffmanCode {
+    HuffmanCode::new(
+        &[0, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0],
+        &[10, 11, 8, 9, 6, 7, 4, 5, 2, 3, 1, 0, 12, 13, 14],
+    )
+    .unwrap()
+}
+
+#[test]
+fn uncompressed_test() {
+    let rows = rows(8, 3, 12);
+    let data = pack(&rows.concat(), 12, BitOrder::MSB);
+    assert_eq!(decode(&Pef::new(8, 3, data.clone())), Ok(rows.clone()));
+    // Despite its name, this compression stores the samples as is.
+    let mut pef = Pef::new(8, 3, data);
+    pef.compression = u16::try_from(COMPRESSION_PACKBITS).unwrap();
+    assert_eq!(decode(&pef), Ok(rows));
+}
+
+#[test]
+fn uncompressed_16_bit_test() {
+    let rows = rows(2, 3, 16);
+    let mut pef = Pef::new(2, 3, pack(&rows.concat(), 16, BitOrder::MSB));
+    pef.bits = 16;
+    assert_eq!(decode(&pef), Ok(rows));
+}
+
+#[test]
+fn pixel_shift_test() {
+    let rows = rows(2 * 4, 3, 16);
+    let mut pef = Pef::new(2, 3, pack(&rows.concat(), 16, BitOrder::MSB));
+    pef.bits = 16;
+    pef.cpp = Some(4);
+    assert_eq!(decode(&pef), Ok(rows));
+}
+
+#[test]
+fn unsupported_samples_per_pixel_test() {
+    let mut pef = Pef::new(8, 3, vec![0; 8 * 3 * 3 * 2]);
+    pef.cpp = Some(3);
+    assert_eq!(
+        new_demuxer_err(&pef.build()),
+        "Unsupported samples per pixel: 3"
+    );
+    let rows = rows(8, 3, 12);
+    let mut compressed = compressed(&rows, &legacy_code());
+    compressed.cpp = Some(4);
+    assert_eq!(
+        new_demuxer_err(&compressed.build()),
+        "Unsupported samples per pixel for compressed PEF: 4"
+    );
+}
+
+#[test]
+fn legacy_compressed_test() {
+    let rows = rows(6, 4, 12);
+    assert_eq!(decode(&compressed(&rows, &legacy_code())), Ok(rows));
+}
+
+#[test]
+fn makernote_huffman_table_test() {
+    let rows = rows(6, 4, 12);
+    for endianness in [Endianness::Little, Endianness::Big] {
+        let mut pef = compressed(&rows, &custom_code());
+        pef.makernote = Some(makernote(
+            endianness,
+            vec![tag(
+                TiffTag::PENTAX_HUFFMAN_TABLE,
+                Value::Undefined(huffman_table(&custom_code(), endianness)),
+            )],
+        ));
+        assert_eq!(decode(&pef), Ok(rows.clone()));
+    }
+}
+
+#[test]
+fn aoc_makernote_huffman_table_test() {
+    let rows = rows(6, 4, 12);
+    let mut pef = compressed(&rows, &custom_code());
+    pef.endianness = Endianness::Big;
+    pef.set_aoc_makernote(vec![tag(
+        TiffTag::PENTAX_HUFFMAN_TABLE,
+        Value::Undefined(huffman_table(&custom_code(), Endianness::Big)),
+    )]);
+    assert_eq!(decode(&pef), Ok(rows));
+}
+
+#[test]
+fn invalid_huffman_table_test() {
+    let mut table = huffman_table(&legacy_code(), Endianness::Little);
+    *table.last_mut().unwrap() = 0;
+    let mut pef = compressed(&rows(6, 4, 12), &legacy_code());
+    pef.makernote = Some(makernote(
+        Endianness::Little,
+        vec![tag(TiffTag::PENTAX_HUFFMAN_TABLE, Value::Undefined(table))],
+    ));
+    assert_eq!(
+        new_demuxer_err(&pef.build()),
+        "PentaxError(InvalidHuffmanTable)"
+    );
+    let valid_table = huffman_table(&legacy_code(), Endianness::Little);
+    pef.makernote = Some(makernote(
+        Endianness::Little,
+        vec![tag(TiffTag::PENTAX_HUFFMAN_TABLE, Value::Byte(valid_table))],
+    ));
+    assert_eq!(
+        new_demuxer_err(&pef.build()),
+        "The PEF Huffman table has an unexpected type"
+    );
+}
+
+#[test]
+fn odd_width_test() {
+    let mut pef = Pef::new(5, 2, vec![0; 16]);
+    pef.compression = u16::try_from(COMPRESSION_PENTAX).unwrap();
+    assert_eq!(new_demuxer_err(&pef.build()), "PentaxError(OddWidth)");
+}
+
+#[test]
+fn unsupported_compression_test() {
+    let mut pef = Pef::new(8, 2, vec![0; 24]);
+    pef.compression = 7;
+    assert_eq!(
+        new_demuxer_err(&pef.build()),
+        "Unsupported PEF compression: 7"
+    );
+}
+
+#[test]
+fn unsupported_bits_per_sample_test() {
+    let mut pef = Pef::new(8, 2, vec![0; 24]);
+    pef.bits = 17;
+    assert_eq!(
+        new_demuxer_err(&pef.build()),
+        "Unsupported bits per sample: 17"
+    );
+}
+
+#[test]
+fn unsupported_row_test() {
+    assert_eq!(
+        new_demuxer_err(&Pef::new(3, 2, vec![0; 24]).build()),
+        "Unsupported PEF row length: 36 bits"
+    );
+}
+
+#[test]
+fn odd_row_pitch_test() {
+    let rows = rows(6, 2, 12);
+    let data = pack(&rows.concat(), 12, BitOrder::MSB);
+    assert_eq!(decode(&Pef::new(6, 2, data)), Ok(rows));
+}
+
+#[test]
+fn strip_too_small_test() {
+    assert_eq!(
+        new_demuxer_err(&Pef::new(8, 2, vec![0; 23]).build()),
+        "The PEF strip is too small"
+    );
+}
+
+#[test]
+fn no_raw_image_test() {
+    let mut builder = Pef::new(8, 2, vec![0; 24]).builder();
+    builder.remove(TiffTag::STRIP_OFFSETS);
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "No raw image found in PEF"
+    );
+}
+
+#[test]
+fn zero_dimensions_test() {
+    assert_eq!(
+        new_demuxer_err(&Pef::new(0, 2, vec![0; 24]).build()),
+        "The width is zero"
+    );
+    assert_eq!(
+        new_demuxer_err(&Pef::new(8, 0, vec![0; 24]).build()),
+        "The height is zero"
+    );
+}
@@ -0,0 +1,164 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_std::coord_common::{ColIndex, Coord2D, RowIndex};
+use rawspeed_utils_tiffbuilder::tiffbuilder::{Entries, Value, tag};
+
+use super::{Pef, PefDemuxer, makernote, new_demuxer_err, parse_cameras};
+
+fn unpacked(model: &'static str) -> Pef {
+    let mut pef = Pef::new(8, 2, vec![0; 24]);
+    pef.model = model;
+    pef
+}
+
+fn makernote_entries() -> Entries {
+    vec![
+        tag(
+            TiffTag::PENTAX_BLACK_LEVEL,
+            Value::Short(vec![60, 61, 62, 63]),
+        ),
+        tag(
+            TiffTag::PENTAX_WB_LEVELS,
+            Value::Short(vec![9000, 8192, 8190, 12000]),
+        ),
+    ]
+}
+
+macro_rules! with_demuxer {
+    ($pef:expr, |$demuxer:ident| $body:block) => {{
+        let input = $pef.build();
+        let root = TiffParser::parse(&input).unwrap();
+        let cameras = parse_cameras();
+        let ($demuxer, _) = PefDemuxer::new(
+            &root,
+            &cameras,
+            DecodeableCamera::new_unless_unsupported,
+        )
+        .unwrap();
+        $body
+    }};
+}
+
+fn levels(demuxer: &PefDemuxer<'_>) -> [i32; 4] {
+    let levels = demuxer.blacklevel_separate().unwrap();
+    let level =
+        |row, col| levels[Coord2D::new(RowIndex::new(row), ColIndex::new(col))];
+    [level(0, 0), level(0, 1), level(1, 0), level(1, 1)]
+}
+
+fn assert_wb_coeffs(demuxer: &PefDemuxer<'_>, expected: [f32; 3]) {
+    let [red, green, blue, fourth] = demuxer.wb_coeffs().unwrap();
+    assert_eq!(
+        [red, green, blue].map(f32::to_bits),
+        expected.map(f32::to_bits)
+    );
+    assert!(fourth.is_nan());
+}
+
+#[test]
+fn camera_from_cameras_xml_test() {
+    with_demuxer!(unpacked("PENTAX K-0"), |demuxer| {
+        assert_eq!(demuxer.make(), "PENTAX Corporation");
+        assert_eq!(demuxer.model(), "PENTAX K-0");
+        assert_eq!(demuxer.mode(), None);
+        assert_eq!(demuxer.canonical_make(), "Pentax");
+        assert_eq!(demuxer.canonical_id(), "Pentax K-0");
+        assert_eq!(demuxer.iso_speed(), Some(400));
+        assert_eq!(demuxer.blacklevel(), Some(128));
+        assert_eq!(demuxer.whitelevel(), Some(4095));
+        assert!(demuxer.is_cfa());
+    });
+}
+
+#[test]
+fn pixel_shift_is_not_cfa_test() {
+    let mut pef = Pef::new(8, 2, vec![0; 8 * 2 * 4 * 12 / 8]);
+    pef.cpp = Some(4);
+    with_demuxer!(pef, |demuxer| {
+        assert_eq!(demuxer.cpp(), 4);
+        assert_eq!(demuxer.bpp(), 4 * 2);
+        assert_eq!(demuxer.dim_uncropped().row_len().get(), 8);
+        assert!(!demuxer.is_cfa());
+        assert!(
+            demuxer
+                .cfa(Coord2D::new(RowIndex::new(0), ColIndex::new(0)))
+                .is_none()
+        );
+    });
+}
+
+#[test]
+fn unknown_camera_test() {
+    assert_eq!(
+        new_demuxer_err(&unpacked("PENTAX K-1").build()),
+        "Unknown camera: PENTAX Corporation PENTAX K-1"
+    );
+    assert_eq!(
+        new_demuxer_err(&unpacked("Unsupported").build()),
+        "This camera is not supported (explicit)"
+    );
+}
+
+#[test]
+fn makernote_test() {
+    for endianness in [Endianness::Little, Endianness::Big] {
+        let mut pef = unpacked("PENTAX K-0");
+        pef.makernote = Some(makernote(endianness, makernote_entries()));
+        with_demuxer!(pef, |demuxer| {
+            assert_eq!(levels(&demuxer), [60, 61, 62, 63]);
+            assert_wb_coeffs(&demuxer, [9000.0, 8192.0, 12000.0]);
+        });
+    }
+}
+
+#[test]
+fn aoc_makernote_test() {
+    let mut pef = unpacked("PENTAX K-0");
+    pef.set_aoc_makernote(makernote_entries());
+    with_demuxer!(pef, |demuxer| {
+        assert_eq!(levels(&demuxer), [60, 61, 62, 63]);
+        assert_wb_coeffs(&demuxer, [9000.0, 8192.0, 12000.0]);
+    });
+}
+
+#[test]
+fn partial_makernote_test() {
+    let mut pef = unpacked("PENTAX K-0");
+    pef.makernote = Some(makernote(
+        Endianness::Little,
+        vec![
+            tag(TiffTag::PENTAX_BLACK_LEVEL, Value::Short(vec![60, 61])),
+            tag(TiffTag::PENTAX_WB_LEVELS, Value::Short(vec![9000, 8192])),
+        ],
+    ));
+    with_demuxer!(pef, |demuxer| {
+        assert!(demuxer.blacklevel_separate().is_none());
+        assert!(demuxer.wb_coeffs().is_none());
+    });
+}
+
+#[test]
+fn no_makernote_test() {
+    with_demuxer!(unpacked("PENTAX K-0"), |demuxer| {
+        assert!(demuxer.blacklevel_separate().is_none());
+        assert!(demuxer.wb_coeffs().is_none());
+    });
+    // Other `MakerNote`s are ignored.
+    let mut pef = unpacked("PENTAX K-0");
+    pef.makernote = Some(b"RICOH\0II\0\0".to_vec());
+    with_demuxer!(pef, |demuxer| {
+        assert!(demuxer.wb_coeffs().is_none());
+    });
+}
+
+#[test]
+fn invalid_makernote_byte_order_test() {
+    let mut pef = unpacked("PENTAX K-0");
+    pef.makernote = Some(b"PENTAX \0XX\0\0".to_vec());
+    assert_eq!(
+        new_demuxer_err(&pef.build()),
+        "The PEF MakerNote byte order is invalid"
+    );
+}
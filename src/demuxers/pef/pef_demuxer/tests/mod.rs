@@ -0,0 +1,226 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_codecs_huffman::huffman::HuffmanCode;
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::Cameras;
+use rawspeed_metadata_xmlparser::xmlparser;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_std::coord_common::RowIndex;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+use rawspeed_utils_tiffbuilder::tiffbuilder::{
+    Entries, TiffBuilder, Value, tag,
+};
+
+use super::{
+    COMPRESSION_NONE, COMPRESSION_PACKBITS, COMPRESSION_PENTAX, PefDemuxer,
+};
+
+const CAMERAS: &str = "
+    <Cameras>
+        <Camera make=\"PENTAX Corporation\" model=\"PENTAX K-0\">
+            <ID make=\"Pentax\" model=\"K-0\">Pentax K-0</ID>
+            <CFA width=\"2\" height=\"2\">
+                <Color x=\"0\" y=\"0\">RED</Color>
+                <Color x=\"1\" y=\"0\">GREEN</Color>
+                <Color x=\"0\" y=\"1\">GREEN</Color>
+                <Color x=\"1\" y=\"1\">BLUE</Color>
+            </CFA>
+            <Sensor black=\"128\" white=\"4095\"/>
+        </Camera>
+        <Camera make=\"PENTAX Corporation\" model=\"Unsupported\"
+                supported=\"no\">
+        </Camera>
+    </Cameras>";
+
+/// Encodes the rows with the given code, each sample being predicted
+/// from the previous one of the same color, and the first two of each
+/// row from those two rows above.
+fn compress(code: &HuffmanCode, rows: &[Vec<u16>]) -> Vec<u8> {
+    let codes = code.codes();
+    let mut bits = BitStreamBuilder::new();
+    for (row, samples) in rows.iter().enumerate() {
+        for (col, sample) in samples.iter().enumerate() {
+            let pred = match (col.checked_sub(2), row.checked_sub(2)) {
+                (Some(col), _) => *samples.get(col).unwrap(),
+                (None, Some(row)) => *rows.get(row).unwrap().get(col).unwrap(),
+                (None, None) => 0,
+            };
+            let diff = i32::from(*sample) - i32::from(pred);
+            let len = 32 - diff.unsigned_abs().leading_zeros();
+            let index = code
+                .symbols()
+                .iter()
+                .position(|s| u32::from(*s) == len)
+                .unwrap();
+            let (code_len, bits_of_code) = *codes.get(index).unwrap();
+            bits.put(bits_of_code, code_len);
+            let value = if diff < 0 {
+                diff + (1 << len) - 1
+            } else {
+                diff
+            };
+            bits.put(u32::try_from(value).unwrap(), len);
+        }
+    }
+    bits.finish(BitOrder::MSB)
+}
+
+/// The `MakerNote` Huffman table that describes the code.
+fn huffman_table(code: &HuffmanCode, endianness: Endianness) -> Vec<u8> {
+    let u16_bytes = |val: u16| {
+        let [hi, lo] = [val >> 8, val & 0xFF].map(|b| u8::try_from(b).unwrap());
+        match endianness {
+            Endianness::Little => [lo, hi],
+            Endianness::Big => [hi, lo],
+        }
+    };
+    let mut codes = code
+        .codes()
+        .into_iter()
+        .zip(code.symbols())
+        .map(|((len, bits), symbol)| (*symbol, len, bits))
+        .collect::<Vec<_>>();
+    codes.sort_unstable();
+    let mut out = u16_bytes(u16::try_from(codes.len() - 12).unwrap()).to_vec();
+    out.resize(out.len() + 12, 0);
+    for (_, len, bits) in &codes {
+        out.extend(u16_bytes(u16::try_from(bits << (12 - len)).unwrap()));
+    }
+    for (_, len, _) in &codes {
+        out.push(u8::try_from(*len).unwrap());
+    }
+    out
+}
+
+/// A `MakerNote` of newer cameras, with its own byte order.
+fn makernote(endianness: Endianness, entries: Entries) -> Vec<u8> {
+    let mut builder = TiffBuilder::new(endianness);
+    *builder.ifd(0) = entries;
+    let prefix = match endianness {
+        Endianness::Little => b"PENTAX \0II",
+        Endianness::Big => b"PENTAX \0MM",
+    };
+    builder.build_after(prefix)
+}
+
+#[derive(Debug)]
+struct Pef {
+    endianness: Endianness,
+    model: &'static str,
+    width: u32,
+    height: u32,
+    compression: u16,
+    bits: u16,
+    /// The `SamplesPerPixel`, if any.
+    cpp: Option<u16>,
+    data: Vec<u8>,
+    makernote: Option<Vec<u8>>,
+}
+
+impl Pef {
+    fn new(width: u32, height: u32, data: Vec<u8>) -> Self {
+        Self {
+            endianness: Endianness::Little,
+            model: "PENTAX K-0",
+            width,
+            height,
+            compression: u16::try_from(COMPRESSION_NONE).unwrap(),
+            bits: 12,
+            cpp: None,
+            data,
+            makernote: None,
+        }
+    }
+
+    fn builder(&self) -> TiffBuilder {
+        let mut builder = TiffBuilder::new(self.endianness);
+        builder.ifd(0).extend([
+            tag(TiffTag::MAKE, Value::Ascii("PENTAX Corporation")),
+            tag(TiffTag::MODEL, Value::Ascii(self.model)),
+            tag(TiffTag::IMAGE_WIDTH, Value::Long(vec![self.width])),
+            tag(TiffTag::IMAGE_LENGTH, Value::Long(vec![self.height])),
+            tag(TiffTag::BITS_PER_SAMPLE, Value::Short(vec![self.bits])),
+            tag(TiffTag::COMPRESSION, Value::Short(vec![self.compression])),
+            tag(TiffTag::STRIP_OFFSETS, Value::BlobOffsets(vec![0])),
+            tag(TiffTag::STRIP_BYTE_COUNTS, Value::BlobSizes(vec![0])),
+            tag(TiffTag::EXIF_IFD_POINTER, Value::IFDOffset(1)),
+        ]);
+        if let Some(cpp) = self.cpp {
+            builder
+                .ifd(0)
+                .push(tag(TiffTag::SAMPLES_PER_PIXEL, Value::Short(vec![cpp])));
+        }
+        builder
+            .ifd(1)
+            .push(tag(TiffTag::ISO_SPEED_RATINGS, Value::Short(vec![400])));
+        if let Some(makernote) = &self.makernote {
+            builder.ifd(1).push(tag(
+                TiffTag::MAKER_NOTE,
+                Value::Undefined(makernote.clone()),
+            ));
+        }
+        builder.blobs = vec![self.data.clone()];
+        builder
+    }
+
+    fn build(&self) -> Vec<u8> {
+        self.builder().build()
+    }
+
+    /// Sets a `MakerNote` of older cameras, whose offsets are relative to
+    /// the start of the file, and thus depend on where it is stored.
+    fn set_aoc_makernote(&mut self, entries: Entries) {
+        let mut builder = TiffBuilder::new(self.endianness);
+        *builder.ifd(0) = entries;
+        let signature = match self.endianness {
+            Endianness::Little => b"AOC\0II",
+            Endianness::Big => b"AOC\0MM",
+        };
+        self.makernote = Some(builder.build_after(signature));
+        let input = self.build();
+        let root = TiffParser::parse(&input).unwrap();
+        let offset = root
+            .get_entry_recursive(TiffTag::MAKER_NOTE)
+            .unwrap()
+            .data_offset();
+        let mut prefix = vec![0; offset];
+        prefix.extend(signature);
+        let makernote = builder.build_after(&prefix);
+        self.makernote = Some(makernote.get(offset..).unwrap().to_vec());
+    }
+}
+
+fn parse_cameras() -> Cameras<'static> {
+    xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap()
+}
+
+fn new_demuxer_err(input: &[u8]) -> String {
+    let root = TiffParser::parse(input).unwrap();
+    let cameras = parse_cameras();
+    PefDemuxer::new(&root, &cameras, DecodeableCamera::new_unless_unsupported)
+        .unwrap_err()
+}
+
+fn decode(pef: &Pef) -> Result<Vec<Vec<u16>>, String> {
+    let input = pef.build();
+    let root = TiffParser::parse(&input).unwrap();
+    let cameras = parse_cameras();
+    let (demuxer, request) = PefDemuxer::new(
+        &root,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )?;
+    let mut output_buf = request.fulfill().unwrap();
+    let mut image = output_buf.get_mut();
+    demuxer.decode(&mut image).map_err(|err| err.to_string())?;
+    let output = image.as_u16().unwrap();
+    Ok((0..output.num_rows().get())
+        .map(|row| output.get_row(RowIndex::new(row)).unwrap().to_vec())
+        .collect())
+}
+
+#[cfg(test)]
+mod data;
+#[cfg(test)]
+mod metadata;
@@ -0,0 +1 @@
+pub mod raf_demuxer;
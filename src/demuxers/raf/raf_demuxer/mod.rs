@@ -0,0 +1,549 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_bitstream_bytestream_decoder::bytestreamer::ByteStreamer;
+use rawspeed_codecs_fuji::fuji::FujiDecompressor;
+use rawspeed_codecs_packed_decoder::packed_decoder::Unpacker;
+use rawspeed_demuxers_common::{
+    camera_metadata::CameraMetadata,
+    tiff_utils::{get_root_string, get_u32, get_usize, non_zero, tiff_err},
+};
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::{
+    DataType, ImageRefMut, ProcurementRequest, RawDemuxer, RawDemuxerError,
+};
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_memory_nd_slice_procurement::ndsliceprocurement::NDSliceProcurementRequest;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::{
+    Cameras, Supported, blackareas::BlackArea,
+};
+use rawspeed_metadata_colorfilterarray::colorfilterarray::ColorVariant;
+use rawspeed_parsers_tiffparser::tiffparser::{
+    TiffEntry, TiffIFD, TiffParser, TiffRootIFD, TiffTag,
+};
+use rawspeed_std::coord_common::{
+    ColIndex, Coord2D, Dimensions2D, RowCount, RowIndex, RowLength, RowPitch,
+};
+use rawspeed_std_ndslice::{
+    array2dref::Array2DRef, offsetarray2dref::OffsetArray2DRef,
+};
+
+const RAF_MAGIC: &[u8] = b"FUJIFILM";
+
+/// The (big-endian) offsets and lengths of the embedded JPEG, of the
+/// metadata directory and of the raw data start here.
+const SECTIONS_OFFSET: usize = 0x54;
+
+/// The EXIF TIFF follows the SOI and APP1 markers of the JPEG.
+const JPEG_EXIF_OFFSET: usize = 12;
+
+/// The metadata directory has at most this many entries.
+const MAX_DIRECTORY_ENTRIES: usize = 255;
+
+/// The metadata directory tags: the raw image height and width, the
+/// sensor layout, and the GRGB white balance levels.
+const RAF_IMAGE_SIZE: u16 = 0x100;
+const RAF_LAYOUT: u16 = 0x130;
+const RAF_WB_LEVELS: u16 = 0x2FF0;
+
+/// The bits per sample of the compressed data, unless stated otherwise.
+const DEFAULT_BITS: u32 = 12;
+
+type T = u16;
+
+fn read_u16(data: &[u8]) -> Option<u16> {
+    data.get(..size_of::<u16>())
+        .map(|bytes| ByteStreamer::new(bytes, Endianness::Big).read())
+}
+
+fn read_u32(data: &[u8]) -> Option<usize> {
+    data.get(..size_of::<u32>()).map(|bytes| {
+        let val: u32 = ByteStreamer::new(bytes, Endianness::Big).read();
+        val.try_into().unwrap()
+    })
+}
+
+/// The embedded JPEG, the metadata directory and the raw data.
+#[derive(Debug, Clone, Copy)]
+struct Sections<'a> {
+    jpeg: &'a [u8],
+    directory: &'a [u8],
+    raw: &'a [u8],
+}
+
+impl<'a> Sections<'a> {
+    fn parse(input: &'a [u8]) -> Result<Self, String> {
+        if !input.starts_with(RAF_MAGIC) {
+            return Err("Not a RAF file".to_owned());
+        }
+        let section = |index: usize, what: &str| {
+            let pos = SECTIONS_OFFSET + index * 2 * size_of::<u32>();
+            let offset = input.get(pos..).and_then(read_u32);
+            let len = input.get(pos + size_of::<u32>()..).and_then(read_u32);
+            let (offset, len) =
+                offset.zip(len).ok_or("The RAF header is truncated")?;
+            input
+                .get(offset..)
+                .and_then(|data| data.get(..len))
+                .ok_or_else(|| format!("The RAF {what} is out of bounds"))
+        };
+        Ok(Self {
+            jpeg: section(0, "JPEG")?,
+            directory: section(1, "metadata directory")?,
+            raw: section(2, "raw data")?,
+        })
+    }
+}
+
+/// The metadata directory: the number of entries, then each one's tag,
+/// length and data.
+#[derive(Debug)]
+struct Directory<'a> {
+    entries: Vec<(u16, &'a [u8])>,
+}
+
+impl<'a> Directory<'a> {
+    fn parse(input: &'a [u8]) -> Result<Self, String> {
+        const TRUNCATED: &str = "The RAF metadata directory is truncated";
+        let count = read_u32(input).ok_or(TRUNCATED)?;
+        if count > MAX_DIRECTORY_ENTRIES {
+            return Err(format!(
+                "Too many entries in the RAF metadata directory: {count}"
+            ));
+        }
+        let mut rest = input.get(size_of::<u32>()..).unwrap();
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            let tag = read_u16(rest).ok_or(TRUNCATED)?;
+            let len = rest.get(size_of::<u16>()..).and_then(read_u16);
+            let len = usize::from(len.ok_or(TRUNCATED)?);
+            let (data, next) = rest
+                .get(2 * size_of::<u16>()..)
+                .and_then(|data| data.split_at_checked(len))
+                .ok_or(TRUNCATED)?;
+            entries.push((tag, data));
+            rest = next;
+        }
+        Ok(Self { entries })
+    }
+
+    fn get(&self, tag: u16) -> Option<&'a [u8]> {
+        self.entries
+            .iter()
+            .find(|(entry_tag, _)| *entry_tag == tag)
+            .map(|(_, data)| *data)
+    }
+
+    /// The height precedes the width.
+    fn dims(&self) -> Option<(usize, usize)> {
+        let data = self.get(RAF_IMAGE_SIZE)?;
+        let height = read_u16(data)?;
+        let width = read_u16(data.get(size_of::<u16>()..)?)?;
+        Some((width.into(), height.into()))
+    }
+
+    /// The sensor rows are either horizontal or vertical halves of the
+    /// rotated image.
+    fn alt_layout(&self) -> bool {
+        self.get(RAF_LAYOUT)
+            .and_then(|data| data.first())
+            .is_some_and(|layout| layout >> 7 == 0)
+    }
+
+    /// The levels are stored in GRGB order.
+    fn wb_coeffs(&self) -> Option<[f32; 4]> {
+        let data = self.get(RAF_WB_LEVELS)?;
+        let level = |index: usize| {
+            data.get(index * size_of::<u16>()..)
+                .and_then(read_u16)
+                .map(f32::from)
+        };
+        Some([level(1)?, level(0)?, level(3)?, f32::NAN])
+    }
+}
+
+/// The raw data of newer cameras is described by a TIFF, whose offsets
+/// are relative to its start; older ones store the samples directly.
+#[derive(Debug, Clone, Copy)]
+struct RawData<'a> {
+    data: &'a [u8],
+    endianness: Endianness,
+    dims: Option<(usize, usize)>,
+    bits: Option<u32>,
+}
+
+impl<'a> RawData<'a> {
+    fn parse_tiff(
+        root: &TiffRootIFD<'a>,
+        ifd: &TiffIFD<'_>,
+    ) -> Result<Self, String> {
+        let offset = get_usize(ifd, TiffTag::FUJI_STRIP_OFFSETS)?.unwrap();
+        let len = get_usize(ifd, TiffTag::FUJI_STRIP_BYTE_COUNTS)?
+            .ok_or("The RAF strip byte count is missing")?;
+        let data = root
+            .input()
+            .get(offset..)
+            .and_then(|data| data.get(..len))
+            .filter(|data| !data.is_empty())
+            .ok_or("The RAF strip data is truncated")?;
+        let width = get_usize(ifd, TiffTag::FUJI_RAW_IMAGE_FULL_WIDTH)?;
+        let height = get_usize(ifd, TiffTag::FUJI_RAW_IMAGE_FULL_HEIGHT)?;
+        Ok(Self {
+            data,
+            endianness: root.endianness(),
+            dims: width.zip(height),
+            bits: get_u32(ifd, TiffTag::FUJI_BITS_PER_SAMPLE)?,
+        })
+    }
+
+    fn parse(raw: &'a [u8]) -> Result<Self, String> {
+        if let Ok(root) = TiffParser::parse(raw)
+            && let Some(ifd) = root
+                .get_ifds_with_tag(TiffTag::FUJI_STRIP_OFFSETS)
+                .into_iter()
+                .next()
+        {
+            return Self::parse_tiff(&root, ifd);
+        }
+        Ok(Self {
+            data: raw,
+            endianness: Endianness::Big,
+            dims: None,
+            bits: None,
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Format<'a> {
+    Compressed(FujiDecompressor<'a>),
+    Uncompressed {
+        input: Array2DRef<'a, u8>,
+        bit_order: BitOrder,
+        bits: u32,
+    },
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct RafDemuxer<'a> {
+    metadata: CameraMetadata<'a>,
+    format: Format<'a>,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    wb_coeffs: Option<[f32; 4]>,
+    alt_layout: bool,
+}
+
+impl<'a> RafDemuxer<'a> {
+    fn parse_compressed(
+        raw: &RawData<'a>,
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        metadata: &CameraMetadata<'_>,
+    ) -> Result<Format<'a>, String> {
+        const ZERO_POINT: Coord2D =
+            Coord2D::new(RowIndex::new(0), ColIndex::new(0));
+        let cfa = metadata
+            .cfa(ZERO_POINT)
+            .ok_or("Compressed RAF requires a CFA")?;
+        FujiDecompressor::new(raw.data, dims, &cfa)
+            .map(Format::Compressed)
+            .map_err(|err| err.to_string())
+    }
+
+    /// The samples are bit-packed, in the byte order of the raw data.
+    fn parse_uncompressed(
+        raw: &RawData<'a>,
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+    ) -> Result<Format<'a>, String> {
+        let (width, height) = (dims.row_len().get(), dims.row_count().get());
+        let bits = match raw.bits {
+            Some(bits) => bits,
+            None => u32::try_from(raw.data.len() * 8 / (width * height))
+                .unwrap_or(u32::MAX)
+                .min(T::BITS),
+        };
+        if !(1..=T::BITS).contains(&bits) {
+            return Err(format!("Unsupported bits per sample: {bits}"));
+        }
+        let row_bits = width * usize::try_from(bits).unwrap();
+        if !row_bits.is_multiple_of(8) {
+            return Err(format!("Unsupported RAF row length: {row_bits} bits"));
+        }
+        let bytes_per_row = row_bits / 8;
+        let bit_order = match raw.endianness {
+            Endianness::Little => BitOrder::LSB,
+            Endianness::Big => BitOrder::MSB,
+        };
+        // Rows are read in whole chunks of the bit order.
+        if !bytes_per_row.is_multiple_of(bit_order.mcu_bytelen()) {
+            return Err(format!("Unsupported RAF row pitch: {bytes_per_row}"));
+        }
+        let input = raw
+            .data
+            .get(..bytes_per_row * height)
+            .ok_or("The RAF strip is too small")?;
+        let bytes_per_row = non_zero(bytes_per_row, "row pitch")?;
+        Ok(Format::Uncompressed {
+            input: Array2DRef::new(
+                input,
+                RowLength::new(bytes_per_row),
+                RowPitch::new(bytes_per_row),
+            ),
+            bit_order,
+            bits,
+        })
+    }
+
+    /// The data is compressed if it is too small to hold the samples.
+    fn is_compressed(
+        raw: &RawData<'_>,
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+    ) -> bool {
+        let bits = usize::try_from(raw.bits.unwrap_or(DEFAULT_BITS)).unwrap();
+        raw.data.len() * 8
+            < dims.row_len().get() * dims.row_count().get() * bits
+    }
+
+    #[inline(never)]
+    pub fn new<F>(
+        input: &'a [u8],
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<(Self, ProcurementRequest), String>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let sections = Sections::parse(input)?;
+        let exif = sections
+            .jpeg
+            .get(JPEG_EXIF_OFFSET..)
+            .ok_or("The RAF JPEG is truncated")?;
+        let exif = TiffParser::parse(exif).map_err(tiff_err)?;
+        let directory = Directory::parse(sections.directory)?;
+        let raw = RawData::parse(sections.raw)?;
+        let (width, height) = raw
+            .dims
+            .or_else(|| directory.dims())
+            .ok_or("Unable to find the RAF image size")?;
+        let dims = Dimensions2D::new(
+            RowLength::new(non_zero(width, "width")?),
+            RowCount::new(non_zero(height, "height")?),
+        );
+        let compressed = Self::is_compressed(&raw, dims);
+
+        let make = get_root_string(&exif, TiffTag::MAKE).unwrap_or("");
+        let model = get_root_string(&exif, TiffTag::MODEL).unwrap_or("");
+        let camera = if compressed {
+            cameras.find(make, model, Some("compressed"))
+        } else {
+            None
+        }
+        .or_else(|| cameras.find(make, model, None))
+        .ok_or_else(|| format!("Unknown camera: {make} {model}"))?;
+        check_camera_support_fn(camera.supported)?;
+        let iso_speed = exif
+            .get_entry_recursive(TiffTag::ISO_SPEED_RATINGS)
+            .map(TiffEntry::get_u32s)
+            .and_then(Result::ok)
+            .and_then(|isos| isos.first().copied());
+        let metadata = CameraMetadata::new(camera, make, model, iso_speed);
+
+        let format = if compressed {
+            Self::parse_compressed(&raw, dims, &metadata)?
+        } else {
+            Self::parse_uncompressed(&raw, dims)?
+        };
+        Ok((
+            Self {
+                metadata,
+                format,
+                dims,
+                wb_coeffs: directory.wb_coeffs(),
+                alt_layout: directory.alt_layout(),
+            },
+            NDSliceProcurementRequest::<T>::new(dims).into(),
+        ))
+    }
+
+    /// The `SuperCCD` sensors are rotated by 45 degrees: the size of the
+    /// (square) rotated image, and the column of the first sensor row.
+    fn rotation(&self) -> Option<(usize, usize)> {
+        self.metadata.hint("fuji_rotate")?;
+        let dims = self.dim_cropped().unwrap_or(self.dims);
+        let (width, height) = (dims.row_len().get(), dims.row_count().get());
+        if self.alt_layout {
+            Some((height + width / 2, (width / 2).checked_sub(1)?))
+        } else {
+            Some((width + height / 2, width - 1))
+        }
+    }
+}
+
+#[expect(clippy::missing_trait_methods)]
+impl RawDemuxer for RafDemuxer<'_> {
+    #[inline]
+    fn make(&self) -> &str {
+        self.metadata.make
+    }
+
+    #[inline]
+    fn model(&self) -> &str {
+        self.metadata.model
+    }
+
+    #[inline]
+    fn mode(&self) -> Option<&str> {
+        self.metadata.mode()
+    }
+
+    #[inline]
+    fn canonical_make(&self) -> &str {
+        self.metadata.canonical_make()
+    }
+
+    #[inline]
+    fn canonical_model(&self) -> &str {
+        self.metadata.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_alias(&self) -> &str {
+        self.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_id(&self) -> String {
+        self.metadata.canonical_id()
+    }
+
+    #[inline]
+    fn iso_speed(&self) -> Option<u32> {
+        self.metadata.iso_speed
+    }
+
+    #[inline]
+    fn blacklevel(&self) -> Option<u16> {
+        self.metadata.blacklevel()
+    }
+
+    #[inline]
+    fn whitelevel(&self) -> Option<u16> {
+        self.metadata.whitelevel()
+    }
+
+    #[inline]
+    fn blacklevel_separate(&self) -> Option<Array2DRef<'_, i32>> {
+        None
+    }
+
+    #[inline]
+    fn wb_coeffs(&self) -> Option<[f32; 4]> {
+        self.wb_coeffs
+    }
+
+    #[inline]
+    fn colormatrix(&self) -> Option<Array2DRef<'_, i16>> {
+        self.metadata.colormatrix()
+    }
+
+    #[inline]
+    fn is_cfa(&self) -> bool {
+        self.metadata.is_cfa()
+    }
+
+    #[inline]
+    fn cfa(
+        &self,
+        origin: Coord2D,
+    ) -> Option<OffsetArray2DRef<'_, ColorVariant>> {
+        self.metadata.cfa(origin)
+    }
+
+    #[inline]
+    fn bpp(&self) -> usize {
+        let bpc = match self.datatype() {
+            DataType::U16 => size_of::<u16>(),
+            DataType::F32 => size_of::<f32>(),
+            DataType::U32 => size_of::<u32>(),
+            _ => unreachable!(),
+        };
+        bpc.checked_mul(self.cpp()).unwrap()
+    }
+
+    #[inline]
+    fn cpp(&self) -> usize {
+        1
+    }
+
+    #[inline]
+    fn datatype(&self) -> DataType {
+        DataType::U16
+    }
+
+    #[inline]
+    fn dim_uncropped(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        self.dims
+    }
+
+    #[inline]
+    fn dim_cropped(&self) -> Option<Dimensions2D<core::num::NonZero<usize>>> {
+        self.metadata.dim_cropped(self.dim_uncropped())
+    }
+
+    #[inline]
+    fn crop_offset(&self) -> Option<Coord2D> {
+        self.metadata.crop_offset()
+    }
+
+    #[inline]
+    fn black_areas(&self) -> Option<&[BlackArea]> {
+        self.metadata.black_areas()
+    }
+
+    #[inline]
+    fn fuji_rotation_pos(&self) -> Option<u32> {
+        self.rotation().map(|(_, pos)| pos.try_into().unwrap())
+    }
+
+    /// The rotated image is one row shorter than it is wide.
+    #[inline]
+    #[expect(clippy::float_arithmetic)]
+    fn pixel_aspect_ratio(&self) -> Option<f64> {
+        let (size, _) = self.rotation()?;
+        let size = f64::from(u32::try_from(size).unwrap());
+        Some((size - 1.0) / size)
+    }
+
+    #[inline]
+    fn bad_pixel_positions(&self) -> Vec<Coord2D> {
+        vec![]
+    }
+
+    #[inline(never)]
+    fn decode(
+        &self,
+        output: &mut ImageRefMut<'_>,
+    ) -> Result<(), RawDemuxerError> {
+        let output = output.as_u16()?;
+        if output.dims() != self.dims {
+            return Err(RawDemuxerError::DecoderError(
+                "Output buffer dimensions differ from expected".to_owned(),
+            ));
+        }
+        match &self.format {
+            Format::Compressed(decompressor) => decompressor
+                .decode(output)
+                .map_err(|err| RawDemuxerError::DecoderError(err.to_string())),
+            Format::Uncompressed {
+                input,
+                bit_order,
+                bits,
+            } => {
+                Unpacker::new(*input, *bit_order, *bits, output).unpack();
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,217 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_parsers_tiffparser::tiffparser::TiffTag;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::pack;
+use rawspeed_utils_tiffbuilder::tiffbuilder::Value;
+
+use super::{
+    Raf, compressed_zeros, decode, new_demuxer_err, raw_tiff, u32_bytes,
+};
+use crate::raf_demuxer::{
+    MAX_DIRECTORY_ENTRIES, RAF_WB_LEVELS, SECTIONS_OFFSET,
+};
+
+fn rows(width: usize, height: usize, bits: u32) -> Vec<Vec<u16>> {
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| {
+                    let sample = (row * 977 + col * 313 + 5) % (1 << bits);
+                    u16::try_from(sample).unwrap()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn uncompressed(endianness: Endianness, bits: u32, data: Vec<u8>) -> Raf {
+    Raf::new(raw_tiff(endianness, 8, 2, bits, data).build())
+}
+
+#[test]
+fn uncompressed_little_endian_test() {
+    let rows = rows(8, 2, 16);
+    let data = rows
+        .concat()
+        .iter()
+        .flat_map(|sample| [sample & 0xFF, sample >> 8])
+        .map(|byte| u8::try_from(byte).unwrap())
+        .collect();
+    assert_eq!(
+        decode(&uncompressed(Endianness::Little, 16, data)),
+        Ok(rows)
+    );
+}
+
+#[test]
+fn uncompressed_big_endian_test() {
+    let rows = rows(8, 2, 12);
+    let data = pack(&rows.concat(), 12, BitOrder::MSB);
+    assert_eq!(decode(&uncompressed(Endianness::Big, 12, data)), Ok(rows));
+}
+
+#[test]
+fn uncompressed_without_tiff_test() {
+    // The bits per sample follow from the size of the data.
+    for bits in [12, 16] {
+        let rows = rows(8, 2, bits);
+        let raf =
+            Raf::without_tiff(8, 2, pack(&rows.concat(), bits, BitOrder::MSB));
+        assert_eq!(decode(&raf), Ok(rows));
+    }
+}
+
+#[test]
+fn compressed_test() {
+    let with_tiff = Raf::new(
+        raw_tiff(Endianness::Little, 96, 6, 12, compressed_zeros(96, 6))
+            .build(),
+    );
+    assert_eq!(decode(&with_tiff), Ok(vec![vec![0; 96]; 6]));
+    let without_tiff = Raf::without_tiff(96, 6, compressed_zeros(96, 6));
+    assert_eq!(decode(&without_tiff), Ok(vec![vec![0; 96]; 6]));
+}
+
+#[test]
+fn invalid_compressed_test() {
+    let mut truncated = compressed_zeros(96, 6);
+    truncated.truncate(truncated.len() - 1);
+    assert_eq!(
+        new_demuxer_err(&Raf::without_tiff(96, 6, truncated).build()),
+        "FujiError(UnexpectedEndOfInput)"
+    );
+    let mut data = compressed_zeros(96, 6);
+    let len = data.len();
+    data.get_mut(32..len).unwrap().fill(0);
+    assert_eq!(
+        decode(&Raf::without_tiff(96, 6, data)),
+        Err("RawDemuxerError(DecoderError(FujiError(TruncatedData)))"
+            .to_owned())
+    );
+    // The compressed image must match the dimensions of the raw data.
+    assert_eq!(
+        new_demuxer_err(
+            &Raf::without_tiff(102, 6, compressed_zeros(96, 6)).build()
+        ),
+        "FujiError(DimensionsMismatch)"
+    );
+}
+
+#[test]
+fn compressed_requires_cfa_test() {
+    let mut raf = Raf::without_tiff(96, 6, compressed_zeros(96, 6));
+    raf.model = "X-E0";
+    assert_eq!(
+        new_demuxer_err(&raf.build()),
+        "Compressed RAF requires a CFA"
+    );
+}
+
+#[test]
+fn not_a_raf_test() {
+    let mut input = Raf::new(vec![]).build();
+    *input.first_mut().unwrap() = b'G';
+    assert_eq!(new_demuxer_err(&input), "Not a RAF file");
+}
+
+#[test]
+fn truncated_header_test() {
+    let mut input = Raf::new(vec![]).build();
+    input.truncate(SECTIONS_OFFSET + 4);
+    assert_eq!(new_demuxer_err(&input), "The RAF header is truncated");
+}
+
+#[test]
+fn sections_out_of_bounds_test() {
+    for (index, what) in ["JPEG", "metadata directory", "raw data"]
+        .into_iter()
+        .enumerate()
+    {
+        let mut input = Raf::new(vec![0; 4]).build();
+        let len_pos = SECTIONS_OFFSET + index * 8 + 4;
+        input
+            .get_mut(len_pos..len_pos + 4)
+            .unwrap()
+            .copy_from_slice(&u32_bytes(0x10000));
+        assert_eq!(
+            new_demuxer_err(&input),
+            format!("The RAF {what} is out of bounds")
+        );
+    }
+}
+
+#[test]
+fn invalid_directory_test() {
+    let mut raf = Raf::without_tiff(8, 2, vec![0; 32]);
+    raf.directory.push((RAF_WB_LEVELS, vec![0; 8]));
+    let input = raf.build();
+    let mut truncated = input.clone();
+    // The last entry claims more data than there is.
+    let len_pos = input.len() - 32 - 8 - 2;
+    *truncated.get_mut(len_pos).unwrap() = 0xFF;
+    assert_eq!(
+        new_demuxer_err(&truncated),
+        "The RAF metadata directory is truncated"
+    );
+    let mut too_many = input;
+    let count_pos = too_many.len() - 32 - 8 - 4 - 8 - 4;
+    too_many
+        .get_mut(count_pos..count_pos + 4)
+        .unwrap()
+        .copy_from_slice(&u32_bytes(MAX_DIRECTORY_ENTRIES + 1));
+    assert_eq!(
+        new_demuxer_err(&too_many),
+        "Too many entries in the RAF metadata directory: 256"
+    );
+}
+
+#[test]
+fn no_image_size_test() {
+    assert_eq!(
+        new_demuxer_err(&Raf::new(vec![0; 32]).build()),
+        "Unable to find the RAF image size"
+    );
+}
+
+#[test]
+fn invalid_strip_test() {
+    let mut no_count = raw_tiff(Endianness::Little, 8, 2, 16, vec![0; 32]);
+    no_count.remove(TiffTag::FUJI_STRIP_BYTE_COUNTS);
+    assert_eq!(
+        new_demuxer_err(&Raf::new(no_count.build()).build()),
+        "The RAF strip byte count is missing"
+    );
+    let mut too_long = raw_tiff(Endianness::Little, 8, 2, 16, vec![0; 32]);
+    too_long.set(TiffTag::FUJI_STRIP_BYTE_COUNTS, &Value::Long(vec![33]));
+    assert_eq!(
+        new_demuxer_err(&Raf::new(too_long.build()).build()),
+        "The RAF strip data is truncated"
+    );
+}
+
+#[test]
+fn unsupported_bits_per_sample_test() {
+    assert_eq!(
+        new_demuxer_err(
+            &uncompressed(Endianness::Little, 17, vec![0; 34]).build()
+        ),
+        "Unsupported bits per sample: 17"
+    );
+}
+
+#[test]
+fn unsupported_row_test() {
+    let odd_width =
+        Raf::new(raw_tiff(Endianness::Big, 3, 2, 12, vec![0; 9]).build());
+    assert_eq!(
+        new_demuxer_err(&odd_width.build()),
+        "Unsupported RAF row length: 36 bits"
+    );
+}
+
+#[test]
+fn odd_row_pitch_test() {
+    let rows = rows(8, 2, 14);
+    let data = pack(&rows.concat(), 14, BitOrder::MSB);
+    assert_eq!(decode(&uncompressed(Endianness::Big, 14, data)), Ok(rows));
+}
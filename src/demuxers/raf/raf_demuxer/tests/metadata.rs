@@ -0,0 +1,127 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+
+use super::{
+    Raf, RafDemuxer, compressed_zeros, entry, new_demuxer_err, parse_cameras,
+    raw_tiff,
+};
+use crate::raf_demuxer::{RAF_LAYOUT, RAF_WB_LEVELS};
+
+fn uncompressed(model: &'static str, width: u32, height: u32) -> Raf {
+    let len = usize::try_from(width * height * 2).unwrap();
+    let raw = raw_tiff(Endianness::Little, width, height, 16, vec![0; len]);
+    let mut raf = Raf::new(raw.build());
+    raf.model = model;
+    raf
+}
+
+macro_rules! with_demuxer {
+    ($raf:expr, |$demuxer:ident| $body:block) => {{
+        let input = $raf.build();
+        let cameras = parse_cameras();
+        let ($demuxer, _) = RafDemuxer::new(
+            &input,
+            &cameras,
+            DecodeableCamera::new_unless_unsupported,
+        )
+        .unwrap();
+        $body
+    }};
+}
+
+fn assert_wb_coeffs(demuxer: &RafDemuxer<'_>, expected: [f32; 3]) {
+    let [red, green, blue, fourth] = demuxer.wb_coeffs().unwrap();
+    assert_eq!(
+        [red, green, blue].map(f32::to_bits),
+        expected.map(f32::to_bits)
+    );
+    assert!(fourth.is_nan());
+}
+
+#[test]
+fn camera_from_cameras_xml_test() {
+    with_demuxer!(uncompressed("X-T0", 8, 2), |demuxer| {
+        assert_eq!(demuxer.make(), "FUJIFILM");
+        assert_eq!(demuxer.model(), "X-T0");
+        assert_eq!(demuxer.mode(), None);
+        assert_eq!(demuxer.canonical_make(), "Fujifilm");
+        assert_eq!(demuxer.canonical_id(), "Fujifilm X-T0");
+        assert_eq!(demuxer.iso_speed(), Some(200));
+        assert_eq!(demuxer.blacklevel(), Some(256));
+        assert_eq!(demuxer.whitelevel(), Some(16383));
+        assert!(demuxer.is_cfa());
+    });
+}
+
+#[test]
+fn compressed_mode_test() {
+    with_demuxer!(
+        Raf::without_tiff(96, 6, compressed_zeros(96, 6)),
+        |demuxer| {
+            assert_eq!(demuxer.mode(), Some("compressed"));
+            assert_eq!(demuxer.blacklevel(), Some(64));
+            assert_eq!(demuxer.whitelevel(), Some(4095));
+        }
+    );
+}
+
+#[test]
+fn unknown_camera_test() {
+    assert_eq!(
+        new_demuxer_err(&uncompressed("X-T1", 8, 2).build()),
+        "Unknown camera: FUJIFILM X-T1"
+    );
+    assert_eq!(
+        new_demuxer_err(&uncompressed("Unsupported", 8, 2).build()),
+        "This camera is not supported (explicit)"
+    );
+}
+
+#[test]
+fn wb_coeffs_test() {
+    let mut raf = uncompressed("X-T0", 8, 2);
+    raf.directory
+        .push(entry(RAF_WB_LEVELS, &[302, 550, 302, 720]));
+    with_demuxer!(raf, |demuxer| {
+        assert_wb_coeffs(&demuxer, [550.0, 302.0, 720.0]);
+    });
+    with_demuxer!(uncompressed("X-T0", 8, 2), |demuxer| {
+        assert!(demuxer.wb_coeffs().is_none());
+    });
+    // Too short to hold all the levels.
+    let mut short = uncompressed("X-T0", 8, 2);
+    short.directory.push(entry(RAF_WB_LEVELS, &[302, 550, 302]));
+    with_demuxer!(short, |demuxer| {
+        assert!(demuxer.wb_coeffs().is_none());
+    });
+}
+
+#[test]
+fn rotation_test() {
+    // The cropped image is 6 samples wide and 4 high.
+    let mut horizontal = uncompressed("FinePix S0Pro", 12, 4);
+    horizontal.directory.push(entry(RAF_LAYOUT, &[0x8000]));
+    with_demuxer!(horizontal, |demuxer| {
+        assert_eq!(demuxer.fuji_rotation_pos(), Some(5));
+        let ratio = demuxer.pixel_aspect_ratio().unwrap();
+        assert_eq!(format!("{ratio:.6}"), "0.875000");
+    });
+    let mut vertical = uncompressed("FinePix S0Pro", 12, 4);
+    vertical.directory.push(entry(RAF_LAYOUT, &[0]));
+    with_demuxer!(vertical, |demuxer| {
+        assert_eq!(demuxer.fuji_rotation_pos(), Some(2));
+        let ratio = demuxer.pixel_aspect_ratio().unwrap();
+        assert_eq!(format!("{ratio:.6}"), "0.857143");
+    });
+}
+
+#[test]
+fn no_rotation_test() {
+    let mut raf = uncompressed("X-T0", 12, 4);
+    raf.directory.push(entry(RAF_LAYOUT, &[0x8000]));
+    with_demuxer!(raf, |demuxer| {
+        assert!(demuxer.fuji_rotation_pos().is_none());
+        assert!(demuxer.pixel_aspect_ratio().is_none());
+    });
+}
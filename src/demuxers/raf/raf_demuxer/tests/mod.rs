@@ -0,0 +1,264 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::Cameras;
+use rawspeed_metadata_xmlparser::xmlparser;
+use rawspeed_parsers_tiffparser::tiffparser::TiffTag;
+use rawspeed_std::coord_common::RowIndex;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+use rawspeed_utils_tiffbuilder::tiffbuilder::{TiffBuilder, Value, tag};
+
+use super::{RAF_IMAGE_SIZE, RafDemuxer, SECTIONS_OFFSET};
+
+const CAMERAS: &str = "
+    <Cameras>
+        <Camera make=\"FUJIFILM\" model=\"X-T0\">
+            <ID make=\"Fujifilm\" model=\"X-T0\">Fujifilm X-T0</ID>
+            <CFA width=\"2\" height=\"2\">
+                <Color x=\"0\" y=\"0\">RED</Color>
+                <Color x=\"1\" y=\"0\">GREEN</Color>
+                <Color x=\"0\" y=\"1\">GREEN</Color>
+                <Color x=\"1\" y=\"1\">BLUE</Color>
+            </CFA>
+            <Sensor black=\"256\" white=\"16383\"/>
+        </Camera>
+        <Camera make=\"FUJIFILM\" model=\"X-T0\" mode=\"compressed\">
+            <ID make=\"Fujifilm\" model=\"X-T0\">Fujifilm X-T0</ID>
+            <CFA width=\"2\" height=\"2\">
+                <Color x=\"0\" y=\"0\">RED</Color>
+                <Color x=\"1\" y=\"0\">GREEN</Color>
+                <Color x=\"0\" y=\"1\">GREEN</Color>
+                <Color x=\"1\" y=\"1\">BLUE</Color>
+            </CFA>
+            <Sensor black=\"64\" white=\"4095\"/>
+        </Camera>
+        <Camera make=\"FUJIFILM\" model=\"X-E0\" mode=\"compressed\">
+            <ID make=\"Fujifilm\" model=\"X-E0\">Fujifilm X-E0</ID>
+        </Camera>
+        <Camera make=\"FUJIFILM\" model=\"FinePix S0Pro\">
+            <ID make=\"Fujifilm\" model=\"FinePix S0Pro\">Fujifilm S0Pro</ID>
+            <CFA width=\"2\" height=\"2\">
+                <Color x=\"0\" y=\"0\">GREEN</Color>
+                <Color x=\"1\" y=\"0\">RED</Color>
+                <Color x=\"0\" y=\"1\">BLUE</Color>
+                <Color x=\"1\" y=\"1\">GREEN</Color>
+            </CFA>
+            <Crop x=\"2\" y=\"0\" width=\"-4\" height=\"0\"/>
+            <Sensor black=\"0\" white=\"16383\"/>
+            <Hints>
+                <Hint name=\"fuji_rotate\" value=\"true\"/>
+            </Hints>
+        </Camera>
+        <Camera make=\"FUJIFILM\" model=\"Unsupported\" supported=\"no\">
+        </Camera>
+    </Cameras>";
+
+fn u16_bytes(val: usize) -> [u8; 2] {
+    [val >> 8, val & 0xFF].map(|byte| u8::try_from(byte).unwrap())
+}
+
+fn u32_bytes(val: usize) -> [u8; 4] {
+    [val >> 24, (val >> 16) & 0xFF, (val >> 8) & 0xFF, val & 0xFF]
+        .map(|byte| u8::try_from(byte).unwrap())
+}
+
+/// Codes a zero difference with the given (sum, count) statistics.
+fn put_zero(writer: &mut BitStreamBuilder, (sum, count): &mut (u32, u32)) {
+    let mut bits = 0;
+    while bits < 15 && (*count << bits) < *sum {
+        bits += 1;
+    }
+    writer.put(1 << bits, bits + 1);
+    if *count == 64 {
+        *sum >>= 1;
+        *count >>= 1;
+    }
+    *count += 1;
+}
+
+/// A 12-bit compressed Bayer image, of a single strip, whose samples are
+/// all zero: all the gradients are zero too, so that each group of passes
+/// only uses its first even and odd statistics.
+fn compressed_zeros(width: usize, height: usize) -> Vec<u8> {
+    const LINE_WIDTH: usize = 384;
+    let mut writer = BitStreamBuilder::new();
+    let mut stats = [[(64, 1); 3]; 2];
+    for _ in 0..height / 6 {
+        for grads in [0, 1, 2, 0, 1, 2] {
+            let (mut even, mut odd) = (0, 1);
+            while even < LINE_WIDTH || odd < LINE_WIDTH {
+                if even < LINE_WIDTH {
+                    for _ in 0..2 {
+                        let even_stats = stats.get_mut(0).unwrap();
+                        put_zero(
+                            &mut writer,
+                            even_stats.get_mut(grads).unwrap(),
+                        );
+                    }
+                    even += 2;
+                }
+                if even > 8 {
+                    for _ in 0..2 {
+                        let odd_stats = stats.get_mut(1).unwrap();
+                        put_zero(
+                            &mut writer,
+                            odd_stats.get_mut(grads).unwrap(),
+                        );
+                    }
+                    odd += 2;
+                }
+            }
+        }
+    }
+    let strip = writer.finish(BitOrder::MSB);
+    let mut out = vec![0x49, 0x53, 1, 0, 12];
+    out.extend(u16_bytes(height));
+    out.extend(u16_bytes(0x300));
+    out.extend(u16_bytes(width));
+    out.extend(u16_bytes(0x300));
+    out.push(1);
+    out.extend(u16_bytes(height / 6));
+    out.extend(u32_bytes(strip.len()));
+    out.resize(out.len().next_multiple_of(16), 0);
+    out.extend(strip);
+    out
+}
+
+/// The raw data of newer cameras: a TIFF whose Fuji raw IFD describes
+/// the strip.
+fn raw_tiff(
+    endianness: Endianness,
+    width: u32,
+    height: u32,
+    bits: u32,
+    data: Vec<u8>,
+) -> TiffBuilder {
+    let mut builder = TiffBuilder::new(endianness);
+    builder
+        .ifd(0)
+        .push(tag(TiffTag::FUJI_RAW_IFD, Value::IFDOffset(1)));
+    builder.ifd(1).extend([
+        tag(TiffTag::FUJI_RAW_IMAGE_FULL_WIDTH, Value::Long(vec![width])),
+        tag(
+            TiffTag::FUJI_RAW_IMAGE_FULL_HEIGHT,
+            Value::Long(vec![height]),
+        ),
+        tag(TiffTag::FUJI_BITS_PER_SAMPLE, Value::Long(vec![bits])),
+        tag(TiffTag::FUJI_STRIP_OFFSETS, Value::BlobOffsets(vec![0])),
+        tag(TiffTag::FUJI_STRIP_BYTE_COUNTS, Value::BlobSizes(vec![0])),
+    ]);
+    builder.blobs = vec![data];
+    builder
+}
+
+/// A metadata directory entry with the given (big-endian) values.
+fn entry(tag: u16, values: &[u16]) -> (u16, Vec<u8>) {
+    (
+        tag,
+        values
+            .iter()
+            .flat_map(|val| u16_bytes(usize::from(*val)))
+            .collect(),
+    )
+}
+
+#[derive(Debug)]
+struct Raf {
+    model: &'static str,
+    directory: Vec<(u16, Vec<u8>)>,
+    raw: Vec<u8>,
+}
+
+impl Raf {
+    fn new(raw: Vec<u8>) -> Self {
+        Self {
+            model: "X-T0",
+            directory: vec![],
+            raw,
+        }
+    }
+
+    /// The raw data of older cameras, whose size is only stored in the
+    /// metadata directory.
+    fn without_tiff(width: u16, height: u16, raw: Vec<u8>) -> Self {
+        let mut raf = Self::new(raw);
+        raf.directory.push(entry(RAF_IMAGE_SIZE, &[height, width]));
+        raf
+    }
+
+    /// The embedded JPEG, reduced to the APP1 segment with the EXIF TIFF.
+    fn jpeg(&self) -> Vec<u8> {
+        let mut builder = TiffBuilder::new(Endianness::Big);
+        builder.ifd(0).extend([
+            tag(TiffTag::MAKE, Value::Ascii("FUJIFILM")),
+            tag(TiffTag::MODEL, Value::Ascii(self.model)),
+            tag(TiffTag::EXIF_IFD_POINTER, Value::IFDOffset(1)),
+        ]);
+        builder
+            .ifd(1)
+            .push(tag(TiffTag::ISO_SPEED_RATINGS, Value::Short(vec![200])));
+        let exif = builder.build();
+        let mut out = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        out.extend(u16_bytes(exif.len() + 8));
+        out.extend(b"Exif\0\0");
+        out.extend(exif);
+        out
+    }
+
+    fn directory(&self) -> Vec<u8> {
+        let mut out = u32_bytes(self.directory.len()).to_vec();
+        for (tag, data) in &self.directory {
+            out.extend(u16_bytes(usize::from(*tag)));
+            out.extend(u16_bytes(data.len()));
+            out.extend(data);
+        }
+        out
+    }
+
+    fn build(&self) -> Vec<u8> {
+        let sections = [self.jpeg(), self.directory(), self.raw.clone()];
+        let mut out = b"FUJIFILMCCD-RAW 0201FF000000".to_vec();
+        out.resize(SECTIONS_OFFSET, 0);
+        let mut offset = SECTIONS_OFFSET + sections.len() * 8;
+        for section in &sections {
+            out.extend(u32_bytes(offset));
+            out.extend(u32_bytes(section.len()));
+            offset += section.len();
+        }
+        out.extend(sections.concat());
+        out
+    }
+}
+
+fn parse_cameras() -> Cameras<'static> {
+    xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap()
+}
+
+fn new_demuxer_err(input: &[u8]) -> String {
+    let cameras = parse_cameras();
+    RafDemuxer::new(input, &cameras, DecodeableCamera::new_unless_unsupported)
+        .unwrap_err()
+}
+
+fn decode(raf: &Raf) -> Result<Vec<Vec<u16>>, String> {
+    let input = raf.build();
+    let cameras = parse_cameras();
+    let (demuxer, request) = RafDemuxer::new(
+        &input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )?;
+    let mut output_buf = request.fulfill().unwrap();
+    let mut image = output_buf.get_mut();
+    demuxer.decode(&mut image).map_err(|err| err.to_string())?;
+    let output = image.as_u16().unwrap();
+    Ok((0..output.num_rows().get())
+        .map(|row| output.get_row(RowIndex::new(row)).unwrap().to_vec())
+        .collect())
+}
+
+#[cfg(test)]
+mod data;
+#[cfg(test)]
+mod metadata;
@@ -1,3 +1,6 @@
+use rawspeed_memory_nd_slice_procurement::ndsliceprocurement::{
+    NDSliceProcurementRequest, NDSliceProcurementRequestError, OwnedNDSlice,
+};
 use rawspeed_metadata_camerasxml_parser::camerasxml_parser::blackareas::BlackArea;
 use rawspeed_metadata_colorfilterarray::colorfilterarray::{
     ColorVariant,
@@ -27,9 +30,183 @@ impl core::fmt::Display for RawDemuxerError {
 }
 
 #[non_exhaustive]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DataType {
     U16,
+    F32,
+    U32,
+}
+
+/// The output buffer a demuxer asks for, in the element type it decodes to.
+#[non_exhaustive]
+#[derive(Debug)]
+#[must_use]
+pub enum ProcurementRequest {
+    U16(NDSliceProcurementRequest<u16>),
+    U32(NDSliceProcurementRequest<u32>),
+    F32(NDSliceProcurementRequest<f32>),
+}
+
+impl ProcurementRequest {
+    /// The dimensions of the image, in pixels.
+    #[inline]
+    pub const fn dims(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        match self {
+            ProcurementRequest::U16(request) => request.dims(),
+            ProcurementRequest::U32(request) => request.dims(),
+            ProcurementRequest::F32(request) => request.dims(),
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn cpp(&self) -> core::num::NonZero<usize> {
+        match self {
+            ProcurementRequest::U16(request) => request.cpp(),
+            ProcurementRequest::U32(request) => request.cpp(),
+            ProcurementRequest::F32(request) => request.cpp(),
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn datatype(&self) -> DataType {
+        match self {
+            ProcurementRequest::U16(_) => DataType::U16,
+            ProcurementRequest::U32(_) => DataType::U32,
+            ProcurementRequest::F32(_) => DataType::F32,
+        }
+    }
+
+    #[inline]
+    pub fn fulfill(self) -> Result<OwnedImage, NDSliceProcurementRequestError> {
+        Ok(match self {
+            ProcurementRequest::U16(request) => {
+                OwnedImage::U16(request.fulfill()?)
+            }
+            ProcurementRequest::U32(request) => {
+                OwnedImage::U32(request.fulfill()?)
+            }
+            ProcurementRequest::F32(request) => {
+                OwnedImage::F32(request.fulfill()?)
+            }
+        })
+    }
+}
+
+impl From<NDSliceProcurementRequest<u16>> for ProcurementRequest {
+    #[inline]
+    fn from(request: NDSliceProcurementRequest<u16>) -> Self {
+        ProcurementRequest::U16(request)
+    }
+}
+
+impl From<NDSliceProcurementRequest<u32>> for ProcurementRequest {
+    #[inline]
+    fn from(request: NDSliceProcurementRequest<u32>) -> Self {
+        ProcurementRequest::U32(request)
+    }
+}
+
+impl From<NDSliceProcurementRequest<f32>> for ProcurementRequest {
+    #[inline]
+    fn from(request: NDSliceProcurementRequest<f32>) -> Self {
+        ProcurementRequest::F32(request)
+    }
+}
+
+/// A fulfilled [`ProcurementRequest`].
+#[non_exhaustive]
+#[derive(Debug, PartialEq)]
+#[must_use]
+pub enum OwnedImage {
+    U16(OwnedNDSlice<u16>),
+    U32(OwnedNDSlice<u32>),
+    F32(OwnedNDSlice<f32>),
+}
+
+impl OwnedImage {
+    #[inline]
+    pub const fn get_mut(&mut self) -> ImageRefMut<'_> {
+        match self {
+            OwnedImage::U16(image) => ImageRefMut::U16(image.get_mut()),
+            OwnedImage::U32(image) => ImageRefMut::U32(image.get_mut()),
+            OwnedImage::F32(image) => ImageRefMut::F32(image.get_mut()),
+        }
+    }
+}
+
+/// The output buffer [`RawDemuxer::decode`] writes to.
+#[non_exhaustive]
+#[derive(Debug)]
+#[must_use]
+pub enum ImageRefMut<'a> {
+    U16(Array2DRefMut<'a, u16>),
+    U32(Array2DRefMut<'a, u32>),
+    F32(Array2DRefMut<'a, f32>),
+}
+
+fn element_type_mismatch() -> RawDemuxerError {
+    RawDemuxerError::DecoderError(
+        "Output buffer element type differs from expected".to_owned(),
+    )
+}
+
+impl<'a> ImageRefMut<'a> {
+    #[inline]
+    pub fn dims(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        match self {
+            ImageRefMut::U16(image) => image.dims(),
+            ImageRefMut::U32(image) => image.dims(),
+            ImageRefMut::F32(image) => image.dims(),
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn datatype(&self) -> DataType {
+        match self {
+            ImageRefMut::U16(_) => DataType::U16,
+            ImageRefMut::U32(_) => DataType::U32,
+            ImageRefMut::F32(_) => DataType::F32,
+        }
+    }
+
+    #[inline]
+    pub fn as_u16(
+        &mut self,
+    ) -> Result<&mut Array2DRefMut<'a, u16>, RawDemuxerError> {
+        match self {
+            ImageRefMut::U16(image) => Ok(image),
+            ImageRefMut::U32(_) | ImageRefMut::F32(_) => {
+                Err(element_type_mismatch())
+            }
+        }
+    }
+
+    #[inline]
+    pub fn as_u32(
+        &mut self,
+    ) -> Result<&mut Array2DRefMut<'a, u32>, RawDemuxerError> {
+        match self {
+            ImageRefMut::U32(image) => Ok(image),
+            ImageRefMut::U16(_) | ImageRefMut::F32(_) => {
+                Err(element_type_mismatch())
+            }
+        }
+    }
+
+    #[inline]
+    pub fn as_f32(
+        &mut self,
+    ) -> Result<&mut Array2DRefMut<'a, f32>, RawDemuxerError> {
+        match self {
+            ImageRefMut::F32(image) => Ok(image),
+            ImageRefMut::U16(_) | ImageRefMut::U32(_) => {
+                Err(element_type_mismatch())
+            }
+        }
+    }
 }
 
 pub trait RawDemuxer {
@@ -43,8 +220,8 @@ pub trait RawDemuxer {
     fn iso_speed(&self) -> Option<u32>;
     fn blacklevel(&self) -> Option<u16>;
     fn whitelevel(&self) -> Option<u16>;
-    fn blacklevel_separate(&self) -> Option<()>;
-    fn wb_coeffs(&self) -> Option<()>;
+    fn blacklevel_separate(&self) -> Option<Array2DRef<'_, i32>>;
+    fn wb_coeffs(&self) -> Option<[f32; 4]>;
     fn colormatrix(&self) -> Option<Array2DRef<'_, i16>>;
     fn is_cfa(&self) -> bool;
     fn cfa(
@@ -61,8 +238,12 @@ pub trait RawDemuxer {
             .try_into()
     }
     fn bpp(&self) -> usize;
+    /// The number of components of each pixel, which are interleaved in
+    /// the rows of the output.
     fn cpp(&self) -> usize;
     fn datatype(&self) -> DataType;
+    /// The dimensions of the image in pixels, rather than in output
+    /// elements. So are the crop, the CFA and the black areas.
     fn dim_uncropped(&self) -> Dimensions2D<core::num::NonZero<usize>>;
     fn dim_cropped(&self) -> Option<Dimensions2D<core::num::NonZero<usize>>>;
     fn crop_offset(&self) -> Option<Coord2D>;
@@ -71,8 +252,10 @@ pub trait RawDemuxer {
     fn pixel_aspect_ratio(&self) -> Option<f64>;
     fn bad_pixel_positions(&self) -> Vec<Coord2D>;
 
+    /// Decodes the image into a fulfillment of the [`ProcurementRequest`]
+    /// the demuxer was created with.
     fn decode(
         &self,
-        output: &mut Array2DRefMut<'_, u16>,
+        output: &mut ImageRefMut<'_>,
     ) -> Result<(), RawDemuxerError>;
 }
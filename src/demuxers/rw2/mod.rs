@@ -0,0 +1 @@
+pub mod rw2_demuxer;
@@ -0,0 +1,476 @@
+use rawspeed_codecs_panasonic::panasonic::{
+    PanasonicV4Decompressor, PanasonicV5Decompressor, SECTION_SPLIT_OFFSET,
+};
+use rawspeed_demuxers_common::{
+    camera_metadata::CameraMetadata,
+    tiff_utils::{
+        OwnedArray2D, get_root_string, get_u32, get_usize, non_zero, tiff_err,
+    },
+};
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::{
+    DataType, ImageRefMut, ProcurementRequest, RawDemuxer, RawDemuxerError,
+};
+use rawspeed_memory_nd_slice_procurement::ndsliceprocurement::NDSliceProcurementRequest;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::{
+    Cameras, Supported, blackareas::BlackArea,
+};
+use rawspeed_metadata_colorfilterarray::colorfilterarray::ColorVariant;
+use rawspeed_parsers_tiffparser::tiffparser::{
+    TiffEntry, TiffIFD, TiffRootIFD, TiffTag,
+};
+use rawspeed_std::coord_common::{
+    ColIndex, Coord2D, Dimensions2D, RowCount, RowIndex, RowLength,
+};
+use rawspeed_std_ndslice::{
+    array2dref::Array2DRef, offsetarray2dref::OffsetArray2DRef,
+};
+
+/// The sample size, unless specified otherwise.
+const DEFAULT_BITS_PER_SAMPLE: u32 = 12;
+
+/// Up to this version, the stored black levels are too low.
+const LAST_OFFSET_BLACK_LEVELS_VERSION: u16 = 4;
+const BLACK_LEVEL_OFFSET: i32 = 15;
+
+/// The camera modes, which are named after their aspect ratio.
+const MODES: [(&str, u64, u64); 4] =
+    [("16:9", 16, 9), ("3:2", 3, 2), ("4:3", 4, 3), ("1:1", 1, 1)];
+
+type T = u16;
+
+/// The offset and the size of the sensor area.
+type Crop = (Coord2D, Dimensions2D<core::num::NonZero<usize>>);
+
+/// The mode whose aspect ratio is the closest to that of the image.
+fn guess_mode(dims: Dimensions2D<core::num::NonZero<usize>>) -> &'static str {
+    let width = u64::try_from(dims.row_len().get()).unwrap();
+    let height = u64::try_from(dims.row_count().get()).unwrap();
+    // The distance to `num / den`, scaled by `height * den`.
+    let distance = |num: u64, den: u64| (width * den).abs_diff(num * height);
+    MODES
+        .iter()
+        .min_by(|(_, num_a, den_a), (_, num_b, den_b)| {
+            (distance(*num_a, *den_a) * den_b)
+                .cmp(&(distance(*num_b, *den_b) * den_a))
+        })
+        .map(|(mode, _, _)| *mode)
+        .unwrap()
+}
+
+fn get_u16(ifd: &TiffIFD<'_>, tag: TiffTag) -> Result<Option<u16>, String> {
+    ifd.get_entry(tag)
+        .map(|entry| entry.get_u16(0))
+        .transpose()
+        .map_err(tiff_err)
+}
+
+/// Cameras that store the sensor area report it via its borders.
+fn parse_crop(
+    ifd: &TiffIFD<'_>,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+) -> Result<Option<Crop>, String> {
+    let (Some(top), Some(left), Some(bottom), Some(right)) = (
+        get_usize(ifd, TiffTag::PANASONIC_SENSOR_TOP_BORDER)?,
+        get_usize(ifd, TiffTag::PANASONIC_SENSOR_LEFT_BORDER)?,
+        get_usize(ifd, TiffTag::PANASONIC_SENSOR_BOTTOM_BORDER)?,
+        get_usize(ifd, TiffTag::PANASONIC_SENSOR_RIGHT_BORDER)?,
+    ) else {
+        return Ok(None);
+    };
+    if right > dims.row_len().get() || bottom > dims.row_count().get() {
+        return Err("The RW2 sensor borders are out of bounds".to_owned());
+    }
+    let (Some(width), Some(height)) = (
+        right.checked_sub(left).and_then(core::num::NonZero::new),
+        bottom.checked_sub(top).and_then(core::num::NonZero::new),
+    ) else {
+        return Err("The RW2 sensor borders are empty".to_owned());
+    };
+    Ok(Some((
+        Coord2D::new(RowIndex::new(top), ColIndex::new(left)),
+        Dimensions2D::new(RowLength::new(width), RowCount::new(height)),
+    )))
+}
+
+/// The white balance levels are stored per color.
+fn parse_wb_coeffs(ifd: &TiffIFD<'_>) -> Result<Option<[f32; 4]>, String> {
+    let (Some(red), Some(green), Some(blue)) = (
+        get_u16(ifd, TiffTag::PANASONIC_WB_RED_LEVEL)?,
+        get_u16(ifd, TiffTag::PANASONIC_WB_GREEN_LEVEL)?,
+        get_u16(ifd, TiffTag::PANASONIC_WB_BLUE_LEVEL)?,
+    ) else {
+        return Ok(None);
+    };
+    Ok(Some([red.into(), green.into(), blue.into(), f32::NAN]))
+}
+
+/// The black levels are stored per color, and are laid out to match the
+/// CFA of the camera.
+#[expect(clippy::wildcard_enum_match_arm)]
+fn parse_black_levels(
+    ifd: &TiffIFD<'_>,
+    cfa: OffsetArray2DRef<'_, ColorVariant>,
+    offset: i32,
+) -> Result<Option<OwnedArray2D<i32>>, String> {
+    let (Some(red), Some(green), Some(blue)) = (
+        get_u16(ifd, TiffTag::PANASONIC_BLACK_LEVEL_RED)?,
+        get_u16(ifd, TiffTag::PANASONIC_BLACK_LEVEL_GREEN)?,
+        get_u16(ifd, TiffTag::PANASONIC_BLACK_LEVEL_BLUE)?,
+    ) else {
+        return Ok(None);
+    };
+    if cfa.row_length().get() < 2 || cfa.num_rows().get() < 2 {
+        return Ok(None);
+    }
+    let mut levels = Vec::with_capacity(4);
+    for index in 0..4 {
+        let position =
+            Coord2D::new(RowIndex::new(index / 2), ColIndex::new(index % 2));
+        let level = match cfa[position] {
+            ColorVariant::Red => red,
+            ColorVariant::Green => green,
+            ColorVariant::Blue => blue,
+            color => {
+                return Err(format!("Unexpected CFA color: {color:?}"));
+            }
+        };
+        levels.push(i32::from(level) + offset);
+    }
+    OwnedArray2D::new(levels, 2).map(Some)
+}
+
+#[derive(Debug)]
+enum Format<'a> {
+    V4(PanasonicV4Decompressor<'a>),
+    V5(PanasonicV5Decompressor<'a>),
+}
+
+/// Panasonic's RW2, and Leica's RWL, which is the same container: the
+/// two only differ by the make `cameras.xml` lists the camera under.
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct Rw2Demuxer<'a> {
+    metadata: CameraMetadata<'a>,
+    format: Format<'a>,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    crop: Option<Crop>,
+    black_levels: Option<OwnedArray2D<i32>>,
+    wb_coeffs: Option<[f32; 4]>,
+}
+
+impl<'a> Rw2Demuxer<'a> {
+    fn parse_dims(
+        ifd: &TiffIFD<'_>,
+    ) -> Result<Dimensions2D<core::num::NonZero<usize>>, String> {
+        let width =
+            get_usize(ifd, TiffTag::PANASONIC_SENSOR_WIDTH)?.unwrap_or(0);
+        let height =
+            get_usize(ifd, TiffTag::PANASONIC_SENSOR_HEIGHT)?.unwrap_or(0);
+        Ok(Dimensions2D::new(
+            RowLength::new(non_zero(width, "width")?),
+            RowCount::new(non_zero(height, "height")?),
+        ))
+    }
+
+    /// The image data extends up to the end of the file.
+    fn get_data(
+        root: &TiffRootIFD<'a>,
+        ifd: &TiffIFD<'_>,
+        tag: TiffTag,
+    ) -> Result<&'a [u8], String> {
+        let offsets = ifd
+            .get_required_entry(tag)
+            .and_then(TiffEntry::get_u32s)
+            .map_err(tiff_err)?;
+        let [offset] = offsets.as_slice() else {
+            return Err(format!(
+                "Unsupported RW2 strip count: {}",
+                offsets.len()
+            ));
+        };
+        root.input()
+            .get(usize::try_from(*offset).unwrap()..)
+            .filter(|data| !data.is_empty())
+            .ok_or_else(|| "The RW2 strip is out of bounds".to_owned())
+    }
+
+    /// Newer cameras have a dedicated strip offset, and the version of
+    /// the compression.
+    fn parse_format(
+        root: &TiffRootIFD<'a>,
+        ifd: &TiffIFD<'_>,
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        version: Option<u16>,
+    ) -> Result<Format<'a>, String> {
+        let format = match version {
+            None => {
+                let data = Self::get_data(root, ifd, TiffTag::STRIP_OFFSETS)?;
+                let area = dims.row_len().get() * dims.row_count().get();
+                if data.len() >= area * 3 / 2 {
+                    return Err(
+                        "Uncompressed RW2 data is not supported".to_owned()
+                    );
+                }
+                PanasonicV4Decompressor::new(data, dims, 0).map(Format::V4)
+            }
+            Some(4) => {
+                let data =
+                    Self::get_data(root, ifd, TiffTag::PANASONIC_STRIP_OFFSET)?;
+                PanasonicV4Decompressor::new(data, dims, SECTION_SPLIT_OFFSET)
+                    .map(Format::V4)
+            }
+            Some(5) => {
+                let data =
+                    Self::get_data(root, ifd, TiffTag::PANASONIC_STRIP_OFFSET)?;
+                let bps = get_u32(ifd, TiffTag::PANASONIC_BITS_PER_SAMPLE)?
+                    .unwrap_or(DEFAULT_BITS_PER_SAMPLE);
+                PanasonicV5Decompressor::new(data, dims, bps).map(Format::V5)
+            }
+            Some(version) => {
+                return Err(format!("Unsupported RW2 version: {version}"));
+            }
+        };
+        format.map_err(|err| err.to_string())
+    }
+
+    /// The raw image is found via the dedicated strip offset of newer
+    /// cameras, or the regular one of older cameras, which have no
+    /// version.
+    fn find_raw_ifd<'b>(
+        root: &'b TiffRootIFD<'a>,
+    ) -> Result<(&'b TiffIFD<'a>, Option<u16>), String> {
+        if let Some(ifd) = root
+            .get_ifds_with_tag(TiffTag::PANASONIC_STRIP_OFFSET)
+            .into_iter()
+            .next()
+        {
+            let version = ifd
+                .get_required_entry(TiffTag::PANASONIC_RAW_FORMAT)
+                .and_then(|entry| entry.get_u16(0))
+                .map_err(tiff_err)?;
+            return Ok((ifd, Some(version)));
+        }
+        root.get_ifds_with_tag(TiffTag::STRIP_OFFSETS)
+            .into_iter()
+            .next()
+            .map(|ifd| (ifd, None))
+            .ok_or_else(|| "No raw image found in RW2".to_owned())
+    }
+
+    #[inline(never)]
+    pub fn new<F>(
+        root: &TiffRootIFD<'a>,
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<(Self, ProcurementRequest), String>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let (ifd, version) = Self::find_raw_ifd(root)?;
+        let dims = Self::parse_dims(ifd)?;
+
+        let make = get_root_string(root, TiffTag::MAKE).unwrap_or("");
+        let model = get_root_string(root, TiffTag::MODEL).unwrap_or("");
+        let camera = cameras
+            .find(make, model, Some(guess_mode(dims)))
+            .or_else(|| cameras.find(make, model, None))
+            .ok_or_else(|| format!("Unknown camera: {make} {model}"))?;
+        check_camera_support_fn(camera.supported)?;
+        let iso_speed = match get_u32(ifd, TiffTag::PANASONIC_ISO)? {
+            Some(iso) => Some(iso),
+            None => get_u32(ifd, TiffTag::ISO_SPEED_RATINGS)?,
+        };
+        let metadata = CameraMetadata::new(camera, make, model, iso_speed);
+
+        let format = Self::parse_format(root, ifd, dims, version)?;
+        let black_level_offset = match version {
+            Some(version) if version > LAST_OFFSET_BLACK_LEVELS_VERSION => 0,
+            Some(_) | None => BLACK_LEVEL_OFFSET,
+        };
+        let black_levels = match metadata
+            .cfa(Coord2D::new(RowIndex::new(0), ColIndex::new(0)))
+        {
+            Some(cfa) => parse_black_levels(ifd, cfa, black_level_offset)?,
+            None => None,
+        };
+        Ok((
+            Self {
+                metadata,
+                format,
+                dims,
+                crop: parse_crop(ifd, dims)?,
+                black_levels,
+                wb_coeffs: parse_wb_coeffs(ifd)?,
+            },
+            NDSliceProcurementRequest::<T>::new(dims).into(),
+        ))
+    }
+}
+
+#[expect(clippy::missing_trait_methods)]
+impl RawDemuxer for Rw2Demuxer<'_> {
+    #[inline]
+    fn make(&self) -> &str {
+        self.metadata.make
+    }
+
+    #[inline]
+    fn model(&self) -> &str {
+        self.metadata.model
+    }
+
+    #[inline]
+    fn mode(&self) -> Option<&str> {
+        self.metadata.mode()
+    }
+
+    #[inline]
+    fn canonical_make(&self) -> &str {
+        self.metadata.canonical_make()
+    }
+
+    #[inline]
+    fn canonical_model(&self) -> &str {
+        self.metadata.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_alias(&self) -> &str {
+        self.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_id(&self) -> String {
+        self.metadata.canonical_id()
+    }
+
+    #[inline]
+    fn iso_speed(&self) -> Option<u32> {
+        self.metadata.iso_speed
+    }
+
+    #[inline]
+    fn blacklevel(&self) -> Option<u16> {
+        self.metadata.blacklevel()
+    }
+
+    #[inline]
+    fn whitelevel(&self) -> Option<u16> {
+        self.metadata.whitelevel()
+    }
+
+    #[inline]
+    fn blacklevel_separate(&self) -> Option<Array2DRef<'_, i32>> {
+        self.black_levels.as_ref().map(OwnedArray2D::mat)
+    }
+
+    #[inline]
+    fn wb_coeffs(&self) -> Option<[f32; 4]> {
+        self.wb_coeffs
+    }
+
+    #[inline]
+    fn colormatrix(&self) -> Option<Array2DRef<'_, i16>> {
+        self.metadata.colormatrix()
+    }
+
+    #[inline]
+    fn is_cfa(&self) -> bool {
+        self.metadata.is_cfa()
+    }
+
+    #[inline]
+    fn cfa(
+        &self,
+        origin: Coord2D,
+    ) -> Option<OffsetArray2DRef<'_, ColorVariant>> {
+        self.metadata.cfa(origin)
+    }
+
+    #[inline]
+    fn bpp(&self) -> usize {
+        let bpc = match self.datatype() {
+            DataType::U16 => size_of::<u16>(),
+            DataType::F32 => size_of::<f32>(),
+            DataType::U32 => size_of::<u32>(),
+            _ => unreachable!(),
+        };
+        bpc.checked_mul(self.cpp()).unwrap()
+    }
+
+    #[inline]
+    fn cpp(&self) -> usize {
+        1
+    }
+
+    #[inline]
+    fn datatype(&self) -> DataType {
+        DataType::U16
+    }
+
+    #[inline]
+    fn dim_uncropped(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        self.dims
+    }
+
+    /// The sensor borders, if any, take precedence over the crop of the
+    /// camera.
+    #[inline]
+    fn dim_cropped(&self) -> Option<Dimensions2D<core::num::NonZero<usize>>> {
+        match self.crop {
+            Some((_, dims)) => Some(dims),
+            None => self.metadata.dim_cropped(self.dim_uncropped()),
+        }
+    }
+
+    #[inline]
+    fn crop_offset(&self) -> Option<Coord2D> {
+        match self.crop {
+            Some((offset, _)) => Some(offset),
+            None => self.metadata.crop_offset(),
+        }
+    }
+
+    #[inline]
+    fn black_areas(&self) -> Option<&[BlackArea]> {
+        self.metadata.black_areas()
+    }
+
+    #[inline]
+    fn fuji_rotation_pos(&self) -> Option<u32> {
+        None
+    }
+
+    #[inline]
+    fn pixel_aspect_ratio(&self) -> Option<f64> {
+        None
+    }
+
+    #[inline]
+    fn bad_pixel_positions(&self) -> Vec<Coord2D> {
+        vec![]
+    }
+
+    #[inline(never)]
+    fn decode(
+        &self,
+        output: &mut ImageRefMut<'_>,
+    ) -> Result<(), RawDemuxerError> {
+        let output = output.as_u16()?;
+        if output.dims() != self.dims {
+            return Err(RawDemuxerError::DecoderError(
+                "Output buffer dimensions differ from expected".to_owned(),
+            ));
+        }
+        match &self.format {
+            Format::V4(decompressor) => decompressor.decode(output),
+            Format::V5(decompressor) => decompressor.decode(output),
+        }
+        .map_err(|err| RawDemuxerError::DecoderError(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,154 @@
+use rawspeed_parsers_tiffparser::tiffparser::TiffTag;
+use rawspeed_utils_tiffbuilder::tiffbuilder::{Value, tag};
+
+use super::{
+    BLOCK_SIZE, Rw2, SECTION_SPLIT_OFFSET, decode, encode_v4, encode_v5,
+    new_demuxer_err,
+};
+
+/// Distinct samples of the given bit depth.
+fn samples(len: usize, bps: u32) -> Vec<u16> {
+    (0_usize..len)
+        .map(|index| u16::try_from((index * 2711 + 5) % (1 << bps)).unwrap())
+        .collect()
+}
+
+/// Samples that vary slowly enough for [`encode_v4`].
+fn smooth_samples(len: usize) -> Vec<u16> {
+    (0_usize..len)
+        .map(|index| {
+            let sample = 0x800 + (index * 37) % 200 + (index % 2) * 0x400;
+            u16::try_from(sample).unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn v4_test() {
+    let samples = smooth_samples(28 * 2);
+    let data = encode_v4(&samples, SECTION_SPLIT_OFFSET);
+    assert_eq!(decode(&Rw2::new(28, 2, Some(4), data)), Ok(samples));
+}
+
+#[test]
+fn old_v4_test() {
+    // Without a version, the sections of the blocks are not swapped.
+    let samples = smooth_samples(14 * 3);
+    let data = encode_v4(&samples, 0);
+    assert_eq!(data.len(), 3 * 16);
+    assert_eq!(decode(&Rw2::new(14, 3, None, data)), Ok(samples));
+}
+
+#[test]
+fn v5_12_bit_test() {
+    // The sample size defaults to 12 bits.
+    let samples = samples(20 * 3, 12);
+    let data = encode_v5(&samples, 12);
+    assert_eq!(decode(&Rw2::new(20, 3, Some(5), data)), Ok(samples));
+}
+
+#[test]
+fn v5_14_bit_test() {
+    let samples = samples(18 * 3, 14);
+    let mut rw2 = Rw2::new(18, 3, Some(5), encode_v5(&samples, 14));
+    rw2.extra.push(tag(
+        TiffTag::PANASONIC_BITS_PER_SAMPLE,
+        Value::Short(vec![14]),
+    ));
+    assert_eq!(decode(&rw2), Ok(samples));
+}
+
+#[test]
+fn unsupported_bits_per_sample_test() {
+    let mut rw2 = Rw2::new(20, 3, Some(5), vec![0; BLOCK_SIZE]);
+    rw2.extra.push(tag(
+        TiffTag::PANASONIC_BITS_PER_SAMPLE,
+        Value::Short(vec![16]),
+    ));
+    assert_eq!(
+        new_demuxer_err(&rw2.build()),
+        "PanasonicError(UnsupportedBitsPerSample)"
+    );
+}
+
+#[test]
+fn unsupported_version_test() {
+    assert_eq!(
+        new_demuxer_err(&Rw2::new(20, 3, Some(6), vec![0; 16]).build()),
+        "Unsupported RW2 version: 6"
+    );
+}
+
+#[test]
+fn old_uncompressed_test() {
+    assert_eq!(
+        new_demuxer_err(&Rw2::new(14, 2, None, vec![0; 14 * 3]).build()),
+        "Uncompressed RW2 data is not supported"
+    );
+}
+
+#[test]
+fn invalid_width_test() {
+    assert_eq!(
+        new_demuxer_err(&Rw2::new(16, 2, Some(4), vec![0; BLOCK_SIZE]).build()),
+        "PanasonicError(InvalidWidth)"
+    );
+    assert_eq!(
+        new_demuxer_err(&Rw2::new(16, 2, Some(5), vec![0; BLOCK_SIZE]).build()),
+        "PanasonicError(InvalidWidth)"
+    );
+}
+
+#[test]
+fn truncated_test() {
+    // The blocks must be complete, unless they are stored as is.
+    assert_eq!(
+        new_demuxer_err(&Rw2::new(14, 2, Some(4), vec![0; 32]).build()),
+        "PanasonicError(TruncatedData)"
+    );
+    assert_eq!(
+        new_demuxer_err(
+            &Rw2::new(20, 2, Some(5), vec![0; BLOCK_SIZE - 1]).build()
+        ),
+        "PanasonicError(TruncatedData)"
+    );
+    assert_eq!(
+        new_demuxer_err(&Rw2::new(14, 2, None, vec![0; 31]).build()),
+        "PanasonicError(TruncatedData)"
+    );
+}
+
+#[test]
+fn strip_count_test() {
+    let mut rw2 = Rw2::new(20, 2, Some(5), vec![0; BLOCK_SIZE]);
+    rw2.version = None;
+    let mut builder = rw2.builder();
+    builder.set(TiffTag::STRIP_OFFSETS, &Value::Long(vec![8, 16]));
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "Unsupported RW2 strip count: 2"
+    );
+}
+
+#[test]
+fn no_raw_image_test() {
+    let rw2 = Rw2::new(20, 2, None, vec![0; 16]);
+    let mut builder = rw2.builder();
+    builder.remove(TiffTag::STRIP_OFFSETS);
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "No raw image found in RW2"
+    );
+}
+
+#[test]
+fn zero_dimensions_test() {
+    assert_eq!(
+        new_demuxer_err(&Rw2::new(0, 2, Some(5), vec![0; 16]).build()),
+        "The width is zero"
+    );
+    assert_eq!(
+        new_demuxer_err(&Rw2::new(20, 0, Some(5), vec![0; 16]).build()),
+        "The height is zero"
+    );
+}
@@ -0,0 +1,245 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_std::coord_common::{
+    ColIndex, Coord2D, Dimensions2D, RowCount, RowIndex, RowLength,
+};
+use rawspeed_utils_tiffbuilder::tiffbuilder::{Entries, Value, tag};
+
+use super::{BLOCK_SIZE, Rw2, Rw2Demuxer, new_demuxer_err, parse_cameras};
+
+/// An image with the given aspect ratio.
+fn v5(width: u16, height: u16) -> Rw2 {
+    Rw2::new(width, height, Some(5), vec![0; BLOCK_SIZE * 2])
+}
+
+macro_rules! with_demuxer {
+    ($rw2:expr, |$demuxer:ident| $body:block) => {{
+        let input = $rw2.build();
+        let root = TiffParser::parse(&input).unwrap();
+        let cameras = parse_cameras();
+        let ($demuxer, _) = Rw2Demuxer::new(
+            &root,
+            &cameras,
+            DecodeableCamera::new_unless_unsupported,
+        )
+        .unwrap();
+        $body
+    }};
+}
+
+fn levels(demuxer: &Rw2Demuxer<'_>) -> [i32; 4] {
+    let levels = demuxer.blacklevel_separate().unwrap();
+    let level =
+        |row, col| levels[Coord2D::new(RowIndex::new(row), ColIndex::new(col))];
+    [level(0, 0), level(0, 1), level(1, 0), level(1, 1)]
+}
+
+fn black_levels() -> Entries {
+    vec![
+        tag(TiffTag::PANASONIC_BLACK_LEVEL_RED, Value::Short(vec![100])),
+        tag(
+            TiffTag::PANASONIC_BLACK_LEVEL_GREEN,
+            Value::Short(vec![110]),
+        ),
+        tag(TiffTag::PANASONIC_BLACK_LEVEL_BLUE, Value::Short(vec![120])),
+    ]
+}
+
+fn dims(
+    width: usize,
+    height: usize,
+) -> Dimensions2D<core::num::NonZero<usize>> {
+    Dimensions2D::new(
+        RowLength::new(core::num::NonZero::new(width).unwrap()),
+        RowCount::new(core::num::NonZero::new(height).unwrap()),
+    )
+}
+
+#[test]
+fn camera_from_cameras_xml_test() {
+    with_demuxer!(v5(40, 30), |demuxer| {
+        assert_eq!(demuxer.make(), "Panasonic");
+        assert_eq!(demuxer.model(), "DMC-G0");
+        assert_eq!(demuxer.mode(), Some("4:3"));
+        assert_eq!(demuxer.canonical_id(), "Panasonic DMC-G0");
+        assert_eq!(demuxer.iso_speed(), Some(400));
+        assert_eq!(demuxer.blacklevel(), Some(128));
+        assert_eq!(demuxer.whitelevel(), Some(4095));
+        assert!(demuxer.is_cfa());
+    });
+}
+
+#[test]
+fn camera_mode_test() {
+    // The camera with the mode of the closest aspect ratio is preferred.
+    with_demuxer!(v5(40, 31), |demuxer| {
+        assert_eq!(demuxer.mode(), Some("4:3"));
+    });
+    // Otherwise, the camera without a mode is used.
+    with_demuxer!(v5(30, 20), |demuxer| {
+        assert_eq!(demuxer.mode(), None);
+        assert_eq!(demuxer.blacklevel(), Some(144));
+    });
+}
+
+#[test]
+fn leica_rwl_test() {
+    // Leica's RWL is the RW2 container of the Panasonic-made bodies.
+    let mut rwl = v5(30, 20);
+    rwl.make = "LEICA";
+    rwl.model = "D-LUX 0";
+    with_demuxer!(rwl, |demuxer| {
+        assert_eq!(demuxer.make(), "LEICA");
+        assert_eq!(demuxer.mode(), Some("3:2"));
+        assert_eq!(demuxer.canonical_make(), "Leica");
+        assert_eq!(demuxer.canonical_id(), "Leica D-LUX 0");
+        assert_eq!(demuxer.blacklevel(), Some(15));
+    });
+    // There is no camera for the other modes.
+    rwl.height = 30;
+    assert_eq!(
+        new_demuxer_err(&rwl.build()),
+        "Unknown camera: LEICA D-LUX 0"
+    );
+}
+
+#[test]
+fn unknown_camera_test() {
+    let mut rw2 = v5(30, 20);
+    rw2.model = "DMC-G1";
+    assert_eq!(
+        new_demuxer_err(&rw2.build()),
+        "Unknown camera: Panasonic DMC-G1"
+    );
+    rw2.model = "Unsupported";
+    assert_eq!(
+        new_demuxer_err(&rw2.build()),
+        "This camera is not supported (explicit)"
+    );
+}
+
+#[test]
+fn sensor_borders_test() {
+    let mut rw2 = v5(40, 30);
+    rw2.extra = vec![
+        tag(TiffTag::PANASONIC_SENSOR_TOP_BORDER, Value::Short(vec![1])),
+        tag(TiffTag::PANASONIC_SENSOR_LEFT_BORDER, Value::Short(vec![4])),
+        tag(
+            TiffTag::PANASONIC_SENSOR_BOTTOM_BORDER,
+            Value::Short(vec![29]),
+        ),
+        tag(
+            TiffTag::PANASONIC_SENSOR_RIGHT_BORDER,
+            Value::Short(vec![38]),
+        ),
+    ];
+    with_demuxer!(rw2, |demuxer| {
+        assert_eq!(
+            demuxer.crop_offset(),
+            Some(Coord2D::new(RowIndex::new(1), ColIndex::new(4)))
+        );
+        assert_eq!(demuxer.dim_cropped(), Some(dims(34, 28)));
+    });
+}
+
+#[test]
+fn crop_without_sensor_borders_test() {
+    with_demuxer!(v5(40, 30), |demuxer| {
+        assert_eq!(
+            demuxer.crop_offset(),
+            Some(Coord2D::new(RowIndex::new(0), ColIndex::new(2)))
+        );
+        assert_eq!(demuxer.dim_cropped(), Some(dims(36, 30)));
+    });
+}
+
+#[test]
+fn invalid_sensor_borders_test() {
+    let borders = |bottom, right| {
+        let mut rw2 = v5(40, 30);
+        rw2.extra = vec![
+            tag(TiffTag::PANASONIC_SENSOR_TOP_BORDER, Value::Short(vec![2])),
+            tag(TiffTag::PANASONIC_SENSOR_LEFT_BORDER, Value::Short(vec![2])),
+            tag(
+                TiffTag::PANASONIC_SENSOR_BOTTOM_BORDER,
+                Value::Short(vec![bottom]),
+            ),
+            tag(
+                TiffTag::PANASONIC_SENSOR_RIGHT_BORDER,
+                Value::Short(vec![right]),
+            ),
+        ];
+        new_demuxer_err(&rw2.build())
+    };
+    assert_eq!(borders(31, 40), "The RW2 sensor borders are out of bounds");
+    assert_eq!(borders(30, 41), "The RW2 sensor borders are out of bounds");
+    assert_eq!(borders(2, 40), "The RW2 sensor borders are empty");
+    assert_eq!(borders(30, 1), "The RW2 sensor borders are empty");
+}
+
+#[test]
+fn black_levels_test() {
+    // Since version 5, the black levels are stored as is.
+    let mut rw2 = v5(40, 30);
+    rw2.extra = black_levels();
+    with_demuxer!(rw2, |demuxer| {
+        assert_eq!(levels(&demuxer), [100, 110, 110, 120]);
+    });
+}
+
+#[test]
+fn black_levels_follow_cfa_test() {
+    // BG/GR.
+    let mut rw2 = v5(30, 20);
+    rw2.extra = black_levels();
+    with_demuxer!(rw2, |demuxer| {
+        assert_eq!(levels(&demuxer), [120, 110, 110, 100]);
+    });
+}
+
+#[test]
+fn offset_black_levels_test() {
+    // Older versions store them too low.
+    let mut rw2 = Rw2::new(28, 21, Some(4), vec![0; BLOCK_SIZE]);
+    rw2.extra = black_levels();
+    with_demuxer!(rw2, |demuxer| {
+        assert_eq!(levels(&demuxer), [115, 125, 125, 135]);
+    });
+    let mut old_rw2 = Rw2::new(28, 21, None, vec![0; 16 * 42]);
+    old_rw2.extra = black_levels();
+    with_demuxer!(old_rw2, |demuxer| {
+        assert_eq!(levels(&demuxer), [115, 125, 125, 135]);
+    });
+}
+
+#[test]
+fn partial_black_levels_test() {
+    let mut rw2 = v5(40, 30);
+    rw2.extra = black_levels();
+    rw2.extra.pop();
+    with_demuxer!(rw2, |demuxer| {
+        assert!(demuxer.blacklevel_separate().is_none());
+    });
+}
+
+#[test]
+fn wb_coeffs_test() {
+    with_demuxer!(v5(40, 30), |demuxer| {
+        assert_eq!(demuxer.wb_coeffs(), None);
+    });
+    let mut rw2 = v5(40, 30);
+    rw2.extra = vec![
+        tag(TiffTag::PANASONIC_WB_RED_LEVEL, Value::Short(vec![520])),
+        tag(TiffTag::PANASONIC_WB_GREEN_LEVEL, Value::Short(vec![256])),
+        tag(TiffTag::PANASONIC_WB_BLUE_LEVEL, Value::Short(vec![410])),
+    ];
+    with_demuxer!(rw2, |demuxer| {
+        let [red, green, blue, fourth] = demuxer.wb_coeffs().unwrap();
+        assert_eq!(
+            [red, green, blue].map(f32::to_bits),
+            [520.0_f32, 256.0, 410.0].map(f32::to_bits)
+        );
+        assert!(fourth.is_nan());
+    });
+}
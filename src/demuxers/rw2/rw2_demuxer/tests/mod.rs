@@ -0,0 +1,237 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::Cameras;
+use rawspeed_metadata_xmlparser::xmlparser;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_std::coord_common::RowIndex;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+use rawspeed_utils_tiffbuilder::tiffbuilder::{
+    Entries, TiffBuilder, Value, tag,
+};
+
+use super::Rw2Demuxer;
+
+const CAMERAS: &str = "
+    <Cameras>
+        <Camera make=\"Panasonic\" model=\"DMC-G0\" mode=\"4:3\">
+            <ID make=\"Panasonic\" model=\"DMC-G0\">Panasonic DMC-G0</ID>
+            <CFA width=\"2\" height=\"2\">
+                <Color x=\"0\" y=\"0\">RED</Color>
+                <Color x=\"1\" y=\"0\">GREEN</Color>
+                <Color x=\"0\" y=\"1\">GREEN</Color>
+                <Color x=\"1\" y=\"1\">BLUE</Color>
+            </CFA>
+            <Crop x=\"2\" y=\"0\" width=\"-2\" height=\"0\"/>
+            <Sensor black=\"128\" white=\"4095\"/>
+        </Camera>
+        <Camera make=\"Panasonic\" model=\"DMC-G0\">
+            <ID make=\"Panasonic\" model=\"DMC-G0\">Panasonic DMC-G0</ID>
+            <CFA width=\"2\" height=\"2\">
+                <Color x=\"0\" y=\"0\">BLUE</Color>
+                <Color x=\"1\" y=\"0\">GREEN</Color>
+                <Color x=\"0\" y=\"1\">GREEN</Color>
+                <Color x=\"1\" y=\"1\">RED</Color>
+            </CFA>
+            <Sensor black=\"144\" white=\"16383\"/>
+        </Camera>
+        <Camera make=\"LEICA\" model=\"D-LUX 0\" mode=\"3:2\">
+            <ID make=\"Leica\" model=\"D-LUX 0\">Leica D-LUX 0</ID>
+            <Sensor black=\"15\" white=\"4095\"/>
+        </Camera>
+        <Camera make=\"Panasonic\" model=\"Unsupported\" supported=\"no\">
+        </Camera>
+    </Cameras>";
+
+/// The block size of both compression schemes.
+const BLOCK_SIZE: usize = 0x4000;
+
+/// The length of the first section of each block, which is stored last.
+const SECTION_SPLIT_OFFSET: usize = 0x1FF8;
+
+/// Swaps the sections of a block, the way they are stored.
+fn rotate_block(block: &[u8], section_split_offset: usize) -> Vec<u8> {
+    let split = block.len() - section_split_offset;
+    let mut out = block.get(split..).unwrap().to_vec();
+    out.extend(block.get(..split).unwrap());
+    out
+}
+
+/// Writes the bits in the order of the older scheme: from the top down,
+/// and the bytes of each packet from its last one down to its first one.
+#[derive(Debug)]
+struct V4Writer {
+    buf: Vec<u8>,
+    pos: u32,
+}
+
+impl V4Writer {
+    fn new() -> Self {
+        Self {
+            buf: vec![0; BLOCK_SIZE + 1],
+            pos: 0,
+        }
+    }
+
+    fn put(&mut self, bits: u32, nbits: u32) {
+        self.pos = self.pos.wrapping_sub(nbits) & 0x1_FFFF;
+        let byte = usize::try_from(self.pos >> 3).unwrap() ^ 0x3FF0;
+        let word = bits << (self.pos & 7);
+        *self.buf.get_mut(byte).unwrap() |= u8::try_from(word & 0xFF).unwrap();
+        *self.buf.get_mut(byte + 1).unwrap() |=
+            u8::try_from(word >> 8).unwrap();
+    }
+}
+
+/// Encodes the samples with the older scheme, without scaling the
+/// differences: the first two samples of each packet must be at least 16,
+/// and the others must be within 127 of the previous sample of the same
+/// color, which must be at least 128.
+fn encode_v4(samples: &[u16], section_split_offset: usize) -> Vec<u8> {
+    let mut writer = V4Writer::new();
+    for packet in samples.chunks(14) {
+        for (pixel, sample) in packet.iter().enumerate() {
+            if pixel % 3 == 2 {
+                writer.put(0, 2);
+            }
+            let sample = u32::from(*sample);
+            if pixel < 2 {
+                writer.put(sample >> 4, 8);
+                writer.put(sample & 0xF, 4);
+                continue;
+            }
+            let prev = u32::from(*packet.get(pixel - 2).unwrap());
+            let diff = if sample == prev {
+                0
+            } else {
+                sample + 0x80 - prev
+            };
+            writer.put(diff, 8);
+        }
+    }
+    writer.buf.truncate(samples.len() / 14 * 16);
+    if section_split_offset == 0 {
+        return writer.buf;
+    }
+    writer.buf.resize(BLOCK_SIZE, 0);
+    rotate_block(&writer.buf, section_split_offset)
+}
+
+/// Encodes the samples with the newer scheme, in a single block.
+fn encode_v5(samples: &[u16], bps: u32) -> Vec<u8> {
+    let pixels_per_packet = if bps == 12 { 10 } else { 9 };
+    let mut writer = BitStreamBuilder::new();
+    for packet in samples.chunks(pixels_per_packet) {
+        for sample in packet {
+            writer.put(u32::from(*sample), bps);
+        }
+        writer.put(0, 128 % bps);
+    }
+    let mut block = writer.finish(BitOrder::LSB);
+    block.resize(BLOCK_SIZE, 0);
+    rotate_block(&block, SECTION_SPLIT_OFFSET)
+}
+
+#[derive(Debug)]
+struct Rw2 {
+    make: &'static str,
+    model: &'static str,
+    width: u16,
+    height: u16,
+    /// Older cameras store no version, and use the regular strip offset.
+    version: Option<u16>,
+    data: Vec<u8>,
+    extra: Entries,
+}
+
+impl Rw2 {
+    fn new(
+        width: u16,
+        height: u16,
+        version: Option<u16>,
+        data: Vec<u8>,
+    ) -> Self {
+        Self {
+            make: "Panasonic",
+            model: "DMC-G0",
+            width,
+            height,
+            version,
+            data,
+            extra: vec![],
+        }
+    }
+
+    fn builder(&self) -> TiffBuilder {
+        let mut builder = TiffBuilder::new(Endianness::Little);
+        builder.magic = TiffParser::MAGIC_RW2;
+        builder.ifd(0).extend([
+            tag(
+                TiffTag::PANASONIC_SENSOR_WIDTH,
+                Value::Short(vec![self.width]),
+            ),
+            tag(
+                TiffTag::PANASONIC_SENSOR_HEIGHT,
+                Value::Short(vec![self.height]),
+            ),
+            tag(TiffTag::PANASONIC_ISO, Value::Short(vec![400])),
+            tag(TiffTag::MAKE, Value::Ascii(self.make)),
+            tag(TiffTag::MODEL, Value::Ascii(self.model)),
+        ]);
+        if let Some(version) = self.version {
+            builder.ifd(0).extend([
+                tag(TiffTag::PANASONIC_RAW_FORMAT, Value::Short(vec![version])),
+                tag(
+                    TiffTag::PANASONIC_STRIP_OFFSET,
+                    Value::BlobOffsets(vec![0]),
+                ),
+            ]);
+        } else {
+            builder
+                .ifd(0)
+                .push(tag(TiffTag::STRIP_OFFSETS, Value::BlobOffsets(vec![0])));
+        }
+        builder.ifd(0).extend(self.extra.iter().cloned());
+        builder.blobs = vec![self.data.clone()];
+        builder
+    }
+
+    fn build(&self) -> Vec<u8> {
+        self.builder().build()
+    }
+}
+
+fn parse_cameras() -> Cameras<'static> {
+    xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap()
+}
+
+fn new_demuxer_err(input: &[u8]) -> String {
+    let root = TiffParser::parse(input).unwrap();
+    let cameras = parse_cameras();
+    Rw2Demuxer::new(&root, &cameras, DecodeableCamera::new_unless_unsupported)
+        .unwrap_err()
+}
+
+fn decode(rw2: &Rw2) -> Result<Vec<u16>, String> {
+    let input = rw2.build();
+    let root = TiffParser::parse(&input).unwrap();
+    let cameras = parse_cameras();
+    let (demuxer, request) = Rw2Demuxer::new(
+        &root,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )?;
+    let mut output_buf = request.fulfill().unwrap();
+    let mut image = output_buf.get_mut();
+    demuxer.decode(&mut image).map_err(|err| err.to_string())?;
+    let output = image.as_u16().unwrap();
+    Ok((0..output.num_rows().get())
+        .flat_map(|row| output.get_row(RowIndex::new(row)).unwrap().to_vec())
+        .collect())
+}
+
+#[cfg(test)]
+mod data;
+#[cfg(test)]
+mod metadata;
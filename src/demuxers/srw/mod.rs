@@ -0,0 +1 @@
+pub mod srw_demuxer;
@@ -0,0 +1,489 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_codecs_packed_decoder::packed_decoder::Unpacker;
+use rawspeed_codecs_samsung::samsung::{
+    SamsungV0Decompressor, SamsungV1Decompressor, SamsungV2Decompressor,
+};
+use rawspeed_demuxers_common::{
+    camera_metadata::CameraMetadata,
+    tiff_utils::{
+        OwnedArray2D, get_root_string, get_strip, get_u32, get_usize, non_zero,
+        tiff_err,
+    },
+};
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::{
+    DataType, ImageRefMut, ProcurementRequest, RawDemuxer, RawDemuxerError,
+};
+use rawspeed_memory_nd_slice_procurement::ndsliceprocurement::NDSliceProcurementRequest;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::{
+    Cameras, Supported, blackareas::BlackArea,
+};
+use rawspeed_metadata_colorfilterarray::colorfilterarray::ColorVariant;
+use rawspeed_parsers_tiffparser::tiffparser::{
+    TiffEntry, TiffIFD, TiffParser, TiffRootIFD, TiffTag,
+};
+use rawspeed_std::coord_common::{
+    Coord2D, Dimensions2D, RowCount, RowLength, RowPitch,
+};
+use rawspeed_std_ndslice::{
+    array2dref::Array2DRef, offsetarray2dref::OffsetArray2DRef,
+};
+
+const COMPRESSION_UNCOMPRESSED: u32 = 0x8001;
+/// Uncompressed, unless the raw IFD has a row offset table, in which case
+/// the rows are compressed with the oldest scheme.
+const COMPRESSION_V0: u32 = 0x8002;
+const COMPRESSION_V1: u32 = 0x8004;
+const COMPRESSION_V2: u32 = 0x8005;
+
+type T = u16;
+
+/// The `MakerNote` is a bare IFD, in the byte order of the file, whose
+/// offsets are relative to the start of the file.
+fn parse_makernote<'a>(
+    root: &TiffRootIFD<'a>,
+) -> Result<Option<TiffRootIFD<'a>>, String> {
+    let Some(entry) = root.get_entry_recursive(TiffTag::MAKER_NOTE) else {
+        return Ok(None);
+    };
+    TiffParser::parse_ifd_chain(
+        root.input(),
+        root.endianness(),
+        entry.data_offset(),
+    )
+    .map(Some)
+    .map_err(tiff_err)
+}
+
+/// Four RGGB values.
+fn get_rggb(
+    makernote: &TiffRootIFD<'_>,
+    tag: TiffTag,
+) -> Result<Option<Vec<u32>>, String> {
+    makernote
+        .get_entry_recursive(tag)
+        .filter(|entry| entry.count() == 4)
+        .map(TiffEntry::get_u32s)
+        .transpose()
+        .map_err(tiff_err)
+}
+
+/// Per-CFA-position black levels.
+fn parse_black_levels(
+    makernote: &TiffRootIFD<'_>,
+) -> Result<Option<OwnedArray2D<i32>>, String> {
+    let Some(levels) =
+        get_rggb(makernote, TiffTag::SAMSUNG_WB_RGGB_LEVELS_BLACK)?
+    else {
+        return Ok(None);
+    };
+    let levels = levels
+        .into_iter()
+        .map(|level| {
+            i32::try_from(level).map_err(|_err| "Invalid SRW black level")
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    OwnedArray2D::new(levels, 2).map(Some)
+}
+
+/// The white balance is stored as the RGGB levels, before the black
+/// levels are subtracted.
+#[expect(clippy::cast_precision_loss)]
+fn parse_wb_coeffs(
+    makernote: &TiffRootIFD<'_>,
+) -> Result<Option<[f32; 4]>, String> {
+    let (Some(levels), Some(black)) = (
+        get_rggb(makernote, TiffTag::SAMSUNG_WB_RGGB_LEVELS_UNCORRECTED)?,
+        get_rggb(makernote, TiffTag::SAMSUNG_WB_RGGB_LEVELS_BLACK)?,
+    ) else {
+        return Ok(None);
+    };
+    let [red, green, blue] = [0, 1, 3].map(|index| {
+        let level = i64::from(*levels.get(index).unwrap());
+        let black = i64::from(*black.get(index).unwrap());
+        (level - black) as f32
+    });
+    Ok(Some([red, green, blue, f32::NAN]))
+}
+
+fn get_bit_order_hint(
+    metadata: &CameraMetadata<'_>,
+    default: BitOrder,
+) -> Result<BitOrder, String> {
+    match metadata.hint("msb_override") {
+        None => Ok(default),
+        Some("true") => Ok(BitOrder::MSB),
+        Some("false") => Ok(BitOrder::LSB),
+        Some(hint) => Err(format!("Invalid msb_override hint: {hint}")),
+    }
+}
+
+#[derive(Debug)]
+enum Format<'a> {
+    V0(SamsungV0Decompressor<'a>),
+    V1(SamsungV1Decompressor<'a>),
+    V2(SamsungV2Decompressor<'a>),
+    Uncompressed {
+        input: Array2DRef<'a, u8>,
+        order: BitOrder,
+        bits: u32,
+    },
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct SrwDemuxer<'a> {
+    metadata: CameraMetadata<'a>,
+    format: Format<'a>,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+    black_levels: Option<OwnedArray2D<i32>>,
+    wb_coeffs: Option<[f32; 4]>,
+}
+
+impl<'a> SrwDemuxer<'a> {
+    fn parse_dims(
+        ifd: &TiffIFD<'_>,
+    ) -> Result<Dimensions2D<core::num::NonZero<usize>>, String> {
+        let width = get_usize(ifd, TiffTag::IMAGE_WIDTH)?.unwrap_or(0);
+        let height = get_usize(ifd, TiffTag::IMAGE_LENGTH)?.unwrap_or(0);
+        Ok(Dimensions2D::new(
+            RowLength::new(non_zero(width, "width")?),
+            RowCount::new(non_zero(height, "height")?),
+        ))
+    }
+
+    /// The row offset table holds the offset of each row into the strip,
+    /// as little-endian 32-bit integers, and is stored at the given offset
+    /// into the file.
+    fn parse_v0(
+        input: &[u8],
+        strip: &'a [u8],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        table_offset: usize,
+    ) -> Result<Format<'a>, String> {
+        let height = dims.row_count().get();
+        let table = table_offset
+            .checked_add(height * size_of::<u32>())
+            .and_then(|end| input.get(table_offset..end))
+            .ok_or("The SRW row offset table is truncated")?;
+        let row_offsets = table
+            .chunks_exact(size_of::<u32>())
+            .map(|bytes| {
+                usize::try_from(u32::from_le_bytes(bytes.try_into().unwrap()))
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        SamsungV0Decompressor::new(strip, &row_offsets, dims)
+            .map(Format::V0)
+            .map_err(|err| err.to_string())
+    }
+
+    /// The samples are bit-packed.
+    fn parse_uncompressed(
+        strip: &'a [u8],
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        bits: u32,
+        order: BitOrder,
+    ) -> Result<Format<'a>, String> {
+        let (width, height) = (dims.row_len().get(), dims.row_count().get());
+        let row_bits = width * usize::try_from(bits).unwrap();
+        if !row_bits.is_multiple_of(8) {
+            return Err(format!("Unsupported SRW row length: {row_bits} bits"));
+        }
+        let bytes_per_row = row_bits / 8;
+        // Rows are read in whole chunks of the bit order.
+        if !bytes_per_row.is_multiple_of(order.mcu_bytelen()) {
+            return Err(format!("Unsupported SRW row pitch: {bytes_per_row}"));
+        }
+        let input = strip
+            .get(..bytes_per_row * height)
+            .ok_or("The SRW strip is too small")?;
+        let bytes_per_row = non_zero(bytes_per_row, "row pitch")?;
+        Ok(Format::Uncompressed {
+            input: Array2DRef::new(
+                input,
+                RowLength::new(bytes_per_row),
+                RowPitch::new(bytes_per_row),
+            ),
+            order,
+            bits,
+        })
+    }
+
+    /// Without a row offset table, the samples of the oldest scheme are
+    /// stored uncompressed, and those of 12 bits most significant bit
+    /// first, unless the camera says otherwise.
+    fn parse_format(
+        root: &TiffRootIFD<'a>,
+        ifd: &TiffIFD<'_>,
+        (compression, bits): (u32, u32),
+        dims: Dimensions2D<core::num::NonZero<usize>>,
+        metadata: &CameraMetadata<'_>,
+    ) -> Result<Format<'a>, String> {
+        let strip = get_strip(root.input(), ifd)?;
+        let table_offset = get_usize(ifd, TiffTag::SAMSUNG_ROW_OFFSETS)?;
+        match (compression, table_offset) {
+            (COMPRESSION_V0, Some(table_offset)) => {
+                Self::parse_v0(root.input(), strip, dims, table_offset)
+            }
+            (COMPRESSION_V0, None) => {
+                let default = if bits == 12 {
+                    BitOrder::MSB
+                } else {
+                    BitOrder::LSB
+                };
+                let order = get_bit_order_hint(metadata, default)?;
+                Self::parse_uncompressed(strip, dims, bits, order)
+            }
+            (COMPRESSION_V1, _) => {
+                SamsungV1Decompressor::new(strip, dims, bits)
+                    .map(Format::V1)
+                    .map_err(|err| err.to_string())
+            }
+            (COMPRESSION_V2, _) => {
+                SamsungV2Decompressor::new(strip, dims, bits)
+                    .map(Format::V2)
+                    .map_err(|err| err.to_string())
+            }
+            (_, _) => {
+                let order = get_bit_order_hint(metadata, BitOrder::LSB)?;
+                Self::parse_uncompressed(strip, dims, bits, order)
+            }
+        }
+    }
+
+    #[inline(never)]
+    pub fn new<F>(
+        root: &TiffRootIFD<'a>,
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<(Self, ProcurementRequest), String>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let ifd = root
+            .get_ifds_with_tag(TiffTag::STRIP_OFFSETS)
+            .into_iter()
+            .next()
+            .ok_or("No raw image found in SRW")?;
+        let compression = get_u32(ifd, TiffTag::COMPRESSION)?.unwrap_or(0);
+        if !matches!(
+            compression,
+            COMPRESSION_UNCOMPRESSED
+                | COMPRESSION_V0
+                | COMPRESSION_V1
+                | COMPRESSION_V2
+        ) {
+            return Err(format!("Unsupported SRW compression: {compression}"));
+        }
+        let bits = get_u32(ifd, TiffTag::BITS_PER_SAMPLE)?.unwrap_or(0);
+        if !matches!(bits, 12 | 14) {
+            return Err(format!("Unsupported bits per sample: {bits}"));
+        }
+        let dims = Self::parse_dims(ifd)?;
+
+        let make = get_root_string(root, TiffTag::MAKE).unwrap_or("");
+        let model = get_root_string(root, TiffTag::MODEL).unwrap_or("");
+        let mode = format!("{bits}bit");
+        let camera = cameras
+            .find(make, model, Some(&mode))
+            .or_else(|| cameras.find(make, model, None))
+            .ok_or_else(|| format!("Unknown camera: {make} {model}"))?;
+        check_camera_support_fn(camera.supported)?;
+        let iso_speed = root
+            .get_entry_recursive(TiffTag::ISO_SPEED_RATINGS)
+            .map(TiffEntry::get_u32s)
+            .and_then(Result::ok)
+            .and_then(|isos| isos.first().copied());
+        let metadata = CameraMetadata::new(camera, make, model, iso_speed);
+
+        let format = Self::parse_format(
+            root,
+            ifd,
+            (compression, bits),
+            dims,
+            &metadata,
+        )?;
+        let (black_levels, wb_coeffs) = match parse_makernote(root)? {
+            Some(makernote) => (
+                parse_black_levels(&makernote)?,
+                parse_wb_coeffs(&makernote)?,
+            ),
+            None => (None, None),
+        };
+        Ok((
+            Self {
+                metadata,
+                format,
+                dims,
+                black_levels,
+                wb_coeffs,
+            },
+            NDSliceProcurementRequest::<T>::new(dims).into(),
+        ))
+    }
+}
+
+#[expect(clippy::missing_trait_methods)]
+impl RawDemuxer for SrwDemuxer<'_> {
+    #[inline]
+    fn make(&self) -> &str {
+        self.metadata.make
+    }
+
+    #[inline]
+    fn model(&self) -> &str {
+        self.metadata.model
+    }
+
+    #[inline]
+    fn mode(&self) -> Option<&str> {
+        self.metadata.mode()
+    }
+
+    #[inline]
+    fn canonical_make(&self) -> &str {
+        self.metadata.canonical_make()
+    }
+
+    #[inline]
+    fn canonical_model(&self) -> &str {
+        self.metadata.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_alias(&self) -> &str {
+        self.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_id(&self) -> String {
+        self.metadata.canonical_id()
+    }
+
+    #[inline]
+    fn iso_speed(&self) -> Option<u32> {
+        self.metadata.iso_speed
+    }
+
+    #[inline]
+    fn blacklevel(&self) -> Option<u16> {
+        self.metadata.blacklevel()
+    }
+
+    #[inline]
+    fn whitelevel(&self) -> Option<u16> {
+        self.metadata.whitelevel()
+    }
+
+    #[inline]
+    fn blacklevel_separate(&self) -> Option<Array2DRef<'_, i32>> {
+        self.black_levels.as_ref().map(OwnedArray2D::mat)
+    }
+
+    #[inline]
+    fn wb_coeffs(&self) -> Option<[f32; 4]> {
+        self.wb_coeffs
+    }
+
+    #[inline]
+    fn colormatrix(&self) -> Option<Array2DRef<'_, i16>> {
+        self.metadata.colormatrix()
+    }
+
+    #[inline]
+    fn is_cfa(&self) -> bool {
+        self.metadata.is_cfa()
+    }
+
+    #[inline]
+    fn cfa(
+        &self,
+        origin: Coord2D,
+    ) -> Option<OffsetArray2DRef<'_, ColorVariant>> {
+        self.metadata.cfa(origin)
+    }
+
+    #[inline]
+    fn bpp(&self) -> usize {
+        let bpc = match self.datatype() {
+            DataType::U16 => size_of::<u16>(),
+            DataType::F32 => size_of::<f32>(),
+            DataType::U32 => size_of::<u32>(),
+            _ => unreachable!(),
+        };
+        bpc.checked_mul(self.cpp()).unwrap()
+    }
+
+    #[inline]
+    fn cpp(&self) -> usize {
+        1
+    }
+
+    #[inline]
+    fn datatype(&self) -> DataType {
+        DataType::U16
+    }
+
+    #[inline]
+    fn dim_uncropped(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        self.dims
+    }
+
+    #[inline]
+    fn dim_cropped(&self) -> Option<Dimensions2D<core::num::NonZero<usize>>> {
+        self.metadata.dim_cropped(self.dim_uncropped())
+    }
+
+    #[inline]
+    fn crop_offset(&self) -> Option<Coord2D> {
+        self.metadata.crop_offset()
+    }
+
+    #[inline]
+    fn black_areas(&self) -> Option<&[BlackArea]> {
+        self.metadata.black_areas()
+    }
+
+    #[inline]
+    fn fuji_rotation_pos(&self) -> Option<u32> {
+        None
+    }
+
+    #[inline]
+    fn pixel_aspect_ratio(&self) -> Option<f64> {
+        None
+    }
+
+    #[inline]
+    fn bad_pixel_positions(&self) -> Vec<Coord2D> {
+        vec![]
+    }
+
+    #[inline(never)]
+    fn decode(
+        &self,
+        output: &mut ImageRefMut<'_>,
+    ) -> Result<(), RawDemuxerError> {
+        let output = output.as_u16()?;
+        if output.dims() != self.dims {
+            return Err(RawDemuxerError::DecoderError(
+                "Output buffer dimensions differ from expected".to_owned(),
+            ));
+        }
+        let result = match &self.format {
+            Format::V0(decompressor) => decompressor.decode(output),
+            Format::V1(decompressor) => decompressor.decode(output),
+            Format::V2(decompressor) => decompressor.decode(output),
+            Format::Uncompressed { input, order, bits } => {
+                Unpacker::new(*input, *order, *bits, output).unpack();
+                Ok(())
+            }
+        };
+        result.map_err(|err| RawDemuxerError::DecoderError(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,197 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_parsers_tiffparser::tiffparser::TiffTag;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::pack;
+
+use super::{
+    COMPRESSION_V0, COMPRESSION_V1, COMPRESSION_V2, Srw, decode,
+    new_demuxer_err, v1_first_100, v2_flat,
+};
+
+fn rows(width: usize, height: usize, bits: u32) -> Vec<Vec<u16>> {
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| {
+                    let sample = (row * 977 + col * 313 + 5) % (1 << bits);
+                    u16::try_from(sample).unwrap()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn uncompressed(
+    model: &'static str,
+    compression: u32,
+    bits: u16,
+    data: Vec<u8>,
+) -> Srw {
+    let mut srw = Srw::new(16, 2, data);
+    srw.model = model;
+    srw.compression = compression;
+    srw.bits = bits;
+    srw
+}
+
+#[test]
+fn uncompressed_test() {
+    // Least significant bit first, unless the camera says otherwise.
+    let rows = rows(8, 2, 12);
+    let lsb = pack(&rows.concat(), 12, BitOrder::LSB);
+    assert_eq!(decode(&Srw::new(8, 2, lsb)), Ok(rows.clone()));
+    let msb = pack(&rows.concat(), 12, BitOrder::MSB);
+    let mut srw = Srw::new(8, 2, msb);
+    srw.model = "NX0 MSB";
+    assert_eq!(decode(&srw), Ok(rows));
+}
+
+#[test]
+fn uncompressed_without_row_offsets_test() {
+    // 12-bit samples are stored most significant bit first.
+    let rows_12 = rows(16, 2, 12);
+    let msb = pack(&rows_12.concat(), 12, BitOrder::MSB);
+    assert_eq!(
+        decode(&uncompressed("NX0", COMPRESSION_V0, 12, msb)),
+        Ok(rows_12.clone())
+    );
+    let lsb = pack(&rows_12.concat(), 12, BitOrder::LSB);
+    assert_eq!(
+        decode(&uncompressed("NX0 LSB", COMPRESSION_V0, 12, lsb)),
+        Ok(rows_12)
+    );
+    let rows_14 = rows(16, 2, 14);
+    let lsb_14 = pack(&rows_14.concat(), 14, BitOrder::LSB);
+    assert_eq!(
+        decode(&uncompressed("NX0", COMPRESSION_V0, 14, lsb_14)),
+        Ok(rows_14)
+    );
+}
+
+#[test]
+fn v0_test() {
+    for width in [32, 40] {
+        let srw = Srw::v0(width, 3);
+        let width = usize::try_from(width).unwrap();
+        assert_eq!(decode(&srw), Ok(vec![vec![128; width]; 3]));
+    }
+}
+
+#[test]
+fn invalid_row_offsets_test() {
+    let mut truncated = Srw::v0(32, 3);
+    truncated.row_offsets.as_mut().unwrap().truncate(11);
+    assert_eq!(
+        new_demuxer_err(&truncated.build()),
+        "The SRW row offset table is truncated"
+    );
+    let mut reversed = Srw::v0(32, 3);
+    reversed.row_offsets.as_mut().unwrap().reverse();
+    assert_eq!(
+        new_demuxer_err(&reversed.build()),
+        "SamsungError(InvalidRowOffsets)"
+    );
+}
+
+#[test]
+fn v1_test() {
+    let mut srw = Srw::new(8, 4, v1_first_100(8, 4));
+    srw.compression = COMPRESSION_V1;
+    let even = [100, 0].repeat(4);
+    assert_eq!(
+        decode(&srw),
+        Ok(vec![even.clone(), vec![0; 8], even, vec![0; 8]])
+    );
+}
+
+#[test]
+fn invalid_v1_test() {
+    // Past the end, the (zero) bits decode to negative differences.
+    let mut data = v1_first_100(64, 64);
+    data.truncate(8);
+    let mut srw = Srw::new(64, 64, data);
+    srw.compression = COMPRESSION_V1;
+    assert_eq!(
+        decode(&srw),
+        Err("RawDemuxerError(DecoderError(SamsungError(InvalidSample)))"
+            .to_owned())
+    );
+}
+
+#[test]
+fn v2_test() {
+    for bits in [12, 14] {
+        let mut srw = Srw::new(32, 2, v2_flat(32, 2, bits, 300));
+        srw.compression = COMPRESSION_V2;
+        srw.bits = u16::try_from(bits).unwrap();
+        assert_eq!(decode(&srw), Ok(vec![vec![300; 32]; 2]));
+    }
+}
+
+#[test]
+fn v2_header_mismatch_test() {
+    let mut dims = Srw::new(48, 2, v2_flat(32, 2, 12, 300));
+    dims.compression = COMPRESSION_V2;
+    assert_eq!(
+        new_demuxer_err(&dims.build()),
+        "SamsungError(InvalidDimensions)"
+    );
+    let mut bits = Srw::new(32, 2, v2_flat(32, 2, 14, 300));
+    bits.compression = COMPRESSION_V2;
+    assert_eq!(
+        new_demuxer_err(&bits.build()),
+        "SamsungError(InvalidHeader)"
+    );
+}
+
+#[test]
+fn unsupported_compression_test() {
+    let mut srw = Srw::new(8, 2, vec![0; 24]);
+    srw.compression = 0x8003;
+    assert_eq!(
+        new_demuxer_err(&srw.build()),
+        "Unsupported SRW compression: 32771"
+    );
+}
+
+#[test]
+fn unsupported_bits_per_sample_test() {
+    let mut srw = Srw::new(8, 2, vec![0; 32]);
+    srw.bits = 16;
+    assert_eq!(
+        new_demuxer_err(&srw.build()),
+        "Unsupported bits per sample: 16"
+    );
+}
+
+#[test]
+fn unsupported_row_test() {
+    assert_eq!(
+        new_demuxer_err(&Srw::new(3, 2, vec![0; 24]).build()),
+        "Unsupported SRW row length: 36 bits"
+    );
+}
+
+#[test]
+fn odd_row_pitch_test() {
+    let rows = rows(6, 2, 12);
+    let data = pack(&rows.concat(), 12, BitOrder::LSB);
+    assert_eq!(decode(&Srw::new(6, 2, data)), Ok(rows));
+}
+
+#[test]
+fn strip_too_small_test() {
+    assert_eq!(
+        new_demuxer_err(&Srw::new(8, 2, vec![0; 23]).build()),
+        "The SRW strip is too small"
+    );
+}
+
+#[test]
+fn no_raw_image_test() {
+    let mut builder = Srw::new(8, 2, vec![0; 24]).builder();
+    builder.remove(TiffTag::STRIP_OFFSETS);
+    assert_eq!(
+        new_demuxer_err(&builder.build()),
+        "No raw image found in SRW"
+    );
+}
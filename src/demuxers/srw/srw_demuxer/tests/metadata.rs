@@ -0,0 +1,146 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_std::coord_common::{ColIndex, Coord2D, RowIndex};
+use rawspeed_utils_tiffbuilder::tiffbuilder::{Entries, Value, tag};
+
+use super::{Srw, SrwDemuxer, new_demuxer_err, parse_cameras};
+
+fn unpacked(model: &'static str) -> Srw {
+    let mut srw = Srw::new(8, 2, vec![0; 24]);
+    srw.model = model;
+    srw
+}
+
+fn makernote_entries() -> Entries {
+    vec![
+        tag(
+            TiffTag::SAMSUNG_WB_RGGB_LEVELS_UNCORRECTED,
+            Value::Long(vec![2128, 1153, 1154, 1731]),
+        ),
+        tag(
+            TiffTag::SAMSUNG_WB_RGGB_LEVELS_BLACK,
+            Value::Long(vec![128, 129, 130, 131]),
+        ),
+    ]
+}
+
+macro_rules! with_demuxer {
+    ($srw:expr, |$demuxer:ident| $body:block) => {{
+        let input = $srw.build();
+        let root = TiffParser::parse(&input).unwrap();
+        let cameras = parse_cameras();
+        let ($demuxer, _) = SrwDemuxer::new(
+            &root,
+            &cameras,
+            DecodeableCamera::new_unless_unsupported,
+        )
+        .unwrap();
+        $body
+    }};
+}
+
+fn levels(demuxer: &SrwDemuxer<'_>) -> [i32; 4] {
+    let levels = demuxer.blacklevel_separate().unwrap();
+    let level =
+        |row, col| levels[Coord2D::new(RowIndex::new(row), ColIndex::new(col))];
+    [level(0, 0), level(0, 1), level(1, 0), level(1, 1)]
+}
+
+fn assert_wb_coeffs(demuxer: &SrwDemuxer<'_>, expected: [f32; 3]) {
+    let [red, green, blue, fourth] = demuxer.wb_coeffs().unwrap();
+    assert_eq!(
+        [red, green, blue].map(f32::to_bits),
+        expected.map(f32::to_bits)
+    );
+    assert!(fourth.is_nan());
+}
+
+#[test]
+fn camera_from_cameras_xml_test() {
+    with_demuxer!(unpacked("NX0"), |demuxer| {
+        assert_eq!(demuxer.make(), "SAMSUNG");
+        assert_eq!(demuxer.model(), "NX0");
+        assert_eq!(demuxer.mode(), None);
+        assert_eq!(demuxer.canonical_make(), "Samsung");
+        assert_eq!(demuxer.canonical_id(), "Samsung NX0");
+        assert_eq!(demuxer.iso_speed(), Some(800));
+        assert_eq!(demuxer.blacklevel(), Some(0));
+        assert_eq!(demuxer.whitelevel(), Some(4095));
+        assert!(demuxer.is_cfa());
+    });
+}
+
+#[test]
+fn bits_mode_test() {
+    let mut srw = Srw::new(16, 2, vec![0; 56]);
+    srw.bits = 14;
+    with_demuxer!(srw, |demuxer| {
+        assert_eq!(demuxer.mode(), Some("14bit"));
+        assert_eq!(demuxer.blacklevel(), Some(512));
+        assert_eq!(demuxer.whitelevel(), Some(16383));
+    });
+}
+
+#[test]
+fn unknown_camera_test() {
+    assert_eq!(
+        new_demuxer_err(&unpacked("NX1").build()),
+        "Unknown camera: SAMSUNG NX1"
+    );
+    assert_eq!(
+        new_demuxer_err(&unpacked("Unsupported").build()),
+        "This camera is not supported (explicit)"
+    );
+}
+
+#[test]
+fn makernote_test() {
+    for endianness in [Endianness::Little, Endianness::Big] {
+        let mut srw = unpacked("NX0");
+        srw.endianness = endianness;
+        srw.makernote = Some(makernote_entries());
+        with_demuxer!(srw, |demuxer| {
+            assert_eq!(levels(&demuxer), [128, 129, 130, 131]);
+            assert_wb_coeffs(&demuxer, [2000.0, 1024.0, 1600.0]);
+        });
+    }
+}
+
+#[test]
+fn partial_makernote_test() {
+    // The white balance levels need the black levels.
+    let mut srw = unpacked("NX0");
+    srw.makernote = Some(vec![tag(
+        TiffTag::SAMSUNG_WB_RGGB_LEVELS_UNCORRECTED,
+        Value::Long(vec![2128, 1153, 1154, 1731]),
+    )]);
+    with_demuxer!(srw, |demuxer| {
+        assert!(demuxer.blacklevel_separate().is_none());
+        assert!(demuxer.wb_coeffs().is_none());
+    });
+    let mut short = unpacked("NX0");
+    short.makernote = Some(vec![
+        tag(
+            TiffTag::SAMSUNG_WB_RGGB_LEVELS_UNCORRECTED,
+            Value::Long(vec![2128, 1153, 1154, 1731]),
+        ),
+        tag(
+            TiffTag::SAMSUNG_WB_RGGB_LEVELS_BLACK,
+            Value::Long(vec![128, 129, 130]),
+        ),
+    ]);
+    with_demuxer!(short, |demuxer| {
+        assert!(demuxer.blacklevel_separate().is_none());
+        assert!(demuxer.wb_coeffs().is_none());
+    });
+}
+
+#[test]
+fn no_makernote_test() {
+    with_demuxer!(unpacked("NX0"), |demuxer| {
+        assert!(demuxer.blacklevel_separate().is_none());
+        assert!(demuxer.wb_coeffs().is_none());
+    });
+}
@@ -0,0 +1,261 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::Cameras;
+use rawspeed_metadata_xmlparser::xmlparser;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_std::coord_common::RowIndex;
+use rawspeed_utils_bitstreambuilder::bitstreambuilder::BitStreamBuilder;
+use rawspeed_utils_tiffbuilder::tiffbuilder::{
+    Entries, TiffBuilder, Value, tag,
+};
+
+use super::{
+    COMPRESSION_UNCOMPRESSED, COMPRESSION_V0, COMPRESSION_V1, COMPRESSION_V2,
+    SrwDemuxer,
+};
+
+const CAMERAS: &str = "
+    <Cameras>
+        <Camera make=\"SAMSUNG\" model=\"NX0\">
+            <ID make=\"Samsung\" model=\"NX0\">Samsung NX0</ID>
+            <CFA width=\"2\" height=\"2\">
+                <Color x=\"0\" y=\"0\">RED</Color>
+                <Color x=\"1\" y=\"0\">GREEN</Color>
+                <Color x=\"0\" y=\"1\">GREEN</Color>
+                <Color x=\"1\" y=\"1\">BLUE</Color>
+            </CFA>
+            <Sensor black=\"0\" white=\"4095\"/>
+        </Camera>
+        <Camera make=\"SAMSUNG\" model=\"NX0\" mode=\"14bit\">
+            <ID make=\"Samsung\" model=\"NX0\">Samsung NX0</ID>
+            <CFA width=\"2\" height=\"2\">
+                <Color x=\"0\" y=\"0\">RED</Color>
+                <Color x=\"1\" y=\"0\">GREEN</Color>
+                <Color x=\"0\" y=\"1\">GREEN</Color>
+                <Color x=\"1\" y=\"1\">BLUE</Color>
+            </CFA>
+            <Sensor black=\"512\" white=\"16383\"/>
+        </Camera>
+        <Camera make=\"SAMSUNG\" model=\"NX0 MSB\">
+            <ID make=\"Samsung\" model=\"NX0 MSB\">Samsung NX0 MSB</ID>
+            <Hints>
+                <Hint name=\"msb_override\" value=\"true\"/>
+            </Hints>
+        </Camera>
+        <Camera make=\"SAMSUNG\" model=\"NX0 LSB\">
+            <ID make=\"Samsung\" model=\"NX0 LSB\">Samsung NX0 LSB</ID>
+            <Hints>
+                <Hint name=\"msb_override\" value=\"false\"/>
+            </Hints>
+        </Camera>
+        <Camera make=\"SAMSUNG\" model=\"Unsupported\" supported=\"no\">
+        </Camera>
+    </Cameras>";
+
+/// Packs the bits MSB-first into 32-bit words, which are stored
+/// little-endian, padded to a multiple of `alignment` bytes.
+fn pack_msb32(fields: &[(u32, u32)], alignment: usize) -> Vec<u8> {
+    let mut words = BitStreamBuilder::new();
+    for (bits, nbits) in fields {
+        words.put(*bits, *nbits);
+    }
+    let mut out = words.finish(BitOrder::MSB32);
+    out.resize(out.len().next_multiple_of(alignment), 0);
+    out
+}
+
+/// Samsung's oldest scheme: each block of each row has no differences, so
+/// all the samples are the initial prediction.
+fn v0_flat(width: usize, height: usize) -> (Vec<u8>, Vec<u8>) {
+    let mut data = vec![];
+    let mut table = vec![];
+    for _ in 0..height {
+        table.extend(u32::try_from(data.len()).unwrap().to_le_bytes());
+        // Left prediction, and explicit zero lengths.
+        let block = [(0, 1), (0xFF, 8), (0, 16)];
+        data.extend(pack_msb32(&block.repeat(width.div_ceil(16)), 4));
+    }
+    (data, table)
+}
+
+/// The NX100-era scheme: a first difference of 100, then zero ones.
+fn v1_first_100(width: usize, height: usize) -> Vec<u8> {
+    let mut bits = BitStreamBuilder::new();
+    // The code of 7-bit differences, then that of no difference.
+    bits.put(0b001, 3);
+    bits.put(100, 7);
+    for _ in 1..width * height {
+        bits.put(0b11_0100, 6);
+    }
+    bits.finish(BitOrder::MSB)
+}
+
+/// The adaptive scheme: every block is predicted from the left without
+/// differences, so all the samples are the initial value.
+fn v2_flat(width: usize, height: usize, bits: u32, value: u32) -> Vec<u8> {
+    let [width_bits, height_bits] =
+        [width, height].map(|len| u32::try_from(len).unwrap());
+    let mut out = pack_msb32(
+        &[
+            (1, 16),
+            (0, 4),
+            (bits - 1, 4),
+            (0, 8),
+            (width_bits, 16),
+            (height_bits, 16),
+            (width_bits, 16),
+            (0, 8),
+            (0, 24),
+            (0, 2),
+            (value, 14),
+        ],
+        16,
+    );
+    for _ in 0..height {
+        let mut row = vec![];
+        for col in (0..width).step_by(16) {
+            if col % 64 == 0 {
+                row.push((0, 2));
+            }
+            // The motion is kept, and the block skipped.
+            row.extend([(1, 1), (1, 1)]);
+        }
+        out.extend(pack_msb32(&row, 16));
+    }
+    out
+}
+
+#[derive(Debug)]
+struct Srw {
+    endianness: Endianness,
+    model: &'static str,
+    width: u32,
+    height: u32,
+    compression: u32,
+    bits: u16,
+    data: Vec<u8>,
+    row_offsets: Option<Vec<u8>>,
+    makernote: Option<Entries>,
+}
+
+impl Srw {
+    fn new(width: u32, height: u32, data: Vec<u8>) -> Self {
+        Self {
+            endianness: Endianness::Little,
+            model: "NX0",
+            width,
+            height,
+            compression: COMPRESSION_UNCOMPRESSED,
+            bits: 12,
+            data,
+            row_offsets: None,
+            makernote: None,
+        }
+    }
+
+    fn v0(width: u32, height: u32) -> Self {
+        let (data, table) = v0_flat(
+            usize::try_from(width).unwrap(),
+            usize::try_from(height).unwrap(),
+        );
+        let mut srw = Self::new(width, height, data);
+        srw.compression = COMPRESSION_V0;
+        srw.row_offsets = Some(table);
+        srw
+    }
+
+    fn builder(&self) -> TiffBuilder {
+        let mut builder = TiffBuilder::new(self.endianness);
+        builder.ifd(0).extend([
+            tag(TiffTag::MAKE, Value::Ascii("SAMSUNG")),
+            tag(TiffTag::MODEL, Value::Ascii(self.model)),
+            tag(TiffTag::EXIF_IFD_POINTER, Value::IFDOffset(1)),
+            tag(TiffTag::SUB_IFDS, Value::IFDOffset(2)),
+        ]);
+        builder
+            .ifd(1)
+            .push(tag(TiffTag::ISO_SPEED_RATINGS, Value::Short(vec![800])));
+        builder.ifd(2).extend([
+            tag(TiffTag::IMAGE_WIDTH, Value::Long(vec![self.width])),
+            tag(TiffTag::IMAGE_LENGTH, Value::Long(vec![self.height])),
+            tag(TiffTag::BITS_PER_SAMPLE, Value::Short(vec![self.bits])),
+            tag(TiffTag::COMPRESSION, Value::Long(vec![self.compression])),
+            tag(TiffTag::STRIP_OFFSETS, Value::BlobOffsets(vec![0])),
+            tag(TiffTag::STRIP_BYTE_COUNTS, Value::BlobSizes(vec![0])),
+        ]);
+        builder.blobs = vec![self.data.clone()];
+        if let Some(table) = &self.row_offsets {
+            builder.ifd(2).push(tag(
+                TiffTag::SAMSUNG_ROW_OFFSETS,
+                Value::BlobOffsets(vec![1]),
+            ));
+            builder.blobs.push(table.clone());
+        }
+        if let Some(entries) = &self.makernote {
+            Self::push_makernote(&mut builder, entries);
+        }
+        builder
+    }
+
+    /// The offsets of the `MakerNote` are relative to the start of the
+    /// file, and thus depend on where it is stored.
+    fn push_makernote(builder: &mut TiffBuilder, entries: &Entries) {
+        let mut makernote = TiffBuilder::new(builder.endianness);
+        *makernote.ifd(0) = entries.clone();
+        builder.ifd(1).push(tag(
+            TiffTag::MAKER_NOTE,
+            Value::Undefined(makernote.build_after(&[])),
+        ));
+        let input = builder.build();
+        let root = TiffParser::parse(&input).unwrap();
+        let offset = root
+            .get_entry_recursive(TiffTag::MAKER_NOTE)
+            .unwrap()
+            .data_offset();
+        let data = makernote.build_after(&vec![0; offset]);
+        builder.set(
+            TiffTag::MAKER_NOTE,
+            &Value::Undefined(data.get(offset..).unwrap().to_vec()),
+        );
+    }
+
+    fn build(&self) -> Vec<u8> {
+        self.builder().build()
+    }
+}
+
+fn parse_cameras() -> Cameras<'static> {
+    xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap()
+}
+
+fn new_demuxer_err(input: &[u8]) -> String {
+    let root = TiffParser::parse(input).unwrap();
+    let cameras = parse_cameras();
+    SrwDemuxer::new(&root, &cameras, DecodeableCamera::new_unless_unsupported)
+        .unwrap_err()
+}
+
+fn decode(srw: &Srw) -> Result<Vec<Vec<u16>>, String> {
+    let input = srw.build();
+    let root = TiffParser::parse(&input).unwrap();
+    let cameras = parse_cameras();
+    let (demuxer, request) = SrwDemuxer::new(
+        &root,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )?;
+    let mut output_buf = request.fulfill().unwrap();
+    let mut image = output_buf.get_mut();
+    demuxer.decode(&mut image).map_err(|err| err.to_string())?;
+    let output = image.as_u16().unwrap();
+    Ok((0..output.num_rows().get())
+        .map(|row| output.get_row(RowIndex::new(row)).unwrap().to_vec())
+        .collect())
+}
+
+#[cfg(test)]
+mod data;
+#[cfg(test)]
+mod metadata;
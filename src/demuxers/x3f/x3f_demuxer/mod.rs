@@ -0,0 +1,405 @@
+use rawspeed_bitstream_bytestream_decoder::bytestreamer::ByteStreamer;
+use rawspeed_codecs_sigma::sigma::{NUM_PLANES, TrueDecompressor};
+use rawspeed_demuxers_common::{
+    camera_metadata::CameraMetadata, tiff_utils::non_zero,
+};
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::{
+    DataType, ImageRefMut, ProcurementRequest, RawDemuxer, RawDemuxerError,
+};
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_memory_nd_slice_procurement::ndsliceprocurement::NDSliceProcurementRequest;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::{
+    Cameras, Supported, blackareas::BlackArea,
+};
+use rawspeed_metadata_colorfilterarray::colorfilterarray::ColorVariant;
+use rawspeed_std::coord_common::{Coord2D, Dimensions2D, RowCount, RowLength};
+use rawspeed_std_ndslice::{
+    array2dref::Array2DRef, offsetarray2dref::OffsetArray2DRef,
+};
+
+const X3F_MAGIC: &[u8] = b"FOVb";
+
+/// The directory, whose offset is the last word of the file, starts
+/// with its magic, its version and its number of entries.
+const DIRECTORY_MAGIC: &[u8] = b"SECd";
+const DIRECTORY_HEADER_LEN: usize = 12;
+
+/// Each directory entry is the offset, length and type of a section.
+const DIRECTORY_ENTRY_LEN: usize = 12;
+const SECTION_IMAGE: &[&[u8]] = &[b"IMAG", b"IMA2"];
+const SECTION_PROPERTIES: &[u8] = b"PROP";
+
+/// The image header: magic, version, type, format, width, height and
+/// row size, followed by the image data.
+const IMAGE_MAGIC: &[u8] = b"SECi";
+const IMAGE_HEADER_LEN: usize = 28;
+
+/// The types of the raw images, as opposed to the previews.
+const IMAGE_TYPES_RAW: [usize; 2] = [1, 3];
+const IMAGE_FORMAT_TRUE: usize = 30;
+const IMAGE_FORMAT_QUATTRO: usize = 35;
+
+/// The properties header: magic, version, number of properties, character
+/// format, a reserved word and the number of characters, followed by the
+/// offsets of the names and values, and by the characters.
+const PROPERTIES_MAGIC: &[u8] = b"SECp";
+const PROPERTIES_HEADER_LEN: usize = 24;
+const PROPERTIES_FORMAT_UTF16: usize = 0;
+
+type T = u16;
+
+fn read_u32(data: &[u8], offset: usize) -> Option<usize> {
+    data.get(offset..)
+        .and_then(|rest| rest.get(..size_of::<u32>()))
+        .map(|bytes| {
+            let val: u32 = ByteStreamer::new(bytes, Endianness::Little).read();
+            val.try_into().unwrap()
+        })
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Section<'a> {
+    section_type: &'a [u8],
+    data: &'a [u8],
+}
+
+fn parse_directory(input: &[u8]) -> Result<Vec<Section<'_>>, String> {
+    const TRUNCATED: &str = "The X3F directory is truncated";
+    if !input.starts_with(X3F_MAGIC) {
+        return Err("Not an X3F file".to_owned());
+    }
+    let offset = input
+        .len()
+        .checked_sub(size_of::<u32>())
+        .and_then(|last| read_u32(input, last))
+        .ok_or(TRUNCATED)?;
+    let directory = input.get(offset..).ok_or(TRUNCATED)?;
+    if !directory.starts_with(DIRECTORY_MAGIC) {
+        return Err("The X3F directory is invalid".to_owned());
+    }
+    let count = read_u32(directory, 8).ok_or(TRUNCATED)?;
+    (0..count)
+        .map(|index| {
+            let entry = DIRECTORY_HEADER_LEN + index * DIRECTORY_ENTRY_LEN;
+            let section_offset = read_u32(directory, entry).ok_or(TRUNCATED)?;
+            let len = read_u32(directory, entry + 4).ok_or(TRUNCATED)?;
+            let section_type = directory
+                .get(entry + 8..entry + DIRECTORY_ENTRY_LEN)
+                .ok_or(TRUNCATED)?;
+            let data = input
+                .get(section_offset..)
+                .and_then(|rest| rest.get(..len))
+                .ok_or("The X3F section is truncated")?;
+            Ok(Section { section_type, data })
+        })
+        .collect()
+}
+
+/// The name and value of each property, which are NUL-terminated
+/// UTF-16 strings.
+fn parse_properties(section: &[u8]) -> Result<Vec<(String, String)>, String> {
+    const TRUNCATED: &str = "The X3F properties are truncated";
+    if !section.starts_with(PROPERTIES_MAGIC) {
+        return Err("The X3F properties are invalid".to_owned());
+    }
+    let [Some(count), Some(format), Some(len)] =
+        [8, 12, 20].map(|offset| read_u32(section, offset))
+    else {
+        return Err(TRUNCATED.to_owned());
+    };
+    if format != PROPERTIES_FORMAT_UTF16 {
+        return Err(format!("Unsupported X3F property format: {format}"));
+    }
+    let chars_offset = PROPERTIES_HEADER_LEN + count * 2 * size_of::<u32>();
+    let chars: Vec<u16> = section
+        .get(chars_offset..)
+        .and_then(|rest| rest.get(..len * size_of::<u16>()))
+        .ok_or(TRUNCATED)?
+        .chunks_exact(size_of::<u16>())
+        .map(|bytes| ByteStreamer::new(bytes, Endianness::Little).read())
+        .collect();
+    let string = |offset: usize| {
+        let rest = chars.get(offset..).ok_or(TRUNCATED)?;
+        let end = rest.iter().position(|c| *c == 0).ok_or(TRUNCATED)?;
+        String::from_utf16(rest.get(..end).unwrap())
+            .map_err(|_err| "The X3F properties are invalid")
+    };
+    (0..count)
+        .map(|index| {
+            let entry = PROPERTIES_HEADER_LEN + index * 2 * size_of::<u32>();
+            let [Some(name), Some(value)] =
+                [entry, entry + 4].map(|offset| read_u32(section, offset))
+            else {
+                return Err(TRUNCATED.to_owned());
+            };
+            Ok((string(name)?, string(value)?))
+        })
+        .collect()
+}
+
+fn get_property<'b>(
+    properties: &'b [(String, String)],
+    name: &str,
+) -> Option<&'b str> {
+    properties
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_str())
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct X3fDemuxer<'a> {
+    metadata: CameraMetadata<'a>,
+    decompressor: TrueDecompressor<'a>,
+    dims: Dimensions2D<core::num::NonZero<usize>>,
+}
+
+impl<'a> X3fDemuxer<'a> {
+    /// The first raw image, and its dimensions.
+    fn parse_image(
+        sections: &[Section<'a>],
+    ) -> Result<
+        (
+            TrueDecompressor<'a>,
+            Dimensions2D<core::num::NonZero<usize>>,
+        ),
+        String,
+    > {
+        const TRUNCATED: &str = "The X3F image header is truncated";
+        let mut images = sections
+            .iter()
+            .filter(|section| SECTION_IMAGE.contains(&section.section_type))
+            .map(|section| section.data);
+        let (header, format) = images
+            .find_map(|section| {
+                let image_type = read_u32(section, 8)?;
+                let format = read_u32(section, 12)?;
+                IMAGE_TYPES_RAW
+                    .contains(&image_type)
+                    .then_some((section, format))
+            })
+            .ok_or("No raw image found in X3F")?;
+        if !header.starts_with(IMAGE_MAGIC) {
+            return Err("The X3F image header is invalid".to_owned());
+        }
+        let quattro = match format {
+            IMAGE_FORMAT_TRUE => false,
+            IMAGE_FORMAT_QUATTRO => true,
+            _ => return Err(format!("Unsupported X3F image format: {format}")),
+        };
+        let width = read_u32(header, 16).ok_or(TRUNCATED)?;
+        let height = read_u32(header, 20).ok_or(TRUNCATED)?;
+        let dims = Dimensions2D::new(
+            RowLength::new(non_zero(width, "width")?),
+            RowCount::new(non_zero(height, "height")?),
+        );
+        let data = header.get(IMAGE_HEADER_LEN..).ok_or(TRUNCATED)?;
+        let decompressor = TrueDecompressor::new(data, dims, quattro)
+            .map_err(|err| err.to_string())?;
+        Ok((decompressor, dims))
+    }
+
+    #[inline(never)]
+    pub fn new<F>(
+        input: &'a [u8],
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<(Self, ProcurementRequest), String>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let sections = parse_directory(input)?;
+        let properties = match sections
+            .iter()
+            .find(|section| section.section_type == SECTION_PROPERTIES)
+        {
+            Some(section) => parse_properties(section.data)?,
+            None => vec![],
+        };
+
+        let make = get_property(&properties, "CAMMANUF").unwrap_or("");
+        let model = get_property(&properties, "CAMMODEL").unwrap_or("");
+        let camera = cameras
+            .find(make, model, None)
+            .ok_or_else(|| format!("Unknown camera: {make} {model}"))?;
+        check_camera_support_fn(camera.supported)?;
+        let iso_speed =
+            get_property(&properties, "ISO").and_then(|iso| iso.parse().ok());
+        // The properties are decoded from UTF-16, so the make and model
+        // are those of the `cameras.xml` entry they match.
+        let metadata = CameraMetadata::new(
+            camera,
+            camera.make.as_ref(),
+            camera.model.as_ref(),
+            iso_speed,
+        );
+
+        let (decompressor, dims) = Self::parse_image(&sections)?;
+        Ok((
+            Self {
+                metadata,
+                decompressor,
+                dims,
+            },
+            // The planes are interleaved.
+            NDSliceProcurementRequest::<T>::new(dims)
+                .set_cpp(non_zero(NUM_PLANES, "component count")?)
+                .into(),
+        ))
+    }
+}
+
+#[expect(clippy::missing_trait_methods)]
+impl RawDemuxer for X3fDemuxer<'_> {
+    #[inline]
+    fn make(&self) -> &str {
+        self.metadata.make
+    }
+
+    #[inline]
+    fn model(&self) -> &str {
+        self.metadata.model
+    }
+
+    #[inline]
+    fn mode(&self) -> Option<&str> {
+        self.metadata.mode()
+    }
+
+    #[inline]
+    fn canonical_make(&self) -> &str {
+        self.metadata.canonical_make()
+    }
+
+    #[inline]
+    fn canonical_model(&self) -> &str {
+        self.metadata.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_alias(&self) -> &str {
+        self.canonical_model()
+    }
+
+    #[inline]
+    fn canonical_id(&self) -> String {
+        self.metadata.canonical_id()
+    }
+
+    #[inline]
+    fn iso_speed(&self) -> Option<u32> {
+        self.metadata.iso_speed
+    }
+
+    #[inline]
+    fn blacklevel(&self) -> Option<u16> {
+        self.metadata.blacklevel()
+    }
+
+    #[inline]
+    fn whitelevel(&self) -> Option<u16> {
+        self.metadata.whitelevel()
+    }
+
+    #[inline]
+    fn blacklevel_separate(&self) -> Option<Array2DRef<'_, i32>> {
+        None
+    }
+
+    #[inline]
+    fn wb_coeffs(&self) -> Option<[f32; 4]> {
+        None
+    }
+
+    #[inline]
+    fn colormatrix(&self) -> Option<Array2DRef<'_, i16>> {
+        self.metadata.colormatrix()
+    }
+
+    /// Each pixel has all three components.
+    #[inline]
+    fn is_cfa(&self) -> bool {
+        false
+    }
+
+    #[inline]
+    fn cfa(
+        &self,
+        _origin: Coord2D,
+    ) -> Option<OffsetArray2DRef<'_, ColorVariant>> {
+        None
+    }
+
+    #[inline]
+    fn bpp(&self) -> usize {
+        let bpc = match self.datatype() {
+            DataType::U16 => size_of::<u16>(),
+            DataType::F32 => size_of::<f32>(),
+            DataType::U32 => size_of::<u32>(),
+            _ => unreachable!(),
+        };
+        bpc.checked_mul(self.cpp()).unwrap()
+    }
+
+    #[inline]
+    fn cpp(&self) -> usize {
+        NUM_PLANES
+    }
+
+    #[inline]
+    fn datatype(&self) -> DataType {
+        DataType::U16
+    }
+
+    #[inline]
+    fn dim_uncropped(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        self.dims
+    }
+
+    #[inline]
+    fn dim_cropped(&self) -> Option<Dimensions2D<core::num::NonZero<usize>>> {
+        self.metadata.dim_cropped(self.dim_uncropped())
+    }
+
+    #[inline]
+    fn crop_offset(&self) -> Option<Coord2D> {
+        self.metadata.crop_offset()
+    }
+
+    #[inline]
+    fn black_areas(&self) -> Option<&[BlackArea]> {
+        self.metadata.black_areas()
+    }
+
+    #[inline]
+    fn fuji_rotation_pos(&self) -> Option<u32> {
+        None
+    }
+
+    #[inline]
+    fn pixel_aspect_ratio(&self) -> Option<f64> {
+        None
+    }
+
+    #[inline]
+    fn bad_pixel_positions(&self) -> Vec<Coord2D> {
+        vec![]
+    }
+
+    #[inline(never)]
+    fn decode(
+        &self,
+        output: &mut ImageRefMut<'_>,
+    ) -> Result<(), RawDemuxerError> {
+        let output = output.as_u16()?;
+        self.decompressor
+            .decode(output)
+            .map_err(|err| RawDemuxerError::DecoderError(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,87 @@
+use super::{X3f, decode, new_demuxer_err, rows};
+use crate::x3f_demuxer::IMAGE_FORMAT_QUATTRO;
+
+/// The planes of [`X3f::new`], interleaved.
+fn interleaved(width: usize, height: usize) -> Vec<Vec<u16>> {
+    let planes = [0, 1, 2].map(|plane| rows(width, height, plane));
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .flat_map(|col| {
+                    planes.iter().map(move |plane| {
+                        let sample = *plane.get(row).unwrap().get(col).unwrap();
+                        u16::try_from(sample).unwrap()
+                    })
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn true_test() {
+    assert_eq!(decode(&X3f::new(5, 3).build()), Ok(interleaved(5, 3)));
+}
+
+#[test]
+fn quattro_test() {
+    let mut quattro = X3f::new(4, 2);
+    quattro.format = IMAGE_FORMAT_QUATTRO;
+    // All planes have the full resolution.
+    let mut data: Vec<u8> = [4_u16, 2]
+        .repeat(3)
+        .iter()
+        .flat_map(|val| val.to_le_bytes())
+        .collect();
+    data.extend(&quattro.data);
+    quattro.data = data;
+    assert_eq!(decode(&quattro.build()), Ok(interleaved(4, 2)));
+}
+
+#[test]
+fn unsupported_format_test() {
+    let mut x3f = X3f::new(4, 2);
+    x3f.format = 6;
+    assert_eq!(
+        new_demuxer_err(&x3f.build()),
+        "Unsupported X3F image format: 6"
+    );
+}
+
+#[test]
+fn no_raw_image_test() {
+    let mut x3f = X3f::new(4, 2);
+    x3f.image_type = 2;
+    assert_eq!(new_demuxer_err(&x3f.build()), "No raw image found in X3F");
+}
+
+#[test]
+fn invalid_data_test() {
+    let mut truncated = X3f::new(4, 2);
+    truncated.data.truncate(40);
+    assert_eq!(
+        new_demuxer_err(&truncated.build()),
+        "SigmaError(TruncatedData)"
+    );
+    let mut taller = X3f::new(4, 2);
+    taller.height = 64;
+    assert_eq!(
+        decode(&taller.build()),
+        Err("RawDemuxerError(DecoderError(SigmaError(TruncatedData)))"
+            .to_owned())
+    );
+}
+
+#[test]
+fn invalid_directory_test() {
+    assert_eq!(new_demuxer_err(b"FOVa\0\0\0\0"), "Not an X3F file");
+    let mut x3f = X3f::new(4, 2).build();
+    let len = x3f.len();
+    x3f.get_mut(len - 4..)
+        .unwrap()
+        .copy_from_slice(&[0, 1, 0, 0]);
+    assert_eq!(new_demuxer_err(&x3f), "The X3F directory is invalid");
+    x3f.truncate(len - 5);
+    x3f.extend([0xFF; 4]);
+    assert_eq!(new_demuxer_err(&x3f), "The X3F directory is truncated");
+}
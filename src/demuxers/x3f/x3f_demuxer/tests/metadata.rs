@@ -0,0 +1,67 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_std::coord_common::{ColIndex, Coord2D, RowIndex};
+
+use super::{X3f, X3fDemuxer, new_demuxer_err, parse_cameras};
+
+#[test]
+fn three_plane_test() {
+    let input = X3f::new(5, 3).build();
+    let cameras = parse_cameras();
+    let (demuxer, request) = X3fDemuxer::new(
+        &input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )
+    .unwrap();
+    assert!(!demuxer.is_cfa());
+    assert!(
+        demuxer
+            .cfa(Coord2D::new(RowIndex::new(0), ColIndex::new(0)))
+            .is_none()
+    );
+    assert_eq!(demuxer.cpp(), 3);
+    assert_eq!(demuxer.bpp(), 6);
+    let dims = demuxer.dim_uncropped();
+    assert_eq!((dims.row_len().get(), dims.row_count().get()), (5, 3));
+    let mut output_buf = request.fulfill().unwrap();
+    assert_eq!(output_buf.get_mut().dims().row_len().get(), 15);
+}
+
+#[test]
+fn camera_from_properties_test() {
+    let input = X3f::new(4, 2).build();
+    let cameras = parse_cameras();
+    let (demuxer, _) = X3fDemuxer::new(
+        &input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )
+    .unwrap();
+    assert_eq!(demuxer.make(), "SIGMA");
+    assert_eq!(demuxer.model(), "SIGMA DP2 Merrill");
+    assert_eq!(demuxer.canonical_id(), "Sigma DP2 Merrill");
+    assert_eq!(demuxer.iso_speed(), Some(200));
+    assert_eq!(demuxer.blacklevel(), Some(16));
+    assert_eq!(demuxer.whitelevel(), Some(4095));
+    assert!(demuxer.wb_coeffs().is_none());
+}
+
+#[test]
+fn unknown_camera_test() {
+    let mut x3f = X3f::new(4, 2);
+    x3f.model = "SIGMA SD0";
+    assert_eq!(
+        new_demuxer_err(&x3f.build()),
+        "Unknown camera: SIGMA SIGMA SD0"
+    );
+    let mut unsupported = X3f::new(4, 2);
+    unsupported.model = "Unsupported";
+    assert_eq!(
+        new_demuxer_err(&unsupported.build()),
+        "This camera is not supported (explicit)"
+    );
+    let mut no_properties = X3f::new(4, 2);
+    no_properties.properties = false;
+    assert_eq!(new_demuxer_err(&no_properties.build()), "Unknown camera:  ");
+}
@@ -0,0 +1,244 @@
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer as _;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::Cameras;
+use rawspeed_metadata_xmlparser::xmlparser;
+use rawspeed_std::coord_common::RowIndex;
+
+use super::{IMAGE_FORMAT_TRUE, X3fDemuxer};
+
+const CAMERAS: &str = "
+    <Cameras>
+        <Camera make=\"SIGMA\" model=\"SIGMA DP2 Merrill\">
+            <ID make=\"Sigma\" model=\"DP2 Merrill\">Sigma DP2 Merrill</ID>
+            <Sensor black=\"16\" white=\"4095\"/>
+        </Camera>
+        <Camera make=\"SIGMA\" model=\"Unsupported\" supported=\"no\">
+        </Camera>
+    </Cameras>";
+
+/// The code of the test files is unary: as many ones as the length of
+/// the difference, then a zero. Codes are at most a byte long.
+const NUM_CODES: u32 = 8;
+
+/// Appends the unary code of the length of each difference, and the
+/// difference bits, MSB-first.
+fn encode_plane(samples: &[Vec<i32>], seed: i32) -> Vec<u8> {
+    let mut bits: Vec<bool> = vec![];
+    let mut put = |value: u32, len: u32| {
+        bits.extend((0..len).rev().map(|bit| (value >> bit) & 1 == 1));
+    };
+    for (row, values) in samples.iter().enumerate() {
+        for (col, value) in values.iter().enumerate() {
+            let pred = match (col.checked_sub(2), row.checked_sub(2)) {
+                (Some(col), _) => *values.get(col).unwrap(),
+                (None, Some(row)) => {
+                    *samples.get(row).unwrap().get(col).unwrap()
+                }
+                (None, None) => seed,
+            };
+            let diff = value - pred;
+            let len = 32 - diff.unsigned_abs().leading_zeros();
+            put((1 << (len + 1)) - 2, len + 1);
+            let diff_bits = if diff < 0 {
+                diff + (1 << len) - 1
+            } else {
+                diff
+            };
+            put(u32::try_from(diff_bits).unwrap(), len);
+        }
+    }
+    bits.chunks(8)
+        .map(|byte| {
+            (0..8).fold(0_u8, |acc, index| {
+                (acc << 1) | u8::from(*byte.get(index).unwrap_or(&false))
+            })
+        })
+        .collect()
+}
+
+/// The `(length, left-aligned code)` pairs of the unary code.
+fn table() -> Vec<u8> {
+    let mut out = vec![];
+    for len in 0..NUM_CODES {
+        out.push(u8::try_from(len + 1).unwrap());
+        out.push(u8::try_from((0xFF_u32 << (8 - len)) & 0xFF).unwrap());
+    }
+    out.extend([0, 0]);
+    out
+}
+
+fn u32_bytes(val: usize) -> [u8; 4] {
+    u32::try_from(val).unwrap().to_le_bytes()
+}
+
+fn rows(width: usize, height: usize, plane: usize) -> Vec<Vec<i32>> {
+    (0..height)
+        .map(|row| {
+            (0..width)
+                .map(|col| {
+                    i32::try_from((row * 37 + col * 11 + plane * 5) % 64 + 16)
+                        .unwrap()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The TRUE image data of the planes.
+fn true_data(planes: &[Vec<Vec<i32>>; 3]) -> Vec<u8> {
+    let seeds = [16, 32, 48];
+    let mut out: Vec<u8> = seeds
+        .iter()
+        .flat_map(|seed| u16::try_from(*seed).unwrap().to_le_bytes())
+        .collect();
+    out.extend([0, 0]);
+    out.extend(table());
+    let encoded: Vec<Vec<u8>> = planes
+        .iter()
+        .zip(seeds)
+        .map(|(plane, seed)| encode_plane(plane, seed))
+        .collect();
+    for plane in &encoded {
+        out.extend(u32_bytes(plane.len()));
+    }
+    for mut plane in encoded {
+        plane.resize(plane.len().next_multiple_of(16), 0);
+        out.extend(plane);
+    }
+    out
+}
+
+/// Properties, as NUL-terminated UTF-16 strings.
+fn properties(entries: &[(&str, &str)]) -> Vec<u8> {
+    let mut chars: Vec<u16> = vec![];
+    let mut offsets = vec![];
+    for (name, value) in entries {
+        for string in [name, value] {
+            offsets.push(chars.len());
+            chars.extend(string.encode_utf16());
+            chars.push(0);
+        }
+    }
+    let mut out = b"SECp".to_vec();
+    for val in [0x0001_0000, entries.len(), 0, 0, chars.len()] {
+        out.extend(u32_bytes(val));
+    }
+    for offset in offsets {
+        out.extend(u32_bytes(offset));
+    }
+    out.extend(chars.iter().flat_map(|c| c.to_le_bytes()));
+    out
+}
+
+#[derive(Debug)]
+struct X3f {
+    model: &'static str,
+    iso: &'static str,
+    image_type: usize,
+    format: usize,
+    width: usize,
+    height: usize,
+    data: Vec<u8>,
+    properties: bool,
+}
+
+impl X3f {
+    fn new(width: usize, height: usize) -> Self {
+        let planes = [0, 1, 2].map(|plane| rows(width, height, plane));
+        Self {
+            model: "SIGMA DP2 Merrill",
+            iso: "200",
+            image_type: 3,
+            format: IMAGE_FORMAT_TRUE,
+            width,
+            height,
+            data: true_data(&planes),
+            properties: true,
+        }
+    }
+
+    fn image(&self) -> Vec<u8> {
+        let mut out = b"SECi".to_vec();
+        for val in [
+            0x0002_0000,
+            self.image_type,
+            self.format,
+            self.width,
+            self.height,
+            0,
+        ] {
+            out.extend(u32_bytes(val));
+        }
+        out.extend(&self.data);
+        out
+    }
+
+    fn build(&self) -> Vec<u8> {
+        // A preview, which comes first.
+        let mut preview = b"SECi".to_vec();
+        for val in [0x0002_0000, 2, 18, 4, 4, 0] {
+            preview.extend(u32_bytes(val));
+        }
+        let mut sections = vec![(b"IMA2", preview)];
+        if self.properties {
+            sections.push((
+                b"PROP",
+                properties(&[
+                    ("CAMMANUF", "SIGMA"),
+                    ("CAMMODEL", self.model),
+                    ("ISO", self.iso),
+                ]),
+            ));
+        }
+        sections.push((b"IMA2", self.image()));
+        let mut out = b"FOVb".to_vec();
+        out.resize(256, 0);
+        let mut entries = vec![];
+        for (section_type, data) in sections {
+            entries.push((out.len(), data.len(), section_type));
+            out.extend(data);
+        }
+        let directory = out.len();
+        out.extend(b"SECd");
+        out.extend(u32_bytes(0x0002_0000));
+        out.extend(u32_bytes(entries.len()));
+        for (offset, len, section_type) in entries {
+            out.extend(u32_bytes(offset));
+            out.extend(u32_bytes(len));
+            out.extend(section_type);
+        }
+        out.extend(u32_bytes(directory));
+        out
+    }
+}
+
+fn parse_cameras() -> Cameras<'static> {
+    xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap()
+}
+
+fn new_demuxer_err(input: &[u8]) -> String {
+    let cameras = parse_cameras();
+    X3fDemuxer::new(input, &cameras, DecodeableCamera::new_unless_unsupported)
+        .unwrap_err()
+}
+
+fn decode(input: &[u8]) -> Result<Vec<Vec<u16>>, String> {
+    let cameras = parse_cameras();
+    let (demuxer, request) = X3fDemuxer::new(
+        input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    )?;
+    let mut output_buf = request.fulfill().unwrap();
+    let mut image = output_buf.get_mut();
+    demuxer.decode(&mut image).map_err(|err| err.to_string())?;
+    let output = image.as_u16().unwrap();
+    Ok((0..output.num_rows().get())
+        .map(|row| output.get_row(RowIndex::new(row)).unwrap().to_vec())
+        .collect())
+}
+
+#[cfg(test)]
+mod data;
+#[cfg(test)]
+mod metadata;
@@ -97,6 +97,7 @@ where
     T: Sized,
 {
     dims: Dimensions2D<core::num::NonZero<usize>>,
+    cpp: core::num::NonZero<usize>,
     extra_row_padding: EltCount,
     row_alignment: Align,
     base_alignment: Align,
@@ -108,6 +109,7 @@ impl<T> NDSliceProcurementRequest<T> {
     pub fn new(dims: Dimensions2D<core::num::NonZero<usize>>) -> Self {
         Self {
             dims,
+            cpp: core::num::NonZero::<usize>::MIN,
             extra_row_padding: EltCount::new(0),
             row_alignment: Align::new(ByteMultiple::new(1)).unwrap(),
             base_alignment: Align::new(ByteMultiple::new(1)).unwrap(),
@@ -115,11 +117,34 @@ impl<T> NDSliceProcurementRequest<T> {
         }
     }
 
+    /// The dimensions of the image, in pixels.
     #[inline]
     pub const fn dims(&self) -> Dimensions2D<core::num::NonZero<usize>> {
         self.dims
     }
 
+    /// The number of (interleaved) components per pixel.
+    #[inline]
+    #[must_use]
+    pub const fn cpp(&self) -> core::num::NonZero<usize> {
+        self.cpp
+    }
+
+    /// The dimensions of the image, in elements, i.e. with each row
+    /// holding `cpp` elements per pixel.
+    #[inline]
+    pub fn element_dims(&self) -> Dimensions2D<core::num::NonZero<usize>> {
+        Dimensions2D::new(
+            RowLength::new(self.dims.row_len().checked_mul(self.cpp).unwrap()),
+            self.dims.row_count(),
+        )
+    }
+
+    #[inline]
+    pub const fn set_cpp(self, cpp: core::num::NonZero<usize>) -> Self {
+        Self { cpp, ..self }
+    }
+
     #[inline]
     pub const fn set_extra_row_padding(
         self,
@@ -155,7 +180,7 @@ impl<T> NDSliceProcurementRequest<T> {
         core::alloc::LayoutError,
     > {
         let row_len = self
-            .dims()
+            .element_dims()
             .row_len()
             .checked_add(*self.extra_row_padding)
             .unwrap();
@@ -199,9 +224,12 @@ impl<T> NDSliceProcurementRequest<T> {
             _ => unreachable!(),
         };
 
-        let mut res =
-            OwnedNDSlice::new(storage, self.dims().row_len(), row_pitch);
-        assert_eq!(res.get_mut().dims(), self.dims());
+        let mut res = OwnedNDSlice::new(
+            storage,
+            self.element_dims().row_len(),
+            row_pitch,
+        );
+        assert_eq!(res.get_mut().dims(), self.element_dims());
         Ok(res)
     }
 }
@@ -251,3 +251,24 @@ fn usable_max_test() {
         .fulfill()
     );
 }
+
+#[test]
+fn cpp_test() {
+    let dims = Dimensions2D::new(
+        RowLength::new(core::num::NonZero::new(3).unwrap()),
+        RowCount::new(core::num::NonZero::new(2).unwrap()),
+    );
+    let request = NDSliceProcurementRequest::<u16>::new(dims)
+        .set_cpp(core::num::NonZero::new(4).unwrap())
+        .set_extra_row_padding(EltCount::new(1));
+    assert_eq!(request.dims(), dims);
+    assert_eq!(request.cpp().get(), 4);
+    let element_dims = request.element_dims();
+    assert_eq!(element_dims.row_len().get(), 3 * 4);
+    assert_eq!(element_dims.row_count(), dims.row_count());
+    let (layout, row_pitch) = request.get_layout().unwrap();
+    assert_eq!(row_pitch.get(), 3 * 4 + 1);
+    assert_eq!(layout.size(), (3 * 4 + 1) * 2 * size_of::<u16>());
+    let mut img = request.fulfill().unwrap();
+    assert_eq!(img.get_mut().dims(), element_dims);
+}
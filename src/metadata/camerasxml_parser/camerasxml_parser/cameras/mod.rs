@@ -51,5 +51,22 @@ impl<'a> core::ops::Deref for Cameras<'a> {
     }
 }
 
+impl<'a> Cameras<'a> {
+    #[inline]
+    #[must_use]
+    pub fn find(
+        &self,
+        make: &str,
+        model: &str,
+        mode: Option<&str>,
+    ) -> Option<&camera::Camera<'a>> {
+        self.iter().find(|camera| {
+            **camera.make == make
+                && **camera.model == model
+                && camera.mode.map(|v| **v) == mode
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests;
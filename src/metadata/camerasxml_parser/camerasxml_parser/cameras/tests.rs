@@ -175,3 +175,33 @@ fn parse_test() {
     }
     assert_eq!(results, expected);
 }
+
+#[test]
+fn find_test() {
+    let input = "<Cameras>
+        <Camera make=\"Make\" model=\"Model\">
+        </Camera>
+        <Camera make=\"Make\" model=\"Model\" mode=\"sRaw1\">
+        </Camera>
+        <Camera make=\"Other Make\" model=\"Model\">
+        </Camera>
+    </Cameras>";
+    let cameras = xmlparser::parse_str::<T<'_>>(input).unwrap();
+    let found = |make, model, mode| {
+        cameras.find(make, model, mode).map(|camera| {
+            (**camera.make, **camera.model, camera.mode.map(|v| **v))
+        })
+    };
+    assert_eq!(found("Make", "Model", None), Some(("Make", "Model", None)));
+    assert_eq!(
+        found("Make", "Model", Some("sRaw1")),
+        Some(("Make", "Model", Some("sRaw1")))
+    );
+    assert_eq!(
+        found("Other Make", "Model", None),
+        Some(("Other Make", "Model", None))
+    );
+    assert_eq!(found("Make", "Model", Some("sRaw2")), None);
+    assert_eq!(found("Make", "Other Model", None), None);
+    assert_eq!(found("make", "Model", None), None);
+}
@@ -0,0 +1,204 @@
+use rawspeed_bitstream_bytestream_decoder::bytestreamer::ByteStreamer;
+use rawspeed_memory_endianness::endianness::Endianness;
+
+/// The type of a box.
+pub type FourCC = [u8; 4];
+
+/// The type of the boxes whose actual type is a UUID.
+pub const UUID: FourCC = *b"uuid";
+
+/// The size and the type of a box.
+const HEADER_SIZE: usize = 8;
+/// The size of the boxes whose size field is this follows the type.
+const LARGE_SIZE: u32 = 1;
+/// The boxes whose size field is this extend to the end of their parent.
+const SIZE_TO_END: u32 = 0;
+/// The 'uuid' boxes have a 16-byte user type after their header.
+const USER_TYPE_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IsoBmffParserError {
+    TruncatedBox(usize),
+    InvalidBoxSize(usize),
+    BoxNotFound(FourCC),
+}
+
+impl core::fmt::Display for IsoBmffParserError {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            IsoBmffParserError::TruncatedBox(offset) => {
+                write!(f, "IsoBmffParserError(TruncatedBox({offset}))")
+            }
+            IsoBmffParserError::InvalidBoxSize(offset) => {
+                write!(f, "IsoBmffParserError(InvalidBoxSize({offset}))")
+            }
+            IsoBmffParserError::BoxNotFound(kind) => {
+                let kind = String::from_utf8_lossy(kind);
+                write!(f, "IsoBmffParserError(BoxNotFound({kind}))")
+            }
+        }
+    }
+}
+
+/// A box, which may contain other boxes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct IsoBox<'a> {
+    kind: FourCC,
+    user_type: Option<[u8; USER_TYPE_SIZE]>,
+    /// Of the box, within the input.
+    offset: usize,
+    header_size: usize,
+    payload: &'a [u8],
+}
+
+impl<'a> IsoBox<'a> {
+    #[inline]
+    #[must_use]
+    pub const fn kind(&self) -> FourCC {
+        self.kind
+    }
+
+    /// The UUID of the 'uuid' boxes.
+    #[inline]
+    #[must_use]
+    pub const fn user_type(&self) -> Option<[u8; USER_TYPE_SIZE]> {
+        self.user_type
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn offset(&self) -> usize {
+        self.offset
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+
+    /// The boxes within the payload, after the first `skip` bytes of it.
+    #[inline]
+    pub fn children(
+        &self,
+        skip: usize,
+    ) -> Result<Vec<IsoBox<'a>>, IsoBmffParserError> {
+        let offset = self.offset + self.header_size;
+        let payload = self
+            .payload
+            .get(skip..)
+            .ok_or(IsoBmffParserError::TruncatedBox(offset))?;
+        parse_boxes(payload, offset + skip)
+    }
+}
+
+fn read_u32(input: &[u8], pos: usize) -> Option<u32> {
+    input
+        .get(pos..pos + size_of::<u32>())
+        .map(|bytes| ByteStreamer::new(bytes, Endianness::Big).read())
+}
+
+fn read_u64(input: &[u8], pos: usize) -> Option<u64> {
+    input
+        .get(pos..pos + size_of::<u64>())
+        .map(|bytes| ByteStreamer::new(bytes, Endianness::Big).read())
+}
+
+/// The box at the start of `input`, and its size.
+fn parse_box(
+    input: &[u8],
+    offset: usize,
+) -> Result<(IsoBox<'_>, usize), IsoBmffParserError> {
+    let truncated = IsoBmffParserError::TruncatedBox(offset);
+    let size_field = read_u32(input, 0).ok_or(truncated)?;
+    let kind: FourCC = input
+        .get(4..HEADER_SIZE)
+        .ok_or(truncated)?
+        .try_into()
+        .unwrap();
+    let (size, mut header_size) = match size_field {
+        SIZE_TO_END => (input.len(), HEADER_SIZE),
+        LARGE_SIZE => {
+            let large_size = read_u64(input, HEADER_SIZE).ok_or(truncated)?;
+            (
+                usize::try_from(large_size).map_err(|_err| truncated)?,
+                HEADER_SIZE + size_of::<u64>(),
+            )
+        }
+        _ => (usize::try_from(size_field).unwrap(), HEADER_SIZE),
+    };
+    let user_type = if kind == UUID {
+        let uuid = input
+            .get(header_size..header_size + USER_TYPE_SIZE)
+            .ok_or(truncated)?
+            .try_into()
+            .unwrap();
+        header_size += USER_TYPE_SIZE;
+        Some(uuid)
+    } else {
+        None
+    };
+    if size < header_size {
+        return Err(IsoBmffParserError::InvalidBoxSize(offset));
+    }
+    let payload = input.get(header_size..size).ok_or(truncated)?;
+    Ok((
+        IsoBox {
+            kind,
+            user_type,
+            offset,
+            header_size,
+            payload,
+        },
+        size,
+    ))
+}
+
+/// The boxes of `input`, which starts at `offset`.
+fn parse_boxes(
+    input: &[u8],
+    offset: usize,
+) -> Result<Vec<IsoBox<'_>>, IsoBmffParserError> {
+    let mut boxes = vec![];
+    let mut pos = 0;
+    while pos < input.len() {
+        let (parsed, size) =
+            parse_box(input.get(pos..).unwrap(), offset + pos)?;
+        boxes.push(parsed);
+        pos += size;
+    }
+    Ok(boxes)
+}
+
+/// The first box of the kind.
+#[inline]
+pub fn find_box<'a>(
+    boxes: &[IsoBox<'a>],
+    kind: FourCC,
+) -> Result<IsoBox<'a>, IsoBmffParserError> {
+    boxes
+        .iter()
+        .find(|b| b.kind == kind)
+        .copied()
+        .ok_or(IsoBmffParserError::BoxNotFound(kind))
+}
+
+/// A parser of the ISO base media file format, which is a sequence of
+/// boxes, each of which may contain more of them.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct IsoBmffParser;
+
+impl IsoBmffParser {
+    /// The top-level boxes of the input.
+    #[inline]
+    pub fn parse(input: &[u8]) -> Result<Vec<IsoBox<'_>>, IsoBmffParserError> {
+        parse_boxes(input, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,118 @@
+use super::{IsoBmffParser, IsoBmffParserError, IsoBox, find_box};
+
+fn be32(val: u32) -> [u8; 4] {
+    let mut bytes = val.to_le_bytes();
+    bytes.reverse();
+    bytes
+}
+
+fn iso_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut out = be32(u32::try_from(payload.len() + 8).unwrap()).to_vec();
+    out.extend(kind);
+    out.extend(payload);
+    out
+}
+
+#[test]
+fn parse_test() {
+    let mut input = iso_box(b"ftyp", b"crx \0\0\0\x01");
+    input.extend(iso_box(b"moov", &iso_box(b"trak", &[1, 2, 3])));
+    let boxes = IsoBmffParser::parse(&input).unwrap();
+    assert_eq!(
+        boxes.iter().map(IsoBox::kind).collect::<Vec<_>>(),
+        [*b"ftyp", *b"moov"]
+    );
+    let ftyp = find_box(&boxes, *b"ftyp").unwrap();
+    assert_eq!((ftyp.offset(), ftyp.payload()), (0, &b"crx \0\0\0\x01"[..]));
+    let moov = find_box(&boxes, *b"moov").unwrap();
+    let trak = moov.children(0).unwrap();
+    assert_eq!(trak.len(), 1);
+    let trak = trak.first().unwrap();
+    assert_eq!((trak.offset(), trak.payload()), (24, &[1, 2, 3][..]));
+}
+
+#[test]
+fn children_skip_test() {
+    let mut payload = vec![0; 4];
+    payload.extend(iso_box(b"CMP1", &[5]));
+    let input = iso_box(b"CRAW", &payload);
+    let craw = *IsoBmffParser::parse(&input).unwrap().first().unwrap();
+    let children = craw.children(4).unwrap();
+    let cmp1 = find_box(&children, *b"CMP1").unwrap();
+    assert_eq!((cmp1.offset(), cmp1.payload()), (12, &[5][..]));
+    assert_eq!(
+        craw.children(0x10),
+        Err(IsoBmffParserError::TruncatedBox(8))
+    );
+}
+
+#[test]
+fn uuid_test() {
+    let uuid = core::array::from_fn(|i| u8::try_from(i).unwrap());
+    let mut payload = uuid.to_vec();
+    payload.extend(iso_box(b"CMT1", &[]));
+    let input = iso_box(b"uuid", &payload);
+    let boxes = IsoBmffParser::parse(&input).unwrap();
+    let uuid_box = find_box(&boxes, *b"uuid").unwrap();
+    assert_eq!(uuid_box.user_type(), Some(uuid));
+    let children = uuid_box.children(0).unwrap();
+    assert_eq!(find_box(&children, *b"CMT1").unwrap().offset(), 24);
+}
+
+#[test]
+fn large_size_test() {
+    let mut input = be32(1).to_vec();
+    input.extend(b"mdat");
+    input.extend([0, 0, 0, 0]);
+    input.extend(be32(0x13));
+    input.extend([7, 8, 9]);
+    input.extend(iso_box(b"free", &[]));
+    let boxes = IsoBmffParser::parse(&input).unwrap();
+    let mdat = find_box(&boxes, *b"mdat").unwrap();
+    assert_eq!(mdat.payload(), &[7, 8, 9]);
+    assert_eq!(find_box(&boxes, *b"free").unwrap().offset(), 0x13);
+}
+
+#[test]
+fn size_to_end_test() {
+    let mut input = iso_box(b"ftyp", &[]);
+    input.extend(be32(0));
+    input.extend(b"mdat");
+    input.extend([1, 2]);
+    let boxes = IsoBmffParser::parse(&input).unwrap();
+    assert_eq!(find_box(&boxes, *b"mdat").unwrap().payload(), &[1, 2]);
+}
+
+#[test]
+fn invalid_test() {
+    let mut too_small = iso_box(b"ftyp", &[]);
+    too_small.extend(be32(4));
+    too_small.extend(b"free");
+    assert_eq!(
+        IsoBmffParser::parse(&too_small),
+        Err(IsoBmffParserError::InvalidBoxSize(8))
+    );
+    let mut truncated = iso_box(b"moov", &[1, 2, 3]);
+    truncated.pop();
+    assert_eq!(
+        IsoBmffParser::parse(&truncated),
+        Err(IsoBmffParserError::TruncatedBox(0))
+    );
+    assert_eq!(
+        IsoBmffParser::parse(&[0, 0, 0]),
+        Err(IsoBmffParserError::TruncatedBox(0))
+    );
+    assert_eq!(
+        IsoBmffParser::parse(&iso_box(b"uuid", &[0; 8])),
+        Err(IsoBmffParserError::TruncatedBox(0))
+    );
+}
+
+#[test]
+fn box_not_found_test() {
+    let input = iso_box(b"ftyp", &[]);
+    let boxes = IsoBmffParser::parse(&input).unwrap();
+    let err = find_box(&boxes, *b"moov").unwrap_err();
+    assert_eq!(err, IsoBmffParserError::BoxNotFound(*b"moov"));
+    assert_eq!(err.to_string(), "IsoBmffParserError(BoxNotFound(moov))");
+}
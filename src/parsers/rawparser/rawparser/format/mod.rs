@@ -0,0 +1,79 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RawFormat {
+    Tiff,
+    Orf,
+    Rw2,
+    Raf,
+    Crw,
+    X3f,
+    Cr3,
+    Mrw,
+}
+
+impl RawFormat {
+    /// All the formats that can be recognized by their signature,
+    /// in the order in which they are probed.
+    pub const PROBED: [Self; 8] = [
+        Self::Raf,
+        Self::Crw,
+        Self::X3f,
+        Self::Cr3,
+        Self::Mrw,
+        Self::Orf,
+        Self::Rw2,
+        Self::Tiff,
+    ];
+
+    #[inline]
+    #[must_use]
+    pub fn matches(self, input: &[u8]) -> bool {
+        match self {
+            RawFormat::Tiff => {
+                input.starts_with(b"II*\0") || input.starts_with(b"MM\0*")
+            }
+            RawFormat::Orf => {
+                input.starts_with(b"IIRO")
+                    || input.starts_with(b"IIRS")
+                    || input.starts_with(b"MMOR")
+            }
+            RawFormat::Rw2 => input.starts_with(b"IIU\0"),
+            RawFormat::Raf => input.starts_with(b"FUJIFILM"),
+            RawFormat::Crw => {
+                input.starts_with(b"II")
+                    && input.get(6..14) == Some(b"HEAPCCDR")
+            }
+            RawFormat::X3f => input.starts_with(b"FOVb"),
+            RawFormat::Cr3 => input.get(4..12) == Some(b"ftypcrx "),
+            RawFormat::Mrw => input.starts_with(b"\0MRM"),
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn sniff(input: &[u8]) -> Option<Self> {
+        Self::PROBED
+            .into_iter()
+            .find(|format| format.matches(input))
+    }
+}
+
+impl core::fmt::Display for RawFormat {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let name = match self {
+            RawFormat::Tiff => "TIFF",
+            RawFormat::Orf => "ORF",
+            RawFormat::Rw2 => "RW2",
+            RawFormat::Raf => "RAF",
+            RawFormat::Crw => "CRW",
+            RawFormat::X3f => "X3F",
+            RawFormat::Cr3 => "CR3",
+            RawFormat::Mrw => "MRW",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,44 @@
+use super::RawFormat;
+
+#[test]
+fn sniff_test() {
+    let expected: Vec<(&[u8], Option<RawFormat>)> = vec![
+        (b"", None),
+        (b"I", None),
+        (b"II", None),
+        (b"\0\0\0\0\0\0\0\0", None),
+        (b"II*\0\x08\0\0\0", Some(RawFormat::Tiff)),
+        (b"MM\0*\0\0\0\x08", Some(RawFormat::Tiff)),
+        (b"II\0*\0\0\0\x08", None),
+        (b"MM*\0\x08\0\0\0", None),
+        (b"IIRO\x08\0\0\0", Some(RawFormat::Orf)),
+        (b"IIRS\x08\0\0\0", Some(RawFormat::Orf)),
+        (b"MMOR\0\0\0\x08", Some(RawFormat::Orf)),
+        (b"IIU\0\x08\0\0\0", Some(RawFormat::Rw2)),
+        (b"FUJIFILMCCD-RAW 0201", Some(RawFormat::Raf)),
+        (b"FUJIFIL", None),
+        (b"II\x1a\0\0\0HEAPCCDR", Some(RawFormat::Crw)),
+        (b"MM\0\0\0\x1aHEAPCCDR", None),
+        (b"II\x1a\0\0\0HEAPCCD", None),
+        (b"FOVb\0\0\x04\0", Some(RawFormat::X3f)),
+        (b"FOVa", None),
+        (b"\0\0\0\x18ftypcrx \0\0\0\x01", Some(RawFormat::Cr3)),
+        (b"\0\0\0\x18ftypisom", None),
+        (b"\0\0\0\x18ftypcrx", None),
+        (b"\0MRM\0\0\0\x08", Some(RawFormat::Mrw)),
+        (b"\0MRW", None),
+    ];
+    for (input, format) in expected {
+        assert_eq!(RawFormat::sniff(input), format);
+    }
+}
+
+#[test]
+fn display_test() {
+    let names: Vec<String> =
+        RawFormat::PROBED.iter().map(ToString::to_string).collect();
+    assert_eq!(
+        names,
+        vec!["RAF", "CRW", "X3F", "CR3", "MRW", "ORF", "RW2", "TIFF"]
+    );
+}
@@ -1,10 +1,34 @@
+use rawspeed_demuxers_arw::{arw_demuxer::ArwDemuxer, srf_demuxer::SrfDemuxer};
+use rawspeed_demuxers_common::tiff_utils::get_root_string;
+use rawspeed_demuxers_cr2::cr2_demuxer::Cr2Demuxer;
+use rawspeed_demuxers_cr3::cr3_demuxer::Cr3Demuxer;
+use rawspeed_demuxers_crw::crw_demuxer::CrwDemuxer;
+use rawspeed_demuxers_dng::dng_demuxer::DngDemuxer;
+use rawspeed_demuxers_hasselblad::hasselblad_demuxer::HasselbladDemuxer;
+use rawspeed_demuxers_iiq::iiq_demuxer::IiqDemuxer;
+use rawspeed_demuxers_kodak::kodak_demuxer::KodakDemuxer;
+use rawspeed_demuxers_mos::mos_demuxer::MosDemuxer;
+use rawspeed_demuxers_mrw::mrw_demuxer::MrwDemuxer;
+use rawspeed_demuxers_nef::nef_demuxer::NefDemuxer;
+use rawspeed_demuxers_orf::orf_demuxer::OrfDemuxer;
 use rawspeed_demuxers_packed::naked_demuxer::NakedDemuxer;
-use rawspeed_demuxers_rawdemuxer::rawdemuxer::RawDemuxer;
-use rawspeed_memory_nd_slice_procurement::ndsliceprocurement::NDSliceProcurementRequest;
+use rawspeed_demuxers_pef::pef_demuxer::PefDemuxer;
+use rawspeed_demuxers_raf::raf_demuxer::RafDemuxer;
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::{
+    ProcurementRequest, RawDemuxer,
+};
+use rawspeed_demuxers_rw2::rw2_demuxer::Rw2Demuxer;
+use rawspeed_demuxers_srw::srw_demuxer::SrwDemuxer;
+use rawspeed_demuxers_x3f::x3f_demuxer::X3fDemuxer;
 use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
 use rawspeed_metadata_camerasxml_parser::camerasxml_parser::{
     Cameras, Supported,
 };
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+
+pub mod format;
+
+pub use format::RawFormat;
 
 #[derive(Debug, PartialEq)]
 #[non_exhaustive]
@@ -28,25 +52,261 @@ impl core::fmt::Display for RawParserError {
 #[must_use]
 pub struct RawParser;
 
+type Decoder<'a> = (Box<dyn RawDemuxer + 'a>, ProcurementRequest);
+
 impl<'a> RawParser {
+    fn get_tiff_decoder<F>(
+        input: &'a [u8],
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<Decoder<'a>, RawParserError>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let root = TiffParser::parse(input)
+            .map_err(|err| RawParserError::DecoderError(err.to_string()))?;
+        if DngDemuxer::is_dng(&root) {
+            let (d, r) =
+                DngDemuxer::new(&root, cameras, check_camera_support_fn)
+                    .map_err(RawParserError::DecoderError)?;
+            return Ok((Box::new(d), r));
+        }
+        if IiqDemuxer::is_iiq(&root) {
+            let (d, r) =
+                IiqDemuxer::new(&root, cameras, check_camera_support_fn)
+                    .map_err(RawParserError::DecoderError)?;
+            return Ok((Box::new(d), r));
+        }
+        if MosDemuxer::is_mos(&root) {
+            let (d, r) =
+                MosDemuxer::new(&root, cameras, check_camera_support_fn)
+                    .map_err(RawParserError::DecoderError)?;
+            return Ok((Box::new(d), r));
+        }
+        let make = get_root_string(&root, TiffTag::MAKE).unwrap_or("");
+        if make == "Canon" {
+            let (d, r) =
+                Cr2Demuxer::new(&root, cameras, check_camera_support_fn)
+                    .map_err(RawParserError::DecoderError)?;
+            return Ok((Box::new(d), r));
+        }
+        if make.starts_with("NIKON") {
+            let (d, r) =
+                NefDemuxer::new(&root, cameras, check_camera_support_fn)
+                    .map_err(RawParserError::DecoderError)?;
+            return Ok((Box::new(d), r));
+        }
+        if make == "SONY" {
+            if SrfDemuxer::is_srf(&root) {
+                let (d, r) =
+                    SrfDemuxer::new(&root, cameras, check_camera_support_fn)
+                        .map_err(RawParserError::DecoderError)?;
+                return Ok((Box::new(d), r));
+            }
+            let (d, r) =
+                ArwDemuxer::new(&root, cameras, check_camera_support_fn)
+                    .map_err(RawParserError::DecoderError)?;
+            return Ok((Box::new(d), r));
+        }
+        if make.starts_with("PENTAX") || make == "RICOH IMAGING COMPANY, LTD." {
+            let (d, r) =
+                PefDemuxer::new(&root, cameras, check_camera_support_fn)
+                    .map_err(RawParserError::DecoderError)?;
+            return Ok((Box::new(d), r));
+        }
+        if make == "SAMSUNG" {
+            let (d, r) =
+                SrwDemuxer::new(&root, cameras, check_camera_support_fn)
+                    .map_err(RawParserError::DecoderError)?;
+            return Ok((Box::new(d), r));
+        }
+        if make == "Hasselblad" {
+            let (d, r) =
+                HasselbladDemuxer::new(&root, cameras, check_camera_support_fn)
+                    .map_err(RawParserError::DecoderError)?;
+            return Ok((Box::new(d), r));
+        }
+        if make == "Kodak" || make == "EASTMAN KODAK COMPANY" {
+            let (d, r) =
+                KodakDemuxer::new(&root, cameras, check_camera_support_fn)
+                    .map_err(RawParserError::DecoderError)?;
+            return Ok((Box::new(d), r));
+        }
+        Err(RawParserError::DecoderError(format!(
+            "{} input is recognized, but is not supported",
+            RawFormat::Tiff
+        )))
+    }
+
+    fn get_orf_decoder<F>(
+        input: &'a [u8],
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<Decoder<'a>, RawParserError>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let root = TiffParser::parse(input)
+            .map_err(|err| RawParserError::DecoderError(err.to_string()))?;
+        let (d, r) = OrfDemuxer::new(&root, cameras, check_camera_support_fn)
+            .map_err(RawParserError::DecoderError)?;
+        Ok((Box::new(d), r))
+    }
+
+    fn get_raf_decoder<F>(
+        input: &'a [u8],
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<Decoder<'a>, RawParserError>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let (d, r) = RafDemuxer::new(input, cameras, check_camera_support_fn)
+            .map_err(RawParserError::DecoderError)?;
+        Ok((Box::new(d), r))
+    }
+
+    fn get_cr3_decoder<F>(
+        input: &'a [u8],
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<Decoder<'a>, RawParserError>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let (d, r) = Cr3Demuxer::new(input, cameras, check_camera_support_fn)
+            .map_err(RawParserError::DecoderError)?;
+        Ok((Box::new(d), r))
+    }
+
+    fn get_crw_decoder<F>(
+        input: &'a [u8],
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<Decoder<'a>, RawParserError>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let (d, r) = CrwDemuxer::new(input, cameras, check_camera_support_fn)
+            .map_err(RawParserError::DecoderError)?;
+        Ok((Box::new(d), r))
+    }
+
+    fn get_mrw_decoder<F>(
+        input: &'a [u8],
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<Decoder<'a>, RawParserError>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let (d, r) = MrwDemuxer::new(input, cameras, check_camera_support_fn)
+            .map_err(RawParserError::DecoderError)?;
+        Ok((Box::new(d), r))
+    }
+
+    fn get_x3f_decoder<F>(
+        input: &'a [u8],
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<Decoder<'a>, RawParserError>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let (d, r) = X3fDemuxer::new(input, cameras, check_camera_support_fn)
+            .map_err(RawParserError::DecoderError)?;
+        Ok((Box::new(d), r))
+    }
+
+    fn get_rw2_decoder<F>(
+        input: &'a [u8],
+        cameras: &'a Cameras<'a>,
+        check_camera_support_fn: F,
+    ) -> Result<Decoder<'a>, RawParserError>
+    where
+        F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
+    {
+        let root = TiffParser::parse(input)
+            .map_err(|err| RawParserError::DecoderError(err.to_string()))?;
+        let (d, r) = Rw2Demuxer::new(&root, cameras, check_camera_support_fn)
+            .map_err(RawParserError::DecoderError)?;
+        Ok((Box::new(d), r))
+    }
+
     #[inline(never)]
     pub fn get_decoder<F>(
         input: &'a [u8],
         cameras: &'a Cameras<'a>,
         check_camera_support_fn: F,
-    ) -> Result<
-        (Box<dyn RawDemuxer + 'a>, NDSliceProcurementRequest<u16>),
-        RawParserError,
-    >
+    ) -> Result<Decoder<'a>, RawParserError>
     where
         F: FnOnce(Supported) -> Result<DecodeableCamera, String>,
     {
+        if input.is_empty() {
+            return Err(RawParserError::DecoderError(
+                "Input buffer must be non-empty".to_owned(),
+            ));
+        }
+        if let Some(format) = RawFormat::sniff(input) {
+            return match format {
+                RawFormat::Tiff => Self::get_tiff_decoder(
+                    input,
+                    cameras,
+                    check_camera_support_fn,
+                ),
+                RawFormat::Orf => Self::get_orf_decoder(
+                    input,
+                    cameras,
+                    check_camera_support_fn,
+                ),
+                RawFormat::Raf => Self::get_raf_decoder(
+                    input,
+                    cameras,
+                    check_camera_support_fn,
+                ),
+                RawFormat::Cr3 => Self::get_cr3_decoder(
+                    input,
+                    cameras,
+                    check_camera_support_fn,
+                ),
+                RawFormat::Crw => Self::get_crw_decoder(
+                    input,
+                    cameras,
+                    check_camera_support_fn,
+                ),
+                RawFormat::Mrw => Self::get_mrw_decoder(
+                    input,
+                    cameras,
+                    check_camera_support_fn,
+                ),
+                RawFormat::X3f => Self::get_x3f_decoder(
+                    input,
+                    cameras,
+                    check_camera_support_fn,
+                ),
+                RawFormat::Rw2 => Self::get_rw2_decoder(
+                    input,
+                    cameras,
+                    check_camera_support_fn,
+                ),
+            };
+        }
         match NakedDemuxer::new(input, cameras, check_camera_support_fn) {
             Ok((d, r)) => {
                 let d = Box::new(d);
                 Ok((d, r))
             }
-            Err(s) => Err(RawParserError::DecoderError(s)),
+            Err(s) => {
+                let probed = RawFormat::PROBED
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                Err(RawParserError::DecoderError(format!(
+                    "Unrecognized input format (probed: {probed}), \
+                     and naked fallback failed: {s}"
+                )))
+            }
         }
     }
 }
@@ -1,2 +1,4 @@
 #[cfg(test)]
 mod packed;
+#[cfg(test)]
+mod sniffing;
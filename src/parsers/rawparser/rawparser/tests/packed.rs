@@ -28,7 +28,11 @@ fn empty_buffer_test() {
     assert_eq!(
         res.err(),
         Some(RawParserError::DecoderError(
-            "No known cameras match the given input size".to_owned()
+            "Unrecognized input format \
+             (probed: RAF, CRW, X3F, CR3, MRW, ORF, RW2, TIFF), \
+             and naked fallback failed: \
+             No known cameras match the given input size"
+                .to_owned()
         ))
     );
 }
@@ -55,8 +59,9 @@ fn unpack_8bytes_into_2_rows_of_4_elts_at_8bpc_test() {
     )
     .unwrap();
     let mut output_buf = out_buf_request.fulfill().unwrap();
-    let mut output = output_buf.get_mut();
-    res.decode(&mut output).unwrap();
+    let mut image = output_buf.get_mut();
+    res.decode(&mut image).unwrap();
+    let output = image.as_u16().unwrap();
     for row in 1..=2 {
         for col in 1..=4 {
             assert_eq!(
@@ -0,0 +1,455 @@
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
+use rawspeed_metadata_camerasxml_parser::camerasxml_parser::Cameras;
+use rawspeed_metadata_xmlparser::xmlparser;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffParser, TiffTag};
+use rawspeed_utils_tiffbuilder::tiffbuilder::{TiffBuilder, Value, tag};
+
+use crate::rawparser::{RawParser, RawParserError};
+
+const CAMERAS: &str = "
+    <Cameras>
+        <Camera make=\"Make\" model=\"Model\">
+            <Hints>
+                <Hint name=\"filesize\" value=\"8\"/>
+                <Hint name=\"full_width\" value=\"4\"/>
+                <Hint name=\"full_height\" value=\"2\"/>
+                <Hint name=\"order\" value=\"plain\"/>
+            </Hints>
+        </Camera>
+    </Cameras>";
+
+#[test]
+fn empty_input_test() {
+    let cameras = xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap();
+    let res = RawParser::get_decoder(
+        &[],
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    );
+    assert_eq!(
+        res.err(),
+        Some(RawParserError::DecoderError(
+            "Input buffer must be non-empty".to_owned()
+        ))
+    );
+}
+
+#[test]
+fn signature_takes_precedence_over_filesize_test() {
+    let cameras = xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap();
+    let input = b"FOVb\0\0\0\0";
+    let res = RawParser::get_decoder(
+        input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    );
+    assert_eq!(
+        res.err(),
+        Some(RawParserError::DecoderError(
+            "The X3F directory is invalid".to_owned()
+        ))
+    );
+}
+
+#[test]
+fn non_dng_tiff_is_not_supported_test() {
+    let cameras = xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap();
+    let input = b"II*\0\x08\0\0\0\0\0\0\0\0\0";
+    let res = RawParser::get_decoder(
+        input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    );
+    assert_eq!(
+        res.err(),
+        Some(RawParserError::DecoderError(
+            "TIFF input is recognized, but is not supported".to_owned()
+        ))
+    );
+}
+
+#[test]
+fn malformed_tiff_test() {
+    let cameras = xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap();
+    let input = b"MM\0*\0\0\0\xFF";
+    let res = RawParser::get_decoder(
+        input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    );
+    assert_eq!(
+        res.err(),
+        Some(RawParserError::DecoderError(
+            "TiffParserError(OffsetOutOfBounds(255))".to_owned()
+        ))
+    );
+}
+
+#[test]
+fn canon_tiff_is_cr2_test() {
+    let cameras = xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap();
+    let mut builder = TiffBuilder::new(Endianness::Little);
+    builder
+        .ifd(0)
+        .push(tag(TiffTag::MAKE, Value::Ascii("Canon")));
+    let input = builder.build();
+    let res = RawParser::get_decoder(
+        &input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    );
+    assert_eq!(
+        res.err(),
+        Some(RawParserError::DecoderError(
+            "No raw image found in CR2".to_owned()
+        ))
+    );
+}
+
+#[test]
+fn nikon_tiff_is_nef_test() {
+    let cameras = xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap();
+    let mut builder = TiffBuilder::new(Endianness::Little);
+    builder
+        .ifd(0)
+        .push(tag(TiffTag::MAKE, Value::Ascii("NIKON CORPORATION")));
+    let input = builder.build();
+    let res = RawParser::get_decoder(
+        &input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    );
+    assert_eq!(
+        res.err(),
+        Some(RawParserError::DecoderError(
+            "No raw image found in NEF".to_owned()
+        ))
+    );
+}
+
+#[test]
+fn sony_tiff_is_arw_test() {
+    let cameras = xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap();
+    let mut builder = TiffBuilder::new(Endianness::Little);
+    builder
+        .ifd(0)
+        .push(tag(TiffTag::MAKE, Value::Ascii("SONY")));
+    let input = builder.build();
+    let res = RawParser::get_decoder(
+        &input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    );
+    assert_eq!(
+        res.err(),
+        Some(RawParserError::DecoderError(
+            "No raw image found in ARW".to_owned()
+        ))
+    );
+}
+
+#[test]
+fn sony_tiff_without_strips_is_srf_test() {
+    let cameras = xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap();
+    let mut builder = TiffBuilder::new(Endianness::Big);
+    builder.ifd(0).extend([
+        tag(TiffTag::MAKE, Value::Ascii("SONY")),
+        tag(TiffTag::IMAGE_WIDTH, Value::Long(vec![4])),
+        tag(TiffTag::IMAGE_LENGTH, Value::Long(vec![2])),
+    ]);
+    let input = builder.build();
+    let res = RawParser::get_decoder(
+        &input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    );
+    assert_eq!(
+        res.err(),
+        Some(RawParserError::DecoderError(
+            "Unknown camera: SONY ".to_owned()
+        ))
+    );
+}
+
+#[test]
+fn pentax_tiff_is_pef_test() {
+    let cameras = xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap();
+    for make in ["PENTAX Corporation", "RICOH IMAGING COMPANY, LTD."] {
+        let mut builder = TiffBuilder::new(Endianness::Little);
+        builder.ifd(0).push(tag(TiffTag::MAKE, Value::Ascii(make)));
+        let input = builder.build();
+        let res = RawParser::get_decoder(
+            &input,
+            &cameras,
+            DecodeableCamera::new_unless_unsupported,
+        );
+        assert_eq!(
+            res.err(),
+            Some(RawParserError::DecoderError(
+                "No raw image found in PEF".to_owned()
+            ))
+        );
+    }
+}
+
+#[test]
+fn samsung_tiff_is_srw_test() {
+    let cameras = xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap();
+    let mut builder = TiffBuilder::new(Endianness::Little);
+    builder
+        .ifd(0)
+        .push(tag(TiffTag::MAKE, Value::Ascii("SAMSUNG")));
+    let input = builder.build();
+    let res = RawParser::get_decoder(
+        &input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    );
+    assert_eq!(
+        res.err(),
+        Some(RawParserError::DecoderError(
+            "No raw image found in SRW".to_owned()
+        ))
+    );
+}
+
+#[test]
+fn hasselblad_tiff_is_3fr_test() {
+    let cameras = xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap();
+    let mut builder = TiffBuilder::new(Endianness::Little);
+    builder
+        .ifd(0)
+        .push(tag(TiffTag::MAKE, Value::Ascii("Hasselblad")));
+    let input = builder.build();
+    let res = RawParser::get_decoder(
+        &input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    );
+    assert_eq!(
+        res.err(),
+        Some(RawParserError::DecoderError(
+            "No raw image found in 3FR".to_owned()
+        ))
+    );
+}
+
+#[test]
+fn kodak_tiff_is_dcr_or_kdc_test() {
+    let cameras = xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap();
+    for make in ["Kodak", "EASTMAN KODAK COMPANY"] {
+        let mut builder = TiffBuilder::new(Endianness::Little);
+        builder.ifd(0).push(tag(TiffTag::MAKE, Value::Ascii(make)));
+        let input = builder.build();
+        let res = RawParser::get_decoder(
+            &input,
+            &cameras,
+            DecodeableCamera::new_unless_unsupported,
+        );
+        assert_eq!(
+            res.err(),
+            Some(RawParserError::DecoderError(
+                "No raw image found in DCR or KDC".to_owned()
+            ))
+        );
+    }
+}
+
+#[test]
+fn phase_one_tiff_is_iiq_test() {
+    let cameras = xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap();
+    let mut builder = TiffBuilder::new(Endianness::Little);
+    builder
+        .ifd(0)
+        .push(tag(TiffTag::MAKE, Value::Ascii("Phase One A/S")));
+    // The IIQ header, with an empty directory, follows the TIFF one.
+    let mut prefix = b"II*\0".to_vec();
+    prefix.extend(28_u32.to_le_bytes());
+    prefix.extend(b"IIIIRAW ");
+    prefix.extend(12_u32.to_le_bytes());
+    prefix.extend([0; 8]);
+    let input = builder.build_after(&prefix);
+    let res = RawParser::get_decoder(
+        &input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    );
+    assert_eq!(
+        res.err(),
+        Some(RawParserError::DecoderError(
+            "Unsupported IIQ raw format: 0".to_owned()
+        ))
+    );
+    // Without the IIQ header, it is a plain TIFF.
+    let tiff = builder.build();
+    let tiff_res = RawParser::get_decoder(
+        &tiff,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    );
+    assert_eq!(
+        tiff_res.err(),
+        Some(RawParserError::DecoderError(
+            "TIFF input is recognized, but is not supported".to_owned()
+        ))
+    );
+}
+
+#[test]
+fn leaf_tiff_is_mos_test() {
+    let cameras = xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap();
+    let mut builder = TiffBuilder::new(Endianness::Big);
+    builder
+        .ifd(0)
+        .push(tag(TiffTag::MAKE, Value::Ascii("Leaf")));
+    let input = builder.build();
+    let res = RawParser::get_decoder(
+        &input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    );
+    assert_eq!(
+        res.err(),
+        Some(RawParserError::DecoderError(
+            "No raw image found in MOS".to_owned()
+        ))
+    );
+}
+
+#[test]
+fn orf_signature_is_orf_test() {
+    let cameras = xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap();
+    let mut builder = TiffBuilder::new(Endianness::Little);
+    builder.magic = TiffParser::MAGIC_ORF;
+    builder
+        .ifd(0)
+        .push(tag(TiffTag::MAKE, Value::Ascii("OLYMPUS IMAGING CORP.")));
+    let input = builder.build();
+    assert_eq!(input.get(..4), Some(b"IIRO".as_slice()));
+    let res = RawParser::get_decoder(
+        &input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    );
+    assert_eq!(
+        res.err(),
+        Some(RawParserError::DecoderError(
+            "No raw image found in ORF".to_owned()
+        ))
+    );
+}
+
+#[test]
+fn rw2_signature_is_rw2_test() {
+    let cameras = xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap();
+    let mut builder = TiffBuilder::new(Endianness::Little);
+    builder.magic = TiffParser::MAGIC_RW2;
+    builder
+        .ifd(0)
+        .push(tag(TiffTag::MAKE, Value::Ascii("Panasonic")));
+    let input = builder.build();
+    assert_eq!(input.get(..4), Some(b"IIU\0".as_slice()));
+    let res = RawParser::get_decoder(
+        &input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    );
+    assert_eq!(
+        res.err(),
+        Some(RawParserError::DecoderError(
+            "No raw image found in RW2".to_owned()
+        ))
+    );
+}
+
+#[test]
+fn rwl_signature_is_rw2_test() {
+    let cameras = xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap();
+    let mut builder = TiffBuilder::new(Endianness::Little);
+    builder.magic = TiffParser::MAGIC_RW2;
+    builder
+        .ifd(0)
+        .push(tag(TiffTag::MAKE, Value::Ascii("LEICA")));
+    let input = builder.build();
+    let res = RawParser::get_decoder(
+        &input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    );
+    assert_eq!(
+        res.err(),
+        Some(RawParserError::DecoderError(
+            "No raw image found in RW2".to_owned()
+        ))
+    );
+}
+
+#[test]
+fn raf_signature_is_raf_test() {
+    let cameras = xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap();
+    let mut input = b"FUJIFILMCCD-RAW 0201FF000000".to_vec();
+    input.resize(0x54, 0);
+    let res = RawParser::get_decoder(
+        &input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    );
+    assert_eq!(
+        res.err(),
+        Some(RawParserError::DecoderError(
+            "The RAF header is truncated".to_owned()
+        ))
+    );
+}
+
+#[test]
+fn cr3_signature_is_cr3_test() {
+    let cameras = xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap();
+    let input = b"\0\0\0\x10ftypcrx \0\0\0\x01";
+    let res = RawParser::get_decoder(
+        input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    );
+    assert_eq!(
+        res.err(),
+        Some(RawParserError::DecoderError(
+            "IsoBmffParserError(BoxNotFound(moov))".to_owned()
+        ))
+    );
+}
+
+#[test]
+fn crw_signature_is_crw_test() {
+    let cameras = xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap();
+    let input = b"II\x1a\0\0\0HEAPCCDR";
+    let res = RawParser::get_decoder(
+        input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    );
+    assert_eq!(
+        res.err(),
+        Some(RawParserError::DecoderError(
+            "The CRW header is truncated".to_owned()
+        ))
+    );
+}
+
+#[test]
+fn mrw_signature_is_mrw_test() {
+    let cameras = xmlparser::parse_str::<Cameras<'_>>(CAMERAS).unwrap();
+    let input = b"\0MRM\0\0\0\x08\0PRD\0\0\0\x17";
+    let res = RawParser::get_decoder(
+        input,
+        &cameras,
+        DecodeableCamera::new_unless_unsupported,
+    );
+    assert_eq!(
+        res.err(),
+        Some(RawParserError::DecoderError(
+            "The MRW blocks are truncated".to_owned()
+        ))
+    );
+}
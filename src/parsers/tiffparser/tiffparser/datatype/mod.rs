@@ -0,0 +1,87 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TiffDataType {
+    Byte,
+    Ascii,
+    Short,
+    Long,
+    Rational,
+    SByte,
+    Undefined,
+    SShort,
+    SLong,
+    SRational,
+    Float,
+    Double,
+    IFD,
+}
+
+impl TiffDataType {
+    #[inline]
+    #[must_use]
+    pub const fn byte_size(self) -> usize {
+        match self {
+            TiffDataType::Byte
+            | TiffDataType::Ascii
+            | TiffDataType::SByte
+            | TiffDataType::Undefined => 1,
+            TiffDataType::Short | TiffDataType::SShort => 2,
+            TiffDataType::Long
+            | TiffDataType::SLong
+            | TiffDataType::Float
+            | TiffDataType::IFD => 4,
+            TiffDataType::Rational
+            | TiffDataType::SRational
+            | TiffDataType::Double => 8,
+        }
+    }
+}
+
+impl core::fmt::Display for TiffDataType {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let name = match self {
+            TiffDataType::Byte => "Byte",
+            TiffDataType::Ascii => "Ascii",
+            TiffDataType::Short => "Short",
+            TiffDataType::Long => "Long",
+            TiffDataType::Rational => "Rational",
+            TiffDataType::SByte => "SByte",
+            TiffDataType::Undefined => "Undefined",
+            TiffDataType::SShort => "SShort",
+            TiffDataType::SLong => "SLong",
+            TiffDataType::SRational => "SRational",
+            TiffDataType::Float => "Float",
+            TiffDataType::Double => "Double",
+            TiffDataType::IFD => "IFD",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl TryFrom<u16> for TiffDataType {
+    type Error = u16;
+
+    #[inline]
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        Ok(match value {
+            1 => TiffDataType::Byte,
+            2 => TiffDataType::Ascii,
+            3 => TiffDataType::Short,
+            4 => TiffDataType::Long,
+            5 => TiffDataType::Rational,
+            6 => TiffDataType::SByte,
+            7 => TiffDataType::Undefined,
+            8 => TiffDataType::SShort,
+            9 => TiffDataType::SLong,
+            10 => TiffDataType::SRational,
+            11 => TiffDataType::Float,
+            12 => TiffDataType::Double,
+            13 => TiffDataType::IFD,
+            _ => return Err(value),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,30 @@
+use super::TiffDataType;
+
+#[test]
+fn roundtrip_test() {
+    let expected: Vec<(u16, Result<TiffDataType, u16>, usize)> = vec![
+        (0, Err(0), 0),
+        (1, Ok(TiffDataType::Byte), 1),
+        (2, Ok(TiffDataType::Ascii), 1),
+        (3, Ok(TiffDataType::Short), 2),
+        (4, Ok(TiffDataType::Long), 4),
+        (5, Ok(TiffDataType::Rational), 8),
+        (6, Ok(TiffDataType::SByte), 1),
+        (7, Ok(TiffDataType::Undefined), 1),
+        (8, Ok(TiffDataType::SShort), 2),
+        (9, Ok(TiffDataType::SLong), 4),
+        (10, Ok(TiffDataType::SRational), 8),
+        (11, Ok(TiffDataType::Float), 4),
+        (12, Ok(TiffDataType::Double), 8),
+        (13, Ok(TiffDataType::IFD), 4),
+        (14, Err(14), 0),
+        (u16::MAX, Err(u16::MAX), 0),
+    ];
+    for (raw, datatype, size) in expected {
+        let res = TiffDataType::try_from(raw);
+        assert_eq!(res, datatype);
+        if let Ok(parsed) = res {
+            assert_eq!(parsed.byte_size(), size);
+        }
+    }
+}
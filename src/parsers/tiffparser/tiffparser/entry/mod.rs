@@ -0,0 +1,308 @@
+use rawspeed_bitstream_bytestream_decoder::bytestreamer::ByteStreamer;
+use rawspeed_memory_endianness::endianness::Endianness;
+
+use super::{ParseContext, TiffDataType, TiffParserError, TiffTag};
+
+const ENTRY_BYTE_SIZE: usize = 12;
+const INLINE_VALUE_BYTE_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+#[must_use]
+pub struct Rational<T> {
+    pub num: T,
+    pub den: T,
+}
+
+impl<T> Rational<T> {
+    #[inline]
+    pub const fn new(num: T, den: T) -> Self {
+        Self { num, den }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+#[must_use]
+pub struct TiffEntry<'a> {
+    tag: TiffTag,
+    datatype: TiffDataType,
+    count: u32,
+    data: &'a [u8],
+    data_offset: usize,
+    endianness: Endianness,
+}
+
+impl<'a> TiffEntry<'a> {
+    pub(super) const BYTE_SIZE: usize = ENTRY_BYTE_SIZE;
+
+    pub(super) fn parse(
+        ctx: &ParseContext<'a>,
+        offset: usize,
+    ) -> Result<Option<Self>, TiffParserError> {
+        let tag = TiffTag::new(ctx.read_u16(offset)?);
+        let datatype = ctx.read_u16(offset + 2)?;
+        let count = ctx.read_u32(offset + 4)?;
+        let value_offset = offset + 8;
+        let _ = ctx.get_bytes(value_offset, INLINE_VALUE_BYTE_SIZE)?;
+
+        // Entries of unknown type must be skipped over, as per the spec.
+        let Ok(datatype) = TiffDataType::try_from(datatype) else {
+            return Ok(None);
+        };
+
+        let byte_size = usize::try_from(count)
+            .ok()
+            .and_then(|count| count.checked_mul(datatype.byte_size()))
+            .ok_or(TiffParserError::OffsetOutOfBounds(value_offset))?;
+
+        let data_offset = if byte_size > INLINE_VALUE_BYTE_SIZE {
+            ctx.read_u32(value_offset)?.try_into().unwrap()
+        } else {
+            value_offset
+        };
+        let data = ctx.get_bytes(data_offset, byte_size)?;
+
+        Ok(Some(Self {
+            tag,
+            datatype,
+            count,
+            data,
+            data_offset,
+            endianness: ctx.endianness,
+        }))
+    }
+
+    #[inline]
+    pub const fn tag(&self) -> TiffTag {
+        self.tag
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn datatype(&self) -> TiffDataType {
+        self.datatype
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn count(&self) -> u32 {
+        self.count
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn data_offset(&self) -> usize {
+        self.data_offset
+    }
+
+    fn elt_bytes(&self, index: usize) -> Result<&'a [u8], TiffParserError> {
+        let size = self.datatype.byte_size();
+        index
+            .checked_mul(size)
+            .and_then(|begin| self.data.get(begin..)?.get(..size))
+            .ok_or(TiffParserError::IndexOutOfBounds(self.tag, index))
+    }
+
+    const fn mismatch<T>(&self) -> Result<T, TiffParserError> {
+        Err(TiffParserError::UnexpectedDataType(self.tag, self.datatype))
+    }
+
+    fn streamer(
+        &self,
+        index: usize,
+    ) -> Result<ByteStreamer<'a>, TiffParserError> {
+        Ok(ByteStreamer::new(self.elt_bytes(index)?, self.endianness))
+    }
+
+    #[inline]
+    #[expect(clippy::wildcard_enum_match_arm)]
+    pub fn get_u8(&self, index: usize) -> Result<u8, TiffParserError> {
+        Ok(match self.datatype {
+            TiffDataType::Byte
+            | TiffDataType::Ascii
+            | TiffDataType::Undefined => self.streamer(index)?.read::<u8>(),
+            _ => return self.mismatch(),
+        })
+    }
+
+    #[inline]
+    #[expect(clippy::wildcard_enum_match_arm)]
+    pub fn get_u16(&self, index: usize) -> Result<u16, TiffParserError> {
+        Ok(match self.datatype {
+            TiffDataType::Byte | TiffDataType::Undefined => {
+                self.get_u8(index)?.into()
+            }
+            TiffDataType::Short => self.streamer(index)?.read::<u16>(),
+            _ => return self.mismatch(),
+        })
+    }
+
+    #[inline]
+    #[expect(clippy::wildcard_enum_match_arm)]
+    pub fn get_u32(&self, index: usize) -> Result<u32, TiffParserError> {
+        Ok(match self.datatype {
+            TiffDataType::Byte
+            | TiffDataType::Undefined
+            | TiffDataType::Short => self.get_u16(index)?.into(),
+            TiffDataType::Long | TiffDataType::IFD => {
+                self.streamer(index)?.read::<u32>()
+            }
+            _ => return self.mismatch(),
+        })
+    }
+
+    #[inline]
+    #[expect(clippy::wildcard_enum_match_arm)]
+    pub fn get_i32(&self, index: usize) -> Result<i32, TiffParserError> {
+        Ok(match self.datatype {
+            TiffDataType::Byte | TiffDataType::Short => {
+                self.get_u16(index)?.into()
+            }
+            TiffDataType::SByte => self.streamer(index)?.read::<i8>().into(),
+            TiffDataType::SShort => self.streamer(index)?.read::<i16>().into(),
+            TiffDataType::SLong => self.streamer(index)?.read::<i32>(),
+            _ => return self.mismatch(),
+        })
+    }
+
+    #[inline]
+    #[expect(clippy::wildcard_enum_match_arm)]
+    pub fn get_rational(
+        &self,
+        index: usize,
+    ) -> Result<Rational<u32>, TiffParserError> {
+        Ok(match self.datatype {
+            TiffDataType::Byte | TiffDataType::Short | TiffDataType::Long => {
+                Rational::new(self.get_u32(index)?, 1)
+            }
+            TiffDataType::Rational => {
+                let mut bs = self.streamer(index)?;
+                let num = bs.read::<u32>();
+                let den = bs.read::<u32>();
+                Rational::new(num, den)
+            }
+            _ => return self.mismatch(),
+        })
+    }
+
+    #[inline]
+    #[expect(clippy::wildcard_enum_match_arm)]
+    pub fn get_srational(
+        &self,
+        index: usize,
+    ) -> Result<Rational<i32>, TiffParserError> {
+        Ok(match self.datatype {
+            TiffDataType::Byte
+            | TiffDataType::Short
+            | TiffDataType::SByte
+            | TiffDataType::SShort
+            | TiffDataType::SLong => Rational::new(self.get_i32(index)?, 1),
+            TiffDataType::SRational => {
+                let mut bs = self.streamer(index)?;
+                let num = bs.read::<i32>();
+                let den = bs.read::<i32>();
+                Rational::new(num, den)
+            }
+            _ => return self.mismatch(),
+        })
+    }
+
+    #[inline]
+    #[expect(clippy::wildcard_enum_match_arm, clippy::float_arithmetic)]
+    pub fn get_f64(&self, index: usize) -> Result<f64, TiffParserError> {
+        Ok(match self.datatype {
+            TiffDataType::Byte
+            | TiffDataType::Short
+            | TiffDataType::Long
+            | TiffDataType::IFD => self.get_u32(index)?.into(),
+            TiffDataType::SByte
+            | TiffDataType::SShort
+            | TiffDataType::SLong => self.get_i32(index)?.into(),
+            TiffDataType::Rational => {
+                let val = self.get_rational(index)?;
+                if val.den == 0 {
+                    return Err(TiffParserError::DivisionByZero(self.tag));
+                }
+                f64::from(val.num) / f64::from(val.den)
+            }
+            TiffDataType::SRational => {
+                let val = self.get_srational(index)?;
+                if val.den == 0 {
+                    return Err(TiffParserError::DivisionByZero(self.tag));
+                }
+                f64::from(val.num) / f64::from(val.den)
+            }
+            TiffDataType::Float => self.streamer(index)?.read::<f32>().into(),
+            TiffDataType::Double => self.streamer(index)?.read::<f64>(),
+            _ => return self.mismatch(),
+        })
+    }
+
+    #[inline]
+    #[expect(clippy::cast_possible_truncation, clippy::as_conversions)]
+    pub fn get_f32(&self, index: usize) -> Result<f32, TiffParserError> {
+        self.get_f64(index).map(|val| val as f32)
+    }
+
+    #[inline]
+    pub fn get_u16s(&self) -> Result<Vec<u16>, TiffParserError> {
+        (0..self.len()).map(|i| self.get_u16(i)).collect()
+    }
+
+    #[inline]
+    pub fn get_u32s(&self) -> Result<Vec<u32>, TiffParserError> {
+        (0..self.len()).map(|i| self.get_u32(i)).collect()
+    }
+
+    #[inline]
+    pub fn get_f32s(&self) -> Result<Vec<f32>, TiffParserError> {
+        (0..self.len()).map(|i| self.get_f32(i)).collect()
+    }
+
+    #[inline]
+    #[expect(clippy::wildcard_enum_match_arm)]
+    pub fn get_string(&self) -> Result<&'a str, TiffParserError> {
+        match self.datatype {
+            TiffDataType::Byte
+            | TiffDataType::Ascii
+            | TiffDataType::Undefined => {}
+            _ => return self.mismatch(),
+        }
+        let len = self
+            .data
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(self.data.len());
+        let (bytes, _) = self.data.split_at(len);
+        core::str::from_utf8(bytes)
+            .map_err(|_err| TiffParserError::InvalidString(self.tag))
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.count.try_into().unwrap()
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,143 @@
+use rawspeed_memory_endianness::endianness::Endianness;
+
+use super::{Rational, TiffEntry};
+use crate::tiffparser::{TiffDataType, TiffParserError, TiffTag};
+
+const TAG: TiffTag = TiffTag::new(0x1234);
+
+fn entry(
+    datatype: TiffDataType,
+    data: &[u8],
+    endianness: Endianness,
+) -> TiffEntry<'_> {
+    TiffEntry {
+        tag: TAG,
+        datatype,
+        count: (data.len() / datatype.byte_size()).try_into().unwrap(),
+        data,
+        data_offset: 0,
+        endianness,
+    }
+}
+
+const fn mismatch<T>(datatype: TiffDataType) -> Result<T, TiffParserError> {
+    Err(TiffParserError::UnexpectedDataType(TAG, datatype))
+}
+
+#[test]
+fn short_le_test() {
+    let e = entry(TiffDataType::Short, &[1, 2, 3, 4], Endianness::Little);
+    assert_eq!(e.len(), 2);
+    assert_eq!(e.get_u16(0), Ok(0x0201));
+    assert_eq!(e.get_u32(1), Ok(0x0403));
+    assert_eq!(e.get_i32(1), Ok(0x0403));
+    assert_eq!(e.get_u16s(), Ok(vec![0x0201, 0x0403]));
+    assert_eq!(e.get_u16(2), Err(TiffParserError::IndexOutOfBounds(TAG, 2)));
+    assert_eq!(e.get_u8(0), mismatch(TiffDataType::Short));
+}
+
+#[test]
+fn short_be_test() {
+    let e = entry(TiffDataType::Short, &[1, 2, 3, 4], Endianness::Big);
+    assert_eq!(e.get_u16s(), Ok(vec![0x0102, 0x0304]));
+}
+
+#[test]
+fn long_test() {
+    let e = entry(TiffDataType::Long, &[1, 2, 3, 4], Endianness::Big);
+    assert_eq!(e.get_u32s(), Ok(vec![0x0102_0304]));
+    assert_eq!(e.get_u16(0), mismatch(TiffDataType::Long));
+}
+
+#[test]
+fn byte_test() {
+    let e = entry(TiffDataType::Byte, &[0xFF, 7], Endianness::Big);
+    assert_eq!(e.get_u8(0), Ok(0xFF));
+    assert_eq!(e.get_u32s(), Ok(vec![0xFF, 7]));
+}
+
+#[test]
+fn sshort_test() {
+    let e = entry(TiffDataType::SShort, &[0xFF, 0xFE], Endianness::Big);
+    assert_eq!(e.get_i32(0), Ok(-2));
+    assert_eq!(e.get_u32(0), mismatch(TiffDataType::SShort));
+}
+
+#[test]
+fn sbyte_test() {
+    let e = entry(TiffDataType::SByte, &[0x80], Endianness::Little);
+    assert_eq!(e.get_i32(0), Ok(-128));
+}
+
+#[test]
+fn slong_test() {
+    let e = entry(TiffDataType::SLong, &[0xFF; 4], Endianness::Little);
+    assert_eq!(e.get_i32(0), Ok(-1));
+    assert_eq!(e.get_srational(0), Ok(Rational::new(-1, 1)));
+}
+
+#[test]
+fn rational_test() {
+    let e = entry(
+        TiffDataType::Rational,
+        &[0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0, 1, 0, 0, 0, 0],
+        Endianness::Big,
+    );
+    assert_eq!(e.len(), 2);
+    assert_eq!(e.get_rational(0), Ok(Rational::new(3, 4)));
+    assert_eq!(e.get_f32(0), Ok(0.75));
+    assert_eq!(e.get_f64(1), Err(TiffParserError::DivisionByZero(TAG)));
+}
+
+#[test]
+fn srational_test() {
+    let e = entry(
+        TiffDataType::SRational,
+        &[0xFD, 0xFF, 0xFF, 0xFF, 2, 0, 0, 0],
+        Endianness::Little,
+    );
+    assert_eq!(e.get_srational(0), Ok(Rational::new(-3, 2)));
+    assert_eq!(e.get_f32s(), Ok(vec![-1.5]));
+    assert_eq!(e.get_rational(0), mismatch(TiffDataType::SRational));
+}
+
+#[test]
+fn float_test() {
+    let e = entry(TiffDataType::Float, &[0x3F, 0xA0, 0, 0], Endianness::Big);
+    assert_eq!(e.get_f32(0), Ok(1.25));
+}
+
+#[test]
+fn double_test() {
+    let e = entry(
+        TiffDataType::Double,
+        &[0, 0, 0, 0, 0, 0, 0x04, 0xC0],
+        Endianness::Little,
+    );
+    assert_eq!(e.get_f64(0), Ok(-2.5));
+}
+
+#[test]
+fn ascii_is_not_a_number_test() {
+    let e = entry(TiffDataType::Ascii, b"1", Endianness::Big);
+    assert_eq!(e.get_f64(0), mismatch(TiffDataType::Ascii));
+}
+
+#[test]
+fn string_test() {
+    for (datatype, data, expected) in [
+        (TiffDataType::Ascii, &b"Canon\0\0\0"[..], Ok("Canon")),
+        (TiffDataType::Undefined, b"NoNul", Ok("NoNul")),
+        (
+            TiffDataType::Ascii,
+            &[0xFF, 0xFE, 0],
+            Err(TiffParserError::InvalidString(TAG)),
+        ),
+        (TiffDataType::Short, &[0, 0], mismatch(TiffDataType::Short)),
+    ] {
+        assert_eq!(
+            entry(datatype, data, Endianness::Big).get_string(),
+            expected
+        );
+    }
+}
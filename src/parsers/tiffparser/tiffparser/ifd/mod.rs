@@ -0,0 +1,134 @@
+use super::{ParseContext, TiffEntry, TiffParserError, TiffTag};
+
+const MAX_SUB_IFD_DEPTH: usize = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+#[must_use]
+pub struct TiffIFD<'a> {
+    offset: usize,
+    entries: Vec<TiffEntry<'a>>,
+    sub_ifds: Vec<TiffIFD<'a>>,
+}
+
+impl<'a> TiffIFD<'a> {
+    const fn is_sub_ifd_pointer(tag: TiffTag) -> bool {
+        matches!(
+            tag,
+            TiffTag::SUB_IFDS
+                | TiffTag::EXIF_IFD_POINTER
+                | TiffTag::FUJI_RAW_IFD
+        )
+    }
+
+    /// Parses the IFD at the given `offset`, and returns it along with
+    /// the offset of the next IFD in the chain (zero if there is none).
+    pub(super) fn parse(
+        ctx: &mut ParseContext<'a>,
+        offset: usize,
+        depth: usize,
+    ) -> Result<(Self, usize), TiffParserError> {
+        if depth > MAX_SUB_IFD_DEPTH {
+            return Err(TiffParserError::NestingTooDeep);
+        }
+        ctx.mark_as_visited(offset)?;
+
+        let num_entries = usize::from(ctx.read_u16(offset)?);
+        let entries_offset = offset + 2;
+        let next_ifd_offset =
+            entries_offset + num_entries * TiffEntry::BYTE_SIZE;
+        let next_ifd = ctx.read_u32(next_ifd_offset)?.try_into().unwrap();
+
+        let mut entries = Vec::with_capacity(num_entries);
+        let mut sub_ifds = vec![];
+        for entry_offset in
+            (entries_offset..next_ifd_offset).step_by(TiffEntry::BYTE_SIZE)
+        {
+            let Some(entry) = TiffEntry::parse(ctx, entry_offset)? else {
+                continue;
+            };
+            if Self::is_sub_ifd_pointer(entry.tag()) {
+                for sub_ifd_offset in entry.get_u32s()? {
+                    if sub_ifd_offset == 0 {
+                        continue;
+                    }
+                    let (sub_ifd, _) = Self::parse(
+                        ctx,
+                        sub_ifd_offset.try_into().unwrap(),
+                        depth + 1,
+                    )?;
+                    sub_ifds.push(sub_ifd);
+                }
+            }
+            entries.push(entry);
+        }
+
+        Ok((
+            Self {
+                offset,
+                entries,
+                sub_ifds,
+            },
+            next_ifd,
+        ))
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn offset(&self) -> usize {
+        self.offset
+    }
+
+    #[inline]
+    pub fn entries(&self) -> &[TiffEntry<'a>] {
+        &self.entries
+    }
+
+    #[inline]
+    pub fn sub_ifds(&self) -> &[TiffIFD<'a>] {
+        &self.sub_ifds
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn has_entry(&self, tag: TiffTag) -> bool {
+        self.get_entry(tag).is_some()
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn get_entry(&self, tag: TiffTag) -> Option<&TiffEntry<'a>> {
+        self.entries.iter().find(|entry| entry.tag() == tag)
+    }
+
+    #[inline]
+    pub fn get_required_entry(
+        &self,
+        tag: TiffTag,
+    ) -> Result<&TiffEntry<'a>, TiffParserError> {
+        self.get_entry(tag).ok_or(TiffParserError::TagNotFound(tag))
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn get_entry_recursive(&self, tag: TiffTag) -> Option<&TiffEntry<'a>> {
+        self.get_entry(tag).or_else(|| {
+            self.sub_ifds
+                .iter()
+                .find_map(|ifd| ifd.get_entry_recursive(tag))
+        })
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn get_ifds_with_tag(&self, tag: TiffTag) -> Vec<&TiffIFD<'a>> {
+        let mut res = vec![];
+        if self.has_entry(tag) {
+            res.push(self);
+        }
+        for ifd in &self.sub_ifds {
+            res.extend(ifd.get_ifds_with_tag(tag));
+        }
+        res
+    }
+}
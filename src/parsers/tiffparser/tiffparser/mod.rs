@@ -0,0 +1,250 @@
+use rawspeed_bitstream_bytestream_decoder::bytestreamer::ByteStreamer;
+use rawspeed_memory_endianness::endianness::Endianness;
+
+pub mod datatype;
+pub mod entry;
+pub mod ifd;
+pub mod tag;
+
+pub use datatype::TiffDataType;
+pub use entry::{Rational, TiffEntry};
+pub use ifd::TiffIFD;
+pub use tag::TiffTag;
+
+const MAX_IFD_COUNT: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum TiffParserError {
+    UnknownByteOrderMark,
+    UnexpectedMagic(u16),
+    OffsetOutOfBounds(usize),
+    IFDCycle(usize),
+    TooManyIFDs,
+    NestingTooDeep,
+    TagNotFound(TiffTag),
+    UnexpectedDataType(TiffTag, TiffDataType),
+    IndexOutOfBounds(TiffTag, usize),
+    DivisionByZero(TiffTag),
+    InvalidString(TiffTag),
+}
+
+impl core::fmt::Display for TiffParserError {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TiffParserError::UnknownByteOrderMark => {
+                write!(f, "TiffParserError(UnknownByteOrderMark)")
+            }
+            TiffParserError::UnexpectedMagic(magic) => {
+                write!(f, "TiffParserError(UnexpectedMagic(0x{magic:04x}))")
+            }
+            TiffParserError::OffsetOutOfBounds(offset) => {
+                write!(f, "TiffParserError(OffsetOutOfBounds({offset}))")
+            }
+            TiffParserError::IFDCycle(offset) => {
+                write!(f, "TiffParserError(IFDCycle({offset}))")
+            }
+            TiffParserError::TooManyIFDs => {
+                write!(f, "TiffParserError(TooManyIFDs)")
+            }
+            TiffParserError::NestingTooDeep => {
+                write!(f, "TiffParserError(NestingTooDeep)")
+            }
+            TiffParserError::TagNotFound(tag) => {
+                write!(f, "TiffParserError(TagNotFound({tag}))")
+            }
+            TiffParserError::UnexpectedDataType(tag, datatype) => {
+                write!(
+                    f,
+                    "TiffParserError(UnexpectedDataType({tag}, {datatype}))"
+                )
+            }
+            TiffParserError::IndexOutOfBounds(tag, index) => {
+                write!(f, "TiffParserError(IndexOutOfBounds({tag}, {index}))")
+            }
+            TiffParserError::DivisionByZero(tag) => {
+                write!(f, "TiffParserError(DivisionByZero({tag}))")
+            }
+            TiffParserError::InvalidString(tag) => {
+                write!(f, "TiffParserError(InvalidString({tag}))")
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ParseContext<'a> {
+    input: &'a [u8],
+    endianness: Endianness,
+    visited: std::collections::BTreeSet<usize>,
+}
+
+impl<'a> ParseContext<'a> {
+    const fn new(input: &'a [u8], endianness: Endianness) -> Self {
+        Self {
+            input,
+            endianness,
+            visited: std::collections::BTreeSet::new(),
+        }
+    }
+
+    fn get_bytes(
+        &self,
+        offset: usize,
+        len: usize,
+    ) -> Result<&'a [u8], TiffParserError> {
+        self.input
+            .get(offset..)
+            .and_then(|bytes| bytes.get(..len))
+            .ok_or(TiffParserError::OffsetOutOfBounds(offset))
+    }
+
+    fn read_u16(&self, offset: usize) -> Result<u16, TiffParserError> {
+        let bytes = self.get_bytes(offset, size_of::<u16>())?;
+        Ok(ByteStreamer::new(bytes, self.endianness).read())
+    }
+
+    fn read_u32(&self, offset: usize) -> Result<u32, TiffParserError> {
+        let bytes = self.get_bytes(offset, size_of::<u32>())?;
+        Ok(ByteStreamer::new(bytes, self.endianness).read())
+    }
+
+    fn mark_as_visited(
+        &mut self,
+        offset: usize,
+    ) -> Result<(), TiffParserError> {
+        if !self.visited.insert(offset) {
+            return Err(TiffParserError::IFDCycle(offset));
+        }
+        if self.visited.len() > MAX_IFD_COUNT {
+            return Err(TiffParserError::TooManyIFDs);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+#[must_use]
+pub struct TiffHeader {
+    pub endianness: Endianness,
+    pub magic: u16,
+    pub first_ifd_offset: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+#[must_use]
+pub struct TiffRootIFD<'a> {
+    input: &'a [u8],
+    endianness: Endianness,
+    ifds: Vec<TiffIFD<'a>>,
+}
+
+impl<'a> TiffRootIFD<'a> {
+    #[inline]
+    #[must_use]
+    pub const fn input(&self) -> &'a [u8] {
+        self.input
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+
+    #[inline]
+    pub fn ifds(&self) -> &[TiffIFD<'a>] {
+        &self.ifds
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn get_entry_recursive(&self, tag: TiffTag) -> Option<&TiffEntry<'a>> {
+        self.ifds
+            .iter()
+            .find_map(|ifd| ifd.get_entry_recursive(tag))
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn get_ifds_with_tag(&self, tag: TiffTag) -> Vec<&TiffIFD<'a>> {
+        self.ifds
+            .iter()
+            .flat_map(|ifd| ifd.get_ifds_with_tag(tag))
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+#[must_use]
+pub struct TiffParser;
+
+impl TiffParser {
+    pub const MAGIC: u16 = 42;
+    pub const MAGIC_ORF: u16 = 0x4F52;
+    pub const MAGIC_ORF_SPECIAL: u16 = 0x5352;
+    pub const MAGIC_RW2: u16 = 0x0055;
+
+    #[inline]
+    pub fn parse_header(input: &[u8]) -> Result<TiffHeader, TiffParserError> {
+        let endianness = match input.get(..2) {
+            Some(b"II") => Endianness::Little,
+            Some(b"MM") => Endianness::Big,
+            Some(_) => return Err(TiffParserError::UnknownByteOrderMark),
+            None => return Err(TiffParserError::OffsetOutOfBounds(0)),
+        };
+        let ctx = ParseContext::new(input, endianness);
+        let magic = ctx.read_u16(2)?;
+        if !matches!(
+            magic,
+            Self::MAGIC
+                | Self::MAGIC_ORF
+                | Self::MAGIC_ORF_SPECIAL
+                | Self::MAGIC_RW2
+        ) {
+            return Err(TiffParserError::UnexpectedMagic(magic));
+        }
+        let first_ifd_offset = ctx.read_u32(4)?.try_into().unwrap();
+        Ok(TiffHeader {
+            endianness,
+            magic,
+            first_ifd_offset,
+        })
+    }
+
+    #[inline]
+    pub fn parse(input: &[u8]) -> Result<TiffRootIFD<'_>, TiffParserError> {
+        let header = Self::parse_header(input)?;
+        Self::parse_ifd_chain(input, header.endianness, header.first_ifd_offset)
+    }
+
+    /// Parses a header-less chain of IFDs (e.g. a `MakerNote`),
+    /// starting at `first_ifd_offset`. All offsets are relative to `input`.
+    #[inline]
+    pub fn parse_ifd_chain(
+        input: &[u8],
+        endianness: Endianness,
+        first_ifd_offset: usize,
+    ) -> Result<TiffRootIFD<'_>, TiffParserError> {
+        let mut ctx = ParseContext::new(input, endianness);
+        let mut ifds = vec![];
+        let mut next_ifd = first_ifd_offset;
+        while next_ifd != 0 {
+            let (ifd, next) = TiffIFD::parse(&mut ctx, next_ifd, 0)?;
+            ifds.push(ifd);
+            next_ifd = next;
+        }
+        Ok(TiffRootIFD {
+            input,
+            endianness,
+            ifds,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests;
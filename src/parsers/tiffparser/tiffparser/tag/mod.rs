@@ -0,0 +1,143 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[non_exhaustive]
+#[must_use]
+pub struct TiffTag {
+    val: u16,
+}
+
+impl TiffTag {
+    pub const PANASONIC_SENSOR_WIDTH: Self = Self::new(0x0002);
+    pub const PANASONIC_SENSOR_HEIGHT: Self = Self::new(0x0003);
+    pub const PANASONIC_SENSOR_TOP_BORDER: Self = Self::new(0x0004);
+    pub const PANASONIC_SENSOR_LEFT_BORDER: Self = Self::new(0x0005);
+    pub const PANASONIC_SENSOR_BOTTOM_BORDER: Self = Self::new(0x0006);
+    pub const PANASONIC_SENSOR_RIGHT_BORDER: Self = Self::new(0x0007);
+    pub const PANASONIC_BITS_PER_SAMPLE: Self = Self::new(0x000A);
+    pub const NIKON_WB_RB_LEVELS: Self = Self::new(0x000C);
+    pub const HASSELBLAD_PIXEL_BASE_OFFSET: Self = Self::new(0x0011);
+    pub const PANASONIC_ISO: Self = Self::new(0x0017);
+    pub const PANASONIC_BLACK_LEVEL_RED: Self = Self::new(0x001C);
+    pub const PANASONIC_BLACK_LEVEL_GREEN: Self = Self::new(0x001D);
+    pub const PANASONIC_BLACK_LEVEL_BLUE: Self = Self::new(0x001E);
+    pub const PANASONIC_WB_RED_LEVEL: Self = Self::new(0x0024);
+    pub const PANASONIC_WB_GREEN_LEVEL: Self = Self::new(0x0025);
+    pub const PANASONIC_WB_BLUE_LEVEL: Self = Self::new(0x0026);
+    pub const PANASONIC_RAW_FORMAT: Self = Self::new(0x002D);
+    pub const NIKON_BLACK_LEVEL: Self = Self::new(0x003D);
+    pub const NIKON_LINEARIZATION_TABLE: Self = Self::new(0x0096);
+    pub const NEW_SUBFILE_TYPE: Self = Self::new(0x00FE);
+    pub const SUBFILE_TYPE: Self = Self::new(0x00FF);
+    pub const IMAGE_WIDTH: Self = Self::new(0x0100);
+    pub const OLYMPUS_WB_RB_LEVELS: Self = Self::new(0x0100);
+    pub const IMAGE_LENGTH: Self = Self::new(0x0101);
+    pub const BITS_PER_SAMPLE: Self = Self::new(0x0102);
+    pub const COMPRESSION: Self = Self::new(0x0103);
+    pub const PHOTOMETRIC_INTERPRETATION: Self = Self::new(0x0106);
+    pub const FILL_ORDER: Self = Self::new(0x010A);
+    pub const MAKE: Self = Self::new(0x010F);
+    pub const MODEL: Self = Self::new(0x0110);
+    pub const STRIP_OFFSETS: Self = Self::new(0x0111);
+    pub const ORIENTATION: Self = Self::new(0x0112);
+    pub const SAMPLES_PER_PIXEL: Self = Self::new(0x0115);
+    pub const ROWS_PER_STRIP: Self = Self::new(0x0116);
+    pub const STRIP_BYTE_COUNTS: Self = Self::new(0x0117);
+    pub const PANASONIC_STRIP_OFFSET: Self = Self::new(0x0118);
+    pub const PLANAR_CONFIGURATION: Self = Self::new(0x011C);
+    pub const PREDICTOR: Self = Self::new(0x013D);
+    pub const TILE_WIDTH: Self = Self::new(0x0142);
+    pub const TILE_LENGTH: Self = Self::new(0x0143);
+    pub const TILE_OFFSETS: Self = Self::new(0x0144);
+    pub const TILE_BYTE_COUNTS: Self = Self::new(0x0145);
+    pub const SUB_IFDS: Self = Self::new(0x014A);
+    pub const SAMPLE_FORMAT: Self = Self::new(0x0153);
+    pub const PENTAX_BLACK_LEVEL: Self = Self::new(0x0200);
+    pub const JPEG_INTERCHANGE_FORMAT: Self = Self::new(0x0201);
+    pub const PENTAX_WB_LEVELS: Self = Self::new(0x0201);
+    pub const JPEG_INTERCHANGE_FORMAT_LENGTH: Self = Self::new(0x0202);
+    pub const PENTAX_HUFFMAN_TABLE: Self = Self::new(0x0220);
+    pub const XMP: Self = Self::new(0x02BC);
+    pub const KODAK_WB_INFO: Self = Self::new(0x03FD);
+    pub const OLYMPUS_BLACK_LEVEL2: Self = Self::new(0x0600);
+    pub const KODAK_LINEARIZATION: Self = Self::new(0x090D);
+    pub const OLYMPUS_RED_MULTIPLIER: Self = Self::new(0x1017);
+    pub const OLYMPUS_BLUE_MULTIPLIER: Self = Self::new(0x1018);
+    pub const KODAK_ISO: Self = Self::new(0x1784);
+    pub const OLYMPUS_IMAGE_PROCESSING: Self = Self::new(0x2040);
+    pub const SONY_CURVE: Self = Self::new(0x7010);
+    pub const SONY_SR2_SUB_IFD_OFFSET: Self = Self::new(0x7200);
+    pub const SONY_SR2_SUB_IFD_LENGTH: Self = Self::new(0x7201);
+    pub const SONY_SR2_SUB_IFD_KEY: Self = Self::new(0x7221);
+    pub const SONY_BLACK_LEVEL: Self = Self::new(0x7300);
+    pub const SONY_WB_GRBG_LEVELS: Self = Self::new(0x7303);
+    pub const SONY_WB_RGGB_LEVELS: Self = Self::new(0x7313);
+    pub const CFA_REPEAT_PATTERN_DIM: Self = Self::new(0x828D);
+    pub const CFA_PATTERN: Self = Self::new(0x828E);
+    pub const KODAK_IFD: Self = Self::new(0x8290);
+    pub const LEAF_METADATA: Self = Self::new(0x8606);
+    pub const EXIF_IFD_POINTER: Self = Self::new(0x8769);
+    pub const ISO_SPEED_RATINGS: Self = Self::new(0x8827);
+    pub const MAKER_NOTE: Self = Self::new(0x927C);
+    pub const SAMSUNG_ROW_OFFSETS: Self = Self::new(0xA010);
+    pub const SAMSUNG_WB_RGGB_LEVELS_UNCORRECTED: Self = Self::new(0xA021);
+    pub const SAMSUNG_WB_RGGB_LEVELS_BLACK: Self = Self::new(0xA028);
+    pub const DNG_VERSION: Self = Self::new(0xC612);
+    pub const UNIQUE_CAMERA_MODEL: Self = Self::new(0xC614);
+    pub const CFA_PLANE_COLOR: Self = Self::new(0xC616);
+    pub const CFA_LAYOUT: Self = Self::new(0xC617);
+    pub const LINEARIZATION_TABLE: Self = Self::new(0xC618);
+    pub const BLACK_LEVEL_REPEAT_DIM: Self = Self::new(0xC619);
+    pub const BLACK_LEVEL: Self = Self::new(0xC61A);
+    pub const BLACK_LEVEL_DELTA_H: Self = Self::new(0xC61B);
+    pub const BLACK_LEVEL_DELTA_V: Self = Self::new(0xC61C);
+    pub const WHITE_LEVEL: Self = Self::new(0xC61D);
+    pub const DEFAULT_SCALE: Self = Self::new(0xC61E);
+    pub const DEFAULT_CROP_ORIGIN: Self = Self::new(0xC61F);
+    pub const DEFAULT_CROP_SIZE: Self = Self::new(0xC620);
+    pub const COLOR_MATRIX1: Self = Self::new(0xC621);
+    pub const COLOR_MATRIX2: Self = Self::new(0xC622);
+    pub const AS_SHOT_NEUTRAL: Self = Self::new(0xC628);
+    pub const DNG_PRIVATE_DATA: Self = Self::new(0xC634);
+    pub const CANON_CR2_SLICE: Self = Self::new(0xC640);
+    pub const CALIBRATION_ILLUMINANT1: Self = Self::new(0xC65A);
+    pub const CALIBRATION_ILLUMINANT2: Self = Self::new(0xC65B);
+    pub const ACTIVE_AREA: Self = Self::new(0xC68D);
+    pub const MASKED_AREAS: Self = Self::new(0xC68E);
+    pub const FUJI_RAW_IFD: Self = Self::new(0xF000);
+    pub const FUJI_RAW_IMAGE_FULL_WIDTH: Self = Self::new(0xF001);
+    pub const FUJI_RAW_IMAGE_FULL_HEIGHT: Self = Self::new(0xF002);
+    pub const FUJI_BITS_PER_SAMPLE: Self = Self::new(0xF003);
+    pub const FUJI_STRIP_OFFSETS: Self = Self::new(0xF007);
+    pub const FUJI_STRIP_BYTE_COUNTS: Self = Self::new(0xF008);
+    pub const KODAK_KDC_SENSOR_WIDTH: Self = Self::new(0xFA13);
+    pub const KODAK_KDC_SENSOR_HEIGHT: Self = Self::new(0xFA14);
+    pub const KODAK_KDC_WB: Self = Self::new(0xFA2A);
+    pub const KODAK_KDC_OFFSET: Self = Self::new(0xFD04);
+    pub const KODAK_IFD2: Self = Self::new(0xFE00);
+
+    #[inline]
+    pub const fn new(val: u16) -> Self {
+        Self { val }
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn val(&self) -> u16 {
+        self.val
+    }
+}
+
+impl core::ops::Deref for TiffTag {
+    type Target = u16;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.val
+    }
+}
+
+impl core::fmt::Display for TiffTag {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "0x{:04x}", self.val)
+    }
+}
@@ -0,0 +1,372 @@
+use rawspeed_memory_endianness::endianness::Endianness;
+
+use super::{
+    TiffDataType, TiffIFD, TiffParser, TiffParserError, TiffRootIFD, TiffTag,
+};
+
+const ENDIANNESSES: [Endianness; 2] = [Endianness::Little, Endianness::Big];
+
+struct TiffWriter {
+    endianness: Endianness,
+    buf: Vec<u8>,
+}
+
+impl TiffWriter {
+    fn new(endianness: Endianness) -> Self {
+        Self {
+            endianness,
+            buf: vec![],
+        }
+    }
+
+    fn with_header(endianness: Endianness, magic: u16, ifd: u32) -> Self {
+        let mut w = Self::new(endianness);
+        w.buf.extend_from_slice(match endianness {
+            Endianness::Little => b"II",
+            Endianness::Big => b"MM",
+        });
+        w.u16(magic);
+        w.u32(ifd);
+        w
+    }
+
+    fn bytes(&mut self, mut bytes: Vec<u8>) {
+        if self.endianness == Endianness::Big {
+            bytes.reverse();
+        }
+        self.buf.extend(bytes);
+    }
+
+    fn u16(&mut self, val: u16) {
+        self.bytes(val.to_le_bytes().to_vec());
+    }
+
+    fn u32(&mut self, val: u32) {
+        self.bytes(val.to_le_bytes().to_vec());
+    }
+
+    fn entry(&mut self, tag: u16, datatype: u16, count: u32, value: u32) {
+        self.u16(tag);
+        self.u16(datatype);
+        self.u32(count);
+        // A single inline SHORT is left-justified within the value field.
+        if datatype == 3 && count == 1 {
+            self.u16(value.try_into().unwrap());
+            self.u16(0);
+        } else {
+            self.u32(value);
+        }
+    }
+
+    fn ifd(&mut self, entries: &[(u16, u16, u32, u32)], next: u32) {
+        self.u16(entries.len().try_into().unwrap());
+        for &(tag, datatype, count, value) in entries {
+            self.entry(tag, datatype, count, value);
+        }
+        self.u32(next);
+    }
+
+    fn pos(&self) -> u32 {
+        self.buf.len().try_into().unwrap()
+    }
+}
+
+fn ifd_offsets(ifds: &[TiffIFD<'_>]) -> Vec<usize> {
+    ifds.iter().map(TiffIFD::offset).collect()
+}
+
+#[test]
+fn header_test() {
+    for endianness in ENDIANNESSES {
+        for magic in [
+            TiffParser::MAGIC,
+            TiffParser::MAGIC_ORF,
+            TiffParser::MAGIC_ORF_SPECIAL,
+            TiffParser::MAGIC_RW2,
+        ] {
+            let w = TiffWriter::with_header(endianness, magic, 1234);
+            let header = TiffParser::parse_header(&w.buf).unwrap();
+            assert_eq!(header.endianness, endianness);
+            assert_eq!(header.magic, magic);
+            assert_eq!(header.first_ifd_offset, 1234);
+        }
+    }
+}
+
+#[test]
+fn bad_header_test() {
+    assert_eq!(
+        TiffParser::parse(&[]),
+        Err(TiffParserError::OffsetOutOfBounds(0))
+    );
+    assert_eq!(
+        TiffParser::parse(b"II"),
+        Err(TiffParserError::OffsetOutOfBounds(2))
+    );
+    assert_eq!(
+        TiffParser::parse(b"IM*\0\0\0\0\0"),
+        Err(TiffParserError::UnknownByteOrderMark)
+    );
+    assert_eq!(
+        TiffParser::parse(b"MM\0*\0\0"),
+        Err(TiffParserError::OffsetOutOfBounds(4))
+    );
+    for endianness in ENDIANNESSES {
+        let w = TiffWriter::with_header(endianness, 43, 0);
+        assert_eq!(
+            TiffParser::parse(&w.buf),
+            Err(TiffParserError::UnexpectedMagic(43))
+        );
+    }
+}
+
+#[test]
+fn empty_chain_test() {
+    for endianness in ENDIANNESSES {
+        let w = TiffWriter::with_header(endianness, TiffParser::MAGIC, 0);
+        let root = TiffParser::parse(&w.buf).unwrap();
+        assert!(root.ifds().is_empty());
+        assert_eq!(root.endianness(), endianness);
+    }
+}
+
+#[test]
+fn ifd_chain_test() {
+    for endianness in ENDIANNESSES {
+        let mut w = TiffWriter::with_header(endianness, TiffParser::MAGIC, 8);
+        // IFD0 @ 8: 1 entry, 18 bytes long.
+        w.ifd(&[(0x0100, 4, 1, 640)], 26);
+        // IFD1 @ 26: 2 entries.
+        w.ifd(&[(0x0100, 4, 1, 320), (0x0101, 4, 1, 240)], 0);
+        let root = TiffParser::parse(&w.buf).unwrap();
+        assert_eq!(ifd_offsets(root.ifds()), vec![8, 26]);
+        let ifd0 = root.ifds().first().unwrap();
+        let ifd1 = root.ifds().get(1).unwrap();
+        assert_eq!(ifd0.entries().len(), 1);
+        assert_eq!(ifd1.entries().len(), 2);
+        assert_eq!(
+            ifd0.get_entry(TiffTag::IMAGE_WIDTH)
+                .unwrap()
+                .get_u32(0)
+                .unwrap(),
+            640
+        );
+        assert_eq!(
+            ifd1.get_required_entry(TiffTag::IMAGE_LENGTH)
+                .unwrap()
+                .get_u32(0)
+                .unwrap(),
+            240
+        );
+        assert_eq!(
+            ifd0.get_required_entry(TiffTag::IMAGE_LENGTH),
+            Err(TiffParserError::TagNotFound(TiffTag::IMAGE_LENGTH))
+        );
+        assert_eq!(root.get_ifds_with_tag(TiffTag::IMAGE_LENGTH), vec![ifd1]);
+        assert_eq!(root.get_ifds_with_tag(TiffTag::IMAGE_WIDTH).len(), 2);
+    }
+}
+
+#[test]
+fn sub_ifd_test() {
+    for endianness in ENDIANNESSES {
+        let mut w = TiffWriter::with_header(endianness, TiffParser::MAGIC, 8);
+        // IFD0 @ 8: SubIFDs pointing to two IFDs, plus an EXIF pointer.
+        // Entries end at 8 + 2 + 2 * 12 + 4 = 38.
+        w.ifd(&[(0x014A, 4, 2, 38), (0x8769, 4, 1, 64)], 0);
+        // SubIFD offsets array @ 38.
+        w.u32(46);
+        w.u32(0);
+        // SubIFD @ 46.
+        w.ifd(&[(0x0100, 3, 1, 7)], 0);
+        assert_eq!(w.pos(), 64);
+        // EXIF IFD @ 64.
+        w.ifd(&[(0x8827, 3, 1, 100)], 0);
+        let root = TiffParser::parse(&w.buf).unwrap();
+        assert_eq!(ifd_offsets(root.ifds()), vec![8]);
+        let ifd0 = root.ifds().first().unwrap();
+        assert_eq!(ifd_offsets(ifd0.sub_ifds()), vec![46, 64]);
+        assert!(!ifd0.has_entry(TiffTag::ISO_SPEED_RATINGS));
+        assert_eq!(
+            root.get_entry_recursive(TiffTag::ISO_SPEED_RATINGS)
+                .unwrap()
+                .get_u16(0)
+                .unwrap(),
+            100
+        );
+        assert_eq!(
+            ifd_offsets(
+                &root
+                    .get_ifds_with_tag(TiffTag::IMAGE_WIDTH)
+                    .into_iter()
+                    .cloned()
+                    .collect::<Vec<_>>()
+            ),
+            vec![46]
+        );
+    }
+}
+
+#[test]
+fn inline_and_offset_data_test() {
+    for endianness in ENDIANNESSES {
+        let mut w = TiffWriter::with_header(endianness, TiffParser::MAGIC, 8);
+        // 8 + 2 + 2 * 12 + 4 = 38
+        w.ifd(&[(0x010F, 2, 4, 0), (0x0110, 2, 6, 38)], 0);
+        w.buf.extend_from_slice(b"Model\0");
+        // Inline ASCII lives in the value field itself.
+        let inline_value = 8 + 2 + 8;
+        w.buf
+            .get_mut(inline_value..inline_value + 4)
+            .unwrap()
+            .copy_from_slice(b"Abc\0");
+        let root = TiffParser::parse(&w.buf).unwrap();
+        let make = root.get_entry_recursive(TiffTag::MAKE).unwrap();
+        assert_eq!(make.datatype(), TiffDataType::Ascii);
+        assert_eq!(make.data_offset(), inline_value);
+        assert_eq!(make.get_string(), Ok("Abc"));
+        let model = root.get_entry_recursive(TiffTag::MODEL).unwrap();
+        assert_eq!(model.data_offset(), 38);
+        assert_eq!(model.get_string(), Ok("Model"));
+    }
+}
+
+#[test]
+fn unknown_datatype_is_skipped_test() {
+    for endianness in ENDIANNESSES {
+        let mut w = TiffWriter::with_header(endianness, TiffParser::MAGIC, 8);
+        w.ifd(
+            &[(0x0100, 0, 1, 0), (0x0101, 99, 1, 0), (0x0102, 3, 1, 8)],
+            0,
+        );
+        let root = TiffParser::parse(&w.buf).unwrap();
+        let ifd0 = root.ifds().first().unwrap();
+        assert_eq!(ifd0.entries().len(), 1);
+        assert!(ifd0.has_entry(TiffTag::BITS_PER_SAMPLE));
+    }
+}
+
+type TiffBuilder = fn(&mut TiffWriter);
+
+fn check_errors(cases: &[(TiffBuilder, TiffParserError)]) {
+    for endianness in ENDIANNESSES {
+        for (build, err) in cases {
+            let mut w =
+                TiffWriter::with_header(endianness, TiffParser::MAGIC, 8);
+            build(&mut w);
+            assert_eq!(TiffParser::parse(&w.buf), Err(*err));
+        }
+    }
+}
+
+#[test]
+fn out_of_bounds_test() {
+    check_errors(&[
+        // No IFD at all.
+        (|_| {}, TiffParserError::OffsetOutOfBounds(8)),
+        // Entry table is truncated.
+        (|w| w.u16(1), TiffParserError::OffsetOutOfBounds(22)),
+        // Out-of-line data points past the end of input.
+        (
+            |w| w.ifd(&[(0x0110, 2, 6, 1000)], 0),
+            TiffParserError::OffsetOutOfBounds(1000),
+        ),
+        // Out-of-line data overflows the input.
+        (
+            |w| w.ifd(&[(0x0110, 2, 100, 8)], 0),
+            TiffParserError::OffsetOutOfBounds(8),
+        ),
+        // Next IFD points past the end of input.
+        (|w| w.ifd(&[], 100), TiffParserError::OffsetOutOfBounds(100)),
+    ]);
+}
+
+#[test]
+fn ifd_cycle_test() {
+    check_errors(&[
+        // IFD pointing to itself.
+        (|w| w.ifd(&[], 8), TiffParserError::IFDCycle(8)),
+        // Two IFDs pointing to each other.
+        (
+            |w| {
+                w.ifd(&[], 14);
+                w.ifd(&[], 8);
+            },
+            TiffParserError::IFDCycle(8),
+        ),
+        // SubIFD pointing back to its parent.
+        (
+            |w| {
+                w.ifd(&[(0x014A, 4, 1, 26)], 0);
+                w.ifd(&[(0x014A, 4, 1, 8)], 0);
+            },
+            TiffParserError::IFDCycle(8),
+        ),
+        // SubIFD that is also the next IFD in the chain.
+        (
+            |w| {
+                w.ifd(&[(0x014A, 4, 1, 26)], 26);
+                w.ifd(&[], 0);
+            },
+            TiffParserError::IFDCycle(26),
+        ),
+    ]);
+}
+
+#[test]
+fn nesting_too_deep_test() {
+    for endianness in ENDIANNESSES {
+        let mut w = TiffWriter::with_header(endianness, TiffParser::MAGIC, 8);
+        // Each IFD is 18 bytes long, and points to the next one as SubIFD.
+        for i in 0..8 {
+            w.ifd(&[(0x014A, 4, 1, 8 + 18 * (i + 1))], 0);
+        }
+        w.ifd(&[], 0);
+        assert_eq!(
+            TiffParser::parse(&w.buf),
+            Err(TiffParserError::NestingTooDeep)
+        );
+    }
+}
+
+#[test]
+fn too_many_ifds_test() {
+    for endianness in ENDIANNESSES {
+        let mut w = TiffWriter::with_header(endianness, TiffParser::MAGIC, 8);
+        for _ in 0..1000 {
+            let next = w.pos() + 6;
+            w.ifd(&[], next);
+        }
+        w.ifd(&[], 0);
+        assert_eq!(
+            TiffParser::parse(&w.buf),
+            Err(TiffParserError::TooManyIFDs)
+        );
+    }
+}
+
+#[test]
+fn ifd_chain_without_header_test() {
+    for endianness in ENDIANNESSES {
+        let mut w = TiffWriter::new(endianness);
+        w.buf.extend_from_slice(b"Nikon\0");
+        w.ifd(&[(0x0096, 7, 1, 0)], 0);
+        let root: TiffRootIFD<'_> =
+            TiffParser::parse_ifd_chain(&w.buf, endianness, 6).unwrap();
+        assert_eq!(ifd_offsets(root.ifds()), vec![6]);
+        assert!(root.get_entry_recursive(TiffTag::new(0x0096)).is_some());
+        assert_eq!(root.input().len(), w.buf.len());
+    }
+}
+
+#[test]
+fn error_display_test() {
+    assert_eq!(
+        TiffParserError::TagNotFound(TiffTag::MAKE).to_string(),
+        "TiffParserError(TagNotFound(0x010f))"
+    );
+    assert_eq!(
+        TiffParserError::UnexpectedMagic(43).to_string(),
+        "TiffParserError(UnexpectedMagic(0x002b))"
+    );
+}
@@ -0,0 +1,97 @@
+use rawspeed_bitstream_bitstream_encoder::bitvacuumer::{
+    jpeg::BitVacuumerJPEG, lsb::BitVacuumerLSB, msb::BitVacuumerMSB,
+    msb16::BitVacuumerMSB16, msb32::BitVacuumerMSB32,
+};
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+use rawspeed_codecs_packed_encoder::packed_encoder::{
+    ExtraPadding, NumBytes, Packer,
+};
+use rawspeed_common_bitseq::bitseq::{BitLen, BitSeq};
+use rawspeed_std::coord_common::{RowLength, RowPitch};
+use rawspeed_std_ndslice::array2dref::Array2DRef;
+
+/// Collects the bit sequences of a bitstream, which is then laid out
+/// in the given [`BitOrder`] by the matching bit vacuumer.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+#[must_use]
+pub struct BitStreamBuilder {
+    bits: Vec<BitSeq<u64>>,
+}
+
+impl BitStreamBuilder {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends the `nbits`-bit sequence `bits`, which must fit in it.
+    #[inline]
+    pub fn put(&mut self, bits: u32, nbits: u32) {
+        self.bits
+            .push(BitSeq::new(BitLen::new(nbits), u64::from(bits)).unwrap());
+    }
+
+    fn vacuum(
+        self,
+        bit_order: BitOrder,
+        out: &mut Vec<u8>,
+    ) -> std::io::Result<()> {
+        match bit_order {
+            BitOrder::LSB => {
+                let mut vac = BitVacuumerLSB::new(out);
+                self.bits.into_iter().try_for_each(|bits| vac.put(bits))?;
+                vac.flush()
+            }
+            BitOrder::MSB => {
+                let mut vac = BitVacuumerMSB::new(out);
+                self.bits.into_iter().try_for_each(|bits| vac.put(bits))?;
+                vac.flush()
+            }
+            BitOrder::MSB16 => {
+                let mut vac = BitVacuumerMSB16::new(out);
+                self.bits.into_iter().try_for_each(|bits| vac.put(bits))?;
+                vac.flush()
+            }
+            BitOrder::MSB32 => {
+                let mut vac = BitVacuumerMSB32::new(out);
+                self.bits.into_iter().try_for_each(|bits| vac.put(bits))?;
+                vac.flush()
+            }
+            BitOrder::JPEG => {
+                let mut vac = BitVacuumerJPEG::new(out);
+                self.bits.into_iter().try_for_each(|bits| vac.put(bits))?;
+                vac.flush()
+            }
+            _ => unreachable!("TODO"),
+        }
+    }
+
+    /// The bitstream, with the last chunk padded with zero bits.
+    #[inline]
+    #[must_use]
+    pub fn finish(self, bit_order: BitOrder) -> Vec<u8> {
+        let mut out = vec![];
+        self.vacuum(bit_order, &mut out).unwrap();
+        out
+    }
+}
+
+/// Packs the samples, `bits` each, as a single row of the packed encoder.
+#[inline]
+#[must_use]
+pub fn pack(samples: &[u16], bits: u32, bit_order: BitOrder) -> Vec<u8> {
+    let len = core::num::NonZero::new(samples.len()).unwrap();
+    let input =
+        Array2DRef::new(samples, RowLength::new(len), RowPitch::new(len));
+    let mut out = vec![];
+    let _pitch = Packer::new(&mut out, bit_order, bits, input, |_| {
+        ExtraPadding::new(NumBytes::new(0))
+    })
+    .pack()
+    .unwrap();
+    out
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,57 @@
+use rawspeed_bitstream_bitstreams::bitstreams::BitOrder;
+
+use super::{BitStreamBuilder, pack};
+
+fn build(bit_order: BitOrder) -> Vec<u8> {
+    let mut bits = BitStreamBuilder::new();
+    bits.put(0b101, 3);
+    bits.put(0xFF, 8);
+    bits.put(0, 0);
+    bits.put(0x1F, 5);
+    bits.put(0b1, 1);
+    bits.finish(bit_order)
+}
+
+#[test]
+fn msb_test() {
+    assert_eq!(build(BitOrder::MSB), vec![0b1011_1111, 0b1111_1111, 0x80]);
+}
+
+#[test]
+fn lsb_test() {
+    assert_eq!(build(BitOrder::LSB), vec![0b1111_1101, 0b1111_1111, 0x01]);
+}
+
+#[test]
+fn msb32_test() {
+    assert_eq!(build(BitOrder::MSB32), vec![0, 0x80, 0xFF, 0xBF]);
+}
+
+#[test]
+fn jpeg_test() {
+    assert_eq!(build(BitOrder::JPEG), vec![0b1011_1111, 0xFF, 0x00, 0x80]);
+}
+
+#[test]
+fn empty_test() {
+    assert!(BitStreamBuilder::new().finish(BitOrder::MSB).is_empty());
+}
+
+#[test]
+#[should_panic(expected = "called `Option::unwrap()` on a `None` value")]
+fn overlong_bits_test() {
+    BitStreamBuilder::new().put(0b100, 2);
+}
+
+#[test]
+fn pack_test() {
+    let samples = [0x123, 0x456, 0x789];
+    assert_eq!(
+        pack(&samples, 12, BitOrder::MSB),
+        vec![0x12, 0x34, 0x56, 0x78, 0x90]
+    );
+    assert_eq!(
+        pack(&samples, 12, BitOrder::LSB),
+        vec![0x23, 0x61, 0x45, 0x89, 0x07]
+    );
+}
@@ -1,5 +1,7 @@
 use rawspeed_common_generic_num::generic_num::bit_transmutation::ToLeBytes;
-use rawspeed_demuxers_rawdemuxer::rawdemuxer::{RawDemuxer, RawDemuxerError};
+use rawspeed_demuxers_rawdemuxer::rawdemuxer::{
+    ImageRefMut, RawDemuxer, RawDemuxerError,
+};
 use rawspeed_memory_nd_slice_procurement::ndsliceprocurement::NDSliceProcurementRequestError;
 use rawspeed_metadata_camerametadata::camerametadata::DecodeableCamera;
 use rawspeed_metadata_camerasxml_parser::{
@@ -59,7 +61,11 @@ struct Hash {
 }
 
 #[expect(clippy::too_many_lines, clippy::cognitive_complexity)]
-fn img_hash(demux: &dyn RawDemuxer, img: Array2DRef<'_, u16>) -> Hash {
+fn img_hash<T>(demux: &dyn RawDemuxer, img: Array2DRef<'_, T>) -> Hash
+where
+    T: Copy + ToLeBytes,
+    <T as ToLeBytes>::Output: AsSlice<Element = u8>,
+{
     let hash = format!(
         concat!(
             "make: {make}\n",
@@ -102,12 +108,30 @@ fn img_hash(demux: &dyn RawDemuxer, img: Array2DRef<'_, u16>) -> Hash {
         whitePoint = demux
             .whitelevel()
             .map_or("unknown".to_owned(), |v| v.to_string()),
-        blackLevelSeparate = demux
-            .blacklevel_separate()
-            .map_or("none".to_owned(), |()| unreachable!()),
-        wbCoeffs = demux
-            .wb_coeffs()
-            .map_or("(none)".to_owned(), |()| unreachable!()),
+        blackLevelSeparate = {
+            let mut repr = String::new();
+            if let Some(mat) = demux.blacklevel_separate() {
+                use core::fmt::Write as _;
+                write!(
+                    repr,
+                    "({} x {}):",
+                    mat.row_length().get(),
+                    mat.num_rows().get()
+                )
+                .unwrap();
+                for row in 0..mat.num_rows().get() {
+                    for e in &mat[RowIndex::new(row)] {
+                        write!(repr, " {e}").unwrap();
+                    }
+                }
+            } else {
+                "none".clone_into(&mut repr);
+            }
+            repr
+        },
+        wbCoeffs = demux.wb_coeffs().map_or("(none)".to_owned(), |wb| {
+            format!("{:.6} {:.6} {:.6} {:.6}", wb[0], wb[1], wb[2], wb[3])
+        }),
         colorMatrix = {
             let mut repr = String::new();
             if let Some(mat) = demux.colormatrix() {
@@ -382,8 +406,12 @@ fn compute_hash_for_file_impl(
     let mut output_buf = out_buf_request.fulfill()?;
     let mut output = output_buf.get_mut();
     res.decode(&mut output)?;
-    let output: Array2DRef<'_, u16> = output.into();
-    Ok(img_hash(&*res, output))
+    Ok(match output {
+        ImageRefMut::U16(output) => img_hash(&*res, output.into()),
+        ImageRefMut::U32(output) => img_hash(&*res, output.into()),
+        ImageRefMut::F32(output) => img_hash(&*res, output.into()),
+        _ => unreachable!(),
+    })
 }
 
 fn compute_hash_for_file(
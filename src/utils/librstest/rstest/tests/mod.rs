@@ -10,7 +10,7 @@ use rawspeed_std::coord_common::{
 };
 use test_file_system::TestFileSystem;
 
-use super::{AsSlice, Hash, img_data_hash, img_hash};
+use super::{AsSlice, Hash, ImageRefMut, img_data_hash, img_hash};
 use crate::rstest::camerasxml_parser::Cameras;
 mod test_file_system;
 
@@ -167,8 +167,11 @@ fn image_hashfile_test() {
     )
     .unwrap();
     let mut output_buf = out_buf_request.fulfill().unwrap();
-    let mut output = output_buf.get_mut();
-    res.decode(&mut output).unwrap();
+    let mut image = output_buf.get_mut();
+    res.decode(&mut image).unwrap();
+    let ImageRefMut::U16(output) = image else {
+        unreachable!()
+    };
     assert_eq!(
         Hash {
             hash: REF_HASH.to_owned()
@@ -230,8 +233,11 @@ fn image_barebones_hashfile_test() {
     )
     .unwrap();
     let mut output_buf = out_buf_request.fulfill().unwrap();
-    let mut output = output_buf.get_mut();
-    res.decode(&mut output).unwrap();
+    let mut image = output_buf.get_mut();
+    res.decode(&mut image).unwrap();
+    let ImageRefMut::U16(output) = image else {
+        unreachable!()
+    };
     assert_eq!(
         Hash {
             hash: REF_HASH_BAREBONES.to_owned()
@@ -0,0 +1,300 @@
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_parsers_tiffparser::tiffparser::TiffTag;
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Value {
+    Byte(Vec<u8>),
+    Ascii(&'static str),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    SLong(Vec<i32>),
+    Rational(Vec<(u32, u32)>),
+    Undefined(Vec<u8>),
+    SRational(Vec<(i32, i32)>),
+    /// Offsets of the given blobs, as LONGs.
+    BlobOffsets(Vec<usize>),
+    /// Byte counts of the given blobs, as LONGs.
+    BlobSizes(Vec<usize>),
+    /// Offset of the given IFD, as a LONG.
+    IFDOffset(usize),
+}
+
+pub type Entry = (u16, Value);
+pub type Entries = Vec<Entry>;
+
+#[inline]
+#[must_use]
+pub const fn tag(tag: TiffTag, value: Value) -> Entry {
+    (tag.val(), value)
+}
+
+#[derive(Debug)]
+struct Layout {
+    endianness: Endianness,
+    blob_offsets: Vec<u32>,
+    blob_sizes: Vec<u32>,
+    ifd_offsets: Vec<u32>,
+}
+
+fn to_u32(val: usize) -> u32 {
+    val.try_into().unwrap()
+}
+
+impl Value {
+    const fn datatype(&self) -> u16 {
+        match self {
+            Value::Byte(_) => 1,
+            Value::Ascii(_) => 2,
+            Value::Short(_) => 3,
+            Value::Long(_)
+            | Value::BlobOffsets(_)
+            | Value::BlobSizes(_)
+            | Value::IFDOffset(_) => 4,
+            Value::Rational(_) => 5,
+            Value::Undefined(_) => 7,
+            Value::SLong(_) => 9,
+            Value::SRational(_) => 10,
+        }
+    }
+
+    const fn count(&self) -> usize {
+        match self {
+            Value::Byte(v) | Value::Undefined(v) => v.len(),
+            Value::Ascii(s) => s.len() + 1,
+            Value::Short(v) => v.len(),
+            Value::Long(v) => v.len(),
+            Value::SLong(v) => v.len(),
+            Value::Rational(v) => v.len(),
+            Value::SRational(v) => v.len(),
+            Value::BlobOffsets(v) | Value::BlobSizes(v) => v.len(),
+            Value::IFDOffset(_) => 1,
+        }
+    }
+
+    const fn byte_size(&self) -> usize {
+        let elt_size = match self.datatype() {
+            1 | 2 | 7 => 1,
+            3 => 2,
+            4 | 9 => 4,
+            _ => 8,
+        };
+        elt_size * self.count()
+    }
+
+    fn encode(&self, layout: &Layout) -> Vec<u8> {
+        let mut out = vec![];
+        let mut push = |mut bytes: Vec<u8>| {
+            if layout.endianness == Endianness::Big {
+                bytes.reverse();
+            }
+            out.extend(bytes);
+        };
+        let lookup = |values: &[u32], indices: &[usize]| -> Vec<u32> {
+            indices.iter().map(|i| *values.get(*i).unwrap()).collect()
+        };
+        let longs = match self {
+            Value::Byte(v) | Value::Undefined(v) => {
+                return v.clone();
+            }
+            Value::Ascii(s) => return s.bytes().chain([0]).collect(),
+            Value::Short(v) => {
+                for e in v {
+                    push(e.to_le_bytes().to_vec());
+                }
+                return out;
+            }
+            Value::Long(v) => v.clone(),
+            Value::SLong(v) => {
+                v.iter().copied().map(i32::cast_unsigned).collect()
+            }
+            Value::Rational(v) => {
+                v.iter().flat_map(|(num, den)| [*num, *den]).collect()
+            }
+            Value::SRational(v) => v
+                .iter()
+                .flat_map(|(num, den)| [*num, *den])
+                .map(i32::cast_unsigned)
+                .collect(),
+            Value::BlobOffsets(v) => lookup(&layout.blob_offsets, v),
+            Value::BlobSizes(v) => lookup(&layout.blob_sizes, v),
+            Value::IFDOffset(index) => lookup(&layout.ifd_offsets, &[*index]),
+        };
+        for e in longs {
+            push(e.to_le_bytes().to_vec());
+        }
+        out
+    }
+}
+
+const fn ifd_size(entries: &Entries) -> usize {
+    2 + 12 * entries.len() + 4
+}
+
+fn out_of_line_size(entries: &Entries) -> usize {
+    entries
+        .iter()
+        .map(|(_, value)| value.byte_size())
+        .filter(|size| *size > 4)
+        .sum()
+}
+
+/// Writes a TIFF file laid out as: the header, then each IFD directly
+/// followed by its out-of-line values, and then all the blobs.
+///
+/// The first `chain_len` IFDs form the IFD chain starting at IFD0,
+/// the rest are only reachable via [`Value::IFDOffset`] (e.g. `SubIFDs`).
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+#[must_use]
+pub struct TiffBuilder {
+    pub endianness: Endianness,
+    pub magic: u16,
+    pub ifds: Vec<Entries>,
+    pub chain_len: usize,
+    pub blobs: Vec<Vec<u8>>,
+}
+
+impl TiffBuilder {
+    #[inline]
+    pub const fn new(endianness: Endianness) -> Self {
+        Self {
+            endianness,
+            magic: 42,
+            ifds: vec![],
+            chain_len: 1,
+            blobs: vec![],
+        }
+    }
+
+    /// The entries of the given IFD, which is created (along with all the
+    /// IFDs before it) if it does not exist yet.
+    #[inline]
+    pub fn ifd(&mut self, index: usize) -> &mut Entries {
+        if self.ifds.len() <= index {
+            self.ifds.resize(index + 1, vec![]);
+        }
+        self.ifds.get_mut(index).unwrap()
+    }
+
+    /// Replaces the value of all the entries with the given tag.
+    #[inline]
+    pub fn set(&mut self, tag: TiffTag, value: &Value) {
+        for (entry_tag, entry_value) in self.ifds.iter_mut().flatten() {
+            if *entry_tag == tag.val() {
+                *entry_value = value.clone();
+            }
+        }
+    }
+
+    /// Removes all the entries with the given tag.
+    #[inline]
+    pub fn remove(&mut self, tag: TiffTag) {
+        for ifd in &mut self.ifds {
+            ifd.retain(|(entry_tag, _)| *entry_tag != tag.val());
+        }
+    }
+
+    fn write(&self, out: &mut Vec<u8>, value: &Value) {
+        let layout = Layout {
+            endianness: self.endianness,
+            blob_offsets: vec![],
+            blob_sizes: vec![],
+            ifd_offsets: vec![],
+        };
+        out.extend(value.encode(&layout));
+    }
+
+    fn write_ifd(
+        &self,
+        out: &mut Vec<u8>,
+        entries: &Entries,
+        next_ifd_offset: u32,
+        layout: &Layout,
+    ) {
+        let mut data_offset = out.len() + ifd_size(entries);
+        let mut data = vec![];
+        let mut entries = entries.clone();
+        entries.sort_by_key(|(tag, _)| *tag);
+        self.write(out, &Value::Short(vec![entries.len().try_into().unwrap()]));
+        for (tag, value) in &entries {
+            self.write(out, &Value::Short(vec![*tag, value.datatype()]));
+            self.write(out, &Value::Long(vec![to_u32(value.count())]));
+            let mut bytes = value.encode(layout);
+            if bytes.len() > 4 {
+                self.write(out, &Value::Long(vec![to_u32(data_offset)]));
+                data_offset += bytes.len();
+                data.extend(bytes);
+            } else {
+                bytes.resize(4, 0);
+                out.extend(bytes);
+            }
+        }
+        self.write(out, &Value::Long(vec![next_ifd_offset]));
+        out.extend(data);
+    }
+
+    /// Lays out the IFDs and the blobs after a header of the given length.
+    fn layout(&self, header_len: usize) -> Layout {
+        let mut offset = header_len;
+        let mut ifd_offsets = vec![];
+        for ifd in &self.ifds {
+            ifd_offsets.push(to_u32(offset));
+            offset += ifd_size(ifd) + out_of_line_size(ifd);
+        }
+        let mut blob_offsets = vec![];
+        for blob in &self.blobs {
+            blob_offsets.push(to_u32(offset));
+            offset += blob.len();
+        }
+        Layout {
+            endianness: self.endianness,
+            blob_offsets,
+            blob_sizes: self.blobs.iter().map(|b| to_u32(b.len())).collect(),
+            ifd_offsets,
+        }
+    }
+
+    fn write_body(&self, out: &mut Vec<u8>, layout: &Layout) {
+        for (index, ifd) in self.ifds.iter().enumerate() {
+            let next_ifd_offset = match layout.ifd_offsets.get(index + 1) {
+                Some(next) if index + 1 < self.chain_len => *next,
+                _ => 0,
+            };
+            self.write_ifd(out, ifd, next_ifd_offset, layout);
+        }
+        for blob in &self.blobs {
+            out.extend(blob);
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn build(&self) -> Vec<u8> {
+        let layout = self.layout(8);
+        let mut out = match self.endianness {
+            Endianness::Little => b"II".to_vec(),
+            Endianness::Big => b"MM".to_vec(),
+        };
+        self.write(&mut out, &Value::Short(vec![self.magic]));
+        let first_ifd_offset = layout.ifd_offsets.first().copied().unwrap_or(0);
+        self.write(&mut out, &Value::Long(vec![first_ifd_offset]));
+        self.write_body(&mut out, &layout);
+        out
+    }
+
+    /// Writes the IFDs directly after the given `prefix` instead of a TIFF
+    /// header, with offsets relative to the start of the prefix, as in
+    /// the `MakerNote`s whose IFD follows a vendor signature.
+    #[inline]
+    #[must_use]
+    pub fn build_after(&self, prefix: &[u8]) -> Vec<u8> {
+        let layout = self.layout(prefix.len());
+        let mut out = prefix.to_vec();
+        self.write_body(&mut out, &layout);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,110 @@
+use rawspeed_memory_endianness::endianness::Endianness;
+use rawspeed_parsers_tiffparser::tiffparser::{TiffIFD, TiffParser, TiffTag};
+
+use super::{TiffBuilder, Value, tag};
+
+fn builder(endianness: Endianness) -> TiffBuilder {
+    let mut builder = TiffBuilder::new(endianness);
+    builder.ifd(0).extend([
+        tag(TiffTag::MAKE, Value::Ascii("Make")),
+        tag(TiffTag::SUB_IFDS, Value::IFDOffset(2)),
+    ]);
+    builder.ifd(1).extend([
+        tag(TiffTag::STRIP_OFFSETS, Value::BlobOffsets(vec![0, 1])),
+        tag(TiffTag::STRIP_BYTE_COUNTS, Value::BlobSizes(vec![0, 1])),
+    ]);
+    builder.ifd(2).extend([
+        tag(TiffTag::IMAGE_WIDTH, Value::Short(vec![3])),
+        tag(
+            TiffTag::DEFAULT_CROP_ORIGIN,
+            Value::Rational(vec![(1, 2), (3, 4)]),
+        ),
+        tag(TiffTag::COLOR_MATRIX1, Value::SRational(vec![(-1, 2)])),
+        tag(TiffTag::BLACK_LEVEL, Value::SLong(vec![-3])),
+        tag(TiffTag::CFA_PATTERN, Value::Undefined(vec![0, 1, 1, 2])),
+    ]);
+    builder.chain_len = 2;
+    builder.blobs = vec![vec![7; 5], vec![9; 3]];
+    builder
+}
+
+fn check_sub_ifd(sub_ifd: &TiffIFD<'_>) {
+    assert_eq!(
+        sub_ifd.get_entry(TiffTag::IMAGE_WIDTH).unwrap().get_u32(0),
+        Ok(3)
+    );
+    let crop = sub_ifd.get_entry(TiffTag::DEFAULT_CROP_ORIGIN).unwrap();
+    assert_eq!(crop.get_f32s(), Ok(vec![0.5, 0.75]));
+    let matrix = sub_ifd.get_entry(TiffTag::COLOR_MATRIX1).unwrap();
+    assert_eq!(matrix.get_f32s(), Ok(vec![-0.5]));
+    let black = sub_ifd.get_entry(TiffTag::BLACK_LEVEL).unwrap();
+    assert_eq!(black.get_i32(0), Ok(-3));
+    let cfa = sub_ifd.get_entry(TiffTag::CFA_PATTERN).unwrap();
+    assert_eq!(cfa.get_u8(3), Ok(2));
+}
+
+fn check_round_trip(endianness: Endianness) {
+    let input = builder(endianness).build();
+    let root = TiffParser::parse(&input).unwrap();
+    assert_eq!(root.ifds().len(), 2);
+    let [ifd0, ifd1] = root.ifds() else {
+        unreachable!();
+    };
+    assert_eq!(ifd0.sub_ifds().len(), 1);
+    let sub_ifd = ifd0.sub_ifds().first().unwrap();
+    assert_eq!(
+        ifd0.get_entry(TiffTag::MAKE).unwrap().get_string(),
+        Ok("Make")
+    );
+    let offsets = ifd1.get_entry(TiffTag::STRIP_OFFSETS).unwrap();
+    let counts = ifd1.get_entry(TiffTag::STRIP_BYTE_COUNTS).unwrap();
+    assert_eq!(counts.get_u32s(), Ok(vec![5, 3]));
+    let offset = usize::try_from(offsets.get_u32(1).unwrap()).unwrap();
+    assert_eq!(input.get(offset..), Some([9; 3].as_slice()));
+    check_sub_ifd(sub_ifd);
+}
+
+#[test]
+fn round_trip_test() {
+    for endianness in [Endianness::Little, Endianness::Big] {
+        check_round_trip(endianness);
+    }
+}
+
+#[test]
+fn set_and_remove_test() {
+    let mut builder = builder(Endianness::Little);
+    builder.set(TiffTag::MAKE, &Value::Ascii("Other"));
+    builder.remove(TiffTag::SUB_IFDS);
+    builder.magic = 0x55;
+    let input = builder.build();
+    assert_eq!(input.get(..4), Some(b"IIU\0".as_slice()));
+    let root = TiffParser::parse(&input).unwrap();
+    let ifd0 = root.ifds().first().unwrap();
+    assert!(ifd0.sub_ifds().is_empty());
+    assert_eq!(
+        ifd0.get_entry(TiffTag::MAKE).unwrap().get_string(),
+        Ok("Other")
+    );
+}
+
+#[test]
+fn build_after_test() {
+    let mut builder = TiffBuilder::new(Endianness::Big);
+    builder.ifd(0).extend([
+        tag(TiffTag::MAKE, Value::Ascii("Make")),
+        tag(TiffTag::STRIP_OFFSETS, Value::BlobOffsets(vec![0])),
+    ]);
+    builder.blobs = vec![vec![7; 3]];
+    let input = builder.build_after(b"PREFIX");
+    assert_eq!(input.get(..6), Some(b"PREFIX".as_slice()));
+    let root = TiffParser::parse_ifd_chain(&input, Endianness::Big, 6).unwrap();
+    let ifd0 = root.ifds().first().unwrap();
+    assert_eq!(
+        ifd0.get_entry(TiffTag::MAKE).unwrap().get_string(),
+        Ok("Make")
+    );
+    let offset = ifd0.get_entry(TiffTag::STRIP_OFFSETS).unwrap().get_u32(0);
+    let offset = usize::try_from(offset.unwrap()).unwrap();
+    assert_eq!(input.get(offset..), Some([7; 3].as_slice()));
+}